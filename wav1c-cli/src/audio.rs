@@ -0,0 +1,339 @@
+//! Audio tracks passed through into MP4 output (`--audio`): Ogg Opus and
+//! ADTS AAC are split into their packets without decoding them.
+
+use crate::mp4::Mp4Sample;
+
+/// Sample rates indexed by the ADTS `sampling_frequency_index`.
+const AAC_SAMPLE_RATES: [u32; 13] = [
+    96_000, 88_200, 64_000, 48_000, 44_100, 32_000, 24_000, 22_050, 16_000, 12_000, 11_025, 8_000,
+    7_350,
+];
+
+/// PCM samples in every AAC frame (one raw data block).
+const AAC_FRAME_SAMPLES: u32 = 1024;
+
+/// Opus timestamps and durations always count 48 kHz samples.
+pub const OPUS_SAMPLE_RATE: u32 = 48_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AudioCodec {
+    /// AAC with its two-byte `AudioSpecificConfig`.
+    Aac { audio_specific_config: [u8; 2] },
+    /// Opus with the `OpusHead` fields the MP4 `dOps` box repeats.
+    Opus {
+        input_sample_rate: u32,
+        output_gain: i16,
+        /// Channel mapping family, then for families other than 0 the
+        /// stream count, coupled count and channel mapping.
+        channel_mapping: Vec<u8>,
+    },
+}
+
+/// A demuxed audio track: codec setup and one sample per packet, with
+/// durations in `sample_rate` units.
+pub struct AudioTrack {
+    pub codec: AudioCodec,
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Decoded samples at the start that are not presented (Opus pre-skip).
+    pub pre_skip: u32,
+    /// Samples presented after `pre_skip`; less than the packets' total when
+    /// the last packet is padded.
+    pub presented: u64,
+    pub samples: Vec<Mp4Sample>,
+}
+
+impl AudioTrack {
+    /// Duration of every packet together, in `sample_rate` units.
+    pub fn media_duration(&self) -> u64 {
+        self.samples.iter().map(|s| s.duration as u64).sum()
+    }
+}
+
+/// Reads `path` as Ogg Opus or ADTS AAC, told apart by their first bytes.
+pub fn read_audio(path: &str) -> Result<AudioTrack, String> {
+    let data = std::fs::read(path).map_err(|e| format!("reading {path}: {e}"))?;
+    let track = if data.starts_with(b"OggS") {
+        parse_ogg_opus(&data)
+    } else if data.len() >= 2 && data[0] == 0xFF && data[1] & 0xF0 == 0xF0 {
+        parse_adts(&data)
+    } else {
+        Err("not an Ogg Opus or ADTS AAC file".to_owned())
+    };
+    track.map_err(|e| format!("{path}: {e}"))
+}
+
+/// Splits an ADTS stream into raw AAC frames. Every frame must share the
+/// first one's profile, sample rate and channel configuration.
+pub fn parse_adts(data: &[u8]) -> Result<AudioTrack, String> {
+    let mut samples = Vec::new();
+    let mut setup = None;
+    let mut pos = 0;
+    while pos < data.len() {
+        let header = data.get(pos..pos + 7).ok_or("truncated ADTS header")?;
+        if header[0] != 0xFF || header[1] & 0xF6 != 0xF0 {
+            return Err(format!("no ADTS sync word at byte {pos}"));
+        }
+        let header_len = if header[1] & 1 == 0 { 9 } else { 7 };
+        let profile = header[2] >> 6;
+        let frequency_index = (header[2] >> 2) & 0xF;
+        let channels = ((header[2] & 1) << 2) | (header[3] >> 6);
+        let frame_len = ((header[3] as usize & 3) << 11)
+            | ((header[4] as usize) << 3)
+            | (header[5] as usize >> 5);
+        let blocks = (header[6] & 3) as u32 + 1;
+        if frame_len < header_len || pos + frame_len > data.len() {
+            return Err(format!("truncated ADTS frame at byte {pos}"));
+        }
+        match setup {
+            None => setup = Some((profile, frequency_index, channels)),
+            Some(first) if first != (profile, frequency_index, channels) => {
+                return Err(format!("ADTS frame at byte {pos} changes the stream setup"));
+            }
+            Some(_) => {}
+        }
+        samples.push(Mp4Sample {
+            data: data[pos + header_len..pos + frame_len].to_vec(),
+            is_sync: true,
+            duration: AAC_FRAME_SAMPLES * blocks,
+            disposable: false,
+        });
+        pos += frame_len;
+    }
+    let (profile, frequency_index, channels) = setup.ok_or("no ADTS frames")?;
+    let sample_rate = *AAC_SAMPLE_RATES
+        .get(frequency_index as usize)
+        .ok_or("reserved ADTS sampling frequency index")?;
+    if channels == 0 {
+        return Err("ADTS channel configuration 0 (in-band) is not supported".to_owned());
+    }
+    let object_type = profile as u16 + 1;
+    let asc = (object_type << 11) | ((frequency_index as u16) << 7) | ((channels as u16) << 3);
+    let mut track = AudioTrack {
+        codec: AudioCodec::Aac {
+            audio_specific_config: asc.to_be_bytes(),
+        },
+        sample_rate,
+        channels: channels as u16,
+        pre_skip: 0,
+        presented: 0,
+        samples,
+    };
+    track.presented = track.media_duration();
+    Ok(track)
+}
+
+/// Reassembles the packets of the first logical stream of an Ogg file and
+/// reads them as Opus: `OpusHead`, `OpusTags`, then audio. The final
+/// granule position trims padding off the last packet.
+pub fn parse_ogg_opus(data: &[u8]) -> Result<AudioTrack, String> {
+    let mut packets: Vec<Vec<u8>> = Vec::new();
+    let mut partial = Vec::new();
+    let mut serial = None;
+    let mut last_granule = 0u64;
+    let mut pos = 0;
+    while pos < data.len() {
+        let header = data.get(pos..pos + 27).ok_or("truncated Ogg page header")?;
+        if &header[..4] != b"OggS" {
+            return Err(format!("no Ogg capture pattern at byte {pos}"));
+        }
+        let granule = u64::from_le_bytes(header[6..14].try_into().unwrap());
+        let page_serial = u32::from_le_bytes(header[14..18].try_into().unwrap());
+        let segments = header[26] as usize;
+        let lacing = data
+            .get(pos + 27..pos + 27 + segments)
+            .ok_or("truncated Ogg lacing table")?;
+        let mut body = pos + 27 + segments;
+        let page_end = body + lacing.iter().map(|&l| l as usize).sum::<usize>();
+        if page_end > data.len() {
+            return Err(format!("truncated Ogg page at byte {pos}"));
+        }
+        if *serial.get_or_insert(page_serial) == page_serial {
+            for &len in lacing {
+                partial.extend_from_slice(&data[body..body + len as usize]);
+                body += len as usize;
+                if len < 255 {
+                    packets.push(std::mem::take(&mut partial));
+                }
+            }
+            // -1 marks a page on which no packet ends.
+            if granule != u64::MAX {
+                last_granule = granule;
+            }
+        }
+        pos = page_end;
+    }
+
+    let mut packets = packets.into_iter();
+    let head = packets.next().ok_or("no Ogg packets")?;
+    if head.len() < 19 || &head[..8] != b"OpusHead" {
+        return Err("first Ogg packet is not an OpusHead".to_owned());
+    }
+    if head[8] >> 4 != 0 {
+        return Err(format!("unsupported OpusHead version {}", head[8]));
+    }
+    let channels = head[9] as u16;
+    let pre_skip = u16::from_le_bytes([head[10], head[11]]) as u32;
+    let input_sample_rate = u32::from_le_bytes(head[12..16].try_into().unwrap());
+    let output_gain = i16::from_le_bytes([head[16], head[17]]);
+    let family = head[18];
+    let mapping_len = if family == 0 {
+        1
+    } else {
+        3 + channels as usize
+    };
+    let channel_mapping = head
+        .get(18..18 + mapping_len)
+        .ok_or("truncated OpusHead channel mapping")?
+        .to_vec();
+    match packets.next() {
+        Some(tags) if tags.starts_with(b"OpusTags") => {}
+        _ => return Err("second Ogg packet is not OpusTags".to_owned()),
+    }
+
+    let samples = packets
+        .map(|packet| {
+            let duration = opus_packet_samples(&packet)?;
+            Ok(Mp4Sample {
+                data: packet,
+                is_sync: true,
+                duration,
+                disposable: false,
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let mut track = AudioTrack {
+        codec: AudioCodec::Opus {
+            input_sample_rate,
+            output_gain,
+            channel_mapping,
+        },
+        sample_rate: OPUS_SAMPLE_RATE,
+        channels,
+        pre_skip,
+        presented: 0,
+        samples,
+    };
+    let available = track.media_duration().saturating_sub(pre_skip as u64);
+    track.presented = last_granule.saturating_sub(pre_skip as u64).min(available);
+    Ok(track)
+}
+
+/// Duration in 48 kHz samples of an Opus packet, from its TOC byte and,
+/// for code 3 packets, its frame count byte (RFC 6716, 3.1).
+fn opus_packet_samples(packet: &[u8]) -> Result<u32, String> {
+    let toc = *packet.first().ok_or("empty Opus packet")?;
+    let config = toc >> 3;
+    let frame = match config {
+        0..=11 => [480, 960, 1920, 2880][config as usize % 4],
+        12..=15 => [480, 960][config as usize % 2],
+        _ => [120, 240, 480, 960][config as usize % 4],
+    };
+    let frames = match toc & 3 {
+        0 => 1,
+        1 | 2 => 2,
+        _ => (*packet.get(1).ok_or("truncated Opus packet")? & 0x3F) as u32,
+    };
+    Ok(frame * frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn adts_frame(payload: &[u8]) -> Vec<u8> {
+        // AAC LC, 44.1 kHz, stereo, no CRC, one raw data block.
+        let len = payload.len() + 7;
+        let mut frame = vec![
+            0xFF,
+            0xF1,
+            (1 << 6) | (4 << 2),
+            (2 << 6) | (len >> 11) as u8,
+            (len >> 3) as u8,
+            ((len & 7) << 5) as u8 | 0x1F,
+            0xFC,
+        ];
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn adts_frames_become_raw_aac_samples() {
+        let data = [adts_frame(&[1, 2, 3]), adts_frame(&[4; 300])].concat();
+        let track = parse_adts(&data).unwrap();
+        assert_eq!(
+            track.codec,
+            AudioCodec::Aac {
+                audio_specific_config: [0x12, 0x10]
+            }
+        );
+        assert_eq!((track.sample_rate, track.channels), (44_100, 2));
+        assert_eq!(track.samples.len(), 2);
+        assert_eq!(track.samples[0].data, [1, 2, 3]);
+        assert_eq!(track.samples[1].data.len(), 300);
+        assert_eq!(track.presented, 2048);
+        assert!(parse_adts(&data[..data.len() - 1]).is_err());
+    }
+
+    fn ogg_page(serial: u32, granule: u64, packets: &[&[u8]]) -> Vec<u8> {
+        let mut lacing = Vec::new();
+        for p in packets {
+            lacing.extend(std::iter::repeat_n(255u8, p.len() / 255));
+            lacing.push((p.len() % 255) as u8);
+        }
+        let mut page = b"OggS\0\0".to_vec();
+        page.extend_from_slice(&granule.to_le_bytes());
+        page.extend_from_slice(&serial.to_le_bytes());
+        page.extend_from_slice(&[0; 8]);
+        page.push(lacing.len() as u8);
+        page.extend_from_slice(&lacing);
+        for p in packets {
+            page.extend_from_slice(p);
+        }
+        page
+    }
+
+    fn opus_head(pre_skip: u16) -> Vec<u8> {
+        let mut head = b"OpusHead\x01\x02".to_vec();
+        head.extend_from_slice(&pre_skip.to_le_bytes());
+        head.extend_from_slice(&44_100u32.to_le_bytes());
+        head.extend_from_slice(&0i16.to_le_bytes());
+        head.push(0);
+        head
+    }
+
+    #[test]
+    fn ogg_opus_packets_carry_toc_durations_and_trim_the_end() {
+        let long = vec![0xFC; 600]; // CELT 20 ms, one frame
+        let data = [
+            ogg_page(7, 0, &[&opus_head(312)]),
+            ogg_page(9, 0, &[b"ignored other stream"]),
+            ogg_page(7, 0, &[b"OpusTags\0\0\0\0\0\0\0\0"]),
+            // SILK 60 ms, then two 10 ms hybrid frames (code 1).
+            ogg_page(7, 3840, &[&[0x18, 0], &[0x61, 0, 0]]),
+            ogg_page(7, 4000, &[&long]),
+        ]
+        .concat();
+        let track = parse_ogg_opus(&data).unwrap();
+        let durations: Vec<u32> = track.samples.iter().map(|s| s.duration).collect();
+        assert_eq!(durations, [2880, 960, 960]);
+        assert_eq!(track.samples[2].data, long);
+        assert_eq!((track.sample_rate, track.channels), (48_000, 2));
+        assert_eq!(track.pre_skip, 312);
+        assert_eq!(track.presented, 4000 - 312);
+        assert!(matches!(
+            track.codec,
+            AudioCodec::Opus {
+                input_sample_rate: 44_100,
+                ref channel_mapping,
+                ..
+            } if channel_mapping == &[0]
+        ));
+    }
+
+    #[test]
+    fn opus_code_3_packets_count_their_frames() {
+        assert_eq!(opus_packet_samples(&[0xF3, 0x05]), Ok(5 * 480));
+        assert!(opus_packet_samples(&[0xF3]).is_err());
+    }
+}
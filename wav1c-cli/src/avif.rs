@@ -1,6 +1,7 @@
 use std::io::{self, Write};
 
 use crate::mp4::{box_wrap, build_av1c, build_colr, full_box, strip_temporal_delimiters};
+use crate::orientation::Orientation;
 use wav1c::{BitDepth, ContentLightLevel, MasteringDisplayMetadata, VideoSignal};
 
 #[cfg(feature = "heic")]
@@ -11,10 +12,21 @@ const FRACTION_SCALE: u32 = 1_000_000;
 pub struct AvifConfig {
     pub width: u32,
     pub height: u32,
-    pub config_obus: Vec<u8>,
+    /// `AV1CodecConfigurationRecord` from `Encoder::av1c` (or
+    /// `av1c_still_picture`); the item data starts with its configOBUs.
+    pub av1c: Vec<u8>,
     pub video_signal: VideoSignal,
     pub content_light: Option<ContentLightLevel>,
     pub mastering_display: Option<MasteringDisplayMetadata>,
+    /// Display transform written as `irot`/`imir`; `width`/`height` stay the
+    /// coded (stored) dimensions.
+    pub orientation: Orientation,
+}
+
+impl AvifConfig {
+    fn config_obus(&self) -> &[u8] {
+        self.av1c.get(4..).unwrap_or_default()
+    }
 }
 
 #[cfg(feature = "heic")]
@@ -279,45 +291,34 @@ fn parse_obu_type_and_len(data: &[u8], start: usize) -> Option<(u8, usize)> {
     Some((obu_type, total_len))
 }
 
-pub fn write_avif<W: Write>(w: &mut W, config: &AvifConfig, obu_data: &[u8]) -> io::Result<()> {
-    let data = build_item_obu_data(&config.config_obus, obu_data);
+pub struct AvifAuxiliaryItem {
+    pub aux_type: String,
+    pub config: AvifConfig,
+    pub obu_data: Vec<u8>,
+}
+
+pub fn write_avif<W: Write>(
+    w: &mut W,
+    config: &AvifConfig,
+    obu_data: &[u8],
+    aux_items: &[AvifAuxiliaryItem],
+) -> io::Result<()> {
+    let first_aux_item_id = 2u16;
+    let data = build_item_obu_data(config.config_obus(), obu_data);
+    let aux_data = build_auxiliary_item_data(aux_items);
 
     let ftyp = build_ftyp();
     let hdlr = build_hdlr();
     let pitm = build_pitm();
-    let iinf = build_iinf_single();
-    let iprp = build_iprp_single(config);
-
-    let children_before_iloc = [&hdlr[..], &pitm[..], &iinf[..], &iprp[..]].concat();
-    let iloc = build_iloc(&[IlocEntry {
-        item_id: 1,
-        offset: 0,
-        length: data.len() as u32,
-    }]);
-
-    let meta_content_size = 4 + children_before_iloc.len() as u32 + iloc.len() as u32;
-    let meta_size = 8 + meta_content_size;
-    let data_offset = ftyp.len() as u32 + meta_size + 8;
-
-    let iloc = build_iloc(&[IlocEntry {
-        item_id: 1,
-        offset: data_offset,
-        length: data.len() as u32,
-    }]);
-
-    let mut meta_payload = Vec::new();
-    meta_payload.push(0);
-    meta_payload.extend_from_slice(&0u32.to_be_bytes()[1..4]);
-    meta_payload.extend_from_slice(&children_before_iloc);
-    meta_payload.extend_from_slice(&iloc);
-    let meta = box_wrap(b"meta", &meta_payload);
+    let iinf = build_iinf_single(first_aux_item_id, aux_items.len());
+    let iref = build_iref_auxiliary(first_aux_item_id, aux_items.len());
+    let iprp = build_iprp_single(config, first_aux_item_id, aux_items);
 
-    let mdat = box_wrap(b"mdat", &data);
+    let children_before_iloc = [&hdlr[..], &pitm[..], &iinf[..], &iref[..], &iprp[..]].concat();
+    let mut items = vec![(1u16, data.as_slice())];
+    items.extend(auxiliary_item_payloads(first_aux_item_id, &aux_data));
 
-    w.write_all(&ftyp)?;
-    w.write_all(&meta)?;
-    w.write_all(&mdat)?;
-    Ok(())
+    write_meta_and_mdat(w, &ftyp, &children_before_iloc, &items)
 }
 
 #[cfg(feature = "heic")]
@@ -328,20 +329,19 @@ pub fn write_avif_with_tmap_gain_map<W: Write>(
     gain_map_config: &AvifConfig,
     gain_map_obu_data: &[u8],
     tmap_payload: &[u8],
+    aux_items: &[AvifAuxiliaryItem],
 ) -> io::Result<()> {
-    let base_data = build_item_obu_data(&base_config.config_obus, base_obu_data);
-    let gain_map_data = build_item_obu_data(&gain_map_config.config_obus, gain_map_obu_data);
-    let mut mdat_payload = Vec::new();
-    mdat_payload.extend_from_slice(&base_data);
-    mdat_payload.extend_from_slice(tmap_payload);
-    mdat_payload.extend_from_slice(&gain_map_data);
+    let first_aux_item_id = 4u16;
+    let base_data = build_item_obu_data(base_config.config_obus(), base_obu_data);
+    let gain_map_data = build_item_obu_data(gain_map_config.config_obus(), gain_map_obu_data);
+    let aux_data = build_auxiliary_item_data(aux_items);
 
     let ftyp = build_ftyp_tmap();
     let hdlr = build_hdlr();
     let pitm = build_pitm();
-    let iinf = build_iinf_tmap();
-    let iref = build_iref_tmap();
-    let iprp = build_iprp_tmap(base_config, gain_map_config);
+    let iinf = build_iinf_tmap(first_aux_item_id, aux_items.len());
+    let iref = build_iref_tmap(first_aux_item_id, aux_items.len());
+    let iprp = build_iprp_tmap(base_config, gain_map_config, first_aux_item_id, aux_items);
     let grpl = build_grpl_altr_tmap();
     let children_before_iloc = [
         &hdlr[..],
@@ -353,57 +353,74 @@ pub fn write_avif_with_tmap_gain_map<W: Write>(
     ]
     .concat();
 
-    let temp_iloc = build_iloc(&[
-        IlocEntry {
-            item_id: 1,
-            offset: 0,
-            length: base_data.len() as u32,
-        },
-        IlocEntry {
-            item_id: 2,
-            offset: 0,
-            length: tmap_payload.len() as u32,
-        },
-        IlocEntry {
-            item_id: 3,
-            offset: 0,
-            length: gain_map_data.len() as u32,
-        },
-    ]);
+    let mut items = vec![
+        (1u16, base_data.as_slice()),
+        (2u16, tmap_payload),
+        (3u16, gain_map_data.as_slice()),
+    ];
+    items.extend(auxiliary_item_payloads(first_aux_item_id, &aux_data));
+
+    write_meta_and_mdat(w, &ftyp, &children_before_iloc, &items)
+}
+
+fn build_auxiliary_item_data(aux_items: &[AvifAuxiliaryItem]) -> Vec<Vec<u8>> {
+    aux_items
+        .iter()
+        .map(|aux| build_item_obu_data(aux.config.config_obus(), &aux.obu_data))
+        .collect()
+}
+
+fn auxiliary_item_payloads(
+    first_aux_item_id: u16,
+    aux_data: &[Vec<u8>],
+) -> impl Iterator<Item = (u16, &[u8])> {
+    aux_data
+        .iter()
+        .enumerate()
+        .map(move |(i, data)| (first_aux_item_id + i as u16, data.as_slice()))
+}
+
+fn write_meta_and_mdat<W: Write>(
+    w: &mut W,
+    ftyp: &[u8],
+    children_before_iloc: &[u8],
+    items: &[(u16, &[u8])],
+) -> io::Result<()> {
+    let temp_iloc = build_iloc(
+        &items
+            .iter()
+            .map(|(item_id, data)| IlocEntry {
+                item_id: *item_id,
+                offset: 0,
+                length: data.len() as u32,
+            })
+            .collect::<Vec<_>>(),
+    );
     let meta_content_size = 4 + children_before_iloc.len() as u32 + temp_iloc.len() as u32;
     let meta_size = 8 + meta_content_size;
     let data_offset = ftyp.len() as u32 + meta_size + 8;
 
-    let base_offset = data_offset;
-    let tmap_offset = base_offset + base_data.len() as u32;
-    let gain_offset = tmap_offset + tmap_payload.len() as u32;
-    let iloc = build_iloc(&[
-        IlocEntry {
-            item_id: 1,
-            offset: base_offset,
-            length: base_data.len() as u32,
-        },
-        IlocEntry {
-            item_id: 2,
-            offset: tmap_offset,
-            length: tmap_payload.len() as u32,
-        },
-        IlocEntry {
-            item_id: 3,
-            offset: gain_offset,
-            length: gain_map_data.len() as u32,
-        },
-    ]);
+    let mut entries = Vec::with_capacity(items.len());
+    let mut mdat_payload = Vec::new();
+    for (item_id, data) in items {
+        entries.push(IlocEntry {
+            item_id: *item_id,
+            offset: data_offset + mdat_payload.len() as u32,
+            length: data.len() as u32,
+        });
+        mdat_payload.extend_from_slice(data);
+    }
+    let iloc = build_iloc(&entries);
 
     let mut meta_payload = Vec::new();
     meta_payload.push(0);
     meta_payload.extend_from_slice(&0u32.to_be_bytes()[1..4]);
-    meta_payload.extend_from_slice(&children_before_iloc);
+    meta_payload.extend_from_slice(children_before_iloc);
     meta_payload.extend_from_slice(&iloc);
     let meta = box_wrap(b"meta", &meta_payload);
 
     let mdat = box_wrap(b"mdat", &mdat_payload);
-    w.write_all(&ftyp)?;
+    w.write_all(ftyp)?;
     w.write_all(&meta)?;
     w.write_all(&mdat)?;
     Ok(())
@@ -483,19 +500,20 @@ fn build_infe(entry: &InfeEntry<'_>) -> Vec<u8> {
     full_box(b"infe", 2, if entry.hidden { 1 } else { 0 }, &payload)
 }
 
-fn build_iinf_single() -> Vec<u8> {
-    let entries = [InfeEntry {
+fn build_iinf_single(first_aux_item_id: u16, aux_count: usize) -> Vec<u8> {
+    let mut entries = vec![InfeEntry {
         item_id: 1,
         item_type: *b"av01",
         hidden: false,
         name: "Color",
     }];
+    entries.extend(auxiliary_infe_entries(first_aux_item_id, aux_count));
     build_iinf(&entries)
 }
 
 #[cfg(feature = "heic")]
-fn build_iinf_tmap() -> Vec<u8> {
-    let entries = [
+fn build_iinf_tmap(first_aux_item_id: u16, aux_count: usize) -> Vec<u8> {
+    let mut entries = vec![
         InfeEntry {
             item_id: 1,
             item_type: *b"av01",
@@ -515,9 +533,22 @@ fn build_iinf_tmap() -> Vec<u8> {
             name: "GMap",
         },
     ];
+    entries.extend(auxiliary_infe_entries(first_aux_item_id, aux_count));
     build_iinf(&entries)
 }
 
+fn auxiliary_infe_entries(
+    first_aux_item_id: u16,
+    aux_count: usize,
+) -> impl Iterator<Item = InfeEntry<'static>> {
+    (0..aux_count).map(move |i| InfeEntry {
+        item_id: first_aux_item_id + i as u16,
+        item_type: *b"av01",
+        hidden: false,
+        name: "Aux",
+    })
+}
+
 fn build_iinf(entries: &[InfeEntry<'_>]) -> Vec<u8> {
     let mut p = Vec::new();
     p.extend_from_slice(&(entries.len() as u16).to_be_bytes());
@@ -528,14 +559,39 @@ fn build_iinf(entries: &[InfeEntry<'_>]) -> Vec<u8> {
 }
 
 #[cfg(feature = "heic")]
-fn build_iref_tmap() -> Vec<u8> {
+fn build_iref_tmap(first_aux_item_id: u16, aux_count: usize) -> Vec<u8> {
     let mut dimg_payload = Vec::new();
     dimg_payload.extend_from_slice(&2u16.to_be_bytes()); // from_item_id (tmap)
     dimg_payload.extend_from_slice(&2u16.to_be_bytes()); // reference_count
     dimg_payload.extend_from_slice(&1u16.to_be_bytes()); // base
     dimg_payload.extend_from_slice(&3u16.to_be_bytes()); // gain map
-    let dimg = box_wrap(b"dimg", &dimg_payload);
-    full_box(b"iref", 0, 0, &dimg)
+    let mut refs = box_wrap(b"dimg", &dimg_payload);
+    refs.extend_from_slice(&build_auxl_refs(first_aux_item_id, aux_count));
+    full_box(b"iref", 0, 0, &refs)
+}
+
+fn build_iref_auxiliary(first_aux_item_id: u16, aux_count: usize) -> Vec<u8> {
+    if aux_count == 0 {
+        return Vec::new();
+    }
+    full_box(
+        b"iref",
+        0,
+        0,
+        &build_auxl_refs(first_aux_item_id, aux_count),
+    )
+}
+
+fn build_auxl_refs(first_aux_item_id: u16, aux_count: usize) -> Vec<u8> {
+    let mut out = Vec::new();
+    for i in 0..aux_count {
+        let mut auxl_payload = Vec::new();
+        auxl_payload.extend_from_slice(&(first_aux_item_id + i as u16).to_be_bytes()); // from_item_id
+        auxl_payload.extend_from_slice(&1u16.to_be_bytes()); // reference_count
+        auxl_payload.extend_from_slice(&1u16.to_be_bytes()); // primary item
+        out.extend_from_slice(&box_wrap(b"auxl", &auxl_payload));
+    }
+    out
 }
 
 #[cfg(feature = "heic")]
@@ -549,14 +605,18 @@ fn build_grpl_altr_tmap() -> Vec<u8> {
     box_wrap(b"grpl", &altr)
 }
 
-fn build_iprp_single(config: &AvifConfig) -> Vec<u8> {
+fn build_iprp_single(
+    config: &AvifConfig,
+    first_aux_item_id: u16,
+    aux_items: &[AvifAuxiliaryItem],
+) -> Vec<u8> {
     let mut ipco_payload = Vec::new();
     let mut next_property_index = 1u8;
     let mut base_associations = vec![
         append_property(
             &mut ipco_payload,
             &mut next_property_index,
-            build_av1c(config.video_signal.bit_depth, &config.config_obus),
+            build_av1c(&config.av1c),
         ),
         append_property(
             &mut ipco_payload,
@@ -588,8 +648,17 @@ fn build_iprp_single(config: &AvifConfig) -> Vec<u8> {
             build_mdcv(&mdcv),
         ));
     }
+    append_orientation_properties(
+        &mut ipco_payload,
+        &mut next_property_index,
+        config.orientation,
+        &mut base_associations,
+    );
+    let aux_associations =
+        append_auxiliary_properties(&mut ipco_payload, &mut next_property_index, aux_items);
     let ipco = box_wrap(b"ipco", &ipco_payload);
-    let ipma_entries = [(1u16, base_associations.as_slice())];
+    let mut ipma_entries = vec![(1u16, base_associations.as_slice())];
+    ipma_entries.extend(auxiliary_ipma_entries(first_aux_item_id, &aux_associations));
     let ipma = build_ipma(&ipma_entries);
 
     let mut p = Vec::new();
@@ -599,7 +668,12 @@ fn build_iprp_single(config: &AvifConfig) -> Vec<u8> {
 }
 
 #[cfg(feature = "heic")]
-fn build_iprp_tmap(base: &AvifConfig, gain: &AvifConfig) -> Vec<u8> {
+fn build_iprp_tmap(
+    base: &AvifConfig,
+    gain: &AvifConfig,
+    first_aux_item_id: u16,
+    aux_items: &[AvifAuxiliaryItem],
+) -> Vec<u8> {
     let mut ipco_payload = Vec::new();
     let mut next_property_index = 1u8;
 
@@ -607,7 +681,7 @@ fn build_iprp_tmap(base: &AvifConfig, gain: &AvifConfig) -> Vec<u8> {
     base_associations.push(append_property(
         &mut ipco_payload,
         &mut next_property_index,
-        build_av1c(base.video_signal.bit_depth, &base.config_obus),
+        build_av1c(&base.av1c),
     ));
     base_associations.push(append_property(
         &mut ipco_payload,
@@ -638,6 +712,12 @@ fn build_iprp_tmap(base: &AvifConfig, gain: &AvifConfig) -> Vec<u8> {
             build_mdcv(&mdcv),
         ));
     }
+    append_orientation_properties(
+        &mut ipco_payload,
+        &mut next_property_index,
+        base.orientation,
+        &mut base_associations,
+    );
 
     let mut tmap_associations = Vec::new();
     tmap_associations.push(append_property(
@@ -655,12 +735,18 @@ fn build_iprp_tmap(base: &AvifConfig, gain: &AvifConfig) -> Vec<u8> {
         &mut next_property_index,
         build_pixi(base.video_signal.bit_depth),
     ));
+    append_orientation_properties(
+        &mut ipco_payload,
+        &mut next_property_index,
+        base.orientation,
+        &mut tmap_associations,
+    );
 
     let mut gain_associations = Vec::new();
     gain_associations.push(append_property(
         &mut ipco_payload,
         &mut next_property_index,
-        build_av1c(gain.video_signal.bit_depth, &gain.config_obus),
+        build_av1c(&gain.av1c),
     ));
     gain_associations.push(append_property(
         &mut ipco_payload,
@@ -677,12 +763,15 @@ fn build_iprp_tmap(base: &AvifConfig, gain: &AvifConfig) -> Vec<u8> {
         &mut next_property_index,
         build_pixi(gain.video_signal.bit_depth),
     ));
+    let aux_associations =
+        append_auxiliary_properties(&mut ipco_payload, &mut next_property_index, aux_items);
     let ipco = box_wrap(b"ipco", &ipco_payload);
-    let ipma_entries = [
+    let mut ipma_entries = vec![
         (1u16, base_associations.as_slice()),
         (2u16, tmap_associations.as_slice()),
         (3u16, gain_associations.as_slice()),
     ];
+    ipma_entries.extend(auxiliary_ipma_entries(first_aux_item_id, &aux_associations));
     let ipma = build_ipma(&ipma_entries);
 
     let mut p = Vec::new();
@@ -691,6 +780,78 @@ fn build_iprp_tmap(base: &AvifConfig, gain: &AvifConfig) -> Vec<u8> {
     box_wrap(b"iprp", &p)
 }
 
+fn append_auxiliary_properties(
+    ipco_payload: &mut Vec<u8>,
+    next_property_index: &mut u8,
+    aux_items: &[AvifAuxiliaryItem],
+) -> Vec<Vec<u8>> {
+    aux_items
+        .iter()
+        .map(|aux| {
+            vec![
+                append_property(
+                    ipco_payload,
+                    next_property_index,
+                    build_av1c(&aux.config.av1c),
+                ),
+                append_property(
+                    ipco_payload,
+                    next_property_index,
+                    build_ispe(aux.config.width, aux.config.height),
+                ),
+                append_property(
+                    ipco_payload,
+                    next_property_index,
+                    build_pixi(aux.config.video_signal.bit_depth),
+                ),
+                append_property(ipco_payload, next_property_index, build_auxc(&aux.aux_type)),
+            ]
+        })
+        .collect()
+}
+
+fn auxiliary_ipma_entries(
+    first_aux_item_id: u16,
+    aux_associations: &[Vec<u8>],
+) -> impl Iterator<Item = (u16, &[u8])> {
+    aux_associations
+        .iter()
+        .enumerate()
+        .map(move |(i, associations)| (first_aux_item_id + i as u16, associations.as_slice()))
+}
+
+/// Appends `irot`/`imir` for a non-identity orientation. These are
+/// transformative properties, so they follow every descriptive one.
+fn append_orientation_properties(
+    ipco_payload: &mut Vec<u8>,
+    next_property_index: &mut u8,
+    orientation: Orientation,
+    associations: &mut Vec<u8>,
+) {
+    let (quarter_turns, mirror_axis) = orientation.heif_transforms();
+    if quarter_turns != 0 {
+        associations.push(append_property(
+            ipco_payload,
+            next_property_index,
+            box_wrap(b"irot", &[quarter_turns]),
+        ));
+    }
+    if let Some(axis) = mirror_axis {
+        associations.push(append_property(
+            ipco_payload,
+            next_property_index,
+            box_wrap(b"imir", &[axis]),
+        ));
+    }
+}
+
+fn build_auxc(aux_type: &str) -> Vec<u8> {
+    let mut p = Vec::new();
+    p.extend_from_slice(aux_type.as_bytes());
+    p.push(0);
+    full_box(b"auxC", 0, 0, &p)
+}
+
 fn build_ispe(width: u32, height: u32) -> Vec<u8> {
     let mut p = Vec::new();
     p.extend_from_slice(&width.to_be_bytes());
@@ -766,6 +927,7 @@ mod tests {
                 transfer_characteristics: 16,
                 matrix_coefficients: 9,
             }),
+            ..VideoSignal::default()
         }
     }
 
@@ -773,6 +935,11 @@ mod tests {
         haystack.windows(needle.len()).any(|w| w == needle)
     }
 
+    pub(super) fn test_av1c(config_obus: &[u8]) -> Vec<u8> {
+        let level = wav1c::sequence::SEQ_LEVEL_IDX_5_1;
+        wav1c::sequence::codec_configuration_record(&VideoSignal::default(), level, config_obus)
+    }
+
     #[test]
     fn single_item_avif_includes_clli_and_mdcv_properties() {
         let cll = ContentLightLevel {
@@ -788,14 +955,15 @@ mod tests {
         let config = AvifConfig {
             width: 64,
             height: 64,
-            config_obus: vec![0x0A, 0x01, 0x80],
+            av1c: test_av1c(&[0x0A, 0x01, 0x80]),
             video_signal: sample_signal(BitDepth::Ten),
             content_light: Some(cll),
             mastering_display: Some(mdcv),
+            orientation: Orientation::IDENTITY,
         };
 
         let mut out = Vec::new();
-        write_avif(&mut out, &config, &[0x12, 0x00, 0x11, 0x22]).expect("write");
+        write_avif(&mut out, &config, &[0x12, 0x00, 0x11, 0x22], &[]).expect("write");
 
         assert!(contains(&out, &build_clli(&cll)));
         assert!(contains(&out, &build_mdcv(&mdcv)));
@@ -806,19 +974,75 @@ mod tests {
         let config = AvifConfig {
             width: 64,
             height: 64,
-            config_obus: vec![0x0A, 0x01, 0x80],
+            av1c: test_av1c(&[0x0A, 0x01, 0x80]),
             video_signal: sample_signal(BitDepth::Ten),
             content_light: None,
             mastering_display: None,
+            orientation: Orientation::IDENTITY,
         };
 
         let mut out = Vec::new();
-        write_avif(&mut out, &config, &[0x12, 0x00, 0x11, 0x22]).expect("write");
+        write_avif(&mut out, &config, &[0x12, 0x00, 0x11, 0x22], &[]).expect("write");
 
         assert!(!contains(&out, b"clli"));
         assert!(!contains(&out, b"mdcv"));
     }
 
+    #[test]
+    fn auxiliary_items_carry_auxc_and_auxl_to_primary() {
+        let config = AvifConfig {
+            width: 64,
+            height: 64,
+            av1c: test_av1c(&[0x0A, 0x01, 0x80]),
+            video_signal: sample_signal(BitDepth::Eight),
+            content_light: None,
+            mastering_display: None,
+            orientation: Orientation::IDENTITY,
+        };
+        let depth_type = "urn:com:apple:photo:2018:aux:portraiteffectsmatte";
+        let aux = AvifAuxiliaryItem {
+            aux_type: depth_type.to_owned(),
+            config: AvifConfig {
+                width: 32,
+                height: 32,
+                av1c: test_av1c(&[0x0A, 0x01, 0x81]),
+                video_signal: VideoSignal::default(),
+                content_light: None,
+                mastering_display: None,
+                orientation: Orientation::IDENTITY,
+            },
+            obu_data: vec![0x12, 0x00, 0x33, 0x44],
+        };
+
+        let mut out = Vec::new();
+        write_avif(&mut out, &config, &[0x12, 0x00, 0x11, 0x22], &[aux]).expect("write");
+
+        assert!(contains(&out, &build_auxc(depth_type)));
+        assert!(contains(&out, b"\x00\x02\x00\x00av01Aux\x00"));
+        assert!(contains(&out, b"auxl\x00\x02\x00\x01\x00\x01"));
+        assert!(contains(&out, &build_ispe(32, 32)));
+        assert!(out.ends_with(&[0x0A, 0x01, 0x81, 0x33, 0x44]));
+    }
+
+    #[test]
+    fn single_item_avif_has_no_iref_without_auxiliary_items() {
+        let config = AvifConfig {
+            width: 64,
+            height: 64,
+            av1c: test_av1c(&[0x0A, 0x01, 0x80]),
+            video_signal: sample_signal(BitDepth::Eight),
+            content_light: None,
+            mastering_display: None,
+            orientation: Orientation::IDENTITY,
+        };
+
+        let mut out = Vec::new();
+        write_avif(&mut out, &config, &[0x12, 0x00, 0x11, 0x22], &[]).expect("write");
+
+        assert!(!contains(&out, b"iref"));
+        assert!(!contains(&out, b"auxC"));
+    }
+
     #[test]
     fn item_data_uses_config_obus_and_drops_packet_seq_prefix() {
         let config_obus = vec![0x0A, 0x01, 0x1C];
@@ -833,6 +1057,7 @@ mod tests {
 
 #[cfg(all(test, feature = "heic"))]
 mod heic_tests {
+    use super::tests::test_av1c;
     use super::*;
     use wav1c::{ColorDescription, ColorRange, VideoSignal};
 
@@ -982,15 +1207,16 @@ mod heic_tests {
         let base_cfg = AvifConfig {
             width: 640,
             height: 480,
-            config_obus: vec![0x01, 0x02, 0x03],
+            av1c: test_av1c(&[0x01, 0x02, 0x03]),
             video_signal: sample_signal(BitDepth::Eight),
             content_light: None,
             mastering_display: None,
+            orientation: Orientation::IDENTITY,
         };
         let gain_cfg = AvifConfig {
             width: 320,
             height: 240,
-            config_obus: vec![0x04, 0x05, 0x06],
+            av1c: test_av1c(&[0x04, 0x05, 0x06]),
             video_signal: VideoSignal {
                 bit_depth: BitDepth::Eight,
                 color_range: ColorRange::Full,
@@ -1002,6 +1228,7 @@ mod heic_tests {
             },
             content_light: None,
             mastering_display: None,
+            orientation: Orientation::IDENTITY,
         };
         let tmap = vec![0u8; 62];
         let mut out = Vec::new();
@@ -1012,6 +1239,7 @@ mod heic_tests {
             &gain_cfg,
             &[0x12, 0x00, 0x33, 0x44],
             &tmap,
+            &[],
         )
         .expect("write");
 
@@ -1027,4 +1255,92 @@ mod heic_tests {
             b"altr\x00\x00\x00\x00\x00\x00\x00\x01\x00\x00\x00\x02\x00\x00\x00\x02\x00\x00\x00\x01"
         ));
     }
+
+    #[test]
+    fn gain_map_avif_places_auxiliary_items_after_gain_map() {
+        let base_cfg = AvifConfig {
+            width: 640,
+            height: 480,
+            av1c: test_av1c(&[0x01, 0x02, 0x03]),
+            video_signal: sample_signal(BitDepth::Eight),
+            content_light: None,
+            mastering_display: None,
+            orientation: Orientation::IDENTITY,
+        };
+        let gain_cfg = AvifConfig {
+            width: 320,
+            height: 240,
+            av1c: test_av1c(&[0x04, 0x05, 0x06]),
+            video_signal: sample_signal(BitDepth::Eight),
+            content_light: None,
+            mastering_display: None,
+            orientation: Orientation::IDENTITY,
+        };
+        let aux = AvifAuxiliaryItem {
+            aux_type: "urn:mpeg:hevc:2015:auxid:2".to_owned(),
+            config: AvifConfig {
+                width: 160,
+                height: 120,
+                av1c: test_av1c(&[0x07, 0x08, 0x09]),
+                video_signal: VideoSignal::default(),
+                content_light: None,
+                mastering_display: None,
+                orientation: Orientation::IDENTITY,
+            },
+            obu_data: vec![0x12, 0x00, 0x55, 0x66],
+        };
+        let tmap = vec![0u8; 62];
+        let mut out = Vec::new();
+        write_avif_with_tmap_gain_map(
+            &mut out,
+            &base_cfg,
+            &[0x12, 0x00, 0x11, 0x22],
+            &gain_cfg,
+            &[0x12, 0x00, 0x33, 0x44],
+            &tmap,
+            &[aux],
+        )
+        .expect("write");
+
+        assert!(contains(&out, b"\x00\x04\x00\x00av01Aux\x00"));
+        assert!(contains(&out, b"auxl\x00\x04\x00\x01\x00\x01"));
+        assert!(contains(&out, &build_auxc("urn:mpeg:hevc:2015:auxid:2")));
+        assert!(out.ends_with(&[0x07, 0x08, 0x09, 0x55, 0x66]));
+    }
+
+    #[test]
+    fn rotated_still_writes_irot_and_imir_with_coded_ispe() {
+        let mut config = AvifConfig {
+            width: 64,
+            height: 48,
+            av1c: test_av1c(&[0x0A, 0x01, 0x80]),
+            video_signal: sample_signal(BitDepth::Eight),
+            content_light: None,
+            mastering_display: None,
+            orientation: Orientation::from_exif(6).unwrap(),
+        };
+        let mut ispe = Vec::new();
+        ispe.extend_from_slice(&64u32.to_be_bytes());
+        ispe.extend_from_slice(&48u32.to_be_bytes());
+        let ispe = full_box(b"ispe", 0, 0, &ispe);
+
+        let mut out = Vec::new();
+        write_avif(&mut out, &config, &[0x12, 0x00, 0x11], &[]).expect("write");
+        assert!(contains(&out, &box_wrap(b"irot", &[3])));
+        assert!(!contains(&out, b"imir"));
+        assert!(contains(&out, &ispe));
+
+        config.orientation = Orientation::from_exif(7).unwrap();
+        let mut out = Vec::new();
+        write_avif(&mut out, &config, &[0x12, 0x00, 0x11], &[]).expect("write");
+        assert!(contains(&out, &box_wrap(b"irot", &[1])));
+        assert!(contains(&out, &box_wrap(b"imir", &[0])));
+        assert!(contains(&out, &ispe));
+
+        config.orientation = Orientation::IDENTITY;
+        let mut out = Vec::new();
+        write_avif(&mut out, &config, &[0x12, 0x00, 0x11], &[]).expect("write");
+        assert!(!contains(&out, b"irot"));
+        assert!(!contains(&out, b"imir"));
+    }
 }
@@ -0,0 +1,179 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use wav1c::y4m::FramePixels;
+use wav1c::{ColorDescription, ColorRange};
+
+use crate::heif::HeifFile;
+use crate::orientation::Orientation;
+
+pub struct AvifDecodeResult {
+    pub frame: FramePixels,
+    pub color_description: Option<ColorDescription>,
+    /// The source's `irot`/`imir` transform; `frame` holds the stored
+    /// (untransformed) samples, so it must be carried to the output.
+    pub orientation: Orientation,
+}
+
+pub fn decode_avif(path: &str) -> Result<AvifDecodeResult, String> {
+    let data = std::fs::read(path).map_err(|e| format!("failed to read AVIF file: {e}"))?;
+    let file = HeifFile::parse(&data)?;
+    let primary = file
+        .primary_item()
+        .ok_or_else(|| format!("primary item {} is not declared", file.primary_item_id))?;
+    if &primary.item_type != b"av01" {
+        return Err(format!(
+            "primary item type '{}' is not supported (expected av01)",
+            primary.item_type_str()
+        ));
+    }
+
+    let obu_stream = build_section5_stream(&file.item_data(&data, primary.id)?);
+    let mut frame = decode_with_dav1d(&obu_stream)?;
+    primary
+        .orientation
+        .validate_coded_dimensions((frame.width, frame.height), primary.dimensions)?;
+
+    frame.color_range = match primary.nclx {
+        Some(nclx) if nclx.full_range => ColorRange::Full,
+        _ => ColorRange::Limited,
+    };
+    // 2/2/2 is how AVIF writers (including ours) spell "unspecified".
+    let color_description = primary.nclx.and_then(|nclx| {
+        if (
+            nclx.color_primaries,
+            nclx.transfer_characteristics,
+            nclx.matrix_coefficients,
+        ) == (2, 2, 2)
+        {
+            return None;
+        }
+        Some(ColorDescription {
+            color_primaries: u8::try_from(nclx.color_primaries).ok()?,
+            transfer_characteristics: u8::try_from(nclx.transfer_characteristics).ok()?,
+            matrix_coefficients: u8::try_from(nclx.matrix_coefficients).ok()?,
+        })
+    });
+
+    Ok(AvifDecodeResult {
+        frame,
+        color_description,
+        orientation: primary.orientation,
+    })
+}
+
+fn build_section5_stream(item_data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(item_data.len() + 2);
+    out.extend_from_slice(&[0x12, 0x00]); // temporal delimiter
+    out.extend_from_slice(item_data);
+    out
+}
+
+fn dav1d_path() -> PathBuf {
+    std::env::var_os("DAV1D")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("dav1d"))
+}
+
+/// A file in the temp directory for dav1d to read or write. It is created
+/// exclusively, so a name another process got to first is never reused, and
+/// removed on drop, whichever way the decode ends.
+struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    fn create(extension: &str) -> Result<(Self, File), String> {
+        static NEXT: AtomicU32 = AtomicU32::new(0);
+        loop {
+            let n = NEXT.fetch_add(1, Ordering::Relaxed);
+            let path =
+                std::env::temp_dir().join(format!("wav1c_dav1d_{}_{n}.{extension}", process::id()));
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => return Ok((Self { path }, file)),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(format!("failed to create {}: {e}", path.display())),
+            }
+        }
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Decodes every frame of an AV1 file dav1d reads directly (IVF, Annex B
+/// or a section 5 OBU stream), e.g. for `wav1c compare`.
+pub fn decode_av1_file(path: &Path) -> Result<Vec<FramePixels>, String> {
+    let (y4m, _) = TempFile::create("y4m")?;
+    run_dav1d(path, &y4m.path)
+}
+
+fn decode_with_dav1d(obu_stream: &[u8]) -> Result<FramePixels, String> {
+    let (obu, mut obu_file) = TempFile::create("obu")?;
+    obu_file
+        .write_all(obu_stream)
+        .map_err(|e| format!("failed to write {}: {e}", obu.path.display()))?;
+    drop(obu_file);
+    let (y4m, _) = TempFile::create("y4m")?;
+    run_dav1d(&obu.path, &y4m.path).map(|mut frames| frames.swap_remove(0))
+}
+
+/// Runs dav1d on `input`; the result holds at least one frame.
+fn run_dav1d(input: &Path, y4m_path: &Path) -> Result<Vec<FramePixels>, String> {
+    let dav1d = dav1d_path();
+    let output = Command::new(&dav1d)
+        .arg("-q")
+        .arg("-i")
+        .arg(input)
+        .arg("-o")
+        .arg(y4m_path)
+        .output()
+        .map_err(|e| {
+            format!(
+                "failed to run {} ({e}); set DAV1D or install dav1d in PATH",
+                dav1d.display()
+            )
+        })?;
+    if !output.status.success() {
+        return Err(format!(
+            "dav1d failed to decode {}: {}",
+            input.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    let frames = FramePixels::all_from_y4m_file(y4m_path)
+        .map_err(|e| format!("failed to read dav1d output: {e}"))?;
+    if frames.is_empty() {
+        return Err("dav1d produced no frames".to_owned());
+    }
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn temp_files_are_unique_and_removed_on_drop() {
+        let (a, _) = TempFile::create("y4m").unwrap();
+        let (b, _) = TempFile::create("y4m").unwrap();
+        assert_ne!(a.path, b.path);
+        let path = a.path.clone();
+        assert!(path.exists());
+        drop(a);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn section5_stream_starts_with_temporal_delimiter() {
+        let stream = build_section5_stream(&[0x0A, 0x01, 0x80, 0x32, 0x01, 0xAA]);
+        assert_eq!(stream, vec![0x12, 0x00, 0x0A, 0x01, 0x80, 0x32, 0x01, 0xAA]);
+    }
+}
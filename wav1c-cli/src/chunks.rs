@@ -0,0 +1,182 @@
+//! Scheduling for `--scene-chunks`: the independent segments to encode and a
+//! work-stealing pool that keeps every worker busy until none is left.
+//!
+//! Each worker starts with a contiguous run of chunks of about equal frame
+//! count and takes them front to back. A worker that runs dry steals from the
+//! back of whichever run has the most frames left, so one long scene no
+//! longer holds up the chunks queued behind it.
+
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// How `--jobs` above one encodes chunks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkWorkers {
+    /// A child `wav1c` process per chunk, logging to the chunk's `.log`.
+    Processes,
+    /// Threads of this process, sharing the decoded input.
+    Threads,
+}
+
+impl ChunkWorkers {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "processes" => Ok(Self::Processes),
+            "threads" => Ok(Self::Threads),
+            _ => Err(format!(
+                "--chunk-workers must be processes or threads, got: {value}"
+            )),
+        }
+    }
+}
+
+/// `scenes` split every `keyint` frames from their start, so each piece is
+/// one GOP. The keyframes land where they would in a scene encoded whole.
+pub fn gop_ranges(scenes: &[Range<usize>], keyint: usize) -> Vec<Range<usize>> {
+    if keyint == 0 {
+        return scenes.to_vec();
+    }
+    scenes
+        .iter()
+        .flat_map(|scene| {
+            scene
+                .clone()
+                .step_by(keyint)
+                .map(move |start| start..(start + keyint).min(scene.end))
+        })
+        .collect()
+}
+
+/// Per-worker deques of chunk indices, weighted by `costs`.
+pub struct WorkQueue {
+    costs: Vec<usize>,
+    deques: Vec<Mutex<VecDeque<usize>>>,
+}
+
+impl WorkQueue {
+    /// Deals `costs.len()` chunks out to `workers` in contiguous runs of
+    /// about `total / workers` cost each, placing every chunk by the middle
+    /// of its cost.
+    pub fn new(costs: &[usize], workers: usize) -> Self {
+        let workers = workers.max(1);
+        let total: usize = costs.iter().sum();
+        let mut deques: Vec<VecDeque<usize>> = vec![VecDeque::new(); workers];
+        let mut dealt = 0;
+        for (i, &cost) in costs.iter().enumerate() {
+            let worker = ((dealt + cost / 2) * workers / total.max(1)).min(workers - 1);
+            deques[worker].push_back(i);
+            dealt += cost;
+        }
+        Self {
+            costs: costs.to_vec(),
+            deques: deques.into_iter().map(Mutex::new).collect(),
+        }
+    }
+
+    /// The next chunk for `worker`: the front of its own run, else the back
+    /// of the run with the most cost left. `None` once every run is empty.
+    pub fn next(&self, worker: usize) -> Option<usize> {
+        if let Some(i) = self.deques[worker].lock().unwrap().pop_front() {
+            return Some(i);
+        }
+        loop {
+            let victim = self
+                .deques
+                .iter()
+                .enumerate()
+                .map(|(w, deque)| {
+                    let left: usize = deque.lock().unwrap().iter().map(|&i| self.costs[i]).sum();
+                    (left, w)
+                })
+                .filter(|&(left, _)| left > 0)
+                .max()?
+                .1;
+            // Another worker may have emptied the victim meanwhile.
+            if let Some(i) = self.deques[victim].lock().unwrap().pop_back() {
+                return Some(i);
+            }
+        }
+    }
+}
+
+/// Runs `job` on every chunk with up to `workers` threads stealing work
+/// from each other. After a failure no further chunks are started; the
+/// error of the lowest failed chunk is returned.
+pub fn run<F>(costs: &[usize], workers: usize, job: F) -> Result<(), String>
+where
+    F: Fn(usize) -> Result<(), String> + Sync,
+{
+    let workers = workers.clamp(1, costs.len().max(1));
+    let queue = WorkQueue::new(costs, workers);
+    let failed = AtomicBool::new(false);
+    let errors = Mutex::new(Vec::new());
+    std::thread::scope(|scope| {
+        for worker in 0..workers {
+            let (queue, failed, errors, job) = (&queue, &failed, &errors, &job);
+            scope.spawn(move || {
+                while !failed.load(Ordering::Relaxed) {
+                    let Some(i) = queue.next(worker) else { break };
+                    if let Err(e) = job(i) {
+                        failed.store(true, Ordering::Relaxed);
+                        errors.lock().unwrap().push((i, e));
+                    }
+                }
+            });
+        }
+    });
+    match errors.into_inner().unwrap().into_iter().min() {
+        Some((_, e)) => Err(e),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gops_split_scenes_from_their_start() {
+        assert_eq!(
+            gop_ranges(&[0..4, 4..11], 3),
+            vec![0..3, 3..4, 4..7, 7..10, 10..11]
+        );
+        assert_eq!(gop_ranges(&[0..4, 4..11], 0), vec![0..4, 4..11]);
+    }
+
+    #[test]
+    fn idle_workers_steal_the_largest_backlog() {
+        let queue = WorkQueue::new(&[10, 10, 10, 10, 1, 1, 1, 1], 2);
+        assert_eq!(queue.next(0), Some(0));
+        assert_eq!(queue.next(1), Some(2));
+        // Worker 1 drains its run, then steals from the back of worker 0's.
+        for expected in [3, 4, 5, 6, 7, 1] {
+            assert_eq!(queue.next(1), Some(expected));
+        }
+        assert_eq!(queue.next(0), None);
+        assert_eq!(queue.next(1), None);
+    }
+
+    #[test]
+    fn pool_runs_every_chunk_once_and_reports_the_first_failure() {
+        let done = Mutex::new(Vec::new());
+        run(&[3, 1, 4, 1, 5, 9, 2, 6], 3, |i| {
+            done.lock().unwrap().push(i);
+            Ok(())
+        })
+        .unwrap();
+        let mut done = done.into_inner().unwrap();
+        done.sort();
+        assert_eq!(done, (0..8).collect::<Vec<_>>());
+
+        let result = run(&[1; 6], 1, |i| {
+            if i >= 2 {
+                Err(format!("chunk {i} failed"))
+            } else {
+                Ok(())
+            }
+        });
+        assert_eq!(result, Err("chunk 2 failed".to_owned()));
+    }
+}
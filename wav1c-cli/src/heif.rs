@@ -0,0 +1,839 @@
+use std::collections::HashMap;
+
+use crate::orientation::Orientation;
+
+pub const XMP_CONTENT_TYPE: &str = "application/rdf+xml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Nclx {
+    pub color_primaries: u16,
+    pub transfer_characteristics: u16,
+    pub matrix_coefficients: u16,
+    pub full_range: bool,
+}
+
+/// Fields of an `av1C` codec configuration record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Av1Config {
+    pub seq_profile: u8,
+    pub seq_level_idx: u8,
+    pub high_tier: bool,
+    pub bit_depth: u8,
+    pub monochrome: bool,
+    pub chroma_subsampling_x: bool,
+    pub chroma_subsampling_y: bool,
+    pub chroma_sample_position: u8,
+    pub config_obus_len: usize,
+}
+
+impl Av1Config {
+    fn parse(payload: &[u8]) -> Result<Self, String> {
+        let header = payload.get(..4).ok_or("truncated av1C box")?;
+        if header[0] != 0x81 {
+            return Err(format!(
+                "unsupported av1C marker/version byte {:#04x}",
+                header[0]
+            ));
+        }
+        let high_bitdepth = header[2] & 0x40 != 0;
+        let twelve_bit = header[2] & 0x20 != 0;
+        Ok(Self {
+            seq_profile: header[1] >> 5,
+            seq_level_idx: header[1] & 0x1F,
+            high_tier: header[2] & 0x80 != 0,
+            bit_depth: match (high_bitdepth, twelve_bit) {
+                (true, true) => 12,
+                (true, false) => 10,
+                _ => 8,
+            },
+            monochrome: header[2] & 0x10 != 0,
+            chroma_subsampling_x: header[2] & 0x08 != 0,
+            chroma_subsampling_y: header[2] & 0x04 != 0,
+            chroma_sample_position: header[2] & 0x03,
+            config_obus_len: payload.len() - 4,
+        })
+    }
+
+    pub fn chroma_format(&self) -> &'static str {
+        match (
+            self.monochrome,
+            self.chroma_subsampling_x,
+            self.chroma_subsampling_y,
+        ) {
+            (true, _, _) => "4:0:0",
+            (false, true, true) => "4:2:0",
+            (false, true, false) => "4:2:2",
+            (false, false, _) => "4:4:4",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeifItem {
+    pub id: u32,
+    pub item_type: [u8; 4],
+    pub content_type: Option<String>,
+    pub hidden: bool,
+    pub dimensions: Option<(u32, u32)>,
+    pub nclx: Option<Nclx>,
+    /// Display transform from the item's `irot`/`imir` properties.
+    pub orientation: Orientation,
+    pub aux_type: Option<String>,
+    pub aux_for: Option<u32>,
+    pub described_items: Vec<u32>,
+    pub av1_config: Option<Av1Config>,
+    /// Bits per channel from `pixi`.
+    pub bits_per_channel: Vec<u8>,
+    /// Size of an ICC profile given in `colr` (`prof`/`rICC`).
+    pub icc_profile_len: Option<usize>,
+    /// Four-character codes of every associated property, in `ipma` order.
+    pub property_types: Vec<[u8; 4]>,
+    construction_method: u8,
+    extents: Vec<(u64, u64)>,
+}
+
+impl HeifItem {
+    pub fn item_type_str(&self) -> String {
+        String::from_utf8_lossy(&self.item_type).into_owned()
+    }
+}
+
+#[derive(Debug)]
+pub struct HeifFile {
+    pub major_brand: [u8; 4],
+    pub compatible_brands: Vec<[u8; 4]>,
+    pub primary_item_id: u32,
+    pub items: Vec<HeifItem>,
+    idat: Vec<u8>,
+}
+
+impl HeifFile {
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        let mut major_brand = None;
+        let mut compatible_brands = Vec::new();
+        let mut meta = None;
+
+        for (box_type, payload) in BoxIter::new(data) {
+            let payload = payload?;
+            match &box_type {
+                b"ftyp" => {
+                    if payload.len() < 8 {
+                        return Err("truncated ftyp box".to_owned());
+                    }
+                    major_brand = Some(fourcc(payload, 0)?);
+                    compatible_brands = payload[8..]
+                        .chunks_exact(4)
+                        .map(|c| [c[0], c[1], c[2], c[3]])
+                        .collect();
+                }
+                b"meta" => meta = Some(payload),
+                _ => {}
+            }
+        }
+
+        let major_brand = major_brand.ok_or("missing ftyp box")?;
+        let meta = meta.ok_or("missing meta box")?;
+        let mut file = parse_meta(full_box_payload(meta)?.2)?;
+        file.major_brand = major_brand;
+        file.compatible_brands = compatible_brands;
+        Ok(file)
+    }
+
+    pub fn item(&self, id: u32) -> Option<&HeifItem> {
+        self.items.iter().find(|item| item.id == id)
+    }
+
+    pub fn primary_item(&self) -> Option<&HeifItem> {
+        self.item(self.primary_item_id)
+    }
+
+    pub fn auxiliary_items(&self, master_id: u32) -> impl Iterator<Item = &HeifItem> {
+        self.items
+            .iter()
+            .filter(move |item| item.aux_for == Some(master_id))
+    }
+
+    pub fn metadata_items(&self, described_id: u32) -> impl Iterator<Item = &HeifItem> {
+        self.items
+            .iter()
+            .filter(move |item| item.described_items.contains(&described_id))
+    }
+
+    pub fn exif_item(&self, described_id: u32) -> Option<&HeifItem> {
+        self.metadata_items(described_id)
+            .find(|item| &item.item_type == b"Exif")
+    }
+
+    pub fn xmp_item(&self, described_id: u32) -> Option<&HeifItem> {
+        self.metadata_items(described_id).find(|item| {
+            &item.item_type == b"mime" && item.content_type.as_deref() == Some(XMP_CONTENT_TYPE)
+        })
+    }
+
+    pub fn item_data(&self, data: &[u8], id: u32) -> Result<Vec<u8>, String> {
+        let item = self
+            .item(id)
+            .ok_or_else(|| format!("item {id} not found"))?;
+        let source = match item.construction_method {
+            0 => data,
+            1 => &self.idat[..],
+            m => {
+                return Err(format!(
+                    "item {id} uses unsupported construction method {m}"
+                ));
+            }
+        };
+        let mut out = Vec::new();
+        for &(offset, length) in &item.extents {
+            let start = usize::try_from(offset)
+                .map_err(|_| format!("item {id} extent offset too large"))?;
+            let end = if length == 0 {
+                source.len()
+            } else {
+                usize::try_from(length)
+                    .ok()
+                    .and_then(|len| start.checked_add(len))
+                    .ok_or_else(|| format!("item {id} extent length overflow"))?
+            };
+            let extent = source
+                .get(start..end)
+                .ok_or_else(|| format!("item {id} extent points outside the file"))?;
+            out.extend_from_slice(extent);
+        }
+        Ok(out)
+    }
+}
+
+struct BoxIter<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BoxIter<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+}
+
+impl<'a> Iterator for BoxIter<'a> {
+    type Item = ([u8; 4], Result<&'a [u8], String>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let remaining = self.data.len().checked_sub(self.pos)?;
+        if remaining < 8 {
+            return None;
+        }
+        let start = self.pos;
+        let size = u32::from_be_bytes(self.data[start..start + 4].try_into().ok()?) as u64;
+        let box_type = [
+            self.data[start + 4],
+            self.data[start + 5],
+            self.data[start + 6],
+            self.data[start + 7],
+        ];
+        let (header_len, total_len) = match size {
+            0 => (8u64, remaining as u64),
+            1 => match self.data.get(start + 8..start + 16) {
+                Some(large) => (16u64, u64::from_be_bytes(large.try_into().ok()?)),
+                None => {
+                    self.pos = self.data.len();
+                    return Some((box_type, Err("truncated largesize box header".to_owned())));
+                }
+            },
+            n => (8u64, n),
+        };
+        if total_len < header_len || total_len > remaining as u64 {
+            self.pos = self.data.len();
+            let name = String::from_utf8_lossy(&box_type).into_owned();
+            return Some((
+                box_type,
+                Err(format!("box '{name}' has invalid size {total_len}")),
+            ));
+        }
+        self.pos = start + total_len as usize;
+        let payload = &self.data[start + header_len as usize..self.pos];
+        Some((box_type, Ok(payload)))
+    }
+}
+
+fn parse_meta(meta: &[u8]) -> Result<HeifFile, String> {
+    let mut primary_item_id = None;
+    let mut items: Vec<HeifItem> = Vec::new();
+    let mut locations = ItemLocations::new();
+    let mut references = Vec::new();
+    let mut properties = Vec::new();
+    let mut associations = Vec::new();
+    let mut idat = Vec::new();
+
+    for (box_type, payload) in BoxIter::new(meta) {
+        let payload = payload?;
+        match &box_type {
+            b"pitm" => {
+                let (version, _, p) = full_box_payload(payload)?;
+                primary_item_id = Some(if version == 0 {
+                    read_u16(p, 0)? as u32
+                } else {
+                    read_u32(p, 0)?
+                });
+            }
+            b"iinf" => items = parse_iinf(payload)?,
+            b"iloc" => locations = parse_iloc(payload)?,
+            b"iref" => references = parse_iref(payload)?,
+            b"iprp" => (properties, associations) = parse_iprp(payload)?,
+            b"idat" => idat = payload.to_vec(),
+            _ => {}
+        }
+    }
+
+    for item in &mut items {
+        if let Some((construction_method, extents)) = locations.remove(&item.id) {
+            item.construction_method = construction_method;
+            item.extents = extents;
+        }
+    }
+
+    for (ref_type, from_id, to_ids) in references {
+        let Some(item) = items.iter_mut().find(|item| item.id == from_id) else {
+            continue;
+        };
+        match &ref_type {
+            b"auxl" => item.aux_for = to_ids.first().copied(),
+            b"cdsc" => item.described_items.extend(to_ids),
+            _ => {}
+        }
+    }
+
+    for (item_id, property_indices) in associations {
+        let Some(item) = items.iter_mut().find(|item| item.id == item_id) else {
+            continue;
+        };
+        let (mut quarter_turns, mut mirror_axis) = (0, None);
+        for index in property_indices {
+            let Some((property_type, property)) = index
+                .checked_sub(1)
+                .and_then(|i| properties.get(i as usize))
+            else {
+                continue;
+            };
+            item.property_types.push(*property_type);
+            match property {
+                Property::Ispe(width, height) => item.dimensions = Some((*width, *height)),
+                Property::AuxC(aux_type) => item.aux_type = Some(aux_type.clone()),
+                Property::Nclx(nclx) => item.nclx = Some(*nclx),
+                Property::Icc(len) => item.icc_profile_len = Some(*len),
+                Property::Av1C(config) => item.av1_config = Some(*config),
+                Property::Pixi(bits) => item.bits_per_channel = bits.clone(),
+                Property::Irot(turns) => quarter_turns = *turns,
+                Property::Imir(axis) => mirror_axis = Some(*axis),
+                Property::Other => {}
+            }
+        }
+        item.orientation = Orientation::from_heif(quarter_turns, mirror_axis);
+    }
+
+    Ok(HeifFile {
+        major_brand: [0; 4],
+        compatible_brands: Vec::new(),
+        primary_item_id: primary_item_id.ok_or("missing pitm box")?,
+        items,
+        idat,
+    })
+}
+
+fn parse_iinf(payload: &[u8]) -> Result<Vec<HeifItem>, String> {
+    let (version, _, p) = full_box_payload(payload)?;
+    let entries_start = if version == 0 { 2 } else { 4 };
+    let mut items = Vec::new();
+    for (box_type, infe) in BoxIter::new(p.get(entries_start..).ok_or("truncated iinf box")?) {
+        let infe = infe?;
+        if &box_type != b"infe" {
+            continue;
+        }
+        let (version, flags, p) = full_box_payload(infe)?;
+        if version < 2 {
+            continue;
+        }
+        let (id, mut pos) = if version == 2 {
+            (read_u16(p, 0)? as u32, 2)
+        } else {
+            (read_u32(p, 0)?, 4)
+        };
+        pos += 2; // item_protection_index
+        let item_type = fourcc(p, pos)?;
+        pos += 4;
+        let (_name, next) = read_cstr(p, pos)?;
+        let content_type = if &item_type == b"mime" {
+            Some(read_cstr(p, next)?.0)
+        } else {
+            None
+        };
+        items.push(HeifItem {
+            id,
+            item_type,
+            content_type,
+            hidden: flags & 1 != 0,
+            dimensions: None,
+            nclx: None,
+            orientation: Orientation::IDENTITY,
+            aux_type: None,
+            aux_for: None,
+            described_items: Vec::new(),
+            av1_config: None,
+            bits_per_channel: Vec::new(),
+            icc_profile_len: None,
+            property_types: Vec::new(),
+            construction_method: 0,
+            extents: Vec::new(),
+        });
+    }
+    Ok(items)
+}
+
+type ItemLocations = HashMap<u32, (u8, Vec<(u64, u64)>)>;
+
+fn parse_iloc(payload: &[u8]) -> Result<ItemLocations, String> {
+    let (version, _, p) = full_box_payload(payload)?;
+    if version > 2 {
+        return Err(format!("unsupported iloc version {version}"));
+    }
+    let sizes = read_u16(p, 0)?;
+    let offset_size = (sizes >> 12) as usize;
+    let length_size = ((sizes >> 8) & 0xF) as usize;
+    let base_offset_size = ((sizes >> 4) & 0xF) as usize;
+    let index_size = if version > 0 {
+        (sizes & 0xF) as usize
+    } else {
+        0
+    };
+    let mut pos = 2;
+    let item_count = if version < 2 {
+        let n = read_u16(p, pos)? as u32;
+        pos += 2;
+        n
+    } else {
+        let n = read_u32(p, pos)?;
+        pos += 4;
+        n
+    };
+
+    let mut locations = HashMap::new();
+    for _ in 0..item_count {
+        let id = if version < 2 {
+            let id = read_u16(p, pos)? as u32;
+            pos += 2;
+            id
+        } else {
+            let id = read_u32(p, pos)?;
+            pos += 4;
+            id
+        };
+        let construction_method = if version > 0 {
+            let method = (read_u16(p, pos)? & 0xF) as u8;
+            pos += 2;
+            method
+        } else {
+            0
+        };
+        pos += 2; // data_reference_index
+        let base_offset = read_sized(p, pos, base_offset_size)?;
+        pos += base_offset_size;
+        let extent_count = read_u16(p, pos)?;
+        pos += 2;
+        let mut extents = Vec::with_capacity(extent_count as usize);
+        for _ in 0..extent_count {
+            pos += index_size;
+            let offset = read_sized(p, pos, offset_size)?;
+            pos += offset_size;
+            let length = read_sized(p, pos, length_size)?;
+            pos += length_size;
+            let offset = base_offset
+                .checked_add(offset)
+                .ok_or_else(|| format!("item {id} extent offset overflow"))?;
+            extents.push((offset, length));
+        }
+        locations.insert(id, (construction_method, extents));
+    }
+    Ok(locations)
+}
+
+type ItemReference = ([u8; 4], u32, Vec<u32>);
+
+fn parse_iref(payload: &[u8]) -> Result<Vec<ItemReference>, String> {
+    let (version, _, p) = full_box_payload(payload)?;
+    let id_size = if version == 0 { 2 } else { 4 };
+    let read_id = |data: &[u8], pos: usize| -> Result<u32, String> {
+        if id_size == 2 {
+            Ok(read_u16(data, pos)? as u32)
+        } else {
+            read_u32(data, pos)
+        }
+    };
+
+    let mut references = Vec::new();
+    for (ref_type, r) in BoxIter::new(p) {
+        let r = r?;
+        let from_id = read_id(r, 0)?;
+        let count = read_u16(r, id_size)? as usize;
+        let mut to_ids = Vec::with_capacity(count);
+        for i in 0..count {
+            to_ids.push(read_id(r, id_size + 2 + i * id_size)?);
+        }
+        references.push((ref_type, from_id, to_ids));
+    }
+    Ok(references)
+}
+
+enum Property {
+    Ispe(u32, u32),
+    AuxC(String),
+    Nclx(Nclx),
+    Icc(usize),
+    Av1C(Av1Config),
+    Pixi(Vec<u8>),
+    Irot(u8),
+    Imir(u8),
+    Other,
+}
+
+type ItemAssociations = Vec<(u32, Vec<u16>)>;
+type TypedProperties = Vec<([u8; 4], Property)>;
+
+fn parse_iprp(payload: &[u8]) -> Result<(TypedProperties, ItemAssociations), String> {
+    let mut properties = Vec::new();
+    let mut associations = Vec::new();
+    for (box_type, child) in BoxIter::new(payload) {
+        let child = child?;
+        match &box_type {
+            b"ipco" => {
+                for (property_type, property) in BoxIter::new(child) {
+                    let property = property?;
+                    let parsed = match &property_type {
+                        b"ispe" => {
+                            let (_, _, p) = full_box_payload(property)?;
+                            Property::Ispe(read_u32(p, 0)?, read_u32(p, 4)?)
+                        }
+                        b"auxC" => {
+                            let (_, _, p) = full_box_payload(property)?;
+                            Property::AuxC(read_cstr(p, 0)?.0)
+                        }
+                        b"colr" if property.starts_with(b"nclx") => Property::Nclx(Nclx {
+                            color_primaries: read_u16(property, 4)?,
+                            transfer_characteristics: read_u16(property, 6)?,
+                            matrix_coefficients: read_u16(property, 8)?,
+                            full_range: property.get(10).ok_or("truncated colr box")? & 0x80 != 0,
+                        }),
+                        b"colr"
+                            if property.starts_with(b"prof") || property.starts_with(b"rICC") =>
+                        {
+                            Property::Icc(property.len() - 4)
+                        }
+                        b"av1C" => Property::Av1C(Av1Config::parse(property)?),
+                        b"pixi" => {
+                            let (_, _, p) = full_box_payload(property)?;
+                            let count = *p.first().ok_or("truncated pixi box")? as usize;
+                            Property::Pixi(
+                                p.get(1..1 + count).ok_or("truncated pixi box")?.to_vec(),
+                            )
+                        }
+                        b"irot" => {
+                            Property::Irot(property.first().ok_or("truncated irot box")? & 3)
+                        }
+                        b"imir" => {
+                            Property::Imir(property.first().ok_or("truncated imir box")? & 1)
+                        }
+                        _ => Property::Other,
+                    };
+                    properties.push((property_type, parsed));
+                }
+            }
+            b"ipma" => associations.extend(parse_ipma(child)?),
+            _ => {}
+        }
+    }
+    Ok((properties, associations))
+}
+
+fn parse_ipma(payload: &[u8]) -> Result<ItemAssociations, String> {
+    let (version, flags, p) = full_box_payload(payload)?;
+    let entry_count = read_u32(p, 0)?;
+    let mut pos = 4;
+    let mut entries = Vec::new();
+    for _ in 0..entry_count {
+        let item_id = if version < 1 {
+            let id = read_u16(p, pos)? as u32;
+            pos += 2;
+            id
+        } else {
+            let id = read_u32(p, pos)?;
+            pos += 4;
+            id
+        };
+        let count = *p.get(pos).ok_or("truncated ipma entry")?;
+        pos += 1;
+        let mut indices = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            if flags & 1 != 0 {
+                indices.push(read_u16(p, pos)? & 0x7FFF);
+                pos += 2;
+            } else {
+                indices.push((*p.get(pos).ok_or("truncated ipma entry")? & 0x7F) as u16);
+                pos += 1;
+            }
+        }
+        entries.push((item_id, indices));
+    }
+    Ok(entries)
+}
+
+fn full_box_payload(payload: &[u8]) -> Result<(u8, u32, &[u8]), String> {
+    let header = read_u32(payload, 0)?;
+    Ok(((header >> 24) as u8, header & 0x00FF_FFFF, &payload[4..]))
+}
+
+fn fourcc(data: &[u8], pos: usize) -> Result<[u8; 4], String> {
+    data.get(pos..pos + 4)
+        .and_then(|b| b.try_into().ok())
+        .ok_or_else(|| "unexpected EOF while reading four-character code".to_owned())
+}
+
+fn read_u16(data: &[u8], pos: usize) -> Result<u16, String> {
+    data.get(pos..pos + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .ok_or_else(|| "unexpected EOF while reading u16".to_owned())
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Result<u32, String> {
+    data.get(pos..pos + 4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| "unexpected EOF while reading u32".to_owned())
+}
+
+fn read_sized(data: &[u8], pos: usize, size: usize) -> Result<u64, String> {
+    match size {
+        0 => Ok(0),
+        4 => Ok(read_u32(data, pos)? as u64),
+        8 => data
+            .get(pos..pos + 8)
+            .map(|b| u64::from_be_bytes(b.try_into().unwrap_or_default()))
+            .ok_or_else(|| "unexpected EOF while reading u64".to_owned()),
+        n => Err(format!("unsupported iloc field size {n}")),
+    }
+}
+
+fn read_cstr(data: &[u8], pos: usize) -> Result<(String, usize), String> {
+    let rest = data
+        .get(pos..)
+        .ok_or_else(|| "unexpected EOF while reading string".to_owned())?;
+    let len = rest
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or_else(|| "unterminated string".to_owned())?;
+    Ok((
+        String::from_utf8_lossy(&rest[..len]).into_owned(),
+        pos + len + 1,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::avif::{AvifAuxiliaryItem, AvifConfig, write_avif};
+    use crate::mp4::{box_wrap, full_box};
+    use wav1c::{ColorDescription, ColorRange, VideoSignal};
+
+    fn sample_config(width: u32, height: u32, config_obus: Vec<u8>) -> AvifConfig {
+        let video_signal = VideoSignal::default();
+        AvifConfig {
+            width,
+            height,
+            av1c: wav1c::sequence::codec_configuration_record(
+                &video_signal,
+                wav1c::sequence::SEQ_LEVEL_IDX_5_1,
+                &config_obus,
+            ),
+            video_signal,
+            content_light: None,
+            mastering_display: None,
+            orientation: Orientation::IDENTITY,
+        }
+    }
+
+    #[test]
+    fn parses_codec_configuration_properties() {
+        let mut config = sample_config(64, 48, vec![0x0A, 0x01, 0x80]);
+        config.video_signal.bit_depth = wav1c::BitDepth::Ten;
+        config.av1c = wav1c::sequence::codec_configuration_record(
+            &config.video_signal,
+            13,
+            &[0x0A, 0x01, 0x80],
+        );
+        let mut out = Vec::new();
+        write_avif(&mut out, &config, &[0x12, 0x00, 0x11], &[]).expect("write");
+
+        let file = HeifFile::parse(&out).expect("parse");
+        let primary = file.primary_item().expect("primary");
+        let av1c = primary.av1_config.expect("av1C");
+        assert_eq!(av1c.seq_profile, 0);
+        assert_eq!(av1c.bit_depth, 10);
+        assert_eq!(av1c.chroma_format(), "4:2:0");
+        assert!(!av1c.high_tier);
+        assert_eq!(av1c.config_obus_len, 3);
+        assert_eq!(primary.bits_per_channel, vec![10, 10, 10]);
+        assert_eq!(primary.icc_profile_len, None);
+        for property in [b"ispe", b"av1C", b"pixi", b"colr"] {
+            assert!(primary.property_types.contains(property));
+        }
+    }
+
+    #[test]
+    fn parses_items_written_by_avif_muxer() {
+        let aux = AvifAuxiliaryItem {
+            aux_type: "urn:mpeg:hevc:2015:auxid:2".to_owned(),
+            config: sample_config(32, 16, vec![0x0A, 0x01, 0x81]),
+            obu_data: vec![0x12, 0x00, 0x33, 0x44],
+        };
+        let mut out = Vec::new();
+        write_avif(
+            &mut out,
+            &sample_config(64, 48, vec![0x0A, 0x01, 0x80]),
+            &[0x12, 0x00, 0x11, 0x22],
+            &[aux],
+        )
+        .expect("write");
+
+        let file = HeifFile::parse(&out).expect("parse");
+        assert_eq!(&file.major_brand, b"avif");
+        assert_eq!(file.primary_item_id, 1);
+
+        let primary = file.primary_item().expect("primary");
+        assert_eq!(&primary.item_type, b"av01");
+        assert_eq!(primary.dimensions, Some((64, 48)));
+        assert_eq!(
+            file.item_data(&out, 1).expect("data"),
+            vec![0x0A, 0x01, 0x80, 0x11, 0x22]
+        );
+
+        assert_eq!(
+            primary.nclx,
+            Some(Nclx {
+                color_primaries: 2,
+                transfer_characteristics: 2,
+                matrix_coefficients: 2,
+                full_range: false,
+            })
+        );
+
+        let aux: Vec<_> = file.auxiliary_items(1).collect();
+        assert_eq!(aux.len(), 1);
+        assert_eq!(
+            aux[0].aux_type.as_deref(),
+            Some("urn:mpeg:hevc:2015:auxid:2")
+        );
+        assert_eq!(aux[0].dimensions, Some((32, 16)));
+        assert_eq!(
+            file.item_data(&out, aux[0].id).expect("data"),
+            vec![0x0A, 0x01, 0x81, 0x33, 0x44]
+        );
+    }
+
+    #[test]
+    fn parses_nclx_color_properties() {
+        let mut config = sample_config(16, 16, vec![0x0A, 0x01, 0x80]);
+        config.video_signal = VideoSignal::hdr10(ColorRange::Full);
+        let mut out = Vec::new();
+        write_avif(&mut out, &config, &[0x12, 0x00, 0x11], &[]).expect("write");
+
+        let file = HeifFile::parse(&out).expect("parse");
+        let nclx = file
+            .primary_item()
+            .and_then(|item| item.nclx)
+            .expect("nclx");
+        let ColorDescription {
+            color_primaries,
+            transfer_characteristics,
+            matrix_coefficients,
+        } = config
+            .video_signal
+            .color_description
+            .expect("hdr10 description");
+        assert_eq!(nclx.color_primaries, color_primaries as u16);
+        assert_eq!(
+            nclx.transfer_characteristics,
+            transfer_characteristics as u16
+        );
+        assert_eq!(nclx.matrix_coefficients, matrix_coefficients as u16);
+        assert!(nclx.full_range);
+    }
+
+    #[test]
+    fn resolves_cdsc_metadata_and_idat_construction() {
+        let mut ftyp = b"heic".to_vec();
+        ftyp.extend_from_slice(&0u32.to_be_bytes());
+        ftyp.extend_from_slice(b"mif1heic");
+
+        let mut infe_hvc1 = 1u16.to_be_bytes().to_vec();
+        infe_hvc1.extend_from_slice(&[0, 0]);
+        infe_hvc1.extend_from_slice(b"hvc1\0");
+        let mut infe_mime = 2u16.to_be_bytes().to_vec();
+        infe_mime.extend_from_slice(&[0, 0]);
+        infe_mime.extend_from_slice(b"mimeXMP\0application/rdf+xml\0");
+        let mut iinf = 2u16.to_be_bytes().to_vec();
+        iinf.extend_from_slice(&full_box(b"infe", 2, 0, &infe_hvc1));
+        iinf.extend_from_slice(&full_box(b"infe", 2, 1, &infe_mime));
+
+        let mut cdsc = 2u16.to_be_bytes().to_vec();
+        cdsc.extend_from_slice(&1u16.to_be_bytes());
+        cdsc.extend_from_slice(&1u16.to_be_bytes());
+
+        // Version 1 iloc: item 2 lives in idat (construction_method 1).
+        let mut iloc = vec![0x44, 0x00];
+        iloc.extend_from_slice(&1u16.to_be_bytes());
+        iloc.extend_from_slice(&2u16.to_be_bytes());
+        iloc.extend_from_slice(&1u16.to_be_bytes());
+        iloc.extend_from_slice(&0u16.to_be_bytes());
+        iloc.extend_from_slice(&1u16.to_be_bytes());
+        iloc.extend_from_slice(&2u32.to_be_bytes());
+        iloc.extend_from_slice(&3u32.to_be_bytes());
+
+        let meta = [
+            full_box(b"pitm", 0, 0, &1u16.to_be_bytes()),
+            full_box(b"iinf", 0, 0, &iinf),
+            full_box(b"iref", 0, 0, &box_wrap(b"cdsc", &cdsc)),
+            full_box(b"iloc", 1, 0, &iloc),
+            box_wrap(b"idat", b"..xmp"),
+        ]
+        .concat();
+        let file_data = [box_wrap(b"ftyp", &ftyp), full_box(b"meta", 0, 0, &meta)].concat();
+
+        let file = HeifFile::parse(&file_data).expect("parse");
+        let xmp = file.xmp_item(1).expect("xmp item");
+        assert!(xmp.hidden);
+        assert_eq!(xmp.item_type_str(), "mime");
+        assert_eq!(file.item_data(&file_data, xmp.id).expect("data"), b"xmp");
+        assert!(file.exif_item(1).is_none());
+        assert_eq!(file.compatible_brands, vec![*b"mif1", *b"heic"]);
+    }
+
+    #[test]
+    fn rejects_box_with_size_past_end() {
+        let mut data = box_wrap(b"ftyp", b"heic\0\0\0\0");
+        data[3] = 0xFF;
+        let err = HeifFile::parse(&data).unwrap_err();
+        assert!(err.contains("invalid size"));
+    }
+
+    #[test]
+    fn parses_orientation_of_rotated_stills() {
+        for exif in [3, 6, 7, 8] {
+            let mut config = sample_config(64, 48, vec![0x0A, 0x01, 0x80]);
+            config.orientation = Orientation::from_exif(exif).unwrap();
+            let mut out = Vec::new();
+            write_avif(&mut out, &config, &[0x12, 0x00, 0x11], &[]).expect("write");
+
+            let file = HeifFile::parse(&out).expect("parse");
+            let primary = file.primary_item().expect("primary");
+            assert_eq!(primary.orientation, config.orientation);
+            assert_eq!(primary.dimensions, Some((64, 48)));
+        }
+    }
+}
@@ -1,21 +1,33 @@
 #![deny(unsafe_code)]
 
+mod apple_hdr;
+mod audio;
 mod avif;
-mod ivf;
+#[cfg(feature = "avif-input")]
+mod avif_input;
+mod chunks;
+mod heif;
 mod mp4;
+mod orientation;
+mod png;
+mod progress;
+mod text;
 
 #[cfg(feature = "heic")]
 mod heic;
 
 use std::env;
 use std::fs::File;
-use std::io::Write;
+use std::io::{self, BufWriter, Read, Write};
 use std::path::Path;
 use std::process;
 
+use progress::{LogLevel, Progress};
+
 use wav1c::{
-    BitDepth, ColorDescription, ColorRange, ContentLightLevel, EncodeConfig, EncoderConfig, Fps,
-    MasteringDisplayMetadata, VideoSignal,
+    AqMode, BitDepth, ChromaDeltaQ, ChromaSamplePosition, CicpField, ColorDescription, ColorRange,
+    ContentLightLevel, DeadZone, EncodeConfig, EncoderConfig, Fps, MasteringDisplayMetadata,
+    Preset, Projection, Tune, VideoSignal, ivf,
 };
 
 struct CliArgs {
@@ -25,9 +37,37 @@ struct CliArgs {
     fps_explicit: bool,
     bit_depth_explicit: bool,
     color_range_explicit: bool,
-    #[cfg(feature = "heic")]
+    chroma_position_explicit: bool,
+    #[cfg(any(feature = "heic", feature = "avif-input"))]
     color_description_explicit: bool,
+    #[cfg(feature = "heic")]
+    preserve_aux: bool,
+    #[cfg(feature = "heic")]
+    gain_map_quality: GainMapQuality,
     hdr10_requested: bool,
+    progressive: bool,
+    convert_range: bool,
+    check_signal: bool,
+    analyze: bool,
+    orientation: Option<orientation::Orientation>,
+    rc_report: bool,
+    companion: Option<(mp4::CompanionKind, String)>,
+    audio: Option<String>,
+    subtitles: Option<String>,
+    chapters: Option<String>,
+    faststart: bool,
+    dedup_frames: bool,
+    distortion_maps: Option<String>,
+    motion_fields: Option<String>,
+    preview: Option<String>,
+    frame_range: Option<(usize, usize)>,
+    scene_chunks: Option<String>,
+    jobs: usize,
+    chunk_gops: bool,
+    chunk_workers: chunks::ChunkWorkers,
+    keyint_explicit: bool,
+    keyint_sec: Option<f64>,
+    log_level: LogLevel,
 }
 
 enum InputMode {
@@ -45,6 +85,21 @@ enum InputMode {
     },
     #[cfg(feature = "heic")]
     Heic(String),
+    #[cfg(feature = "avif-input")]
+    Avif(String),
+    Probe(String),
+}
+
+/// Parses `<first>-<last>`, an inclusive range of 0-based frame indices.
+fn parse_frame_range(s: &str) -> Result<(usize, usize), String> {
+    let invalid = || format!("invalid frame range: {s} (expected <first>-<last>)");
+    let (first, last) = s.split_once('-').ok_or_else(invalid)?;
+    let first = first.parse::<usize>().map_err(|_| invalid())?;
+    let last = last.parse::<usize>().map_err(|_| invalid())?;
+    if first > last {
+        return Err(format!("frame range {s} ends before it starts"));
+    }
+    Ok((first, last))
 }
 
 fn parse_bitrate(s: &str) -> Result<u64, String> {
@@ -60,6 +115,18 @@ fn parse_bitrate(s: &str) -> Result<u64, String> {
         .map_err(|_| format!("invalid bitrate: {s}"))
 }
 
+fn parse_companion(s: &str) -> Result<(mp4::CompanionKind, String), String> {
+    let (kind, path) = s
+        .split_once('=')
+        .ok_or_else(|| format!("invalid companion track (expected <kind>=<file.y4m>): {s}"))?;
+    let kind = mp4::CompanionKind::from_name(kind)
+        .ok_or_else(|| format!("invalid companion kind (expected alpha or gain-map): {kind}"))?;
+    if path.is_empty() {
+        return Err("companion track needs a Y4M path".to_owned());
+    }
+    Ok((kind, path.to_owned()))
+}
+
 fn parse_color_range(s: &str) -> Result<ColorRange, String> {
     match s {
         "limited" | "tv" => Ok(ColorRange::Limited),
@@ -96,6 +163,77 @@ fn parse_fps(s: &str) -> Result<Fps, String> {
     Fps::from_int(fps).map_err(|e| format!("invalid --fps value: {e}"))
 }
 
+/// `--tile-columns` or `--tile-rows`, named by `flag`.
+fn parse_tile_count(flag: &str, s: &str) -> Result<usize, String> {
+    let n: usize = s
+        .parse()
+        .map_err(|_| format!("invalid {flag} value: {s}"))?;
+    if !(1..=64).contains(&n) || !n.is_power_of_two() {
+        return Err(format!(
+            "invalid {flag} value: {s} (expected a power of two from 1 to 64)"
+        ));
+    }
+    Ok(n)
+}
+
+fn parse_dead_zone(s: &str) -> Result<DeadZone, String> {
+    let values = s
+        .split(',')
+        .map(|x| {
+            x.parse::<u8>()
+                .map_err(|_| format!("invalid u8 value in --dead-zone: {x}"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let [intra_dc, intra_ac, inter_dc, inter_ac] = values[..] else {
+        return Err(
+            "invalid --dead-zone value: expected intra_dc,intra_ac,inter_dc,inter_ac".to_owned(),
+        );
+    };
+    Ok(DeadZone {
+        intra_dc,
+        intra_ac,
+        inter_dc,
+        inter_ac,
+        ..DeadZone::default()
+    })
+}
+
+fn parse_chroma_delta_q(s: &str) -> Result<ChromaDeltaQ, String> {
+    let values = s
+        .split(',')
+        .map(|x| {
+            x.parse::<i8>()
+                .ok()
+                .filter(|v| (ChromaDeltaQ::MIN..=ChromaDeltaQ::MAX).contains(v))
+                .ok_or_else(|| format!("invalid value in --chroma-delta-q: {x} (expected -64..63)"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    match values[..] {
+        [u_dc, u_ac, v_dc, v_ac] => Ok(ChromaDeltaQ {
+            u_dc,
+            u_ac,
+            v_dc,
+            v_ac,
+        }),
+        [dc, ac] => Ok(ChromaDeltaQ {
+            u_dc: dc,
+            u_ac: ac,
+            v_dc: dc,
+            v_ac: ac,
+        }),
+        _ => {
+            Err("invalid --chroma-delta-q value: expected u_dc,u_ac,v_dc,v_ac or dc,ac".to_owned())
+        }
+    }
+}
+
+fn parse_orientation(s: &str) -> Result<orientation::Orientation, String> {
+    s.parse::<u16>()
+        .ok()
+        .and_then(orientation::Orientation::from_exif)
+        .ok_or_else(|| format!("invalid --orientation value: {s} (expected Exif 1-8)"))
+}
+
 fn parse_mdcv(s: &str) -> Result<MasteringDisplayMetadata, String> {
     let values: Vec<&str> = s.split(',').collect();
     if values.len() != 10 {
@@ -121,25 +259,59 @@ fn parse_mdcv(s: &str) -> Result<MasteringDisplayMetadata, String> {
     })
 }
 
+/// Longest `--keyint-sec` accepted: an hour between keyframes.
+const MAX_KEYINT_SECONDS: f64 = 3600.0;
+
 fn parse_cli() -> CliArgs {
     let mut positional = Vec::new();
     let mut output_path: Option<String> = None;
 
     let mut config = EncodeConfig::default();
     let mut fps_explicit = false;
+    let mut keyint_explicit = false;
+    let mut keyint_sec: Option<f64> = None;
+    let mut verbose = false;
+    let mut quiet = false;
     let mut bit_depth_explicit = false;
     let mut color_range_explicit = false;
+    let mut chroma_position_explicit = false;
     let mut hdr10 = false;
 
     let mut cp: Option<u8> = None;
     let mut tc: Option<u8> = None;
     let mut mc: Option<u8> = None;
-    #[cfg(feature = "heic")]
+    #[cfg(any(feature = "heic", feature = "avif-input"))]
     let mut color_description_explicit = false;
+    #[cfg(feature = "heic")]
+    let mut preserve_aux = false;
+    #[cfg(feature = "heic")]
+    let mut gain_map_quality = GainMapQuality::Auto;
     let mut max_cll: Option<u16> = None;
     let mut max_fall: Option<u16> = None;
     let mut mdcv: Option<MasteringDisplayMetadata> = None;
     let mut pattern: Option<String> = None;
+    let mut probe = false;
+    let mut progressive = false;
+    let mut convert_range = false;
+    let mut check_signal = false;
+    let mut analyze = false;
+    let mut lossless = false;
+    let mut orientation = None;
+    let mut companion = None;
+    let mut audio = None;
+    let mut subtitles = None;
+    let mut chapters = None;
+    let mut faststart = false;
+    let mut dedup_frames = false;
+    let mut distortion_maps = None;
+    let mut motion_fields = None;
+    let mut preview = None;
+    let mut rc_report = false;
+    let mut frame_range = None;
+    let mut scene_chunks = None;
+    let mut jobs = 1;
+    let mut chunk_gops = false;
+    let mut chunk_workers = None;
 
     let mut args = env::args().skip(1).peekable();
     while let Some(arg) = args.next() {
@@ -152,12 +324,160 @@ fn parse_cli() -> CliArgs {
                     process::exit(1);
                 });
             }
+            "--preset" => {
+                let value = args.next().unwrap_or_default();
+                let preset = Preset::from_name(&value).unwrap_or_else(|| {
+                    let names: Vec<&str> = Preset::ALL.iter().map(|p| p.name()).collect();
+                    eprintln!(
+                        "Error: unknown --preset: {value} (expected one of: {})",
+                        names.join(", ")
+                    );
+                    process::exit(1);
+                });
+                preset.apply(&mut config);
+            }
+            "--tune" => {
+                let value = args.next().unwrap_or_default();
+                config.tune = Tune::from_name(&value).unwrap_or_else(|| {
+                    let names: Vec<&str> = Tune::ALL.iter().map(|t| t.name()).collect();
+                    eprintln!(
+                        "Error: unknown --tune: {value} (expected one of: {})",
+                        names.join(", ")
+                    );
+                    process::exit(1);
+                });
+            }
+            "--aq-mode" => {
+                let value = args.next().unwrap_or_default();
+                config.aq_mode = AqMode::from_name(&value).unwrap_or_else(|| {
+                    let names: Vec<&str> = AqMode::ALL.iter().map(|m| m.name()).collect();
+                    eprintln!(
+                        "Error: unknown --aq-mode: {value} (expected one of: {})",
+                        names.join(", ")
+                    );
+                    process::exit(1);
+                });
+            }
+            "--projection" => {
+                let value = args.next().unwrap_or_default();
+                config.projection = parse_projection(&value).unwrap_or_else(|e| {
+                    eprintln!("Error: {e}");
+                    process::exit(1);
+                });
+            }
             "--keyint" => {
                 let value = args.next().unwrap_or_default();
                 config.keyint = value.parse().unwrap_or_else(|_| {
                     eprintln!("Error: invalid --keyint value: {value}");
                     process::exit(1);
                 });
+                keyint_explicit = true;
+            }
+            "-v" | "--verbose" => verbose = true,
+            "--quiet" => quiet = true,
+            "--keyint-sec" => {
+                let value = args.next().unwrap_or_default();
+                keyint_sec = Some(
+                    value
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|s| s.is_finite() && *s > 0.0 && *s <= MAX_KEYINT_SECONDS)
+                        .unwrap_or_else(|| {
+                            eprintln!(
+                                "Error: --keyint-sec must be a number of seconds in (0, {MAX_KEYINT_SECONDS}], got: {value}"
+                            );
+                            process::exit(1);
+                        }),
+                );
+            }
+            "--tile-columns" => {
+                let value = args.next().unwrap_or_default();
+                config.tile_columns =
+                    parse_tile_count("--tile-columns", &value).unwrap_or_else(|e| {
+                        eprintln!("Error: {e}");
+                        process::exit(1);
+                    });
+            }
+            "--tile-rows" => {
+                let value = args.next().unwrap_or_default();
+                config.tile_rows = parse_tile_count("--tile-rows", &value).unwrap_or_else(|e| {
+                    eprintln!("Error: {e}");
+                    process::exit(1);
+                });
+            }
+            "--threads" => {
+                let value = args.next().unwrap_or_default();
+                config.threads = match value.parse::<usize>() {
+                    Ok(n) if n > 0 => n,
+                    _ => {
+                        eprintln!("Error: --threads must be a positive integer, got: {value}");
+                        process::exit(1);
+                    }
+                };
+            }
+            "--dead-zone" => {
+                let value = args.next().unwrap_or_default();
+                let skip_bias = config.dead_zone.skip_bias;
+                config.dead_zone = parse_dead_zone(&value).unwrap_or_else(|e| {
+                    eprintln!("Error: {e}");
+                    process::exit(1);
+                });
+                config.dead_zone.skip_bias = skip_bias;
+            }
+            "--chroma-delta-q" => {
+                let value = args.next().unwrap_or_default();
+                config.chroma_delta_q = parse_chroma_delta_q(&value).unwrap_or_else(|e| {
+                    eprintln!("Error: {e}");
+                    process::exit(1);
+                });
+            }
+            "--skip-bias" => {
+                let value = args.next().unwrap_or_default();
+                config.dead_zone.skip_bias = value.parse().unwrap_or_else(|_| {
+                    eprintln!("Error: invalid --skip-bias value: {value}");
+                    process::exit(1);
+                });
+            }
+            "--cdef-search" => {
+                config.cdef_search = true;
+            }
+            "--loop-restoration" => {
+                config.loop_restoration = true;
+            }
+            "--build-info" => {
+                config.build_info = true;
+            }
+            "--mv-search-range" => {
+                let value = args.next().unwrap_or_default();
+                config.mv_search_range = value
+                    .parse()
+                    .ok()
+                    .filter(|range| (1..=wav1c::tile::MAX_MV_SEARCH_RANGE).contains(range))
+                    .unwrap_or_else(|| {
+                        eprintln!("Error: --mv-search-range must be 1-256, got: {value}");
+                        process::exit(1);
+                    });
+            }
+            "--me-prefilter" => {
+                config.me_prefilter = true;
+            }
+            "--first-pass-scale" => {
+                let value = args.next().unwrap_or_default();
+                config.first_pass_scale = parse_first_pass_scale(&value).unwrap_or_else(|e| {
+                    eprintln!("Error: {e}");
+                    process::exit(1);
+                });
+            }
+            "--speed" => {
+                let value = args.next().unwrap_or_default();
+                config.speed = value
+                    .parse()
+                    .ok()
+                    .filter(|speed| *speed <= wav1c::tile::MAX_SPEED)
+                    .unwrap_or_else(|| {
+                        eprintln!("Error: --speed must be 0-2, got: {value}");
+                        process::exit(1);
+                    });
             }
             "--bitrate" => {
                 let value = args.next().unwrap_or_default();
@@ -166,6 +486,32 @@ fn parse_cli() -> CliArgs {
                     process::exit(1);
                 }));
             }
+            "--intra-budget" => {
+                let value = args.next().unwrap_or_default();
+                config.intra_frame_budget = Some(
+                    value
+                        .parse()
+                        .ok()
+                        .filter(|bytes| *bytes > 0)
+                        .unwrap_or_else(|| {
+                            eprintln!("Error: invalid --intra-budget value: {value}");
+                            process::exit(1);
+                        }),
+                );
+            }
+            "--max-frame-bytes" => {
+                let value = args.next().unwrap_or_default();
+                config.max_frame_bytes = Some(
+                    value
+                        .parse()
+                        .ok()
+                        .filter(|bytes| *bytes > 0)
+                        .unwrap_or_else(|| {
+                            eprintln!("Error: invalid --max-frame-bytes value: {value}");
+                            process::exit(1);
+                        }),
+                );
+            }
             "--fps" => {
                 let value = args.next().unwrap_or_default();
                 config.fps = parse_fps(&value).unwrap_or_else(|e| {
@@ -185,6 +531,22 @@ fn parse_cli() -> CliArgs {
             "--hdr10" => {
                 hdr10 = true;
             }
+            "--signal" => {
+                let value = args.next().unwrap_or_default();
+                let signal: VideoSignal = value.parse().unwrap_or_else(|e| {
+                    eprintln!("Error: {e}");
+                    process::exit(1);
+                });
+                config.video_signal.bit_depth = signal.bit_depth;
+                config.video_signal.color_range = signal.color_range;
+                bit_depth_explicit = true;
+                color_range_explicit = true;
+                if let Some(cd) = signal.color_description {
+                    cp = Some(cd.color_primaries);
+                    tc = Some(cd.transfer_characteristics);
+                    mc = Some(cd.matrix_coefficients);
+                }
+            }
             "--color-range" => {
                 let value = args.next().unwrap_or_default();
                 config.video_signal.color_range = parse_color_range(&value).unwrap_or_else(|e| {
@@ -193,26 +555,46 @@ fn parse_cli() -> CliArgs {
                 });
                 color_range_explicit = true;
             }
+            "--chroma-position" => {
+                let value = args.next().unwrap_or_default();
+                config.video_signal.chroma_sample_position =
+                    ChromaSamplePosition::from_name(&value).unwrap_or_else(|| {
+                        eprintln!(
+                            "Error: invalid --chroma-position value: {value} (expected one of: {})",
+                            ChromaSamplePosition::NAMES.join(", ")
+                        );
+                        process::exit(1);
+                    });
+                chroma_position_explicit = true;
+            }
             "--color-primaries" => {
                 let value = args.next().unwrap_or_default();
-                cp = Some(value.parse::<u8>().unwrap_or_else(|_| {
+                cp = Some(CicpField::ColorPrimaries.parse(&value).unwrap_or_else(|| {
                     eprintln!("Error: invalid --color-primaries value: {value}");
                     process::exit(1);
                 }));
             }
             "--transfer" => {
                 let value = args.next().unwrap_or_default();
-                tc = Some(value.parse::<u8>().unwrap_or_else(|_| {
-                    eprintln!("Error: invalid --transfer value: {value}");
-                    process::exit(1);
-                }));
+                tc = Some(
+                    CicpField::TransferCharacteristics
+                        .parse(&value)
+                        .unwrap_or_else(|| {
+                            eprintln!("Error: invalid --transfer value: {value}");
+                            process::exit(1);
+                        }),
+                );
             }
             "--matrix" => {
                 let value = args.next().unwrap_or_default();
-                mc = Some(value.parse::<u8>().unwrap_or_else(|_| {
-                    eprintln!("Error: invalid --matrix value: {value}");
-                    process::exit(1);
-                }));
+                mc = Some(
+                    CicpField::MatrixCoefficients
+                        .parse(&value)
+                        .unwrap_or_else(|| {
+                            eprintln!("Error: invalid --matrix value: {value}");
+                            process::exit(1);
+                        }),
+                );
             }
             "--max-cll" => {
                 let value = args.next().unwrap_or_default();
@@ -235,15 +617,163 @@ fn parse_cli() -> CliArgs {
                     process::exit(1);
                 }));
             }
+            "--metadata-cadence" => {
+                let value = args.next().unwrap_or_default();
+                config.metadata_cadence = match value.as_str() {
+                    "frame" => wav1c::MetadataCadence::EveryFrame,
+                    "keyframe" => wav1c::MetadataCadence::Keyframes,
+                    "once" => wav1c::MetadataCadence::Once,
+                    _ => {
+                        eprintln!(
+                            "Error: --metadata-cadence must be frame, keyframe or once, got: {value}"
+                        );
+                        process::exit(1);
+                    }
+                };
+            }
             "--pattern" => {
                 pattern = Some(args.next().unwrap_or_default());
             }
+            "--probe" => {
+                probe = true;
+            }
+            "--progressive" => {
+                progressive = true;
+            }
+            "--faststart" => {
+                faststart = true;
+            }
+            "--dedup-frames" => {
+                dedup_frames = true;
+            }
+            "--distortion-maps" => {
+                distortion_maps = Some(args.next().unwrap_or_default());
+            }
+            "--motion-fields" => {
+                motion_fields = Some(args.next().unwrap_or_default());
+            }
+            "--preview" => {
+                preview = Some(args.next().unwrap_or_default());
+            }
+            "--preview-q" => {
+                let value = args.next().unwrap_or_default();
+                config.preview_q_idx =
+                    Some(value.parse().ok().filter(|q| *q > 0).unwrap_or_else(|| {
+                        eprintln!("Error: invalid --preview-q value: {value}");
+                        process::exit(1);
+                    }));
+            }
+            "--convert-range" => {
+                convert_range = true;
+            }
+            "--check-signal" => {
+                check_signal = true;
+            }
+            "--analyze" => {
+                analyze = true;
+            }
+            "--lossless" => {
+                lossless = true;
+            }
+            "--rc-report" => {
+                rc_report = true;
+            }
+            "--frame-range" => {
+                let value = args.next().unwrap_or_default();
+                frame_range = Some(parse_frame_range(&value).unwrap_or_else(|e| {
+                    eprintln!("Error: {e}");
+                    process::exit(1);
+                }));
+            }
+            "--scene-chunks" => {
+                scene_chunks = Some(args.next().unwrap_or_default());
+            }
+            "--jobs" => {
+                let value = args.next().unwrap_or_default();
+                jobs = match value.parse::<usize>() {
+                    Ok(n) if n > 0 => n,
+                    _ => {
+                        eprintln!("Error: --jobs must be a positive integer, got: {value}");
+                        process::exit(1);
+                    }
+                };
+            }
+            "--chunk-gops" => {
+                chunk_gops = true;
+            }
+            "--chunk-workers" => {
+                let value = args.next().unwrap_or_default();
+                chunk_workers = Some(chunks::ChunkWorkers::parse(&value).unwrap_or_else(|e| {
+                    eprintln!("Error: {e}");
+                    process::exit(1);
+                }));
+            }
+            "--companion" => {
+                let value = args.next().unwrap_or_default();
+                companion = Some(parse_companion(&value).unwrap_or_else(|e| {
+                    eprintln!("Error: {e}");
+                    process::exit(1);
+                }));
+            }
+            "--audio" => {
+                let value = args.next().unwrap_or_default();
+                if value.is_empty() {
+                    eprintln!("Error: --audio requires a file");
+                    process::exit(1);
+                }
+                audio = Some(value);
+            }
+            "--subtitles" | "--chapters" => {
+                let value = args.next().unwrap_or_default();
+                if value.is_empty() {
+                    eprintln!("Error: {arg} requires a WebVTT file");
+                    process::exit(1);
+                }
+                if arg == "--subtitles" {
+                    subtitles = Some(value);
+                } else {
+                    chapters = Some(value);
+                }
+            }
+            "--orientation" => {
+                let value = args.next().unwrap_or_default();
+                orientation = Some(parse_orientation(&value).unwrap_or_else(|e| {
+                    eprintln!("Error: {e}");
+                    process::exit(1);
+                }));
+            }
+            #[cfg(feature = "heic")]
+            "--preserve-aux" => {
+                preserve_aux = true;
+            }
+            #[cfg(feature = "heic")]
+            "--gain-map-q" => {
+                let value = args.next().unwrap_or_default();
+                gain_map_quality = GainMapQuality::Q(value.parse().unwrap_or_else(|_| {
+                    eprintln!("Error: invalid --gain-map-q value: {value}");
+                    process::exit(1);
+                }));
+            }
+            #[cfg(feature = "heic")]
+            "--gain-map-psnr" => {
+                let value = args.next().unwrap_or_default();
+                gain_map_quality = match value.parse::<f64>() {
+                    Ok(db) if db.is_finite() && db > 0.0 => GainMapQuality::Psnr(db),
+                    _ => {
+                        eprintln!("Error: invalid --gain-map-psnr value: {value}");
+                        process::exit(1);
+                    }
+                };
+            }
             _ => positional.push(arg),
         }
     }
 
     if hdr10 {
-        config.video_signal = VideoSignal::hdr10(config.video_signal.color_range);
+        config.video_signal = VideoSignal {
+            chroma_sample_position: config.video_signal.chroma_sample_position,
+            ..VideoSignal::hdr10(config.video_signal.color_range)
+        };
         if !bit_depth_explicit {
             config.video_signal.bit_depth = BitDepth::Ten;
         }
@@ -256,7 +786,7 @@ fn parse_cli() -> CliArgs {
                 transfer_characteristics,
                 matrix_coefficients,
             });
-            #[cfg(feature = "heic")]
+            #[cfg(any(feature = "heic", feature = "avif-input"))]
             {
                 color_description_explicit = true;
             }
@@ -286,15 +816,106 @@ fn parse_cli() -> CliArgs {
 
     config.mastering_display = mdcv;
 
+    if lossless {
+        if config.target_bitrate.is_some() {
+            eprintln!("Error: --lossless cannot be combined with --bitrate");
+            process::exit(1);
+        }
+        if config.chroma_delta_q != ChromaDeltaQ::default() {
+            eprintln!("Error: --lossless cannot be combined with --chroma-delta-q");
+            process::exit(1);
+        }
+        config.base_q_idx = 0;
+    }
+
+    if config.intra_frame_budget.is_some() && config.target_bitrate.is_some() {
+        eprintln!("Error: --intra-budget cannot be combined with --bitrate");
+        process::exit(1);
+    }
+
+    if rc_report && config.target_bitrate.is_none() {
+        eprintln!("Error: --rc-report requires --bitrate");
+        process::exit(1);
+    }
+
+    if verbose && quiet {
+        eprintln!("Error: --verbose and --quiet are mutually exclusive");
+        process::exit(1);
+    }
+    let log_level = if quiet {
+        LogLevel::Quiet
+    } else if verbose {
+        LogLevel::Verbose
+    } else {
+        LogLevel::Normal
+    };
+
+    if keyint_explicit && keyint_sec.is_some() {
+        eprintln!("Error: --keyint and --keyint-sec are mutually exclusive");
+        process::exit(1);
+    }
+
+    if jobs > 1 && scene_chunks.is_none() {
+        eprintln!("Error: --jobs requires --scene-chunks");
+        process::exit(1);
+    }
+    if (chunk_gops || chunk_workers.is_some()) && scene_chunks.is_none() {
+        eprintln!("Error: --chunk-gops and --chunk-workers require --scene-chunks");
+        process::exit(1);
+    }
+    if scene_chunks.as_deref() == Some("") {
+        eprintln!("Error: --scene-chunks requires a directory");
+        process::exit(1);
+    }
+    if distortion_maps.as_deref() == Some("") {
+        eprintln!("Error: --distortion-maps requires a directory");
+        process::exit(1);
+    }
+    if motion_fields.as_deref() == Some("") {
+        eprintln!("Error: --motion-fields requires a file");
+        process::exit(1);
+    }
+    if (distortion_maps.is_some() || motion_fields.is_some()) && scene_chunks.is_some() {
+        eprintln!(
+            "Error: --distortion-maps and --motion-fields cannot be combined with --scene-chunks"
+        );
+        process::exit(1);
+    }
+    config.distortion_maps = distortion_maps.is_some();
+    config.motion_fields = motion_fields.is_some();
+    match preview.as_deref() {
+        Some("") => {
+            eprintln!("Error: --preview requires a file");
+            process::exit(1);
+        }
+        Some(_) if scene_chunks.is_some() => {
+            eprintln!("Error: --preview cannot be combined with --scene-chunks");
+            process::exit(1);
+        }
+        Some(_) => {
+            config.preview_q_idx = config
+                .preview_q_idx
+                .or(Some(wav1c::preview::DEFAULT_PREVIEW_Q_IDX));
+        }
+        None if config.preview_q_idx.is_some() => {
+            eprintln!("Error: --preview-q requires --preview");
+            process::exit(1);
+        }
+        None => {}
+    }
+
     let output_path = match output_path {
         Some(p) if !p.is_empty() => p,
+        _ if probe && positional.len() == 1 => String::new(),
         _ => {
             print_usage();
             process::exit(1);
         }
     };
 
-    let input = if positional.len() == 1 && positional[0].ends_with(".y4m") {
+    let input = if probe && positional.len() == 1 {
+        InputMode::Probe(positional[0].clone())
+    } else if positional.len() == 1 && (positional[0].ends_with(".y4m") || positional[0] == "-") {
         InputMode::Y4m(positional[0].clone())
     } else if positional.len() == 1
         && (positional[0].ends_with(".heic") || positional[0].ends_with(".heif"))
@@ -306,6 +927,17 @@ fn parse_cli() -> CliArgs {
         #[cfg(not(feature = "heic"))]
         {
             eprintln!("Error: HEIC input requires building with --features heic (needs libheif)");
+            eprintln!("Hint: use --probe to inspect the HEIC container without libheif");
+            process::exit(1);
+        }
+    } else if positional.len() == 1 && positional[0].ends_with(".avif") {
+        #[cfg(feature = "avif-input")]
+        {
+            InputMode::Avif(positional[0].clone())
+        }
+        #[cfg(not(feature = "avif-input"))]
+        {
+            eprintln!("Error: AVIF input requires building with --features avif-input");
             process::exit(1);
         }
     } else if positional.len() == 2 && pattern.is_some() {
@@ -366,32 +998,303 @@ fn parse_cli() -> CliArgs {
         fps_explicit,
         bit_depth_explicit,
         color_range_explicit,
-        #[cfg(feature = "heic")]
+        chroma_position_explicit,
+        #[cfg(any(feature = "heic", feature = "avif-input"))]
         color_description_explicit,
+        #[cfg(feature = "heic")]
+        preserve_aux,
+        #[cfg(feature = "heic")]
+        gain_map_quality,
         hdr10_requested: hdr10,
+        progressive,
+        convert_range,
+        check_signal,
+        analyze,
+        orientation,
+        rc_report,
+        companion,
+        audio,
+        subtitles,
+        chapters,
+        faststart,
+        dedup_frames,
+        distortion_maps,
+        motion_fields,
+        preview,
+        frame_range,
+        scene_chunks,
+        jobs,
+        chunk_gops,
+        chunk_workers: chunk_workers.unwrap_or(chunks::ChunkWorkers::Processes),
+        keyint_explicit,
+        keyint_sec,
+        log_level,
     }
 }
 
 fn print_usage() {
-    eprintln!("Usage: wav1c <input.y4m|heic> -o <output.ivf|mp4|avif> [options]");
+    eprintln!("Usage: wav1c <input.y4m|heic|avif> -o <output.ivf|mp4|avif> [options]");
+    eprintln!("       wav1c <input.heic|avif> --probe");
+    eprintln!("       wav1c info <file.avif|heic>");
+    eprintln!("       wav1c analyze <input.y4m> [--target-psnr <dB>] [--first-pass-scale <1-8>]");
+    eprintln!("       wav1c concat <segment.ivf>... -o <output.ivf>");
+    eprintln!("       wav1c compare <a.y4m> <b.y4m> [--summary] [--projection equirect]");
     eprintln!("       wav1c <width> <height> <Y> <U> <V> -o <output.ivf|mp4|avif> [options]");
     eprintln!("       wav1c <width> <height> --pattern <name> -o <output.ivf|mp4|avif> [options]");
     eprintln!();
+    eprintln!("A Y4M input of - is read from stdin. IVF and MP4 output stream it a frame at");
+    eprintln!("a time unless --frame-range, --dedup-frames, --scene-chunks, --check-signal");
+    eprintln!("or --analyze needs the whole clip.");
+    eprintln!();
     eprintln!("Options:");
     eprintln!("  -q <0-255>              Quantizer index (default=128)");
-    eprintln!("  --keyint <N>            Keyframe interval (default=25)");
+    eprintln!("  --lossless              Bit-exact intra-only coding (same as -q 0)");
+    eprintln!("  --preset <name>         Tuned settings: photo-lossless-ish (visually lossless");
+    eprintln!("                          stills); options after it override its values");
+    eprintln!("  --tune <default|screen|animation>");
+    eprintln!("                          Bias block decisions for camera, screen or cartoon");
+    eprintln!("                          content");
+    eprintln!("  --aq-mode <off|cyclic>  Cyclic: refresh a rotating tenth of each inter frame");
+    eprintln!("                          at finer q (low-latency CBR)");
+    eprintln!("  --projection <flat|equirect>");
+    eprintln!("                          Equirect: code 360 video coarser towards the poles");
+    eprintln!("  --keyint <N>            Keyframe interval in frames (default: one second)");
+    eprintln!("  --keyint-sec <S>        Keyframe interval in seconds, converted with --fps");
+    eprintln!("  --tile-columns <N>      Split frames into N tile columns (power of two)");
+    eprintln!("  --tile-rows <N>         Split frames into N tile rows (power of two)");
+    eprintln!("  --threads <N>           Code up to N tiles of a frame at once (default=1)");
     eprintln!("  --bitrate <N>           Target bitrate (e.g. 500k, 2M)");
+    eprintln!("  --rc-report             Print per-GOP bits, qp histogram and buffer levels");
+    eprintln!("  --intra-budget <BYTES>  All keyframes, each packet at most BYTES (q adapts)");
+    eprintln!("  --max-frame-bytes <BYTES>");
+    eprintln!("                          Hard cap on every packet; frames over it are re-encoded");
+    eprintln!("                          coarser, or repeat the previous frame");
+    eprintln!("  --dead-zone <idc,iac,pdc,pac>");
+    eprintln!("                          Quantizer rounding in 1/256 step (default=128 each)");
+    eprintln!("  --skip-bias <N>         Zero blocks with at most N +/-1 coefficients");
+    eprintln!("  --cdef-search           Pick CDEF strength per superblock row (slower)");
+    eprintln!("  --loop-restoration      Wiener-filter CDEF output per restoration unit (slower)");
+    eprintln!(
+        "  --mv-search-range <N>   Motion search radius in pixels per frame of distance (default=32)"
+    );
+    eprintln!("  --me-prefilter          Search motion on smoothed luma (noisy, high-ISO sources)");
+    eprintln!("  --speed <0-2>           Stop block searches early when good enough (default=0)");
+    eprintln!("  --first-pass-scale <1-8>");
+    eprintln!("                          Run complexity analysis on frames downscaled by this");
+    eprintln!("                          factor (default=1, full resolution)");
+    eprintln!("  --chroma-delta-q <udc,uac,vdc,vac|dc,ac>");
+    eprintln!("                          Chroma q index offsets, -64..63 (negative = finer)");
     eprintln!("  --fps <INT|NUM/DEN>     Frame rate (e.g. 30 or 30000/1001)");
     eprintln!("  --bit-depth <8|10>      Signal bit depth");
     eprintln!("  --hdr10                 Apply HDR10 defaults (BT.2020/PQ/BT.2020NC)");
+    eprintln!("  --signal <name>         Signal preset: sdr709, srgb-full, hdr10, hlg; options");
+    eprintln!("                          after it override its values");
     eprintln!("  --color-range <limited|full>");
-    eprintln!("  --color-primaries <u8>");
-    eprintln!("  --transfer <u8>");
-    eprintln!("  --matrix <u8>");
+    eprintln!("  --chroma-position <unknown|vertical|colocated>");
+    eprintln!("                          Chroma siting to signal (default: from the Y4M C tag)");
+    eprintln!("  --convert-range         Rescale input samples to --color-range if it differs");
+    eprintln!("  --check-signal          Warn if the samples look mis-tagged (range, bit depth)");
+    eprintln!("  --analyze               Print the input's estimated noise level before encoding");
+    eprintln!("  --orientation <1-8>     Exif orientation to signal (AVIF irot/imir, MP4 matrix)");
+    eprintln!("  --color-primaries <u8|name>");
+    eprintln!("                          e.g. bt709, bt2020, display-p3");
+    eprintln!("  --transfer <u8|name>    e.g. bt709, srgb, smpte2084 (pq), hlg");
+    eprintln!("  --matrix <u8|name>      e.g. bt709, bt601, bt2020nc, identity");
     eprintln!("  --max-cll <u16>         Content light level metadata");
     eprintln!("  --max-fall <u16>        Content light level metadata");
     eprintln!("  --mdcv <rx,ry,gx,gy,bx,by,wx,wy,max_lum,min_lum>");
+    eprintln!("  --metadata-cadence <frame|keyframe|once>");
+    eprintln!("                          Packets that repeat CLL/MDCV metadata (default=frame)");
+    eprintln!("  --build-info            Name the encoder version and settings in a metadata OBU");
+    eprintln!("                          on every keyframe and, for MP4, in the udta box");
     eprintln!("  --pattern <name>        Test pattern (grid)");
+    eprintln!("  --probe                 Print HEIF/AVIF item graph and Apple HDR metadata");
+    eprintln!("  --companion <alpha|gain-map>=<file.y4m>");
+    eprintln!("                          MP4: encode a second Y4M as an auxiliary track;");
+    eprintln!("                          AVIF: its first frame as the alpha item");
+    eprintln!("  --audio <FILE>          MP4: mux an Ogg Opus or ADTS AAC track as is");
+    eprintln!("  --subtitles <FILE.vtt>  MP4: mux WebVTT cues as a timed text track");
+    eprintln!("  --chapters <FILE.vtt>   MP4: mux WebVTT cue titles as a chapter track");
+    eprintln!("  --faststart             MP4: write moov before mdat for progressive playback");
+    eprintln!("  --dedup-frames          Code runs of identical input frames as one frame shown");
+    eprintln!("                          for the whole run (MP4 sample durations, IVF pts)");
+    eprintln!("  --progressive           Write packets as they are encoded (fragmented MP4, IVF");
+    eprintln!("                          header updated per GOP) so partial files stay playable");
+    eprintln!("  --frame-range <A-B>     Encode only input frames A to B (0-based, inclusive)");
+    eprintln!("  --distortion-maps <DIR> Write a grayscale PNG per coded frame to DIR: luma PSNR");
+    eprintln!("                          per 64x64 superblock, white at 20 dB, black at 50 dB");
+    eprintln!("  --motion-fields <FILE>  Write the coded and searched motion vectors of every");
+    eprintln!("                          inter frame to FILE (binary, see README)");
+    eprintln!("  --preview <FILE.ivf>    Also write a half-width, half-height preview stream");
+    eprintln!("  --preview-q <1-255>     Fixed quantizer of the preview stream (default=200)");
+    eprintln!("  --scene-chunks <DIR>    IVF: encode each detected scene as its own segment in");
+    eprintln!("                          DIR, then join the segments into the output");
+    eprintln!("  --jobs <N>              Encode up to N scene chunks at once");
+    eprintln!("  --chunk-gops            Split scene chunks further at every keyframe interval");
+    eprintln!("  --chunk-workers <processes|threads>");
+    eprintln!("                          Encode chunks in child processes (default) or threads");
+    eprintln!("  -v, --verbose           Print a line per coded frame instead of the progress bar");
+    eprintln!("  --quiet                 Print only errors and warnings");
+    #[cfg(feature = "heic")]
+    eprintln!("  --preserve-aux          Keep HEIC depth/matte auxiliary images as AVIF aux items");
+    #[cfg(feature = "heic")]
+    eprintln!("  --gain-map-q <0-255>    Fixed quantizer for the HEIC gain-map item");
+    #[cfg(feature = "heic")]
+    eprintln!("  --gain-map-psnr <dB>    Gain-map PSNR target (default: from its dynamic range)");
+}
+
+/// First bytes of a `--motion-fields` file.
+const MOTION_FIELDS_MAGIC: &[u8; 8] = b"WAV1CMV1";
+/// Stored for units without a vector.
+const MOTION_FIELDS_NONE: i16 = i16::MIN;
+
+/// A `--motion-fields` record: frame and reference display indices (u64),
+/// the field size in 4x4 units (u16 columns, u16 rows), then per unit in
+/// raster order the coded and the searched vector as i16 `x, y` pairs in
+/// 1/8 pel. All little-endian.
+fn motion_field_record(field: &wav1c::tile::MotionField) -> Vec<u8> {
+    let (cols, rows) = (field.coded.mi_cols(), field.coded.mi_rows());
+    let mut out = Vec::with_capacity(20 + 8 * (cols * rows) as usize);
+    out.extend_from_slice(&field.frame_number.to_le_bytes());
+    out.extend_from_slice(&field.reference_frame_number.to_le_bytes());
+    out.extend_from_slice(&(cols as u16).to_le_bytes());
+    out.extend_from_slice(&(rows as u16).to_le_bytes());
+    let pair = |mv: Option<(i32, i32)>| {
+        let (x, y) = mv.map_or((MOTION_FIELDS_NONE, MOTION_FIELDS_NONE), |(x, y)| {
+            (x as i16, y as i16)
+        });
+        [x.to_le_bytes(), y.to_le_bytes()]
+    };
+    for y in 0..rows {
+        for x in 0..cols {
+            for half in [pair(field.coded.get(x, y)), pair(field.estimates.get(x, y))] {
+                out.extend(half.concat());
+            }
+        }
+    }
+    out
+}
+
+/// Per-frame diagnostics written next to the output while encoding.
+struct Sidecars {
+    distortion_dir: Option<std::path::PathBuf>,
+    motion_fields: Option<(String, BufWriter<File>)>,
+    preview: Option<(String, ivf::IvfWriter<BufWriter<File>>)>,
+}
+
+impl Sidecars {
+    fn open(cli: &CliArgs, encoder: &wav1c::Encoder) -> Result<Self, String> {
+        let distortion_dir = cli.distortion_maps.as_deref().map(std::path::PathBuf::from);
+        if let Some(dir) = &distortion_dir {
+            std::fs::create_dir_all(dir).map_err(|e| format!("creating {}: {e}", dir.display()))?;
+        }
+        let motion_fields = match &cli.motion_fields {
+            Some(path) => {
+                let mut file = File::create(path)
+                    .map(BufWriter::new)
+                    .map_err(|e| format!("creating {path}: {e}"))?;
+                file.write_all(MOTION_FIELDS_MAGIC)
+                    .map_err(|e| format!("writing {path}: {e}"))?;
+                Some((path.clone(), file))
+            }
+            None => None,
+        };
+        let preview = match (&cli.preview, encoder.preview()) {
+            (Some(path), Some(preview)) => {
+                let writer = File::create(path)
+                    .map(BufWriter::new)
+                    .and_then(|file| {
+                        ivf::IvfWriter::new(
+                            file,
+                            preview.width(),
+                            preview.height(),
+                            cli.config.fps.num,
+                            cli.config.fps.den,
+                        )
+                    })
+                    .map_err(|e| format!("creating {path}: {e}"))?;
+                Some((path.clone(), writer))
+            }
+            _ => None,
+        };
+        Ok(Self {
+            distortion_dir,
+            motion_fields,
+            preview,
+        })
+    }
+
+    fn drain(&mut self, encoder: &mut wav1c::Encoder, runs: &FrameRuns) {
+        if let Some(dir) = &self.distortion_dir {
+            while let Some(map) = encoder.receive_distortion_map() {
+                let path = dir.join(format!("frame_{:05}.png", map.frame_number));
+                if let Err(e) = std::fs::write(&path, distortion_map_png(&map)) {
+                    eprintln!("Error writing {}: {}", path.display(), e);
+                    process::exit(1);
+                }
+            }
+        }
+        if let Some((path, file)) = &mut self.motion_fields {
+            while let Some(field) = encoder.receive_motion_field() {
+                if let Err(e) = file.write_all(&motion_field_record(&field)) {
+                    eprintln!("Error writing {path}: {e}");
+                    process::exit(1);
+                }
+            }
+        }
+        if let Some((path, writer)) = &mut self.preview {
+            while let Some(packet) = encoder.receive_preview_packet() {
+                let timestamp = runs.start(packet.frame_number);
+                if let Err(e) = writer.write_frame(timestamp, &packet.data) {
+                    eprintln!("Error writing {path}: {e}");
+                    process::exit(1);
+                }
+                encoder.recycle_packet(packet);
+            }
+        }
+    }
+
+    fn finish(self) -> Result<(), String> {
+        if let Some((path, mut file)) = self.motion_fields {
+            file.flush().map_err(|e| format!("writing {path}: {e}"))?;
+        }
+        if let Some((path, writer)) = self.preview {
+            writer
+                .finish()
+                .map_err(|e| format!("writing {path}: {e}"))?;
+        }
+        Ok(())
+    }
+}
+
+/// PSNR drawn black in `--distortion-maps` images; 20 dB and below is
+/// white, with a linear ramp between.
+const DISTORTION_MAP_BLACK_DB: f64 = 50.0;
+const DISTORTION_MAP_WHITE_DB: f64 = 20.0;
+/// Side in pixels of each superblock in a `--distortion-maps` image.
+const DISTORTION_MAP_SCALE: usize = 8;
+
+/// A distortion map as a grayscale PNG, brighter where more was lost.
+fn distortion_map_png(map: &wav1c::metrics::DistortionMap) -> Vec<u8> {
+    let levels: Vec<u8> = map
+        .psnr()
+        .iter()
+        .map(|&psnr| {
+            let loss = (DISTORTION_MAP_BLACK_DB - psnr)
+                / (DISTORTION_MAP_BLACK_DB - DISTORTION_MAP_WHITE_DB);
+            (loss.clamp(0.0, 1.0) * 255.0).round() as u8
+        })
+        .collect();
+    let width = map.sb_cols * DISTORTION_MAP_SCALE;
+    let height = map.sb_rows * DISTORTION_MAP_SCALE;
+    let pixels: Vec<u8> = (0..height)
+        .flat_map(|y| {
+            let row = y / DISTORTION_MAP_SCALE * map.sb_cols;
+            (0..width).map(move |x| row + x / DISTORTION_MAP_SCALE)
+        })
+        .map(|sb| levels[sb])
+        .collect();
+    png::encode_gray(width as u32, height as u32, &pixels)
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -425,6 +1328,56 @@ fn validate_bit_depth_constraints(
     Ok(())
 }
 
+/// Quality control for the HEIC gain-map sub-encode, independent of `-q`.
+#[cfg(feature = "heic")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum GainMapQuality {
+    /// Search for a PSNR target derived from the gain map's dynamic range.
+    Auto,
+    /// Search for the coarsest quantizer reaching this PSNR (dB).
+    Psnr(f64),
+    Q(u8),
+}
+
+/// PSNR the auto mode aims for relative to the span the gain map covers.
+#[cfg(feature = "heic")]
+const GAIN_MAP_RELATIVE_PSNR: f64 = 34.0;
+
+/// Gain maps rarely use the full 8-bit range, and an error of a few levels is
+/// a large share of a narrow one, so the target rises as the span shrinks.
+#[cfg(feature = "heic")]
+fn auto_gain_map_psnr_target(frame: &wav1c::y4m::FramePixels) -> f64 {
+    let (lo, hi) = frame
+        .y
+        .iter()
+        .fold((u16::MAX, 0u16), |(lo, hi), &s| (lo.min(s), hi.max(s)));
+    let span = hi.saturating_sub(lo).max(1) as f64;
+    let peak = frame.bit_depth.max_value() as f64;
+    (GAIN_MAP_RELATIVE_PSNR + 20.0 * (peak / span).log10()).clamp(32.0, 50.0)
+}
+
+#[cfg(feature = "heic")]
+fn resolve_gain_map_q(
+    frame: &wav1c::y4m::FramePixels,
+    config: &EncodeConfig,
+    quality: GainMapQuality,
+) -> u8 {
+    let target = match quality {
+        GainMapQuality::Q(q) => return q,
+        GainMapQuality::Psnr(db) => db,
+        GainMapQuality::Auto => auto_gain_map_psnr_target(frame),
+    };
+    let result = wav1c::search_q_for_psnr(frame, config, target).unwrap_or_else(|e| {
+        eprintln!("Error searching gain-map quantizer: {e}");
+        process::exit(1);
+    });
+    eprintln!(
+        "gain map: q={} psnr={:.2}dB (target {:.2}dB) {} bytes",
+        result.base_q_idx, result.psnr, target, result.bytes
+    );
+    result.base_q_idx
+}
+
 #[cfg(feature = "heic")]
 fn should_use_auto_heic_gain_map(
     is_heic_input: bool,
@@ -453,6 +1406,13 @@ fn detect_format(path: &str) -> OutputFormat {
     }
 }
 
+fn color_range_name(range: ColorRange) -> &'static str {
+    match range {
+        ColorRange::Limited => "limited",
+        ColorRange::Full => "full",
+    }
+}
+
 fn validate_output_dimensions(format: OutputFormat, width: u32, height: u32) -> Result<(), String> {
     let max = u16::MAX as u32;
     if (format == OutputFormat::Ivf || format == OutputFormat::Mp4) && (width > max || height > max)
@@ -471,39 +1431,991 @@ fn validate_output_dimensions(format: OutputFormat, width: u32, height: u32) ->
     Ok(())
 }
 
-fn avif_config_obus(encoder: &wav1c::Encoder, packet_count: usize) -> Vec<u8> {
+/// Opens a Y4M input for reading a frame at a time; `-` is stdin.
+fn open_y4m(path: &str) -> io::Result<wav1c::y4m::Y4mReader<Box<dyn Read>>> {
+    let input: Box<dyn Read> = if path == "-" {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(File::open(path)?)
+    };
+    wav1c::y4m::Y4mReader::new(input)
+}
+
+fn input_name(path: &str) -> &str {
+    if path == "-" { "stdin" } else { path }
+}
+
+/// Whether Y4M input is read while encoding rather than all up front, so a
+/// long clip takes one frame of memory. Options that look at every frame
+/// before coding the first need the whole clip.
+fn streams_input(cli: &CliArgs, format: OutputFormat) -> bool {
+    format != OutputFormat::Avif
+        && cli.frame_range.is_none()
+        && !cli.dedup_frames
+        && cli.scene_chunks.is_none()
+        && !cli.check_signal
+        && !cli.analyze
+}
+
+/// Where the coded frames sit on the input's timeline. `--dedup-frames`
+/// codes one frame per run of identical input frames; without it every
+/// input frame is coded and the runs are all one frame long.
+#[derive(Debug, Default, PartialEq)]
+struct FrameRuns {
+    /// Input frame index each coded frame starts at, when deduplicating.
+    starts: Vec<u64>,
+    /// Input frame count.
+    end: u64,
+}
+
+impl FrameRuns {
+    /// Drops every input frame that repeats the one before it.
+    fn dedup(frames: Vec<wav1c::y4m::FramePixels>) -> (Vec<wav1c::y4m::FramePixels>, Self) {
+        let end = frames.len() as u64;
+        let mut starts = Vec::new();
+        let mut kept: Vec<wav1c::y4m::FramePixels> = Vec::new();
+        for (i, frame) in frames.into_iter().enumerate() {
+            let repeat = kept
+                .last()
+                .is_some_and(|last| last.y == frame.y && last.u == frame.u && last.v == frame.v);
+            if !repeat {
+                starts.push(i as u64);
+                kept.push(frame);
+            }
+        }
+        (kept, Self { starts, end })
+    }
+
+    /// Input frame index (the IVF timestamp) of coded frame `frame_number`.
+    fn start(&self, frame_number: u64) -> u64 {
+        self.starts
+            .get(frame_number as usize)
+            .copied()
+            .unwrap_or(frame_number)
+    }
+
+    /// Input frames coded frame `frame_number` is shown for.
+    fn len(&self, frame_number: u64) -> u64 {
+        if self.starts.is_empty() {
+            return 1;
+        }
+        let next = self
+            .starts
+            .get(frame_number as usize + 1)
+            .copied()
+            .unwrap_or(self.end);
+        next - self.start(frame_number)
+    }
+
+    fn mp4_sample(&self, packet: &wav1c::Packet, fps_den: u32) -> mp4::Mp4Sample {
+        mp4::Mp4Sample {
+            data: mp4::strip_temporal_delimiters(&packet.data),
+            is_sync: packet.frame_type.is_sync(),
+            duration: (self.len(packet.frame_number) * fps_den as u64)
+                .try_into()
+                .unwrap_or(u32::MAX),
+            disposable: !packet.is_reference,
+        }
+    }
+}
+
+/// Streaming writers for `--progressive`: packets reach the file as they are
+/// encoded and the container is left valid at every GOP boundary.
+enum ProgressiveOutput {
+    Ivf(ivf::IvfWriter<BufWriter<File>>),
+    Mp4(mp4::FragmentedMp4Writer<BufWriter<File>>),
+}
+
+impl ProgressiveOutput {
+    fn write_packet(
+        &mut self,
+        packet: &wav1c::Packet,
+        runs: &FrameRuns,
+        fps_den: u32,
+    ) -> io::Result<()> {
+        match self {
+            Self::Ivf(writer) => {
+                if packet.frame_type.is_sync() {
+                    writer.sync()?;
+                }
+                writer.write_frame(runs.start(packet.frame_number), &packet.data)
+            }
+            Self::Mp4(writer) => writer.push_sample(runs.mp4_sample(packet, fps_den)),
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Self::Ivf(writer) => writer.finish()?.flush(),
+            Self::Mp4(writer) => writer.finish()?.flush(),
+        }
+    }
+}
+
+/// Encodes the `--companion` Y4M with the main track's settings (but its own
+/// bit depth and range) into an MP4 auxiliary track.
+fn encode_companion(
+    kind: mp4::CompanionKind,
+    path: &str,
+    config: &EncodeConfig,
+    main_samples: usize,
+) -> mp4::Mp4Companion {
+    let frames = wav1c::y4m::FramePixels::all_from_y4m_file(Path::new(path)).unwrap_or_else(|e| {
+        eprintln!("Error reading {}: {}", path, e);
+        process::exit(1);
+    });
+    let Some(first) = frames.first() else {
+        eprintln!("Error: {path} has no frames");
+        process::exit(1);
+    };
+    let mut config = config.clone();
+    config.video_signal = VideoSignal {
+        bit_depth: first.bit_depth,
+        color_range: first.color_range,
+        color_description: None,
+        ..VideoSignal::default()
+    };
+    config.content_light = None;
+    config.mastering_display = None;
+    let packets = wav1c::TemporalUnitGrouper::group(wav1c::encode_packets(&frames, &config));
+    if packets.len() != main_samples {
+        eprintln!(
+            "Error: companion track {path} codes {} samples, the main track {main_samples}",
+            packets.len()
+        );
+        process::exit(1);
+    }
+    let encoder = wav1c::Encoder::new(first.width, first.height, EncoderConfig::from(&config))
+        .unwrap_or_else(|e| {
+            eprintln!("Error creating companion encoder: {e}");
+            process::exit(1);
+        });
+    mp4::Mp4Companion {
+        kind,
+        config: mp4::Mp4Config {
+            width: first.width,
+            height: first.height,
+            fps_num: config.fps.num,
+            fps_den: config.fps.den,
+            av1c: encoder.av1c(),
+            video_signal: config.video_signal,
+            orientation: orientation::Orientation::IDENTITY,
+            pixel_aspect: None,
+            encoder_info: None,
+        },
+        samples: packets
+            .iter()
+            .map(|p| FrameRuns::default().mp4_sample(p, config.fps.den))
+            .collect(),
+    }
+}
+
+/// Encodes the first frame of the `--companion alpha` Y4M as the AVIF alpha
+/// item of a `width`x`height` image, with the image's settings but its own
+/// bit depth and range.
+fn encode_alpha_item(
+    path: &str,
+    config: &EncodeConfig,
+    width: u32,
+    height: u32,
+) -> avif::AvifAuxiliaryItem {
+    let frames = wav1c::y4m::FramePixels::all_from_y4m_file(Path::new(path)).unwrap_or_else(|e| {
+        eprintln!("Error reading {}: {}", path, e);
+        process::exit(1);
+    });
+    let alpha = &frames[0];
+    if (alpha.width, alpha.height) != (width, height) {
+        eprintln!(
+            "Error: alpha {path} is {}x{}, the image {width}x{height}",
+            alpha.width, alpha.height
+        );
+        process::exit(1);
+    }
+    let mut config = config.clone();
+    config.target_bitrate = None;
+    config.content_light = None;
+    config.mastering_display = None;
+    config.video_signal = VideoSignal {
+        bit_depth: alpha.bit_depth,
+        color_range: alpha.color_range,
+        color_description: None,
+        ..VideoSignal::default()
+    };
+    let (config, obu_data) = encode_still_avif_item(alpha, &config, "alpha");
+    avif::AvifAuxiliaryItem {
+        aux_type: mp4::CompanionKind::Alpha.aux_type().to_owned(),
+        config,
+        obu_data,
+    }
+}
+
+fn open_progressive_output(
+    format: OutputFormat,
+    cli: &CliArgs,
+    encoder: &wav1c::Encoder,
+    width: u32,
+    height: u32,
+    orientation: orientation::Orientation,
+    pixel_aspect: Option<(u32, u32)>,
+) -> io::Result<ProgressiveOutput> {
+    if format == OutputFormat::Avif {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--progressive only applies to IVF and MP4 output",
+        ));
+    }
+    let file = BufWriter::new(File::create(&cli.output_path)?);
+    match format {
+        OutputFormat::Ivf => Ok(ProgressiveOutput::Ivf(ivf::IvfWriter::new(
+            file,
+            width,
+            height,
+            cli.config.fps.num,
+            cli.config.fps.den,
+        )?)),
+        OutputFormat::Mp4 => {
+            let mp4_config = mp4::Mp4Config {
+                width,
+                height,
+                fps_num: cli.config.fps.num,
+                fps_den: cli.config.fps.den,
+                av1c: encoder.av1c(),
+                video_signal: cli.config.video_signal,
+                orientation,
+                pixel_aspect,
+                encoder_info: cli.config.build_info.then(|| encoder.build_info()),
+            };
+            Ok(ProgressiveOutput::Mp4(mp4::FragmentedMp4Writer::new(
+                file,
+                &mp4_config,
+            )?))
+        }
+        OutputFormat::Avif => unreachable!("rejected above"),
+    }
+}
+
+fn avif_av1c(encoder: &wav1c::Encoder, packet_count: usize) -> Vec<u8> {
     if packet_count == 1 {
-        encoder.headers_still_picture()
+        encoder.av1c_still_picture()
     } else {
-        encoder.headers()
+        encoder.av1c()
     }
 }
 
+fn encode_still_avif_item(
+    frame: &wav1c::y4m::FramePixels,
+    config: &EncodeConfig,
+    label: &str,
+) -> (avif::AvifConfig, Vec<u8>) {
+    let mut encoder = wav1c::Encoder::new(frame.width, frame.height, EncoderConfig::from(config))
+        .unwrap_or_else(|e| {
+            eprintln!("Error creating {label} encoder: {:?}", e);
+            process::exit(1);
+        });
+
+    let mut packets = Vec::new();
+    encoder.send_frame(frame).unwrap_or_else(|e| {
+        eprintln!("Error encoding {label} frame: {:?}", e);
+        process::exit(1);
+    });
+    while let Some(packet) = encoder.receive_packet() {
+        packets.push(packet);
+    }
+    encoder.flush();
+    while let Some(packet) = encoder.receive_packet() {
+        packets.push(packet);
+    }
+    if packets.is_empty() {
+        eprintln!("Error: {label} encoder produced no frames");
+        process::exit(1);
+    }
+
+    let avif_config = avif::AvifConfig {
+        width: frame.width,
+        height: frame.height,
+        av1c: avif_av1c(&encoder, packets.len()),
+        video_signal: config.video_signal,
+        content_light: None,
+        mastering_display: None,
+        orientation: orientation::Orientation::IDENTITY,
+    };
+    (avif_config, packets.swap_remove(0).data)
+}
+
+#[cfg(feature = "heic")]
+fn encode_auxiliary_items(
+    images: &[heic::AuxiliaryImage],
+    base_config: &EncodeConfig,
+) -> Vec<avif::AvifAuxiliaryItem> {
+    images
+        .iter()
+        .map(|image| {
+            let mut aux_encode_config = base_config.clone();
+            aux_encode_config.target_bitrate = None;
+            aux_encode_config.content_light = None;
+            aux_encode_config.mastering_display = None;
+            aux_encode_config.video_signal = VideoSignal {
+                bit_depth: image.frame.bit_depth,
+                color_range: ColorRange::Full,
+                color_description: None,
+            };
+            let label = format!("auxiliary image ({})", image.aux_type);
+            let (config, obu_data) =
+                encode_still_avif_item(&image.frame, &aux_encode_config, &label);
+            avif::AvifAuxiliaryItem {
+                aux_type: image.aux_type.clone(),
+                config,
+                obu_data,
+            }
+        })
+        .collect()
+}
+
+fn print_apple_gain_map(
+    file: &heif::HeifFile,
+    data: &[u8],
+    primary: &heif::HeifItem,
+) -> Result<(), String> {
+    let gain_map = file
+        .auxiliary_items(primary.id)
+        .find(|item| item.aux_type.as_deref() == Some(apple_hdr::APPLE_HDR_GAINMAP_AUX_TYPE));
+    match gain_map {
+        Some(gain_map) => {
+            let has_version = match file.xmp_item(gain_map.id) {
+                Some(xmp) => file
+                    .item_data(data, xmp.id)?
+                    .windows(apple_hdr::HDR_GAINMAP_VERSION_KEY.len())
+                    .any(|w| w == apple_hdr::HDR_GAINMAP_VERSION_KEY),
+                None => false,
+            };
+            println!(
+                "apple gain map: item {} (HDRGainMapVersion {})",
+                gain_map.id,
+                if has_version { "present" } else { "missing" }
+            );
+        }
+        None => println!("apple gain map: none"),
+    }
+    Ok(())
+}
+
+/// PSNR `wav1c analyze` suggests settings for unless `--target-psnr` is given.
+const DEFAULT_TARGET_PSNR: f64 = 40.0;
+
+fn run_analyze(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let mut path = None;
+    let mut target_psnr = DEFAULT_TARGET_PSNR;
+    let mut scale = 1;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--first-pass-scale" => {
+                scale = parse_first_pass_scale(&args.next().unwrap_or_default())?
+            }
+            "--target-psnr" => {
+                let value = args.next().unwrap_or_default();
+                target_psnr = value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|psnr| psnr.is_finite() && *psnr > 0.0)
+                    .ok_or_else(|| {
+                        format!("--target-psnr must be a positive number, got: {value}")
+                    })?;
+            }
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(arg),
+            _ => return Err(format!("unexpected analyze argument: {arg}")),
+        }
+    }
+    let path = path.ok_or("analyze requires an input .y4m file")?;
+    let (frames, fps) = wav1c::y4m::FramePixels::all_from_y4m_file_with_fps(Path::new(&path))
+        .map_err(|e| format!("failed to read {path}: {e}"))?;
+    let fps = fps.unwrap_or_default();
+    let complexity = frame_complexity(&frames, scale);
+    let noise: Vec<wav1c::NoiseStats> = frames.iter().map(wav1c::estimate_noise).collect();
+    print!(
+        "{}",
+        format_analysis(&frames[0], fps, &complexity, &noise, target_psnr)
+    );
+    Ok(())
+}
+
+/// `wav1c concat <segment.ivf>... -o <output.ivf>`: joins segments encoded
+/// separately, e.g. scene chunks from other machines, into one stream.
+fn run_concat(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let mut output = None;
+    let mut segments = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = args.next(),
+            _ if !arg.starts_with('-') => segments.push(arg),
+            _ => return Err(format!("unexpected concat argument: {arg}")),
+        }
+    }
+    let output = output.ok_or("concat requires -o <output.ivf>")?;
+    if segments.is_empty() {
+        return Err("concat requires at least one input segment".into());
+    }
+    let files = segments
+        .iter()
+        .map(|path| read_ivf_file(Path::new(path)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let joined = ivf::concat(&files).map_err(|e| e.to_string())?;
+    let mut data = Vec::new();
+    joined.write_to(&mut data).map_err(|e| e.to_string())?;
+    std::fs::write(&output, &data).map_err(|e| format!("failed to write {output}: {e}"))?;
+    eprintln!(
+        "Wrote {} bytes to {output} ({} packets from {} segments)",
+        data.len(),
+        joined.frames.len(),
+        files.len()
+    );
+    Ok(())
+}
+
+fn parse_projection(value: &str) -> Result<Projection, String> {
+    Projection::from_name(value).ok_or_else(|| {
+        let names: Vec<&str> = Projection::ALL.iter().map(|p| p.name()).collect();
+        format!(
+            "unknown --projection: {value} (expected one of: {})",
+            names.join(", ")
+        )
+    })
+}
+
+/// `wav1c compare <a> <b> [--summary] [--projection equirect]`: per-frame
+/// and average PSNR and SSIM between two clips, e.g. a source and a decoded
+/// encode. Equirectangular clips report spherical WS-PSNR instead of PSNR.
+fn run_compare(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let mut paths = Vec::new();
+    let mut summary_only = false;
+    let mut projection = Projection::Flat;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--summary" => summary_only = true,
+            "--projection" => projection = parse_projection(&args.next().unwrap_or_default())?,
+            _ if !arg.starts_with('-') => paths.push(arg),
+            _ => return Err(format!("unexpected compare argument: {arg}")),
+        }
+    }
+    let [a_path, b_path] = paths.as_slice() else {
+        return Err("compare requires exactly two input files".into());
+    };
+    let a = read_compare_input(a_path)?;
+    let b = read_compare_input(b_path)?;
+    if a.len() != b.len() {
+        return Err(format!(
+            "{a_path} has {} frames but {b_path} has {}",
+            a.len(),
+            b.len()
+        ));
+    }
+    if a.is_empty() {
+        return Err(format!("{a_path} has no frames"));
+    }
+    let (fa, fb) = (&a[0], &b[0]);
+    if (fa.width, fa.height, fa.bit_depth) != (fb.width, fb.height, fb.bit_depth) {
+        return Err(format!(
+            "{a_path} is {}x{} {}-bit but {b_path} is {}x{} {}-bit",
+            fa.width,
+            fa.height,
+            fa.bit_depth.bits(),
+            fb.width,
+            fb.height,
+            fb.bit_depth.bits()
+        ));
+    }
+    let quality: Vec<_> = a
+        .iter()
+        .zip(&b)
+        .map(|(x, y)| {
+            let psnr = match projection {
+                Projection::Flat => wav1c::metrics::frame_psnr(x, y),
+                Projection::Equirect => wav1c::metrics::frame_ws_psnr(x, y),
+            };
+            (psnr, wav1c::metrics::frame_ssim(x, y))
+        })
+        .collect();
+    let psnr_label = match projection {
+        Projection::Flat => "PSNR",
+        Projection::Equirect => "WS-PSNR",
+    };
+    print!("{}", format_comparison(&quality, psnr_label, summary_only));
+    Ok(())
+}
+
+fn read_compare_input(path: &str) -> Result<Vec<wav1c::y4m::FramePixels>, String> {
+    let ext = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+    match ext.as_deref() {
+        Some("y4m") => wav1c::y4m::FramePixels::all_from_y4m_file(Path::new(path))
+            .map_err(|e| format!("failed to read {path}: {e}")),
+        #[cfg(feature = "avif-input")]
+        Some("ivf" | "obu") => avif_input::decode_av1_file(Path::new(path)),
+        #[cfg(feature = "avif-input")]
+        Some("avif") => avif_input::decode_avif(path).map(|decoded| vec![decoded.frame]),
+        _ if cfg!(feature = "avif-input") => Err(format!(
+            "compare reads .y4m, .ivf, .obu and .avif files, got: {path}"
+        )),
+        _ => Err(format!(
+            "compare reads .y4m files (.ivf and .avif need --features avif-input), got: {path}"
+        )),
+    }
+}
+
+fn format_comparison(
+    quality: &[(wav1c::metrics::FramePsnr, wav1c::metrics::FrameSsim)],
+    psnr_label: &str,
+    summary_only: bool,
+) -> String {
+    use std::fmt::Write as _;
+
+    let line = |psnr: &wav1c::metrics::FramePsnr, ssim: &wav1c::metrics::FrameSsim| {
+        format!(
+            "{psnr_label} Y {:.2} U {:.2} V {:.2} W {:.2} dB  SSIM Y {:.4} U {:.4} V {:.4} W {:.4}",
+            psnr.y,
+            psnr.u,
+            psnr.v,
+            psnr.weighted(),
+            ssim.y,
+            ssim.u,
+            ssim.v,
+            ssim.weighted()
+        )
+    };
+    let mut out = String::new();
+    if !summary_only {
+        for (i, (psnr, ssim)) in quality.iter().enumerate() {
+            let _ = writeln!(out, "frame {i:>5}: {}", line(psnr, ssim));
+        }
+    }
+    let n = quality.len() as f64;
+    let mean = |f: &dyn Fn(&(wav1c::metrics::FramePsnr, wav1c::metrics::FrameSsim)) -> f64| {
+        quality.iter().map(f).sum::<f64>() / n
+    };
+    let psnr = wav1c::metrics::FramePsnr {
+        y: mean(&|q| q.0.y),
+        u: mean(&|q| q.0.u),
+        v: mean(&|q| q.0.v),
+    };
+    let ssim = wav1c::metrics::FrameSsim {
+        y: mean(&|q| q.1.y),
+        u: mean(&|q| q.1.u),
+        v: mean(&|q| q.1.v),
+    };
+    let _ = writeln!(
+        out,
+        "average ({} frames): {}",
+        quality.len(),
+        line(&psnr, &ssim)
+    );
+    if let Some((worst, (psnr, _))) = quality
+        .iter()
+        .enumerate()
+        .min_by(|a, b| a.1.0.weighted().total_cmp(&b.1.0.weighted()))
+    {
+        let _ = writeln!(
+            out,
+            "worst frame: {worst} ({psnr_label} W {:.2} dB)",
+            psnr.weighted()
+        );
+    }
+    out
+}
+
+fn frame_complexity(frames: &[wav1c::y4m::FramePixels], scale: u32) -> Vec<wav1c::FrameComplexity> {
+    frames
+        .iter()
+        .enumerate()
+        .map(|(i, frame)| {
+            let previous = i.checked_sub(1).map(|p| &frames[p]);
+            wav1c::analysis::analyze_frame_scaled(i as u64, frame, previous, scale)
+        })
+        .collect()
+}
+
+fn parse_first_pass_scale(value: &str) -> Result<u32, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|scale| (1..=wav1c::analysis::MAX_FIRST_PASS_SCALE).contains(scale))
+        .ok_or_else(|| format!("--first-pass-scale must be 1-8, got: {value}"))
+}
+
+fn format_analysis(
+    first: &wav1c::y4m::FramePixels,
+    fps: Fps,
+    complexity: &[wav1c::FrameComplexity],
+    noise: &[wav1c::NoiseStats],
+    target_psnr: f64,
+) -> String {
+    use std::fmt::Write as _;
+
+    let frames = complexity.len() as u64;
+    let mut out = format!(
+        "{}x{} {}-bit, {frames} frames at {}/{} fps\n",
+        first.width,
+        first.height,
+        first.bit_depth.bits(),
+        fps.num,
+        fps.den
+    );
+    let cuts = wav1c::scene_cuts(complexity);
+    let _ = writeln!(out, "Scenes: {}", cuts.len());
+    for (i, &start) in cuts.iter().enumerate() {
+        let end = cuts.get(i + 1).map_or(frames, |&next| next) - 1;
+        let _ = writeln!(out, "  scene {i}: frames {start}-{end}");
+    }
+    out.push_str(&format_noise_report(noise, first.bit_depth));
+    let suggested = wav1c::suggest_settings(
+        complexity,
+        first.width,
+        first.height,
+        fps,
+        first.bit_depth,
+        target_psnr,
+    );
+    let _ = writeln!(
+        out,
+        "Suggested for {target_psnr:.1} dB PSNR: -q {} (about {}kbps) --keyint {}",
+        suggested.base_q_idx,
+        suggested.target_bitrate.div_ceil(1000),
+        suggested.keyint
+    );
+    out
+}
+
+fn print_info(path: &str) -> Result<(), String> {
+    let data = std::fs::read(path).map_err(|e| format!("failed to read {path}: {e}"))?;
+    let file = heif::HeifFile::parse(&data)?;
+
+    println!("file: {path} ({} bytes)", data.len());
+    println!(
+        "brand: {} (compatible: {})",
+        String::from_utf8_lossy(&file.major_brand),
+        file.compatible_brands
+            .iter()
+            .map(|b| String::from_utf8_lossy(b).into_owned())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    let primary = file
+        .primary_item()
+        .ok_or_else(|| format!("primary item {} is not declared", file.primary_item_id))?;
+
+    for item in &file.items {
+        let size = file
+            .item_data(&data, item.id)
+            .map(|d| format!("{} bytes", d.len()))
+            .unwrap_or_else(|e| format!("unreadable ({e})"));
+        let role = if item.id == primary.id {
+            " primary".to_owned()
+        } else if let Some(master) = item.aux_for {
+            format!(" aux of {master}")
+        } else {
+            String::new()
+        };
+        let hidden = if item.hidden { " hidden" } else { "" };
+        println!(
+            "item {:>3}  {}  {size}{role}{hidden}",
+            item.id,
+            item.item_type_str()
+        );
+        if !item.property_types.is_empty() {
+            let types: Vec<String> = item
+                .property_types
+                .iter()
+                .map(|t| String::from_utf8_lossy(t).into_owned())
+                .collect();
+            println!("    properties: {}", types.join(" "));
+        }
+        if let Some((w, h)) = item.dimensions {
+            println!("    ispe: {w}x{h}");
+        }
+        if let Some(n) = item.nclx {
+            println!(
+                "    colr: nclx primaries={} transfer={} matrix={} range={}",
+                n.color_primaries,
+                n.transfer_characteristics,
+                n.matrix_coefficients,
+                if n.full_range { "full" } else { "limited" }
+            );
+        }
+        if let Some(len) = item.icc_profile_len {
+            println!("    colr: icc profile, {len} bytes");
+        }
+        if let Some(c) = item.av1_config {
+            println!(
+                "    av1C: profile {} level {} tier {} {}-bit {} chroma position {} ({} bytes config OBUs)",
+                c.seq_profile,
+                c.seq_level_idx,
+                if c.high_tier { "high" } else { "main" },
+                c.bit_depth,
+                c.chroma_format(),
+                c.chroma_sample_position,
+                c.config_obus_len
+            );
+        }
+        if !item.bits_per_channel.is_empty() {
+            println!("    pixi: {:?}", item.bits_per_channel);
+        }
+        if !item.orientation.is_identity() {
+            println!("    orientation: {}", item.orientation.exif_value());
+        }
+        if let Some(aux_type) = &item.aux_type {
+            println!("    auxC: {aux_type}");
+        }
+    }
+
+    print_apple_gain_map(&file, &data, primary)?;
+    match file.items.iter().find(|item| &item.item_type == b"tmap") {
+        Some(tmap) => println!("iso gain map: tmap item {}", tmap.id),
+        None => println!("iso gain map: none"),
+    }
+    Ok(())
+}
+
+fn probe_heif(path: &str) -> Result<(), String> {
+    let data = std::fs::read(path).map_err(|e| format!("failed to read {path}: {e}"))?;
+    let file = heif::HeifFile::parse(&data)?;
+
+    let brands: Vec<String> = file
+        .compatible_brands
+        .iter()
+        .map(|b| String::from_utf8_lossy(b).into_owned())
+        .collect();
+    println!(
+        "brand: {} (compatible: {})",
+        String::from_utf8_lossy(&file.major_brand),
+        brands.join(", ")
+    );
+
+    let primary = file
+        .primary_item()
+        .ok_or_else(|| format!("primary item {} is not declared", file.primary_item_id))?;
+    for item in &file.items {
+        let role = if item.id == primary.id {
+            "primary".to_owned()
+        } else if let Some(master) = item.aux_for {
+            format!("aux of {master}")
+        } else if !item.described_items.is_empty() {
+            format!("metadata for {:?}", item.described_items)
+        } else {
+            "item".to_owned()
+        };
+        let dimensions = item
+            .dimensions
+            .map(|(w, h)| format!(" {w}x{h}"))
+            .unwrap_or_default();
+        let hidden = if item.hidden { " hidden" } else { "" };
+        let nclx = item
+            .nclx
+            .map(|n| {
+                format!(
+                    " nclx={}/{}/{}/{}",
+                    n.color_primaries,
+                    n.transfer_characteristics,
+                    n.matrix_coefficients,
+                    if n.full_range { "full" } else { "limited" }
+                )
+            })
+            .unwrap_or_default();
+        let aux_type = item
+            .aux_type
+            .as_deref()
+            .map(|t| format!(" [{t}]"))
+            .unwrap_or_default();
+        let orientation = if item.orientation.is_identity() {
+            String::new()
+        } else {
+            let (w, h) = item.dimensions.unwrap_or_default();
+            let (dw, dh) = item.orientation.display_dimensions(w, h);
+            format!(
+                " orientation={} (displays {dw}x{dh})",
+                item.orientation.exif_value()
+            )
+        };
+        println!(
+            "item {:>3}  {}  {role}{dimensions}{orientation}{nclx}{hidden}{aux_type}",
+            item.id,
+            item.item_type_str()
+        );
+    }
+
+    print_apple_gain_map(&file, &data, primary)?;
+
+    if let Some(exif) = file.exif_item(primary.id) {
+        let exif_data = file.item_data(&data, exif.id)?;
+        match apple_hdr::parse_apple_hdr_scalars(&exif_data) {
+            Ok(scalars) => println!(
+                "apple hdr: headroom={}/{} gain={}/{}",
+                scalars.hdr_headroom.numerator,
+                scalars.hdr_headroom.denominator,
+                scalars.hdr_gain.numerator,
+                scalars.hdr_gain.denominator
+            ),
+            Err(e) => println!("apple hdr: unavailable ({e})"),
+        }
+        if let Some(value) = apple_hdr::parse_exif_orientation(&exif_data) {
+            let note = match orientation::Orientation::from_exif(value) {
+                Some(o) if o == primary.orientation => "",
+                _ => " (ignored: irot/imir take precedence)",
+            };
+            println!("exif orientation: {value}{note}");
+        }
+    }
+    Ok(())
+}
+
 fn main() {
+    let mut args = env::args().skip(1);
+    let subcommand = args.next();
+    if subcommand.as_deref() == Some("analyze") {
+        if let Err(e) = run_analyze(args) {
+            eprintln!("Error: {e}");
+            process::exit(1);
+        }
+        return;
+    }
+    if subcommand.as_deref() == Some("compare") {
+        if let Err(e) = run_compare(args) {
+            eprintln!("Error: {e}");
+            process::exit(1);
+        }
+        return;
+    }
+    if subcommand.as_deref() == Some("concat") {
+        if let Err(e) = run_concat(args) {
+            eprintln!("Error: {e}");
+            process::exit(1);
+        }
+        return;
+    }
+    if subcommand.as_deref() == Some("info") {
+        let Some(path) = args.next() else {
+            eprintln!("Error: info requires an input file");
+            print_usage();
+            process::exit(1);
+        };
+        if let Err(e) = print_info(&path) {
+            eprintln!("Error reading {path}: {e}");
+            process::exit(1);
+        }
+        return;
+    }
     let mut cli = parse_cli();
+    if let InputMode::Probe(path) = &cli.input {
+        if let Err(e) = probe_heif(path) {
+            eprintln!("Error probing {path}: {e}");
+            process::exit(1);
+        }
+        return;
+    }
     let format = detect_format(&cli.output_path);
+    if let Some((kind, _)) = &cli.companion {
+        let supported = match format {
+            OutputFormat::Mp4 => !cli.progressive,
+            OutputFormat::Avif => *kind == mp4::CompanionKind::Alpha,
+            OutputFormat::Ivf => false,
+        };
+        if !supported {
+            eprintln!(
+                "Error: --companion requires non-progressive MP4 output (or AVIF output for alpha)"
+            );
+            process::exit(1);
+        }
+    }
+    if cli.audio.is_some() && (format != OutputFormat::Mp4 || cli.progressive) {
+        eprintln!("Error: --audio requires non-progressive MP4 output");
+        process::exit(1);
+    }
+    let audio = cli.audio.as_deref().map(|path| {
+        audio::read_audio(path).unwrap_or_else(|e| {
+            eprintln!("Error: {e}");
+            process::exit(1);
+        })
+    });
+    if (cli.subtitles.is_some() || cli.chapters.is_some())
+        && (format != OutputFormat::Mp4 || cli.progressive)
+    {
+        eprintln!("Error: --subtitles and --chapters require non-progressive MP4 output");
+        process::exit(1);
+    }
+    let read_cues = |path: &str| {
+        text::read_webvtt(path).unwrap_or_else(|e| {
+            eprintln!("Error: {e}");
+            process::exit(1);
+        })
+    };
+    let subtitles = cli.subtitles.as_deref().map(read_cues);
+    let chapters = cli.chapters.as_deref().map(read_cues);
+    if cli.dedup_frames && (cli.companion.is_some() || cli.scene_chunks.is_some()) {
+        eprintln!("Error: --dedup-frames cannot be combined with --companion or --scene-chunks");
+        process::exit(1);
+    }
+    if cli.dedup_frames && format == OutputFormat::Avif {
+        eprintln!("Warning: --dedup-frames only applies to IVF and MP4 output.");
+    }
+    if cli.faststart && (format != OutputFormat::Mp4 || cli.progressive) {
+        eprintln!(
+            "Warning: --faststart only applies to non-progressive MP4 output; fragmented \
+             MP4 already starts with moov."
+        );
+    }
 
     #[cfg(feature = "heic")]
     let mut heic_gain_map: Option<wav1c::y4m::FramePixels> = None;
     #[cfg(feature = "heic")]
-    let mut heic_apple_hdr_scalars: Option<heic::AppleHdrScalars> = None;
+    let mut heic_apple_hdr_scalars: Option<apple_hdr::AppleHdrScalars> = None;
     #[cfg(feature = "heic")]
     let mut heic_apple_hdr_error: Option<String> = None;
     #[cfg(feature = "heic")]
     let mut heic_gain_map_has_xmp_version = false;
     #[cfg(feature = "heic")]
     let mut heic_source_nclx: Option<heic::SourceNclx> = None;
+    #[cfg(feature = "heic")]
+    let mut heic_auxiliary_images: Vec<heic::AuxiliaryImage> = Vec::new();
+
+    #[cfg(feature = "avif-input")]
+    let mut avif_source_color_description: Option<ColorDescription> = None;
+    #[cfg(feature = "avif-input")]
+    let mut avif_source_orientation = orientation::Orientation::IDENTITY;
 
     let mut source_fps: Option<Fps> = None;
-    let frames = match &cli.input {
+    let mut source_pixel_aspect: Option<(u32, u32)> = None;
+    let mut input_stream = None;
+    let mut frames = match &cli.input {
         InputMode::Y4m(path) => {
-            let (frames, fps) =
-                wav1c::y4m::FramePixels::all_from_y4m_file_with_fps(Path::new(path))
-                    .unwrap_or_else(|e| {
-                        eprintln!("Error reading {}: {}", path, e);
-                        process::exit(1);
-                    });
-            source_fps = fps;
+            let read_error = |e: io::Error| -> ! {
+                eprintln!("Error reading {}: {}", input_name(path), e);
+                process::exit(1);
+            };
+            let mut reader = open_y4m(path).unwrap_or_else(|e| read_error(e));
+            let metadata = reader.metadata().clone();
+            let frames = if streams_input(&cli, format) {
+                // Only the first frame up front, for the stream's properties.
+                let first = reader.next().transpose().unwrap_or_else(|e| read_error(e));
+                input_stream = Some((path.clone(), reader));
+                first.into_iter().collect()
+            } else {
+                reader
+                    .collect::<io::Result<Vec<_>>>()
+                    .unwrap_or_else(|e| read_error(e))
+            };
+            if metadata
+                .interlacing
+                .is_some_and(|i| i != wav1c::y4m::Interlacing::Progressive)
+            {
+                eprintln!(
+                    "Warning: {path} is interlaced; its fields are coded together as \
+                     progressive frames"
+                );
+            }
+            source_fps = metadata.fps;
+            source_pixel_aspect = metadata.pixel_aspect;
+            if !cli.chroma_position_explicit {
+                cli.config.video_signal.chroma_sample_position = metadata.chroma_sample_position;
+            }
             frames
         }
         InputMode::Solid {
@@ -554,7 +2466,7 @@ fn main() {
         }
         #[cfg(feature = "heic")]
         InputMode::Heic(path) => {
-            let decoded = heic::decode_heic(path).unwrap_or_else(|e| {
+            let decoded = heic::decode_heic(path, cli.preserve_aux).unwrap_or_else(|e| {
                 eprintln!("Error reading HEIC {}: {}", path, e);
                 process::exit(1);
             });
@@ -563,8 +2475,20 @@ fn main() {
             heic_apple_hdr_error = decoded.apple_hdr_scalars_error;
             heic_gain_map_has_xmp_version = decoded.gain_map_has_xmp_version;
             heic_source_nclx = decoded.source_nclx;
+            heic_auxiliary_images = decoded.auxiliary_images;
             vec![decoded.base]
         }
+        #[cfg(feature = "avif-input")]
+        InputMode::Avif(path) => {
+            let decoded = avif_input::decode_avif(path).unwrap_or_else(|e| {
+                eprintln!("Error reading AVIF {}: {}", path, e);
+                process::exit(1);
+            });
+            avif_source_color_description = decoded.color_description;
+            avif_source_orientation = decoded.orientation;
+            vec![decoded.frame]
+        }
+        InputMode::Probe(_) => unreachable!("probe input is handled before encoding"),
     };
 
     if frames.is_empty() {
@@ -572,10 +2496,40 @@ fn main() {
         process::exit(1);
     }
 
+    if let Some((first, last)) = cli.frame_range {
+        if last >= frames.len() {
+            eprintln!(
+                "Error: --frame-range {first}-{last} is past the last input frame ({})",
+                frames.len() - 1
+            );
+            process::exit(1);
+        }
+        frames.truncate(last + 1);
+        frames.drain(..first);
+    }
+
+    let runs = if cli.dedup_frames && format != OutputFormat::Avif {
+        let input_frames = frames.len();
+        let (kept, runs) = FrameRuns::dedup(frames);
+        frames = kept;
+        if cli.log_level > LogLevel::Quiet && frames.len() < input_frames {
+            eprintln!(
+                "Dropped {} repeated frames; coding {} of {input_frames}.",
+                input_frames - frames.len(),
+                frames.len()
+            );
+        }
+        runs
+    } else {
+        FrameRuns::default()
+    };
+
     let is_file_input = match &cli.input {
         InputMode::Y4m(_) => true,
         #[cfg(feature = "heic")]
         InputMode::Heic(_) => true,
+        #[cfg(feature = "avif-input")]
+        InputMode::Avif(_) => true,
         _ => false,
     };
 
@@ -593,6 +2547,22 @@ fn main() {
             cli.config.fps = fps;
         }
     }
+    cli.config.keyint = match cli.keyint_sec {
+        Some(seconds) => cli
+            .config
+            .fps
+            .frames_in(std::time::Duration::from_secs_f64(seconds)),
+        None if !cli.keyint_explicit => wav1c::default_keyint(cli.config.fps),
+        None => cli.config.keyint,
+    };
+
+    #[cfg(feature = "avif-input")]
+    if !cli.color_description_explicit
+        && !cli.hdr10_requested
+        && let Some(color_description) = avif_source_color_description
+    {
+        cli.config.video_signal.color_description = Some(color_description);
+    }
 
     if let Err(message) = validate_bit_depth_constraints(
         cli.hdr10_requested,
@@ -614,6 +2584,14 @@ fn main() {
     #[cfg(not(feature = "heic"))]
     let use_heic_gain_map_path = false;
 
+    #[cfg(feature = "heic")]
+    if !heic_auxiliary_images.is_empty() && format != OutputFormat::Avif {
+        eprintln!(
+            "Warning: --preserve-aux only applies to AVIF output; dropping {} auxiliary image(s).",
+            heic_auxiliary_images.len()
+        );
+    }
+
     #[cfg(feature = "heic")]
     if use_heic_gain_map_path {
         if !heic_gain_map_has_xmp_version {
@@ -645,6 +2623,33 @@ fn main() {
         }
     }
 
+    let target_range = cli.config.video_signal.color_range;
+    if frames[0].color_range != target_range {
+        if cli.convert_range {
+            frames = frames
+                .iter()
+                .map(|frame| frame.convert_range(target_range))
+                .collect();
+        } else {
+            eprintln!(
+                "Warning: input is {} range but output signals {} range; samples are not \
+                 rescaled (use --convert-range).",
+                color_range_name(frames[0].color_range),
+                color_range_name(target_range)
+            );
+        }
+    }
+
+    if cli.check_signal {
+        for mismatch in wav1c::check_signal(&frames, target_range) {
+            eprintln!("Warning: {mismatch}.");
+        }
+    }
+    if cli.analyze {
+        let noise: Vec<wav1c::NoiseStats> = frames.iter().map(wav1c::estimate_noise).collect();
+        eprint!("{}", format_noise_report(&noise, frames[0].bit_depth));
+    }
+
     let width = frames[0].width;
     let height = frames[0].height;
     if let Err(message) = validate_output_dimensions(format, width, height) {
@@ -652,260 +2657,385 @@ fn main() {
         process::exit(1);
     }
 
+    // Samples are always coded as stored; the orientation only travels as
+    // container metadata. HEIC input is decoded upright already.
+    #[cfg(feature = "avif-input")]
+    let source_orientation = avif_source_orientation;
+    #[cfg(not(feature = "avif-input"))]
+    let source_orientation = orientation::Orientation::IDENTITY;
+    let orientation = cli.orientation.unwrap_or(source_orientation);
+    if format == OutputFormat::Ivf && !orientation.is_identity() {
+        eprintln!(
+            "Warning: IVF cannot signal orientation {}; the output displays as stored \
+             ({width}x{height}).",
+            orientation.exif_value()
+        );
+    }
+
+    if let Some(dir) = &cli.scene_chunks {
+        if format != OutputFormat::Ivf || cli.progressive {
+            eprintln!("Error: --scene-chunks requires non-progressive IVF output");
+            process::exit(1);
+        }
+        if let Err(e) = encode_scene_chunks(&cli, Path::new(dir), &frames) {
+            eprintln!("Error: {e}");
+            process::exit(1);
+        }
+        return;
+    }
+
     let encoder_config = EncoderConfig::from(&cli.config);
     let mut encoder = wav1c::Encoder::new(width, height, encoder_config).unwrap_or_else(|e| {
         eprintln!("Error creating encoder: {:?}", e);
         process::exit(1);
     });
 
+    let mut progressive = if cli.progressive {
+        let output = open_progressive_output(
+            format,
+            &cli,
+            &encoder,
+            width,
+            height,
+            orientation,
+            source_pixel_aspect,
+        )
+        .unwrap_or_else(|e| {
+            eprintln!("Error creating {}: {}", cli.output_path, e);
+            process::exit(1);
+        });
+        Some(output)
+    } else {
+        None
+    };
+
     let mut packets: Vec<wav1c::Packet> = Vec::new();
+    let mut units = wav1c::TemporalUnitGrouper::new();
+    // Stores each temporal unit once its shown frame arrives, and returns
+    // packets already written, for the encoder to reuse their buffers.
+    let mut store_packet = |packet: wav1c::Packet| {
+        let packet = units.push(packet)?;
+        match progressive.as_mut() {
+            Some(output) => {
+                output
+                    .write_packet(&packet, &runs, cli.config.fps.den)
+                    .unwrap_or_else(|e| {
+                        eprintln!("Error writing {}: {}", cli.output_path, e);
+                        process::exit(1);
+                    });
+                Some(packet)
+            }
+            None => {
+                packets.push(packet);
+                None
+            }
+        }
+    };
+
+    let mut sidecars = Sidecars::open(&cli, &encoder).unwrap_or_else(|e| {
+        eprintln!("Error: {e}");
+        process::exit(1);
+    });
 
-    for frame in &frames {
-        encoder.send_frame(frame).unwrap_or_else(|e| {
+    let total = input_stream.is_none().then_some(frames.len());
+    let mut report = Progress::new(cli.log_level, total, cli.config.fps);
+    let streamed = input_stream.into_iter().flat_map(|(path, reader)| {
+        reader.map(move |frame| {
+            let frame = frame.unwrap_or_else(|e| {
+                eprintln!("Error reading {}: {}", input_name(&path), e);
+                process::exit(1);
+            });
+            if cli.convert_range && frame.color_range != target_range {
+                frame.convert_range(target_range)
+            } else {
+                frame
+            }
+        })
+    });
+    let mut frame_count = 0;
+    for frame in frames.into_iter().chain(streamed) {
+        encoder.send_frame(&frame).unwrap_or_else(|e| {
             eprintln!("Error encoding frame: {:?}", e);
             process::exit(1);
         });
+        frame_count += 1;
 
         while let Some(packet) = encoder.receive_packet() {
-            let frame_type_str = match packet.frame_type {
-                wav1c::FrameType::Key => "KEY",
-                wav1c::FrameType::Inter => "INTER",
-            };
-            eprintln!(
-                "frame {:>4}  {:>5}  {} bytes",
-                packet.frame_number,
-                frame_type_str,
-                packet.data.len()
-            );
-            packets.push(packet);
+            report.packet(&packet);
+            if let Some(written) = store_packet(packet) {
+                encoder.recycle_packet(written);
+            }
         }
+        sidecars.drain(&mut encoder, &runs);
     }
 
     encoder.flush();
 
     while let Some(packet) = encoder.receive_packet() {
-        let frame_type_str = match packet.frame_type {
-            wav1c::FrameType::Key => "KEY",
-            wav1c::FrameType::Inter => "INTER",
-        };
-        eprintln!(
-            "frame {:>4}  {:>5}  {} bytes",
-            packet.frame_number,
-            frame_type_str,
-            packet.data.len()
-        );
-        packets.push(packet);
+        report.packet(&packet);
+        if let Some(written) = store_packet(packet) {
+            encoder.recycle_packet(written);
+        }
     }
-
-    let mut file = File::create(&cli.output_path).unwrap_or_else(|e| {
-        eprintln!("Error creating {}: {}", cli.output_path, e);
+    sidecars.drain(&mut encoder, &runs);
+    if let Err(e) = sidecars.finish() {
+        eprintln!("Error: {e}");
         process::exit(1);
-    });
+    }
+    report.finish();
 
-    let output_size = match format {
-        OutputFormat::Ivf => {
-            let mut output = Vec::new();
-            ivf::write_ivf_header(
-                &mut output,
-                width,
-                height,
-                packets.len() as u32,
-                cli.config.fps.num,
-                cli.config.fps.den,
-            )
-            .unwrap();
-            for p in &packets {
-                ivf::write_ivf_frame(&mut output, p.frame_number, &p.data).unwrap();
+    let output_size = if let Some(output) = progressive {
+        output.finish().unwrap_or_else(|e| {
+            eprintln!("Error writing {}: {}", cli.output_path, e);
+            process::exit(1);
+        });
+        std::fs::metadata(&cli.output_path)
+            .map(|m| m.len() as usize)
+            .unwrap_or(0)
+    } else {
+        let mut file = File::create(&cli.output_path).unwrap_or_else(|e| {
+            eprintln!("Error creating {}: {}", cli.output_path, e);
+            process::exit(1);
+        });
+
+        match format {
+            OutputFormat::Ivf => {
+                let mut output = Vec::new();
+                ivf::write_ivf_header(
+                    &mut output,
+                    width,
+                    height,
+                    packets.len() as u32,
+                    cli.config.fps.num,
+                    cli.config.fps.den,
+                )
+                .unwrap();
+                for p in &packets {
+                    ivf::write_ivf_frame(&mut output, runs.start(p.frame_number), &p.data).unwrap();
+                }
+                file.write_all(&output).unwrap_or_else(|e| {
+                    eprintln!("Error writing {}: {}", cli.output_path, e);
+                    process::exit(1);
+                });
+                output.len()
             }
-            file.write_all(&output).unwrap_or_else(|e| {
-                eprintln!("Error writing {}: {}", cli.output_path, e);
-                process::exit(1);
-            });
-            output.len()
-        }
-        OutputFormat::Mp4 => {
-            let config_obus = encoder.headers();
-            let samples: Vec<mp4::Mp4Sample> = packets
-                .iter()
-                .map(|p| mp4::Mp4Sample {
-                    data: mp4::strip_temporal_delimiters(&p.data),
-                    is_sync: p.frame_type == wav1c::FrameType::Key,
-                })
-                .collect();
-            let mp4_config = mp4::Mp4Config {
-                width,
-                height,
-                fps_num: cli.config.fps.num,
-                fps_den: cli.config.fps.den,
-                config_obus,
-                video_signal: cli.config.video_signal,
-            };
-            let mut output = Vec::new();
-            mp4::write_mp4(&mut output, &mp4_config, &samples).unwrap();
-            file.write_all(&output).unwrap_or_else(|e| {
-                eprintln!("Error writing {}: {}", cli.output_path, e);
-                process::exit(1);
-            });
-            output.len()
-        }
-        OutputFormat::Avif => {
-            if packets.is_empty() {
-                eprintln!("Error: no frames to encode");
-                process::exit(1);
+            OutputFormat::Mp4 => {
+                let av1c = encoder.av1c();
+                let samples: Vec<mp4::Mp4Sample> = packets
+                    .iter()
+                    .map(|p| runs.mp4_sample(p, cli.config.fps.den))
+                    .collect();
+                let mp4_config = mp4::Mp4Config {
+                    width,
+                    height,
+                    fps_num: cli.config.fps.num,
+                    fps_den: cli.config.fps.den,
+                    av1c,
+                    video_signal: cli.config.video_signal,
+                    orientation,
+                    pixel_aspect: source_pixel_aspect,
+                    encoder_info: cli.config.build_info.then(|| encoder.build_info()),
+                };
+                let companion = cli
+                    .companion
+                    .as_ref()
+                    .map(|(kind, path)| encode_companion(*kind, path, &cli.config, packets.len()));
+                let duration_ms = samples.iter().map(|s| s.duration as u64).sum::<u64>() * 1000
+                    / cli.config.fps.num.max(1) as u64;
+                let text_tracks: Vec<text::TextTrack> = subtitles
+                    .iter()
+                    .map(|cues| text::TextTrack::subtitles(cues))
+                    .chain(
+                        chapters
+                            .iter()
+                            .map(|cues| text::TextTrack::chapters(cues, duration_ms)),
+                    )
+                    .collect();
+                let mut output = Vec::new();
+                let placement = if cli.faststart {
+                    mp4::MoovPlacement::Front
+                } else {
+                    mp4::MoovPlacement::End
+                };
+                mp4::write_mp4(
+                    &mut output,
+                    &mp4_config,
+                    &samples,
+                    companion.as_ref(),
+                    audio.as_ref(),
+                    &text_tracks,
+                    placement,
+                )
+                .unwrap_or_else(|e| {
+                    eprintln!("Error writing {}: {}", cli.output_path, e);
+                    process::exit(1);
+                });
+                file.write_all(&output).unwrap_or_else(|e| {
+                    eprintln!("Error writing {}: {}", cli.output_path, e);
+                    process::exit(1);
+                });
+                output.len()
             }
-            let mut output = Vec::new();
-            if use_heic_gain_map_path {
-                #[cfg(feature = "heic")]
-                {
-                    let gain_map_frame = heic_gain_map.as_ref().unwrap_or_else(|| {
-                        eprintln!(
-                            "Error: HEIC gain-map path selected but no Apple HDR gain-map \
-                             auxiliary image was decoded."
-                        );
-                        process::exit(1);
-                    });
-                    if gain_map_frame.bit_depth != BitDepth::Eight {
-                        eprintln!(
-                            "Error: Apple HDR gain-map auxiliary image must be 8-bit, got {}-bit.",
-                            gain_map_frame.bit_depth.bits()
-                        );
-                        process::exit(1);
-                    }
-
-                    let hdr_scalars = heic_apple_hdr_scalars.unwrap_or_else(|| {
-                        if let Some(err) = heic_apple_hdr_error.as_deref() {
+            OutputFormat::Avif => {
+                if packets.is_empty() {
+                    eprintln!("Error: no frames to encode");
+                    process::exit(1);
+                }
+                let alpha_item = cli
+                    .companion
+                    .as_ref()
+                    .map(|(_, path)| encode_alpha_item(path, &cli.config, width, height));
+                let mut output = Vec::new();
+                if use_heic_gain_map_path {
+                    #[cfg(feature = "heic")]
+                    {
+                        let gain_map_frame = heic_gain_map.as_ref().unwrap_or_else(|| {
                             eprintln!(
-                                "Error: could not parse Apple HDR MakerNote tags (0x0021/0x0030): \
-                                 {err}"
+                                "Error: HEIC gain-map path selected but no Apple HDR gain-map \
+                                 auxiliary image was decoded."
                             );
-                        } else {
+                            process::exit(1);
+                        });
+                        if gain_map_frame.bit_depth != BitDepth::Eight {
                             eprintln!(
-                                "Error: missing Apple HDR MakerNote tags (0x0021 HDRHeadroom \
-                                 and 0x0030 HDRGain)."
+                                "Error: Apple HDR gain-map auxiliary image must be 8-bit, got {}-bit.",
+                                gain_map_frame.bit_depth.bits()
                             );
+                            process::exit(1);
                         }
-                        process::exit(1);
-                    });
 
-                    let tmap_metadata = avif::derive_tmap_metadata_from_apple(
-                        hdr_scalars.hdr_headroom.numerator,
-                        hdr_scalars.hdr_headroom.denominator,
-                        hdr_scalars.hdr_gain.numerator,
-                        hdr_scalars.hdr_gain.denominator,
-                    )
-                    .unwrap_or_else(|e| {
-                        eprintln!("Error: failed to derive tmap metadata from Apple HDR tags: {e}");
-                        process::exit(1);
-                    });
-                    let tmap_payload =
-                        avif::build_tmap_payload(&tmap_metadata).unwrap_or_else(|e| {
-                            eprintln!("Error: failed to serialize tmap metadata payload: {e}");
+                        let hdr_scalars = heic_apple_hdr_scalars.unwrap_or_else(|| {
+                            if let Some(err) = heic_apple_hdr_error.as_deref() {
+                                eprintln!(
+                                    "Error: could not parse Apple HDR MakerNote tags (0x0021/0x0030): \
+                                     {err}"
+                                );
+                            } else {
+                                eprintln!(
+                                    "Error: missing Apple HDR MakerNote tags (0x0021 HDRHeadroom \
+                                     and 0x0030 HDRGain)."
+                                );
+                            }
                             process::exit(1);
                         });
 
-                    let mut gain_map_encode_config = cli.config.clone();
-                    gain_map_encode_config.target_bitrate = None;
-                    gain_map_encode_config.video_signal = VideoSignal {
-                        bit_depth: BitDepth::Eight,
-                        color_range: ColorRange::Full,
-                        color_description: Some(ColorDescription {
-                            color_primaries: 2,
-                            transfer_characteristics: 2,
-                            matrix_coefficients: 2,
-                        }),
-                    };
-                    let gain_encoder_config = EncoderConfig::from(&gain_map_encode_config);
-                    let mut gain_encoder = wav1c::Encoder::new(
-                        gain_map_frame.width,
-                        gain_map_frame.height,
-                        gain_encoder_config,
-                    )
-                    .unwrap_or_else(|e| {
-                        eprintln!("Error creating gain-map encoder: {:?}", e);
-                        process::exit(1);
-                    });
-
-                    let mut gain_packets = Vec::new();
-                    gain_encoder.send_frame(gain_map_frame).unwrap_or_else(|e| {
-                        eprintln!("Error encoding gain-map frame: {:?}", e);
-                        process::exit(1);
-                    });
-                    while let Some(packet) = gain_encoder.receive_packet() {
-                        gain_packets.push(packet);
-                    }
-                    gain_encoder.flush();
-                    while let Some(packet) = gain_encoder.receive_packet() {
-                        gain_packets.push(packet);
+                        let tmap_metadata = avif::derive_tmap_metadata_from_apple(
+                            hdr_scalars.hdr_headroom.numerator,
+                            hdr_scalars.hdr_headroom.denominator,
+                            hdr_scalars.hdr_gain.numerator,
+                            hdr_scalars.hdr_gain.denominator,
+                        )
+                        .unwrap_or_else(|e| {
+                            eprintln!(
+                                "Error: failed to derive tmap metadata from Apple HDR tags: {e}"
+                            );
+                            process::exit(1);
+                        });
+                        let tmap_payload =
+                            avif::build_tmap_payload(&tmap_metadata).unwrap_or_else(|e| {
+                                eprintln!("Error: failed to serialize tmap metadata payload: {e}");
+                                process::exit(1);
+                            });
+
+                        let mut gain_map_encode_config = cli.config.clone();
+                        gain_map_encode_config.target_bitrate = None;
+                        gain_map_encode_config.video_signal = VideoSignal {
+                            bit_depth: BitDepth::Eight,
+                            color_range: ColorRange::Full,
+                            color_description: Some(ColorDescription {
+                                color_primaries: 2,
+                                transfer_characteristics: 2,
+                                matrix_coefficients: 2,
+                            }),
+                        };
+                        gain_map_encode_config.base_q_idx = resolve_gain_map_q(
+                            gain_map_frame,
+                            &gain_map_encode_config,
+                            cli.gain_map_quality,
+                        );
+                        let (gain_map_avif_config, gain_map_obu_data) = encode_still_avif_item(
+                            gain_map_frame,
+                            &gain_map_encode_config,
+                            "gain-map",
+                        );
+                        let mut aux_items =
+                            encode_auxiliary_items(&heic_auxiliary_images, &cli.config);
+                        aux_items.extend(alpha_item);
+
+                        let base_avif_config = avif::AvifConfig {
+                            width,
+                            height,
+                            av1c: avif_av1c(&encoder, packets.len()),
+                            video_signal: cli.config.video_signal,
+                            content_light: cli.config.content_light,
+                            mastering_display: cli.config.mastering_display,
+                            orientation,
+                        };
+                        avif::write_avif_with_tmap_gain_map(
+                            &mut output,
+                            &base_avif_config,
+                            &packets[0].data,
+                            &gain_map_avif_config,
+                            &gain_map_obu_data,
+                            &tmap_payload,
+                            &aux_items,
+                        )
+                        .unwrap_or_else(|e| {
+                            eprintln!("Error writing gain-map AVIF: {e}");
+                            process::exit(1);
+                        });
                     }
-                    if gain_packets.is_empty() {
-                        eprintln!("Error: gain-map encoder produced no frames");
-                        process::exit(1);
+                    #[cfg(not(feature = "heic"))]
+                    {
+                        unreachable!("HEIC gain-map path is unavailable without heic feature");
                     }
-
-                    let base_avif_config = avif::AvifConfig {
+                } else {
+                    let avif_config = avif::AvifConfig {
                         width,
                         height,
-                        config_obus: avif_config_obus(&encoder, packets.len()),
+                        av1c: avif_av1c(&encoder, packets.len()),
                         video_signal: cli.config.video_signal,
                         content_light: cli.config.content_light,
                         mastering_display: cli.config.mastering_display,
+                        orientation,
                     };
-                    let gain_map_avif_config = avif::AvifConfig {
-                        width: gain_map_frame.width,
-                        height: gain_map_frame.height,
-                        config_obus: avif_config_obus(&gain_encoder, gain_packets.len()),
-                        video_signal: gain_map_encode_config.video_signal,
-                        content_light: None,
-                        mastering_display: None,
-                    };
-                    avif::write_avif_with_tmap_gain_map(
-                        &mut output,
-                        &base_avif_config,
-                        &packets[0].data,
-                        &gain_map_avif_config,
-                        &gain_packets[0].data,
-                        &tmap_payload,
-                    )
-                    .unwrap_or_else(|e| {
-                        eprintln!("Error writing gain-map AVIF: {e}");
-                        process::exit(1);
-                    });
+                    #[cfg(feature = "heic")]
+                    let mut aux_items = encode_auxiliary_items(&heic_auxiliary_images, &cli.config);
+                    #[cfg(not(feature = "heic"))]
+                    let mut aux_items = Vec::new();
+                    aux_items.extend(alpha_item);
+                    avif::write_avif(&mut output, &avif_config, &packets[0].data, &aux_items)
+                        .unwrap();
                 }
-                #[cfg(not(feature = "heic"))]
-                {
-                    unreachable!("HEIC gain-map path is unavailable without heic feature");
-                }
-            } else {
-                let avif_config = avif::AvifConfig {
-                    width,
-                    height,
-                    config_obus: avif_config_obus(&encoder, packets.len()),
-                    video_signal: cli.config.video_signal,
-                    content_light: cli.config.content_light,
-                    mastering_display: cli.config.mastering_display,
-                };
-                avif::write_avif(&mut output, &avif_config, &packets[0].data).unwrap();
+                file.write_all(&output).unwrap_or_else(|e| {
+                    eprintln!("Error writing {}: {}", cli.output_path, e);
+                    process::exit(1);
+                });
+                output.len()
             }
-            file.write_all(&output).unwrap_or_else(|e| {
-                eprintln!("Error writing {}: {}", cli.output_path, e);
-                process::exit(1);
-            });
-            output.len()
         }
     };
 
-    eprintln!();
+    if cli.log_level == LogLevel::Quiet {
+        return;
+    }
+    if cli.log_level == LogLevel::Verbose {
+        eprintln!();
+    }
     if let Some(stats) = encoder.rate_control_stats() {
         eprintln!(
             "Wrote {} bytes to {} ({} frames, target={}kbps, avg_qp={}, buffer={}%, keyint={})",
             output_size,
             cli.output_path,
-            frames.len(),
+            frame_count,
             stats.target_bitrate / 1000,
             stats.avg_qp,
             stats.buffer_fullness_pct,
             cli.config.keyint
         );
+        if cli.rc_report {
+            eprint!("{}", format_rc_report(&stats, cli.config.fps));
+        }
     } else {
         let dq = wav1c::dequant::lookup_dequant(
             cli.config.base_q_idx,
@@ -915,7 +3045,7 @@ fn main() {
             "Wrote {} bytes to {} ({} frames, q={}, keyint={}, bit_depth={}, dc_dq={}, ac_dq={})",
             output_size,
             cli.output_path,
-            frames.len(),
+            frame_count,
             cli.config.base_q_idx,
             cli.config.keyint,
             cli.config.video_signal.bit_depth.bits(),
@@ -923,6 +3053,248 @@ fn main() {
             dq.ac
         );
     }
+    let cap = encoder.frame_size_cap_stats();
+    if cap != wav1c::FrameSizeCapStats::default() {
+        eprintln!(
+            "Frame size cap: {} frames re-encoded coarser, {} repeated, {} flattened",
+            cap.requantized, cap.repeated, cap.flattened
+        );
+    }
+}
+
+/// Splits `frames` at the detected scene cuts (and with `--chunk-gops` at
+/// every keyframe interval within a scene), encodes each piece into its own
+/// IVF segment in `dir` on up to `--jobs` workers and joins the segments into
+/// the output file.
+fn encode_scene_chunks(
+    cli: &CliArgs,
+    dir: &Path,
+    frames: &[wav1c::y4m::FramePixels],
+) -> Result<(), String> {
+    std::fs::create_dir_all(dir).map_err(|e| format!("failed to create {}: {e}", dir.display()))?;
+    let cuts = wav1c::scene_cuts(&frame_complexity(frames, cli.config.first_pass_scale));
+    let scenes = scene_ranges(&cuts, frames.len());
+    let (ranges, name) = if cli.chunk_gops {
+        (chunks::gop_ranges(&scenes, cli.config.keyint), "gop")
+    } else {
+        (scenes.clone(), "scene")
+    };
+    let chunk_paths: Vec<_> = (0..ranges.len())
+        .map(|i| dir.join(format!("{name}-{i:04}.ivf")))
+        .collect();
+    let costs: Vec<usize> = ranges.iter().map(|range| range.len()).collect();
+
+    if cli.jobs > 1 && cli.chunk_workers == chunks::ChunkWorkers::Processes {
+        let exe = env::current_exe().map_err(|e| format!("cannot locate wav1c: {e}"))?;
+        let args: Vec<String> = env::args().skip(1).collect();
+        let range_start = cli.frame_range.map_or(0, |(first, _)| first);
+        chunks::run(&costs, cli.jobs, |i| {
+            let log_path = chunk_paths[i].with_extension("log");
+            let log = File::create(&log_path)
+                .map_err(|e| format!("failed to create {}: {e}", log_path.display()))?;
+            let child = process::Command::new(&exe)
+                .args(chunk_child_args(
+                    &args,
+                    range_start + ranges[i].start..range_start + ranges[i].end,
+                    &chunk_paths[i].to_string_lossy(),
+                ))
+                .stdout(process::Stdio::null())
+                .stderr(log)
+                .spawn()
+                .map_err(|e| format!("failed to start {name} {i}: {e}"))?;
+            wait_for_chunk(&format!("{name} {i}"), child, &log_path)
+        })?;
+    } else {
+        chunks::run(&costs, cli.jobs, |i| {
+            write_chunk(&frames[ranges[i].clone()], &cli.config, &chunk_paths[i])
+        })?;
+    }
+
+    let mut chunks = Vec::with_capacity(ranges.len());
+    for (i, (range, path)) in ranges.iter().zip(&chunk_paths).enumerate() {
+        let chunk = read_ivf_file(path)?;
+        if cli.log_level > LogLevel::Quiet {
+            eprintln!(
+                "{name} {i:>3}  frames {}-{}  {} packets  {}",
+                range.start,
+                range.end - 1,
+                chunk.frames.len(),
+                path.display()
+            );
+        }
+        chunks.push(chunk);
+    }
+    let mut output = Vec::new();
+    ivf::concat(&chunks)
+        .map_err(|e| format!("cannot join scene chunks: {e}"))?
+        .write_to(&mut output)
+        .map_err(|e| e.to_string())?;
+    std::fs::write(&cli.output_path, &output)
+        .map_err(|e| format!("failed to write {}: {e}", cli.output_path))?;
+
+    if cli.log_level == LogLevel::Quiet {
+        return Ok(());
+    }
+    eprintln!();
+    eprintln!(
+        "Wrote {} bytes to {} ({} frames in {} scenes, {} chunks, q={}, keyint={})",
+        output.len(),
+        cli.output_path,
+        frames.len(),
+        scenes.len(),
+        ranges.len(),
+        cli.config.base_q_idx,
+        cli.config.keyint
+    );
+    Ok(())
+}
+
+/// Encodes `frames` with a fresh encoder into the IVF file `path`.
+fn write_chunk(
+    frames: &[wav1c::y4m::FramePixels],
+    config: &EncodeConfig,
+    path: &Path,
+) -> Result<(), String> {
+    let packets = wav1c::TemporalUnitGrouper::group(wav1c::encode_packets(frames, config));
+    let mut output = Vec::new();
+    ivf::write_ivf_header(
+        &mut output,
+        frames[0].width,
+        frames[0].height,
+        packets.len() as u32,
+        config.fps.num,
+        config.fps.den,
+    )
+    .map_err(|e| e.to_string())?;
+    for p in &packets {
+        ivf::write_ivf_frame(&mut output, p.frame_number, &p.data).map_err(|e| e.to_string())?;
+    }
+    std::fs::write(path, output).map_err(|e| format!("failed to write {}: {e}", path.display()))
+}
+
+fn read_ivf_file(path: &Path) -> Result<ivf::IvfFile, String> {
+    let data =
+        std::fs::read(path).map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+    ivf::IvfFile::parse(&data).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+fn wait_for_chunk(chunk: &str, mut child: process::Child, log_path: &Path) -> Result<(), String> {
+    let status = child
+        .wait()
+        .map_err(|e| format!("failed to wait for {chunk}: {e}"))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "{chunk} failed ({status}); see {}",
+            log_path.display()
+        ))
+    }
+}
+
+/// Frame ranges of the scenes starting at each of `cuts`.
+fn scene_ranges(cuts: &[u64], frame_count: usize) -> Vec<std::ops::Range<usize>> {
+    cuts.iter()
+        .enumerate()
+        .map(|(i, &start)| {
+            let end = cuts.get(i + 1).map_or(frame_count, |&next| next as usize);
+            start as usize..end
+        })
+        .collect()
+}
+
+/// The command line that encodes input `frames` of the original invocation
+/// `args` into `output`: everything but the output and chunking options is
+/// passed through, so the child decodes and prepares the input the same way.
+fn chunk_child_args(args: &[String], frames: std::ops::Range<usize>, output: &str) -> Vec<String> {
+    let mut child_args = Vec::with_capacity(args.len() + 4);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--scene-chunks" | "--jobs" | "--chunk-workers" | "--frame-range" => {
+                args.next();
+            }
+            "--chunk-gops" => {}
+            _ => child_args.push(arg.clone()),
+        }
+    }
+    child_args.extend([
+        "--frame-range".to_string(),
+        format!("{}-{}", frames.start, frames.end - 1),
+        "-o".to_string(),
+        output.to_string(),
+    ]);
+    child_args
+}
+
+fn format_noise_report(noise: &[wav1c::NoiseStats], bit_depth: BitDepth) -> String {
+    let mean = |plane: fn(&wav1c::NoiseStats) -> Option<f64>| {
+        let measured: Vec<f64> = noise.iter().filter_map(plane).collect();
+        if measured.is_empty() {
+            "n/a".to_string()
+        } else {
+            format!(
+                "{:.2}",
+                measured.iter().sum::<f64>() / measured.len() as f64
+            )
+        }
+    };
+    let luma: Vec<f64> = noise.iter().filter_map(|n| n.y).collect();
+    let range = if luma.is_empty() {
+        String::new()
+    } else {
+        let min = luma.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = luma.iter().copied().fold(0.0, f64::max);
+        format!("; luma per frame {min:.2}-{max:.2}")
+    };
+    format!(
+        "Noise estimate (sigma in {}-bit samples, mean of {} frames): Y {} U {} V {}{range}\n",
+        bit_depth.bits(),
+        noise.len(),
+        mean(|n| n.y),
+        mean(|n| n.u),
+        mean(|n| n.v),
+    )
+}
+
+fn format_rc_report(stats: &wav1c::rc::RateControlStats, fps: Fps) -> String {
+    use std::fmt::Write as _;
+
+    let mut out = String::from("Rate control report:\n");
+    for (i, gop) in stats.gops.iter().enumerate() {
+        let frames = gop.frames();
+        let last = gop.first_frame + frames as u64 - 1;
+        let kbps = gop.total_bits() as f64 * fps.as_f64() / frames as f64 / 1000.0;
+        let inter_avg = gop
+            .inter_bits
+            .checked_div(gop.inter_frames as u64)
+            .unwrap_or(0);
+        let _ = writeln!(
+            out,
+            "  GOP {i} (frames {}-{last}): {} bits, {kbps:.0}kbps; key {}x {} bits, \
+             inter {}x avg {inter_avg} bits, peak {} bits",
+            gop.first_frame,
+            gop.total_bits(),
+            gop.keyframes,
+            gop.keyframe_bits,
+            gop.inter_frames,
+            gop.peak_frame_bits,
+        );
+        let histogram: Vec<String> = gop
+            .qp_histogram
+            .iter()
+            .enumerate()
+            .filter(|&(_, &count)| count > 0)
+            .map(|(bucket, count)| {
+                let lo = bucket * wav1c::rc::QP_HISTOGRAM_BUCKET;
+                format!("{lo}-{}:{count}", lo + wav1c::rc::QP_HISTOGRAM_BUCKET - 1)
+            })
+            .collect();
+        let _ = writeln!(out, "    qp {}", histogram.join(" "));
+        let trajectory: Vec<String> = gop.buffer_trajectory.iter().map(u32::to_string).collect();
+        let _ = writeln!(out, "    buffer% {}", trajectory.join(" "));
+    }
+    out
 }
 
 #[cfg(test)]
@@ -953,6 +3325,289 @@ mod tests {
         assert!(err.contains("use INT or NUM/DEN"));
     }
 
+    #[test]
+    fn parse_companion_splits_kind_and_path() {
+        assert_eq!(
+            parse_companion("alpha=matte.y4m"),
+            Ok((mp4::CompanionKind::Alpha, "matte.y4m".to_owned()))
+        );
+        assert_eq!(
+            parse_companion("gain-map=gm.y4m").map(|(kind, _)| kind),
+            Ok(mp4::CompanionKind::GainMap)
+        );
+        assert!(parse_companion("depth=d.y4m").is_err());
+        assert!(parse_companion("alpha").is_err());
+        assert!(parse_companion("alpha=").is_err());
+    }
+
+    #[test]
+    fn parse_tile_count_requires_power_of_two() {
+        assert_eq!(parse_tile_count("--tile-columns", "4"), Ok(4));
+        assert!(parse_tile_count("--tile-columns", "3").is_err());
+        assert!(parse_tile_count("--tile-columns", "0").is_err());
+        assert!(parse_tile_count("--tile-columns", "128").is_err());
+        assert_eq!(
+            parse_tile_count("--tile-rows", "x"),
+            Err("invalid --tile-rows value: x".to_owned())
+        );
+    }
+
+    #[test]
+    fn parse_first_pass_scale_is_bounded() {
+        assert_eq!(parse_first_pass_scale("4"), Ok(4));
+        assert!(parse_first_pass_scale("0").is_err());
+        assert!(parse_first_pass_scale("9").is_err());
+    }
+
+    #[test]
+    fn parse_dead_zone_reads_four_offsets() {
+        let dz = parse_dead_zone("128,96,112,80").expect("expected dead zone to parse");
+        assert_eq!(
+            (dz.intra_dc, dz.intra_ac, dz.inter_dc, dz.inter_ac),
+            (128, 96, 112, 80)
+        );
+        assert_eq!(dz.skip_bias, 0);
+        assert!(parse_dead_zone("128,96,112").is_err());
+        assert!(parse_dead_zone("128,96,112,300").is_err());
+    }
+
+    #[test]
+    fn parse_frame_range_is_inclusive_and_ordered() {
+        assert_eq!(parse_frame_range("0-0"), Ok((0, 0)));
+        assert_eq!(parse_frame_range("12-40"), Ok((12, 40)));
+        assert!(parse_frame_range("5-4").is_err());
+        assert!(parse_frame_range("5").is_err());
+        assert!(parse_frame_range("-4").is_err());
+    }
+
+    #[test]
+    fn scene_ranges_cover_every_frame_once() {
+        assert_eq!(scene_ranges(&[0, 4, 9], 12), vec![0..4, 4..9, 9..12]);
+        assert_eq!(scene_ranges(&[0], 1), vec![0..1]);
+    }
+
+    #[test]
+    fn chunk_children_replace_output_and_chunking_options() {
+        let args: Vec<String> = [
+            "in.y4m",
+            "-o",
+            "out.ivf",
+            "--scene-chunks",
+            "chunks",
+            "-q",
+            "90",
+            "--jobs",
+            "4",
+            "--chunk-gops",
+            "--chunk-workers",
+            "processes",
+            "--frame-range",
+            "10-99",
+        ]
+        .map(String::from)
+        .to_vec();
+        assert_eq!(
+            chunk_child_args(&args, 14..30, "chunks/scene-0001.ivf"),
+            [
+                "in.y4m",
+                "-q",
+                "90",
+                "--frame-range",
+                "14-29",
+                "-o",
+                "chunks/scene-0001.ivf"
+            ]
+        );
+    }
+
+    #[test]
+    fn rc_report_lists_each_gop() {
+        let config = EncodeConfig {
+            target_bitrate: Some(300_000),
+            keyint: 3,
+            ..EncodeConfig::default()
+        };
+        let mut encoder = wav1c::Encoder::new(64, 64, EncoderConfig::from(&config)).unwrap();
+        let frame = wav1c::y4m::FramePixels::solid(64, 64, 90, 120, 140);
+        for _ in 0..5 {
+            encoder.send_frame(&frame).unwrap();
+        }
+        encoder.flush();
+        let report = format_rc_report(&encoder.rate_control_stats().unwrap(), config.fps);
+        assert!(report.contains("GOP 0 (frames 0-2)"), "{report}");
+        assert!(report.contains("GOP 1 (frames 3-4)"), "{report}");
+        assert_eq!(report.matches("    qp ").count(), 2);
+        assert_eq!(report.matches("    buffer% ").count(), 2);
+    }
+
+    #[test]
+    fn dedup_frames_shows_one_frame_per_identical_run() {
+        let solid = |y| wav1c::y4m::FramePixels::solid(16, 16, y, 128, 128);
+        let input = vec![
+            solid(10),
+            solid(10),
+            solid(10),
+            solid(20),
+            solid(10),
+            solid(10),
+        ];
+        let (kept, runs) = FrameRuns::dedup(input);
+        assert_eq!(
+            kept.iter().map(|f| f.y[0]).collect::<Vec<_>>(),
+            [10, 20, 10]
+        );
+        assert_eq!((0..3).map(|n| runs.start(n)).collect::<Vec<_>>(), [0, 3, 4]);
+        assert_eq!((0..3).map(|n| runs.len(n)).collect::<Vec<_>>(), [3, 1, 2]);
+
+        let packet = wav1c::Packet {
+            data: vec![0x12, 0x00, 0x0A],
+            ..wav1c::encode_packets(&kept[..1], &EncodeConfig::default()).remove(0)
+        };
+        let sample = runs.mp4_sample(&packet, 1_001);
+        assert_eq!((sample.data, sample.duration), (vec![0x0A], 3_003));
+        assert_eq!(FrameRuns::default().len(7), 1);
+        assert_eq!(FrameRuns::default().start(7), 7);
+    }
+
+    #[test]
+    fn motion_field_records_hold_both_vectors_per_unit() {
+        let config = EncodeConfig {
+            motion_fields: true,
+            ..EncodeConfig::default()
+        };
+        let mut encoder = wav1c::Encoder::new(40, 24, wav1c::EncoderConfig::from(&config)).unwrap();
+        for shift in [0, 3] {
+            let mut frame = wav1c::y4m::FramePixels::solid(40, 24, 0, 128, 128);
+            for (i, s) in frame.y.iter_mut().enumerate() {
+                *s = ((i % 40 + shift) * 4 + i / 40 * 3) as u16;
+            }
+            encoder.send_frame(&frame).unwrap();
+        }
+        let field = encoder.receive_motion_field().unwrap();
+        let record = motion_field_record(&field);
+
+        assert_eq!(
+            &record[..16],
+            &[1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(&record[16..20], &[10, 0, 6, 0]);
+        assert_eq!(record.len(), 20 + 60 * 8);
+        let value = |at: usize| i16::from_le_bytes([record[at], record[at + 1]]);
+        let expect = |mv: Option<(i32, i32)>| {
+            mv.map_or([MOTION_FIELDS_NONE; 2], |(x, y)| [x as i16, y as i16])
+        };
+        for (unit, (x, y)) in (0..6)
+            .flat_map(|y| (0..10).map(move |x| (x, y)))
+            .enumerate()
+        {
+            let at = 20 + 8 * unit;
+            assert_eq!([value(at), value(at + 2)], expect(field.coded.get(x, y)));
+            assert_eq!(
+                [value(at + 4), value(at + 6)],
+                expect(field.estimates.get(x, y))
+            );
+        }
+    }
+
+    #[test]
+    fn distortion_map_images_brighten_where_quality_is_lost() {
+        let map = wav1c::metrics::DistortionMap {
+            frame_number: 0,
+            sb_cols: 3,
+            sb_rows: 1,
+            // 100, 35 and about 18 dB.
+            sse: vec![0, 4096 * 20, 4096 * 1000],
+            samples: vec![4096; 3],
+            max_value: 255,
+        };
+        let png = distortion_map_png(&map);
+        // 24x8 pixels: IHDR size, then the first row behind its filter byte.
+        assert_eq!(&png[16..24], &[0, 0, 0, 24, 0, 0, 0, 8]);
+        let row = &png[49..73];
+        assert!(row[..8].iter().all(|&p| p == 0));
+        assert!(row[8..16].iter().all(|&p| p == row[8]) && (100..160).contains(&row[8]));
+        assert!(row[16..].iter().all(|&p| p == 255));
+    }
+
+    #[test]
+    fn analysis_lists_scenes_and_suggestions() {
+        let grid = wav1c::y4m::FramePixels::grid(
+            64,
+            64,
+            8,
+            [220, 128, 128],
+            [30, 128, 128],
+            BitDepth::Eight,
+            ColorRange::Limited,
+        );
+        let flat = wav1c::y4m::FramePixels::solid(64, 64, 30, 128, 128);
+        let frames = [&grid, &grid, &flat, &flat, &flat];
+        let complexity: Vec<_> = frames
+            .iter()
+            .enumerate()
+            .map(|(i, frame)| {
+                wav1c::analysis::analyze_frame(i as u64, frame, i.checked_sub(1).map(|p| frames[p]))
+            })
+            .collect();
+        let noise: Vec<_> = frames.iter().map(|f| wav1c::estimate_noise(f)).collect();
+        let report = format_analysis(&grid, Fps::default(), &complexity, &noise, 40.0);
+        assert!(
+            report.starts_with("64x64 8-bit, 5 frames at 25/1 fps\n"),
+            "{report}"
+        );
+        assert!(
+            report.contains("Scenes: 2\n  scene 0: frames 0-1\n  scene 1: frames 2-4\n"),
+            "{report}"
+        );
+        assert!(
+            report.contains("Suggested for 40.0 dB PSNR: -q "),
+            "{report}"
+        );
+    }
+
+    #[test]
+    fn noise_report_averages_measured_frames() {
+        let noise = [
+            wav1c::NoiseStats {
+                y: Some(2.0),
+                u: Some(1.0),
+                v: None,
+            },
+            wav1c::NoiseStats {
+                y: Some(3.0),
+                u: Some(1.5),
+                v: None,
+            },
+        ];
+        assert_eq!(
+            format_noise_report(&noise, BitDepth::Eight),
+            "Noise estimate (sigma in 8-bit samples, mean of 2 frames): Y 2.50 U 1.25 V n/a; \
+             luma per frame 2.00-3.00\n"
+        );
+    }
+
+    #[test]
+    fn parse_orientation_accepts_exif_values() {
+        assert_eq!(parse_orientation("6").map(|o| o.exif_value()), Ok(6));
+        assert!(parse_orientation("0").is_err());
+        assert!(parse_orientation("9").is_err());
+        assert!(parse_orientation("cw").is_err());
+    }
+
+    #[test]
+    fn parse_chroma_delta_q_accepts_shared_and_separate_offsets() {
+        let shared = parse_chroma_delta_q("-8,-4").expect("expected shared offsets to parse");
+        assert_eq!(
+            (shared.u_dc, shared.u_ac, shared.v_dc, shared.v_ac),
+            (-8, -4, -8, -4)
+        );
+        assert!(!shared.separate_uv());
+        let separate = parse_chroma_delta_q("-8,-4,2,0").expect("expected four offsets to parse");
+        assert!(separate.separate_uv());
+        assert!(parse_chroma_delta_q("64,0").is_err());
+        assert!(parse_chroma_delta_q("-8,-4,2").is_err());
+    }
+
     #[test]
     fn hdr10_on_8bit_input_is_rejected() {
         let err = validate_bit_depth_constraints(true, BitDepth::Eight, BitDepth::Ten)
@@ -967,6 +3622,22 @@ mod tests {
         assert!(err.contains("Automatic bit-depth scaling was removed"));
     }
 
+    #[test]
+    #[cfg(feature = "heic")]
+    fn auto_gain_map_target_rises_for_narrow_maps() {
+        let mut frame = wav1c::y4m::FramePixels::solid(8, 8, 0, 128, 128);
+        for (i, s) in frame.y.iter_mut().enumerate() {
+            *s = (i * 4) as u16;
+        }
+        let wide = auto_gain_map_psnr_target(&frame);
+        for s in frame.y.iter_mut() {
+            *s = 100 + *s / 16;
+        }
+        let narrow = auto_gain_map_psnr_target(&frame);
+        assert!(narrow > wide);
+        assert!((32.0..=50.0).contains(&narrow));
+    }
+
     #[test]
     #[cfg(feature = "heic")]
     fn heic_avif_auto_gain_map_path_trigger_conditions() {
@@ -1032,4 +3703,39 @@ mod tests {
     fn oversized_avif_output_is_allowed() {
         validate_output_dimensions(OutputFormat::Avif, 70_000, 70_000).expect("expected AVIF ok");
     }
+
+    #[test]
+    fn comparison_reports_frames_average_and_worst() {
+        use wav1c::metrics::{FramePsnr, FrameSsim};
+
+        let frame = |y: f64, ssim: f64| {
+            (
+                FramePsnr {
+                    y,
+                    u: 44.0,
+                    v: 44.0,
+                },
+                FrameSsim {
+                    y: ssim,
+                    u: 0.99,
+                    v: 0.99,
+                },
+            )
+        };
+        let quality = [frame(40.0, 0.98), frame(36.0, 0.94)];
+        let report = format_comparison(&quality, "PSNR", false);
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].starts_with("frame     1: PSNR Y 36.00 U 44.00 V 44.00 W 38.00 dB"));
+        assert_eq!(
+            lines[2],
+            "average (2 frames): PSNR Y 38.00 U 44.00 V 44.00 W 39.50 dB  \
+             SSIM Y 0.9600 U 0.9900 V 0.9900 W 0.9675"
+        );
+        assert_eq!(lines[3], "worst frame: 1 (PSNR W 38.00 dB)");
+        assert_eq!(format_comparison(&quality, "PSNR", true).lines().count(), 2);
+        let spherical = format_comparison(&quality, "WS-PSNR", true);
+        assert!(spherical.starts_with("average (2 frames): WS-PSNR Y 38.00"));
+        assert!(spherical.ends_with("worst frame: 1 (WS-PSNR W 38.00 dB)\n"));
+    }
 }
@@ -1,19 +1,192 @@
 use std::io::{self, Write};
 
-use wav1c::{BitDepth, ColorRange, VideoSignal};
+use crate::apple_hdr::APPLE_HDR_GAINMAP_AUX_TYPE;
+use crate::audio::{AudioCodec, AudioTrack};
+use crate::orientation::Orientation;
+use crate::text::{TEXT_TIMESCALE, TextKind, TextTrack};
+use wav1c::{ColorRange, VideoSignal};
 
 pub struct Mp4Config {
     pub width: u32,
     pub height: u32,
     pub fps_num: u32,
     pub fps_den: u32,
-    pub config_obus: Vec<u8>,
+    /// `AV1CodecConfigurationRecord` from `Encoder::av1c`.
+    pub av1c: Vec<u8>,
     pub video_signal: VideoSignal,
+    /// Display transform written as the `tkhd` matrix; `width`/`height` stay
+    /// the coded dimensions everywhere.
+    pub orientation: Orientation,
+    /// Pixel aspect ratio written in `pasp`; `None` for square pixels.
+    pub pixel_aspect: Option<(u32, u32)>,
+    /// Encoder name and settings written as the iTunes-style `©too` tag in
+    /// `moov/udta`, as `Encoder::build_info` returns them.
+    pub encoder_info: Option<String>,
 }
 
 pub struct Mp4Sample {
     pub data: Vec<u8>,
     pub is_sync: bool,
+    /// Display time in `fps_num` timescale units: `fps_den` for one frame,
+    /// a multiple of it for a frame standing in for repeated ones.
+    pub duration: u32,
+    /// No later sample depends on this one (a B-frame), so a player may
+    /// skip it when it falls behind.
+    pub disposable: bool,
+}
+
+impl Mp4Sample {
+    /// `sample_depends_on` and `sample_is_depended_on` (ISO/IEC 14496-12
+    /// 8.6.4), placed as in an `sdtp` entry: 2 for "no", 1 for "yes".
+    fn dependency_flags(&self) -> u8 {
+        let depends_on = if self.is_sync { 2 } else { 1 };
+        let is_depended_on = if self.disposable { 2 } else { 1 };
+        (depends_on << 4) | (is_depended_on << 2)
+    }
+}
+
+/// What a companion track carries alongside the main video.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompanionKind {
+    /// Alpha plane coded as luma.
+    Alpha,
+    /// Apple-style HDR gain map.
+    GainMap,
+}
+
+impl CompanionKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "alpha" => Some(CompanionKind::Alpha),
+            "gain-map" => Some(CompanionKind::GainMap),
+            _ => None,
+        }
+    }
+
+    /// URN written in the sample entry's `auxi` box, or an AVIF item's
+    /// `auxC`.
+    pub fn aux_type(self) -> &'static str {
+        match self {
+            CompanionKind::Alpha => "urn:mpeg:mpegB:cicp:systems:auxiliary:alpha",
+            CompanionKind::GainMap => APPLE_HDR_GAINMAP_AUX_TYPE,
+        }
+    }
+}
+
+/// A second video track written as an auxiliary (`auxv`) track that
+/// references the main track with `tref`/`auxl`. Its samples are decoded in
+/// step with the main track's; dimensions may differ.
+pub struct Mp4Companion {
+    pub kind: CompanionKind,
+    pub config: Mp4Config,
+    pub samples: Vec<Mp4Sample>,
+}
+
+/// Where [`write_mp4`] puts the `moov` box.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MoovPlacement {
+    /// After `mdat`, as written in one pass.
+    #[default]
+    End,
+    /// Before `mdat` ("faststart"), so a player fetching the file over HTTP
+    /// can start before the rest has arrived.
+    Front,
+}
+
+/// What a `trak` carries.
+enum TrackMedia<'a> {
+    Video {
+        config: &'a Mp4Config,
+        companion: Option<CompanionKind>,
+    },
+    Audio(&'a AudioTrack),
+    Text(TextKind),
+}
+
+/// One `trak` of a non-fragmented file.
+struct TrackSpec<'a> {
+    track_id: u32,
+    media: TrackMedia<'a>,
+    /// `tref` entry: reference type and the track it points to.
+    reference: Option<(&'static [u8; 4], u32)>,
+    samples: &'a [Mp4Sample],
+    samples_per_chunk: usize,
+    /// File offset of each chunk.
+    chunk_offsets: Vec<u32>,
+}
+
+impl<'a> TrackSpec<'a> {
+    fn video(
+        track_id: u32,
+        config: &'a Mp4Config,
+        samples: &'a [Mp4Sample],
+        companion: Option<CompanionKind>,
+    ) -> Self {
+        Self {
+            track_id,
+            media: TrackMedia::Video { config, companion },
+            reference: companion.map(|_| (b"auxl", 1)),
+            samples,
+            samples_per_chunk: samples_per_chunk(config.fps_num, config.fps_den),
+            chunk_offsets: Vec::new(),
+        }
+    }
+
+    fn audio(track_id: u32, audio: &'a AudioTrack) -> Self {
+        let packet_duration = audio.samples.first().map_or(1, |s| s.duration);
+        Self {
+            track_id,
+            media: TrackMedia::Audio(audio),
+            reference: None,
+            samples: &audio.samples,
+            samples_per_chunk: samples_per_chunk(audio.sample_rate, packet_duration),
+            chunk_offsets: Vec::new(),
+        }
+    }
+
+    fn text(track_id: u32, text: &'a TextTrack) -> Self {
+        Self {
+            track_id,
+            media: TrackMedia::Text(text.kind),
+            reference: None,
+            samples: &text.samples,
+            samples_per_chunk: text.samples.len().max(1),
+            chunk_offsets: Vec::new(),
+        }
+    }
+
+    fn timescale(&self) -> u32 {
+        match self.media {
+            TrackMedia::Video { config, .. } => config.fps_num,
+            TrackMedia::Audio(audio) => audio.sample_rate,
+            TrackMedia::Text(_) => TEXT_TIMESCALE,
+        }
+    }
+
+    fn chunks(&self) -> std::slice::Chunks<'_, Mp4Sample> {
+        self.samples.chunks(self.samples_per_chunk)
+    }
+
+    /// Media time each chunk starts at, in the track's timescale.
+    fn chunk_starts(&self) -> Vec<u64> {
+        let mut start = 0;
+        self.chunks()
+            .map(|chunk| {
+                let chunk_start = start;
+                start += chunk.iter().map(|s| s.duration as u64).sum::<u64>();
+                chunk_start
+            })
+            .collect()
+    }
+}
+
+/// About one second of samples per chunk, so the tracks of a file interleave
+/// at a granularity players read ahead comfortably.
+fn samples_per_chunk(timescale: u32, sample_duration: u32) -> usize {
+    (timescale as usize)
+        .checked_div(sample_duration as usize)
+        .unwrap_or(0)
+        .max(1)
 }
 
 pub fn strip_temporal_delimiters(data: &[u8]) -> Vec<u8> {
@@ -24,26 +197,183 @@ pub fn strip_temporal_delimiters(data: &[u8]) -> Vec<u8> {
     }
 }
 
-pub fn write_mp4<W: Write>(w: &mut W, config: &Mp4Config, samples: &[Mp4Sample]) -> io::Result<()> {
+/// Writes a non-fragmented MP4, with an optional companion track, an
+/// optional passed-through audio track and any text tracks after the main
+/// video track. Each audio and video track is stored in chunks of about a
+/// second, laid out in `mdat` in order of their start times so the tracks
+/// interleave; a text track is one chunk. A chapter track is referenced from
+/// the main track with `tref`/`chap`.
+pub fn write_mp4<W: Write>(
+    w: &mut W,
+    config: &Mp4Config,
+    samples: &[Mp4Sample],
+    companion: Option<&Mp4Companion>,
+    audio: Option<&AudioTrack>,
+    text: &[TextTrack],
+    placement: MoovPlacement,
+) -> io::Result<()> {
     validate_mp4_dimensions(config.width, config.height)?;
+    if let Some(c) = companion {
+        validate_mp4_dimensions(c.config.width, c.config.height)?;
+    }
 
     let ftyp = build_ftyp();
 
+    let mut tracks = vec![TrackSpec::video(1, config, samples, None)];
+    if let Some(c) = companion {
+        tracks.push(TrackSpec::video(2, &c.config, &c.samples, Some(c.kind)));
+    }
+    if let Some(audio) = audio {
+        tracks.push(TrackSpec::audio(tracks.len() as u32 + 1, audio));
+    }
+    for text in text {
+        let track_id = tracks.len() as u32 + 1;
+        if text.kind == TextKind::Chapters && tracks[0].reference.is_none() {
+            tracks[0].reference = Some((b"chap", track_id));
+        }
+        tracks.push(TrackSpec::text(track_id, text));
+    }
+
+    // Lay out mdat chunk by chunk, earliest start first (ties in track
+    // order), recording offsets relative to its payload.
+    let mut order: Vec<(usize, usize, u64, u32)> = tracks
+        .iter()
+        .enumerate()
+        .flat_map(|(t, track)| {
+            let timescale = track.timescale().max(1);
+            track
+                .chunk_starts()
+                .into_iter()
+                .enumerate()
+                .map(move |(chunk, start)| (t, chunk, start, timescale))
+        })
+        .collect();
+    order.sort_by(|a, b| {
+        let (a_time, b_time) = (a.2 as u128 * b.3 as u128, b.2 as u128 * a.3 as u128);
+        a_time.cmp(&b_time).then(a.0.cmp(&b.0))
+    });
     let mut mdat_payload = Vec::new();
-    for s in samples {
-        mdat_payload.extend_from_slice(&s.data);
+    let mut relative_offsets = vec![Vec::new(); tracks.len()];
+    for (t, chunk, _, _) in order {
+        relative_offsets[t].push(mdat_payload.len() as u64);
+        if let Some(samples) = tracks[t].chunks().nth(chunk) {
+            for s in samples {
+                mdat_payload.extend_from_slice(&s.data);
+            }
+        }
     }
     let mdat = build_mdat(&mdat_payload);
 
-    let data_offset = ftyp.len() as u32 + 8;
-    let moov = build_moov(config, samples, data_offset);
+    // The tables have the same size whatever the offsets, so a first build
+    // with placeholder offsets tells where mdat starts when moov precedes it.
+    for (track, offsets) in tracks.iter_mut().zip(&relative_offsets) {
+        track.chunk_offsets = vec![0; offsets.len()];
+    }
+    let mdat_start = match placement {
+        MoovPlacement::End => ftyp.len(),
+        MoovPlacement::Front => ftyp.len() + build_moov(&tracks).len(),
+    } as u64;
+    for (track, offsets) in tracks.iter_mut().zip(&relative_offsets) {
+        track.chunk_offsets = offsets
+            .iter()
+            .map(|offset| u32::try_from(mdat_start + 8 + offset))
+            .collect::<Result<_, _>>()
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "MP4 sample data exceeds the 32-bit chunk offset limit",
+                )
+            })?;
+    }
+    let moov = build_moov(&tracks);
 
     w.write_all(&ftyp)?;
-    w.write_all(&mdat)?;
-    w.write_all(&moov)?;
+    match placement {
+        MoovPlacement::End => {
+            w.write_all(&mdat)?;
+            w.write_all(&moov)?;
+        }
+        MoovPlacement::Front => {
+            w.write_all(&moov)?;
+            w.write_all(&mdat)?;
+        }
+    }
     Ok(())
 }
 
+/// Fragmented MP4 writer: `ftyp` and an empty-table `moov` go out first, then
+/// one `moof`+`mdat` pair per GOP (flushed when the next sync sample arrives),
+/// so a file cut short by a crash stays playable up to the last whole GOP.
+pub struct FragmentedMp4Writer<W: Write> {
+    writer: W,
+    fps_den: u32,
+    sequence_number: u32,
+    base_decode_time: u64,
+    pending: Vec<Mp4Sample>,
+}
+
+impl<W: Write> FragmentedMp4Writer<W> {
+    pub fn new(mut writer: W, config: &Mp4Config) -> io::Result<Self> {
+        validate_mp4_dimensions(config.width, config.height)?;
+        writer.write_all(&build_ftyp())?;
+        writer.write_all(&build_fragmented_moov(config))?;
+        writer.flush()?;
+        Ok(Self {
+            writer,
+            fps_den: config.fps_den,
+            sequence_number: 1,
+            base_decode_time: 0,
+            pending: Vec::new(),
+        })
+    }
+
+    pub fn push_sample(&mut self, sample: Mp4Sample) -> io::Result<()> {
+        if sample.is_sync && !self.pending.is_empty() {
+            self.write_fragment()?;
+        }
+        self.pending.push(sample);
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.pending.is_empty() {
+            self.write_fragment()?;
+        }
+        Ok(self.writer)
+    }
+
+    fn write_fragment(&mut self) -> io::Result<()> {
+        let samples = std::mem::take(&mut self.pending);
+        let moof_len = build_moof(
+            self.sequence_number,
+            self.base_decode_time,
+            self.fps_den,
+            &samples,
+            0,
+        )
+        .len();
+        let moof = build_moof(
+            self.sequence_number,
+            self.base_decode_time,
+            self.fps_den,
+            &samples,
+            moof_len as u32 + 8,
+        );
+        let mut mdat_payload = Vec::new();
+        for s in &samples {
+            mdat_payload.extend_from_slice(&s.data);
+        }
+
+        self.writer.write_all(&moof)?;
+        self.writer.write_all(&build_mdat(&mdat_payload))?;
+        self.writer.flush()?;
+
+        self.sequence_number += 1;
+        self.base_decode_time += samples.iter().map(|s| s.duration as u64).sum::<u64>();
+        Ok(())
+    }
+}
+
 fn validate_mp4_dimensions(width: u32, height: u32) -> io::Result<()> {
     let max = u16::MAX as u32;
     if width > max {
@@ -101,31 +431,147 @@ fn build_mdat(data: &[u8]) -> Vec<u8> {
     box_wrap(b"mdat", data)
 }
 
-fn build_moov(config: &Mp4Config, samples: &[Mp4Sample], data_offset: u32) -> Vec<u8> {
-    let num_samples = samples.len() as u64;
-    let media_duration = num_samples * config.fps_den as u64;
-    let total_ms = if config.fps_num > 0 {
-        (media_duration * 1000) / config.fps_num as u64
+fn build_moov(tracks: &[TrackSpec]) -> Vec<u8> {
+    let mut traks = Vec::new();
+    let mut movie_ms = 0;
+    for track in tracks {
+        let media_duration: u64 = track.samples.iter().map(|s| s.duration as u64).sum();
+        // Audio presents less than it decodes: the pre-skip and padding.
+        let presented = match track.media {
+            TrackMedia::Audio(audio) => audio.presented,
+            TrackMedia::Video { .. } | TrackMedia::Text(_) => media_duration,
+        };
+        let total_ms = (presented * 1000)
+            .checked_div(track.timescale() as u64)
+            .unwrap_or(0);
+        movie_ms = movie_ms.max(total_ms);
+        traks.extend_from_slice(&build_trak(track, media_duration as u32, total_ms as u32));
+    }
+
+    let mut payload = Vec::new();
+    payload.extend_from_slice(&build_mvhd(movie_ms as u32, tracks.len() as u32 + 1));
+    payload.extend_from_slice(&traks);
+    if let TrackMedia::Video { config, .. } = tracks[0].media
+        && let Some(info) = &config.encoder_info
+    {
+        payload.extend_from_slice(&build_udta(info));
+    }
+    box_wrap(b"moov", &payload)
+}
+
+fn build_fragmented_moov(config: &Mp4Config) -> Vec<u8> {
+    let mut stbl = Vec::new();
+    stbl.extend_from_slice(&build_stsd(config, None));
+    stbl.extend_from_slice(&full_box(b"stts", 0, 0, &0u32.to_be_bytes()));
+    stbl.extend_from_slice(&full_box(b"stsc", 0, 0, &0u32.to_be_bytes()));
+    stbl.extend_from_slice(&build_stsz(&[]));
+    stbl.extend_from_slice(&full_box(b"stco", 0, 0, &0u32.to_be_bytes()));
+
+    let mut minf = Vec::new();
+    minf.extend_from_slice(&full_box(b"vmhd", 0, 1, &[0u8; 8]));
+    minf.extend_from_slice(&build_dinf());
+    minf.extend_from_slice(&box_wrap(b"stbl", &stbl));
+
+    let mut mdia = Vec::new();
+    mdia.extend_from_slice(&build_mdhd(config.fps_num, 0));
+    mdia.extend_from_slice(&build_hdlr(b"vide", b"VideoHandler\0"));
+    mdia.extend_from_slice(&box_wrap(b"minf", &minf));
+
+    let mut trak = Vec::new();
+    trak.extend_from_slice(&build_tkhd(config, 1, 0, true));
+    trak.extend_from_slice(&box_wrap(b"mdia", &mdia));
+
+    let mut trex = Vec::new();
+    trex.extend_from_slice(&1u32.to_be_bytes());
+    trex.extend_from_slice(&1u32.to_be_bytes());
+    trex.extend_from_slice(&config.fps_den.to_be_bytes());
+    trex.extend_from_slice(&0u32.to_be_bytes());
+    trex.extend_from_slice(&0u32.to_be_bytes());
+    let mvex = box_wrap(b"mvex", &full_box(b"trex", 0, 0, &trex));
+
+    let mut payload = Vec::new();
+    payload.extend_from_slice(&build_mvhd(0, 2));
+    payload.extend_from_slice(&box_wrap(b"trak", &trak));
+    payload.extend_from_slice(&mvex);
+    if let Some(info) = &config.encoder_info {
+        payload.extend_from_slice(&build_udta(info));
+    }
+    box_wrap(b"moov", &payload)
+}
+
+/// `udta/meta/ilst` holding a single `©too` (encoding tool) tag, the layout
+/// FFmpeg writes and reads for its `encoder` tag.
+fn build_udta(encoder_info: &str) -> Vec<u8> {
+    let mut hdlr = Vec::new();
+    hdlr.extend_from_slice(&0u32.to_be_bytes());
+    hdlr.extend_from_slice(b"mdir");
+    hdlr.extend_from_slice(b"appl");
+    hdlr.extend_from_slice(&[0u8; 8]);
+    hdlr.push(0);
+
+    let mut data = Vec::new();
+    // Well-known type 1 (UTF-8), default locale.
+    data.extend_from_slice(&1u32.to_be_bytes());
+    data.extend_from_slice(&0u32.to_be_bytes());
+    data.extend_from_slice(encoder_info.as_bytes());
+    let tag = box_wrap(b"\xa9too", &box_wrap(b"data", &data));
+
+    let mut meta = full_box(b"hdlr", 0, 0, &hdlr);
+    meta.extend_from_slice(&box_wrap(b"ilst", &tag));
+    box_wrap(b"udta", &full_box(b"meta", 0, 0, &meta))
+}
+
+fn build_moof(
+    sequence_number: u32,
+    base_decode_time: u64,
+    sample_duration: u32,
+    samples: &[Mp4Sample],
+    data_offset: u32,
+) -> Vec<u8> {
+    let mfhd = full_box(b"mfhd", 0, 0, &sequence_number.to_be_bytes());
+
+    // default-base-is-moof | default-sample-duration-present
+    let mut tfhd = Vec::new();
+    tfhd.extend_from_slice(&1u32.to_be_bytes());
+    tfhd.extend_from_slice(&sample_duration.to_be_bytes());
+    let tfhd = full_box(b"tfhd", 0, 0x020008, &tfhd);
+
+    let tfdt = full_box(b"tfdt", 1, 0, &base_decode_time.to_be_bytes());
+
+    // data-offset | sample-size | sample-flags present, plus sample-duration
+    // when a sample lasts other than one frame.
+    let per_sample_duration = samples.iter().any(|s| s.duration != sample_duration);
+    let mut trun = Vec::new();
+    trun.extend_from_slice(&(samples.len() as u32).to_be_bytes());
+    trun.extend_from_slice(&data_offset.to_be_bytes());
+    for s in samples {
+        if per_sample_duration {
+            trun.extend_from_slice(&s.duration.to_be_bytes());
+        }
+        trun.extend_from_slice(&(s.data.len() as u32).to_be_bytes());
+        let non_sync = if s.is_sync { 0 } else { 0x0001_0000 };
+        let flags = ((s.dependency_flags() as u32) << 20) | non_sync;
+        trun.extend_from_slice(&flags.to_be_bytes());
+    }
+    let trun_flags = if per_sample_duration {
+        0x000701
     } else {
-        0
+        0x000601
     };
+    let trun = full_box(b"trun", 0, trun_flags, &trun);
 
-    let mvhd = build_mvhd(total_ms as u32);
-    let trak = build_trak(
-        config,
-        samples,
-        data_offset,
-        media_duration as u32,
-        total_ms as u32,
-    );
+    let mut traf = Vec::new();
+    traf.extend_from_slice(&tfhd);
+    traf.extend_from_slice(&tfdt);
+    traf.extend_from_slice(&trun);
 
     let mut payload = Vec::new();
-    payload.extend_from_slice(&mvhd);
-    payload.extend_from_slice(&trak);
-    box_wrap(b"moov", &payload)
+    payload.extend_from_slice(&mfhd);
+    payload.extend_from_slice(&box_wrap(b"traf", &traf));
+    box_wrap(b"moof", &payload)
 }
 
-fn build_mvhd(duration_ms: u32) -> Vec<u8> {
+fn build_mvhd(duration_ms: u32, next_track_id: u32) -> Vec<u8> {
     let mut p = Vec::new();
     p.extend_from_slice(&0u32.to_be_bytes());
     p.extend_from_slice(&0u32.to_be_bytes());
@@ -139,33 +585,42 @@ fn build_mvhd(duration_ms: u32) -> Vec<u8> {
         p.extend_from_slice(&m.to_be_bytes());
     }
     p.extend_from_slice(&[0u8; 24]);
-    p.extend_from_slice(&2u32.to_be_bytes());
+    p.extend_from_slice(&next_track_id.to_be_bytes());
     full_box(b"mvhd", 0, 0, &p)
 }
 
-fn build_trak(
-    config: &Mp4Config,
-    samples: &[Mp4Sample],
-    data_offset: u32,
-    media_duration: u32,
-    duration_ms: u32,
-) -> Vec<u8> {
-    let tkhd = build_tkhd(config, duration_ms);
-    let edts = build_edts(duration_ms);
-    let mdia = build_mdia(config, samples, data_offset, media_duration);
+fn build_trak(track: &TrackSpec, media_duration: u32, duration_ms: u32) -> Vec<u8> {
+    let (tkhd, media_time) = match track.media {
+        // Companion tracks are enabled but not presented on their own.
+        TrackMedia::Video { config, companion } => (
+            build_tkhd(config, track.track_id, duration_ms, companion.is_none()),
+            0,
+        ),
+        TrackMedia::Audio(audio) => (
+            build_audio_tkhd(track.track_id, duration_ms),
+            audio.pre_skip,
+        ),
+        TrackMedia::Text(kind) => (build_text_tkhd(track.track_id, duration_ms, kind), 0),
+    };
+    let edts = build_edts(duration_ms, media_time);
+    let mdia = build_mdia(track, media_duration);
 
     let mut payload = Vec::new();
     payload.extend_from_slice(&tkhd);
+    if let Some((reference_type, track_id)) = track.reference {
+        let entry = box_wrap(reference_type, &track_id.to_be_bytes());
+        payload.extend_from_slice(&box_wrap(b"tref", &entry));
+    }
     payload.extend_from_slice(&edts);
     payload.extend_from_slice(&mdia);
     box_wrap(b"trak", &payload)
 }
 
-fn build_tkhd(config: &Mp4Config, duration_ms: u32) -> Vec<u8> {
+fn build_tkhd(config: &Mp4Config, track_id: u32, duration_ms: u32, in_movie: bool) -> Vec<u8> {
     let mut p = Vec::new();
     p.extend_from_slice(&0u32.to_be_bytes());
     p.extend_from_slice(&0u32.to_be_bytes());
-    p.extend_from_slice(&1u32.to_be_bytes());
+    p.extend_from_slice(&track_id.to_be_bytes());
     p.extend_from_slice(&0u32.to_be_bytes());
     p.extend_from_slice(&duration_ms.to_be_bytes());
     p.extend_from_slice(&[0u8; 8]);
@@ -173,34 +628,90 @@ fn build_tkhd(config: &Mp4Config, duration_ms: u32) -> Vec<u8> {
     p.extend_from_slice(&0u16.to_be_bytes());
     p.extend_from_slice(&0u16.to_be_bytes());
     p.extend_from_slice(&0u16.to_be_bytes());
-    let matrix: [u32; 9] = [0x00010000, 0, 0, 0, 0x00010000, 0, 0, 0, 0x40000000];
+    let matrix = config.orientation.tkhd_matrix(config.width, config.height);
     for m in &matrix {
         p.extend_from_slice(&m.to_be_bytes());
     }
     p.extend_from_slice(&(config.width << 16).to_be_bytes());
     p.extend_from_slice(&(config.height << 16).to_be_bytes());
+    // track_enabled | track_in_movie
+    let flags = if in_movie { 3 } else { 1 };
+    full_box(b"tkhd", 0, flags, &p)
+}
+
+/// `tkhd` of an audio track: full volume, alternate group 1, no size.
+fn build_audio_tkhd(track_id: u32, duration_ms: u32) -> Vec<u8> {
+    let mut p = Vec::new();
+    p.extend_from_slice(&0u32.to_be_bytes());
+    p.extend_from_slice(&0u32.to_be_bytes());
+    p.extend_from_slice(&track_id.to_be_bytes());
+    p.extend_from_slice(&0u32.to_be_bytes());
+    p.extend_from_slice(&duration_ms.to_be_bytes());
+    p.extend_from_slice(&[0u8; 8]);
+    p.extend_from_slice(&0u16.to_be_bytes());
+    p.extend_from_slice(&1u16.to_be_bytes());
+    p.extend_from_slice(&0x0100u16.to_be_bytes());
+    p.extend_from_slice(&0u16.to_be_bytes());
+    for m in Orientation::IDENTITY.tkhd_matrix(0, 0) {
+        p.extend_from_slice(&m.to_be_bytes());
+    }
+    p.extend_from_slice(&0u32.to_be_bytes());
+    p.extend_from_slice(&0u32.to_be_bytes());
     full_box(b"tkhd", 0, 3, &p)
 }
 
-fn build_edts(duration_ms: u32) -> Vec<u8> {
+/// `tkhd` of a text track. Chapter tracks are disabled, as players only
+/// read them through the video track's `chap` reference.
+fn build_text_tkhd(track_id: u32, duration_ms: u32, kind: TextKind) -> Vec<u8> {
+    let mut p = Vec::new();
+    p.extend_from_slice(&0u32.to_be_bytes());
+    p.extend_from_slice(&0u32.to_be_bytes());
+    p.extend_from_slice(&track_id.to_be_bytes());
+    p.extend_from_slice(&0u32.to_be_bytes());
+    p.extend_from_slice(&duration_ms.to_be_bytes());
+    p.extend_from_slice(&[0u8; 8]);
+    p.extend_from_slice(&0u16.to_be_bytes());
+    p.extend_from_slice(&0u16.to_be_bytes());
+    p.extend_from_slice(&0u16.to_be_bytes());
+    p.extend_from_slice(&0u16.to_be_bytes());
+    for m in Orientation::IDENTITY.tkhd_matrix(0, 0) {
+        p.extend_from_slice(&m.to_be_bytes());
+    }
+    p.extend_from_slice(&0u32.to_be_bytes());
+    p.extend_from_slice(&0u32.to_be_bytes());
+    let flags = match kind {
+        TextKind::Subtitles => 3,
+        TextKind::Chapters => 0,
+    };
+    full_box(b"tkhd", 0, flags, &p)
+}
+
+/// One edit presenting `duration_ms` of media from `media_time` on, in the
+/// track's timescale.
+fn build_edts(duration_ms: u32, media_time: u32) -> Vec<u8> {
     let mut p = Vec::new();
     p.extend_from_slice(&1u32.to_be_bytes());
     p.extend_from_slice(&duration_ms.to_be_bytes());
-    p.extend_from_slice(&0i32.to_be_bytes());
+    p.extend_from_slice(&media_time.to_be_bytes());
     p.extend_from_slice(&0x00010000u32.to_be_bytes());
     let elst = full_box(b"elst", 0, 0, &p);
     box_wrap(b"edts", &elst)
 }
 
-fn build_mdia(
-    config: &Mp4Config,
-    samples: &[Mp4Sample],
-    data_offset: u32,
-    media_duration: u32,
-) -> Vec<u8> {
-    let mdhd = build_mdhd(config.fps_num, media_duration);
-    let hdlr = build_hdlr();
-    let minf = build_minf(config, samples, data_offset);
+fn build_mdia(track: &TrackSpec, media_duration: u32) -> Vec<u8> {
+    let mdhd = build_mdhd(track.timescale(), media_duration);
+    let hdlr = match track.media {
+        TrackMedia::Video {
+            companion: None, ..
+        } => build_hdlr(b"vide", b"VideoHandler\0"),
+        TrackMedia::Video {
+            companion: Some(_), ..
+        } => build_hdlr(b"auxv", b"AuxiliaryVideoHandler\0"),
+        TrackMedia::Audio(_) => build_hdlr(b"soun", b"SoundHandler\0"),
+        TrackMedia::Text(TextKind::Subtitles) => build_hdlr(b"sbtl", b"SubtitleHandler\0"),
+        TrackMedia::Text(TextKind::Chapters) => build_hdlr(b"text", b"ChapterHandler\0"),
+    };
+    let minf = build_minf(track);
 
     let mut payload = Vec::new();
     payload.extend_from_slice(&mdhd);
@@ -220,22 +731,26 @@ fn build_mdhd(timescale: u32, duration: u32) -> Vec<u8> {
     full_box(b"mdhd", 0, 0, &p)
 }
 
-fn build_hdlr() -> Vec<u8> {
+fn build_hdlr(handler: &[u8; 4], name: &[u8]) -> Vec<u8> {
     let mut p = Vec::new();
     p.extend_from_slice(&0u32.to_be_bytes());
-    p.extend_from_slice(b"vide");
+    p.extend_from_slice(handler);
     p.extend_from_slice(&[0u8; 12]);
-    p.extend_from_slice(b"VideoHandler\0");
+    p.extend_from_slice(name);
     full_box(b"hdlr", 0, 0, &p)
 }
 
-fn build_minf(config: &Mp4Config, samples: &[Mp4Sample], data_offset: u32) -> Vec<u8> {
-    let vmhd = full_box(b"vmhd", 0, 1, &[0u8; 8]);
+fn build_minf(track: &TrackSpec) -> Vec<u8> {
+    let media_header = match track.media {
+        TrackMedia::Video { .. } => full_box(b"vmhd", 0, 1, &[0u8; 8]),
+        TrackMedia::Audio(_) => full_box(b"smhd", 0, 0, &[0u8; 4]),
+        TrackMedia::Text(_) => full_box(b"nmhd", 0, 0, &[]),
+    };
     let dinf = build_dinf();
-    let stbl = build_stbl(config, samples, data_offset);
+    let stbl = build_stbl(track);
 
     let mut payload = Vec::new();
-    payload.extend_from_slice(&vmhd);
+    payload.extend_from_slice(&media_header);
     payload.extend_from_slice(&dinf);
     payload.extend_from_slice(&stbl);
     box_wrap(b"minf", &payload)
@@ -252,12 +767,25 @@ fn build_dinf() -> Vec<u8> {
     box_wrap(b"dinf", &dref)
 }
 
-fn build_stbl(config: &Mp4Config, samples: &[Mp4Sample], data_offset: u32) -> Vec<u8> {
-    let stsd = build_stsd(config);
-    let stts = build_stts(samples.len() as u32, config.fps_den);
-    let stsc = build_stsc(samples.len() as u32);
+fn build_stbl(track: &TrackSpec) -> Vec<u8> {
+    let samples = track.samples;
+    let stsd = match track.media {
+        TrackMedia::Video { config, companion } => build_stsd(config, companion),
+        TrackMedia::Audio(audio) => {
+            let mut p = 1u32.to_be_bytes().to_vec();
+            p.extend_from_slice(&build_audio_sample_entry(audio));
+            full_box(b"stsd", 0, 0, &p)
+        }
+        TrackMedia::Text(_) => {
+            let mut p = 1u32.to_be_bytes().to_vec();
+            p.extend_from_slice(&build_tx3g());
+            full_box(b"stsd", 0, 0, &p)
+        }
+    };
+    let stts = build_stts(samples);
+    let stsc = build_stsc(samples.len(), track.samples_per_chunk);
     let stsz = build_stsz(samples);
-    let stco = build_stco(data_offset);
+    let stco = build_stco(&track.chunk_offsets);
 
     let mut payload = Vec::new();
     payload.extend_from_slice(&stsd);
@@ -271,12 +799,15 @@ fn build_stbl(config: &Mp4Config, samples: &[Mp4Sample], data_offset: u32) -> Ve
         let stss = build_stss(samples);
         payload.extend_from_slice(&stss);
     }
+    if let TrackMedia::Video { .. } = track.media {
+        payload.extend_from_slice(&build_sdtp(samples));
+    }
 
     box_wrap(b"stbl", &payload)
 }
 
-fn build_stsd(config: &Mp4Config) -> Vec<u8> {
-    let av01 = build_av01(config);
+fn build_stsd(config: &Mp4Config, companion: Option<CompanionKind>) -> Vec<u8> {
+    let av01 = build_av01(config, companion);
 
     let mut p = Vec::new();
     p.extend_from_slice(&1u32.to_be_bytes());
@@ -284,7 +815,7 @@ fn build_stsd(config: &Mp4Config) -> Vec<u8> {
     full_box(b"stsd", 0, 0, &p)
 }
 
-fn build_av01(config: &Mp4Config) -> Vec<u8> {
+fn build_av01(config: &Mp4Config, companion: Option<CompanionKind>) -> Vec<u8> {
     let mut p = Vec::new();
     p.extend_from_slice(&[0u8; 6]);
     p.extend_from_slice(&1u16.to_be_bytes());
@@ -299,27 +830,112 @@ fn build_av01(config: &Mp4Config) -> Vec<u8> {
     p.extend_from_slice(&0x0018u16.to_be_bytes());
     p.extend_from_slice(&0xFFFFu16.to_be_bytes());
 
-    p.extend_from_slice(&build_av1c(
-        config.video_signal.bit_depth,
-        &config.config_obus,
-    ));
+    p.extend_from_slice(&build_av1c(&config.av1c));
     p.extend_from_slice(&build_colr(&config.video_signal));
-    p.extend_from_slice(&build_pasp());
+    p.extend_from_slice(&build_pasp(config.pixel_aspect.unwrap_or((1, 1))));
+    if let Some(kind) = companion {
+        let mut auxi = kind.aux_type().as_bytes().to_vec();
+        auxi.push(0);
+        p.extend_from_slice(&full_box(b"auxi", 0, 0, &auxi));
+    }
 
     box_wrap(b"av01", &p)
 }
 
-pub(crate) fn build_av1c(bit_depth: BitDepth, config_obus: &[u8]) -> Vec<u8> {
-    let high_bitdepth = bit_depth == BitDepth::Ten;
+/// `mp4a` with an `esds`, or `Opus` with a `dOps`, for `audio`.
+fn build_audio_sample_entry(audio: &AudioTrack) -> Vec<u8> {
+    let mut p = Vec::new();
+    p.extend_from_slice(&[0u8; 6]);
+    p.extend_from_slice(&1u16.to_be_bytes());
+    p.extend_from_slice(&[0u8; 8]);
+    p.extend_from_slice(&audio.channels.to_be_bytes());
+    p.extend_from_slice(&16u16.to_be_bytes());
+    p.extend_from_slice(&[0u8; 4]);
+    // 16.16 fixed point; rates above 65535 Hz do not fit and are left 0.
+    let rate = if audio.sample_rate <= u16::MAX as u32 {
+        audio.sample_rate << 16
+    } else {
+        0
+    };
+    p.extend_from_slice(&rate.to_be_bytes());
+
+    match &audio.codec {
+        AudioCodec::Aac {
+            audio_specific_config,
+        } => {
+            p.extend_from_slice(&build_esds(audio, audio_specific_config));
+            box_wrap(b"mp4a", &p)
+        }
+        AudioCodec::Opus {
+            input_sample_rate,
+            output_gain,
+            channel_mapping,
+        } => {
+            let mut dops = vec![0, audio.channels as u8];
+            dops.extend_from_slice(&(audio.pre_skip as u16).to_be_bytes());
+            dops.extend_from_slice(&input_sample_rate.to_be_bytes());
+            dops.extend_from_slice(&output_gain.to_be_bytes());
+            dops.extend_from_slice(channel_mapping);
+            p.extend_from_slice(&box_wrap(b"dOps", &dops));
+            box_wrap(b"Opus", &p)
+        }
+    }
+}
+
+/// MPEG-4 elementary stream descriptor of an AAC track (ISO/IEC 14496-1).
+fn build_esds(audio: &AudioTrack, audio_specific_config: &[u8; 2]) -> Vec<u8> {
+    fn descriptor(tag: u8, payload: &[u8]) -> Vec<u8> {
+        let mut out = vec![tag, payload.len() as u8];
+        out.extend_from_slice(payload);
+        out
+    }
+    let seconds = (audio.media_duration() / audio.sample_rate.max(1) as u64).max(1);
+    let bytes: u64 = audio.samples.iter().map(|s| s.data.len() as u64).sum();
+    let avg_bitrate = (bytes * 8 / seconds) as u32;
+    let max_frame = audio
+        .samples
+        .iter()
+        .map(|s| s.data.len())
+        .max()
+        .unwrap_or(0) as u32;
+
+    let mut config = vec![0x40, 0x15];
+    config.extend_from_slice(&max_frame.to_be_bytes()[1..]);
+    config.extend_from_slice(&avg_bitrate.to_be_bytes());
+    config.extend_from_slice(&avg_bitrate.to_be_bytes());
+    config.extend_from_slice(&descriptor(0x05, audio_specific_config));
+
+    let mut es = vec![0, 0, 0];
+    es.extend_from_slice(&descriptor(0x04, &config));
+    es.extend_from_slice(&descriptor(0x06, &[0x02]));
+    full_box(b"esds", 0, 0, &descriptor(0x03, &es))
+}
 
+/// 3GPP timed text sample entry (TS 26.245): bottom-centred white text in
+/// one sans-serif font, no text box or background.
+fn build_tx3g() -> Vec<u8> {
     let mut p = Vec::new();
-    p.push(0x81);
-    p.push(0x0D);
-    p.push(if high_bitdepth { 0x4C } else { 0x0C });
-    p.push(0x00);
-    p.extend_from_slice(config_obus);
+    p.extend_from_slice(&[0u8; 6]);
+    p.extend_from_slice(&1u16.to_be_bytes());
+    p.extend_from_slice(&0u32.to_be_bytes()); // display flags
+    p.push(1); // horizontal justification: centre
+    p.push(0xFF); // vertical justification: bottom
+    p.extend_from_slice(&[0u8; 4]); // background RGBA
+    p.extend_from_slice(&[0u8; 8]); // default text box
+    // Style record: characters 0-0, font 1, plain, 18 px, opaque white.
+    p.extend_from_slice(&[0, 0, 0, 0, 0, 1, 0, 18, 0xFF, 0xFF, 0xFF, 0xFF]);
+    let font = b"Sans-Serif";
+    let mut ftab = Vec::new();
+    ftab.extend_from_slice(&1u16.to_be_bytes());
+    ftab.extend_from_slice(&1u16.to_be_bytes());
+    ftab.push(font.len() as u8);
+    ftab.extend_from_slice(font);
+    p.extend_from_slice(&box_wrap(b"ftab", &ftab));
+    box_wrap(b"tx3g", &p)
+}
 
-    box_wrap(b"av1C", &p)
+pub(crate) fn build_av1c(record: &[u8]) -> Vec<u8> {
+    box_wrap(b"av1C", record)
 }
 
 pub(crate) fn build_colr(video_signal: &VideoSignal) -> Vec<u8> {
@@ -345,27 +961,49 @@ pub(crate) fn build_colr(video_signal: &VideoSignal) -> Vec<u8> {
     box_wrap(b"colr", &p)
 }
 
-fn build_pasp() -> Vec<u8> {
+fn build_pasp((h_spacing, v_spacing): (u32, u32)) -> Vec<u8> {
     let mut p = Vec::new();
-    p.extend_from_slice(&1u32.to_be_bytes());
-    p.extend_from_slice(&1u32.to_be_bytes());
+    p.extend_from_slice(&h_spacing.to_be_bytes());
+    p.extend_from_slice(&v_spacing.to_be_bytes());
     box_wrap(b"pasp", &p)
 }
 
-fn build_stts(num_samples: u32, sample_delta: u32) -> Vec<u8> {
+/// One `(sample_count, sample_delta)` entry per run of equal durations.
+fn build_stts(samples: &[Mp4Sample]) -> Vec<u8> {
+    let mut entries: Vec<(u32, u32)> = Vec::new();
+    for s in samples {
+        match entries.last_mut() {
+            Some((count, delta)) if *delta == s.duration => *count += 1,
+            _ => entries.push((1, s.duration)),
+        }
+    }
     let mut p = Vec::new();
-    p.extend_from_slice(&1u32.to_be_bytes());
-    p.extend_from_slice(&num_samples.to_be_bytes());
-    p.extend_from_slice(&sample_delta.to_be_bytes());
+    p.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+    for (count, delta) in entries {
+        p.extend_from_slice(&count.to_be_bytes());
+        p.extend_from_slice(&delta.to_be_bytes());
+    }
     full_box(b"stts", 0, 0, &p)
 }
 
-fn build_stsc(num_samples: u32) -> Vec<u8> {
+fn build_stsc(num_samples: usize, samples_per_chunk: usize) -> Vec<u8> {
+    // (first_chunk, samples_per_chunk): full chunks, then a shorter last one.
+    let full = num_samples / samples_per_chunk;
+    let rest = num_samples % samples_per_chunk;
+    let mut entries = Vec::new();
+    if full > 0 {
+        entries.push((1, samples_per_chunk));
+    }
+    if rest > 0 {
+        entries.push((full + 1, rest));
+    }
     let mut p = Vec::new();
-    p.extend_from_slice(&1u32.to_be_bytes());
-    p.extend_from_slice(&1u32.to_be_bytes());
-    p.extend_from_slice(&num_samples.to_be_bytes());
-    p.extend_from_slice(&1u32.to_be_bytes());
+    p.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+    for (first_chunk, count) in entries {
+        p.extend_from_slice(&(first_chunk as u32).to_be_bytes());
+        p.extend_from_slice(&(count as u32).to_be_bytes());
+        p.extend_from_slice(&1u32.to_be_bytes());
+    }
     full_box(b"stsc", 0, 0, &p)
 }
 
@@ -379,10 +1017,12 @@ fn build_stsz(samples: &[Mp4Sample]) -> Vec<u8> {
     full_box(b"stsz", 0, 0, &p)
 }
 
-fn build_stco(chunk_offset: u32) -> Vec<u8> {
+fn build_stco(chunk_offsets: &[u32]) -> Vec<u8> {
     let mut p = Vec::new();
-    p.extend_from_slice(&1u32.to_be_bytes());
-    p.extend_from_slice(&chunk_offset.to_be_bytes());
+    p.extend_from_slice(&(chunk_offsets.len() as u32).to_be_bytes());
+    for offset in chunk_offsets {
+        p.extend_from_slice(&offset.to_be_bytes());
+    }
     full_box(b"stco", 0, 0, &p)
 }
 
@@ -402,6 +1042,17 @@ fn build_stss(samples: &[Mp4Sample]) -> Vec<u8> {
     full_box(b"stss", 0, 0, &p)
 }
 
+/// Which samples need earlier ones and which no later one needs, so
+/// players can drop B-frames to catch up.
+///
+/// There is no `ctts`: each sample is a temporal unit holding exactly one
+/// shown frame, with hidden frames stored alongside the next shown one, so
+/// decode order is presentation order even with B-frames.
+fn build_sdtp(samples: &[Mp4Sample]) -> Vec<u8> {
+    let flags: Vec<u8> = samples.iter().map(Mp4Sample::dependency_flags).collect();
+    full_box(b"sdtp", 0, 0, &flags)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -412,8 +1063,11 @@ mod tests {
             height: 64,
             fps_num: 25,
             fps_den: 1,
-            config_obus: Vec::new(),
+            av1c: Vec::new(),
             video_signal: VideoSignal::default(),
+            orientation: Orientation::IDENTITY,
+            pixel_aspect: None,
+            encoder_info: None,
         }
     }
 
@@ -422,7 +1076,8 @@ mod tests {
         let mut cfg = base_config();
         cfg.width = 70_000;
         let mut out = Vec::new();
-        let err = write_mp4(&mut out, &cfg, &[]).expect_err("expected rejection");
+        let err = write_mp4(&mut out, &cfg, &[], None, None, &[], MoovPlacement::End)
+            .expect_err("expected rejection");
         assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
     }
 
@@ -431,10 +1086,23 @@ mod tests {
         let mut cfg = base_config();
         cfg.height = 70_000;
         let mut out = Vec::new();
-        let err = write_mp4(&mut out, &cfg, &[]).expect_err("expected rejection");
+        let err = write_mp4(&mut out, &cfg, &[], None, None, &[], MoovPlacement::End)
+            .expect_err("expected rejection");
         assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
     }
 
+    #[test]
+    fn box_fields_are_big_endian() {
+        assert_eq!(
+            full_box(b"test", 1, 0x0a0b0c, &[0xee]),
+            [
+                0, 0, 0, 13, b't', b'e', b's', b't', 1, 0x0a, 0x0b, 0x0c, 0xee
+            ]
+        );
+        let mdhd = build_mdhd(0x0102_0304, 0x0506_0708);
+        assert_eq!(&mdhd[20..28], &[1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
     #[test]
     fn mdhd_uses_exact_fps_num_as_timescale() {
         let mdhd = build_mdhd(30_000, 1_001);
@@ -444,10 +1112,515 @@ mod tests {
         assert_eq!(duration, 1_001);
     }
 
+    fn top_level_boxes(data: &[u8]) -> Vec<[u8; 4]> {
+        let mut boxes = Vec::new();
+        let mut pos = 0;
+        while pos + 8 <= data.len() {
+            let size = u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+            boxes.push(data[pos + 4..pos + 8].try_into().unwrap());
+            pos += size;
+        }
+        assert_eq!(pos, data.len());
+        boxes
+    }
+
+    fn sample(len: usize, is_sync: bool) -> Mp4Sample {
+        Mp4Sample {
+            data: vec![0xAB; len],
+            is_sync,
+            duration: 1,
+            disposable: false,
+        }
+    }
+
+    #[test]
+    fn fragmented_writer_emits_one_fragment_per_gop() {
+        let mut out = Vec::new();
+        let mut writer = FragmentedMp4Writer::new(&mut out, &base_config()).unwrap();
+        for is_sync in [true, false, false, true, false] {
+            writer.push_sample(sample(10, is_sync)).unwrap();
+        }
+        writer.finish().unwrap();
+
+        assert_eq!(
+            top_level_boxes(&out),
+            vec![*b"ftyp", *b"moov", *b"moof", *b"mdat", *b"moof", *b"mdat"]
+        );
+    }
+
+    #[test]
+    fn fragmented_writer_flushes_completed_gops_before_finish() {
+        let mut out = Vec::new();
+        let mut writer = FragmentedMp4Writer::new(&mut out, &base_config()).unwrap();
+        writer.push_sample(sample(10, true)).unwrap();
+        writer.push_sample(sample(10, false)).unwrap();
+        writer.push_sample(sample(10, true)).unwrap();
+        drop(writer);
+
+        assert_eq!(
+            top_level_boxes(&out),
+            vec![*b"ftyp", *b"moov", *b"moof", *b"mdat"]
+        );
+    }
+
+    #[test]
+    fn trun_data_offset_points_past_mdat_header() {
+        let samples = [sample(7, true), sample(5, false)];
+        let moof = build_moof(1, 0, 1, &samples, 0);
+        let moof = build_moof(1, 0, 1, &samples, moof.len() as u32 + 8);
+        let trun = moof.windows(4).position(|w| w == b"trun").unwrap();
+        let offset = u32::from_be_bytes(moof[trun + 12..trun + 16].try_into().unwrap());
+        assert_eq!(offset as usize, moof.len() + 8);
+    }
+
+    #[test]
+    fn pixel_aspect_is_written_to_pasp() {
+        let pasp = |config: &Mp4Config| {
+            let mut out = Vec::new();
+            write_mp4(
+                &mut out,
+                config,
+                &[sample(4, true)],
+                None,
+                None,
+                &[],
+                MoovPlacement::End,
+            )
+            .unwrap();
+            let stsd = child_box(
+                &out,
+                &[b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stsd"],
+            )
+            .unwrap()
+            .to_vec();
+            let at = stsd.windows(4).position(|w| w == b"pasp").unwrap();
+            stsd[at + 4..at + 12].to_vec()
+        };
+        assert_eq!(pasp(&base_config()), [0, 0, 0, 1, 0, 0, 0, 1]);
+        let anamorphic = Mp4Config {
+            pixel_aspect: Some((16, 11)),
+            ..base_config()
+        };
+        assert_eq!(pasp(&anamorphic), [0, 0, 0, 16, 0, 0, 0, 11]);
+    }
+
+    #[test]
+    fn encoder_info_is_written_as_an_ilst_tool_tag() {
+        let cfg = Mp4Config {
+            encoder_info: Some("wav1c 1.0 - AV1 encoder".to_owned()),
+            ..base_config()
+        };
+        let mut progressive = Vec::new();
+        let mut writer = FragmentedMp4Writer::new(&mut progressive, &cfg).unwrap();
+        writer.push_sample(sample(4, true)).unwrap();
+        writer.finish().unwrap();
+        let mut whole = Vec::new();
+        write_mp4(
+            &mut whole,
+            &cfg,
+            &[sample(4, true)],
+            None,
+            None,
+            &[],
+            MoovPlacement::End,
+        )
+        .unwrap();
+
+        for out in [&progressive, &whole] {
+            let meta = child_box(out, &[b"moov", b"udta", b"meta"]).unwrap();
+            // Skip the full box header of `meta`.
+            let hdlr = child_box(&meta[4..], &[b"hdlr"]).unwrap();
+            assert_eq!(&hdlr[8..12], b"mdir");
+            let data = child_box(&meta[4..], &[b"ilst", b"\xa9too", b"data"]).unwrap();
+            assert_eq!(&data[..8], &[0, 0, 0, 1, 0, 0, 0, 0]);
+            assert_eq!(&data[8..], b"wav1c 1.0 - AV1 encoder");
+        }
+        let mut plain = Vec::new();
+        write_mp4(
+            &mut plain,
+            &base_config(),
+            &[sample(4, true)],
+            None,
+            None,
+            &[],
+            MoovPlacement::End,
+        )
+        .unwrap();
+        assert!(child_box(&plain, &[b"moov", b"udta"]).is_none());
+    }
+
+    fn child_box<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+        let Some((first, rest)) = path.split_first() else {
+            return Some(data);
+        };
+        let mut pos = 0;
+        while pos + 8 <= data.len() {
+            let size = u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+            if &data[pos + 4..pos + 8] == *first {
+                return child_box(&data[pos + 8..pos + size], rest);
+            }
+            pos += size;
+        }
+        None
+    }
+
+    fn traks(moov: &[u8]) -> Vec<&[u8]> {
+        let mut out = Vec::new();
+        let mut pos = 0;
+        while pos + 8 <= moov.len() {
+            let size = u32::from_be_bytes(moov[pos..pos + 4].try_into().unwrap()) as usize;
+            if &moov[pos + 4..pos + 8] == b"trak" {
+                out.push(&moov[pos + 8..pos + size]);
+            }
+            pos += size;
+        }
+        out
+    }
+
+    fn stco_offsets(trak: &[u8]) -> Vec<u32> {
+        let stco = child_box(trak, &[b"mdia", b"minf", b"stbl", b"stco"]).unwrap();
+        stco[8..]
+            .chunks(4)
+            .map(|c| u32::from_be_bytes(c.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn companion_track_references_main_track() {
+        let main = [sample(7, true), sample(5, false)];
+        let companion = Mp4Companion {
+            kind: CompanionKind::Alpha,
+            config: Mp4Config {
+                width: 32,
+                height: 32,
+                ..base_config()
+            },
+            samples: vec![sample(3, true), sample(2, false)],
+        };
+        let mut out = Vec::new();
+        write_mp4(
+            &mut out,
+            &base_config(),
+            &main,
+            Some(&companion),
+            None,
+            &[],
+            MoovPlacement::End,
+        )
+        .unwrap();
+        assert_eq!(top_level_boxes(&out), vec![*b"ftyp", *b"mdat", *b"moov"]);
+
+        let moov = child_box(&out, &[b"moov"]).unwrap();
+        let mvhd = child_box(moov, &[b"mvhd"]).unwrap();
+        assert_eq!(&mvhd[mvhd.len() - 4..], &3u32.to_be_bytes());
+        let tracks = traks(moov);
+        assert_eq!(tracks.len(), 2);
+        assert!(child_box(tracks[0], &[b"tref"]).is_none());
+        assert_eq!(
+            child_box(tracks[1], &[b"tref", b"auxl"]),
+            Some(&1u32.to_be_bytes()[..])
+        );
+        let hdlr = child_box(tracks[1], &[b"mdia", b"hdlr"]).unwrap();
+        assert_eq!(&hdlr[8..12], b"auxv");
+        let av01 = child_box(tracks[1], &[b"mdia", b"minf", b"stbl", b"stsd"]).unwrap();
+        let urn = CompanionKind::Alpha.aux_type().as_bytes();
+        assert!(av01.windows(urn.len()).any(|w| w == urn));
+
+        // The companion's chunk starts right after the main track's samples.
+        let main_offset = stco_offsets(tracks[0])[0];
+        assert_eq!(main_offset as usize, build_ftyp().len() + 8);
+        assert_eq!(stco_offsets(tracks[1])[0], main_offset + 12);
+    }
+
+    #[test]
+    fn faststart_puts_moov_first_and_interleaves_chunks() {
+        let cfg = Mp4Config {
+            fps_num: 2,
+            ..base_config()
+        };
+        let main: Vec<Mp4Sample> = (0..5).map(|i| sample(10 + i, i % 4 == 0)).collect();
+        let companion = Mp4Companion {
+            kind: CompanionKind::GainMap,
+            config: Mp4Config {
+                fps_num: 2,
+                ..base_config()
+            },
+            samples: (0..5).map(|i| sample(3, i % 4 == 0)).collect(),
+        };
+        let mut out = Vec::new();
+        write_mp4(
+            &mut out,
+            &cfg,
+            &main,
+            Some(&companion),
+            None,
+            &[],
+            MoovPlacement::Front,
+        )
+        .unwrap();
+        assert_eq!(top_level_boxes(&out), vec![*b"ftyp", *b"moov", *b"mdat"]);
+
+        let moov = child_box(&out, &[b"moov"]).unwrap();
+        let tracks = traks(moov);
+        let stsc = child_box(tracks[0], &[b"mdia", b"minf", b"stbl", b"stsc"]).unwrap();
+        let words: Vec<u32> = stsc[4..]
+            .chunks(4)
+            .map(|c| u32::from_be_bytes(c.try_into().unwrap()))
+            .collect();
+        // Two chunks of two samples, then one of one.
+        assert_eq!(words, [2, 1, 2, 1, 3, 1, 1]);
+
+        // Chunks alternate between the tracks: main 0, companion 0, main 1...
+        let main_offsets = stco_offsets(tracks[0]);
+        let companion_offsets = stco_offsets(tracks[1]);
+        let mdat_data = build_ftyp().len() + moov.len() + 8 + 8;
+        assert_eq!(main_offsets[0] as usize, mdat_data);
+        assert_eq!(companion_offsets[0], main_offsets[0] + 10 + 11);
+        assert_eq!(main_offsets[1], companion_offsets[0] + 6);
+        assert_eq!(companion_offsets[1], main_offsets[1] + 12 + 13);
+        assert_eq!(main_offsets[2], companion_offsets[1] + 6);
+        for (track, offsets) in [
+            (&main, &main_offsets),
+            (&companion.samples, &companion_offsets),
+        ] {
+            for (chunk, &offset) in track.chunks(2).zip(offsets.iter()) {
+                let offset = offset as usize;
+                assert_eq!(
+                    &out[offset..offset + chunk[0].data.len()],
+                    &chunk[0].data[..]
+                );
+            }
+        }
+    }
+
+    fn audio_track(codec: AudioCodec, sample_rate: u32, durations: &[u32]) -> AudioTrack {
+        let samples: Vec<Mp4Sample> = durations
+            .iter()
+            .map(|&duration| Mp4Sample {
+                duration,
+                ..sample(6, true)
+            })
+            .collect();
+        let presented = samples.iter().map(|s| s.duration as u64).sum();
+        AudioTrack {
+            codec,
+            sample_rate,
+            channels: 2,
+            pre_skip: 0,
+            presented,
+            samples,
+        }
+    }
+
+    #[test]
+    fn aac_track_is_a_sound_track_interleaved_by_time() {
+        let cfg = Mp4Config {
+            fps_num: 2,
+            ..base_config()
+        };
+        let main: Vec<Mp4Sample> = (0..5).map(|i| sample(10, i == 0)).collect();
+        let aac = AudioCodec::Aac {
+            audio_specific_config: [0x15, 0x90],
+        };
+        let audio = audio_track(aac, 8_000, &[1024; 24]);
+        let mut out = Vec::new();
+        write_mp4(
+            &mut out,
+            &cfg,
+            &main,
+            None,
+            Some(&audio),
+            &[],
+            MoovPlacement::End,
+        )
+        .unwrap();
+
+        let moov = child_box(&out, &[b"moov"]).unwrap();
+        let mvhd = child_box(moov, &[b"mvhd"]).unwrap();
+        // 24 * 1024 / 8000 s, longer than the 2.5 s of video.
+        assert_eq!(&mvhd[16..20], &3072u32.to_be_bytes());
+        assert_eq!(&mvhd[mvhd.len() - 4..], &3u32.to_be_bytes());
+        let tracks = traks(moov);
+        assert_eq!(tracks.len(), 2);
+        let hdlr = child_box(tracks[1], &[b"mdia", b"hdlr"]).unwrap();
+        assert_eq!(&hdlr[8..12], b"soun");
+        assert!(child_box(tracks[1], &[b"mdia", b"minf", b"smhd"]).is_some());
+        let mdhd = child_box(tracks[1], &[b"mdia", b"mdhd"]).unwrap();
+        assert_eq!(&mdhd[12..20], &[0, 0, 0x1F, 0x40, 0, 0, 0x60, 0]);
+        let stsd = child_box(tracks[1], &[b"mdia", b"minf", b"stbl", b"stsd"]).unwrap();
+        let mp4a = child_box(&stsd[8..], &[b"mp4a"]).unwrap();
+        assert_eq!(&mp4a[16..18], &2u16.to_be_bytes());
+        assert_eq!(&mp4a[24..28], &(8_000u32 << 16).to_be_bytes());
+        let esds = child_box(&mp4a[28..], &[b"esds"]).unwrap();
+        assert!(esds.windows(4).any(|w| w == [0x05, 2, 0x15, 0x90]));
+        assert!(child_box(tracks[1], &[b"mdia", b"minf", b"stbl", b"stss"]).is_none());
+
+        // About a second per chunk: video chunks start at 0, 1 and 2 s,
+        // audio ones (7 frames) every 0.896 s.
+        let video = stco_offsets(tracks[0]);
+        let sound = stco_offsets(tracks[1]);
+        let mut layout: Vec<(u32, char)> = video
+            .iter()
+            .map(|&o| (o, 'v'))
+            .chain(sound.iter().map(|&o| (o, 'a')))
+            .collect();
+        layout.sort();
+        let order: String = layout.iter().map(|&(_, t)| t).collect();
+        assert_eq!(order, "vaavava");
+    }
+
+    #[test]
+    fn opus_track_carries_dops_and_skips_pre_skip() {
+        let opus = AudioCodec::Opus {
+            input_sample_rate: 44_100,
+            output_gain: -256,
+            channel_mapping: vec![0],
+        };
+        let mut audio = audio_track(opus, 48_000, &[960; 50]);
+        audio.pre_skip = 312;
+        audio.presented = 48_000 - 312 - 100;
+        let mut out = Vec::new();
+        write_mp4(
+            &mut out,
+            &base_config(),
+            &[sample(4, true)],
+            None,
+            Some(&audio),
+            &[],
+            MoovPlacement::Front,
+        )
+        .unwrap();
+
+        let moov = child_box(&out, &[b"moov"]).unwrap();
+        let track = traks(moov)[1];
+        let elst = child_box(track, &[b"edts", b"elst"]).unwrap();
+        assert_eq!(
+            &elst[8..12],
+            &((48_000 - 412) * 1000 / 48_000u32).to_be_bytes()
+        );
+        assert_eq!(&elst[12..16], &312u32.to_be_bytes());
+        let stsd = child_box(track, &[b"mdia", b"minf", b"stbl", b"stsd"]).unwrap();
+        let entry = child_box(&stsd[8..], &[b"Opus"]).unwrap();
+        let dops = child_box(&entry[28..], &[b"dOps"]).unwrap();
+        assert_eq!(dops, [0, 2, 0x01, 0x38, 0, 0, 0xAC, 0x44, 0xFF, 0x00, 0]);
+    }
+
+    #[test]
+    fn moov_placement_only_moves_boxes() {
+        let main: Vec<Mp4Sample> = (0..3).map(|i| sample(8, i == 0)).collect();
+        let mut end = Vec::new();
+        write_mp4(
+            &mut end,
+            &base_config(),
+            &main,
+            None,
+            None,
+            &[],
+            MoovPlacement::End,
+        )
+        .unwrap();
+        let mut front = Vec::new();
+        write_mp4(
+            &mut front,
+            &base_config(),
+            &main,
+            None,
+            None,
+            &[],
+            MoovPlacement::Front,
+        )
+        .unwrap();
+        assert_eq!(end.len(), front.len());
+        let stco_of = |data: &[u8]| stco_offsets(traks(child_box(data, &[b"moov"]).unwrap())[0]);
+        let moov_len = child_box(&end, &[b"moov"]).unwrap().len() + 8;
+        assert_eq!(stco_of(&front), [stco_of(&end)[0] + moov_len as u32]);
+    }
+
     #[test]
     fn stts_uses_exact_fps_den_as_sample_delta() {
-        let stts = build_stts(3, 1_001);
+        let samples: Vec<Mp4Sample> = (0..3)
+            .map(|i| Mp4Sample {
+                duration: 1_001,
+                ..sample(4, i == 0)
+            })
+            .collect();
+        let stts = build_stts(&samples);
         let sample_delta = u32::from_be_bytes([stts[20], stts[21], stts[22], stts[23]]);
         assert_eq!(sample_delta, 1_001);
     }
+
+    #[test]
+    fn repeated_frames_extend_sample_durations() {
+        let durations = [1, 1, 4, 1];
+        let samples: Vec<Mp4Sample> = durations
+            .iter()
+            .map(|&duration| Mp4Sample {
+                duration,
+                ..sample(4, false)
+            })
+            .collect();
+        let stts = build_stts(&samples);
+        let words: Vec<u32> = stts[12..]
+            .chunks(4)
+            .map(|w| u32::from_be_bytes(w.try_into().unwrap()))
+            .collect();
+        assert_eq!(words, [3, 2, 1, 1, 4, 1, 1]);
+
+        // Fragments carry the durations per sample and advance the decode
+        // time by their sum.
+        let moof = build_moof(1, 0, 1, &samples, 0);
+        let trun = moof.windows(4).position(|w| w == b"trun").unwrap();
+        assert_eq!(&moof[trun + 5..trun + 8], [0x00, 0x07, 0x01]);
+        let mut out = Vec::new();
+        let mut writer = FragmentedMp4Writer::new(&mut out, &base_config()).unwrap();
+        for s in samples {
+            writer.push_sample(s).unwrap();
+        }
+        writer.push_sample(sample(4, true)).unwrap();
+        writer.finish().unwrap();
+        let tfdt: Vec<usize> = (0..out.len() - 4)
+            .filter(|&i| &out[i..i + 4] == b"tfdt")
+            .collect();
+        let second = u64::from_be_bytes(out[tfdt[1] + 8..tfdt[1] + 16].try_into().unwrap());
+        assert_eq!(second, 7);
+    }
+
+    #[test]
+    fn dependency_flags_mark_keyframes_and_disposable_frames() {
+        let b_frame = Mp4Sample {
+            disposable: true,
+            ..sample(4, false)
+        };
+        let samples = [sample(4, true), sample(4, false), b_frame];
+        let sdtp = build_sdtp(&samples);
+        assert_eq!(&sdtp[4..8], b"sdtp");
+        assert_eq!(&sdtp[12..], [0x24, 0x14, 0x18]);
+
+        let moof = build_moof(1, 0, 1, &samples, 0);
+        let trun = moof.windows(4).position(|w| w == b"trun").unwrap();
+        // Sample size and flags follow the count and data offset.
+        let flags: Vec<u32> = (0..3)
+            .map(|i| trun + 20 + 8 * i)
+            .map(|pos| u32::from_be_bytes(moof[pos..pos + 4].try_into().unwrap()))
+            .collect();
+        assert_eq!(flags, [0x0240_0000, 0x0141_0000, 0x0181_0000]);
+    }
+
+    #[test]
+    fn tkhd_rotates_portrait_tracks_but_keeps_coded_dimensions() {
+        let mut cfg = base_config();
+        cfg.width = 64;
+        cfg.height = 48;
+        cfg.orientation = Orientation::from_exif(6).unwrap();
+        let tkhd = build_tkhd(&cfg, 1, 40, true);
+        let word = |offset: usize| {
+            u32::from_be_bytes(tkhd[12 + offset..12 + offset + 4].try_into().unwrap())
+        };
+        let matrix: Vec<u32> = (0..9).map(|i| word(36 + i * 4)).collect();
+        assert_eq!(matrix, cfg.orientation.tkhd_matrix(64, 48).to_vec());
+        assert_eq!(matrix[1], 0x0001_0000);
+        assert_eq!(matrix[3], 0xFFFF_0000);
+        assert_eq!(word(72), 64 << 16);
+        assert_eq!(word(76), 48 << 16);
+    }
 }
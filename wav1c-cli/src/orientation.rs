@@ -0,0 +1,264 @@
+#[cfg(feature = "heic")]
+use wav1c::y4m::FramePixels;
+
+/// How a stored image is turned upright for display, as one of the eight
+/// Exif `Orientation` values (1 = stored upright).
+///
+/// HEIF/AVIF spell the same transforms as an `irot` rotation (anticlockwise
+/// quarter turns) followed by an `imir` mirror, and MP4 as a `tkhd` matrix.
+/// Coded dimensions (`ispe`, the AV1 sequence header, the `av01` sample entry)
+/// always describe the stored image; only viewers apply the transform.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Orientation(u8);
+
+/// (`irot` quarter turns, `imir` axis) -> Exif orientation. `imir` axis 0
+/// mirrors about the vertical axis (left/right), axis 1 about the horizontal
+/// axis (top/bottom). Each orientation has two spellings; the first one listed
+/// for each value is what we write.
+const HEIF_TRANSFORMS: [((u8, Option<u8>), u8); 12] = [
+    ((0, None), 1),
+    ((0, Some(0)), 2),
+    ((2, None), 3),
+    ((0, Some(1)), 4),
+    ((1, Some(1)), 5),
+    ((3, None), 6),
+    ((1, Some(0)), 7),
+    ((1, None), 8),
+    ((2, Some(1)), 2),
+    ((2, Some(0)), 4),
+    ((3, Some(0)), 5),
+    ((3, Some(1)), 7),
+];
+
+impl Default for Orientation {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Orientation {
+    pub const IDENTITY: Orientation = Orientation(1);
+
+    pub fn from_exif(value: u16) -> Option<Self> {
+        (1..=8).contains(&value).then_some(Self(value as u8))
+    }
+
+    pub fn exif_value(self) -> u8 {
+        self.0
+    }
+
+    pub fn is_identity(self) -> bool {
+        self == Self::IDENTITY
+    }
+
+    /// Orientation produced by an `irot` of `quarter_turns` followed by an
+    /// `imir` about `mirror_axis`.
+    pub fn from_heif(quarter_turns: u8, mirror_axis: Option<u8>) -> Self {
+        let key = (quarter_turns & 3, mirror_axis.map(|axis| axis & 1));
+        HEIF_TRANSFORMS
+            .iter()
+            .find(|(transform, _)| *transform == key)
+            .map(|&(_, exif)| Self(exif))
+            .unwrap_or(Self::IDENTITY)
+    }
+
+    /// The (`irot` quarter turns, `imir` axis) to write for this orientation.
+    pub fn heif_transforms(self) -> (u8, Option<u8>) {
+        HEIF_TRANSFORMS
+            .iter()
+            .find(|(_, exif)| *exif == self.0)
+            .map(|&(transform, _)| transform)
+            .unwrap_or((0, None))
+    }
+
+    /// Whether the displayed image is the stored one turned on its side.
+    pub fn swaps_dimensions(self) -> bool {
+        self.0 >= 5
+    }
+
+    pub fn display_dimensions(self, width: u32, height: u32) -> (u32, u32) {
+        if self.swaps_dimensions() {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+
+    /// `tkhd` transformation matrix (16.16 fixed point, 2.30 for the last
+    /// column) mapping stored `width`x`height` samples to the display.
+    pub fn tkhd_matrix(self, width: u32, height: u32) -> [u32; 9] {
+        const ONE: u32 = 0x0001_0000;
+        const NEG_ONE: u32 = 0xFFFF_0000;
+        let (w, h) = (width << 16, height << 16);
+        // x' = a*x + c*y + tx, y' = b*x + d*y + ty
+        let (a, b, c, d, tx, ty) = match self.0 {
+            2 => (NEG_ONE, 0, 0, ONE, w, 0),
+            3 => (NEG_ONE, 0, 0, NEG_ONE, w, h),
+            4 => (ONE, 0, 0, NEG_ONE, 0, h),
+            5 => (0, ONE, ONE, 0, 0, 0),
+            6 => (0, ONE, NEG_ONE, 0, h, 0),
+            7 => (0, NEG_ONE, NEG_ONE, 0, h, w),
+            8 => (0, NEG_ONE, ONE, 0, 0, w),
+            _ => (ONE, 0, 0, ONE, 0, 0),
+        };
+        [a, b, 0, c, d, 0, tx, ty, 0x4000_0000]
+    }
+
+    /// Checks that the samples handed to the encoder are the stored image
+    /// described by `ispe`, not an already-rotated copy of it.
+    #[cfg(feature = "avif-input")]
+    pub fn validate_coded_dimensions(
+        self,
+        coded: (u32, u32),
+        ispe: Option<(u32, u32)>,
+    ) -> Result<(), String> {
+        let Some(ispe) = ispe else {
+            return Ok(());
+        };
+        if coded == ispe {
+            return Ok(());
+        }
+        let (w, h) = coded;
+        if self.swaps_dimensions() && (h, w) == ispe {
+            return Err(format!(
+                "decoded image is {w}x{h} but ispe says {}x{}: it was already rotated for \
+                 orientation {}, so the transform would be applied twice",
+                ispe.0, ispe.1, self.0
+            ));
+        }
+        Err(format!(
+            "decoded image is {w}x{h} but ispe says {}x{}",
+            ispe.0, ispe.1
+        ))
+    }
+
+    /// Applies the orientation to the samples, producing the upright image.
+    #[cfg(feature = "heic")]
+    pub fn apply(self, frame: &FramePixels) -> FramePixels {
+        if self.is_identity() {
+            return frame.clone();
+        }
+        let (cw, ch) = (frame.width.div_ceil(2), frame.height.div_ceil(2));
+        let (width, height) = self.display_dimensions(frame.width, frame.height);
+        FramePixels {
+            width,
+            height,
+            bit_depth: frame.bit_depth,
+            color_range: frame.color_range,
+            y: self.apply_plane(&frame.y, frame.width, frame.height),
+            u: self.apply_plane(&frame.u, cw, ch),
+            v: self.apply_plane(&frame.v, cw, ch),
+        }
+    }
+
+    #[cfg(feature = "heic")]
+    fn apply_plane(self, plane: &[u16], width: u32, height: u32) -> Vec<u16> {
+        let (w, h) = (width as usize, height as usize);
+        let (out_w, out_h) = if self.swaps_dimensions() {
+            (h, w)
+        } else {
+            (w, h)
+        };
+        let mut out = vec![0u16; plane.len()];
+        for y in 0..h {
+            for x in 0..w {
+                let (dx, dy) = match self.0 {
+                    2 => (w - 1 - x, y),
+                    3 => (w - 1 - x, h - 1 - y),
+                    4 => (x, h - 1 - y),
+                    5 => (y, x),
+                    6 => (h - 1 - y, x),
+                    7 => (h - 1 - y, w - 1 - x),
+                    8 => (y, w - 1 - x),
+                    _ => (x, y),
+                };
+                debug_assert!(dx < out_w && dy < out_h);
+                out[dy * out_w + dx] = plane[y * w + x];
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heif_transforms_round_trip_through_exif() {
+        for value in 1..=8 {
+            let orientation = Orientation::from_exif(value).unwrap();
+            let (turns, mirror) = orientation.heif_transforms();
+            assert_eq!(Orientation::from_heif(turns, mirror), orientation);
+        }
+        assert_eq!(Orientation::from_exif(0), None);
+        assert_eq!(Orientation::from_exif(9), None);
+    }
+
+    #[test]
+    fn portrait_rotations_swap_display_dimensions() {
+        let rotate_cw = Orientation::from_exif(6).unwrap();
+        assert_eq!(rotate_cw.heif_transforms(), (3, None));
+        assert_eq!(rotate_cw.display_dimensions(4032, 3024), (3024, 4032));
+        let upside_down = Orientation::from_exif(3).unwrap();
+        assert_eq!(upside_down.display_dimensions(4032, 3024), (4032, 3024));
+    }
+
+    #[test]
+    fn tkhd_matrix_maps_stored_corners_onto_display() {
+        let (w, h) = (64i64, 48i64);
+        let fixed = |v: u32| (v as i32) as i64;
+        for value in 1..=8 {
+            let orientation = Orientation::from_exif(value).unwrap();
+            let m = orientation.tkhd_matrix(w as u32, h as u32);
+            let (dw, dh) = orientation.display_dimensions(w as u32, h as u32);
+            let mut corners = Vec::new();
+            for (x, y) in [(0, 0), (w, 0), (0, h), (w, h)] {
+                let dx = fixed(m[0]) * x + fixed(m[3]) * y + fixed(m[6]);
+                let dy = fixed(m[1]) * x + fixed(m[4]) * y + fixed(m[7]);
+                corners.push((dx >> 16, dy >> 16));
+            }
+            corners.sort_unstable();
+            let (dw, dh) = (dw as i64, dh as i64);
+            assert_eq!(corners, vec![(0, 0), (0, dh), (dw, 0), (dw, dh)], "{value}");
+        }
+    }
+
+    #[cfg(feature = "avif-input")]
+    #[test]
+    fn already_rotated_samples_are_rejected() {
+        let rotate_cw = Orientation::from_exif(6).unwrap();
+        assert!(
+            rotate_cw
+                .validate_coded_dimensions((4032, 3024), Some((4032, 3024)))
+                .is_ok()
+        );
+        let err = rotate_cw
+            .validate_coded_dimensions((3024, 4032), Some((4032, 3024)))
+            .unwrap_err();
+        assert!(err.contains("already rotated"));
+        assert!(
+            Orientation::IDENTITY
+                .validate_coded_dimensions((100, 50), Some((64, 64)))
+                .is_err()
+        );
+        assert!(
+            Orientation::IDENTITY
+                .validate_coded_dimensions((100, 50), None)
+                .is_ok()
+        );
+    }
+
+    #[cfg(feature = "heic")]
+    #[test]
+    fn apply_rotates_portrait_frames() {
+        let mut frame = FramePixels::solid(4, 2, 0, 128, 128);
+        for (i, s) in frame.y.iter_mut().enumerate() {
+            *s = i as u16;
+        }
+        let rotated = Orientation::from_exif(6).unwrap().apply(&frame);
+        assert_eq!((rotated.width, rotated.height), (2, 4));
+        assert_eq!(rotated.y, vec![4, 0, 5, 1, 6, 2, 7, 3]);
+        assert_eq!(rotated.u.len(), frame.u.len());
+    }
+}
@@ -0,0 +1,180 @@
+use std::io::{IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+use wav1c::{Fps, Packet};
+
+/// How much the encoder reports on stderr. Errors and warnings are always
+/// printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    /// Nothing but errors and warnings.
+    Quiet,
+    /// A progress bar when stderr is a terminal, then the summary line.
+    Normal,
+    /// A line per coded frame instead of the progress bar.
+    Verbose,
+}
+
+const BAR_WIDTH: usize = 24;
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Reports coded packets on stderr according to the log level.
+pub struct Progress {
+    level: LogLevel,
+    draw_bar: bool,
+    /// Frames to code; `None` when streaming input of unknown length.
+    total: Option<usize>,
+    fps: Fps,
+    start: Instant,
+    last_draw: Option<Instant>,
+    frames: usize,
+    bytes: u64,
+}
+
+impl Progress {
+    pub fn new(level: LogLevel, total: Option<usize>, fps: Fps) -> Self {
+        Self {
+            level,
+            draw_bar: level == LogLevel::Normal && std::io::stderr().is_terminal(),
+            total,
+            fps,
+            start: Instant::now(),
+            last_draw: None,
+            frames: 0,
+            bytes: 0,
+        }
+    }
+
+    pub fn packet(&mut self, packet: &Packet) {
+        self.bytes += packet.data.len() as u64;
+        if packet.partial {
+            return;
+        }
+        self.frames = (self.frames + 1).min(self.total.unwrap_or(usize::MAX));
+        if self.level == LogLevel::Verbose {
+            eprintln!(
+                "frame {:>4}  {:>5}  {} bytes",
+                packet.frame_number,
+                packet.frame_type.name(),
+                packet.data.len()
+            );
+        }
+        let now = Instant::now();
+        if self.draw_bar
+            && (Some(self.frames) == self.total
+                || self
+                    .last_draw
+                    .is_none_or(|last| now - last >= REDRAW_INTERVAL))
+        {
+            self.last_draw = Some(now);
+            let line = format_progress(
+                self.frames,
+                self.total,
+                now - self.start,
+                self.bytes,
+                self.fps,
+            );
+            let mut stderr = std::io::stderr().lock();
+            let _ = write!(stderr, "\r{line}\x1b[K");
+            let _ = stderr.flush();
+        }
+    }
+
+    /// Ends the bar's line so the summary starts on a fresh one.
+    pub fn finish(self) {
+        if self.last_draw.is_some() {
+            eprintln!();
+        }
+    }
+}
+
+/// `[#####-----] done/total frames, encode speed, bitrate so far, ETA`, or
+/// just the count, speed and bitrate without a `total`.
+pub fn format_progress(
+    done: usize,
+    total: Option<usize>,
+    elapsed: Duration,
+    bytes: u64,
+    fps: Fps,
+) -> String {
+    let seconds = elapsed.as_secs_f64();
+    let speed = if seconds > 0.0 {
+        done as f64 / seconds
+    } else {
+        0.0
+    };
+    let kbps = if done > 0 {
+        bytes as f64 * 8.0 * fps.as_f64() / done as f64 / 1000.0
+    } else {
+        0.0
+    };
+    let Some(total) = total else {
+        return format!("{done} frames  {speed:.1} fps  {kbps:.0} kbps");
+    };
+    let filled = (done * BAR_WIDTH).checked_div(total).unwrap_or(BAR_WIDTH);
+    let bar = format!("{}{}", "#".repeat(filled), "-".repeat(BAR_WIDTH - filled));
+    let eta = if done > 0 {
+        let remaining = elapsed.mul_f64((total - done) as f64 / done as f64);
+        format_duration(remaining)
+    } else {
+        "--:--".to_owned()
+    };
+    format!("[{bar}] {done}/{total} frames  {speed:.1} fps  {kbps:.0} kbps  ETA {eta}")
+}
+
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs_f64().round() as u64;
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progress_line_shows_rate_and_eta() {
+        let line = format_progress(
+            50,
+            Some(200),
+            Duration::from_secs(10),
+            125_000,
+            Fps::from_int(25).unwrap(),
+        );
+        assert_eq!(
+            line,
+            "[######------------------] 50/200 frames  5.0 fps  500 kbps  ETA 0:30"
+        );
+    }
+
+    #[test]
+    fn progress_line_before_the_first_frame() {
+        let line = format_progress(0, Some(10), Duration::ZERO, 0, Fps::default());
+        assert!(line.starts_with(&format!("[{}] 0/10 frames", "-".repeat(BAR_WIDTH))));
+        assert!(line.ends_with("ETA --:--"));
+    }
+
+    #[test]
+    fn streamed_input_shows_no_bar_or_eta() {
+        let line = format_progress(
+            50,
+            None,
+            Duration::from_secs(10),
+            125_000,
+            Fps::from_int(25).unwrap(),
+        );
+        assert_eq!(line, "50 frames  5.0 fps  500 kbps");
+    }
+
+    #[test]
+    fn long_etas_show_hours() {
+        assert_eq!(
+            format_duration(Duration::from_secs(3 * 3600 + 62)),
+            "3:01:02"
+        );
+        assert_eq!(format_duration(Duration::from_secs(59)), "0:59");
+    }
+}
@@ -0,0 +1,276 @@
+//! Timed text muxed into MP4 output (`--subtitles`, `--chapters`): WebVTT
+//! cues become 3GPP timed text (`tx3g`) samples.
+
+use crate::mp4::Mp4Sample;
+
+/// Text tracks count milliseconds, the resolution of WebVTT timestamps.
+pub const TEXT_TIMESCALE: u32 = 1000;
+
+/// One WebVTT cue, with markup removed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cue {
+    pub start_ms: u64,
+    pub end_ms: u64,
+    pub text: String,
+}
+
+/// What a text track is for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextKind {
+    /// Captions shown over the video, selectable by the player.
+    Subtitles,
+    /// Chapter titles: a disabled track the video references with
+    /// `tref`/`chap`, one sample per chapter lasting until the next.
+    Chapters,
+}
+
+/// A text track: one sample per cue, and empty samples filling the gaps so
+/// sample times line up with the cues.
+pub struct TextTrack {
+    pub kind: TextKind,
+    pub samples: Vec<Mp4Sample>,
+}
+
+impl TextTrack {
+    /// Subtitles showing each cue from its start to its end, or to the next
+    /// cue's start if they overlap.
+    pub fn subtitles(cues: &[Cue]) -> Self {
+        let mut samples = Vec::new();
+        let mut time = 0;
+        for (i, cue) in cues.iter().enumerate() {
+            if cue.start_ms > time {
+                samples.push(text_sample("", cue.start_ms - time));
+                time = cue.start_ms;
+            }
+            let end = cues
+                .get(i + 1)
+                .map_or(cue.end_ms, |next| cue.end_ms.min(next.start_ms));
+            if end > time {
+                samples.push(text_sample(&cue.text, end - time));
+                time = end;
+            }
+        }
+        Self {
+            kind: TextKind::Subtitles,
+            samples,
+        }
+    }
+
+    /// Chapters starting at each cue's start. The last runs to the end of a
+    /// presentation of `duration_ms`, or to its cue's end if that is later.
+    /// Untitled time before the first cue is a chapter with an empty title.
+    pub fn chapters(cues: &[Cue], duration_ms: u64) -> Self {
+        let mut samples = Vec::new();
+        let mut time = 0;
+        for (i, cue) in cues.iter().enumerate() {
+            if cue.start_ms > time {
+                samples.push(text_sample("", cue.start_ms - time));
+                time = cue.start_ms;
+            }
+            let end = match cues.get(i + 1) {
+                Some(next) => next.start_ms,
+                None => duration_ms.max(cue.end_ms),
+            };
+            if end > time {
+                samples.push(text_sample(&cue.text, end - time));
+                time = end;
+            }
+        }
+        Self {
+            kind: TextKind::Chapters,
+            samples,
+        }
+    }
+}
+
+/// A `tx3g` sample: the UTF-8 text behind a 16-bit length. Texts too long
+/// for it are cut at a character boundary.
+fn text_sample(text: &str, duration_ms: u64) -> Mp4Sample {
+    let mut len = text.len().min(u16::MAX as usize);
+    while !text.is_char_boundary(len) {
+        len -= 1;
+    }
+    let mut data = (len as u16).to_be_bytes().to_vec();
+    data.extend_from_slice(&text.as_bytes()[..len]);
+    Mp4Sample {
+        data,
+        is_sync: true,
+        duration: duration_ms.min(u32::MAX as u64) as u32,
+        disposable: false,
+    }
+}
+
+/// Reads `path` as WebVTT.
+pub fn read_webvtt(path: &str) -> Result<Vec<Cue>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("reading {path}: {e}"))?;
+    parse_webvtt(&text).map_err(|e| format!("{path}: {e}"))
+}
+
+/// Parses the cues of a WebVTT file, sorted by start time. `NOTE`, `STYLE`
+/// and `REGION` blocks and cue settings are skipped; cue text keeps its line
+/// breaks and loses its tags, with the common character references decoded.
+pub fn parse_webvtt(text: &str) -> Result<Vec<Cue>, String> {
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let text = text.replace("\r\n", "\n").replace('\r', "\n");
+    let mut blocks = text.split("\n\n").map(|block| block.trim_matches('\n'));
+    let first_word = |block: &str| {
+        block
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_owned()
+    };
+    if blocks.next().map(first_word).as_deref() != Some("WEBVTT") {
+        return Err("missing WEBVTT header".to_owned());
+    }
+    let mut cues = Vec::new();
+    for block in blocks.filter(|b| !b.is_empty()) {
+        if ["NOTE", "STYLE", "REGION"].contains(&first_word(block).as_str()) {
+            continue;
+        }
+        let mut lines = block.lines();
+        let mut timing = lines.next().unwrap_or_default();
+        if !timing.contains("-->") {
+            // Cue identifier.
+            timing = lines.next().unwrap_or_default();
+        }
+        let (start, rest) = timing.split_once("-->").ok_or_else(|| {
+            format!(
+                "cue without timing: {:?}",
+                block.lines().next().unwrap_or("")
+            )
+        })?;
+        let end = rest.split_whitespace().next().unwrap_or_default();
+        let (start_ms, end_ms) = (parse_timestamp(start.trim())?, parse_timestamp(end)?);
+        if end_ms < start_ms {
+            return Err(format!("cue ends before it starts: {}", timing.trim()));
+        }
+        let payload: Vec<&str> = lines.collect();
+        cues.push(Cue {
+            start_ms,
+            end_ms,
+            text: strip_markup(&payload.join("\n")),
+        });
+    }
+    cues.sort_by_key(|cue| cue.start_ms);
+    Ok(cues)
+}
+
+/// `[hh:]mm:ss.ttt` in milliseconds.
+fn parse_timestamp(s: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid WebVTT timestamp {s:?}");
+    let (clock, millis) = s.split_once('.').ok_or_else(invalid)?;
+    let fields: Vec<&str> = clock.split(':').collect();
+    let digits = |field: &str, len: Option<usize>| -> Result<u64, String> {
+        if field.is_empty()
+            || !field.bytes().all(|b| b.is_ascii_digit())
+            || len.is_some_and(|len| field.len() != len)
+        {
+            return Err(invalid());
+        }
+        field.parse().map_err(|_| invalid())
+    };
+    let (hours, minutes, seconds) = match fields[..] {
+        [h, m, s] => (digits(h, None)?, digits(m, Some(2))?, digits(s, Some(2))?),
+        [m, s] => (0, digits(m, Some(2))?, digits(s, Some(2))?),
+        _ => return Err(invalid()),
+    };
+    if minutes > 59 || seconds > 59 {
+        return Err(invalid());
+    }
+    let millis = digits(millis, Some(3))?;
+    Ok(((hours * 60 + minutes) * 60 + seconds) * 1000 + millis)
+}
+
+/// Cue text without `<...>` tags, with `&amp;`, `&lt;`, `&gt;`, `&quot;`,
+/// `&apos;` and `&nbsp;` decoded.
+fn strip_markup(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = rest.find('<') {
+        out.push_str(&rest[..pos]);
+        rest = rest[pos..]
+            .find('>')
+            .map_or("", |end| &rest[pos + end + 1..]);
+    }
+    out.push_str(rest);
+    [
+        ("&lt;", "<"),
+        ("&gt;", ">"),
+        ("&quot;", "\""),
+        ("&apos;", "'"),
+        ("&nbsp;", "\u{a0}"),
+        ("&amp;", "&"),
+    ]
+    .iter()
+    .fold(out, |text, (from, to)| text.replace(from, to))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "\u{feff}WEBVTT - screencast\r\n\r\n\
+        NOTE written by hand\r\n\r\n\
+        intro\r\n00:00:01.500 --> 00:00:04.000 align:start\r\n<b>Intro</b> &amp; setup\r\n\r\n\
+        01:02.250 --> 01:05.000\r\nSecond\r\nline two\r\n";
+
+    fn texts(track: &TextTrack) -> Vec<(String, u32)> {
+        track
+            .samples
+            .iter()
+            .map(|s| (String::from_utf8(s.data[2..].to_vec()).unwrap(), s.duration))
+            .collect()
+    }
+
+    #[test]
+    fn parses_cues_and_strips_markup() {
+        let cues = parse_webvtt(SAMPLE).unwrap();
+        assert_eq!(
+            cues,
+            vec![
+                Cue {
+                    start_ms: 1_500,
+                    end_ms: 4_000,
+                    text: "Intro & setup".to_owned(),
+                },
+                Cue {
+                    start_ms: 62_250,
+                    end_ms: 65_000,
+                    text: "Second\nline two".to_owned(),
+                },
+            ]
+        );
+        assert!(parse_webvtt("00:01.000 --> 00:02.000\nhi\n").is_err());
+        assert!(parse_webvtt("WEBVTT\n\n00:01.00 --> 00:02.000\nhi\n").is_err());
+        assert!(parse_webvtt("WEBVTT\n\n00:03.000 --> 00:02.000\nhi\n").is_err());
+    }
+
+    #[test]
+    fn subtitles_fill_gaps_and_chapters_run_to_the_next() {
+        let cues = parse_webvtt(SAMPLE).unwrap();
+        let subtitles = TextTrack::subtitles(&cues);
+        assert_eq!(
+            texts(&subtitles),
+            vec![
+                (String::new(), 1_500),
+                ("Intro & setup".to_owned(), 2_500),
+                (String::new(), 58_250),
+                ("Second\nline two".to_owned(), 2_750),
+            ]
+        );
+        assert_eq!(subtitles.samples[1].data[..2], [0, 13]);
+
+        let chapters = TextTrack::chapters(&cues, 90_000);
+        assert_eq!(
+            texts(&chapters),
+            vec![
+                (String::new(), 1_500),
+                ("Intro & setup".to_owned(), 60_750),
+                ("Second\nline two".to_owned(), 27_750),
+            ]
+        );
+        let total: u32 = chapters.samples.iter().map(|s| s.duration).sum();
+        assert_eq!(total, 90_000);
+    }
+}
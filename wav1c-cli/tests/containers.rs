@@ -0,0 +1,549 @@
+//! Structural checks of the IVF, MP4 and AVIF files the CLI writes.
+//!
+//! Each test runs the `wav1c` binary and walks its output with the small
+//! parser below, asserting what players and demuxers (ffprobe, MP4Box,
+//! libavif) rely on: box order, sample counts and offsets, `av1C` contents
+//! and IVF header fields. No external tools are needed.
+
+use std::path::PathBuf;
+use std::process::Command;
+
+const WIDTH: u32 = 64;
+const HEIGHT: u32 = 48;
+const FRAMES: usize = 5;
+
+const OBU_SEQUENCE_HEADER: u8 = 1;
+const OBU_TEMPORAL_DELIMITER: u8 = 2;
+const OBU_FRAME: u8 = 6;
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("wav1c-containers-{}-{name}", std::process::id()))
+}
+
+fn write_y4m(name: &str) -> PathBuf {
+    let mut data =
+        format!("YUV4MPEG2 W{WIDTH} H{HEIGHT} F30000:1001 Ip A1:1 C420jpeg\n").into_bytes();
+    for frame in 0..FRAMES {
+        data.extend_from_slice(b"FRAME\n");
+        for i in 0..(WIDTH * HEIGHT) as usize {
+            let (x, y) = (i % WIDTH as usize, i / WIDTH as usize);
+            data.push(((x * 3 + y * 2 + frame * 4) % 200 + 16) as u8);
+        }
+        let chroma = (WIDTH / 2 * HEIGHT / 2) as usize;
+        data.extend(std::iter::repeat_n(128u8, 2 * chroma));
+    }
+    let path = temp_path(name);
+    std::fs::write(&path, data).unwrap();
+    path
+}
+
+/// Runs the CLI on a fresh Y4M and returns the output file's bytes.
+fn encode(output: &str, args: &[&str]) -> Vec<u8> {
+    let input = write_y4m(&format!("{output}.y4m"));
+    let output = temp_path(output);
+    let status = Command::new(env!("CARGO_BIN_EXE_wav1c"))
+        .arg(&input)
+        .arg("-o")
+        .arg(&output)
+        .args(["--keyint", "3"])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        status.status.success(),
+        "wav1c failed: {}",
+        String::from_utf8_lossy(&status.stderr)
+    );
+    let data = std::fs::read(&output).unwrap();
+    let _ = std::fs::remove_file(input);
+    let _ = std::fs::remove_file(output);
+    data
+}
+
+fn be32(data: &[u8], pos: usize) -> u32 {
+    u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap())
+}
+
+fn le32(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+}
+
+fn le16(data: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes(data[pos..pos + 2].try_into().unwrap())
+}
+
+/// `(type, payload, offset of the box in data)` for each box in `data`.
+fn boxes(data: &[u8]) -> Vec<([u8; 4], &[u8], usize)> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        assert!(pos + 8 <= data.len(), "truncated box header at {pos}");
+        let size = be32(data, pos) as usize;
+        assert!(
+            size >= 8 && pos + size <= data.len(),
+            "bad box size at {pos}"
+        );
+        let kind = data[pos + 4..pos + 8].try_into().unwrap();
+        out.push((kind, &data[pos + 8..pos + size], pos));
+        pos += size;
+    }
+    out
+}
+
+fn box_types(data: &[u8]) -> Vec<[u8; 4]> {
+    boxes(data).iter().map(|(kind, _, _)| *kind).collect()
+}
+
+fn child<'a>(data: &'a [u8], kind: &[u8; 4]) -> &'a [u8] {
+    boxes(data)
+        .into_iter()
+        .find(|(k, _, _)| k == kind)
+        .map(|(_, payload, _)| payload)
+        .unwrap_or_else(|| panic!("no {} box", String::from_utf8_lossy(kind)))
+}
+
+fn path<'a>(data: &'a [u8], kinds: &[&[u8; 4]]) -> &'a [u8] {
+    kinds.iter().fold(data, |data, kind| child(data, kind))
+}
+
+/// Fields of a full box payload after version and flags, as big-endian u32s.
+fn full_box_words(payload: &[u8]) -> Vec<u32> {
+    payload[4..].chunks(4).map(|c| be32(c, 0)).collect()
+}
+
+fn leb128(data: &[u8], mut pos: usize) -> (usize, usize) {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[pos];
+        pos += 1;
+        value |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return (value, pos);
+        }
+        shift += 7;
+    }
+}
+
+fn obu_types(data: &[u8]) -> Vec<u8> {
+    let mut types = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let header = data[pos];
+        assert_eq!(header & 0x02, 0x02, "OBU without obu_has_size_field");
+        let (size, payload) = leb128(data, pos + 1);
+        types.push(header >> 3);
+        pos = payload + size;
+    }
+    assert_eq!(pos, data.len());
+    types
+}
+
+/// Checks an `av1C` box payload against the stream settings and returns its
+/// configOBUs.
+fn check_av1c(av1c: &[u8]) -> &[u8] {
+    assert_eq!(av1c[0], 0x81, "marker and version");
+    assert_eq!(av1c[1] >> 5, 0, "seq_profile");
+    assert_eq!(av1c[2] & 0x40, 0, "high_bitdepth for 8-bit input");
+    assert_eq!(
+        av1c[2] & 0x0f,
+        0x0c,
+        "mono_chrome=0 subsampling_x=1 subsampling_y=1"
+    );
+    let config_obus = &av1c[4..];
+    assert_eq!(obu_types(config_obus)[0], OBU_SEQUENCE_HEADER);
+    config_obus
+}
+
+#[test]
+fn ivf_header_and_frames() {
+    let ivf = encode("out.ivf", &[]);
+    assert_eq!(&ivf[0..4], b"DKIF");
+    assert_eq!(le16(&ivf, 4), 0, "version");
+    assert_eq!(le16(&ivf, 6), 32, "header size");
+    assert_eq!(&ivf[8..12], b"AV01");
+    assert_eq!(u32::from(le16(&ivf, 12)), WIDTH);
+    assert_eq!(u32::from(le16(&ivf, 14)), HEIGHT);
+    assert_eq!((le32(&ivf, 16), le32(&ivf, 20)), (30000, 1001), "time base");
+    assert_eq!(le32(&ivf, 24) as usize, FRAMES);
+
+    let mut pos = 32;
+    let mut timestamps = Vec::new();
+    while pos < ivf.len() {
+        let size = le32(&ivf, pos) as usize;
+        timestamps.push(u64::from_le_bytes(
+            ivf[pos + 4..pos + 12].try_into().unwrap(),
+        ));
+        let types = obu_types(&ivf[pos + 12..pos + 12 + size]);
+        assert_eq!(types[0], OBU_TEMPORAL_DELIMITER);
+        assert_eq!(types[1], OBU_SEQUENCE_HEADER);
+        assert_eq!(*types.last().unwrap(), OBU_FRAME);
+        pos += 12 + size;
+    }
+    assert_eq!(pos, ivf.len());
+    assert_eq!(timestamps, (0..FRAMES as u64).collect::<Vec<_>>());
+}
+
+#[test]
+fn preview_ivf_is_half_size_with_matching_timestamps() {
+    let preview = temp_path("preview.ivf");
+    let main = encode(
+        "main-with-preview.ivf",
+        &["--preview", preview.to_str().unwrap(), "--preview-q", "220"],
+    );
+    let ivf = std::fs::read(&preview).unwrap();
+    let _ = std::fs::remove_file(&preview);
+    assert_eq!(&ivf[0..4], b"DKIF");
+    assert_eq!(u32::from(le16(&ivf, 12)), WIDTH.div_ceil(2));
+    assert_eq!(u32::from(le16(&ivf, 14)), HEIGHT.div_ceil(2));
+    assert_eq!((le32(&ivf, 16), le32(&ivf, 20)), (30000, 1001), "time base");
+    assert_eq!(le32(&ivf, 24) as usize, FRAMES);
+    assert!(ivf.len() < main.len());
+
+    let mut pos = 32;
+    let mut timestamps = Vec::new();
+    while pos < ivf.len() {
+        let size = le32(&ivf, pos) as usize;
+        timestamps.push(u64::from_le_bytes(
+            ivf[pos + 4..pos + 12].try_into().unwrap(),
+        ));
+        pos += 12 + size;
+    }
+    assert_eq!(timestamps, (0..FRAMES as u64).collect::<Vec<_>>());
+}
+
+/// Checks the single video track of a non-fragmented MP4, including that
+/// its chunk offsets and sample sizes tile the mdat payload exactly.
+fn check_mp4_track(mp4: &[u8], moov: &[u8]) {
+    let trak = child(moov, b"trak");
+    let tkhd = child(trak, b"tkhd");
+    assert_eq!(be32(tkhd, 12), 1, "track_ID");
+    assert_eq!(be32(tkhd, 76) >> 16, WIDTH);
+    assert_eq!(be32(tkhd, 80) >> 16, HEIGHT);
+
+    let mdhd = path(trak, &[b"mdia", b"mdhd"]);
+    assert_eq!(be32(mdhd, 12), 30000, "timescale");
+    assert_eq!(be32(mdhd, 16), FRAMES as u32 * 1001, "media duration");
+    assert_eq!(&path(trak, &[b"mdia", b"hdlr"])[8..12], b"vide");
+
+    let stbl = path(trak, &[b"mdia", b"minf", b"stbl"]);
+    let stsd = child(stbl, b"stsd");
+    assert_eq!(be32(stsd, 4), 1, "one sample entry");
+    let entry = &stsd[8..];
+    assert_eq!(&entry[4..8], b"av01");
+    let av01 = &entry[8..be32(entry, 0) as usize];
+    assert_eq!(u32::from(u16::from_be_bytes([av01[24], av01[25]])), WIDTH);
+    assert_eq!(u32::from(u16::from_be_bytes([av01[26], av01[27]])), HEIGHT);
+    check_av1c(child(&av01[78..], b"av1C"));
+    assert_eq!(child(&av01[78..], b"colr")[..4], *b"nclx");
+
+    assert_eq!(
+        full_box_words(child(stbl, b"stts")),
+        [1, FRAMES as u32, 1001]
+    );
+    let stsz = full_box_words(child(stbl, b"stsz"));
+    assert_eq!(stsz[0], 0, "per-sample sizes");
+    assert_eq!(stsz[1] as usize, FRAMES);
+    let sizes = &stsz[2..];
+    assert_eq!(full_box_words(child(stbl, b"stss")), [2, 1, 4], "keyint 3");
+
+    let stsc = full_box_words(child(stbl, b"stsc"));
+    let stco = full_box_words(child(stbl, b"stco"));
+    assert_eq!(
+        stsc,
+        [1, 1, FRAMES as u32, 1],
+        "one chunk below a second of video"
+    );
+    assert_eq!(stco.len(), 2);
+
+    let (_, mdat, mdat_pos) = boxes(mp4)
+        .into_iter()
+        .find(|(kind, _, _)| kind == b"mdat")
+        .unwrap();
+    let mut offset = stco[1] as usize;
+    assert_eq!(offset, mdat_pos + 8, "samples start the mdat payload");
+    for &size in sizes {
+        let types = obu_types(&mp4[offset..offset + size as usize]);
+        assert!(!types.contains(&OBU_TEMPORAL_DELIMITER), "TDs are stripped");
+        assert_eq!(*types.last().unwrap(), OBU_FRAME);
+        offset += size as usize;
+    }
+    assert_eq!(offset, mdat_pos + 8 + mdat.len());
+}
+
+#[test]
+fn mp4_box_order_and_sample_tables() {
+    let mp4 = encode("out.mp4", &[]);
+    assert_eq!(box_types(&mp4), [*b"ftyp", *b"mdat", *b"moov"]);
+    let ftyp = child(&mp4, b"ftyp");
+    assert_eq!(&ftyp[0..4], b"isom");
+    assert!(ftyp[8..].chunks(4).any(|brand| brand == b"av01"));
+
+    let moov = child(&mp4, b"moov");
+    let mvhd = child(moov, b"mvhd");
+    assert_eq!(be32(mvhd, 12), 1000, "movie timescale");
+    assert_eq!(be32(mvhd, 16), FRAMES as u32 * 1001 / 30, "movie duration");
+    assert_eq!(be32(mvhd, mvhd.len() - 4), 2, "next_track_ID");
+    check_mp4_track(&mp4, moov);
+}
+
+#[test]
+fn adts_audio_is_muxed_as_a_second_track() {
+    // 10 frames of AAC LC at 48 kHz mono, each 1024 samples.
+    let mut adts = Vec::new();
+    for i in 0..10u8 {
+        let payload = [i; 20];
+        let len = payload.len() + 7;
+        adts.extend_from_slice(&[
+            0xFF,
+            0xF1,
+            (1 << 6) | (3 << 2),
+            (1 << 6) | (len >> 11) as u8,
+            (len >> 3) as u8,
+            ((len & 7) << 5) as u8 | 0x1F,
+            0xFC,
+        ]);
+        adts.extend_from_slice(&payload);
+    }
+    let audio = temp_path("track.aac");
+    std::fs::write(&audio, &adts).unwrap();
+    let mp4 = encode("with-audio.mp4", &["--audio", audio.to_str().unwrap()]);
+    let _ = std::fs::remove_file(&audio);
+
+    let moov = child(&mp4, b"moov");
+    let mvhd = child(moov, b"mvhd");
+    assert_eq!(be32(mvhd, mvhd.len() - 4), 3, "next_track_ID");
+    let traks: Vec<_> = boxes(moov)
+        .into_iter()
+        .filter(|(kind, _, _)| kind == b"trak")
+        .map(|(_, payload, _)| payload)
+        .collect();
+    assert_eq!(traks.len(), 2);
+    let sound = traks[1];
+    assert_eq!(be32(child(sound, b"tkhd"), 12), 2, "track_ID");
+    assert_eq!(&path(sound, &[b"mdia", b"hdlr"])[8..12], b"soun");
+    let mdhd = path(sound, &[b"mdia", b"mdhd"]);
+    assert_eq!((be32(mdhd, 12), be32(mdhd, 16)), (48_000, 10 * 1024));
+    let stbl = path(sound, &[b"mdia", b"minf", b"stbl"]);
+    assert_eq!(&child(stbl, b"stsd")[12..16], b"mp4a");
+    assert_eq!(full_box_words(child(stbl, b"stts")), [1, 10, 1024]);
+
+    // The raw frames, without their ADTS headers, sit where stco points.
+    let offset = full_box_words(child(stbl, b"stco"))[1] as usize;
+    assert_eq!(&mp4[offset..offset + 20], &[0; 20]);
+    assert_eq!(&mp4[offset + 20..offset + 40], &[1; 20]);
+}
+
+#[test]
+fn webvtt_is_muxed_as_subtitle_and_chapter_tracks() {
+    let vtt = temp_path("cues.vtt");
+    std::fs::write(
+        &vtt,
+        "WEBVTT\n\n00:00.000 --> 00:00.100\nOpening\n\n00:00.100 --> 00:00.120\n<i>Details</i>\n",
+    )
+    .unwrap();
+    let vtt_path = vtt.to_str().unwrap();
+    let mp4 = encode(
+        "with-text.mp4",
+        &["--subtitles", vtt_path, "--chapters", vtt_path],
+    );
+    let _ = std::fs::remove_file(&vtt);
+
+    let moov = child(&mp4, b"moov");
+    let traks: Vec<_> = boxes(moov)
+        .into_iter()
+        .filter(|(kind, _, _)| kind == b"trak")
+        .map(|(_, payload, _)| payload)
+        .collect();
+    assert_eq!(traks.len(), 3);
+    assert_eq!(be32(path(traks[0], &[b"tref", b"chap"]), 0), 3);
+    for (trak, handler, flags) in [(traks[1], b"sbtl", 3), (traks[2], b"text", 0)] {
+        assert_eq!(&path(trak, &[b"mdia", b"hdlr"])[8..12], handler);
+        assert_eq!(be32(child(trak, b"tkhd"), 0) & 0xFF_FFFF, flags);
+        let stbl = path(trak, &[b"mdia", b"minf", b"stbl"]);
+        assert_eq!(&child(stbl, b"stsd")[12..16], b"tx3g");
+    }
+
+    // Five frames at 30000/1001 fps last 166 ms; the last chapter runs to
+    // the end, the last cue only to its own.
+    let subtitle_stbl = path(traks[1], &[b"mdia", b"minf", b"stbl"]);
+    assert_eq!(
+        full_box_words(child(subtitle_stbl, b"stts")),
+        [2, 1, 100, 1, 20]
+    );
+    let chapter_stbl = path(traks[2], &[b"mdia", b"minf", b"stbl"]);
+    assert_eq!(
+        full_box_words(child(chapter_stbl, b"stts")),
+        [2, 1, 100, 1, 66]
+    );
+    let offset = full_box_words(child(chapter_stbl, b"stco"))[1] as usize;
+    assert_eq!(&mp4[offset..offset + 9], b"\0\x07Opening");
+    assert_eq!(&mp4[offset + 9..offset + 18], b"\0\x07Details");
+}
+
+#[test]
+fn faststart_mp4_puts_moov_first() {
+    let mp4 = encode("faststart.mp4", &["--faststart"]);
+    assert_eq!(box_types(&mp4), [*b"ftyp", *b"moov", *b"mdat"]);
+    check_mp4_track(&mp4, child(&mp4, b"moov"));
+}
+
+#[test]
+fn fragmented_mp4_has_one_fragment_per_gop() {
+    let mp4 = encode("fragmented.mp4", &["--progressive"]);
+    assert_eq!(
+        box_types(&mp4),
+        [*b"ftyp", *b"moov", *b"moof", *b"mdat", *b"moof", *b"mdat"]
+    );
+    let moov = child(&mp4, b"moov");
+    let stsz = path(moov, &[b"trak", b"mdia", b"minf", b"stbl", b"stsz"]);
+    assert_eq!(full_box_words(stsz), [0, 0], "empty sample table");
+    let trex = path(moov, &[b"mvex", b"trex"]);
+    assert_eq!(full_box_words(trex)[..3], [1, 1, 1001]);
+
+    let all = boxes(&mp4);
+    let mut samples = 0;
+    for (i, pair) in all[2..].chunks(2).enumerate() {
+        let (_, moof, moof_pos) = pair[0];
+        let (_, mdat, mdat_pos) = pair[1];
+        let mfhd = child(moof, b"mfhd");
+        assert_eq!(be32(mfhd, 4), i as u32 + 1, "sequence_number");
+        let traf = child(moof, b"traf");
+        let tfdt = child(traf, b"tfdt");
+        let decode_time = u64::from_be_bytes(tfdt[4..12].try_into().unwrap());
+        assert_eq!(decode_time, samples as u64 * 1001);
+        let trun = child(traf, b"trun");
+        let count = be32(trun, 4) as usize;
+        let data_offset = be32(trun, 8) as usize;
+        assert_eq!(moof_pos + data_offset, mdat_pos + 8);
+        let sizes: usize = (0..count).map(|s| be32(trun, 12 + s * 8) as usize).sum();
+        assert_eq!(sizes, mdat.len());
+        samples += count;
+    }
+    assert_eq!(samples, FRAMES);
+}
+
+#[test]
+fn y4m_from_stdin_streams_to_the_same_mp4() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let input = write_y4m("stdin.y4m");
+    let output = temp_path("stdin.mp4");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_wav1c"))
+        .arg("-")
+        .arg("-o")
+        .arg(&output)
+        .args(["--keyint", "3"])
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let y4m = std::fs::read(&input).unwrap();
+    child.stdin.take().unwrap().write_all(&y4m).unwrap();
+    let status = child.wait_with_output().unwrap();
+    assert!(
+        status.status.success(),
+        "wav1c failed: {}",
+        String::from_utf8_lossy(&status.stderr)
+    );
+    let streamed = std::fs::read(&output).unwrap();
+    let _ = std::fs::remove_file(input);
+    let _ = std::fs::remove_file(output);
+    assert!(
+        streamed == encode("file.mp4", &[]),
+        "stdin and file input differ"
+    );
+}
+
+#[test]
+fn avif_item_structure() {
+    let avif = encode("still.avif", &[]);
+    assert_eq!(box_types(&avif), [*b"ftyp", *b"meta", *b"mdat"]);
+    let ftyp = child(&avif, b"ftyp");
+    assert_eq!(&ftyp[0..4], b"avif");
+
+    let meta = &child(&avif, b"meta")[4..];
+    assert_eq!(&child(meta, b"hdlr")[8..12], b"pict");
+    let pitm = child(meta, b"pitm");
+    assert_eq!(u16::from_be_bytes([pitm[4], pitm[5]]), 1);
+    let iinf = child(meta, b"iinf");
+    let infe = child(&iinf[6..], b"infe");
+    assert_eq!(infe[0], 2, "infe version");
+    assert_eq!(&infe[8..12], b"av01");
+
+    let ipco = path(meta, &[b"iprp", b"ipco"]);
+    let ispe = child(ipco, b"ispe");
+    assert_eq!((be32(ispe, 4), be32(ispe, 8)), (WIDTH, HEIGHT));
+    let config_obus = check_av1c(child(ipco, b"av1C"));
+    let pixi = child(ipco, b"pixi");
+    assert_eq!(&pixi[4..], [3, 8, 8, 8]);
+
+    // iloc v0, 4-byte offsets and lengths: the primary item's one extent
+    // covers the whole mdat payload.
+    let iloc = child(meta, b"iloc");
+    assert_eq!(&iloc[4..6], [0x44, 0x00]);
+    assert_eq!(u16::from_be_bytes([iloc[6], iloc[7]]), 1, "item count");
+    assert_eq!(u16::from_be_bytes([iloc[8], iloc[9]]), 1, "item_ID");
+    let offset = be32(iloc, 14) as usize;
+    let length = be32(iloc, 18) as usize;
+    let (_, mdat, mdat_pos) = boxes(&avif)
+        .into_iter()
+        .find(|(kind, _, _)| kind == b"mdat")
+        .unwrap();
+    assert_eq!((offset, length), (mdat_pos + 8, mdat.len()));
+    let item = &avif[offset..offset + length];
+    assert_eq!(obu_types(item), [OBU_SEQUENCE_HEADER, OBU_FRAME]);
+    assert!(item.starts_with(config_obus));
+}
+
+#[test]
+fn chroma_position_is_carried_in_av1c() {
+    let mp4 = encode("colocated.mp4", &["--chroma-position", "colocated"]);
+    let stsd = path(
+        child(&mp4, b"moov"),
+        &[b"trak", b"mdia", b"minf", b"stbl", b"stsd"],
+    );
+    let entry = &stsd[8..];
+    let av01 = &entry[8..be32(entry, 0) as usize];
+    assert_eq!(child(&av01[78..], b"av1C")[2] & 0x03, 2, "CSP_COLOCATED");
+
+    let avif = encode("vertical.avif", &["--chroma-position", "left"]);
+    let ipco = path(&child(&avif, b"meta")[4..], &[b"iprp", b"ipco"]);
+    assert_eq!(child(ipco, b"av1C")[2] & 0x03, 1, "CSP_VERTICAL");
+}
+
+#[test]
+fn avif_alpha_is_an_auxiliary_item() {
+    let mut matte =
+        format!("YUV4MPEG2 W{WIDTH} H{HEIGHT} F30:1 Cmono XCOLORRANGE=FULL\nFRAME\n").into_bytes();
+    matte.extend((0..WIDTH * HEIGHT).map(|i| (i % WIDTH * 4) as u8));
+    let matte_path = temp_path("matte.y4m");
+    std::fs::write(&matte_path, matte).unwrap();
+    let avif = encode(
+        "alpha.avif",
+        &["--companion", &format!("alpha={}", matte_path.display())],
+    );
+    let _ = std::fs::remove_file(matte_path);
+
+    let meta = &child(&avif, b"meta")[4..];
+    let iinf = child(meta, b"iinf");
+    assert_eq!(u16::from_be_bytes([iinf[4], iinf[5]]), 2, "item count");
+    // auxl: item 2 is an auxiliary image of item 1.
+    let iref = child(meta, b"iref");
+    assert_eq!(&child(&iref[4..], b"auxl")[..6], [0, 2, 0, 1, 0, 1]);
+    let ipco = path(meta, &[b"iprp", b"ipco"]);
+    assert_eq!(
+        &child(ipco, b"auxC")[4..],
+        b"urn:mpeg:mpegB:cicp:systems:auxiliary:alpha\0"
+    );
+
+    // Both items' data sit in mdat, each starting with its configOBUs.
+    let iloc = child(meta, b"iloc");
+    assert_eq!(u16::from_be_bytes([iloc[6], iloc[7]]), 2, "item count");
+    assert_eq!(u16::from_be_bytes([iloc[22], iloc[23]]), 2, "item_ID");
+    let alpha_offset = be32(iloc, 28) as usize;
+    let alpha_length = be32(iloc, 32) as usize;
+    let alpha = &avif[alpha_offset..alpha_offset + alpha_length];
+    assert_eq!(obu_types(alpha), [OBU_SEQUENCE_HEADER, OBU_FRAME]);
+}
@@ -0,0 +1,159 @@
+//! End-to-end checks of `--scene-chunks`: every scene becomes its own IVF
+//! segment starting with a keyframe, and encoding the segments in child
+//! processes or threads gives the same file as encoding them in turn.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 48;
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("wav1c-scene-chunks-{}-{name}", std::process::id()))
+}
+
+/// Eight frames: a slow pan over a ramp, then a cut to unrelated texture.
+fn write_two_scene_y4m(path: &Path) {
+    let mut data = format!("YUV4MPEG2 W{WIDTH} H{HEIGHT} F25:1 Ip A1:1 C420jpeg\n").into_bytes();
+    for frame in 0..8 {
+        data.extend_from_slice(b"FRAME\n");
+        for i in 0..WIDTH * HEIGHT {
+            let (x, y) = (i % WIDTH, i / WIDTH);
+            let sample = if frame < 4 {
+                (x * 3 + y * 2 + frame * 4) % 200 + 16
+            } else {
+                ((x * x * 7 + y * 13) ^ (x * y)) % 220 + 16
+            };
+            data.push(sample as u8);
+        }
+        data.extend(std::iter::repeat_n(128u8, WIDTH * HEIGHT / 2));
+    }
+    std::fs::write(path, data).unwrap();
+}
+
+fn encode_chunked(input: &Path, name: &str, args: &[&str]) -> (Vec<u8>, PathBuf) {
+    let output = temp_path(&format!("{name}.ivf"));
+    let chunks = temp_path(name);
+    let result = Command::new(env!("CARGO_BIN_EXE_wav1c"))
+        .arg(input)
+        .arg("-o")
+        .arg(&output)
+        .arg("--scene-chunks")
+        .arg(&chunks)
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        result.status.success(),
+        "wav1c failed: {}",
+        String::from_utf8_lossy(&result.stderr)
+    );
+    let data = std::fs::read(&output).unwrap();
+    let _ = std::fs::remove_file(output);
+    (data, chunks)
+}
+
+/// `(timestamp, frame data)` of each IVF frame.
+fn ivf_frames(data: &[u8]) -> Vec<(u64, &[u8])> {
+    let mut frames = Vec::new();
+    let mut pos = 32;
+    while pos < data.len() {
+        let size = u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+        let timestamp = u64::from_le_bytes(data[pos + 4..pos + 12].try_into().unwrap());
+        frames.push((timestamp, &data[pos + 12..pos + 12 + size]));
+        pos += 12 + size;
+    }
+    frames
+}
+
+/// Whether the first frame OBU in `packet` codes a keyframe: its header
+/// starts with `show_existing_frame` (0) then `frame_type` (0 for KEY).
+fn is_keyframe(packet: &[u8]) -> bool {
+    let mut pos = 0;
+    while pos < packet.len() {
+        let obu_type = packet[pos] >> 3 & 0xf;
+        let (mut size, mut len_pos) = (0usize, pos + 1);
+        for shift in (0..).step_by(7) {
+            let byte = packet[len_pos];
+            size |= usize::from(byte & 0x7f) << shift;
+            len_pos += 1;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        if obu_type == 6 {
+            return packet[len_pos] >> 5 == 0;
+        }
+        pos = len_pos + size;
+    }
+    false
+}
+
+#[test]
+fn scenes_become_independent_segments() {
+    let input = temp_path("input.y4m");
+    write_two_scene_y4m(&input);
+    let (sequential, sequential_chunks) = encode_chunked(&input, "sequential", &["--jobs", "1"]);
+    let (parallel, parallel_chunks) = encode_chunked(&input, "parallel", &["--jobs", "2"]);
+    let _ = std::fs::remove_file(&input);
+
+    assert_eq!(sequential, parallel);
+    let frames = ivf_frames(&sequential);
+    let timestamps: Vec<u64> = frames.iter().map(|(t, _)| *t).collect();
+    assert_eq!(timestamps, (0..8).collect::<Vec<_>>());
+    let keyframes: Vec<u64> = frames
+        .iter()
+        .filter(|(_, packet)| is_keyframe(packet))
+        .map(|(t, _)| *t)
+        .collect();
+    assert_eq!(keyframes, [0, 4]);
+
+    for dir in [&sequential_chunks, &parallel_chunks] {
+        let second = std::fs::read(dir.join("scene-0001.ivf")).unwrap();
+        assert_eq!(ivf_frames(&second)[0].0, 0);
+        assert!(is_keyframe(ivf_frames(&second)[0].1));
+    }
+    assert!(parallel_chunks.join("scene-0001.log").exists());
+    let _ = std::fs::remove_dir_all(sequential_chunks);
+    let _ = std::fs::remove_dir_all(parallel_chunks);
+}
+
+#[test]
+fn gop_chunks_keep_the_keyframe_schedule_on_any_workers() {
+    let input = temp_path("gops.y4m");
+    write_two_scene_y4m(&input);
+    let gops = ["--chunk-gops", "--keyint", "3"];
+    let (sequential, sequential_chunks) = encode_chunked(
+        &input,
+        "gops-sequential",
+        &[&gops[..], &["--jobs", "1"]].concat(),
+    );
+    let (processes, process_chunks) = encode_chunked(
+        &input,
+        "gops-processes",
+        &[&gops[..], &["--jobs", "3"]].concat(),
+    );
+    let (threads, thread_chunks) = encode_chunked(
+        &input,
+        "gops-threads",
+        &[&gops[..], &["--jobs", "3", "--chunk-workers", "threads"]].concat(),
+    );
+    let _ = std::fs::remove_file(&input);
+
+    assert_eq!(sequential, processes);
+    assert_eq!(sequential, threads);
+    let frames = ivf_frames(&sequential);
+    assert_eq!(frames.len(), 8);
+    let keyframes: Vec<u64> = frames
+        .iter()
+        .filter(|(_, packet)| is_keyframe(packet))
+        .map(|(t, _)| *t)
+        .collect();
+    assert_eq!(keyframes, [0, 3, 4, 7]);
+    assert!(process_chunks.join("gop-0003.ivf").exists());
+    assert!(process_chunks.join("gop-0003.log").exists());
+    assert!(!thread_chunks.join("gop-0003.log").exists());
+    for dir in [sequential_chunks, process_chunks, thread_chunks] {
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -8,19 +8,63 @@
 extern "C" {
 #endif
 
+/* ABI version of this header. The major changes when an existing
+ * declaration changes incompatibly: a signature, or the layout of a struct
+ * (new Wav1cConfig fields included); the minor when entry points or enum
+ * values are added. A host loading
+ * the library at runtime should require wav1c_abi_version() to have the
+ * same major and at least the minor it was built against. */
+#define WAV1C_ABI_VERSION_MAJOR 5
+#define WAV1C_ABI_VERSION_MINOR 0
+#define WAV1C_ABI_VERSION ((WAV1C_ABI_VERSION_MAJOR << 16) | WAV1C_ABI_VERSION_MINOR)
+
+/* wav1c_build_features() bits. */
+enum {
+    WAV1C_FEATURE_10BIT = 1 << 0,   /* bit_depth 10 */
+    WAV1C_FEATURE_THREADS = 1 << 1, /* Wav1cConfig.threads codes the tiles of a frame in parallel */
+    WAV1C_FEATURE_SIMD = 1 << 2,    /* a kernel specialised for the CPU's vector extensions is in use */
+    WAV1C_FEATURE_HEIC = 1 << 3     /* HEIC input (not in this library) */
+};
+
+/* Library version, e.g. "0.2.0". The string is static. */
+const char *wav1c_version(void);
+/* WAV1C_ABI_VERSION of the loaded library. */
+uint32_t wav1c_abi_version(void);
+/* WAV1C_FEATURE_* bits of the loaded library on the running CPU. */
+uint32_t wav1c_build_features(void);
+
 typedef struct Wav1cEncoder Wav1cEncoder;
 
+/* Wav1cPacket.frame_type; the values are stable across releases. */
+enum {
+    WAV1C_FRAME_TYPE_UNKNOWN = -1,  /* added in a later release */
+    WAV1C_FRAME_TYPE_KEY = 0,
+    WAV1C_FRAME_TYPE_INTER = 1,
+    WAV1C_FRAME_TYPE_INTRA_ONLY = 2,
+    WAV1C_FRAME_TYPE_SWITCH = 3,
+    WAV1C_FRAME_TYPE_SHOW_EXISTING = 4, /* shows a frame decoded earlier */
+    WAV1C_FRAME_TYPE_FORWARD_KEY = 5    /* open-GOP hidden keyframe plus its first leading frame */
+};
+
 typedef struct {
     const uint8_t *data;
     size_t         size;
     uint64_t       frame_number;
     int32_t        is_keyframe;
+    void          *user_data; /* value given to *_with_user_data, else NULL */
+    int32_t        sequence_header_changed; /* re-read wav1c_encoder_headers */
+    int32_t        is_partial; /* more packets of the same frame follow (frame_slices) */
+    uint64_t       pts; /* in frame periods, mapped from capture times */
+    int64_t        capture_time_ns; /* from wav1c_encoder_set_next_frame_capture_time, else -1 */
+    int32_t        frame_type; /* WAV1C_FRAME_TYPE_*; is_keyframe is set for KEY only */
 } Wav1cPacket;
 
 enum {
     WAV1C_STATUS_OK = 0,
     WAV1C_STATUS_INVALID_ARGUMENT = -1,
-    WAV1C_STATUS_ENCODE_FAILED = -3
+    WAV1C_STATUS_ENCODE_FAILED = -3,
+    WAV1C_STATUS_FRAME_MISMATCH = -4, /* frame bit depth or size differs from the encoder */
+    WAV1C_STATUS_INVALID_STATE = -5   /* send or reconfigure after flush/abort */
 };
 
 typedef struct {
@@ -50,8 +94,55 @@ typedef struct {
     uint16_t white_y;
     uint32_t max_luminance;
     uint32_t min_luminance;
+    uint32_t tile_columns; /* 0 = only the tiles the frame size requires */
+    int32_t  complexity_analysis;
+    int32_t  metadata_cadence; /* HDR metadata OBUs: 0 = every frame, 1 = keyframes, 2 = once */
+    uint32_t speed; /* 0 = run every block search (default), up to 2 = fastest */
+    uint32_t intra_frame_budget; /* 0 = off, else every frame a keyframe of at most this many bytes */
+    int32_t  tune; /* 0 = default, 1 = screen content, 2 = animation */
+    uint32_t frame_slices; /* 0 = whole-frame packets, else one packet per tile row (at least this many) */
+    int32_t  aq_mode; /* 0 = off, 1 = cyclic refresh of a rotating tenth of each inter frame */
+    int32_t  projection; /* 0 = flat, 1 = equirectangular 360 (coarser q towards the poles) */
+    uint32_t first_pass_scale; /* complexity analysis downscale: 1 = full resolution (default), up to 8 */
+    int32_t  closed_gop; /* 1 = keyframes start a mini-GOP (default); 0 = open GOP with b_frames */
+    int32_t  distortion_maps; /* 1 = per-superblock luma distortion, see wav1c_encoder_receive_distortion_map */
+    uint32_t max_frame_bytes; /* 0 = off, else no packet larger than this many bytes (b_frames and frame_slices off) */
+    uint32_t tile_rows; /* 0 = only the tile rows the frame size requires */
+    uint32_t threads; /* threads coding the tiles of a frame at once; 0 or 1 = one at a time */
+    int32_t  chroma_sample_position; /* 0 = unknown, 1 = vertical (left), 2 = colocated (top-left) */
 } Wav1cConfig;
 
+typedef struct {
+    uint64_t memory_bytes; /* worst-case frame buffer bytes held at once */
+    uint32_t latency_frames; /* frames sent after a frame before its packet is out */
+    uint32_t header_bytes; /* size of wav1c_encoder_headers */
+} Wav1cPreflightReport;
+
+/* Hard limits of the encoder (wav1c_limits). */
+typedef struct {
+    uint32_t max_frame_dimension; /* widest and tallest frame */
+    uint32_t max_tile_columns;
+    uint32_t max_tile_rows;
+    uint32_t max_references; /* reference frames an inter block chooses between */
+    uint32_t max_q_idx;
+    uint32_t max_speed;
+    uint32_t max_mv_search_range;
+    uint32_t max_first_pass_scale;
+    uint32_t bit_depths; /* bit n set when bit_depth n is supported */
+} Wav1cLimits;
+
+/* Picture size and sample rate limits of one AV1 level (wav1c_level_limits). */
+typedef struct {
+    uint32_t seq_level_idx;
+    uint32_t major; /* e.g. 5 and 1 for level 5.1 */
+    uint32_t minor;
+    uint32_t max_width;
+    uint32_t max_height;
+    uint64_t max_pic_size; /* luma samples per frame */
+    uint64_t max_display_rate; /* luma samples shown per second */
+    uint64_t max_decode_rate; /* luma samples decoded per second */
+} Wav1cLevelLimits;
+
 typedef struct {
     uint64_t target_bitrate;
     uint64_t frames_encoded;
@@ -59,15 +150,82 @@ typedef struct {
     uint8_t  avg_qp;
 } Wav1cRateControlStats;
 
+typedef struct {
+    uint64_t requantized; /* frames re-encoded at a coarser quantizer to fit */
+    uint64_t repeated; /* inter frames coded as a repeat of the previous frame */
+    uint64_t flattened; /* frames coded as a flat keyframe of the source's mean colour */
+} Wav1cFrameSizeCapStats;
+
+typedef struct {
+    uint64_t frame_number;
+    uint64_t intra_cost;
+    uint64_t inter_cost; /* 0 when has_inter is 0 */
+    uint32_t motion_magnitude; /* mean |mv| per block, 1/8 pel */
+    uint32_t blocks;
+    int32_t  has_inter;
+} Wav1cFrameComplexity;
+
+/* Luma distortion of one coded frame against its source. The arrays hold
+ * sb_cols * sb_rows 64x64 superblocks in raster order. */
+typedef struct {
+    uint64_t frame_number;
+    uint32_t sb_cols;
+    uint32_t sb_rows;
+    const uint64_t *sse; /* sum of squared errors */
+    const uint32_t *samples; /* luma samples inside the frame */
+    const double *psnr; /* dB, 100 for an exact match */
+    uint16_t max_value; /* peak sample value, 255 or 1023 */
+} Wav1cDistortionMap;
+
 Wav1cConfig wav1c_default_config(void);
 const char *wav1c_last_error_message(void);
 
+enum {
+    WAV1C_CICP_COLOR_PRIMARIES = 0,
+    WAV1C_CICP_TRANSFER = 1,
+    WAV1C_CICP_MATRIX = 2
+};
+
+/* Limits to check user input against before creating an encoder. */
+Wav1cLimits wav1c_limits(void);
+
+/* Fills out_level with the limits of seq_level_idx (13 for 5.1 up to 19 for
+ * 6.3). Returns 0, or -1 for a level the encoder never signals. */
+int wav1c_level_limits(uint32_t seq_level_idx, Wav1cLevelLimits *out_level);
+
+/* seq_level_idx an encoder of this size and frame rate signals: the lowest
+ * level that holds it, 31 when none does, or -1 for a zero frame rate. */
+int wav1c_level_for(uint32_t width, uint32_t height, uint32_t fps_num, uint32_t fps_den);
+
+/* Sets bit_depth, color_range and the CICP triple of cfg from a preset:
+ * "sdr709", "srgb-full", "hdr10" or "hlg" (HDR presets are limited range).
+ * Returns 0, or -1 for an unknown name. */
+int wav1c_config_set_signal(Wav1cConfig *cfg, const char *name);
+
+/* Code point (0-255) for a CICP name such as "bt709", "smpte2084" or
+ * "bt2020nc", or a decimal number; -1 if the name is unknown. */
+int wav1c_cicp_code_point(int field, const char *name);
+
 Wav1cEncoder *wav1c_encoder_new(uint32_t width, uint32_t height, const Wav1cConfig *cfg);
 
+/* Checks width, height and cfg exactly as wav1c_encoder_new would, without
+ * creating an encoder, and fills out_report. Returns 0, -1 (invalid
+ * argument, dimensions or config) or -3 (frame buffers cannot be
+ * allocated); the reason is in wav1c_last_error_message. */
+int wav1c_encoder_preflight(uint32_t width, uint32_t height, const Wav1cConfig *cfg,
+                            Wav1cPreflightReport *out_report);
+
 void wav1c_encoder_free(Wav1cEncoder *enc);
 
+/* Sequence header OBU followed by any CLL/MDCV metadata OBUs (configOBUs).
+ * The buffer is owned by enc and valid until the next call. */
 size_t wav1c_encoder_headers(Wav1cEncoder *enc, const uint8_t **out_data);
 
+/* AV1CodecConfigurationRecord for an ISOBMFF av1C box or Matroska
+ * CodecPrivate: the four fixed bytes, then the configOBUs above. Owned by
+ * enc and valid until the next call. */
+size_t wav1c_encoder_av1c(Wav1cEncoder *enc, const uint8_t **out_data);
+
 int wav1c_encoder_send_frame(Wav1cEncoder *enc,
                              const uint8_t *y, size_t y_len,
                              const uint8_t *u, size_t u_len,
@@ -78,14 +236,147 @@ int wav1c_encoder_send_frame_u16(Wav1cEncoder *enc,
                                  const uint16_t *u, size_t u_len,
                                  const uint16_t *v, size_t v_len,
                                  int y_stride, int uv_stride);
+int wav1c_encoder_send_frame_with_user_data(Wav1cEncoder *enc,
+                                            const uint8_t *y, size_t y_len,
+                                            const uint8_t *u, size_t u_len,
+                                            const uint8_t *v, size_t v_len,
+                                            int y_stride, int uv_stride,
+                                            void *user_data);
+int wav1c_encoder_send_frame_u16_with_user_data(Wav1cEncoder *enc,
+                                                const uint16_t *y, size_t y_len,
+                                                const uint16_t *u, size_t u_len,
+                                                const uint16_t *v, size_t v_len,
+                                                int y_stride, int uv_stride,
+                                                void *user_data);
+
+/* 8-bit semi-planar 4:2:0 as delivered by capture hardware: a luma plane
+ * and one plane of ceil(height/2) rows of ceil(width/2) interleaved chroma
+ * pairs, U/V for NV12 and V/U for NV21. The pairs are deinterleaved while
+ * packing, so no conversion pass is needed. Strides are in bytes; 0 or less
+ * means packed rows. Returns the same codes as wav1c_encoder_send_frame. */
+int wav1c_encoder_send_frame_nv12(Wav1cEncoder *enc,
+                                  const uint8_t *y, size_t y_len,
+                                  const uint8_t *uv, size_t uv_len,
+                                  int y_stride, int uv_stride);
+int wav1c_encoder_send_frame_nv12_with_user_data(Wav1cEncoder *enc,
+                                                 const uint8_t *y, size_t y_len,
+                                                 const uint8_t *uv, size_t uv_len,
+                                                 int y_stride, int uv_stride,
+                                                 void *user_data);
+int wav1c_encoder_send_frame_nv21(Wav1cEncoder *enc,
+                                  const uint8_t *y, size_t y_len,
+                                  const uint8_t *vu, size_t vu_len,
+                                  int y_stride, int vu_stride);
+int wav1c_encoder_send_frame_nv21_with_user_data(Wav1cEncoder *enc,
+                                                 const uint8_t *y, size_t y_len,
+                                                 const uint8_t *vu, size_t vu_len,
+                                                 int y_stride, int vu_stride,
+                                                 void *user_data);
+
+/* Packed 8-bit RGBA or BGRA pixels (alpha ignored), converted internally
+ * to 4:2:0 with the matrix, range and bit depth of the encoder's signal:
+ * each 2x2 block's chroma is averaged. Unspecified matrix coefficients
+ * convert as BT.601; matrices other than BT.709, FCC, BT.601, SMPTE 240M
+ * and BT.2020 NCL return -1. `stride` is in bytes; 0 or less means packed
+ * rows. Returns the same codes as wav1c_encoder_send_frame. */
+int wav1c_encoder_send_frame_rgba(Wav1cEncoder *enc,
+                                  const uint8_t *data, size_t len,
+                                  int stride);
+int wav1c_encoder_send_frame_rgba_with_user_data(Wav1cEncoder *enc,
+                                                 const uint8_t *data, size_t len,
+                                                 int stride, void *user_data);
+int wav1c_encoder_send_frame_bgra(Wav1cEncoder *enc,
+                                  const uint8_t *data, size_t len,
+                                  int stride);
+int wav1c_encoder_send_frame_bgra_with_user_data(Wav1cEncoder *enc,
+                                                 const uint8_t *data, size_t len,
+                                                 int stride, void *user_data);
 
+/* Sets the base q index (1-255) of the next frame sent, overriding rate
+ * control for that frame only; frame types and references are unchanged.
+ * -1 clears a pending override. The override is consumed when a send
+ * succeeds; a failed send keeps it. Returns 0 or -1 (invalid argument). */
+int wav1c_encoder_set_next_frame_qindex(Wav1cEncoder *enc, int qindex);
+
+/* Capture timestamp in nanoseconds (e.g. CLOCK_MONOTONIC from a V4L2
+ * buffer) of the next frame sent. The encoder maps it to Wav1cPacket.pts in
+ * frame periods, measured from an anchor frame so that capture jitter does
+ * not accumulate; pts are strictly increasing. Frames sent without one are
+ * one period after the previous frame. -1 clears a pending timestamp; it is
+ * consumed when a send succeeds. Returns 0 or -1 (invalid argument). */
+int wav1c_encoder_set_next_frame_capture_time(Wav1cEncoder *enc, int64_t capture_time_ns);
+
+/* Codes the next frame sent as an INTRA_ONLY frame: predicted from nothing
+ * like a keyframe, but only the reference the following frames use is
+ * replaced and the decoder is not reset, so it recovers quality mid-GOP
+ * (e.g. after reported loss) without a new random access point. A frame that
+ * is a keyframe anyway stays one. Returns 0, -1 (b_frames on) or -5 (after
+ * flush or abort). */
+int wav1c_encoder_request_intra_refresh(Wav1cEncoder *enc);
+
+/* Next temporal unit, with exactly one shown frame: a hidden frame is held
+ * back and returned with the packet that follows it. NULL when none is
+ * ready. */
 Wav1cPacket *wav1c_encoder_receive_packet(Wav1cEncoder *enc);
 
 void wav1c_packet_free(Wav1cPacket *pkt);
 
+/* Encodes buffered frames and ends the stream; calling it again is a no-op. */
 void wav1c_encoder_flush(Wav1cEncoder *enc);
+
+/* Ends the stream, discarding buffered frames and unreceived packets. */
+void wav1c_encoder_abort(Wav1cEncoder *enc);
+
+enum {
+    WAV1C_STATE_IDLE = 0,     /* no frame sent yet */
+    WAV1C_STATE_ENCODING = 1,
+    WAV1C_STATE_DRAINING = 2, /* flushed, packets remain */
+    WAV1C_STATE_FINISHED = 3  /* every packet received, or aborted */
+};
+
+int wav1c_encoder_state(const Wav1cEncoder *enc);
+
+/* Applies new settings to frames sent from now on. The video signal must not
+ * change. If the sequence header changes, the next packet is a keyframe with
+ * sequence_header_changed set. */
+int wav1c_encoder_reconfigure(Wav1cEncoder *enc, const Wav1cConfig *cfg);
+
+enum {
+    WAV1C_PIXEL_FORMAT_I420 = 0,    /* wav1c_encoder_send_frame */
+    WAV1C_PIXEL_FORMAT_I420_10 = 1, /* wav1c_encoder_send_frame_u16 */
+    WAV1C_PIXEL_FORMAT_NV12 = 2,
+    WAV1C_PIXEL_FORMAT_NV21 = 3,
+    WAV1C_PIXEL_FORMAT_RGBA = 4,
+    WAV1C_PIXEL_FORMAT_BGRA = 5
+};
+
+/* Input formats the encoder accepts given its bit depth and matrix: YUV
+ * 4:2:0 at exactly the configured bit depth, plus RGBA/BGRA when the
+ * matrix can convert them. Writes up to capacity WAV1C_PIXEL_FORMAT_* codes
+ * to out_formats and returns the total count, so a call with NULL, 0 sizes
+ * the array. */
+size_t wav1c_encoder_supported_input_formats(const Wav1cEncoder *enc,
+                                             int32_t *out_formats, size_t capacity);
+/* FFmpeg pix_fmt name ("yuv420p", "nv12", ...) of a WAV1C_PIXEL_FORMAT_*
+ * code, or NULL. The string is static. */
+const char *wav1c_pixel_format_name(int format);
+
+/* Frames sent but not yet encoded, and encoded packets not yet received. */
+size_t wav1c_encoder_frames_in_flight(const Wav1cEncoder *enc);
+size_t wav1c_encoder_pending_packets(const Wav1cEncoder *enc);
+
 int wav1c_encoder_rate_control_stats(const Wav1cEncoder *enc, Wav1cRateControlStats *out_stats);
 
+/* What cfg.max_frame_bytes did to keep packets under the cap so far. */
+int wav1c_encoder_frame_size_cap_stats(const Wav1cEncoder *enc, Wav1cFrameSizeCapStats *out_stats);
+
+int wav1c_encoder_receive_frame_complexity(Wav1cEncoder *enc, Wav1cFrameComplexity *out_complexity);
+
+/* Next map in coding order when cfg.distortion_maps is set, or NULL.
+ * Free it with wav1c_distortion_map_free. */
+Wav1cDistortionMap *wav1c_encoder_receive_distortion_map(Wav1cEncoder *enc);
+void wav1c_distortion_map_free(Wav1cDistortionMap *map);
+
 #ifdef __cplusplus
 }
 #endif
@@ -1,11 +1,29 @@
 #![allow(clippy::missing_safety_doc)]
 
 use std::cell::RefCell;
-use std::ffi::c_char;
+use std::ffi::{CStr, c_char, c_void};
 use std::ptr;
 
+use wav1c::AqMode;
+use wav1c::ChromaDeltaQ;
+use wav1c::ChromaSamplePosition;
+use wav1c::CicpField;
+use wav1c::DeadZone;
 use wav1c::EncoderConfig;
+use wav1c::EncoderError;
+use wav1c::EncoderState;
 use wav1c::Fps;
+use wav1c::FrameComplexity;
+use wav1c::FrameOptions;
+use wav1c::MetadataCadence;
+use wav1c::PixelFormat;
+use wav1c::Projection;
+use wav1c::RgbFormat;
+use wav1c::SemiPlanarFormat;
+use wav1c::TemporalUnitGrouper;
+use wav1c::Tune;
+use wav1c::TwoPass;
+use wav1c::limits;
 use wav1c::packet::FrameType;
 use wav1c::rc::RateControlStats;
 use wav1c::video::{
@@ -17,6 +35,23 @@ use wav1c::y4m::FramePixels;
 const WAV1C_STATUS_OK: i32 = 0;
 const WAV1C_STATUS_INVALID_ARGUMENT: i32 = -1;
 const WAV1C_STATUS_ENCODE_FAILED: i32 = -3;
+const WAV1C_STATUS_FRAME_MISMATCH: i32 = -4;
+const WAV1C_STATUS_INVALID_STATE: i32 = -5;
+
+const WAV1C_ABI_VERSION_MAJOR: u32 = 5;
+const WAV1C_ABI_VERSION_MINOR: u32 = 0;
+
+const WAV1C_FEATURE_10BIT: u32 = 1 << 0;
+const WAV1C_FEATURE_THREADS: u32 = 1 << 1;
+const WAV1C_FEATURE_SIMD: u32 = 1 << 2;
+
+const WAV1C_FRAME_TYPE_KEY: i32 = 0;
+const WAV1C_FRAME_TYPE_INTER: i32 = 1;
+const WAV1C_FRAME_TYPE_INTRA_ONLY: i32 = 2;
+const WAV1C_FRAME_TYPE_SWITCH: i32 = 3;
+const WAV1C_FRAME_TYPE_SHOW_EXISTING: i32 = 4;
+const WAV1C_FRAME_TYPE_FORWARD_KEY: i32 = 5;
+const WAV1C_FRAME_TYPE_UNKNOWN: i32 = -1;
 
 thread_local! {
     static LAST_ERROR: RefCell<Vec<u8>> = RefCell::new(vec![0]);
@@ -43,7 +78,13 @@ fn clear_last_error() {
 pub struct Wav1cEncoder {
     inner: wav1c::Encoder,
     headers_cache: Vec<u8>,
-    color_range: ColorRange,
+    av1c_cache: Vec<u8>,
+    video_signal: VideoSignal,
+    next_q_idx: Option<u8>,
+    next_capture_time_ns: Option<u64>,
+    /// Packets come out as whole temporal units, hidden frames joined to
+    /// the packet showing the next frame.
+    units: TemporalUnitGrouper,
 }
 
 #[repr(C)]
@@ -52,6 +93,12 @@ pub struct Wav1cPacket {
     pub size: usize,
     pub frame_number: u64,
     pub is_keyframe: i32,
+    pub user_data: *mut c_void,
+    pub sequence_header_changed: i32,
+    pub is_partial: i32,
+    pub pts: u64,
+    pub capture_time_ns: i64,
+    pub frame_type: i32,
 }
 
 #[repr(C)]
@@ -82,6 +129,54 @@ pub struct Wav1cConfig {
     pub white_y: u16,
     pub max_luminance: u32,
     pub min_luminance: u32,
+    pub tile_columns: u32, // 0 = only the tiles the frame size requires
+    pub complexity_analysis: i32,
+    pub metadata_cadence: i32,       // 0 every frame, 1 keyframes, 2 once
+    pub speed: u32,                  // 0 runs every search, up to 2
+    pub intra_frame_budget: u32,     // 0 off, else all keyframes of at most this many bytes
+    pub tune: i32,                   // 0 default, 1 screen content
+    pub frame_slices: u32,           // 0 whole frames, else packets per tile row
+    pub aq_mode: i32,                // 0 off, 1 cyclic refresh
+    pub projection: i32,             // 0 flat, 1 equirectangular
+    pub first_pass_scale: u32,       // analysis downscale factor, 1 full resolution, up to 8
+    pub closed_gop: i32,             // 1 closed (default), 0 open GOPs with b_frames
+    pub distortion_maps: i32,        // 1 records per-superblock luma distortion
+    pub max_frame_bytes: u32,        // 0 off, else no packet larger than this many bytes
+    pub tile_rows: u32,              // 0 = only the tile rows the frame size requires
+    pub threads: u32,                // 0 or 1 codes tiles in turn, else tiles in parallel
+    pub chroma_sample_position: i32, // 0 unknown, 1 vertical, 2 colocated
+}
+
+#[repr(C)]
+pub struct Wav1cPreflightReport {
+    pub memory_bytes: u64,
+    pub latency_frames: u32,
+    pub header_bytes: u32,
+}
+
+#[repr(C)]
+pub struct Wav1cLimits {
+    pub max_frame_dimension: u32,
+    pub max_tile_columns: u32,
+    pub max_tile_rows: u32,
+    pub max_references: u32,
+    pub max_q_idx: u32,
+    pub max_speed: u32,
+    pub max_mv_search_range: u32,
+    pub max_first_pass_scale: u32,
+    pub bit_depths: u32, // bit n set when bit_depth n is supported
+}
+
+#[repr(C)]
+pub struct Wav1cLevelLimits {
+    pub seq_level_idx: u32,
+    pub major: u32,
+    pub minor: u32,
+    pub max_width: u32,
+    pub max_height: u32,
+    pub max_pic_size: u64,
+    pub max_display_rate: u64,
+    pub max_decode_rate: u64,
 }
 
 #[repr(C)]
@@ -92,6 +187,34 @@ pub struct Wav1cRateControlStats {
     pub avg_qp: u8,
 }
 
+#[repr(C)]
+pub struct Wav1cFrameSizeCapStats {
+    pub requantized: u64,
+    pub repeated: u64,
+    pub flattened: u64,
+}
+
+#[repr(C)]
+pub struct Wav1cFrameComplexity {
+    pub frame_number: u64,
+    pub intra_cost: u64,
+    pub inter_cost: u64, // 0 when has_inter is 0
+    pub motion_magnitude: u32,
+    pub blocks: u32,
+    pub has_inter: i32,
+}
+
+#[repr(C)]
+pub struct Wav1cDistortionMap {
+    pub frame_number: u64,
+    pub sb_cols: u32,
+    pub sb_rows: u32,
+    pub sse: *const u64,
+    pub samples: *const u32,
+    pub psnr: *const f64,
+    pub max_value: u16,
+}
+
 fn parse_flag(name: &str, value: i32) -> Result<bool, String> {
     match value {
         0 => Ok(false),
@@ -108,6 +231,49 @@ fn parse_color_range(v: i32) -> Result<ColorRange, String> {
     }
 }
 
+fn parse_chroma_sample_position(v: i32) -> Result<ChromaSamplePosition, String> {
+    u8::try_from(v)
+        .ok()
+        .and_then(ChromaSamplePosition::from_code)
+        .ok_or_else(|| {
+            "chroma_sample_position must be 0 (unknown), 1 (vertical) or 2 (colocated)".to_owned()
+        })
+}
+
+fn parse_metadata_cadence(v: i32) -> Result<MetadataCadence, String> {
+    match v {
+        0 => Ok(MetadataCadence::EveryFrame),
+        1 => Ok(MetadataCadence::Keyframes),
+        2 => Ok(MetadataCadence::Once),
+        _ => Err("metadata_cadence must be 0 (every frame), 1 (keyframes) or 2 (once)".to_owned()),
+    }
+}
+
+fn parse_tune(v: i32) -> Result<Tune, String> {
+    match v {
+        0 => Ok(Tune::Default),
+        1 => Ok(Tune::Screen),
+        2 => Ok(Tune::Animation),
+        _ => Err("tune must be 0 (default), 1 (screen) or 2 (animation)".to_owned()),
+    }
+}
+
+fn parse_aq_mode(v: i32) -> Result<AqMode, String> {
+    match v {
+        0 => Ok(AqMode::Off),
+        1 => Ok(AqMode::CyclicRefresh),
+        _ => Err("aq_mode must be 0 (off) or 1 (cyclic refresh)".to_owned()),
+    }
+}
+
+fn parse_projection(v: i32) -> Result<Projection, String> {
+    match v {
+        0 => Ok(Projection::Flat),
+        1 => Ok(Projection::Equirect),
+        _ => Err("projection must be 0 (flat) or 1 (equirectangular)".to_owned()),
+    }
+}
+
 fn parse_code_point(name: &str, value: i32) -> Result<u8, String> {
     if (0..=u8::MAX as i32).contains(&value) {
         Ok(value as u8)
@@ -218,13 +384,39 @@ fn build_encoder_config(cfg: &Wav1cConfig) -> Result<EncoderConfig, String> {
         fps,
         b_frames: cfg.b_frames != 0,
         gop_size: if cfg.gop_size > 0 { cfg.gop_size } else { 3 },
+        closed_gop: cfg.closed_gop != 0,
         video_signal: VideoSignal {
             bit_depth,
             color_range,
             color_description,
+            chroma_sample_position: parse_chroma_sample_position(cfg.chroma_sample_position)?,
         },
         content_light,
         mastering_display,
+        tile_columns: cfg.tile_columns as usize,
+        tile_rows: cfg.tile_rows as usize,
+        threads: cfg.threads as usize,
+        dead_zone: DeadZone::default(),
+        complexity_analysis: cfg.complexity_analysis != 0,
+        distortion_maps: cfg.distortion_maps != 0,
+        motion_fields: false,
+        first_pass_scale: cfg.first_pass_scale,
+        chroma_delta_q: ChromaDeltaQ::default(),
+        cdef_search: false,
+        loop_restoration: false,
+        mv_search_range: wav1c::tile::DEFAULT_MV_SEARCH_RANGE,
+        me_prefilter: false,
+        metadata_cadence: parse_metadata_cadence(cfg.metadata_cadence)?,
+        build_info: false,
+        speed: u8::try_from(cfg.speed).map_err(|_| "speed must be in 0..=2".to_owned())?,
+        intra_frame_budget: (cfg.intra_frame_budget != 0).then_some(cfg.intra_frame_budget),
+        max_frame_bytes: (cfg.max_frame_bytes != 0).then_some(cfg.max_frame_bytes),
+        tune: parse_tune(cfg.tune)?,
+        frame_slices: cfg.frame_slices as usize,
+        aq_mode: parse_aq_mode(cfg.aq_mode)?,
+        projection: parse_projection(cfg.projection)?,
+        preview_q_idx: None,
+        two_pass: TwoPass::Off,
     })
 }
 
@@ -327,11 +519,98 @@ fn pack_u16_plane(
     }
 }
 
+/// The crate version as a static C string.
+#[unsafe(no_mangle)]
+pub extern "C" fn wav1c_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr().cast()
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wav1c_abi_version() -> u32 {
+    (WAV1C_ABI_VERSION_MAJOR << 16) | WAV1C_ABI_VERSION_MINOR
+}
+
+/// `WAV1C_FEATURE_*` bits. Tiles are coded on worker threads when
+/// `threads` asks for it, and this library reads no HEIC, so that bit stays
+/// clear; SIMD is set only when the kernel table for the running CPU holds a
+/// specialised kernel (never with `WAV1C_CPU_MASK=0`).
+#[unsafe(no_mangle)]
+pub extern "C" fn wav1c_build_features() -> u32 {
+    let mut features = WAV1C_FEATURE_10BIT | WAV1C_FEATURE_THREADS;
+    if wav1c::cpu::kernels().specialized != wav1c::cpu::CpuFlags::NONE {
+        features |= WAV1C_FEATURE_SIMD;
+    }
+    features
+}
+
+/// The encoder's hard limits, as in `wav1c::limits`.
+#[unsafe(no_mangle)]
+pub extern "C" fn wav1c_limits() -> Wav1cLimits {
+    Wav1cLimits {
+        max_frame_dimension: limits::MAX_FRAME_DIMENSION,
+        max_tile_columns: limits::MAX_TILE_COLUMNS,
+        max_tile_rows: limits::MAX_TILE_ROWS,
+        max_references: limits::MAX_REFERENCES,
+        max_q_idx: limits::MAX_Q_IDX as u32,
+        max_speed: limits::MAX_SPEED as u32,
+        max_mv_search_range: limits::MAX_MV_SEARCH_RANGE,
+        max_first_pass_scale: limits::MAX_FIRST_PASS_SCALE,
+        bit_depths: limits::SUPPORTED_BIT_DEPTHS
+            .iter()
+            .fold(0, |mask, depth| mask | 1 << depth.bits()),
+    }
+}
+
+/// Fills `out_level` with the limits of `seq_level_idx`. Returns
+/// `WAV1C_STATUS_INVALID_ARGUMENT` for a level the encoder never signals.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wav1c_level_limits(
+    seq_level_idx: u32,
+    out_level: *mut Wav1cLevelLimits,
+) -> i32 {
+    if out_level.is_null() {
+        set_last_error("out_level must not be null");
+        return WAV1C_STATUS_INVALID_ARGUMENT;
+    }
+    let Some(level) = u8::try_from(seq_level_idx)
+        .ok()
+        .and_then(limits::level_limits)
+    else {
+        set_last_error(format!("no limits for seq_level_idx {seq_level_idx}"));
+        return WAV1C_STATUS_INVALID_ARGUMENT;
+    };
+    let (major, minor) = level.version();
+    unsafe {
+        *out_level = Wav1cLevelLimits {
+            seq_level_idx,
+            major: major as u32,
+            minor: minor as u32,
+            max_width: level.max_width,
+            max_height: level.max_height,
+            max_pic_size: level.max_pic_size,
+            max_display_rate: level.max_display_rate,
+            max_decode_rate: level.max_decode_rate,
+        };
+    }
+    clear_last_error();
+    WAV1C_STATUS_OK
+}
+
+/// The `seq_level_idx` an encoder of this size and frame rate signals, 31
+/// when no level holds it, or -1 for a zero frame rate.
+#[unsafe(no_mangle)]
+pub extern "C" fn wav1c_level_for(width: u32, height: u32, fps_num: u32, fps_den: u32) -> i32 {
+    match Fps::new(fps_num, fps_den) {
+        Ok(fps) => limits::level_for(width, height, fps) as i32,
+        Err(_) => -1,
+    }
+}
+
 #[unsafe(no_mangle)]
 pub extern "C" fn wav1c_default_config() -> Wav1cConfig {
     Wav1cConfig {
         base_q_idx: 128,
-        keyint: 25,
+        keyint: wav1c::DEFAULT_KEYINT,
         target_bitrate: 0,
         fps_num: 25,
         fps_den: 1,
@@ -356,6 +635,22 @@ pub extern "C" fn wav1c_default_config() -> Wav1cConfig {
         white_y: 0,
         max_luminance: 0,
         min_luminance: 0,
+        tile_columns: 0,
+        complexity_analysis: 0,
+        metadata_cadence: 0,
+        speed: 0,
+        intra_frame_budget: 0,
+        tune: 0,
+        frame_slices: 0,
+        aq_mode: 0,
+        projection: 0,
+        first_pass_scale: 1,
+        closed_gop: 1,
+        distortion_maps: 0,
+        max_frame_bytes: 0,
+        tile_rows: 0,
+        threads: 0,
+        chroma_sample_position: 0,
     }
 }
 
@@ -364,6 +659,67 @@ pub extern "C" fn wav1c_last_error_message() -> *const c_char {
     LAST_ERROR.with(|slot| slot.borrow().as_ptr() as *const c_char)
 }
 
+/// Fills the signal fields of `cfg` (bit depth, range and CICP triple) from
+/// a preset name: "sdr709", "srgb-full", "hdr10" or "hlg".
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wav1c_config_set_signal(
+    cfg: *mut Wav1cConfig,
+    name: *const c_char,
+) -> i32 {
+    if cfg.is_null() || name.is_null() {
+        set_last_error("cfg and name must not be null");
+        return WAV1C_STATUS_INVALID_ARGUMENT;
+    }
+    let name = unsafe { CStr::from_ptr(name) }.to_string_lossy();
+    let signal: VideoSignal = match name.parse() {
+        Ok(signal) => signal,
+        Err(e) => {
+            set_last_error(e.to_string());
+            return WAV1C_STATUS_INVALID_ARGUMENT;
+        }
+    };
+    let cfg = unsafe { &mut *cfg };
+    cfg.bit_depth = signal.bit_depth.bits();
+    cfg.color_range = match signal.color_range {
+        ColorRange::Limited => 0,
+        ColorRange::Full => 1,
+    };
+    let cd = signal.color_description;
+    cfg.color_primaries = cd.map_or(-1, |cd| cd.color_primaries as i32);
+    cfg.transfer_characteristics = cd.map_or(-1, |cd| cd.transfer_characteristics as i32);
+    cfg.matrix_coefficients = cd.map_or(-1, |cd| cd.matrix_coefficients as i32);
+    clear_last_error();
+    WAV1C_STATUS_OK
+}
+
+/// Code point for a CICP name such as "bt709" or "smpte2084" (or a decimal
+/// number) in `field` (0 primaries, 1 transfer, 2 matrix).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wav1c_cicp_code_point(field: i32, name: *const c_char) -> i32 {
+    let Some(field) = usize::try_from(field)
+        .ok()
+        .and_then(|i| CicpField::ALL.get(i).copied())
+    else {
+        set_last_error("field must be 0 (primaries), 1 (transfer) or 2 (matrix)");
+        return WAV1C_STATUS_INVALID_ARGUMENT;
+    };
+    if name.is_null() {
+        set_last_error("name must not be null");
+        return WAV1C_STATUS_INVALID_ARGUMENT;
+    }
+    let name = unsafe { CStr::from_ptr(name) }.to_string_lossy();
+    match field.parse(&name) {
+        Some(code) => {
+            clear_last_error();
+            code as i32
+        }
+        None => {
+            set_last_error(format!("unknown {field:?} name: {name}"));
+            WAV1C_STATUS_INVALID_ARGUMENT
+        }
+    }
+}
+
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn wav1c_encoder_new(
     width: u32,
@@ -383,7 +739,7 @@ pub unsafe extern "C" fn wav1c_encoder_new(
             return ptr::null_mut();
         }
     };
-    let color_range = config.video_signal.color_range;
+    let video_signal = config.video_signal;
 
     match wav1c::Encoder::new(width, height, config) {
         Ok(inner) => {
@@ -391,7 +747,11 @@ pub unsafe extern "C" fn wav1c_encoder_new(
             Box::into_raw(Box::new(Wav1cEncoder {
                 inner,
                 headers_cache: Vec::new(),
-                color_range,
+                av1c_cache: Vec::new(),
+                video_signal,
+                next_q_idx: None,
+                next_capture_time_ns: None,
+                units: TemporalUnitGrouper::new(),
             }))
         }
         Err(e) => {
@@ -401,6 +761,85 @@ pub unsafe extern "C" fn wav1c_encoder_new(
     }
 }
 
+/// Checks `width`, `height` and `cfg` as `wav1c_encoder_new` would, without
+/// creating an encoder, and fills `out_report` with what one would need.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wav1c_encoder_preflight(
+    width: u32,
+    height: u32,
+    cfg: *const Wav1cConfig,
+    out_report: *mut Wav1cPreflightReport,
+) -> i32 {
+    if cfg.is_null() || out_report.is_null() {
+        set_last_error("cfg and out_report must not be null");
+        return WAV1C_STATUS_INVALID_ARGUMENT;
+    }
+
+    let cfg = unsafe { &*cfg };
+    let config = match build_encoder_config(cfg) {
+        Ok(config) => config,
+        Err(reason) => {
+            set_last_error(reason);
+            return WAV1C_STATUS_INVALID_ARGUMENT;
+        }
+    };
+    match wav1c::Encoder::preflight(width, height, &config) {
+        Ok(report) => {
+            unsafe {
+                *out_report = Wav1cPreflightReport {
+                    memory_bytes: report.memory_bytes,
+                    latency_frames: report.latency_frames as u32,
+                    header_bytes: report.header_bytes as u32,
+                };
+            }
+            clear_last_error();
+            WAV1C_STATUS_OK
+        }
+        Err(e) => {
+            set_last_error(e.to_string());
+            match e {
+                EncoderError::AllocationPreflightFailed { .. } => WAV1C_STATUS_ENCODE_FAILED,
+                _ => WAV1C_STATUS_INVALID_ARGUMENT,
+            }
+        }
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wav1c_encoder_reconfigure(
+    enc: *mut Wav1cEncoder,
+    cfg: *const Wav1cConfig,
+) -> i32 {
+    if enc.is_null() || cfg.is_null() {
+        set_last_error("enc and cfg must not be null");
+        return WAV1C_STATUS_INVALID_ARGUMENT;
+    }
+
+    let enc = unsafe { &mut *enc };
+    let cfg = unsafe { &*cfg };
+    let config = match build_encoder_config(cfg) {
+        Ok(config) => config,
+        Err(reason) => {
+            set_last_error(reason);
+            return WAV1C_STATUS_INVALID_ARGUMENT;
+        }
+    };
+
+    match enc.inner.reconfigure(config) {
+        Ok(()) => {
+            clear_last_error();
+            WAV1C_STATUS_OK
+        }
+        Err(e) => {
+            set_last_error(e.to_string());
+            match e {
+                EncoderError::InvalidState { .. } => WAV1C_STATUS_INVALID_STATE,
+                _ => WAV1C_STATUS_INVALID_ARGUMENT,
+            }
+        }
+    }
+}
+
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn wav1c_encoder_free(enc: *mut Wav1cEncoder) {
     if !enc.is_null() {
@@ -425,6 +864,23 @@ pub unsafe extern "C" fn wav1c_encoder_headers(
     enc.headers_cache.len()
 }
 
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wav1c_encoder_av1c(
+    enc: *mut Wav1cEncoder,
+    out_data: *mut *const u8,
+) -> usize {
+    if enc.is_null() || out_data.is_null() {
+        set_last_error("enc and out_data must not be null");
+        return 0;
+    }
+
+    let enc = unsafe { &mut *enc };
+    enc.av1c_cache = enc.inner.av1c();
+    unsafe { *out_data = enc.av1c_cache.as_ptr() };
+    clear_last_error();
+    enc.av1c_cache.len()
+}
+
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn wav1c_encoder_send_frame(
     enc: *mut Wav1cEncoder,
@@ -436,6 +892,158 @@ pub unsafe extern "C" fn wav1c_encoder_send_frame(
     v_len: usize,
     y_stride: i32,
     uv_stride: i32,
+) -> i32 {
+    unsafe { send_frame_u8_impl(enc, y, y_len, u, u_len, v, v_len, y_stride, uv_stride, None) }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wav1c_encoder_send_frame_with_user_data(
+    enc: *mut Wav1cEncoder,
+    y: *const u8,
+    y_len: usize,
+    u: *const u8,
+    u_len: usize,
+    v: *const u8,
+    v_len: usize,
+    y_stride: i32,
+    uv_stride: i32,
+    user_data: *mut c_void,
+) -> i32 {
+    unsafe {
+        send_frame_u8_impl(
+            enc,
+            y,
+            y_len,
+            u,
+            u_len,
+            v,
+            v_len,
+            y_stride,
+            uv_stride,
+            Some(user_data as usize as u64),
+        )
+    }
+}
+
+fn send_frame_status(e: &EncoderError) -> i32 {
+    match e {
+        EncoderError::DimensionMismatch { .. }
+        | EncoderError::PlaneSizeMismatch { .. }
+        | EncoderError::FrameBitDepthMismatch { .. } => WAV1C_STATUS_FRAME_MISMATCH,
+        EncoderError::InvalidState { .. } => WAV1C_STATUS_INVALID_STATE,
+        EncoderError::InvalidConfig { .. } => WAV1C_STATUS_INVALID_ARGUMENT,
+        _ => WAV1C_STATUS_ENCODE_FAILED,
+    }
+}
+
+fn send_frame_with_options(
+    enc: &mut Wav1cEncoder,
+    frame: FramePixels,
+    user_data: Option<u64>,
+) -> i32 {
+    let options = FrameOptions {
+        user_data,
+        base_q_idx: enc.next_q_idx,
+        capture_time_ns: enc.next_capture_time_ns,
+    };
+    match enc.inner.send_owned_frame(frame, options) {
+        Ok(()) => {
+            enc.next_q_idx = None;
+            enc.next_capture_time_ns = None;
+            clear_last_error();
+            WAV1C_STATUS_OK
+        }
+        Err(e) => {
+            set_last_error(e.to_string());
+            send_frame_status(&e)
+        }
+    }
+}
+
+/// Sets the base q index (1..=255) of the next frame sent, overriding rate
+/// control for that frame only; -1 clears a pending override.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wav1c_encoder_set_next_frame_qindex(
+    enc: *mut Wav1cEncoder,
+    qindex: i32,
+) -> i32 {
+    if enc.is_null() {
+        set_last_error("enc must not be null");
+        return WAV1C_STATUS_INVALID_ARGUMENT;
+    }
+    let enc = unsafe { &mut *enc };
+    enc.next_q_idx = match qindex {
+        -1 => None,
+        1..=255 => Some(qindex as u8),
+        _ => {
+            set_last_error(format!("qindex must be -1 or 1..=255, got {qindex}"));
+            return WAV1C_STATUS_INVALID_ARGUMENT;
+        }
+    };
+    clear_last_error();
+    WAV1C_STATUS_OK
+}
+
+/// Sets the capture timestamp (nanoseconds, e.g. `CLOCK_MONOTONIC` from a
+/// V4L2 buffer) of the next frame sent; -1 clears a pending one. Packets
+/// report it with the pts it maps to.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wav1c_encoder_set_next_frame_capture_time(
+    enc: *mut Wav1cEncoder,
+    capture_time_ns: i64,
+) -> i32 {
+    if enc.is_null() {
+        set_last_error("enc must not be null");
+        return WAV1C_STATUS_INVALID_ARGUMENT;
+    }
+    let enc = unsafe { &mut *enc };
+    enc.next_capture_time_ns = match capture_time_ns {
+        -1 => None,
+        0.. => Some(capture_time_ns as u64),
+        _ => {
+            set_last_error(format!(
+                "capture time must be -1 or non-negative, got {capture_time_ns}"
+            ));
+            return WAV1C_STATUS_INVALID_ARGUMENT;
+        }
+    };
+    clear_last_error();
+    WAV1C_STATUS_OK
+}
+
+/// Codes the next frame sent as an intra-only frame, refreshing quality
+/// without a keyframe. Requires `b_frames` off.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wav1c_encoder_request_intra_refresh(enc: *mut Wav1cEncoder) -> i32 {
+    if enc.is_null() {
+        set_last_error("enc must not be null");
+        return WAV1C_STATUS_INVALID_ARGUMENT;
+    }
+    let enc = unsafe { &mut *enc };
+    match enc.inner.request_intra_refresh() {
+        Ok(()) => {
+            clear_last_error();
+            WAV1C_STATUS_OK
+        }
+        Err(e) => {
+            set_last_error(e.to_string());
+            send_frame_status(&e)
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe fn send_frame_u8_impl(
+    enc: *mut Wav1cEncoder,
+    y: *const u8,
+    y_len: usize,
+    u: *const u8,
+    u_len: usize,
+    v: *const u8,
+    v_len: usize,
+    y_stride: i32,
+    uv_stride: i32,
+    user_data: Option<u64>,
 ) -> i32 {
     if enc.is_null() || y.is_null() || u.is_null() || v.is_null() {
         set_last_error("enc, y, u, and v must not be null");
@@ -492,19 +1100,10 @@ pub unsafe extern "C" fn wav1c_encoder_send_frame(
         width: width as u32,
         height: height as u32,
         bit_depth: BitDepth::Eight,
-        color_range: enc.color_range,
+        color_range: enc.video_signal.color_range,
     };
 
-    match enc.inner.send_frame(&frame) {
-        Ok(()) => {
-            clear_last_error();
-            WAV1C_STATUS_OK
-        }
-        Err(e) => {
-            set_last_error(e.to_string());
-            WAV1C_STATUS_ENCODE_FAILED
-        }
-    }
+    send_frame_with_options(enc, frame, user_data)
 }
 
 #[unsafe(no_mangle)]
@@ -518,6 +1117,51 @@ pub unsafe extern "C" fn wav1c_encoder_send_frame_u16(
     v_len: usize,
     y_stride: i32,
     uv_stride: i32,
+) -> i32 {
+    unsafe { send_frame_u16_impl(enc, y, y_len, u, u_len, v, v_len, y_stride, uv_stride, None) }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wav1c_encoder_send_frame_u16_with_user_data(
+    enc: *mut Wav1cEncoder,
+    y: *const u16,
+    y_len: usize,
+    u: *const u16,
+    u_len: usize,
+    v: *const u16,
+    v_len: usize,
+    y_stride: i32,
+    uv_stride: i32,
+    user_data: *mut c_void,
+) -> i32 {
+    unsafe {
+        send_frame_u16_impl(
+            enc,
+            y,
+            y_len,
+            u,
+            u_len,
+            v,
+            v_len,
+            y_stride,
+            uv_stride,
+            Some(user_data as usize as u64),
+        )
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe fn send_frame_u16_impl(
+    enc: *mut Wav1cEncoder,
+    y: *const u16,
+    y_len: usize,
+    u: *const u16,
+    u_len: usize,
+    v: *const u16,
+    v_len: usize,
+    y_stride: i32,
+    uv_stride: i32,
+    user_data: Option<u64>,
 ) -> i32 {
     if enc.is_null() || y.is_null() || u.is_null() || v.is_null() {
         set_last_error("enc, y, u, and v must not be null");
@@ -574,21 +1218,291 @@ pub unsafe extern "C" fn wav1c_encoder_send_frame_u16(
         width: width as u32,
         height: height as u32,
         bit_depth: BitDepth::Ten,
-        color_range: enc.color_range,
+        color_range: enc.video_signal.color_range,
     };
 
-    match enc.inner.send_frame(&frame) {
-        Ok(()) => {
-            clear_last_error();
-            WAV1C_STATUS_OK
+    send_frame_with_options(enc, frame, user_data)
+}
+
+/// Sends an 8-bit NV12 frame: a luma plane and one plane of interleaved
+/// U/V pairs. Strides are in bytes; 0 or less means packed rows.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wav1c_encoder_send_frame_nv12(
+    enc: *mut Wav1cEncoder,
+    y: *const u8,
+    y_len: usize,
+    uv: *const u8,
+    uv_len: usize,
+    y_stride: i32,
+    uv_stride: i32,
+) -> i32 {
+    unsafe {
+        send_frame_semi_planar_impl(
+            enc,
+            SemiPlanarFormat::Nv12,
+            y,
+            y_len,
+            uv,
+            uv_len,
+            y_stride,
+            uv_stride,
+            None,
+        )
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wav1c_encoder_send_frame_nv12_with_user_data(
+    enc: *mut Wav1cEncoder,
+    y: *const u8,
+    y_len: usize,
+    uv: *const u8,
+    uv_len: usize,
+    y_stride: i32,
+    uv_stride: i32,
+    user_data: *mut c_void,
+) -> i32 {
+    unsafe {
+        send_frame_semi_planar_impl(
+            enc,
+            SemiPlanarFormat::Nv12,
+            y,
+            y_len,
+            uv,
+            uv_len,
+            y_stride,
+            uv_stride,
+            Some(user_data as usize as u64),
+        )
+    }
+}
+
+/// As [`wav1c_encoder_send_frame_nv12`] with the chroma pairs stored V/U.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wav1c_encoder_send_frame_nv21(
+    enc: *mut Wav1cEncoder,
+    y: *const u8,
+    y_len: usize,
+    vu: *const u8,
+    vu_len: usize,
+    y_stride: i32,
+    vu_stride: i32,
+) -> i32 {
+    unsafe {
+        send_frame_semi_planar_impl(
+            enc,
+            SemiPlanarFormat::Nv21,
+            y,
+            y_len,
+            vu,
+            vu_len,
+            y_stride,
+            vu_stride,
+            None,
+        )
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wav1c_encoder_send_frame_nv21_with_user_data(
+    enc: *mut Wav1cEncoder,
+    y: *const u8,
+    y_len: usize,
+    vu: *const u8,
+    vu_len: usize,
+    y_stride: i32,
+    vu_stride: i32,
+    user_data: *mut c_void,
+) -> i32 {
+    unsafe {
+        send_frame_semi_planar_impl(
+            enc,
+            SemiPlanarFormat::Nv21,
+            y,
+            y_len,
+            vu,
+            vu_len,
+            y_stride,
+            vu_stride,
+            Some(user_data as usize as u64),
+        )
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe fn send_frame_semi_planar_impl(
+    enc: *mut Wav1cEncoder,
+    format: SemiPlanarFormat,
+    y: *const u8,
+    y_len: usize,
+    uv: *const u8,
+    uv_len: usize,
+    y_stride: i32,
+    uv_stride: i32,
+    user_data: Option<u64>,
+) -> i32 {
+    if enc.is_null() || y.is_null() || uv.is_null() {
+        set_last_error("enc, y, and uv must not be null");
+        return WAV1C_STATUS_INVALID_ARGUMENT;
+    }
+
+    let enc = unsafe { &mut *enc };
+    let width = enc.inner.width() as usize;
+    let height = enc.inner.height() as usize;
+    let uv_row = 2 * width.div_ceil(2);
+    let uv_h = height.div_ceil(2);
+
+    let layout = parse_stride(y_stride, width, "y").and_then(|y_stride| {
+        let uv_stride = parse_stride(uv_stride, uv_row, "uv")?;
+        validate_plane_layout(width, height, y_stride, y_len, "y")?;
+        validate_plane_layout(uv_row, uv_h, uv_stride, uv_len, "uv")?;
+        Ok((y_stride, uv_stride))
+    });
+    let (y_stride, uv_stride) = match layout {
+        Ok(strides) => strides,
+        Err(reason) => {
+            set_last_error(reason);
+            return WAV1C_STATUS_INVALID_ARGUMENT;
         }
+    };
+
+    // The layouts are validated, so the planes span at least the rows read.
+    let y_plane = unsafe { std::slice::from_raw_parts(y, (height - 1) * y_stride + width) };
+    let uv_plane = unsafe { std::slice::from_raw_parts(uv, (uv_h - 1) * uv_stride + uv_row) };
+    let frame = match FramePixels::from_semi_planar(
+        format,
+        width as u32,
+        height as u32,
+        y_plane,
+        y_stride,
+        uv_plane,
+        uv_stride,
+        enc.video_signal.color_range,
+    ) {
+        Ok(frame) => frame,
         Err(e) => {
             set_last_error(e.to_string());
-            WAV1C_STATUS_ENCODE_FAILED
+            return WAV1C_STATUS_INVALID_ARGUMENT;
         }
+    };
+
+    send_frame_with_options(enc, frame, user_data)
+}
+
+/// Sends a frame of packed 8-bit RGBA pixels, converted to 4:2:0 with the
+/// encoder's matrix, range and bit depth. `stride` is in bytes; 0 or less
+/// means packed rows.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wav1c_encoder_send_frame_rgba(
+    enc: *mut Wav1cEncoder,
+    data: *const u8,
+    len: usize,
+    stride: i32,
+) -> i32 {
+    unsafe { send_frame_rgb_impl(enc, RgbFormat::Rgba, data, len, stride, None) }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wav1c_encoder_send_frame_rgba_with_user_data(
+    enc: *mut Wav1cEncoder,
+    data: *const u8,
+    len: usize,
+    stride: i32,
+    user_data: *mut c_void,
+) -> i32 {
+    unsafe {
+        send_frame_rgb_impl(
+            enc,
+            RgbFormat::Rgba,
+            data,
+            len,
+            stride,
+            Some(user_data as usize as u64),
+        )
     }
 }
 
+/// As [`wav1c_encoder_send_frame_rgba`] with pixels stored B, G, R, A.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wav1c_encoder_send_frame_bgra(
+    enc: *mut Wav1cEncoder,
+    data: *const u8,
+    len: usize,
+    stride: i32,
+) -> i32 {
+    unsafe { send_frame_rgb_impl(enc, RgbFormat::Bgra, data, len, stride, None) }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wav1c_encoder_send_frame_bgra_with_user_data(
+    enc: *mut Wav1cEncoder,
+    data: *const u8,
+    len: usize,
+    stride: i32,
+    user_data: *mut c_void,
+) -> i32 {
+    unsafe {
+        send_frame_rgb_impl(
+            enc,
+            RgbFormat::Bgra,
+            data,
+            len,
+            stride,
+            Some(user_data as usize as u64),
+        )
+    }
+}
+
+unsafe fn send_frame_rgb_impl(
+    enc: *mut Wav1cEncoder,
+    format: RgbFormat,
+    data: *const u8,
+    len: usize,
+    stride: i32,
+    user_data: Option<u64>,
+) -> i32 {
+    if enc.is_null() || data.is_null() {
+        set_last_error("enc and data must not be null");
+        return WAV1C_STATUS_INVALID_ARGUMENT;
+    }
+
+    let enc = unsafe { &mut *enc };
+    let width = enc.inner.width() as usize;
+    let height = enc.inner.height() as usize;
+    let row = 4 * width;
+
+    let layout = parse_stride(stride, row, "rgba").and_then(|stride| {
+        validate_plane_layout(row, height, stride, len, "rgba")?;
+        Ok(stride)
+    });
+    let stride = match layout {
+        Ok(stride) => stride,
+        Err(reason) => {
+            set_last_error(reason);
+            return WAV1C_STATUS_INVALID_ARGUMENT;
+        }
+    };
+
+    // The layout is validated, so the buffer spans at least the rows read.
+    let pixels = unsafe { std::slice::from_raw_parts(data, (height - 1) * stride + row) };
+    let frame = match FramePixels::from_rgba(
+        format,
+        width as u32,
+        height as u32,
+        pixels,
+        stride,
+        &enc.video_signal,
+    ) {
+        Ok(frame) => frame,
+        Err(e) => {
+            set_last_error(e.to_string());
+            return WAV1C_STATUS_INVALID_ARGUMENT;
+        }
+    };
+
+    send_frame_with_options(enc, frame, user_data)
+}
+
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn wav1c_encoder_receive_packet(enc: *mut Wav1cEncoder) -> *mut Wav1cPacket {
     if enc.is_null() {
@@ -598,12 +1512,19 @@ pub unsafe extern "C" fn wav1c_encoder_receive_packet(enc: *mut Wav1cEncoder) ->
 
     let enc = unsafe { &mut *enc };
 
-    match enc.inner.receive_packet() {
+    let unit = std::iter::from_fn(|| enc.inner.receive_packet()).find_map(|p| enc.units.push(p));
+    match unit {
         Some(packet) => {
             clear_last_error();
-            let is_keyframe = match packet.frame_type {
-                FrameType::Key => 1,
-                FrameType::Inter => 0,
+            let is_keyframe = packet.frame_type.is_sync() as i32;
+            let frame_type = match packet.frame_type {
+                FrameType::Key => WAV1C_FRAME_TYPE_KEY,
+                FrameType::Inter => WAV1C_FRAME_TYPE_INTER,
+                FrameType::IntraOnly => WAV1C_FRAME_TYPE_INTRA_ONLY,
+                FrameType::Switch => WAV1C_FRAME_TYPE_SWITCH,
+                FrameType::ShowExisting => WAV1C_FRAME_TYPE_SHOW_EXISTING,
+                FrameType::ForwardKey => WAV1C_FRAME_TYPE_FORWARD_KEY,
+                _ => WAV1C_FRAME_TYPE_UNKNOWN,
             };
 
             let data_boxed = packet.data.into_boxed_slice();
@@ -615,6 +1536,14 @@ pub unsafe extern "C" fn wav1c_encoder_receive_packet(enc: *mut Wav1cEncoder) ->
                 size,
                 frame_number: packet.frame_number,
                 is_keyframe,
+                user_data: packet
+                    .user_data
+                    .map_or(ptr::null_mut(), |v| v as usize as *mut c_void),
+                sequence_header_changed: packet.sequence_header.is_some() as i32,
+                is_partial: packet.partial as i32,
+                pts: packet.pts,
+                capture_time_ns: packet.capture_time_ns.map_or(-1, |ns| ns as i64),
+                frame_type,
             }))
         }
         None => {
@@ -651,6 +1580,105 @@ pub unsafe extern "C" fn wav1c_encoder_flush(enc: *mut Wav1cEncoder) {
     clear_last_error();
 }
 
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wav1c_encoder_abort(enc: *mut Wav1cEncoder) {
+    if enc.is_null() {
+        set_last_error("enc must not be null");
+        return;
+    }
+
+    let enc = unsafe { &mut *enc };
+    enc.inner.abort();
+    enc.units = TemporalUnitGrouper::new();
+    clear_last_error();
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wav1c_encoder_state(enc: *const Wav1cEncoder) -> i32 {
+    if enc.is_null() {
+        set_last_error("enc must not be null");
+        return WAV1C_STATUS_INVALID_ARGUMENT;
+    }
+
+    let enc = unsafe { &*enc };
+    clear_last_error();
+    match enc.inner.state() {
+        EncoderState::Idle => 0,
+        EncoderState::Encoding => 1,
+        EncoderState::Draining => 2,
+        EncoderState::Finished => 3,
+    }
+}
+
+/// Writes up to `capacity` `WAV1C_PIXEL_FORMAT_*` codes the encoder accepts
+/// to `out_formats` and returns how many there are in total.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wav1c_encoder_supported_input_formats(
+    enc: *const Wav1cEncoder,
+    out_formats: *mut i32,
+    capacity: usize,
+) -> usize {
+    if enc.is_null() || (out_formats.is_null() && capacity > 0) {
+        set_last_error("enc must not be null, nor out_formats with a capacity");
+        return 0;
+    }
+
+    let enc = unsafe { &*enc };
+    let formats = enc.inner.supported_input_formats();
+    for (i, format) in formats.iter().take(capacity).enumerate() {
+        let code = PixelFormat::ALL.iter().position(|f| f == format).unwrap();
+        unsafe { *out_formats.add(i) = code as i32 };
+    }
+    clear_last_error();
+    formats.len()
+}
+
+/// The FFmpeg `pix_fmt` name of a `WAV1C_PIXEL_FORMAT_*` code, or null.
+#[unsafe(no_mangle)]
+pub extern "C" fn wav1c_pixel_format_name(format: i32) -> *const c_char {
+    let Some(format) = usize::try_from(format)
+        .ok()
+        .and_then(|i| PixelFormat::ALL.get(i).copied())
+    else {
+        set_last_error(format!("unknown pixel format: {format}"));
+        return ptr::null();
+    };
+    clear_last_error();
+    match format {
+        PixelFormat::I420 => c"yuv420p",
+        PixelFormat::I420P10 => c"yuv420p10le",
+        PixelFormat::Nv12 => c"nv12",
+        PixelFormat::Nv21 => c"nv21",
+        PixelFormat::Rgba => c"rgba",
+        PixelFormat::Bgra => c"bgra",
+    }
+    .as_ptr()
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wav1c_encoder_frames_in_flight(enc: *const Wav1cEncoder) -> usize {
+    if enc.is_null() {
+        set_last_error("enc must not be null");
+        return 0;
+    }
+
+    let enc = unsafe { &*enc };
+    clear_last_error();
+    enc.inner.frames_in_flight()
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wav1c_encoder_pending_packets(enc: *const Wav1cEncoder) -> usize {
+    if enc.is_null() {
+        set_last_error("enc must not be null");
+        return 0;
+    }
+
+    let enc = unsafe { &*enc };
+    clear_last_error();
+    enc.inner.pending_packets()
+}
+
 fn to_ffi_rate_control_stats(stats: RateControlStats) -> Wav1cRateControlStats {
     Wav1cRateControlStats {
         target_bitrate: stats.target_bitrate,
@@ -685,3 +1713,122 @@ pub unsafe extern "C" fn wav1c_encoder_rate_control_stats(
         }
     }
 }
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wav1c_encoder_frame_size_cap_stats(
+    enc: *const Wav1cEncoder,
+    out_stats: *mut Wav1cFrameSizeCapStats,
+) -> i32 {
+    if enc.is_null() || out_stats.is_null() {
+        set_last_error("enc and out_stats must not be null");
+        return WAV1C_STATUS_INVALID_ARGUMENT;
+    }
+
+    let enc = unsafe { &*enc };
+    let stats = enc.inner.frame_size_cap_stats();
+    unsafe {
+        *out_stats = Wav1cFrameSizeCapStats {
+            requantized: stats.requantized,
+            repeated: stats.repeated,
+            flattened: stats.flattened,
+        };
+    }
+    clear_last_error();
+    WAV1C_STATUS_OK
+}
+
+fn to_ffi_frame_complexity(c: FrameComplexity) -> Wav1cFrameComplexity {
+    Wav1cFrameComplexity {
+        frame_number: c.frame_number,
+        intra_cost: c.intra_cost,
+        inter_cost: c.inter_cost.unwrap_or(0),
+        motion_magnitude: c.motion_magnitude.unwrap_or(0),
+        blocks: c.blocks,
+        has_inter: i32::from(c.inter_cost.is_some()),
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wav1c_encoder_receive_frame_complexity(
+    enc: *mut Wav1cEncoder,
+    out_complexity: *mut Wav1cFrameComplexity,
+) -> i32 {
+    if enc.is_null() || out_complexity.is_null() {
+        set_last_error("enc and out_complexity must not be null");
+        return WAV1C_STATUS_INVALID_ARGUMENT;
+    }
+
+    let enc = unsafe { &mut *enc };
+    match enc.inner.receive_frame_complexity() {
+        Some(complexity) => {
+            unsafe {
+                *out_complexity = to_ffi_frame_complexity(complexity);
+            }
+            clear_last_error();
+            1
+        }
+        None => {
+            clear_last_error();
+            0
+        }
+    }
+}
+
+/// Moves a slice to the heap for C; freed by `free_c_slice`.
+fn into_c_slice<T>(values: Vec<T>) -> *const T {
+    Box::into_raw(values.into_boxed_slice()) as *const T
+}
+
+unsafe fn free_c_slice<T>(data: *const T, len: usize) {
+    if !data.is_null() {
+        unsafe {
+            drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+                data as *mut T,
+                len,
+            )))
+        };
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wav1c_encoder_receive_distortion_map(
+    enc: *mut Wav1cEncoder,
+) -> *mut Wav1cDistortionMap {
+    if enc.is_null() {
+        set_last_error("enc must not be null");
+        return ptr::null_mut();
+    }
+
+    let enc = unsafe { &mut *enc };
+    clear_last_error();
+    match enc.inner.receive_distortion_map() {
+        Some(map) => {
+            let psnr = map.psnr();
+            Box::into_raw(Box::new(Wav1cDistortionMap {
+                frame_number: map.frame_number,
+                sb_cols: map.sb_cols as u32,
+                sb_rows: map.sb_rows as u32,
+                sse: into_c_slice(map.sse),
+                samples: into_c_slice(map.samples),
+                psnr: into_c_slice(psnr),
+                max_value: map.max_value,
+            }))
+        }
+        None => ptr::null_mut(),
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wav1c_distortion_map_free(map: *mut Wav1cDistortionMap) {
+    if map.is_null() {
+        return;
+    }
+
+    let map = unsafe { Box::from_raw(map) };
+    let len = map.sb_cols as usize * map.sb_rows as usize;
+    unsafe {
+        free_c_slice(map.sse, len);
+        free_c_slice(map.samples, len);
+        free_c_slice(map.psnr, len);
+    }
+}
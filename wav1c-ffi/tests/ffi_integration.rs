@@ -4,10 +4,21 @@ use std::process::Command;
 use std::ptr;
 
 use wav1c_ffi::{
-    Wav1cConfig, Wav1cRateControlStats, wav1c_default_config, wav1c_encoder_flush,
-    wav1c_encoder_free, wav1c_encoder_headers, wav1c_encoder_new, wav1c_encoder_rate_control_stats,
-    wav1c_encoder_receive_packet, wav1c_encoder_send_frame, wav1c_encoder_send_frame_u16,
-    wav1c_last_error_message, wav1c_packet_free,
+    Wav1cConfig, Wav1cFrameComplexity, Wav1cFrameSizeCapStats, Wav1cLevelLimits,
+    Wav1cPreflightReport, Wav1cRateControlStats, wav1c_abi_version, wav1c_build_features,
+    wav1c_cicp_code_point, wav1c_config_set_signal, wav1c_default_config,
+    wav1c_distortion_map_free, wav1c_encoder_abort, wav1c_encoder_av1c, wav1c_encoder_flush,
+    wav1c_encoder_frame_size_cap_stats, wav1c_encoder_frames_in_flight, wav1c_encoder_free,
+    wav1c_encoder_headers, wav1c_encoder_new, wav1c_encoder_pending_packets,
+    wav1c_encoder_preflight, wav1c_encoder_rate_control_stats,
+    wav1c_encoder_receive_distortion_map, wav1c_encoder_receive_frame_complexity,
+    wav1c_encoder_receive_packet, wav1c_encoder_reconfigure, wav1c_encoder_request_intra_refresh,
+    wav1c_encoder_send_frame, wav1c_encoder_send_frame_bgra, wav1c_encoder_send_frame_nv12,
+    wav1c_encoder_send_frame_nv21, wav1c_encoder_send_frame_rgba, wav1c_encoder_send_frame_u16,
+    wav1c_encoder_send_frame_with_user_data, wav1c_encoder_set_next_frame_capture_time,
+    wav1c_encoder_set_next_frame_qindex, wav1c_encoder_state,
+    wav1c_encoder_supported_input_formats, wav1c_last_error_message, wav1c_level_for,
+    wav1c_level_limits, wav1c_limits, wav1c_packet_free, wav1c_pixel_format_name, wav1c_version,
 };
 
 fn dav1d_path() -> Option<std::path::PathBuf> {
@@ -177,6 +188,30 @@ fn headers_returns_sequence_header() {
     unsafe { wav1c_encoder_free(enc) };
 }
 
+#[test]
+fn av1c_wraps_headers_in_codec_configuration_record() {
+    let cfg = default_config();
+    let enc = unsafe { wav1c_encoder_new(64, 64, &cfg) };
+    assert!(!enc.is_null());
+
+    let mut headers: *const u8 = ptr::null();
+    let headers_len = unsafe { wav1c_encoder_headers(enc, &mut headers) };
+    let mut av1c: *const u8 = ptr::null();
+    let av1c_len = unsafe { wav1c_encoder_av1c(enc, &mut av1c) };
+    assert_eq!(av1c_len, headers_len + 4);
+
+    let headers = unsafe { std::slice::from_raw_parts(headers, headers_len) };
+    let av1c = unsafe { std::slice::from_raw_parts(av1c, av1c_len) };
+    assert_eq!(&av1c[..3], &[0x81, 0x0D, 0x0C]);
+    assert_eq!(&av1c[4..], headers);
+    assert_eq!(
+        unsafe { wav1c_encoder_av1c(ptr::null_mut(), &mut ptr::null()) },
+        0
+    );
+
+    unsafe { wav1c_encoder_free(enc) };
+}
+
 #[test]
 fn encode_and_decode_with_dav1d() {
     let Some(dav1d) = dav1d_path() else {
@@ -271,6 +306,48 @@ fn invalid_negative_color_description_returns_null() {
     assert!(last_error_message().contains("must be -1 or in 0..=255"));
 }
 
+#[test]
+fn cicp_names_map_to_code_points() {
+    let code = |field, name: &CStr| unsafe { wav1c_cicp_code_point(field, name.as_ptr()) };
+    assert_eq!(code(0, c"bt2020"), 9);
+    assert_eq!(code(1, c"smpte2084"), 16);
+    assert_eq!(code(2, c"bt2020nc"), 9);
+    assert_eq!(code(1, c"18"), 18);
+    assert_eq!(code(2, c"srgb"), -1);
+    assert!(last_error_message().contains("srgb"));
+    assert_eq!(code(3, c"bt709"), -1);
+    assert_eq!(unsafe { wav1c_cicp_code_point(0, ptr::null()) }, -1);
+}
+
+#[test]
+fn signal_preset_fills_config() {
+    let mut cfg = default_config();
+    assert_eq!(
+        unsafe { wav1c_config_set_signal(&mut cfg, c"hlg".as_ptr()) },
+        0
+    );
+    assert_eq!(cfg.bit_depth, 10);
+    assert_eq!(cfg.color_range, 0);
+    assert_eq!(
+        (
+            cfg.color_primaries,
+            cfg.transfer_characteristics,
+            cfg.matrix_coefficients
+        ),
+        (9, 18, 9)
+    );
+    let enc = unsafe { wav1c_encoder_new(64, 64, &cfg) };
+    assert!(!enc.is_null());
+    unsafe { wav1c_encoder_free(enc) };
+
+    assert_eq!(
+        unsafe { wav1c_config_set_signal(&mut cfg, c"rec709".as_ptr()) },
+        -1
+    );
+    assert!(last_error_message().contains("unknown video signal preset"));
+    assert_eq!(cfg.transfer_characteristics, 18);
+}
+
 #[test]
 fn invalid_color_range_returns_null() {
     let mut cfg = default_config();
@@ -358,3 +435,923 @@ fn rate_control_stats_available_when_bitrate_enabled() {
 
     unsafe { wav1c_encoder_free(enc) };
 }
+
+#[test]
+fn max_frame_bytes_caps_packets_and_reports_requantizing() {
+    let mut cfg = default_config();
+    cfg.max_frame_bytes = 8;
+    assert!(unsafe { wav1c_encoder_new(64, 64, &cfg) }.is_null());
+    assert!(last_error_message().contains("smallest keyframe packet"));
+
+    cfg.base_q_idx = 20;
+    cfg.max_frame_bytes = 400;
+    let enc = unsafe { wav1c_encoder_new(64, 64, &cfg) };
+    assert!(!enc.is_null());
+    let y_plane: Vec<u8> = (0..64 * 64).map(|i| (i * 7 % 251) as u8).collect();
+    let u_plane = vec![128u8; 32 * 32];
+    let v_plane = vec![128u8; 32 * 32];
+    let ret = unsafe {
+        wav1c_encoder_send_frame(
+            enc,
+            y_plane.as_ptr(),
+            y_plane.len(),
+            u_plane.as_ptr(),
+            u_plane.len(),
+            v_plane.as_ptr(),
+            v_plane.len(),
+            0,
+            0,
+        )
+    };
+    assert_eq!(ret, 0);
+    let pkt = unsafe { wav1c_encoder_receive_packet(enc) };
+    assert!(!pkt.is_null());
+    assert!(unsafe { &*pkt }.size <= 400);
+
+    let mut stats = Wav1cFrameSizeCapStats {
+        requantized: 0,
+        repeated: 0,
+        flattened: 0,
+    };
+    assert_eq!(
+        unsafe { wav1c_encoder_frame_size_cap_stats(enc, &mut stats) },
+        0
+    );
+    assert_eq!(
+        (stats.requantized, stats.repeated, stats.flattened),
+        (1, 0, 0)
+    );
+
+    unsafe { wav1c_packet_free(pkt) };
+    unsafe { wav1c_encoder_free(enc) };
+}
+
+#[test]
+fn next_frame_qindex_overrides_rate_control() {
+    let y_plane: Vec<u8> = (0..64 * 64).map(|i| (i * 7 % 251) as u8).collect();
+    let u_plane = vec![128u8; 32 * 32];
+    let v_plane = vec![128u8; 32 * 32];
+    let encode_first = |cfg: &Wav1cConfig, qindex: Option<i32>| -> Vec<u8> {
+        let enc = unsafe { wav1c_encoder_new(64, 64, cfg) };
+        assert!(!enc.is_null());
+        if let Some(q) = qindex {
+            assert_eq!(unsafe { wav1c_encoder_set_next_frame_qindex(enc, q) }, 0);
+        }
+        let ret = unsafe {
+            wav1c_encoder_send_frame(
+                enc,
+                y_plane.as_ptr(),
+                y_plane.len(),
+                u_plane.as_ptr(),
+                u_plane.len(),
+                v_plane.as_ptr(),
+                v_plane.len(),
+                0,
+                0,
+            )
+        };
+        assert_eq!(ret, 0);
+        let pkt = unsafe { wav1c_encoder_receive_packet(enc) };
+        assert!(!pkt.is_null());
+        let data = unsafe { std::slice::from_raw_parts((*pkt).data, (*pkt).size) }.to_vec();
+        unsafe {
+            wav1c_packet_free(pkt);
+            wav1c_encoder_free(enc);
+        }
+        data
+    };
+
+    let mut fixed = default_config();
+    fixed.base_q_idx = 200;
+    let mut controlled = default_config();
+    controlled.target_bitrate = 2_000_000;
+    assert_eq!(
+        encode_first(&controlled, Some(200)),
+        encode_first(&fixed, None)
+    );
+    assert_ne!(encode_first(&controlled, None), encode_first(&fixed, None));
+
+    let enc = unsafe { wav1c_encoder_new(64, 64, &controlled) };
+    for qindex in [0, 256, -2] {
+        assert_eq!(
+            unsafe { wav1c_encoder_set_next_frame_qindex(enc, qindex) },
+            -1
+        );
+    }
+    assert_eq!(unsafe { wav1c_encoder_set_next_frame_qindex(enc, -1) }, 0);
+    assert_eq!(
+        unsafe { wav1c_encoder_set_next_frame_qindex(ptr::null_mut(), 80) },
+        -1
+    );
+    unsafe { wav1c_encoder_free(enc) };
+}
+
+#[test]
+fn preflight_matches_encoder_new() {
+    let cfg = default_config();
+    let mut report = Wav1cPreflightReport {
+        memory_bytes: 0,
+        latency_frames: 0,
+        header_bytes: 0,
+    };
+    assert_eq!(
+        unsafe { wav1c_encoder_preflight(64, 64, &cfg, &mut report) },
+        0
+    );
+    let enc = unsafe { wav1c_encoder_new(64, 64, &cfg) };
+    let mut headers: *const u8 = ptr::null();
+    let headers_len = unsafe { wav1c_encoder_headers(enc, &mut headers) };
+    assert_eq!(report.header_bytes as usize, headers_len);
+    assert_eq!(report.latency_frames, 0);
+    assert!(report.memory_bytes > 0);
+    unsafe { wav1c_encoder_free(enc) };
+
+    let mut bad = default_config();
+    bad.b_frames = 1;
+    bad.frame_slices = 2;
+    assert_eq!(
+        unsafe { wav1c_encoder_preflight(64, 64, &bad, &mut report) },
+        -1
+    );
+    assert!(!wav1c_last_error_message().is_null());
+    assert_eq!(
+        unsafe { wav1c_encoder_preflight(0, 64, &cfg, &mut report) },
+        -1
+    );
+}
+
+#[test]
+fn limits_reject_what_encoder_new_rejects() {
+    let limits = wav1c_limits();
+    assert_eq!(limits.bit_depths, 1 << 8 | 1 << 10);
+    assert_eq!(limits.max_tile_columns, 64);
+    let cfg = default_config();
+    let mut report = Wav1cPreflightReport {
+        memory_bytes: 0,
+        latency_frames: 0,
+        header_bytes: 0,
+    };
+    let too_wide = limits.max_frame_dimension + 1;
+    assert_eq!(
+        unsafe { wav1c_encoder_preflight(too_wide, 64, &cfg, &mut report) },
+        -1
+    );
+
+    let mut level = Wav1cLevelLimits {
+        seq_level_idx: 0,
+        major: 0,
+        minor: 0,
+        max_width: 0,
+        max_height: 0,
+        max_pic_size: 0,
+        max_display_rate: 0,
+        max_decode_rate: 0,
+    };
+    let idx = wav1c_level_for(3840, 2160, 60, 1);
+    assert_eq!(idx, 13);
+    assert_eq!(unsafe { wav1c_level_limits(idx as u32, &mut level) }, 0);
+    assert_eq!((level.major, level.minor, level.max_width), (5, 1, 8192));
+    assert_eq!(wav1c_level_for(7680, 4320, 60, 1), 17);
+    assert_eq!(wav1c_level_for(64, 64, 30, 0), -1);
+    assert_eq!(unsafe { wav1c_level_limits(31, &mut level) }, -1);
+    assert_eq!(unsafe { wav1c_level_limits(13, ptr::null_mut()) }, -1);
+}
+
+#[test]
+fn aq_mode_accepts_cyclic_refresh_only() {
+    let mut cfg = default_config();
+    assert_eq!(cfg.aq_mode, 0);
+    cfg.aq_mode = 1;
+    let enc = unsafe { wav1c_encoder_new(64, 64, &cfg) };
+    assert!(!enc.is_null());
+    unsafe { wav1c_encoder_free(enc) };
+
+    cfg.aq_mode = 2;
+    let enc = unsafe { wav1c_encoder_new(64, 64, &cfg) };
+    assert!(enc.is_null());
+    assert!(last_error_message().contains("aq_mode"));
+}
+
+#[test]
+fn projection_accepts_flat_and_equirect() {
+    let mut cfg = default_config();
+    assert_eq!(cfg.projection, 0);
+    cfg.projection = 1;
+    let enc = unsafe { wav1c_encoder_new(64, 128, &cfg) };
+    assert!(!enc.is_null());
+    unsafe { wav1c_encoder_free(enc) };
+
+    cfg.projection = 2;
+    let enc = unsafe { wav1c_encoder_new(64, 128, &cfg) };
+    assert!(enc.is_null());
+    assert!(last_error_message().contains("projection"));
+}
+
+#[test]
+fn capture_times_map_to_packet_pts() {
+    let y_plane = vec![100u8; 64 * 64];
+    let u_plane = vec![128u8; 32 * 32];
+    let v_plane = vec![128u8; 32 * 32];
+    let cfg = default_config();
+    let enc = unsafe { wav1c_encoder_new(64, 64, &cfg) };
+    assert!(!enc.is_null());
+
+    // 25 fps with the third capture arriving a frame late, then a frame
+    // without a timestamp.
+    let mut timing = Vec::new();
+    for capture in [7_000_000_000i64, 7_041_000_000, 7_119_000_000, -1] {
+        assert_eq!(
+            unsafe { wav1c_encoder_set_next_frame_capture_time(enc, capture) },
+            0
+        );
+        let ret = unsafe {
+            wav1c_encoder_send_frame(
+                enc,
+                y_plane.as_ptr(),
+                y_plane.len(),
+                u_plane.as_ptr(),
+                u_plane.len(),
+                v_plane.as_ptr(),
+                v_plane.len(),
+                0,
+                0,
+            )
+        };
+        assert_eq!(ret, 0);
+        let pkt = unsafe { wav1c_encoder_receive_packet(enc) };
+        assert!(!pkt.is_null());
+        timing.push(unsafe { ((*pkt).pts, (*pkt).capture_time_ns) });
+        unsafe { wav1c_packet_free(pkt) };
+    }
+    assert_eq!(
+        timing,
+        [
+            (0, 7_000_000_000),
+            (1, 7_041_000_000),
+            (3, 7_119_000_000),
+            (4, -1)
+        ]
+    );
+
+    assert_eq!(
+        unsafe { wav1c_encoder_set_next_frame_capture_time(enc, -2) },
+        -1
+    );
+    unsafe { wav1c_encoder_free(enc) };
+}
+
+#[test]
+fn user_data_is_returned_on_matching_packets() {
+    let mut cfg = default_config();
+    cfg.b_frames = 1;
+    cfg.gop_size = 3;
+
+    let enc = unsafe { wav1c_encoder_new(64, 64, &cfg) };
+    assert!(!enc.is_null());
+
+    let y_plane = vec![128u8; 64 * 64];
+    let u_plane = vec![128u8; 32 * 32];
+    let v_plane = vec![128u8; 32 * 32];
+
+    let mut tags = [10u32, 11, 12, 13, 14];
+    for tag in tags.iter_mut() {
+        let ret = unsafe {
+            wav1c_encoder_send_frame_with_user_data(
+                enc,
+                y_plane.as_ptr(),
+                y_plane.len(),
+                u_plane.as_ptr(),
+                u_plane.len(),
+                v_plane.as_ptr(),
+                v_plane.len(),
+                0,
+                0,
+                tag as *mut u32 as *mut std::ffi::c_void,
+            )
+        };
+        assert_eq!(ret, 0);
+    }
+    unsafe { wav1c_encoder_flush(enc) };
+
+    let mut count = 0;
+    loop {
+        let pkt = unsafe { wav1c_encoder_receive_packet(enc) };
+        if pkt.is_null() {
+            break;
+        }
+        let packet = unsafe { &*pkt };
+        assert!(!packet.user_data.is_null());
+        let tag = unsafe { *(packet.user_data as *const u32) };
+        assert_eq!(u64::from(tag), 10 + packet.frame_number);
+        count += 1;
+        unsafe { wav1c_packet_free(pkt) };
+    }
+    assert_eq!(count, tags.len());
+
+    unsafe { wav1c_encoder_free(enc) };
+}
+
+#[test]
+fn packets_report_frame_type_codes() {
+    let mut cfg = default_config();
+    cfg.b_frames = 1;
+    cfg.gop_size = 3;
+
+    let enc = unsafe { wav1c_encoder_new(64, 64, &cfg) };
+    assert!(!enc.is_null());
+    let y_plane = vec![128u8; 64 * 64];
+    let uv_plane = vec![128u8; 32 * 32];
+    for _ in 0..4 {
+        let ret = unsafe {
+            wav1c_encoder_send_frame(
+                enc,
+                y_plane.as_ptr(),
+                y_plane.len(),
+                uv_plane.as_ptr(),
+                uv_plane.len(),
+                uv_plane.as_ptr(),
+                uv_plane.len(),
+                0,
+                0,
+            )
+        };
+        assert_eq!(ret, 0);
+    }
+    unsafe { wav1c_encoder_flush(enc) };
+
+    let mut types = Vec::new();
+    loop {
+        let pkt = unsafe { wav1c_encoder_receive_packet(enc) };
+        if pkt.is_null() {
+            break;
+        }
+        let packet = unsafe { &*pkt };
+        assert_eq!(packet.is_keyframe, (packet.frame_type == 0) as i32);
+        types.push(packet.frame_type);
+        unsafe { wav1c_packet_free(pkt) };
+    }
+    // KEY, then a mini-GOP whose hidden P-frame is shown after its B-frames.
+    assert_eq!(types, [0, 1, 4, 1]);
+
+    unsafe { wav1c_encoder_free(enc) };
+}
+
+#[test]
+fn hidden_frames_come_out_with_the_packet_showing_them() {
+    let mut cfg = default_config();
+    cfg.b_frames = 1;
+    cfg.gop_size = 2;
+
+    let enc = unsafe { wav1c_encoder_new(64, 64, &cfg) };
+    assert!(!enc.is_null());
+    let y_plane = vec![128u8; 64 * 64];
+    let uv_plane = vec![128u8; 32 * 32];
+    for _ in 0..4 {
+        let ret = unsafe {
+            wav1c_encoder_send_frame(
+                enc,
+                y_plane.as_ptr(),
+                y_plane.len(),
+                uv_plane.as_ptr(),
+                uv_plane.len(),
+                uv_plane.as_ptr(),
+                uv_plane.len(),
+                0,
+                0,
+            )
+        };
+        assert_eq!(ret, 0);
+    }
+    unsafe { wav1c_encoder_flush(enc) };
+
+    let mut packets = Vec::new();
+    loop {
+        let pkt = unsafe { wav1c_encoder_receive_packet(enc) };
+        if pkt.is_null() {
+            break;
+        }
+        let packet = unsafe { &*pkt };
+        packets.push((packet.frame_type, packet.frame_number));
+        unsafe { wav1c_packet_free(pkt) };
+    }
+    // Frame 1 is the hidden P-frame of a one-frame mini-GOP after the
+    // keyframe; it arrives with the header that shows it.
+    assert_eq!(packets, [(0, 0), (1, 1), (1, 2), (4, 3)]);
+
+    unsafe { wav1c_encoder_free(enc) };
+}
+
+#[test]
+fn intra_refresh_marks_the_next_frame_intra_only() {
+    let cfg = default_config();
+    let enc = unsafe { wav1c_encoder_new(64, 64, &cfg) };
+    assert!(!enc.is_null());
+    let y_plane = vec![128u8; 64 * 64];
+    let uv_plane = vec![128u8; 32 * 32];
+    let mut types = Vec::new();
+    for i in 0..3 {
+        if i == 1 {
+            assert_eq!(unsafe { wav1c_encoder_request_intra_refresh(enc) }, 0);
+        }
+        let ret = unsafe {
+            wav1c_encoder_send_frame(
+                enc,
+                y_plane.as_ptr(),
+                y_plane.len(),
+                uv_plane.as_ptr(),
+                uv_plane.len(),
+                uv_plane.as_ptr(),
+                uv_plane.len(),
+                0,
+                0,
+            )
+        };
+        assert_eq!(ret, 0);
+        let pkt = unsafe { wav1c_encoder_receive_packet(enc) };
+        assert!(!pkt.is_null());
+        types.push(unsafe { (*pkt).frame_type });
+        unsafe { wav1c_packet_free(pkt) };
+    }
+    assert_eq!(types, [0, 2, 1]);
+
+    unsafe { wav1c_encoder_flush(enc) };
+    assert_eq!(unsafe { wav1c_encoder_request_intra_refresh(enc) }, -5);
+    unsafe { wav1c_encoder_free(enc) };
+
+    let mut cfg = default_config();
+    cfg.b_frames = 1;
+    let enc = unsafe { wav1c_encoder_new(64, 64, &cfg) };
+    assert_eq!(unsafe { wav1c_encoder_request_intra_refresh(enc) }, -1);
+    assert!(last_error_message().contains("b_frames"));
+    unsafe { wav1c_encoder_free(enc) };
+}
+
+#[test]
+fn semi_planar_frames_encode_like_planar_ones() {
+    let cfg = default_config();
+    let y_plane: Vec<u8> = (0..64 * 64).map(|i| (i % 251) as u8).collect();
+    let u_plane: Vec<u8> = (0..32 * 32).map(|i| (i % 97) as u8 + 64).collect();
+    let v_plane: Vec<u8> = (0..32 * 32).map(|i| (i % 89) as u8 + 96).collect();
+    let packet_of = |send: &dyn Fn(*mut wav1c_ffi::Wav1cEncoder) -> i32| {
+        let enc = unsafe { wav1c_encoder_new(64, 64, &cfg) };
+        assert!(!enc.is_null());
+        assert_eq!(send(enc), 0);
+        let pkt = unsafe { wav1c_encoder_receive_packet(enc) };
+        assert!(!pkt.is_null());
+        let data = unsafe { std::slice::from_raw_parts((*pkt).data, (*pkt).size) }.to_vec();
+        unsafe { wav1c_packet_free(pkt) };
+        unsafe { wav1c_encoder_free(enc) };
+        data
+    };
+
+    let planar = packet_of(&|enc| unsafe {
+        wav1c_encoder_send_frame(
+            enc,
+            y_plane.as_ptr(),
+            y_plane.len(),
+            u_plane.as_ptr(),
+            u_plane.len(),
+            v_plane.as_ptr(),
+            v_plane.len(),
+            0,
+            0,
+        )
+    });
+    // Chroma rows padded to 80 bytes.
+    let mut uv = vec![0u8; 31 * 80 + 64];
+    let mut vu = uv.clone();
+    for i in 0..32 * 32 {
+        let at = i / 32 * 80 + i % 32 * 2;
+        uv[at..at + 2].copy_from_slice(&[u_plane[i], v_plane[i]]);
+        vu[at..at + 2].copy_from_slice(&[v_plane[i], u_plane[i]]);
+    }
+    let nv12 = packet_of(&|enc| unsafe {
+        wav1c_encoder_send_frame_nv12(
+            enc,
+            y_plane.as_ptr(),
+            y_plane.len(),
+            uv.as_ptr(),
+            uv.len(),
+            0,
+            80,
+        )
+    });
+    let nv21 = packet_of(&|enc| unsafe {
+        wav1c_encoder_send_frame_nv21(
+            enc,
+            y_plane.as_ptr(),
+            y_plane.len(),
+            vu.as_ptr(),
+            vu.len(),
+            0,
+            80,
+        )
+    });
+    assert_eq!(nv12, planar);
+    assert_eq!(nv21, planar);
+
+    let enc = unsafe { wav1c_encoder_new(64, 64, &cfg) };
+    let ret = unsafe {
+        wav1c_encoder_send_frame_nv12(
+            enc,
+            y_plane.as_ptr(),
+            y_plane.len(),
+            uv.as_ptr(),
+            uv.len() - 1,
+            0,
+            80,
+        )
+    };
+    assert_eq!(ret, -1);
+    assert!(last_error_message().contains("uv plane length too small"));
+    let ret = unsafe {
+        wav1c_encoder_send_frame_nv12(
+            enc,
+            y_plane.as_ptr(),
+            y_plane.len(),
+            uv.as_ptr(),
+            uv.len(),
+            0,
+            32,
+        )
+    };
+    assert_eq!(ret, -1);
+    assert!(last_error_message().contains("uv stride"));
+    unsafe { wav1c_encoder_free(enc) };
+}
+
+#[test]
+fn rgba_frames_are_converted_with_the_configured_signal() {
+    let rgba: Vec<u8> = (0..64 * 64)
+        .flat_map(|i| [(i % 64 * 4) as u8, (i / 64 * 4) as u8, 96, 255])
+        .collect();
+    let bgra: Vec<u8> = rgba
+        .chunks(4)
+        .flat_map(|p| [p[2], p[1], p[0], p[3]])
+        .collect();
+    let packet_of = |cfg: &Wav1cConfig, send: &dyn Fn(*mut wav1c_ffi::Wav1cEncoder) -> i32| {
+        let enc = unsafe { wav1c_encoder_new(64, 64, cfg) };
+        assert!(!enc.is_null());
+        assert_eq!(send(enc), 0, "{}", last_error_message());
+        let pkt = unsafe { wav1c_encoder_receive_packet(enc) };
+        assert!(!pkt.is_null());
+        let data = unsafe { std::slice::from_raw_parts((*pkt).data, (*pkt).size) }.to_vec();
+        unsafe { wav1c_packet_free(pkt) };
+        unsafe { wav1c_encoder_free(enc) };
+        data
+    };
+
+    let cfg = default_config();
+    let from_rgba = packet_of(&cfg, &|enc| unsafe {
+        wav1c_encoder_send_frame_rgba(enc, rgba.as_ptr(), rgba.len(), 0)
+    });
+    let from_bgra = packet_of(&cfg, &|enc| unsafe {
+        wav1c_encoder_send_frame_bgra(enc, bgra.as_ptr(), bgra.len(), 256)
+    });
+    assert_eq!(from_rgba, from_bgra);
+
+    let mut full = default_config();
+    full.color_range = 1;
+    let full_range = packet_of(&full, &|enc| unsafe {
+        wav1c_encoder_send_frame_rgba(enc, rgba.as_ptr(), rgba.len(), 0)
+    });
+    assert_ne!(full_range, from_rgba);
+
+    let enc = unsafe { wav1c_encoder_new(64, 64, &cfg) };
+    let ret = unsafe { wav1c_encoder_send_frame_rgba(enc, rgba.as_ptr(), rgba.len() - 1, 0) };
+    assert_eq!(ret, -1);
+    assert!(last_error_message().contains("rgba plane length too small"));
+    unsafe { wav1c_encoder_free(enc) };
+}
+
+#[test]
+fn supported_input_formats_follow_the_signal() {
+    let formats_of = |cfg: &Wav1cConfig| {
+        let enc = unsafe { wav1c_encoder_new(64, 64, cfg) };
+        assert!(!enc.is_null());
+        let count = unsafe { wav1c_encoder_supported_input_formats(enc, ptr::null_mut(), 0) };
+        let mut formats = vec![-1; count + 1];
+        let written =
+            unsafe { wav1c_encoder_supported_input_formats(enc, formats.as_mut_ptr(), count) };
+        assert_eq!(written, count);
+        unsafe { wav1c_encoder_free(enc) };
+        formats.truncate(count);
+        formats
+    };
+
+    assert_eq!(formats_of(&default_config()), [0, 2, 3, 4, 5]);
+    let mut ten_bit = default_config();
+    ten_bit.bit_depth = 10;
+    assert_eq!(formats_of(&ten_bit), [1, 4, 5]);
+
+    let name = unsafe { CStr::from_ptr(wav1c_pixel_format_name(2)) };
+    assert_eq!(name.to_str().unwrap(), "nv12");
+    assert!(wav1c_pixel_format_name(6).is_null());
+}
+
+#[test]
+fn version_and_features_match_the_header() {
+    let version = unsafe { CStr::from_ptr(wav1c_version()) };
+    assert_eq!(version.to_str().unwrap(), env!("CARGO_PKG_VERSION"));
+
+    let header = include_str!("../include/wav1c.h");
+    let abi = wav1c_abi_version();
+    assert!(header.contains(&format!("#define WAV1C_ABI_VERSION_MAJOR {}\n", abi >> 16)));
+    assert!(header.contains(&format!(
+        "#define WAV1C_ABI_VERSION_MINOR {}\n",
+        abi & 0xFFFF
+    )));
+
+    let features = wav1c_build_features();
+    assert_eq!(features & 1, 1, "10-bit is always built");
+    assert_eq!(features & 2, 2, "tiles can be coded on worker threads");
+    assert_eq!(features & !0b111, 0, "no HEIC or unknown bits");
+    assert_eq!(
+        features & 0b100 != 0,
+        wav1c::cpu::kernels().specialized != wav1c::cpu::CpuFlags::NONE,
+        "SIMD only with a specialised kernel"
+    );
+}
+
+#[test]
+fn queue_introspection_reports_buffered_frames() {
+    let mut cfg = default_config();
+    cfg.b_frames = 1;
+    cfg.gop_size = 3;
+
+    let enc = unsafe { wav1c_encoder_new(64, 64, &cfg) };
+    assert!(!enc.is_null());
+    assert_eq!(unsafe { wav1c_encoder_frames_in_flight(enc) }, 0);
+    assert_eq!(unsafe { wav1c_encoder_pending_packets(enc) }, 0);
+
+    let y_plane = vec![128u8; 64 * 64];
+    let uv_plane = vec![128u8; 32 * 32];
+    let ret = unsafe {
+        wav1c_encoder_send_frame(
+            enc,
+            y_plane.as_ptr(),
+            y_plane.len(),
+            uv_plane.as_ptr(),
+            uv_plane.len(),
+            uv_plane.as_ptr(),
+            uv_plane.len(),
+            0,
+            0,
+        )
+    };
+    assert_eq!(ret, 0);
+    assert_eq!(unsafe { wav1c_encoder_frames_in_flight(enc) }, 1);
+    assert_eq!(unsafe { wav1c_encoder_pending_packets(enc) }, 0);
+
+    unsafe { wav1c_encoder_flush(enc) };
+    assert_eq!(unsafe { wav1c_encoder_frames_in_flight(enc) }, 0);
+    assert_eq!(unsafe { wav1c_encoder_pending_packets(enc) }, 1);
+
+    assert_eq!(unsafe { wav1c_encoder_frames_in_flight(ptr::null()) }, 0);
+
+    unsafe { wav1c_encoder_free(enc) };
+}
+
+#[test]
+fn frame_complexity_is_reported_when_enabled() {
+    let mut cfg = default_config();
+    cfg.complexity_analysis = 1;
+
+    let enc = unsafe { wav1c_encoder_new(64, 64, &cfg) };
+    assert!(!enc.is_null());
+
+    let y_plane = vec![128u8; 64 * 64];
+    let uv_plane = vec![128u8; 32 * 32];
+    for _ in 0..2 {
+        let ret = unsafe {
+            wav1c_encoder_send_frame(
+                enc,
+                y_plane.as_ptr(),
+                y_plane.len(),
+                uv_plane.as_ptr(),
+                uv_plane.len(),
+                uv_plane.as_ptr(),
+                uv_plane.len(),
+                0,
+                0,
+            )
+        };
+        assert_eq!(ret, 0);
+    }
+
+    let mut c = unsafe { std::mem::zeroed::<Wav1cFrameComplexity>() };
+    assert_eq!(
+        unsafe { wav1c_encoder_receive_frame_complexity(enc, &mut c) },
+        1
+    );
+    assert_eq!(c.frame_number, 0);
+    assert_eq!(c.has_inter, 0);
+    assert_eq!(c.blocks, 16);
+
+    assert_eq!(
+        unsafe { wav1c_encoder_receive_frame_complexity(enc, &mut c) },
+        1
+    );
+    assert_eq!(c.frame_number, 1);
+    assert_eq!(c.has_inter, 1);
+    assert_eq!(c.inter_cost, 0);
+
+    assert_eq!(
+        unsafe { wav1c_encoder_receive_frame_complexity(enc, &mut c) },
+        0
+    );
+    assert_eq!(
+        unsafe { wav1c_encoder_receive_frame_complexity(ptr::null_mut(), &mut c) },
+        -1
+    );
+
+    unsafe { wav1c_encoder_free(enc) };
+}
+
+#[test]
+fn distortion_maps_are_reported_when_enabled() {
+    let mut cfg = default_config();
+    cfg.distortion_maps = 1;
+
+    let enc = unsafe { wav1c_encoder_new(96, 64, &cfg) };
+    assert!(!enc.is_null());
+
+    let y_plane: Vec<u8> = (0..96 * 64).map(|i| (i * 7 % 251) as u8).collect();
+    let uv_plane = vec![128u8; 48 * 32];
+    let ret = unsafe {
+        wav1c_encoder_send_frame(
+            enc,
+            y_plane.as_ptr(),
+            y_plane.len(),
+            uv_plane.as_ptr(),
+            uv_plane.len(),
+            uv_plane.as_ptr(),
+            uv_plane.len(),
+            0,
+            0,
+        )
+    };
+    assert_eq!(ret, 0);
+
+    let map = unsafe { wav1c_encoder_receive_distortion_map(enc) };
+    assert!(!map.is_null());
+    let m = unsafe { &*map };
+    assert_eq!((m.frame_number, m.sb_cols, m.sb_rows), (0, 2, 1));
+    assert_eq!(m.max_value, 255);
+    let samples = unsafe { std::slice::from_raw_parts(m.samples, 2) };
+    assert_eq!(samples, [64 * 64, 32 * 64]);
+    let sse = unsafe { std::slice::from_raw_parts(m.sse, 2) };
+    let psnr = unsafe { std::slice::from_raw_parts(m.psnr, 2) };
+    assert!(sse.iter().all(|&e| e > 0));
+    assert!(psnr.iter().all(|&p| p > 10.0 && p < 100.0));
+    unsafe { wav1c_distortion_map_free(map) };
+
+    assert!(unsafe { wav1c_encoder_receive_distortion_map(enc) }.is_null());
+    assert!(unsafe { wav1c_encoder_receive_distortion_map(ptr::null_mut()) }.is_null());
+    unsafe { wav1c_distortion_map_free(ptr::null_mut()) };
+
+    unsafe { wav1c_encoder_free(enc) };
+}
+
+#[test]
+fn reconfigure_signals_sequence_header_change_on_packet() {
+    let cfg = default_config();
+    let enc = unsafe { wav1c_encoder_new(64, 64, &cfg) };
+    assert!(!enc.is_null());
+
+    let y_plane = vec![128u8; 64 * 64];
+    let uv_plane = vec![128u8; 32 * 32];
+    let send = || unsafe {
+        wav1c_encoder_send_frame(
+            enc,
+            y_plane.as_ptr(),
+            y_plane.len(),
+            uv_plane.as_ptr(),
+            uv_plane.len(),
+            uv_plane.as_ptr(),
+            uv_plane.len(),
+            0,
+            0,
+        )
+    };
+    let receive_flags = || {
+        let pkt = unsafe { wav1c_encoder_receive_packet(enc) };
+        assert!(!pkt.is_null());
+        let flags = unsafe { ((*pkt).is_keyframe, (*pkt).sequence_header_changed) };
+        unsafe { wav1c_packet_free(pkt) };
+        flags
+    };
+
+    assert_eq!(send(), 0);
+    assert_eq!(receive_flags(), (1, 0));
+
+    let mut ten_bit = default_config();
+    ten_bit.bit_depth = 10;
+    assert!(unsafe { wav1c_encoder_reconfigure(enc, &ten_bit) } < 0);
+    assert!(last_error_message().contains("video signal"));
+
+    // A frame rate high enough to need a higher level changes the header.
+    let mut fast = default_config();
+    fast.fps_num = 300_000;
+    fast.fps_den = 1;
+    assert_eq!(unsafe { wav1c_encoder_reconfigure(enc, &fast) }, 0);
+    assert_eq!(send(), 0);
+    assert_eq!(receive_flags(), (1, 1));
+    assert_eq!(send(), 0);
+    assert_eq!(receive_flags(), (0, 0));
+
+    assert!(unsafe { wav1c_encoder_reconfigure(ptr::null_mut(), &cfg) } < 0);
+    unsafe { wav1c_encoder_free(enc) };
+}
+
+#[test]
+fn eight_bit_frame_to_ten_bit_encoder_reports_mismatch() {
+    let mut cfg = default_config();
+    cfg.bit_depth = 10;
+    let enc = unsafe { wav1c_encoder_new(64, 64, &cfg) };
+    assert!(!enc.is_null());
+
+    let y_plane = vec![128u8; 64 * 64];
+    let uv_plane = vec![128u8; 32 * 32];
+    let ret = unsafe {
+        wav1c_encoder_send_frame(
+            enc,
+            y_plane.as_ptr(),
+            y_plane.len(),
+            uv_plane.as_ptr(),
+            uv_plane.len(),
+            uv_plane.as_ptr(),
+            uv_plane.len(),
+            0,
+            0,
+        )
+    };
+    assert_eq!(ret, -4);
+    assert_eq!(
+        last_error_message(),
+        "frame bit-depth mismatch: expected 10-bit, got 8-bit"
+    );
+    assert!(unsafe { wav1c_encoder_receive_packet(enc) }.is_null());
+
+    unsafe { wav1c_encoder_free(enc) };
+}
+
+#[test]
+fn abort_ends_stream_and_rejects_later_frames() {
+    let mut cfg = default_config();
+    cfg.b_frames = 1;
+    cfg.gop_size = 3;
+    let enc = unsafe { wav1c_encoder_new(64, 64, &cfg) };
+    assert!(!enc.is_null());
+    assert_eq!(unsafe { wav1c_encoder_state(enc) }, 0);
+
+    let y_plane = vec![128u8; 64 * 64];
+    let uv_plane = vec![128u8; 32 * 32];
+    for _ in 0..2 {
+        let ret = unsafe {
+            wav1c_encoder_send_frame(
+                enc,
+                y_plane.as_ptr(),
+                y_plane.len(),
+                uv_plane.as_ptr(),
+                uv_plane.len(),
+                uv_plane.as_ptr(),
+                uv_plane.len(),
+                0,
+                0,
+            )
+        };
+        assert_eq!(ret, 0);
+    }
+    assert_eq!(unsafe { wav1c_encoder_state(enc) }, 1);
+
+    unsafe { wav1c_encoder_abort(enc) };
+    assert_eq!(unsafe { wav1c_encoder_frames_in_flight(enc) }, 0);
+    assert_eq!(unsafe { wav1c_encoder_pending_packets(enc) }, 0);
+    assert_eq!(unsafe { wav1c_encoder_state(enc) }, 3);
+
+    unsafe { wav1c_encoder_flush(enc) };
+    assert!(unsafe { wav1c_encoder_receive_packet(enc) }.is_null());
+
+    let ret = unsafe {
+        wav1c_encoder_send_frame(
+            enc,
+            y_plane.as_ptr(),
+            y_plane.len(),
+            uv_plane.as_ptr(),
+            uv_plane.len(),
+            uv_plane.as_ptr(),
+            uv_plane.len(),
+            0,
+            0,
+        )
+    };
+    assert_eq!(ret, -5);
+    assert_eq!(
+        last_error_message(),
+        "cannot send a frame while the encoder is finished"
+    );
+    assert_eq!(unsafe { wav1c_encoder_reconfigure(enc, &cfg) }, -5);
+
+    unsafe { wav1c_encoder_free(enc) };
+}
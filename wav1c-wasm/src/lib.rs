@@ -4,8 +4,10 @@ use wasm_bindgen::prelude::*;
 use wav1c::packet::FrameType;
 use wav1c::y4m::FramePixels;
 use wav1c::{
-    BitDepth, ColorDescription, ColorRange, ContentLightLevel, EncoderConfig, Fps,
-    MasteringDisplayMetadata, VideoSignal,
+    AqMode, BitDepth, ChromaDeltaQ, ChromaSamplePosition, CicpField, ColorDescription, ColorRange,
+    ContentLightLevel, DeadZone, EncoderConfig, EncoderError, Fps, FrameOptions,
+    MasteringDisplayMetadata, MetadataCadence, Projection, RgbFormat, TemporalUnitGrouper, Tune,
+    TwoPass, VideoSignal,
 };
 
 #[wasm_bindgen]
@@ -39,6 +41,83 @@ impl WasmRateControlStats {
     }
 }
 
+#[wasm_bindgen]
+pub struct WasmFrameComplexity {
+    frame_number: u64,
+    intra_cost: u64,
+    inter_cost: u64,
+    motion_magnitude: u32,
+    has_inter: bool,
+}
+
+#[wasm_bindgen]
+impl WasmFrameComplexity {
+    #[wasm_bindgen(getter)]
+    pub fn frame_number(&self) -> u64 {
+        self.frame_number
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn intra_cost(&self) -> u64 {
+        self.intra_cost
+    }
+
+    /// `0` when `has_inter` is false (first frame).
+    #[wasm_bindgen(getter)]
+    pub fn inter_cost(&self) -> u64 {
+        self.inter_cost
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn motion_magnitude(&self) -> u32 {
+        self.motion_magnitude
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn has_inter(&self) -> bool {
+        self.has_inter
+    }
+}
+
+#[wasm_bindgen]
+pub struct WasmDistortionMap {
+    frame_number: u64,
+    sb_cols: u32,
+    sb_rows: u32,
+    sse: Vec<u64>,
+    psnr: Vec<f64>,
+}
+
+#[wasm_bindgen]
+impl WasmDistortionMap {
+    #[wasm_bindgen(getter)]
+    pub fn frame_number(&self) -> u64 {
+        self.frame_number
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn sb_cols(&self) -> u32 {
+        self.sb_cols
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn sb_rows(&self) -> u32 {
+        self.sb_rows
+    }
+
+    /// Luma sum of squared errors per 64x64 superblock, raster order.
+    #[wasm_bindgen(getter)]
+    pub fn sse(&self) -> Vec<u64> {
+        self.sse.clone()
+    }
+
+    /// Luma PSNR (dB) per superblock, 100 for an exact match.
+    #[wasm_bindgen(getter)]
+    pub fn psnr(&self) -> Vec<f64> {
+        self.psnr.clone()
+    }
+}
+
 #[wasm_bindgen]
 pub struct WasmEncoder {
     encoder: wav1c::Encoder,
@@ -49,6 +128,7 @@ pub struct WasmEncoder {
     last_keyframe: bool,
     last_frame_number: u64,
     last_packet_size: usize,
+    units: TemporalUnitGrouper,
 }
 
 #[wasm_bindgen]
@@ -85,6 +165,7 @@ impl WasmEncoder {
             bit_depth: parse_bit_depth(bit_depth)?,
             color_range: parse_color_range(color_range)?,
             color_description: parse_color_description(color_primaries, transfer, matrix)?,
+            ..VideoSignal::default()
         };
         let content_light = parse_content_light(has_cll, max_cll, max_fall)?;
         let fps = Fps::new(fps_num, fps_den).map_err(|e| JsError::new(&e.to_string()))?;
@@ -100,9 +181,34 @@ impl WasmEncoder {
             fps,
             b_frames,
             gop_size,
+            closed_gop: true,
             video_signal: signal,
             content_light,
             mastering_display: None,
+            tile_columns: 0,
+            tile_rows: 0,
+            threads: 0,
+            dead_zone: DeadZone::default(),
+            complexity_analysis: false,
+            distortion_maps: false,
+            motion_fields: false,
+            first_pass_scale: 1,
+            chroma_delta_q: ChromaDeltaQ::default(),
+            cdef_search: false,
+            loop_restoration: false,
+            mv_search_range: wav1c::tile::DEFAULT_MV_SEARCH_RANGE,
+            me_prefilter: false,
+            metadata_cadence: MetadataCadence::EveryFrame,
+            build_info: false,
+            speed: 0,
+            intra_frame_budget: None,
+            max_frame_bytes: None,
+            tune: Tune::Default,
+            frame_slices: 0,
+            aq_mode: AqMode::Off,
+            projection: Projection::Flat,
+            preview_q_idx: None,
+            two_pass: TwoPass::Off,
         };
         Self::create(width, height, config)
     }
@@ -119,6 +225,7 @@ impl WasmEncoder {
             last_keyframe: false,
             last_frame_number: 0,
             last_packet_size: 0,
+            units: TemporalUnitGrouper::new(),
         })
     }
 
@@ -136,8 +243,8 @@ impl WasmEncoder {
             color_range: self.config.video_signal.color_range,
         };
         self.encoder
-            .send_frame(&frame)
-            .map_err(|e| JsError::new(&e.to_string()))?;
+            .send_owned_frame(frame, FrameOptions::default())
+            .map_err(send_error)?;
         self.frames_submitted += 1;
         Ok(())
     }
@@ -156,14 +263,29 @@ impl WasmEncoder {
             color_range: self.config.video_signal.color_range,
         };
         self.encoder
-            .send_frame(&frame)
-            .map_err(|e| JsError::new(&e.to_string()))?;
+            .send_owned_frame(frame, FrameOptions::default())
+            .map_err(send_error)?;
         self.frames_submitted += 1;
         Ok(())
     }
 
+    /// Send packed 8-bit RGBA pixels (e.g. canvas `ImageData.data`),
+    /// converted to YUV 4:2:0 with the configured matrix, range and bit
+    /// depth. Alpha is ignored.
+    pub fn encode_frame_rgba(&mut self, data: &[u8]) -> Result<(), JsError> {
+        self.encode_rgb(RgbFormat::Rgba, data)
+    }
+
+    /// As `encode_frame_rgba` with pixels stored B, G, R, A.
+    pub fn encode_frame_bgra(&mut self, data: &[u8]) -> Result<(), JsError> {
+        self.encode_rgb(RgbFormat::Bgra, data)
+    }
+
+    /// The next temporal unit, with a hidden frame joined to the packet
+    /// showing the frame after it.
     pub fn receive_packet(&mut self) -> Option<Vec<u8>> {
-        let packet = self.encoder.receive_packet()?;
+        let packet = std::iter::from_fn(|| self.encoder.receive_packet())
+            .find_map(|packet| self.units.push(packet))?;
         self.last_keyframe = matches!(packet.frame_type, FrameType::Key);
         self.last_frame_number = packet.frame_number;
         self.last_packet_size = packet.data.len();
@@ -174,10 +296,34 @@ impl WasmEncoder {
         self.encoder.flush();
     }
 
+    /// Discard buffered frames and packets that have not been received.
+    pub fn abort(&mut self) {
+        self.encoder.abort();
+        self.units = TemporalUnitGrouper::new();
+    }
+
+    /// Code the next frame as an intra-only frame (requires b_frames off).
+    pub fn request_intra_refresh(&mut self) -> Result<(), JsError> {
+        self.encoder
+            .request_intra_refresh()
+            .map_err(|e| JsError::new(&e.to_string()))
+    }
+
+    /// Let keyframes end a B-frame mini-GOP (open GOP) instead of starting
+    /// one, before first frame. Closed GOPs are the default.
+    pub fn set_closed_gop(&mut self, closed: bool) -> Result<(), JsError> {
+        self.ensure_not_started()?;
+        self.config.closed_gop = closed;
+        self.recreate_encoder()
+    }
+
     /// Apply HDR10 defaults (BT.2020 + PQ + BT.2020NC) before first frame.
     pub fn set_hdr10(&mut self, color_range: u8) -> Result<(), JsError> {
         self.ensure_not_started()?;
-        self.config.video_signal = VideoSignal::hdr10(parse_color_range(color_range)?);
+        self.config.video_signal = VideoSignal {
+            chroma_sample_position: self.config.video_signal.chroma_sample_position,
+            ..VideoSignal::hdr10(parse_color_range(color_range)?)
+        };
         self.recreate_encoder()
     }
 
@@ -198,6 +344,147 @@ impl WasmEncoder {
             bit_depth: parse_bit_depth(bit_depth)?,
             color_range: parse_color_range(color_range)?,
             color_description: parse_color_description(color_primaries, transfer, matrix)?,
+            chroma_sample_position: self.config.video_signal.chroma_sample_position,
+        };
+        self.recreate_encoder()
+    }
+
+    /// Signal where chroma samples sit relative to luma, before first frame.
+    ///
+    /// `position`: 0 = unknown, 1 = vertical (left), 2 = colocated (top-left)
+    pub fn set_chroma_sample_position(&mut self, position: u8) -> Result<(), JsError> {
+        self.ensure_not_started()?;
+        self.config.video_signal.chroma_sample_position = ChromaSamplePosition::from_code(position)
+            .ok_or_else(|| {
+                JsError::new(
+                    "chroma_sample_position must be 0 (unknown), 1 (vertical) or 2 (colocated)",
+                )
+            })?;
+        self.recreate_encoder()
+    }
+
+    /// Request at least `tile_columns` tile columns (rounded up to a power of
+    /// two) before first frame. 0 restores the minimum layout.
+    pub fn set_tile_columns(&mut self, tile_columns: u32) -> Result<(), JsError> {
+        self.ensure_not_started()?;
+        self.config.tile_columns = tile_columns as usize;
+        self.recreate_encoder()
+    }
+
+    /// Request at least `tile_rows` tile rows (rounded up to a power of two)
+    /// before first frame. 0 restores the minimum layout.
+    pub fn set_tile_rows(&mut self, tile_rows: u32) -> Result<(), JsError> {
+        self.ensure_not_started()?;
+        self.config.tile_rows = tile_rows as usize;
+        self.recreate_encoder()
+    }
+
+    /// Enable per-frame complexity scores before first frame; read them with
+    /// `receive_frame_complexity`.
+    pub fn set_complexity_analysis(&mut self, enabled: bool) -> Result<(), JsError> {
+        self.ensure_not_started()?;
+        self.config.complexity_analysis = enabled;
+        self.recreate_encoder()
+    }
+
+    /// Record per-superblock luma distortion of every coded frame before
+    /// first frame; read it with `receive_distortion_map`.
+    pub fn set_distortion_maps(&mut self, enabled: bool) -> Result<(), JsError> {
+        self.ensure_not_started()?;
+        self.config.distortion_maps = enabled;
+        self.recreate_encoder()
+    }
+
+    /// Analyse complexity on frames downscaled by `scale` (1 = full
+    /// resolution, up to 8) before first frame.
+    pub fn set_first_pass_scale(&mut self, scale: u32) -> Result<(), JsError> {
+        self.ensure_not_started()?;
+        if scale == 0 || scale > wav1c::analysis::MAX_FIRST_PASS_SCALE {
+            return Err(JsError::new("first pass scale must be in 1..=8"));
+        }
+        self.config.first_pass_scale = scale;
+        self.recreate_encoder()
+    }
+
+    /// Set the speed (0 = every block search, up to 2 = fastest) before first
+    /// frame.
+    pub fn set_speed(&mut self, speed: u8) -> Result<(), JsError> {
+        self.ensure_not_started()?;
+        if speed > wav1c::tile::MAX_SPEED {
+            return Err(JsError::new("speed must be 0, 1 or 2"));
+        }
+        self.config.speed = speed;
+        self.recreate_encoder()
+    }
+
+    /// Code every frame as a keyframe of at most `bytes` bytes (0 = off)
+    /// before first frame. Cannot be combined with a target bitrate.
+    pub fn set_intra_frame_budget(&mut self, bytes: u32) -> Result<(), JsError> {
+        self.ensure_not_started()?;
+        self.config.intra_frame_budget = (bytes != 0).then_some(bytes);
+        self.recreate_encoder()
+    }
+
+    /// Never emit a packet larger than `bytes` bytes (0 = off) before first
+    /// frame. Frames that do not fit are re-encoded coarser, and as a last
+    /// resort repeat the previous frame.
+    pub fn set_max_frame_bytes(&mut self, bytes: u32) -> Result<(), JsError> {
+        self.ensure_not_started()?;
+        self.config.max_frame_bytes = (bytes != 0).then_some(bytes);
+        self.recreate_encoder()
+    }
+
+    /// Replace the bit depth, range and colour description with a preset
+    /// ("sdr709", "srgb-full", "hdr10" or "hlg") before first frame. Frames
+    /// must then match the preset's bit depth.
+    pub fn set_signal(&mut self, name: &str) -> Result<(), JsError> {
+        self.ensure_not_started()?;
+        self.config.video_signal = name
+            .parse::<VideoSignal>()
+            .map_err(|e| JsError::new(&e.to_string()))?;
+        self.recreate_encoder()
+    }
+
+    /// Tune block decisions for a kind of content ("default", "screen" or
+    /// "animation") before first frame.
+    pub fn set_tune(&mut self, tune: &str) -> Result<(), JsError> {
+        self.ensure_not_started()?;
+        self.config.tune = Tune::from_name(tune)
+            .ok_or_else(|| JsError::new("tune must be \"default\", \"screen\" or \"animation\""))?;
+        self.recreate_encoder()
+    }
+
+    /// Choose the adaptive quantization mode ("off" or "cyclic") before
+    /// first frame.
+    pub fn set_aq_mode(&mut self, mode: &str) -> Result<(), JsError> {
+        self.ensure_not_started()?;
+        self.config.aq_mode = AqMode::from_name(mode)
+            .ok_or_else(|| JsError::new("aq mode must be \"off\" or \"cyclic\""))?;
+        self.recreate_encoder()
+    }
+
+    /// Choose the source projection ("flat" or "equirect") before first
+    /// frame.
+    pub fn set_projection(&mut self, projection: &str) -> Result<(), JsError> {
+        self.ensure_not_started()?;
+        self.config.projection = Projection::from_name(projection)
+            .ok_or_else(|| JsError::new("projection must be \"flat\" or \"equirect\""))?;
+        self.recreate_encoder()
+    }
+
+    /// Choose which packets repeat the HDR metadata OBUs before first frame:
+    /// 0 = every frame, 1 = keyframes only, 2 = first packet only.
+    pub fn set_metadata_cadence(&mut self, cadence: u8) -> Result<(), JsError> {
+        self.ensure_not_started()?;
+        self.config.metadata_cadence = match cadence {
+            0 => MetadataCadence::EveryFrame,
+            1 => MetadataCadence::Keyframes,
+            2 => MetadataCadence::Once,
+            _ => {
+                return Err(JsError::new(
+                    "metadata cadence must be 0 (every frame), 1 (keyframes) or 2 (once)",
+                ));
+            }
         };
         self.recreate_encoder()
     }
@@ -263,6 +550,18 @@ impl WasmEncoder {
         self.encoder.headers()
     }
 
+    pub fn av1c(&self) -> Vec<u8> {
+        self.encoder.av1c()
+    }
+
+    pub fn frames_in_flight(&self) -> usize {
+        self.encoder.frames_in_flight()
+    }
+
+    pub fn pending_packets(&self) -> usize {
+        self.encoder.pending_packets()
+    }
+
     pub fn width(&self) -> u32 {
         self.width
     }
@@ -282,12 +581,65 @@ impl WasmEncoder {
             })
     }
 
+    pub fn receive_frame_complexity(&mut self) -> Option<WasmFrameComplexity> {
+        self.encoder
+            .receive_frame_complexity()
+            .map(|c| WasmFrameComplexity {
+                frame_number: c.frame_number,
+                intra_cost: c.intra_cost,
+                inter_cost: c.inter_cost.unwrap_or(0),
+                motion_magnitude: c.motion_magnitude.unwrap_or(0),
+                has_inter: c.inter_cost.is_some(),
+            })
+    }
+
+    pub fn receive_distortion_map(&mut self) -> Option<WasmDistortionMap> {
+        self.encoder
+            .receive_distortion_map()
+            .map(|m| WasmDistortionMap {
+                frame_number: m.frame_number,
+                sb_cols: m.sb_cols as u32,
+                sb_rows: m.sb_rows as u32,
+                psnr: m.psnr(),
+                sse: m.sse,
+            })
+    }
+
     fn recreate_encoder(&mut self) -> Result<(), JsError> {
         self.encoder = wav1c::Encoder::new(self.width, self.height, self.config.clone())
             .map_err(|e| JsError::new(&e.to_string()))?;
         self.last_keyframe = false;
         self.last_frame_number = 0;
         self.last_packet_size = 0;
+        self.units = TemporalUnitGrouper::new();
+        Ok(())
+    }
+
+    fn encode_rgb(&mut self, format: RgbFormat, data: &[u8]) -> Result<(), JsError> {
+        let expected = (self.width as usize)
+            .checked_mul(self.height as usize)
+            .and_then(|pixels| pixels.checked_mul(4))
+            .ok_or_else(|| JsError::new("plane dimensions overflowed"))?;
+        if data.len() != expected {
+            return Err(JsError::new(&format!(
+                "invalid pixel buffer length: expected {}, got {}",
+                expected,
+                data.len()
+            )));
+        }
+        let frame = FramePixels::from_rgba(
+            format,
+            self.width,
+            self.height,
+            data,
+            0,
+            &self.config.video_signal,
+        )
+        .map_err(send_error)?;
+        self.encoder
+            .send_owned_frame(frame, FrameOptions::default())
+            .map_err(send_error)?;
+        self.frames_submitted += 1;
         Ok(())
     }
 
@@ -322,6 +674,18 @@ fn parse_bit_depth(v: u8) -> Result<BitDepth, JsError> {
     BitDepth::from_u8(v).ok_or_else(|| JsError::new("bit_depth must be 8 or 10"))
 }
 
+fn send_error(e: EncoderError) -> JsError {
+    match e {
+        EncoderError::FrameBitDepthMismatch { expected: 10, .. } => {
+            JsError::new(&format!("{e}; use encode_frame_10bit for 10-bit encoders"))
+        }
+        EncoderError::FrameBitDepthMismatch { expected: 8, .. } => {
+            JsError::new(&format!("{e}; use encode_frame for 8-bit encoders"))
+        }
+        _ => JsError::new(&e.to_string()),
+    }
+}
+
 fn parse_color_range(v: u8) -> Result<ColorRange, JsError> {
     match v {
         0 => Ok(ColorRange::Limited),
@@ -338,6 +702,26 @@ fn parse_code_point(name: &str, v: i16) -> Result<u8, JsError> {
     }
 }
 
+/// Code point for a CICP name such as "bt709" or "smpte2084" (or a decimal
+/// number), for the `color_primaries`, `transfer` and `matrix` arguments.
+/// `field` is "primaries", "transfer" or "matrix".
+#[wasm_bindgen]
+pub fn cicp_code_point(field: &str, name: &str) -> Result<u8, JsError> {
+    let field = match field {
+        "primaries" => CicpField::ColorPrimaries,
+        "transfer" => CicpField::TransferCharacteristics,
+        "matrix" => CicpField::MatrixCoefficients,
+        _ => {
+            return Err(JsError::new(
+                "field must be \"primaries\", \"transfer\" or \"matrix\"",
+            ));
+        }
+    };
+    field
+        .parse(name)
+        .ok_or_else(|| JsError::new(&format!("unknown {field:?} name: {name}")))
+}
+
 fn parse_color_description(
     color_primaries: i16,
     transfer: i16,
@@ -10,7 +10,7 @@ fn print_usage() {
     eprintln!();
     eprintln!("Options:");
     eprintln!("  --quality N      Base quantizer index (0-255, default: 128)");
-    eprintln!("  --keyint N       Keyframe interval (default: 25)");
+    eprintln!("  --keyint N       Keyframe interval (default: one second of frames)");
     eprintln!("  --b-frames       Enable B-frame encoding (experimental)");
     eprintln!("  --gop-size N     Mini-GOP size for B-frames (default: 3)");
     eprintln!("  --bitrate N      Target bitrate in kbps (enables rate control)");
@@ -63,6 +63,7 @@ fn main() {
     let output_path = &args[2];
 
     let mut config = EncodeConfig::default();
+    let mut keyint = None;
     let mut i = 3;
     while i < args.len() {
         match args[i].as_str() {
@@ -72,7 +73,7 @@ fn main() {
             }
             "--keyint" => {
                 i += 1;
-                config.keyint = args[i].parse().expect("invalid keyint value");
+                keyint = Some(args[i].parse().expect("invalid keyint value"));
             }
             "--b-frames" => {
                 config.b_frames = true;
@@ -98,6 +99,7 @@ fn main() {
         }
         i += 1;
     }
+    config.keyint = keyint.unwrap_or_else(|| wav1c::default_keyint(config.fps));
 
     println!("Loading frames from {}...", input_path);
     let frames = FramePixels::all_from_y4m_file(Path::new(input_path)).expect("Failed to load y4m");
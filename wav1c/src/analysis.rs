@@ -0,0 +1,671 @@
+use crate::dequant;
+use crate::fps::Fps;
+use crate::rc;
+use crate::satd::compute_satd;
+use crate::tile::{DEFAULT_MV_SEARCH_RANGE, estimate_global_motion, motion_search_block};
+use crate::video::{BitDepth, ColorRange};
+use crate::y4m::FramePixels;
+
+/// Block size used for complexity analysis.
+pub const ANALYSIS_BLOCK_SIZE: u32 = 16;
+
+/// Per-frame content complexity, computed on the source luma before encoding.
+///
+/// Costs are SATD sums over the full 16x16 blocks of the frame, in source
+/// sample units, so they are comparable between frames of one stream but not
+/// across resolutions or bit depths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameComplexity {
+    /// Display index of the frame, matching `Packet::frame_number`.
+    pub frame_number: u64,
+    /// Cost of predicting every block from its own mean (DC-like intra).
+    pub intra_cost: u64,
+    /// Cost of predicting every block from the previous source frame after a
+    /// full-pel motion search. `None` for the first frame.
+    pub inter_cost: Option<u64>,
+    /// Mean motion vector length (`|dx| + |dy|`) per block, in 1/8 pel.
+    /// `None` for the first frame.
+    pub motion_magnitude: Option<u32>,
+    /// Number of blocks the costs were summed over.
+    pub blocks: u32,
+}
+
+impl FrameComplexity {
+    /// Inter-to-intra cost ratio in percent; high values indicate a scene
+    /// change or content that motion compensation does not explain well.
+    pub fn inter_intra_ratio_pct(&self) -> Option<u32> {
+        let inter = self.inter_cost?;
+        if self.intra_cost == 0 {
+            return Some(if inter == 0 { 0 } else { u32::MAX });
+        }
+        Some((inter.saturating_mul(100) / self.intra_cost).min(u32::MAX as u64) as u32)
+    }
+}
+
+pub fn analyze_frame(
+    frame_number: u64,
+    pixels: &FramePixels,
+    previous: Option<&FramePixels>,
+) -> FrameComplexity {
+    let width = pixels.width;
+    let height = pixels.height;
+    let bs = ANALYSIS_BLOCK_SIZE;
+    let n = bs as usize;
+    let stride = width as usize;
+    let blocks_x = width / bs;
+    let blocks_y = height / bs;
+
+    let global_mv = previous.map(|prev| estimate_global_motion(&pixels.y, &prev.y, width, height));
+
+    let mut intra_cost = 0u64;
+    let mut inter_cost = 0u64;
+    let mut motion_sum = 0u64;
+    let mut flat = vec![0u16; n * n];
+    let mut pred = vec![0u16; n * n];
+
+    for by in 0..blocks_y {
+        for bx in 0..blocks_x {
+            let px_x = bx * bs;
+            let px_y = by * bs;
+            let offset = px_y as usize * stride + px_x as usize;
+            let src = &pixels.y[offset..];
+
+            let mut sum = 0u32;
+            for row in 0..n {
+                sum += src[row * stride..row * stride + n]
+                    .iter()
+                    .map(|&s| s as u32)
+                    .sum::<u32>();
+            }
+            flat.fill(((sum + (n * n / 2) as u32) / (n * n) as u32) as u16);
+            intra_cost += compute_satd(src, &flat, n, n, stride, n);
+
+            if let (Some(prev), Some((gx, gy))) = (previous, global_mv) {
+                let (dx, dy) = motion_search_block(
+                    &pixels.y,
+                    &prev.y,
+                    width,
+                    height,
+                    px_x,
+                    px_y,
+                    bs,
+                    &[(gx, gy)],
+                    DEFAULT_MV_SEARCH_RANGE as i32,
+                );
+                let ref_offset = (px_y as i32 + dy) as usize * stride + (px_x as i32 + dx) as usize;
+                for row in 0..n {
+                    let start = ref_offset + row * stride;
+                    pred[row * n..(row + 1) * n].copy_from_slice(&prev.y[start..start + n]);
+                }
+                inter_cost += compute_satd(src, &pred, n, n, stride, n);
+                motion_sum += (dx.unsigned_abs() + dy.unsigned_abs()) as u64 * 8;
+            }
+        }
+    }
+
+    let blocks = blocks_x * blocks_y;
+    let (inter_cost, motion_magnitude) = match previous {
+        Some(_) => (
+            Some(inter_cost),
+            Some(motion_sum.checked_div(blocks as u64).unwrap_or(0) as u32),
+        ),
+        None => (None, None),
+    };
+
+    FrameComplexity {
+        frame_number,
+        intra_cost,
+        inter_cost,
+        motion_magnitude,
+        blocks,
+    }
+}
+
+/// Largest downscale factor [`analyze_frame_scaled`] accepts.
+pub const MAX_FIRST_PASS_SCALE: u32 = 8;
+
+/// [`analyze_frame`] on frames box-downscaled by `scale` in each dimension,
+/// so 4K sources can be analysed at a fraction of the cost. Costs and the
+/// block count are multiplied by `scale * scale` and motion by `scale`, so
+/// the result stays in source units and `blocks * 256` is still the area
+/// covered. The correction is only exact on average: gradients grow steeper
+/// per block and score higher than at full resolution, while noise and fine
+/// texture are averaged away and score lower. A `scale` of 0 or 1 is the
+/// full-resolution analysis.
+pub fn analyze_frame_scaled(
+    frame_number: u64,
+    pixels: &FramePixels,
+    previous: Option<&FramePixels>,
+    scale: u32,
+) -> FrameComplexity {
+    if scale <= 1 {
+        return analyze_frame(frame_number, pixels, previous);
+    }
+    let small = downscale_luma(pixels, scale);
+    let small_previous = previous.map(|p| downscale_luma(p, scale));
+    let complexity = analyze_frame(frame_number, &small, small_previous.as_ref());
+    let area = scale * scale;
+    FrameComplexity {
+        intra_cost: complexity.intra_cost * area as u64,
+        inter_cost: complexity.inter_cost.map(|c| c * area as u64),
+        motion_magnitude: complexity.motion_magnitude.map(|m| m * scale),
+        blocks: complexity.blocks * area,
+        ..complexity
+    }
+}
+
+/// The luma of `pixels` averaged over `scale` x `scale` blocks; partial
+/// blocks at the right and bottom edges are dropped. Chroma is left flat.
+fn downscale_luma(pixels: &FramePixels, scale: u32) -> FramePixels {
+    let (width, height) = (pixels.width / scale, pixels.height / scale);
+    let mut small = FramePixels::solid_with_bit_depth(
+        width,
+        height,
+        0,
+        0,
+        0,
+        pixels.bit_depth,
+        pixels.color_range,
+    );
+    let (s, stride) = (scale as usize, pixels.width as usize);
+    let half = (s * s / 2) as u32;
+    for (i, out) in small.y.iter_mut().enumerate() {
+        let (x, y) = (i % width as usize * s, i / width as usize * s);
+        let sum: u32 = (0..s)
+            .map(|row| {
+                let start = (y + row) * stride + x;
+                pixels.y[start..start + s]
+                    .iter()
+                    .map(|&v| v as u32)
+                    .sum::<u32>()
+            })
+            .sum();
+        *out = ((sum + half) / (s * s) as u32) as u16;
+    }
+    small
+}
+
+/// Share of luma samples, in 1/1000, that must fall outside the nominal
+/// limited range before limited-tagged content is reported as full range.
+const FULL_RANGE_OUTLIER_PERMILLE: u64 = 10;
+
+/// How far (in 8-bit units) inside the nominal limited extents the luma
+/// minimum and maximum may stop and still count as reaching black and white.
+const LIMITED_EXTENT_MARGIN: u16 = 8;
+
+/// Likely disagreement between how content is tagged and what its samples
+/// look like, found by [`check_signal`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalMismatch {
+    /// Tagged limited range, but `outside_permille` / 1000 of the luma samples
+    /// lie below black (16) or above white (235).
+    FullRangeTaggedLimited { outside_permille: u32 },
+    /// Tagged full range, but luma runs from black to white of the limited
+    /// range and never leaves it, and chroma stays within 16..=240.
+    LimitedRangeTaggedFull,
+    /// 10-bit content whose samples all fit in 8 bits: 8-bit data copied into
+    /// a 10-bit container without being shifted up.
+    EightBitValuesInTenBit,
+    /// 10-bit content whose samples are all multiples of 4: 8-bit data
+    /// shifted into 10 bits, coding two bits that carry no information.
+    PaddedEightBit,
+}
+
+impl std::fmt::Display for SignalMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SignalMismatch::FullRangeTaggedLimited { outside_permille } => write!(
+                f,
+                "content is tagged limited range but {}.{}% of luma samples lie outside \
+                 the limited luma range; it is likely full range",
+                outside_permille / 10,
+                outside_permille % 10
+            ),
+            SignalMismatch::LimitedRangeTaggedFull => write!(
+                f,
+                "content is tagged full range but luma spans exactly the limited range; it is \
+                 likely limited range"
+            ),
+            SignalMismatch::EightBitValuesInTenBit => write!(
+                f,
+                "content is 10-bit but every sample is below 256; it is likely 8-bit data \
+                 that was not scaled up"
+            ),
+            SignalMismatch::PaddedEightBit => write!(
+                f,
+                "content is 10-bit but every sample is a multiple of 4; it is likely 8-bit \
+                 data and would encode as well at 8 bits"
+            ),
+        }
+    }
+}
+
+/// Compares the sample statistics of `frames` with the range they are about
+/// to be signalled with (`color_range`) and with their own bit depth, and
+/// returns every mismatch that looks likely. The checks are heuristics over
+/// histogram extents: an empty result does not prove the tagging is right,
+/// and flat or very dark content can pass either way.
+pub fn check_signal(frames: &[FramePixels], color_range: ColorRange) -> Vec<SignalMismatch> {
+    let Some(first) = frames.first() else {
+        return Vec::new();
+    };
+    let shift = first.bit_depth.bits() - 8;
+    let (black, white) = (16u16 << shift, 235u16 << shift);
+    let (chroma_low, chroma_high) = (16u16 << shift, 240u16 << shift);
+
+    let (mut luma_min, mut luma_max) = (u16::MAX, 0u16);
+    let (mut chroma_min, mut chroma_max) = (u16::MAX, 0u16);
+    let (mut luma_samples, mut luma_outside) = (0u64, 0u64);
+    let mut low_bits = 0u16;
+    for frame in frames {
+        for &s in &frame.y {
+            luma_min = luma_min.min(s);
+            luma_max = luma_max.max(s);
+            luma_outside += u64::from(s < black || s > white);
+            low_bits |= s;
+        }
+        luma_samples += frame.y.len() as u64;
+        for &s in frame.u.iter().chain(&frame.v) {
+            chroma_min = chroma_min.min(s);
+            chroma_max = chroma_max.max(s);
+            low_bits |= s;
+        }
+    }
+    if luma_samples == 0 {
+        return Vec::new();
+    }
+
+    let mut mismatches = Vec::new();
+    let outside_permille = luma_outside * 1000 / luma_samples;
+    let margin = LIMITED_EXTENT_MARGIN << shift;
+    match color_range {
+        ColorRange::Limited if outside_permille >= FULL_RANGE_OUTLIER_PERMILLE => {
+            mismatches.push(SignalMismatch::FullRangeTaggedLimited {
+                outside_permille: outside_permille as u32,
+            });
+        }
+        ColorRange::Full
+            if (black..=black + margin).contains(&luma_min)
+                && (white - margin..=white).contains(&luma_max)
+                && chroma_min >= chroma_low
+                && chroma_max <= chroma_high =>
+        {
+            mismatches.push(SignalMismatch::LimitedRangeTaggedFull);
+        }
+        _ => {}
+    }
+    if first.bit_depth == BitDepth::Ten {
+        if luma_max.max(chroma_max) <= 255 {
+            mismatches.push(SignalMismatch::EightBitValuesInTenBit);
+        } else if low_bits & 3 == 0 {
+            mismatches.push(SignalMismatch::PaddedEightBit);
+        }
+    }
+    mismatches
+}
+
+/// Inter-to-intra cost ratio, in percent, from which a frame counts as the
+/// first of a new scene: motion compensation from the previous frame saves
+/// less than a quarter of the cost of coding it on its own.
+pub const SCENE_CUT_RATIO_PCT: u32 = 75;
+
+/// Display indices of the frames in `complexity` that start a scene: the
+/// first frame and every frame whose
+/// [`FrameComplexity::inter_intra_ratio_pct`] reaches
+/// [`SCENE_CUT_RATIO_PCT`].
+pub fn scene_cuts(complexity: &[FrameComplexity]) -> Vec<u64> {
+    complexity
+        .iter()
+        .enumerate()
+        .filter(|(i, c)| {
+            *i == 0
+                || c.inter_intra_ratio_pct()
+                    .is_some_and(|pct| pct >= SCENE_CUT_RATIO_PCT)
+        })
+        .map(|(_, c)| c.frame_number)
+        .collect()
+}
+
+/// What [`suggest_settings`] recommends for a clip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SuggestedSettings {
+    /// Coarsest quantizer index expected to reach the target quality.
+    pub base_q_idx: u8,
+    /// Bitrate a fixed-quantizer encode at `base_q_idx` is expected to
+    /// produce, in bits per second.
+    pub target_bitrate: u64,
+    /// Mean scene length, clamped to one to ten seconds.
+    pub keyint: usize,
+}
+
+/// Suggests encoder settings for a clip from the complexity scores of its
+/// frames, without encoding it.
+///
+/// The quantizer is the coarsest whose step, quantizing every coefficient,
+/// leaves luma noise of `step^2 / 12` at or below `target_psnr` dB. That
+/// holds on detailed content; smooth content zeroes most coefficients and
+/// usually lands a few dB above the target. The bitrate applies rate
+/// control's keyframe size model to each frame, with the inter cost for
+/// frames within a scene and the intra cost for those starting one.
+pub fn suggest_settings(
+    complexity: &[FrameComplexity],
+    width: u32,
+    height: u32,
+    fps: Fps,
+    bit_depth: BitDepth,
+    target_psnr: f64,
+) -> SuggestedSettings {
+    let max = bit_depth.max_value() as f64;
+    let base_q_idx = (1..=255u8)
+        .rev()
+        .find(|&q| {
+            let step = dequant::lookup_dequant(q, bit_depth).ac as f64 / 8.0;
+            10.0 * (max * max * 12.0 / (step * step)).log10() >= target_psnr
+        })
+        .unwrap_or(1);
+    let qstep = dequant::lookup_dequant(base_q_idx, bit_depth).ac;
+
+    let cuts = scene_cuts(complexity);
+    let pixels = width as u128 * height as u128;
+    let total_bits: u128 = complexity
+        .iter()
+        .map(|c| {
+            let cost = match c.inter_cost {
+                Some(inter) if !cuts.contains(&c.frame_number) => inter.min(c.intra_cost),
+                _ => c.intra_cost,
+            };
+            let analyzed = c.blocks as u64 * (ANALYSIS_BLOCK_SIZE * ANALYSIS_BLOCK_SIZE) as u64;
+            let bpp = rc::keyframe_bits_per_pixel(cost, analyzed.max(1), qstep);
+            (bpp.max(0) as u128 * pixels) >> rc::FRAC_BITS
+        })
+        .sum();
+    let frames = complexity.len().max(1) as u128;
+    let target_bitrate = (total_bits * fps.num as u128 / (frames * fps.den.max(1) as u128))
+        .min(u64::MAX as u128) as u64;
+
+    let second = fps.as_f64().round().max(1.0) as usize;
+    let keyint = (complexity.len().max(1) / cuts.len().max(1)).clamp(second, 10 * second);
+
+    SuggestedSettings {
+        base_q_idx,
+        target_bitrate,
+        keyint,
+    }
+}
+
+/// Sobel gradient (`|gx| + |gy|`, 8-bit units) above which a sample counts
+/// as an edge and is left out of the noise estimate.
+const NOISE_EDGE_THRESHOLD: u32 = 50;
+
+/// Fewest smooth samples a plane needs for its noise estimate to count.
+const MIN_NOISE_SAMPLES: u64 = 256;
+
+/// Estimated noise of a source frame, as the standard deviation of the
+/// noise in each plane in sample units of the frame's bit depth. A plane is
+/// `None` when it has too few smooth samples to measure.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NoiseStats {
+    pub y: Option<f64>,
+    pub u: Option<f64>,
+    pub v: Option<f64>,
+}
+
+/// Estimates the noise of `pixels` before encoding, e.g. to choose between
+/// denoising and film grain synthesis. Each plane is filtered with a
+/// Laplacian difference kernel that cancels smooth gradients, and the mean
+/// absolute response over samples away from edges gives the deviation
+/// (Immerkaer's estimator, as libaom's `av1_estimate_noise_from_single_plane`
+/// uses it). Fine texture that is not an edge still reads as noise.
+pub fn estimate_noise(pixels: &FramePixels) -> NoiseStats {
+    let shift = u32::from(pixels.bit_depth.bits() - 8);
+    let (chroma_width, chroma_height) = (pixels.width.div_ceil(2), pixels.height.div_ceil(2));
+    NoiseStats {
+        y: plane_noise(&pixels.y, pixels.width, pixels.height, shift),
+        u: plane_noise(&pixels.u, chroma_width, chroma_height, shift),
+        v: plane_noise(&pixels.v, chroma_width, chroma_height, shift),
+    }
+}
+
+fn plane_noise(plane: &[u16], width: u32, height: u32, shift: u32) -> Option<f64> {
+    let (w, h) = (width as usize, height as usize);
+    if w < 3 || h < 3 {
+        return None;
+    }
+    let threshold = NOISE_EDGE_THRESHOLD << shift;
+    let (mut sum, mut count) = (0u64, 0u64);
+    for y in 1..h - 1 {
+        for x in 1..w - 1 {
+            let at = |dx: isize, dy: isize| {
+                plane[(y as isize + dy) as usize * w + (x as isize + dx) as usize] as i32
+            };
+            let gx =
+                (at(1, -1) + 2 * at(1, 0) + at(1, 1)) - (at(-1, -1) + 2 * at(-1, 0) + at(-1, 1));
+            let gy =
+                (at(-1, 1) + 2 * at(0, 1) + at(1, 1)) - (at(-1, -1) + 2 * at(0, -1) + at(1, -1));
+            if gx.unsigned_abs() + gy.unsigned_abs() >= threshold {
+                continue;
+            }
+            let laplacian = (at(-1, -1) + at(1, -1) + at(-1, 1) + at(1, 1))
+                - 2 * (at(0, -1) + at(-1, 0) + at(1, 0) + at(0, 1))
+                + 4 * at(0, 0);
+            sum += laplacian.unsigned_abs() as u64;
+            count += 1;
+        }
+    }
+    if count < MIN_NOISE_SAMPLES {
+        return None;
+    }
+    Some((std::f64::consts::PI / 2.0).sqrt() * sum as f64 / (6 * count) as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(width: u32, height: u32, f: impl Fn(u32, u32) -> u16) -> FramePixels {
+        let mut pixels = FramePixels::solid(width, height, 0, 128, 128);
+        for y in 0..height {
+            for x in 0..width {
+                pixels.y[(y * width + x) as usize] = f(x, y);
+            }
+        }
+        pixels
+    }
+
+    fn texture(x: u32, y: u32) -> u16 {
+        ((x * 37 + y * 91 + (x * y) % 23) % 200 + 20) as u16
+    }
+
+    #[test]
+    fn flat_frame_has_no_intra_cost() {
+        let f = frame(64, 64, |_, _| 90);
+        let c = analyze_frame(0, &f, None);
+        assert_eq!(c.intra_cost, 0);
+        assert_eq!(c.blocks, 16);
+        assert_eq!(c.inter_cost, None);
+        assert_eq!(c.motion_magnitude, None);
+    }
+
+    #[test]
+    fn textured_frame_costs_more_than_flat() {
+        let flat = analyze_frame(0, &frame(64, 64, |_, _| 90), None);
+        let textured = analyze_frame(0, &frame(64, 64, texture), None);
+        assert!(textured.intra_cost > flat.intra_cost);
+    }
+
+    #[test]
+    fn static_content_has_zero_inter_cost_and_motion() {
+        let f = frame(64, 64, texture);
+        let c = analyze_frame(1, &f, Some(&f));
+        assert_eq!(c.inter_cost, Some(0));
+        assert_eq!(c.motion_magnitude, Some(0));
+        assert_eq!(c.inter_intra_ratio_pct(), Some(0));
+    }
+
+    #[test]
+    fn panning_content_reports_motion() {
+        let prev = frame(96, 96, texture);
+        let cur = frame(96, 96, |x, y| texture(x + 4, y));
+        let c = analyze_frame(1, &cur, Some(&prev));
+        assert!(c.motion_magnitude.unwrap() > 0);
+        assert!(c.inter_cost.unwrap() < c.intra_cost);
+    }
+
+    #[test]
+    fn downscaled_analysis_stays_in_source_units() {
+        // Broad shapes survive downscaling; the pan is a whole number of
+        // samples at every scale.
+        let shape = |x: u32, y: u32| {
+            let (dx, dy) = (x as i32 - 120, y as i32 - 130);
+            (220 - (dx * dx + 2 * dy * dy + dx * dy).min(40000) / 200) as u16
+        };
+        let prev = frame(256, 256, shape);
+        let cur = frame(256, 256, |x, y| shape(x + 8, y));
+        let full = analyze_frame(1, &cur, Some(&prev));
+        assert_eq!(analyze_frame_scaled(1, &cur, Some(&prev), 1), full);
+        for scale in [2, 4] {
+            let scaled = analyze_frame_scaled(1, &cur, Some(&prev), scale);
+            assert_eq!(scaled.blocks, full.blocks);
+            assert!(scaled.inter_intra_ratio_pct().unwrap() < 10);
+            let motion = scaled.motion_magnitude.unwrap();
+            let full_motion = full.motion_magnitude.unwrap();
+            assert!(motion * 4 > full_motion * 3 && motion * 4 < full_motion * 5);
+        }
+    }
+
+    fn textured_in(low: u16, high: u16) -> impl Fn(u32, u32) -> u16 {
+        move |x, y| low + (texture(x, y) - 20) * (high - low) / 199
+    }
+
+    #[test]
+    fn full_range_content_tagged_limited_is_flagged() {
+        let f = frame(64, 64, textured_in(0, 255));
+        let found = check_signal(std::slice::from_ref(&f), ColorRange::Limited);
+        assert!(matches!(
+            found[..],
+            [SignalMismatch::FullRangeTaggedLimited { outside_permille }] if outside_permille > 10
+        ));
+        assert!(check_signal(&[f], ColorRange::Full).is_empty());
+    }
+
+    #[test]
+    fn limited_range_content_tagged_full_is_flagged() {
+        let f = frame(64, 64, textured_in(16, 235));
+        assert_eq!(
+            check_signal(std::slice::from_ref(&f), ColorRange::Full),
+            [SignalMismatch::LimitedRangeTaggedFull]
+        );
+        assert!(check_signal(&[f], ColorRange::Limited).is_empty());
+        // Dim content never reaches white and is not evidence either way.
+        let dim = frame(64, 64, textured_in(16, 120));
+        assert!(check_signal(&[dim], ColorRange::Full).is_empty());
+    }
+
+    #[test]
+    fn eight_bit_data_in_ten_bit_frames_is_flagged() {
+        let eight = frame(64, 64, textured_in(16, 235));
+        let ten = |f: &dyn Fn(u16) -> u16| FramePixels {
+            y: eight.y.iter().map(|&s| f(s)).collect(),
+            u: eight.u.iter().map(|&s| f(s)).collect(),
+            v: eight.v.iter().map(|&s| f(s)).collect(),
+            bit_depth: BitDepth::Ten,
+            ..eight.clone()
+        };
+        let unscaled = ten(&|s| s);
+        assert!(
+            check_signal(&[unscaled], ColorRange::Limited)
+                .contains(&SignalMismatch::EightBitValuesInTenBit)
+        );
+        let padded = ten(&|s| s << 2);
+        assert_eq!(
+            check_signal(&[padded], ColorRange::Limited),
+            [SignalMismatch::PaddedEightBit]
+        );
+        let genuine = ten(&|s| (s << 2) + (s & 3));
+        assert!(check_signal(&[genuine], ColorRange::Limited).is_empty());
+    }
+
+    fn with_noise(mut pixels: FramePixels, amplitude: i32) -> FramePixels {
+        let mut state = 0x2545_f491u32;
+        for s in pixels.y.iter_mut().chain(pixels.u.iter_mut()) {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            *s = (*s as i32 + (state % (2 * amplitude as u32 + 1)) as i32 - amplitude) as u16;
+        }
+        pixels
+    }
+
+    #[test]
+    fn clean_gradient_has_no_noise() {
+        let noise = estimate_noise(&frame(64, 64, |x, y| (40 + x + y) as u16));
+        assert_eq!(noise.y, Some(0.0));
+        assert_eq!(noise.u, Some(0.0));
+    }
+
+    #[test]
+    fn noise_estimate_tracks_added_noise() {
+        // Uniform noise in -a..=a has a deviation of about a / sqrt(3).
+        for amplitude in [3, 6] {
+            let noisy = with_noise(frame(96, 96, |x, _| (60 + x) as u16), amplitude);
+            let noise = estimate_noise(&noisy);
+            let expected = amplitude as f64 / 3f64.sqrt();
+            let y = noise.y.unwrap();
+            assert!((y - expected).abs() < expected * 0.2, "{y} vs {expected}");
+            assert!(noise.u.unwrap() > expected * 0.8);
+            assert_eq!(noise.v, Some(0.0));
+        }
+    }
+
+    #[test]
+    fn edges_are_left_out_of_the_noise_estimate() {
+        let stripes = frame(64, 64, |x, _| if (x / 8) % 2 == 0 { 40 } else { 200 });
+        assert_eq!(estimate_noise(&stripes).y, Some(0.0));
+        let tiny = FramePixels::solid(8, 8, 90, 128, 128);
+        assert_eq!(estimate_noise(&tiny).y, None);
+    }
+
+    fn complexity(frame_number: u64, intra_cost: u64, inter_cost: u64) -> FrameComplexity {
+        FrameComplexity {
+            frame_number,
+            intra_cost,
+            inter_cost: (frame_number > 0).then_some(inter_cost),
+            motion_magnitude: Some(0),
+            blocks: 16,
+        }
+    }
+
+    #[test]
+    fn scene_cuts_start_where_motion_compensation_stops_helping() {
+        let clip = [
+            complexity(0, 1000, 0),
+            complexity(1, 1000, 100),
+            complexity(2, 1000, 900),
+            complexity(3, 1000, 100),
+        ];
+        assert_eq!(scene_cuts(&clip), [0, 2]);
+        assert!(scene_cuts(&[]).is_empty());
+    }
+
+    #[test]
+    fn suggestions_follow_target_quality_and_content() {
+        let fps = Fps::from_int(30).unwrap();
+        let still: Vec<_> = (0..60).map(|i| complexity(i, 40_000, 2_000)).collect();
+        let suggest = |clip: &[FrameComplexity], psnr| {
+            suggest_settings(clip, 64, 64, fps, BitDepth::Eight, psnr)
+        };
+        let high = suggest(&still, 45.0);
+        let low = suggest(&still, 32.0);
+        assert!(high.base_q_idx < low.base_q_idx);
+        assert!(high.target_bitrate > low.target_bitrate);
+        // One scene of two seconds.
+        assert_eq!(high.keyint, 60);
+
+        let busy: Vec<_> = (0..60).map(|i| complexity(i, 40_000, 30_000)).collect();
+        assert!(suggest(&busy, 45.0).target_bitrate > high.target_bitrate);
+        let cuts: Vec<_> = (0..60)
+            .map(|i| complexity(i, 40_000, if i % 10 == 0 { 40_000 } else { 2_000 }))
+            .collect();
+        assert_eq!(suggest(&cuts, 45.0).keyint, 30, "clamped to one second");
+    }
+}
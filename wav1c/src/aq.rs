@@ -0,0 +1,107 @@
+//! Adaptive quantization: varying the q index between superblocks of a
+//! frame.
+
+/// How the q index varies inside inter frames. Keyframes always use one
+/// q index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AqMode {
+    /// One q index per frame.
+    #[default]
+    Off,
+    /// Cyclic refresh for low-latency streams: every inter frame codes a
+    /// rotating tenth of its superblocks a quarter finer, so areas left
+    /// coarse by earlier frames are cleaned up within a few frames instead
+    /// of at the next keyframe. The superblock q indices are coded as
+    /// per-superblock delta q.
+    CyclicRefresh,
+}
+
+impl AqMode {
+    pub const ALL: [AqMode; 2] = [AqMode::Off, AqMode::CyclicRefresh];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AqMode::Off => "off",
+            AqMode::CyclicRefresh => "cyclic",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|m| m.name() == name)
+    }
+}
+
+/// Share of superblocks boosted in each inter frame, in percent.
+pub const CYCLIC_REFRESH_PERCENT: usize = 10;
+
+/// Rate control buffer fullness (percent) from which frames are coded
+/// without a refresh, so the boost never pushes an overspending stream
+/// further over its budget.
+pub const CYCLIC_REFRESH_MAX_BUFFER_PCT: u32 = 75;
+
+/// Which superblocks the next inter frame boosts.
+#[derive(Debug, Clone, Default)]
+pub struct CyclicRefresh {
+    next_sb: usize,
+}
+
+impl CyclicRefresh {
+    /// Flags the superblocks (raster order) to boost in a frame of
+    /// `sb_count`, carrying on from where the previous frame stopped.
+    pub fn next_map(&mut self, sb_count: usize) -> Vec<bool> {
+        let mut map = vec![false; sb_count];
+        if sb_count == 0 {
+            return map;
+        }
+        let count = (sb_count * CYCLIC_REFRESH_PERCENT).div_ceil(100);
+        for i in 0..count {
+            map[(self.next_sb + i) % sb_count] = true;
+        }
+        self.next_sb = (self.next_sb + count) % sb_count;
+        map
+    }
+
+    /// Starts the cycle over, after a keyframe refreshed every superblock.
+    pub fn restart(&mut self) {
+        self.next_sb = 0;
+    }
+}
+
+/// The q index of boosted superblocks in a frame at `base_q_idx`.
+pub fn boost_q_idx(base_q_idx: u8) -> u8 {
+    (base_q_idx - base_q_idx / 4).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cyclic_refresh_covers_every_superblock_in_turn() {
+        let mut refresh = CyclicRefresh::default();
+        let mut boosted = [0u32; 25];
+        for _ in 0..9 {
+            let map = refresh.next_map(25);
+            assert_eq!(map.iter().filter(|&&b| b).count(), 3);
+            for (count, b) in boosted.iter_mut().zip(map) {
+                *count += b as u32;
+            }
+        }
+        // 27 boosts over 25 superblocks: the cycle wraps once.
+        assert!(boosted.iter().all(|&c| c == 1 || c == 2));
+        assert_eq!(boosted.iter().sum::<u32>(), 27);
+
+        refresh.restart();
+        assert!(refresh.next_map(25)[..3].iter().all(|&b| b));
+        assert_eq!(refresh.next_map(1), [true]);
+    }
+
+    #[test]
+    fn boost_is_finer_but_stays_a_valid_q_index() {
+        assert_eq!(boost_q_idx(128), 96);
+        assert_eq!(boost_q_idx(255), 192);
+        assert_eq!(boost_q_idx(1), 1);
+        assert_eq!(AqMode::from_name("cyclic"), Some(AqMode::CyclicRefresh));
+        assert_eq!(AqMode::from_name("Cyclic"), None);
+    }
+}
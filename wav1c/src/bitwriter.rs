@@ -1,4 +1,7 @@
-#[derive(Default)]
+//! MSB-first bit writer for headers and other fixed-width syntax. Values are split into bits
+//! arithmetically, so the output does not depend on host byte order.
+
+#[derive(Clone, Default)]
 pub struct BitWriter {
     buf: Vec<u8>,
     current_byte: u8,
@@ -125,4 +128,15 @@ mod tests {
         let bytes = w.finalize();
         assert_eq!(bytes, vec![0xCA, 0xFE]);
     }
+
+    #[test]
+    fn wide_values_are_written_most_significant_byte_first() {
+        let mut w = BitWriter::new();
+        w.write_bits(0x0123_4567_89ab_cdef, 64);
+        w.write_bits(0xabc, 12);
+        assert_eq!(
+            w.finalize(),
+            [0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0xab, 0xc0]
+        );
+    }
 }
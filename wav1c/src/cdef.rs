@@ -1,3 +1,4 @@
+use crate::tile::SkipMap;
 use crate::y4m::FramePixels;
 
 const CDEF_PRI_TAPS: [[i32; 2]; 2] = [[4, 2], [3, 3]];
@@ -14,6 +15,8 @@ const CDEF_DIRECTIONS: [[(i32, i32); 2]; 8] = [
     [(1, 0), (2, -1)],
 ];
 
+const DIV_TABLE: [i32; 9] = [0, 840, 420, 280, 210, 168, 140, 120, 105];
+
 #[inline]
 fn constrain(diff: i32, strength: i32, damping: i32) -> i32 {
     if strength == 0 {
@@ -29,122 +32,205 @@ fn constrain(diff: i32, strength: i32, damping: i32) -> i32 {
     }
 }
 
-pub fn cdef_analyze_direction(src: &[u16], stride: usize, bw: usize, bh: usize) -> u8 {
+/// The dominant edge direction of the 8x8 luma block at `(x0, y0)` of a
+/// `width`x`height` plane, and how strongly it dominates (spec 7.15.1).
+/// Rows and columns past the plane repeat its last sample.
+pub fn cdef_find_dir(
+    src: &[u16],
+    width: usize,
+    height: usize,
+    x0: usize,
+    y0: usize,
+    coeff_shift: u32,
+) -> (u8, i32) {
+    let mut partial = [[0i32; 15]; 8];
+    for i in 0..8 {
+        let row = (y0 + i).min(height - 1) * width;
+        for j in 0..8 {
+            let x = (src[row + (x0 + j).min(width - 1)] >> coeff_shift) as i32 - 128;
+            partial[0][i + j] += x;
+            partial[1][i + j / 2] += x;
+            partial[2][i] += x;
+            partial[3][3 + i - j / 2] += x;
+            partial[4][7 + i - j] += x;
+            partial[5][3 - i / 2 + j] += x;
+            partial[6][j] += x;
+            partial[7][i / 2 + j] += x;
+        }
+    }
+
     let mut cost = [0i32; 8];
-    for y in 0..bh {
-        for x in 0..bw {
-            let p = src[y * stride + x] as i32;
-            for dir in 0..8 {
-                let (dy, dx) = CDEF_DIRECTIONS[dir][0];
-                let ny1 = y as i32 + dy;
-                let nx1 = x as i32 + dx;
-                let ny2 = y as i32 - dy;
-                let nx2 = x as i32 - dx;
-
-                if ny1 >= 0 && ny1 < bh as i32 && nx1 >= 0 && nx1 < bw as i32 {
-                    let p1 = src[ny1 as usize * stride + nx1 as usize] as i32;
-                    cost[dir] += (p - p1).pow(2);
-                }
-                if ny2 >= 0 && ny2 < bh as i32 && nx2 >= 0 && nx2 < bw as i32 {
-                    let p2 = src[ny2 as usize * stride + nx2 as usize] as i32;
-                    cost[dir] += (p - p2).pow(2);
-                }
-            }
+    let square_sum = |sums: &[i32]| sums.iter().map(|s| s * s).sum::<i32>();
+    cost[2] = square_sum(&partial[2][..8]) * DIV_TABLE[8];
+    cost[6] = square_sum(&partial[6][..8]) * DIV_TABLE[8];
+    for i in 0..7 {
+        cost[0] += (partial[0][i] * partial[0][i] + partial[0][14 - i] * partial[0][14 - i])
+            * DIV_TABLE[i + 1];
+        cost[4] += (partial[4][i] * partial[4][i] + partial[4][14 - i] * partial[4][14 - i])
+            * DIV_TABLE[i + 1];
+    }
+    cost[0] += partial[0][7] * partial[0][7] * DIV_TABLE[8];
+    cost[4] += partial[4][7] * partial[4][7] * DIV_TABLE[8];
+    for i in (1..8).step_by(2) {
+        cost[i] = square_sum(&partial[i][3..8]) * DIV_TABLE[8];
+        for j in 0..3 {
+            cost[i] += (partial[i][j] * partial[i][j] + partial[i][10 - j] * partial[i][10 - j])
+                * DIV_TABLE[2 * j + 2];
         }
     }
 
     let mut best_dir = 0;
-    let mut min_cost = i32::MAX;
+    let mut best_cost = 0;
     for (dir, &c) in cost.iter().enumerate() {
-        if c < min_cost {
-            min_cost = c;
-            best_dir = dir as u8;
+        if c > best_cost {
+            best_cost = c;
+            best_dir = dir;
         }
     }
-    best_dir
+    (best_dir as u8, (best_cost - cost[(best_dir + 4) & 7]) >> 10)
 }
 
+/// Filters the `bw`x`bh` block at `(x0, y0)` of a `width`x`height` plane
+/// into the same place in `dst`, with primary taps along `dir` and
+/// secondary taps 45 degrees off it (spec 7.15.2). Taps past the plane are
+/// left out, and each result is clipped to the samples its taps read.
 #[allow(clippy::too_many_arguments)]
 pub fn cdef_filter_block(
     src: &[u16],
-    stride: usize,
     dst: &mut [u16],
-    dst_stride: usize,
     width: usize,
     height: usize,
+    (x0, y0): (usize, usize),
+    (bw, bh): (usize, usize),
     pri_strength: i32,
     sec_strength: i32,
     damping: i32,
-    max_value: u16,
+    dir: u8,
+    coeff_shift: u32,
 ) {
-    if pri_strength == 0 && sec_strength == 0 {
-        for y in 0..height {
-            for x in 0..width {
-                dst[y * dst_stride + x] = src[y * stride + x];
-            }
-        }
-        return;
-    }
-
-    let dir = cdef_analyze_direction(src, stride, width, height);
-    let pri_taps = &CDEF_PRI_TAPS[(pri_strength == 0) as usize];
-    let sec_taps = &CDEF_SEC_TAPS[(pri_strength == 0) as usize];
-
-    let dir1 = dir as usize;
-    let dir2 = (dir as usize + 2) & 7;
-    let dir3 = (dir as usize + 6) & 7;
+    let taps = ((pri_strength >> coeff_shift) & 1) as usize;
+    let dir = dir as usize;
+    let tap_at = |y: usize, x: usize, (dy, dx): (i32, i32), sign: i32| {
+        let ny = y as i32 + sign * dy;
+        let nx = x as i32 + sign * dx;
+        (ny >= 0 && ny < height as i32 && nx >= 0 && nx < width as i32)
+            .then(|| src[ny as usize * width + nx as usize] as i32)
+    };
 
-    for y in 0..height {
-        for x in 0..width {
-            let p = src[y * stride + x] as i32;
-            let mut sum = 0;
-
-            // Primary direction
+    for y in y0..y0 + bh {
+        for x in x0..x0 + bw {
+            let p = src[y * width + x] as i32;
+            let (mut sum, mut min, mut max) = (0, p, p);
             for k in 0..2 {
-                let (dy, dx) = CDEF_DIRECTIONS[dir1][k];
                 for sign in [-1, 1] {
-                    let ny = y as i32 + sign * dy;
-                    let nx = x as i32 + sign * dx;
-                    if ny >= 0 && ny < height as i32 && nx >= 0 && nx < width as i32 {
-                        let p1 = src[ny as usize * stride + nx as usize] as i32;
-                        let c = constrain(p1 - p, pri_strength, damping);
-                        sum += pri_taps[k] * c;
+                    if let Some(p1) = tap_at(y, x, CDEF_DIRECTIONS[dir][k], sign) {
+                        sum += CDEF_PRI_TAPS[taps][k] * constrain(p1 - p, pri_strength, damping);
+                        (min, max) = (min.min(p1), max.max(p1));
                     }
-                }
-            }
-
-            // Secondary direction 1
-            for k in 0..2 {
-                let (dy, dx) = CDEF_DIRECTIONS[dir2][k];
-                for sign in [-1, 1] {
-                    let ny = y as i32 + sign * dy;
-                    let nx = x as i32 + sign * dx;
-                    if ny >= 0 && ny < height as i32 && nx >= 0 && nx < width as i32 {
-                        let p1 = src[ny as usize * stride + nx as usize] as i32;
-                        let c = constrain(p1 - p, sec_strength, damping);
-                        sum += sec_taps[k] * c;
+                    for sec_dir in [(dir + 6) & 7, (dir + 2) & 7] {
+                        if let Some(s) = tap_at(y, x, CDEF_DIRECTIONS[sec_dir][k], sign) {
+                            sum += CDEF_SEC_TAPS[taps][k] * constrain(s - p, sec_strength, damping);
+                            (min, max) = (min.min(s), max.max(s));
+                        }
                     }
                 }
             }
+            dst[y * width + x] = (p + ((8 + sum - (sum < 0) as i32) >> 4)).clamp(min, max) as u16;
+        }
+    }
+}
 
-            // Secondary direction 2
-            for k in 0..2 {
-                let (dy, dx) = CDEF_DIRECTIONS[dir3][k];
-                for sign in [-1, 1] {
-                    let ny = y as i32 + sign * dy;
-                    let nx = x as i32 + sign * dx;
-                    if ny >= 0 && ny < height as i32 && nx >= 0 && nx < width as i32 {
-                        let p1 = src[ny as usize * stride + nx as usize] as i32;
-                        let c = constrain(p1 - p, sec_strength, damping);
-                        sum += sec_taps[k] * c;
-                    }
-                }
-            }
+/// The direction and variance of every 8x8 block CDEF filters, row-major;
+/// blocks of skipped units hold `None`. They depend only on the unfiltered
+/// luma, so strength trials share them.
+fn block_directions(pixels: &FramePixels, skips: &SkipMap) -> Vec<Option<(u8, i32)>> {
+    let (width, height) = (pixels.width as usize, pixels.height as usize);
+    let coeff_shift = (pixels.bit_depth.bits() - 8) as u32;
+    let cols = width.div_ceil(8);
+    (0..cols * height.div_ceil(8))
+        .map(|i| {
+            let (x8, y8) = (i % cols, i / cols);
+            (!skips.is_skipped(x8 as u32, y8 as u32))
+                .then(|| cdef_find_dir(&pixels.y, width, height, x8 * 8, y8 * 8, coeff_shift))
+        })
+        .collect()
+}
+
+/// Filters every unskipped 8x8 block with the coded (primary, secondary)
+/// strength of its superblock row, as the decoder does (spec 7.15).
+fn filter_sb_rows(
+    pixels: &FramePixels,
+    directions: &[Option<(u8, i32)>],
+    row_strengths: &[(i32, i32)],
+    damping: i32,
+) -> FramePixels {
+    let mut filtered = pixels.clone();
+    let coeff_shift = (pixels.bit_depth.bits() - 8) as u32;
+    let (width, height) = (pixels.width as usize, pixels.height as usize);
+    let (uv_w, uv_h) = (width.div_ceil(2), height.div_ceil(2));
+    let cols = width.div_ceil(8);
+
+    for (i, &direction) in directions.iter().enumerate() {
+        let Some((y_dir, var)) = direction else {
+            continue;
+        };
+        let (x8, y8) = (i % cols, i / cols);
+        let (pri, sec) = row_strengths[(y8 / 8).min(row_strengths.len() - 1)];
+        if pri == 0 && sec == 0 {
+            continue;
+        }
+        // A coded secondary strength of 3 means 4.
+        let sec = (sec + i32::from(sec == 3)) << coeff_shift;
+        let pri = pri << coeff_shift;
+        let dir = if pri == 0 { 0 } else { y_dir };
+
+        let var_strength = if var >> 6 != 0 {
+            (31 - ((var >> 6) as u32).leading_zeros()).min(12) as i32
+        } else {
+            0
+        };
+        let y_pri = if var != 0 {
+            (pri * (4 + var_strength) + 8) >> 4
+        } else {
+            0
+        };
+        let (x0, y0) = (x8 * 8, y8 * 8);
+        let size = ((width - x0).min(8), (height - y0).min(8));
+        let luma_damping = damping + coeff_shift as i32;
+        cdef_filter_block(
+            &pixels.y,
+            &mut filtered.y,
+            width,
+            height,
+            (x0, y0),
+            size,
+            y_pri,
+            sec,
+            luma_damping,
+            dir,
+            coeff_shift,
+        );
 
-            let filtered = p + ((8 + sum - (sum < 0) as i32) >> 4);
-            dst[y * dst_stride + x] = filtered.clamp(0, max_value as i32) as u16;
+        let (cx0, cy0) = (x8 * 4, y8 * 4);
+        let size = ((uv_w - cx0).min(4), (uv_h - cy0).min(4));
+        for (plane, filtered_plane) in [(&pixels.u, &mut filtered.u), (&pixels.v, &mut filtered.v)]
+        {
+            cdef_filter_block(
+                plane,
+                filtered_plane,
+                uv_w,
+                uv_h,
+                (cx0, cy0),
+                size,
+                pri,
+                sec,
+                luma_damping - 1,
+                dir,
+                coeff_shift,
+            );
         }
     }
+    filtered
 }
 
 pub fn apply_cdef_frame(
@@ -152,78 +238,150 @@ pub fn apply_cdef_frame(
     pri_strength: i32,
     sec_strength: i32,
     damping: i32,
+    skips: &SkipMap,
 ) {
     if pri_strength == 0 && sec_strength == 0 {
         return;
     }
+    let sb_rows = pixels.height.div_ceil(64) as usize;
+    apply_cdef_sb_rows(
+        pixels,
+        &vec![(pri_strength, sec_strength); sb_rows],
+        damping,
+        skips,
+    );
+}
+
+/// Filters each 64-row superblock band with its own (primary, secondary)
+/// strength, as coded in the frame header, leaving the 8x8 blocks `skips`
+/// marks alone. Blocks are filtered from the unfiltered frame, so this
+/// matches [`apply_cdef_frame`] when every row uses the same strength.
+///
+/// To match a decoder, `pixels` is the reconstruction padded to whole 8x8
+/// blocks (`tile::pad_to_block_grid`): taps read up to the edge of the
+/// block grid rather than the visible frame (spec 7.15.2 `is_inside`).
+pub fn apply_cdef_sb_rows(
+    pixels: &mut FramePixels,
+    row_strengths: &[(i32, i32)],
+    damping: i32,
+    skips: &SkipMap,
+) {
+    if row_strengths.iter().all(|&(pri, sec)| pri == 0 && sec == 0) {
+        return;
+    }
+    let directions = block_directions(pixels, skips);
+    *pixels = filter_sb_rows(pixels, &directions, row_strengths, damping);
+}
 
-    let mut filtered_y = vec![0u16; pixels.y.len()];
-    let mut filtered_u = vec![0u16; pixels.u.len()];
-    let mut filtered_v = vec![0u16; pixels.v.len()];
-    let max_value = pixels.bit_depth.max_value();
-    let strength_shift = (pixels.bit_depth.bits() - 8) as i32;
-    let pri_strength = pri_strength << strength_shift;
-    let sec_strength = sec_strength << strength_shift;
+/// Squared error against `source` for each 64-row superblock band, over all
+/// three planes. `recon` may be padded past `source`; the padding is not
+/// counted.
+fn sb_row_sse(recon: &FramePixels, source: &FramePixels) -> Vec<u64> {
+    let (width, recon_width) = (source.width as usize, recon.width as usize);
+    let mut sse = vec![0u64; source.height.div_ceil(64) as usize];
+    let mut accumulate = |a: &[u16], b: &[u16], (a_stride, b_stride): (usize, usize), band_rows| {
+        for (row, (ra, rb)) in a.chunks(a_stride).zip(b.chunks(b_stride)).enumerate() {
+            sse[row / band_rows] += ra
+                .iter()
+                .zip(rb)
+                .map(|(&x, &y)| (x as i64 - y as i64).pow(2) as u64)
+                .sum::<u64>();
+        }
+    };
+    let uv_strides = (recon_width.div_ceil(2), width.div_ceil(2));
+    accumulate(&recon.y, &source.y, (recon_width, width), 64);
+    accumulate(&recon.u, &source.u, uv_strides, 32);
+    accumulate(&recon.v, &source.v, uv_strides, 32);
+    sse
+}
 
-    let width = pixels.width as usize;
-    let height = pixels.height as usize;
-    let uv_w = width.div_ceil(2);
-    let uv_h = height.div_ceil(2);
+/// Picks, for each superblock row, the candidate (primary, secondary)
+/// strength whose filtered reconstruction is closest to `source`. Ties go to
+/// the earlier candidate, so list weaker strengths first. `recon` is padded
+/// as for [`apply_cdef_sb_rows`]. Returns indices into `candidates`.
+pub fn search_sb_row_strengths(
+    recon: &FramePixels,
+    source: &FramePixels,
+    candidates: &[(i32, i32)],
+    damping: i32,
+    skips: &SkipMap,
+) -> Vec<usize> {
+    let sb_rows = recon.height.div_ceil(64) as usize;
+    let directions = block_directions(recon, skips);
+    let mut best = vec![(u64::MAX, 0usize); sb_rows];
+    for (idx, &strength) in candidates.iter().enumerate() {
+        let filtered = filter_sb_rows(recon, &directions, &vec![strength; sb_rows], damping);
+        for (row, sse) in sb_row_sse(&filtered, source).into_iter().enumerate() {
+            if sse < best[row].0 {
+                best[row] = (sse, idx);
+            }
+        }
+    }
+    best.into_iter().map(|(_, idx)| idx).collect()
+}
 
-    for by in (0..height).step_by(8) {
-        for bx in (0..width).step_by(8) {
-            let bw = (8).min(width - bx);
-            let bh = (8).min(height - by);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            cdef_filter_block(
-                &pixels.y[by * width + bx..],
-                width,
-                &mut filtered_y[by * width + bx..],
-                width,
-                bw,
-                bh,
-                pri_strength,
-                sec_strength,
-                damping,
-                max_value,
-            );
+    fn textured_frame(width: u32, height: u32) -> FramePixels {
+        let mut frame = FramePixels::solid(width, height, 0, 0, 0);
+        for (i, s) in frame.y.iter_mut().enumerate() {
+            *s = ((i * 13 + (i / width as usize) * 7) % 180 + 30) as u16;
+        }
+        for (i, s) in frame.u.iter_mut().chain(frame.v.iter_mut()).enumerate() {
+            *s = ((i * 5) % 90 + 80) as u16;
         }
+        frame
     }
 
-    for by in (0..uv_h).step_by(4) {
-        for bx in (0..uv_w).step_by(4) {
-            let bw = (4).min(uv_w - bx);
-            let bh = (4).min(uv_h - by);
+    #[test]
+    fn uniform_row_strengths_match_frame_filter() {
+        let source = textured_frame(80, 136);
+        let skips = SkipMap::none_skipped(80, 136);
+        let mut whole = source.clone();
+        apply_cdef_frame(&mut whole, 6, 1, 5, &skips);
+        let mut rows = source.clone();
+        apply_cdef_sb_rows(&mut rows, &[(6, 1); 3], 5, &skips);
+        assert_eq!(whole.y, rows.y);
+        assert_eq!(whole.u, rows.u);
+        assert_eq!(whole.v, rows.v);
+        assert_ne!(whole.y, source.y);
+    }
 
-            cdef_filter_block(
-                &pixels.u[by * uv_w + bx..],
-                uv_w,
-                &mut filtered_u[by * uv_w + bx..],
-                uv_w,
-                bw,
-                bh,
-                pri_strength,
-                sec_strength,
-                damping,
-                max_value,
-            );
+    #[test]
+    fn search_keeps_exact_rows_unfiltered() {
+        let source = textured_frame(64, 128);
+        let skips = SkipMap::none_skipped(64, 128);
+        let choice =
+            search_sb_row_strengths(&source, &source, &[(0, 0), (4, 0), (8, 0)], 5, &skips);
+        assert_eq!(choice, vec![0, 0]);
+    }
 
-            cdef_filter_block(
-                &pixels.v[by * uv_w + bx..],
-                uv_w,
-                &mut filtered_v[by * uv_w + bx..],
-                uv_w,
-                bw,
-                bh,
-                pri_strength,
-                sec_strength,
-                damping,
-                max_value,
-            );
-        }
+    #[test]
+    fn direction_follows_edges() {
+        // Vertical stripes: every column is constant, direction 2 in the
+        // spec's numbering; horizontal stripes are direction 6.
+        let vertical: Vec<u16> = (0..64).map(|i| if i % 8 < 4 { 40 } else { 200 }).collect();
+        let horizontal: Vec<u16> = (0..64).map(|i| if i / 8 < 4 { 40 } else { 200 }).collect();
+        let (dir, var) = cdef_find_dir(&vertical, 8, 8, 0, 0, 0);
+        assert_eq!(dir, 6);
+        assert!(var > 0);
+        assert_eq!(cdef_find_dir(&horizontal, 8, 8, 0, 0, 0).0, 2);
+        assert_eq!(cdef_find_dir(&[128; 64], 8, 8, 0, 0, 0), (0, 0));
     }
 
-    pixels.y = filtered_y;
-    pixels.u = filtered_u;
-    pixels.v = filtered_v;
+    #[test]
+    fn skipped_blocks_are_left_alone() {
+        let source = textured_frame(32, 16);
+        let mut skips = SkipMap::none_skipped(32, 16);
+        skips.fill(0, 0, 4, true);
+        let mut filtered = source.clone();
+        apply_cdef_frame(&mut filtered, 8, 2, 5, &skips);
+        for y in 0..16 {
+            let row = |frame: &FramePixels| frame.y[y * 32..y * 32 + 8].to_vec();
+            assert_eq!(row(&filtered) == row(&source), y < 8, "row {y}");
+        }
+        assert_eq!(filtered.u[..4], source.u[..4]);
+    }
 }
@@ -1143,6 +1143,14 @@ pub const DEFAULT_IS_INTER_CDF: [[u16; 4]; 4] = [
     [6230, 0, 0, 0],
 ];
 
+#[rustfmt::skip]
+pub const DEFAULT_Y_MODE_CDF: [[u16; 16]; 4] = [
+    [9967, 9279, 8475, 8012, 7167, 6645, 6162, 5350, 4823, 3540, 2977, 2417, 0, 0, 0, 0],
+    [14095, 12923, 10137, 9450, 8818, 8119, 7241, 5404, 4616, 3067, 2784, 1916, 0, 0, 0, 0],
+    [12998, 11789, 9372, 8829, 8527, 8114, 7632, 5695, 4938, 3408, 3038, 2109, 0, 0, 0, 0],
+    [12613, 11467, 9930, 9590, 9507, 9235, 9065, 7964, 7416, 6193, 5752, 4719, 0, 0, 0, 0],
+];
+
 #[rustfmt::skip]
 pub const DEFAULT_NEWMV_CDF: [[u16; 4]; 6] = [
     [8733, 0, 0, 0],
@@ -1175,8 +1183,65 @@ pub const DEFAULT_SINGLE_REF_CDF: [[[u16; 4]; 6]; 3] = [
     ],
 ];
 
+#[rustfmt::skip]
+pub const DEFAULT_COMP_MODE_CDF: [[u16; 4]; 5] = [
+    [5940, 0, 0, 0],
+    [8733, 0, 0, 0],
+    [20737, 0, 0, 0],
+    [22128, 0, 0, 0],
+    [29867, 0, 0, 0],
+];
+
+#[rustfmt::skip]
+pub const DEFAULT_COMP_REF_TYPE_CDF: [[u16; 4]; 5] = [
+    [31570, 0, 0, 0],
+    [30698, 0, 0, 0],
+    [23602, 0, 0, 0],
+    [25269, 0, 0, 0],
+    [10293, 0, 0, 0],
+];
+
+#[rustfmt::skip]
+pub const DEFAULT_UNI_COMP_REF_CDF: [[[u16; 4]; 3]; 3] = [
+    [[27484, 0, 0, 0], [28903, 0, 0, 0], [29640, 0, 0, 0]],
+    [[9616, 0, 0, 0], [18595, 0, 0, 0], [17498, 0, 0, 0]],
+    [[994, 0, 0, 0], [7648, 0, 0, 0], [6058, 0, 0, 0]],
+];
+
+#[rustfmt::skip]
+pub const DEFAULT_COMP_REF_CDF: [[[u16; 4]; 3]; 3] = [
+    [[27822, 0, 0, 0], [23300, 0, 0, 0], [31265, 0, 0, 0]],
+    [[12877, 0, 0, 0], [10327, 0, 0, 0], [17608, 0, 0, 0]],
+    [[2037, 0, 0, 0], [1709, 0, 0, 0], [5224, 0, 0, 0]],
+];
+
+#[rustfmt::skip]
+pub const DEFAULT_COMP_BWDREF_CDF: [[[u16; 4]; 2]; 3] = [
+    [[30533, 0, 0, 0], [31345, 0, 0, 0]],
+    [[15586, 0, 0, 0], [17593, 0, 0, 0]],
+    [[2162, 0, 0, 0], [2279, 0, 0, 0]],
+];
+
+#[rustfmt::skip]
+pub const DEFAULT_COMPOUND_MODE_CDF: [[u16; 8]; 8] = [
+    [25008, 18945, 16960, 15127, 13612, 12102, 5877, 0],
+    [22038, 13316, 11623, 10019, 8729, 7637, 4044, 0],
+    [22104, 12547, 11180, 9862, 8473, 7381, 4332, 0],
+    [19470, 15784, 12297, 8586, 7701, 7032, 6346, 0],
+    [13864, 9443, 7526, 5336, 4870, 4510, 2010, 0],
+    [22043, 15314, 12644, 9948, 8573, 7600, 6722, 0],
+    [15643, 8495, 6954, 5276, 4554, 4064, 2176, 0],
+    [19722, 9554, 8263, 6826, 5333, 4326, 3438, 0],
+];
+
+pub const DEFAULT_DELTA_Q_CDF: [u16; 4] = [4608, 648, 91, 0];
+
+pub const DEFAULT_USE_WIENER_CDF: [u16; 4] = [21198, 0, 0, 0];
+
+#[derive(Clone)]
 pub struct CdfContext {
     pub kf_y_mode: [[[u16; 16]; 5]; 5],
+    pub y_mode: [[u16; 16]; 4],
     pub uv_mode: [[[u16; 16]; 13]; 2],
     pub partition: [[[u16; 16]; 4]; 5],
     pub skip: [[u16; 4]; 3],
@@ -1199,13 +1264,16 @@ pub struct CdfContext {
     pub angle_delta: [[u16; 8]; 8],
     pub mv: MvCdf,
     pub drl: [[u16; 4]; 3],
+    pub delta_q: [u16; 4],
+    pub use_wiener: [u16; 4],
 
     // Compound reference CDFs
     pub comp_mode: [[u16; 4]; 5],
     pub comp_ref_type: [[u16; 4]; 5],
-    pub uni_comp_ref: [[[u16; 4]; 3]; 5],
-    pub comp_ref: [[[u16; 4]; 3]; 5],
-    pub comp_bwdref: [[[u16; 4]; 3]; 5],
+    pub uni_comp_ref: [[[u16; 4]; 3]; 3],
+    pub comp_ref: [[[u16; 4]; 3]; 3],
+    pub comp_bwdref: [[[u16; 4]; 2]; 3],
+    pub compound_mode: [[u16; 8]; 8],
 }
 
 impl CdfContext {
@@ -1213,6 +1281,7 @@ impl CdfContext {
         let coef = crate::cdf_coef::coef_cdfs_for_qidx(base_q_idx);
         Self {
             kf_y_mode: DEFAULT_KF_Y_MODE_CDF,
+            y_mode: DEFAULT_Y_MODE_CDF,
             uv_mode: DEFAULT_UV_MODE_CDF,
             partition: DEFAULT_PARTITION_CDF,
             skip: DEFAULT_SKIP_CDF,
@@ -1262,13 +1331,15 @@ impl CdfContext {
             ],
             mv: MvCdf::default_cdfs(),
             drl: DEFAULT_DRL_CDF,
+            delta_q: DEFAULT_DELTA_Q_CDF,
+            use_wiener: DEFAULT_USE_WIENER_CDF,
 
-            // Compound reference defaults
-            comp_mode: [[32768, 32768, 32768, 0]; 5],
-            comp_ref_type: [[32768, 32768, 32768, 0]; 5],
-            uni_comp_ref: [[[32768, 32768, 32768, 0]; 3]; 5],
-            comp_ref: [[[32768, 32768, 32768, 0]; 3]; 5],
-            comp_bwdref: [[[32768, 32768, 32768, 0]; 3]; 5],
+            comp_mode: DEFAULT_COMP_MODE_CDF,
+            comp_ref_type: DEFAULT_COMP_REF_TYPE_CDF,
+            uni_comp_ref: DEFAULT_UNI_COMP_REF_CDF,
+            comp_ref: DEFAULT_COMP_REF_CDF,
+            comp_bwdref: DEFAULT_COMP_BWDREF_CDF,
+            compound_mode: DEFAULT_COMPOUND_MODE_CDF,
         }
     }
 }
@@ -0,0 +1,217 @@
+//! Runtime CPU feature dispatch.
+//!
+//! Hot kernels are called through a [`Kernels`] table of function pointers
+//! that is filled in once, on first use, from the features detected on the
+//! running CPU. A kernel gains a specialised implementation by adding it to
+//! [`Kernels::select`] under the flag it needs; call sites never change and
+//! never need a `cfg!`. Every entry must produce the same output as the
+//! portable kernel in [`crate::simd`], so the choice never changes the
+//! bitstream.
+
+use std::sync::OnceLock;
+
+use crate::simd;
+
+/// A set of CPU features relevant to the kernels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CpuFlags(u32);
+
+impl CpuFlags {
+    pub const SSE2: CpuFlags = CpuFlags(1 << 0);
+    pub const SSSE3: CpuFlags = CpuFlags(1 << 1);
+    pub const SSE41: CpuFlags = CpuFlags(1 << 2);
+    pub const AVX2: CpuFlags = CpuFlags(1 << 3);
+    pub const NEON: CpuFlags = CpuFlags(1 << 8);
+    pub const DOTPROD: CpuFlags = CpuFlags(1 << 9);
+    pub const SIMD128: CpuFlags = CpuFlags(1 << 16);
+
+    pub const NONE: CpuFlags = CpuFlags(0);
+
+    /// Features available on the running CPU.
+    pub fn detect() -> Self {
+        #[allow(unused_mut)]
+        let mut flags = CpuFlags::NONE;
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            for (detected, flag) in [
+                (std::arch::is_x86_feature_detected!("sse2"), Self::SSE2),
+                (std::arch::is_x86_feature_detected!("ssse3"), Self::SSSE3),
+                (std::arch::is_x86_feature_detected!("sse4.1"), Self::SSE41),
+                (std::arch::is_x86_feature_detected!("avx2"), Self::AVX2),
+            ] {
+                if detected {
+                    flags = flags.union(flag);
+                }
+            }
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            flags = flags.union(Self::NEON);
+            if std::arch::is_aarch64_feature_detected!("dotprod") {
+                flags = flags.union(Self::DOTPROD);
+            }
+        }
+        #[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+        {
+            flags = flags.union(Self::SIMD128);
+        }
+        flags
+    }
+
+    pub const fn union(self, other: CpuFlags) -> Self {
+        CpuFlags(self.0 | other.0)
+    }
+
+    pub const fn intersection(self, other: CpuFlags) -> Self {
+        CpuFlags(self.0 & other.0)
+    }
+
+    pub const fn contains(self, other: CpuFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+}
+
+pub type SadFn = fn(&[u16], usize, &[u16], usize, usize, usize) -> u32;
+pub type Hadamard4x4Fn = fn(&[u16], usize, &[u16], usize) -> u32;
+pub type Convolve8TapFn = fn(&[i32], &[i8; 8], &mut [i32]);
+pub type Convolve8TapRowsFn = fn(&[i32], usize, &[i8; 8], &mut [i32]);
+pub type ForwardDct8x8Fn = fn(&[i32; 64]) -> [i32; 64];
+
+/// Function pointer table for the dispatched kernels.
+#[derive(Debug, Clone, Copy)]
+pub struct Kernels {
+    pub flags: CpuFlags,
+    /// The flags whose implementations replaced a portable entry; empty
+    /// when every kernel is portable.
+    pub specialized: CpuFlags,
+    pub sad: SadFn,
+    pub hadamard_4x4_abs_sum: Hadamard4x4Fn,
+    pub convolve_8tap: Convolve8TapFn,
+    pub convolve_8tap_rows: Convolve8TapRowsFn,
+    pub forward_dct_8x8: ForwardDct8x8Fn,
+}
+
+impl Kernels {
+    /// The portable kernels, valid on every target.
+    pub fn portable() -> Self {
+        Self {
+            flags: CpuFlags::NONE,
+            specialized: CpuFlags::NONE,
+            sad: simd::sad,
+            hadamard_4x4_abs_sum: simd::hadamard_4x4_abs_sum,
+            convolve_8tap: simd::convolve_8tap,
+            convolve_8tap_rows: simd::convolve_8tap_rows,
+            forward_dct_8x8: simd::forward_dct_8x8,
+        }
+    }
+
+    /// The best table for `flags`. Starts from the portable kernels and
+    /// overrides each entry with the most specialised implementation the
+    /// flags allow; none are registered yet, so every flag set currently
+    /// resolves to the portable kernels.
+    pub fn select(flags: CpuFlags) -> Self {
+        Self {
+            flags,
+            ..Self::portable()
+        }
+    }
+}
+
+static KERNELS: OnceLock<Kernels> = OnceLock::new();
+
+/// The kernel table for this process, selected on first call from
+/// [`CpuFlags::detect`] masked by `WAV1C_CPU_MASK` (a decimal or `0x` hex
+/// bit mask; `0` forces the portable kernels), for comparing implementations.
+pub fn kernels() -> &'static Kernels {
+    KERNELS.get_or_init(|| {
+        let mut flags = CpuFlags::detect();
+        if let Some(mask) = std::env::var("WAV1C_CPU_MASK")
+            .ok()
+            .and_then(|v| parse_mask(&v))
+        {
+            flags = flags.intersection(CpuFlags(mask));
+        }
+        Kernels::select(flags)
+    })
+}
+
+fn parse_mask(value: &str) -> Option<u32> {
+    let value = value.trim();
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flag_set_operations() {
+        let flags = CpuFlags::SSE2.union(CpuFlags::AVX2);
+        assert!(flags.contains(CpuFlags::SSE2));
+        assert!(flags.contains(CpuFlags::AVX2));
+        assert!(!flags.contains(CpuFlags::SSE41));
+        assert_eq!(flags.intersection(CpuFlags::AVX2), CpuFlags::AVX2);
+        assert_eq!(flags.intersection(CpuFlags::NONE).bits(), 0);
+    }
+
+    #[test]
+    fn cpu_mask_parses_decimal_and_hex() {
+        assert_eq!(parse_mask("0"), Some(0));
+        assert_eq!(parse_mask(" 256 "), Some(256));
+        assert_eq!(parse_mask("0x10"), Some(16));
+        assert_eq!(parse_mask("neon"), None);
+    }
+
+    #[test]
+    fn every_flag_set_matches_portable_kernels() {
+        let src: Vec<u16> = (0..32 * 12).map(|i| ((i * 37) % 1024) as u16).collect();
+        let pred: Vec<u16> = (0..32 * 12).map(|i| ((i * 91) % 1024) as u16).collect();
+        let taps = [-1i8, 3, -10, 122, 18, -6, 2, 0];
+        let line: Vec<i32> = src.iter().map(|&s| s as i32).collect();
+        let portable = Kernels::portable();
+        let all = [
+            CpuFlags::SSE2,
+            CpuFlags::SSSE3,
+            CpuFlags::SSE41,
+            CpuFlags::AVX2,
+            CpuFlags::NEON,
+            CpuFlags::DOTPROD,
+            CpuFlags::SIMD128,
+        ];
+        for flags in [CpuFlags::NONE, CpuFlags::detect()].into_iter().chain(all) {
+            let k = Kernels::select(flags);
+            assert!(flags.contains(k.specialized));
+            assert_eq!(
+                (k.sad)(&src, 32, &pred, 32, 23, 12),
+                (portable.sad)(&src, 32, &pred, 32, 23, 12)
+            );
+            assert_eq!(
+                (k.hadamard_4x4_abs_sum)(&src, 32, &pred, 32),
+                (portable.hadamard_4x4_abs_sum)(&src, 32, &pred, 32)
+            );
+            let (mut a, mut b) = (vec![0; 16], vec![0; 16]);
+            (k.convolve_8tap)(&line[..23], &taps, &mut a);
+            (portable.convolve_8tap)(&line[..23], &taps, &mut b);
+            assert_eq!(a, b);
+            let (mut a, mut b) = (vec![0; 5 * 32], vec![0; 5 * 32]);
+            (k.convolve_8tap_rows)(&line, 32, &taps, &mut a);
+            (portable.convolve_8tap_rows)(&line, 32, &taps, &mut b);
+            assert_eq!(a, b);
+            let residual: [i32; 64] = std::array::from_fn(|i| line[i] - line[i + 64]);
+            assert_eq!(
+                (k.forward_dct_8x8)(&residual),
+                (portable.forward_dct_8x8)(&residual)
+            );
+        }
+    }
+}
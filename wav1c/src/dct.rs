@@ -1,7 +1,27 @@
+use crate::video::BitDepth;
+
 fn clip(v: i32) -> i32 {
     v.clamp(-32768, 32767)
 }
 
+fn clamp_bits(v: i32, bits: u32) -> i32 {
+    v.clamp(-(1 << (bits - 1)), (1 << (bits - 1)) - 1)
+}
+
+/// The widths decoders clamp inverse transforms to: coefficients and row
+/// transform values to `BitDepth + 8` bits, the column transform's input
+/// and values to `Max(BitDepth + 6, 16)` bits (spec 7.13.3).
+fn inverse_clamp_bits(bit_depth: BitDepth) -> (u32, u32) {
+    let bits = u32::from(bit_depth.bits());
+    (bits + 8, (bits + 6).max(16))
+}
+
+fn clamp_all(buf: &mut [i32], bits: u32) {
+    for v in buf {
+        *v = clamp_bits(*v, bits);
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TxType {
     DctDct = 0,
@@ -11,7 +31,7 @@ pub enum TxType {
     Idtx = 9,
 }
 
-fn inv_dct4_1d(data: &mut [i32], offset: usize, stride: usize) {
+fn inv_dct4_1d(data: &mut [i32], offset: usize, stride: usize, bits: u32) {
     let in0 = data[offset];
     let in1 = data[offset + stride];
     let in2 = data[offset + 2 * stride];
@@ -22,14 +42,14 @@ fn inv_dct4_1d(data: &mut [i32], offset: usize, stride: usize) {
     let t2 = ((in1 * 1567 - in3 * (3784 - 4096) + 2048) >> 12) - in3;
     let t3 = ((in1 * (3784 - 4096) + in3 * 1567 + 2048) >> 12) + in1;
 
-    data[offset] = clip(t0 + t3);
-    data[offset + stride] = clip(t1 + t2);
-    data[offset + 2 * stride] = clip(t1 - t2);
-    data[offset + 3 * stride] = clip(t0 - t3);
+    data[offset] = clamp_bits(t0 + t3, bits);
+    data[offset + stride] = clamp_bits(t1 + t2, bits);
+    data[offset + 2 * stride] = clamp_bits(t1 - t2, bits);
+    data[offset + 3 * stride] = clamp_bits(t0 - t3, bits);
 }
 
-fn inv_dct8_1d(data: &mut [i32], offset: usize, stride: usize) {
-    inv_dct4_1d(data, offset, stride * 2);
+fn inv_dct8_1d(data: &mut [i32], offset: usize, stride: usize, bits: u32) {
+    inv_dct4_1d(data, offset, stride * 2, bits);
 
     let in1 = data[offset + stride];
     let in3 = data[offset + 3 * stride];
@@ -41,10 +61,10 @@ fn inv_dct8_1d(data: &mut [i32], offset: usize, stride: usize) {
     let t6a = (in5 * 1138 + in3 * 1703 + 1024) >> 11;
     let t7a = ((in1 * (4017 - 4096) + in7 * 799 + 2048) >> 12) + in1;
 
-    let t4 = clip(t4a + t5a);
-    let t5a = clip(t4a - t5a);
-    let t7 = clip(t7a + t6a);
-    let t6a = clip(t7a - t6a);
+    let t4 = clamp_bits(t4a + t5a, bits);
+    let t5a = clamp_bits(t4a - t5a, bits);
+    let t7 = clamp_bits(t7a + t6a, bits);
+    let t6a = clamp_bits(t7a - t6a, bits);
 
     let t5 = ((t6a - t5a) * 181 + 128) >> 8;
     let t6 = ((t6a + t5a) * 181 + 128) >> 8;
@@ -54,18 +74,18 @@ fn inv_dct8_1d(data: &mut [i32], offset: usize, stride: usize) {
     let t2 = data[offset + 4 * stride];
     let t3 = data[offset + 6 * stride];
 
-    data[offset] = clip(t0 + t7);
-    data[offset + stride] = clip(t1 + t6);
-    data[offset + 2 * stride] = clip(t2 + t5);
-    data[offset + 3 * stride] = clip(t3 + t4);
-    data[offset + 4 * stride] = clip(t3 - t4);
-    data[offset + 5 * stride] = clip(t2 - t5);
-    data[offset + 6 * stride] = clip(t1 - t6);
-    data[offset + 7 * stride] = clip(t0 - t7);
+    data[offset] = clamp_bits(t0 + t7, bits);
+    data[offset + stride] = clamp_bits(t1 + t6, bits);
+    data[offset + 2 * stride] = clamp_bits(t2 + t5, bits);
+    data[offset + 3 * stride] = clamp_bits(t3 + t4, bits);
+    data[offset + 4 * stride] = clamp_bits(t3 - t4, bits);
+    data[offset + 5 * stride] = clamp_bits(t2 - t5, bits);
+    data[offset + 6 * stride] = clamp_bits(t1 - t6, bits);
+    data[offset + 7 * stride] = clamp_bits(t0 - t7, bits);
 }
 
-fn inv_dct16_1d(data: &mut [i32], offset: usize, stride: usize) {
-    inv_dct8_1d(data, offset, stride * 2);
+fn inv_dct16_1d(data: &mut [i32], offset: usize, stride: usize, bits: u32) {
+    inv_dct8_1d(data, offset, stride * 2, bits);
 
     let in1 = data[offset + stride];
     let in3 = data[offset + 3 * stride];
@@ -85,28 +105,28 @@ fn inv_dct16_1d(data: &mut [i32], offset: usize, stride: usize) {
     let t14a = (in9 * 1299 + in7 * 1583 + 1024) >> 11;
     let t15a = ((in1 * (4076 - 4096) + in15 * 401 + 2048) >> 12) + in1;
 
-    let t8 = clip(t8a + t9a);
-    let t9 = clip(t8a - t9a);
-    let t10 = clip(t11a - t10a);
-    let t11 = clip(t11a + t10a);
-    let t12 = clip(t12a + t13a);
-    let t13 = clip(t12a - t13a);
-    let t14 = clip(t15a - t14a);
-    let t15 = clip(t15a + t14a);
+    let t8 = clamp_bits(t8a + t9a, bits);
+    let t9 = clamp_bits(t8a - t9a, bits);
+    let t10 = clamp_bits(t11a - t10a, bits);
+    let t11 = clamp_bits(t11a + t10a, bits);
+    let t12 = clamp_bits(t12a + t13a, bits);
+    let t13 = clamp_bits(t12a - t13a, bits);
+    let t14 = clamp_bits(t15a - t14a, bits);
+    let t15 = clamp_bits(t15a + t14a, bits);
 
     let t9a = ((t14 * 1567 - t9 * (3784 - 4096) + 2048) >> 12) - t9;
     let t14a = ((t14 * (3784 - 4096) + t9 * 1567 + 2048) >> 12) + t14;
     let t10a = ((-(t13 * (3784 - 4096) + t10 * 1567) + 2048) >> 12) - t13;
     let t13a = ((t13 * 1567 - t10 * (3784 - 4096) + 2048) >> 12) - t10;
 
-    let t8a = clip(t8 + t11);
-    let t9 = clip(t9a + t10a);
-    let t10 = clip(t9a - t10a);
-    let t11a = clip(t8 - t11);
-    let t12a = clip(t15 - t12);
-    let t13 = clip(t14a - t13a);
-    let t14 = clip(t14a + t13a);
-    let t15a = clip(t15 + t12);
+    let t8a = clamp_bits(t8 + t11, bits);
+    let t9 = clamp_bits(t9a + t10a, bits);
+    let t10 = clamp_bits(t9a - t10a, bits);
+    let t11a = clamp_bits(t8 - t11, bits);
+    let t12a = clamp_bits(t15 - t12, bits);
+    let t13 = clamp_bits(t14a - t13a, bits);
+    let t14 = clamp_bits(t14a + t13a, bits);
+    let t15a = clamp_bits(t15 + t12, bits);
 
     let t10a = ((t13 - t10) * 181 + 128) >> 8;
     let t13a = ((t13 + t10) * 181 + 128) >> 8;
@@ -122,22 +142,22 @@ fn inv_dct16_1d(data: &mut [i32], offset: usize, stride: usize) {
     let t6 = data[offset + 12 * stride];
     let t7 = data[offset + 14 * stride];
 
-    data[offset] = clip(t0 + t15a);
-    data[offset + stride] = clip(t1 + t14);
-    data[offset + 2 * stride] = clip(t2 + t13a);
-    data[offset + 3 * stride] = clip(t3 + t12);
-    data[offset + 4 * stride] = clip(t4 + t11);
-    data[offset + 5 * stride] = clip(t5 + t10a);
-    data[offset + 6 * stride] = clip(t6 + t9);
-    data[offset + 7 * stride] = clip(t7 + t8a);
-    data[offset + 8 * stride] = clip(t7 - t8a);
-    data[offset + 9 * stride] = clip(t6 - t9);
-    data[offset + 10 * stride] = clip(t5 - t10a);
-    data[offset + 11 * stride] = clip(t4 - t11);
-    data[offset + 12 * stride] = clip(t3 - t12);
-    data[offset + 13 * stride] = clip(t2 - t13a);
-    data[offset + 14 * stride] = clip(t1 - t14);
-    data[offset + 15 * stride] = clip(t0 - t15a);
+    data[offset] = clamp_bits(t0 + t15a, bits);
+    data[offset + stride] = clamp_bits(t1 + t14, bits);
+    data[offset + 2 * stride] = clamp_bits(t2 + t13a, bits);
+    data[offset + 3 * stride] = clamp_bits(t3 + t12, bits);
+    data[offset + 4 * stride] = clamp_bits(t4 + t11, bits);
+    data[offset + 5 * stride] = clamp_bits(t5 + t10a, bits);
+    data[offset + 6 * stride] = clamp_bits(t6 + t9, bits);
+    data[offset + 7 * stride] = clamp_bits(t7 + t8a, bits);
+    data[offset + 8 * stride] = clamp_bits(t7 - t8a, bits);
+    data[offset + 9 * stride] = clamp_bits(t6 - t9, bits);
+    data[offset + 10 * stride] = clamp_bits(t5 - t10a, bits);
+    data[offset + 11 * stride] = clamp_bits(t4 - t11, bits);
+    data[offset + 12 * stride] = clamp_bits(t3 - t12, bits);
+    data[offset + 13 * stride] = clamp_bits(t2 - t13a, bits);
+    data[offset + 14 * stride] = clamp_bits(t1 - t14, bits);
+    data[offset + 15 * stride] = clamp_bits(t0 - t15a, bits);
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -358,7 +378,7 @@ fn fwd_adst4_1d(data: &mut [i32], offset: usize, stride: usize) {
     data[offset + 3 * stride] = (s3 + 2048) >> 12;
 }
 
-fn inv_adst4_1d(data: &mut [i32], offset: usize, stride: usize) {
+fn inv_adst4_1d(data: &mut [i32], offset: usize, stride: usize, bits: u32) {
     let in0 = data[offset];
     let in1 = data[offset + stride];
     let in2 = data[offset + 2 * stride];
@@ -384,10 +404,10 @@ fn inv_adst4_1d(data: &mut [i32], offset: usize, stride: usize) {
         + in2
         - in1;
 
-    data[offset] = clip(o0);
-    data[offset + stride] = clip(o1);
-    data[offset + 2 * stride] = clip(o2);
-    data[offset + 3 * stride] = clip(o3);
+    data[offset] = clamp_bits(o0, bits);
+    data[offset + stride] = clamp_bits(o1, bits);
+    data[offset + 2 * stride] = clamp_bits(o2, bits);
+    data[offset + 3 * stride] = clamp_bits(o3, bits);
 }
 
 fn fwd_adst8_1d(data: &mut [i32], offset: usize, stride: usize) {
@@ -442,7 +462,7 @@ fn fwd_adst8_1d(data: &mut [i32], offset: usize, stride: usize) {
     data[offset + 7 * stride] = -o7;
 }
 
-fn inv_adst8_1d(data: &mut [i32], offset: usize, stride: usize) {
+fn inv_adst8_1d(data: &mut [i32], offset: usize, stride: usize, bits: u32) {
     let in0 = data[offset];
     let in1 = data[offset + stride];
     let in2 = data[offset + 2 * stride];
@@ -461,28 +481,28 @@ fn inv_adst8_1d(data: &mut [i32], offset: usize, stride: usize) {
     let t6a = ((1189 * in1 + (3920 - 4096) * in6 + 2048) >> 12) + in6;
     let t7a = (((3920 - 4096) * in1 - 1189 * in6 + 2048) >> 12) + in1;
 
-    let t0 = clip(t0a + t4a);
-    let t1 = clip(t1a + t5a);
-    let mut t2 = clip(t2a + t6a);
-    let mut t3 = clip(t3a + t7a);
-    let t4 = clip(t0a - t4a);
-    let t5 = clip(t1a - t5a);
-    let mut t6 = clip(t2a - t6a);
-    let mut t7 = clip(t3a - t7a);
+    let t0 = clamp_bits(t0a + t4a, bits);
+    let t1 = clamp_bits(t1a + t5a, bits);
+    let mut t2 = clamp_bits(t2a + t6a, bits);
+    let mut t3 = clamp_bits(t3a + t7a, bits);
+    let t4 = clamp_bits(t0a - t4a, bits);
+    let t5 = clamp_bits(t1a - t5a, bits);
+    let mut t6 = clamp_bits(t2a - t6a, bits);
+    let mut t7 = clamp_bits(t3a - t7a, bits);
 
     let t4b = (((3784 - 4096) * t4 + 1567 * t5 + 2048) >> 12) + t4;
     let t5b = ((1567 * t4 - (3784 - 4096) * t5 + 2048) >> 12) - t5;
     let t6b = (((3784 - 4096) * t7 - 1567 * t6 + 2048) >> 12) + t7;
     let t7b = ((1567 * t7 + (3784 - 4096) * t6 + 2048) >> 12) + t6;
 
-    data[offset] = clip(t0 + t2);
-    data[offset + 7 * stride] = -clip(t1 + t3);
-    t2 = clip(t0 - t2);
-    t3 = clip(t1 - t3);
-    data[offset + stride] = -clip(t4b + t6b);
-    data[offset + 6 * stride] = clip(t5b + t7b);
-    t6 = clip(t4b - t6b);
-    t7 = clip(t5b - t7b);
+    data[offset] = clamp_bits(t0 + t2, bits);
+    data[offset + 7 * stride] = -clamp_bits(t1 + t3, bits);
+    t2 = clamp_bits(t0 - t2, bits);
+    t3 = clamp_bits(t1 - t3, bits);
+    data[offset + stride] = -clamp_bits(t4b + t6b, bits);
+    data[offset + 6 * stride] = clamp_bits(t5b + t7b, bits);
+    t6 = clamp_bits(t4b - t6b, bits);
+    t7 = clamp_bits(t5b - t7b, bits);
 
     data[offset + 3 * stride] = -(((t2 + t3) * 181 + 128) >> 8);
     data[offset + 4 * stride] = ((t2 - t3) * 181 + 128) >> 8;
@@ -497,7 +517,7 @@ fn fwd_identity4_1d(data: &mut [i32], offset: usize, stride: usize) {
     }
 }
 
-fn inv_identity4_1d(data: &mut [i32], offset: usize, stride: usize) {
+fn inv_identity4_1d(data: &mut [i32], offset: usize, stride: usize, _bits: u32) {
     for i in 0..4 {
         let v = data[offset + i * stride];
         data[offset + i * stride] = v + ((v * 1697 + 2048) >> 12);
@@ -510,7 +530,7 @@ fn fwd_identity8_1d(data: &mut [i32], offset: usize, stride: usize) {
     }
 }
 
-fn inv_identity8_1d(data: &mut [i32], offset: usize, stride: usize) {
+fn inv_identity8_1d(data: &mut [i32], offset: usize, stride: usize, _bits: u32) {
     for i in 0..8 {
         data[offset + i * stride] *= 2;
     }
@@ -547,6 +567,7 @@ fn transpose_16x16(buf: &mut [i32; 256]) {
 }
 
 type Transform1dFn = fn(&mut [i32], usize, usize);
+type Inverse1dFn = fn(&mut [i32], usize, usize, u32);
 
 fn get_fwd_1d_fns_4(tx_type: TxType) -> (Transform1dFn, Transform1dFn) {
     match tx_type {
@@ -568,7 +589,7 @@ fn get_fwd_1d_fns_8(tx_type: TxType) -> (Transform1dFn, Transform1dFn) {
     }
 }
 
-fn get_inv_1d_fns_4(tx_type: TxType) -> (Transform1dFn, Transform1dFn) {
+fn get_inv_1d_fns_4(tx_type: TxType) -> (Inverse1dFn, Inverse1dFn) {
     match tx_type {
         TxType::DctDct => (inv_dct4_1d, inv_dct4_1d),
         TxType::AdstDct => (inv_dct4_1d, inv_adst4_1d),
@@ -578,7 +599,7 @@ fn get_inv_1d_fns_4(tx_type: TxType) -> (Transform1dFn, Transform1dFn) {
     }
 }
 
-fn get_inv_1d_fns_8(tx_type: TxType) -> (Transform1dFn, Transform1dFn) {
+fn get_inv_1d_fns_8(tx_type: TxType) -> (Inverse1dFn, Inverse1dFn) {
     match tx_type {
         TxType::DctDct => (inv_dct8_1d, inv_dct8_1d),
         TxType::AdstDct => (inv_dct8_1d, inv_adst8_1d),
@@ -609,6 +630,13 @@ pub fn forward_transform_4x4(residual: &[i32; 16], tx_type: TxType) -> [i32; 16]
 }
 
 pub fn forward_transform_8x8(residual: &[i32; 64], tx_type: TxType) -> [i32; 64] {
+    match tx_type {
+        TxType::DctDct => (crate::cpu::kernels().forward_dct_8x8)(residual),
+        _ => forward_separable_8x8(residual, tx_type),
+    }
+}
+
+pub(crate) fn forward_separable_8x8(residual: &[i32; 64], tx_type: TxType) -> [i32; 64] {
     let (row_fn, col_fn) = get_fwd_1d_fns_8(tx_type);
     let mut buf = *residual;
 
@@ -632,17 +660,24 @@ pub fn forward_transform_8x8(residual: &[i32; 64], tx_type: TxType) -> [i32; 64]
     buf
 }
 
-pub fn inverse_transform_4x4(coeffs: &[i32; 16], tx_type: TxType) -> [i32; 16] {
+pub fn inverse_transform_4x4(
+    coeffs: &[i32; 16],
+    tx_type: TxType,
+    bit_depth: BitDepth,
+) -> [i32; 16] {
     let (row_fn, col_fn) = get_inv_1d_fns_4(tx_type);
+    let (row_bits, col_bits) = inverse_clamp_bits(bit_depth);
     let mut buf = *coeffs;
     transpose_4x4(&mut buf);
+    clamp_all(&mut buf, row_bits);
 
     for row in 0..4 {
-        row_fn(&mut buf, row * 4, 1);
+        row_fn(&mut buf, row * 4, 1, row_bits);
     }
 
+    clamp_all(&mut buf, col_bits);
     for col in 0..4 {
-        col_fn(&mut buf, col, 4);
+        col_fn(&mut buf, col, 4, col_bits);
     }
 
     for v in &mut buf {
@@ -652,21 +687,27 @@ pub fn inverse_transform_4x4(coeffs: &[i32; 16], tx_type: TxType) -> [i32; 16] {
     buf
 }
 
-pub fn inverse_transform_8x8(coeffs: &[i32; 64], tx_type: TxType) -> [i32; 64] {
+pub fn inverse_transform_8x8(
+    coeffs: &[i32; 64],
+    tx_type: TxType,
+    bit_depth: BitDepth,
+) -> [i32; 64] {
     let (row_fn, col_fn) = get_inv_1d_fns_8(tx_type);
+    let (row_bits, col_bits) = inverse_clamp_bits(bit_depth);
     let mut buf = *coeffs;
     transpose_8x8(&mut buf);
+    clamp_all(&mut buf, row_bits);
 
     for row in 0..8 {
-        row_fn(&mut buf, row * 8, 1);
+        row_fn(&mut buf, row * 8, 1, row_bits);
     }
 
     for v in &mut buf {
-        *v = (*v + 1) >> 1;
+        *v = clamp_bits((*v + 1) >> 1, col_bits);
     }
 
     for col in 0..8 {
-        col_fn(&mut buf, col, 8);
+        col_fn(&mut buf, col, 8, col_bits);
     }
 
     for v in &mut buf {
@@ -699,20 +740,22 @@ pub fn forward_transform_16x16(residual: &[i32; 256]) -> [i32; 256] {
     buf
 }
 
-pub fn inverse_transform_16x16(coeffs: &[i32; 256]) -> [i32; 256] {
+pub fn inverse_transform_16x16(coeffs: &[i32; 256], bit_depth: BitDepth) -> [i32; 256] {
+    let (row_bits, col_bits) = inverse_clamp_bits(bit_depth);
     let mut buf = *coeffs;
     transpose_16x16(&mut buf);
+    clamp_all(&mut buf, row_bits);
 
     for row in 0..16 {
-        inv_dct16_1d(&mut buf, row * 16, 1);
+        inv_dct16_1d(&mut buf, row * 16, 1, row_bits);
     }
 
     for v in &mut buf {
-        *v = (*v + 2) >> 2;
+        *v = clamp_bits((*v + 2) >> 2, col_bits);
     }
 
     for col in 0..16 {
-        inv_dct16_1d(&mut buf, col, 16);
+        inv_dct16_1d(&mut buf, col, 16, col_bits);
     }
 
     for v in &mut buf {
@@ -730,12 +773,287 @@ pub fn forward_dct_8x8(residual: &[i32; 64]) -> [i32; 64] {
     forward_transform_8x8(residual, TxType::DctDct)
 }
 
-pub fn inverse_dct_4x4(coeffs: &[i32; 16]) -> [i32; 16] {
-    inverse_transform_4x4(coeffs, TxType::DctDct)
+pub fn inverse_dct_4x4(coeffs: &[i32; 16], bit_depth: BitDepth) -> [i32; 16] {
+    inverse_transform_4x4(coeffs, TxType::DctDct, bit_depth)
+}
+
+pub fn inverse_dct_8x8(coeffs: &[i32; 64], bit_depth: BitDepth) -> [i32; 64] {
+    inverse_transform_8x8(coeffs, TxType::DctDct, bit_depth)
+}
+
+/// `Cos128` of the AV1 spec: `4096 * cos(i * pi / 128)`, rounded.
+#[rustfmt::skip]
+const COS128: [i32; 65] = [
+    4096, 4095, 4091, 4085, 4076, 4065, 4052, 4036, 4017, 3996, 3973, 3948, 3920, 3889, 3857, 3822,
+    3784, 3745, 3703, 3659, 3612, 3564, 3513, 3461, 3406, 3349, 3290, 3229, 3166, 3102, 3035, 2967,
+    2896, 2824, 2751, 2675, 2598, 2520, 2440, 2359, 2276, 2191, 2106, 2019, 1931, 1842, 1751, 1660,
+    1567, 1474, 1380, 1285, 1189, 1092, 995, 897, 799, 700, 601, 501, 401, 301, 201, 101, 0,
+];
+
+/// `4096 * cos(angle * pi / 128)` for any `angle`.
+fn cos128(angle: usize) -> i32 {
+    let a = angle % 256;
+    match a {
+        0..=64 => COS128[a],
+        65..=128 => -COS128[128 - a],
+        129..=192 => -COS128[a - 128],
+        _ => COS128[256 - a],
+    }
+}
+
+fn bit_reverse(x: usize, bits: u32) -> usize {
+    if bits == 0 {
+        0
+    } else {
+        x.reverse_bits() >> (usize::BITS - bits)
+    }
+}
+
+/// Inverse DCT of `n` (16, 32 or 64) points, dav1d's flow graph.
+fn inv_dct_1d_n(data: &mut [i32], offset: usize, stride: usize, n: usize, bits: u32) {
+    if n == 16 {
+        inv_dct8_1d(data, offset, stride * 2, bits);
+    } else {
+        inv_dct_1d_n(data, offset, stride * 2, n / 2, bits);
+    }
+
+    let round = |v: i32| (v + 2048) >> 12;
+    let m = n / 2;
+    let mut t = [0i32; 32];
+
+    let pairs = m / 2;
+    for b in 0..pairs {
+        let p = 1 + 4 * bit_reverse(b, pairs.trailing_zeros());
+        let theta = p * 64 / n;
+        let (c1, c2) = (cos128(64 - theta), cos128(theta));
+        let lo = data[offset + p * stride];
+        let hi = data[offset + (n - p) * stride];
+        t[b] = round(lo * c1 - hi * c2);
+        t[m - 1 - b] = round(lo * c2 + hi * c1);
+    }
+
+    let mut g = 2;
+    loop {
+        for base in (0..m).step_by(g) {
+            let reversed = (base / g) % 2 == 1;
+            for j in 0..g / 2 {
+                let (a, b) = (t[base + j], t[base + g - 1 - j]);
+                if reversed {
+                    t[base + j] = clamp_bits(b - a, bits);
+                    t[base + g - 1 - j] = clamp_bits(b + a, bits);
+                } else {
+                    t[base + j] = clamp_bits(a + b, bits);
+                    t[base + g - 1 - j] = clamp_bits(a - b, bits);
+                }
+            }
+        }
+        if g == m / 2 {
+            for i in m / 4..m / 2 {
+                let (lo, hi) = (t[i], t[m - 1 - i]);
+                t[i] = ((hi - lo) * 181 + 128) >> 8;
+                t[m - 1 - i] = ((hi + lo) * 181 + 128) >> 8;
+            }
+            break;
+        }
+        let blocks = m / (4 * g);
+        for i in 0..m / 2 {
+            let phase = i % (2 * g);
+            if phase < g / 2 || phase >= 3 * g / 2 {
+                continue;
+            }
+            let theta = (16 / blocks) * (1 + 4 * bit_reverse(i / (2 * g), blocks.trailing_zeros()));
+            let (c1, c2) = (cos128(64 - theta), cos128(theta));
+            let (lo, hi) = (t[i], t[m - 1 - i]);
+            if phase < g {
+                t[i] = round(hi * c1 - lo * c2);
+                t[m - 1 - i] = round(hi * c2 + lo * c1);
+            } else {
+                t[i] = round(-(hi * c2 + lo * c1));
+                t[m - 1 - i] = round(hi * c1 - lo * c2);
+            }
+        }
+        g *= 2;
+    }
+
+    let mut even = [0i32; 32];
+    for (j, e) in even.iter_mut().enumerate().take(m) {
+        *e = data[offset + 2 * j * stride];
+    }
+    for j in 0..m {
+        data[offset + j * stride] = clamp_bits(even[j] + t[m - 1 - j], bits);
+        data[offset + (n - 1 - j) * stride] = clamp_bits(even[j] - t[m - 1 - j], bits);
+    }
+}
+
+/// Forward DCT of `n` points, computing only the first `keep` outputs.
+fn fwd_dct_1d_n(input: &[i32], stride: usize, n: usize, keep: usize, shift: u32) -> [i64; 32] {
+    let mut out = [0i64; 32];
+    for (k, o) in out.iter_mut().enumerate().take(keep) {
+        let sum: i64 = (0..n)
+            .map(|x| {
+                let basis = if k == 0 {
+                    COS128[32]
+                } else {
+                    cos128((2 * x + 1) * k * 64 / n)
+                };
+                input[x * stride] as i64 * basis as i64
+            })
+            .sum();
+        *o = (sum + (1 << (shift - 1))) >> shift;
+    }
+    out
+}
+
+/// Forward 2D DCT of an `n x n` residual (32 or 64) in scan layout.
+fn forward_transform_large(residual: &[i32], coeffs: &mut [i32], n: usize, shift: u32) {
+    let keep = n.min(32);
+    let mut rows = vec![0i32; n * keep];
+    for r in 0..n {
+        let row = fwd_dct_1d_n(&residual[r * n..], 1, n, keep, 12);
+        for k in 0..keep {
+            rows[r * keep + k] = row[k] as i32;
+        }
+    }
+    for k in 0..keep {
+        let col = fwd_dct_1d_n(&rows[k..], keep, n, keep, 12 + shift);
+        for v in 0..keep {
+            coeffs[k * n + v] = col[v] as i32;
+        }
+    }
+}
+
+fn inverse_transform_large(coeffs: &[i32], out: &mut [i32], n: usize, bit_depth: BitDepth) {
+    let (row_bits, col_bits) = inverse_clamp_bits(bit_depth);
+    let coded = n.min(32);
+    for v in 0..coded {
+        for k in 0..coded {
+            out[v * n + k] = clamp_bits(coeffs[k * n + v], row_bits);
+        }
+    }
+
+    for row in 0..coded {
+        inv_dct_1d_n(out, row * n, 1, n, row_bits);
+    }
+    for v in &mut out[..] {
+        *v = clamp_bits((*v + 2) >> 2, col_bits);
+    }
+
+    for col in 0..n {
+        inv_dct_1d_n(out, col, n, n, col_bits);
+    }
+    for v in &mut out[..] {
+        *v = (*v + 8) >> 4;
+    }
+}
+
+/// 32x32 DCT_DCT at half the 16x16 scale.
+pub fn forward_transform_32x32(residual: &[i32; 1024]) -> [i32; 1024] {
+    let mut coeffs = [0i32; 1024];
+    forward_transform_large(residual, &mut coeffs, 32, 2);
+    coeffs
+}
+
+pub fn inverse_transform_32x32(coeffs: &[i32; 1024], bit_depth: BitDepth) -> [i32; 1024] {
+    let mut out = [0i32; 1024];
+    inverse_transform_large(coeffs, &mut out, 32, bit_depth);
+    out
+}
+
+/// 64x64 DCT_DCT at a quarter of the 16x16 scale, top-left 32x32 only.
+pub fn forward_transform_64x64(residual: &[i32; 4096]) -> [i32; 4096] {
+    let mut coeffs = [0i32; 4096];
+    forward_transform_large(residual, &mut coeffs, 64, 4);
+    coeffs
+}
+
+pub fn inverse_transform_64x64(coeffs: &[i32; 4096], bit_depth: BitDepth) -> [i32; 4096] {
+    let mut out = [0i32; 4096];
+    inverse_transform_large(coeffs, &mut out, 64, bit_depth);
+    out
+}
+
+/// Quantizer step of the lossless Walsh-Hadamard path: `forward_wht_4x4`
+/// output is scaled by this factor, and qindex 0 dequantizes by it at every
+/// bit depth.
+pub const WHT_UNIT_QUANT: i32 = 4;
+
+fn fwd_wht4_1d(data: &mut [i32], offset: usize, stride: usize) {
+    let mut a = data[offset];
+    let mut b = data[offset + stride];
+    let mut c = data[offset + 2 * stride];
+    let mut d = data[offset + 3 * stride];
+
+    a += b;
+    d -= c;
+    let e = (a - d) >> 1;
+    b = e - b;
+    c = e - c;
+    a -= c;
+    d += b;
+
+    data[offset] = a;
+    data[offset + stride] = c;
+    data[offset + 2 * stride] = d;
+    data[offset + 3 * stride] = b;
 }
 
-pub fn inverse_dct_8x8(coeffs: &[i32; 64]) -> [i32; 64] {
-    inverse_transform_8x8(coeffs, TxType::DctDct)
+fn inv_wht4_1d(data: &mut [i32], offset: usize, stride: usize, shift: u32) {
+    let mut a = data[offset] >> shift;
+    let mut c = data[offset + stride] >> shift;
+    let mut d = data[offset + 2 * stride] >> shift;
+    let mut b = data[offset + 3 * stride] >> shift;
+
+    a += c;
+    d -= b;
+    let e = (a - d) >> 1;
+    b = e - b;
+    c = e - c;
+    a -= b;
+    d += c;
+
+    data[offset] = a;
+    data[offset + stride] = b;
+    data[offset + 2 * stride] = c;
+    data[offset + 3 * stride] = d;
+}
+
+/// Forward 4x4 Walsh-Hadamard transform used by lossless blocks. The output
+/// is exactly divisible by `WHT_UNIT_QUANT`, so quantizing at qindex 0 loses
+/// nothing.
+pub fn forward_wht_4x4(residual: &[i32; 16]) -> [i32; 16] {
+    let mut buf = *residual;
+
+    for col in 0..4 {
+        fwd_wht4_1d(&mut buf, col, 4);
+    }
+
+    for row in 0..4 {
+        fwd_wht4_1d(&mut buf, row * 4, 1);
+    }
+
+    for v in &mut buf {
+        *v *= WHT_UNIT_QUANT;
+    }
+
+    transpose_4x4(&mut buf);
+    buf
+}
+
+/// Inverse 4x4 Walsh-Hadamard transform as specified for lossless blocks;
+/// `coeffs` are dequantized values (levels times `WHT_UNIT_QUANT`).
+pub fn inverse_wht_4x4(coeffs: &[i32; 16]) -> [i32; 16] {
+    let mut buf = *coeffs;
+    transpose_4x4(&mut buf);
+
+    for row in 0..4 {
+        inv_wht4_1d(&mut buf, row * 4, 1, 2);
+    }
+
+    for col in 0..4 {
+        inv_wht4_1d(&mut buf, col, 4, 0);
+    }
+
+    buf
 }
 
 #[cfg(test)]
@@ -781,7 +1099,7 @@ mod tests {
     fn roundtrip_4x4_constant() {
         let original = [42i32; 16];
         let coeffs = forward_dct_4x4(&original);
-        let recovered = inverse_dct_4x4(&coeffs);
+        let recovered = inverse_dct_4x4(&coeffs, BitDepth::Eight);
         for i in 0..16 {
             assert!(
                 (recovered[i] - original[i]).abs() <= 1,
@@ -797,7 +1115,7 @@ mod tests {
     fn roundtrip_8x8_constant() {
         let original = [42i32; 64];
         let coeffs = forward_dct_8x8(&original);
-        let recovered = inverse_dct_8x8(&coeffs);
+        let recovered = inverse_dct_8x8(&coeffs, BitDepth::Eight);
         for i in 0..64 {
             assert!(
                 (recovered[i] - original[i]).abs() <= 1,
@@ -816,7 +1134,7 @@ mod tests {
             original[i] = (i as i32) * 10;
         }
         let coeffs = forward_dct_4x4(&original);
-        let recovered = inverse_dct_4x4(&coeffs);
+        let recovered = inverse_dct_4x4(&coeffs, BitDepth::Eight);
         for i in 0..16 {
             assert!(
                 (recovered[i] - original[i]).abs() <= 1,
@@ -835,7 +1153,7 @@ mod tests {
             original[i] = (i as i32) * 3;
         }
         let coeffs = forward_dct_8x8(&original);
-        let recovered = inverse_dct_8x8(&coeffs);
+        let recovered = inverse_dct_8x8(&coeffs, BitDepth::Eight);
         for i in 0..64 {
             assert!(
                 (recovered[i] - original[i]).abs() <= 1,
@@ -851,7 +1169,7 @@ mod tests {
     fn roundtrip_4x4_small_residual() {
         let original = [3, -1, 2, 0, -2, 1, -3, 4, 1, 0, -1, 2, -4, 3, 0, -2];
         let coeffs = forward_dct_4x4(&original);
-        let recovered = inverse_dct_4x4(&coeffs);
+        let recovered = inverse_dct_4x4(&coeffs, BitDepth::Eight);
         for i in 0..16 {
             assert!(
                 (recovered[i] - original[i]).abs() <= 1,
@@ -870,7 +1188,7 @@ mod tests {
             original[i] = ((i as i32 * 7 + 3) % 11) - 5;
         }
         let coeffs = forward_dct_8x8(&original);
-        let recovered = inverse_dct_8x8(&coeffs);
+        let recovered = inverse_dct_8x8(&coeffs, BitDepth::Eight);
         for i in 0..64 {
             assert!(
                 (recovered[i] - original[i]).abs() <= 1,
@@ -886,7 +1204,7 @@ mod tests {
     fn roundtrip_4x4_typical_residual() {
         let original = [-15, 8, -3, 12, 7, -20, 5, 1, -8, 14, -6, 3, 10, -2, 9, -11];
         let coeffs = forward_dct_4x4(&original);
-        let recovered = inverse_dct_4x4(&coeffs);
+        let recovered = inverse_dct_4x4(&coeffs, BitDepth::Eight);
         for i in 0..16 {
             assert!(
                 (recovered[i] - original[i]).abs() <= 1,
@@ -905,7 +1223,7 @@ mod tests {
             original[i] = ((i as i32 * 13 + 5) % 51) - 25;
         }
         let coeffs = forward_dct_8x8(&original);
-        let recovered = inverse_dct_8x8(&coeffs);
+        let recovered = inverse_dct_8x8(&coeffs, BitDepth::Eight);
         for i in 0..64 {
             assert!(
                 (recovered[i] - original[i]).abs() <= 1,
@@ -923,7 +1241,7 @@ mod tests {
             200, -150, 180, -100, 120, -200, 90, 50, -180, 160, -80, 140, 70, -120, 190, -60,
         ];
         let coeffs = forward_dct_4x4(&original);
-        let recovered = inverse_dct_4x4(&coeffs);
+        let recovered = inverse_dct_4x4(&coeffs, BitDepth::Eight);
         for i in 0..16 {
             assert!(
                 (recovered[i] - original[i]).abs() <= 1,
@@ -942,7 +1260,7 @@ mod tests {
             original[i] = ((i as i32 * 37 + 11) % 401) - 200;
         }
         let coeffs = forward_dct_8x8(&original);
-        let recovered = inverse_dct_8x8(&coeffs);
+        let recovered = inverse_dct_8x8(&coeffs, BitDepth::Eight);
         for i in 0..64 {
             assert!(
                 (recovered[i] - original[i]).abs() <= 1,
@@ -969,14 +1287,14 @@ mod tests {
         let coeffs = [
             100, 20, -10, 5, 30, -15, 8, -3, -5, 12, 0, -7, 18, -9, 4, -2,
         ];
-        let result = inverse_dct_4x4(&coeffs);
+        let result = inverse_dct_4x4(&coeffs, BitDepth::Eight);
         let mut buf = coeffs;
         transpose_4x4(&mut buf);
         for row in 0..4 {
-            inv_dct4_1d(&mut buf, row * 4, 1);
+            inv_dct4_1d(&mut buf, row * 4, 1, 16);
         }
         for col in 0..4 {
-            inv_dct4_1d(&mut buf, col, 4);
+            inv_dct4_1d(&mut buf, col, 4, 16);
         }
         for i in 0..16 {
             assert_eq!(result[i], (buf[i] + 8) >> 4);
@@ -989,17 +1307,17 @@ mod tests {
         for i in 0..64 {
             coeffs[i] = ((i as i32 * 11 + 3) % 41) - 20;
         }
-        let result = inverse_dct_8x8(&coeffs);
+        let result = inverse_dct_8x8(&coeffs, BitDepth::Eight);
         let mut buf = coeffs;
         transpose_8x8(&mut buf);
         for row in 0..8 {
-            inv_dct8_1d(&mut buf, row * 8, 1);
+            inv_dct8_1d(&mut buf, row * 8, 1, 16);
         }
         for i in 0..64 {
             buf[i] = (buf[i] + 1) >> 1;
         }
         for col in 0..8 {
-            inv_dct8_1d(&mut buf, col, 8);
+            inv_dct8_1d(&mut buf, col, 8, 16);
         }
         for i in 0..64 {
             assert_eq!(result[i], (buf[i] + 8) >> 4);
@@ -1025,7 +1343,7 @@ mod tests {
         let mut original = [0i32; 16];
         original[0] = 100;
         let coeffs = forward_dct_4x4(&original);
-        let recovered = inverse_dct_4x4(&coeffs);
+        let recovered = inverse_dct_4x4(&coeffs, BitDepth::Eight);
         for i in 0..16 {
             assert!(
                 (recovered[i] - original[i]).abs() <= 1,
@@ -1042,7 +1360,7 @@ mod tests {
         let mut original = [0i32; 64];
         original[0] = 100;
         let coeffs = forward_dct_8x8(&original);
-        let recovered = inverse_dct_8x8(&coeffs);
+        let recovered = inverse_dct_8x8(&coeffs, BitDepth::Eight);
         for i in 0..64 {
             assert!(
                 (recovered[i] - original[i]).abs() <= 1,
@@ -1060,7 +1378,7 @@ mod tests {
             -50, -30, -10, -70, -20, -60, -40, -80, -5, -15, -25, -35, -45, -55, -65, -75,
         ];
         let coeffs = forward_dct_4x4(&original);
-        let recovered = inverse_dct_4x4(&coeffs);
+        let recovered = inverse_dct_4x4(&coeffs, BitDepth::Eight);
         for i in 0..16 {
             assert!(
                 (recovered[i] - original[i]).abs() <= 1,
@@ -1078,7 +1396,7 @@ mod tests {
             100, -100, 100, -100, -100, 100, -100, 100, 100, -100, 100, -100, -100, 100, -100, 100,
         ];
         let coeffs = forward_dct_4x4(&original);
-        let recovered = inverse_dct_4x4(&coeffs);
+        let recovered = inverse_dct_4x4(&coeffs, BitDepth::Eight);
         for i in 0..16 {
             assert!(
                 (recovered[i] - original[i]).abs() <= 1,
@@ -1099,7 +1417,7 @@ mod tests {
             }
         }
         let coeffs = forward_dct_8x8(&original);
-        let recovered = inverse_dct_8x8(&coeffs);
+        let recovered = inverse_dct_8x8(&coeffs, BitDepth::Eight);
         for i in 0..64 {
             assert!(
                 (recovered[i] - original[i]).abs() <= 1,
@@ -1121,7 +1439,7 @@ mod tests {
             10, 20, 30, 40, 50, 60, 70, 80, 90, 100, 110, 120, 130, 140, 150, 160,
         ];
         let fwd = forward_transform_4x4(&signal, TxType::AdstAdst);
-        let inv = inverse_transform_4x4(&fwd, TxType::AdstAdst);
+        let inv = inverse_transform_4x4(&fwd, TxType::AdstAdst, BitDepth::Eight);
         for i in 0..16 {
             assert!(
                 (signal[i] - inv[i]).abs() <= 1,
@@ -1143,7 +1461,7 @@ mod tests {
             signal[i] = (i as i32) * 3 - 90;
         }
         let fwd = forward_transform_8x8(&signal, TxType::AdstAdst);
-        let inv = inverse_transform_8x8(&fwd, TxType::AdstAdst);
+        let inv = inverse_transform_8x8(&fwd, TxType::AdstAdst, BitDepth::Eight);
         for i in 0..64 {
             assert!(
                 (signal[i] - inv[i]).abs() <= 2,
@@ -1160,7 +1478,7 @@ mod tests {
             10, 20, 30, 40, 50, 60, 70, 80, 90, 100, 110, 120, 130, 140, 150, 160,
         ];
         let fwd = forward_transform_4x4(&signal, TxType::Idtx);
-        let inv = inverse_transform_4x4(&fwd, TxType::Idtx);
+        let inv = inverse_transform_4x4(&fwd, TxType::Idtx, BitDepth::Eight);
         for i in 0..16 {
             assert!(
                 (signal[i] - inv[i]).abs() <= 1,
@@ -1178,7 +1496,7 @@ mod tests {
             signal[i] = (i as i32) * 2 - 60;
         }
         let fwd = forward_transform_8x8(&signal, TxType::Idtx);
-        let inv = inverse_transform_8x8(&fwd, TxType::Idtx);
+        let inv = inverse_transform_8x8(&fwd, TxType::Idtx, BitDepth::Eight);
         for i in 0..64 {
             assert!(
                 (signal[i] - inv[i]).abs() <= 2,
@@ -1197,7 +1515,7 @@ mod tests {
         }
         for tx in [TxType::AdstDct, TxType::DctAdst] {
             let fwd = forward_transform_8x8(&signal, tx);
-            let inv = inverse_transform_8x8(&fwd, tx);
+            let inv = inverse_transform_8x8(&fwd, tx, BitDepth::Eight);
             for i in 0..64 {
                 assert!(
                     (signal[i] - inv[i]).abs() <= 2,
@@ -1239,7 +1557,7 @@ mod tests {
 
     fn assert_roundtrip_16x16(original: &[i32; 256]) {
         let coeffs = forward_transform_16x16(original);
-        let recovered = inverse_transform_16x16(&coeffs);
+        let recovered = inverse_transform_16x16(&coeffs, BitDepth::Eight);
         for (i, (&orig, &rec)) in original.iter().zip(recovered.iter()).enumerate() {
             assert!(
                 (rec - orig).abs() <= 2,
@@ -1291,4 +1609,130 @@ mod tests {
         original[0] = 100;
         assert_roundtrip_16x16(&original);
     }
+
+    /// Deterministic pseudo-random values in `-range..=range`.
+    fn noise(len: usize, seed: u32, range: i32) -> Vec<i32> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                ((state >> 16) as i32 % (2 * range + 1)) - range
+            })
+            .collect()
+    }
+
+    #[test]
+    fn generic_inverse_dct_matches_16_point() {
+        for seed in 0..500 {
+            let input = noise(16, seed, 3000);
+            let mut reference = input.clone();
+            inv_dct16_1d(&mut reference, 0, 1, 16);
+            let mut generic = input;
+            inv_dct_1d_n(&mut generic, 0, 1, 16, 16);
+            assert_eq!(generic, reference, "seed {seed}");
+        }
+    }
+
+    #[test]
+    fn inverse_dct_32_and_64_follow_the_cosine_basis() {
+        for n in [32usize, 64] {
+            for seed in 0..50 {
+                let input = noise(n, seed, 300);
+                let mut out = input.clone();
+                inv_dct_1d_n(&mut out, 0, 1, n, 16);
+                for (x, &got) in out.iter().enumerate() {
+                    let exact = input[0] as f64 / 2f64.sqrt()
+                        + (1..n)
+                            .map(|k| {
+                                input[k] as f64
+                                    * (std::f64::consts::PI * ((2 * x + 1) * k) as f64
+                                        / (2 * n) as f64)
+                                        .cos()
+                            })
+                            .sum::<f64>();
+                    assert!((got as f64 - exact).abs() < 8.0, "n={n} x={x}");
+                }
+            }
+        }
+    }
+
+    fn assert_roundtrip_32x32(original: &[i32; 1024]) {
+        let recovered =
+            inverse_transform_32x32(&forward_transform_32x32(original), BitDepth::Eight);
+        for (i, (&orig, &rec)) in original.iter().zip(recovered.iter()).enumerate() {
+            assert!((rec - orig).abs() <= 2, "pixel {i}: {orig} -> {rec}");
+        }
+    }
+
+    #[test]
+    fn roundtrip_32x32() {
+        assert_roundtrip_32x32(&[42i32; 1024]);
+        let mut gradient = [0i32; 1024];
+        for (i, v) in gradient.iter_mut().enumerate() {
+            *v = (i % 32) as i32 * 3 - (i / 32) as i32 * 2;
+        }
+        assert_roundtrip_32x32(&gradient);
+        let mut residual = [0i32; 1024];
+        residual.copy_from_slice(&noise(1024, 7, 60));
+        assert_roundtrip_32x32(&residual);
+    }
+
+    #[test]
+    fn dc_only_32x32() {
+        let coeffs = forward_transform_32x32(&[100i32; 1024]);
+        assert_ne!(coeffs[0], 0);
+        assert!(coeffs[1..].iter().all(|&c| c == 0));
+    }
+
+    #[test]
+    fn transform_64x64_keeps_the_low_frequencies() {
+        let mut smooth = [0i32; 4096];
+        for (i, v) in smooth.iter_mut().enumerate() {
+            let (r, c) = ((i / 64) as i32, (i % 64) as i32);
+            *v = (r - 32) + 2 * (c - 32) / 3 + 20;
+        }
+        let coeffs = forward_transform_64x64(&smooth);
+        for (i, &c) in coeffs.iter().enumerate() {
+            if i % 64 >= 32 || i / 64 >= 32 {
+                assert_eq!(c, 0, "coefficient {i}");
+            }
+        }
+        let recovered = inverse_transform_64x64(&coeffs, BitDepth::Eight);
+        for (i, (&orig, &rec)) in smooth.iter().zip(recovered.iter()).enumerate() {
+            assert!((rec - orig).abs() <= 4, "pixel {i}: {orig} -> {rec}");
+        }
+
+        let dc = forward_transform_64x64(&[-30i32; 4096]);
+        assert_eq!(
+            inverse_transform_64x64(&dc, BitDepth::Eight),
+            [-30i32; 4096]
+        );
+    }
+
+    #[test]
+    fn ten_bit_inverse_keeps_full_range_residuals() {
+        let coeffs = forward_dct_8x8(&[-1023i32; 64]);
+        let near = |v: &i32| (v + 1023).abs() <= 1;
+        assert!(inverse_dct_8x8(&coeffs, BitDepth::Ten).iter().all(near));
+        assert!(!inverse_dct_8x8(&coeffs, BitDepth::Eight).iter().all(near));
+    }
+
+    #[test]
+    fn wht_4x4_roundtrip_is_exact() {
+        let mut original = [0i32; 16];
+        for i in 0..16 {
+            original[i] = ((i as i32 * 97 + 13) % 511) - 255;
+        }
+        let coeffs = forward_wht_4x4(&original);
+        assert!(coeffs.iter().all(|c| c % WHT_UNIT_QUANT == 0));
+        assert_eq!(inverse_wht_4x4(&coeffs), original);
+    }
+
+    #[test]
+    fn wht_4x4_dc_only_for_constant_residual() {
+        let coeffs = forward_wht_4x4(&[-7i32; 16]);
+        assert_ne!(coeffs[0], 0);
+        assert!(coeffs[1..].iter().all(|&c| c == 0));
+        assert_eq!(inverse_wht_4x4(&coeffs), [-7i32; 16]);
+    }
 }
@@ -1,9 +1,114 @@
 use crate::video::BitDepth;
 
+/// Quantizer steps and rounding offsets for one chroma plane.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlaneQuant {
+    pub dc: u32,
+    pub ac: u32,
+    pub dc_round: u32,
+    pub ac_round: u32,
+}
+
+/// Chroma quantizer index offsets relative to `base_q_idx`, as coded in the
+/// frame header (`DeltaQUDc`, `DeltaQUAc`, `DeltaQVDc`, `DeltaQVAc`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChromaDeltaQ {
+    pub u_dc: i8,
+    pub u_ac: i8,
+    pub v_dc: i8,
+    pub v_ac: i8,
+}
+
+impl ChromaDeltaQ {
+    /// Range of a 7-bit signed `delta_q` field.
+    pub const MIN: i8 = -64;
+    pub const MAX: i8 = 63;
+
+    pub fn is_valid(&self) -> bool {
+        [self.u_dc, self.u_ac, self.v_dc, self.v_ac]
+            .iter()
+            .all(|d| (Self::MIN..=Self::MAX).contains(d))
+    }
+
+    /// Whether V needs its own deltas, which requires `separate_uv_delta_q`
+    /// in the sequence header.
+    pub fn separate_uv(&self) -> bool {
+        (self.u_dc, self.u_ac) != (self.v_dc, self.v_ac)
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct DequantValues {
     pub dc: u32,
     pub ac: u32,
+    /// Rounding offsets added before dividing by the step; `step / 2` is
+    /// plain rounding, smaller values widen the dead zone around zero.
+    pub dc_round: u32,
+    pub ac_round: u32,
+    /// Transform blocks whose quantized levels are all +/-1 and number at
+    /// most this many are zeroed so the block can be coded as skip.
+    pub skip_bias: u32,
+    /// Deltas the U/V steps below were derived from, for the frame header.
+    pub delta_q: ChromaDeltaQ,
+    /// U and V steps; equal to luma's unless `delta_q` is non-zero.
+    pub chroma: [PlaneQuant; 2],
+}
+
+/// Quantizer dead zone, as rounding offsets in 1/256 of a quantizer step
+/// (128 = round to nearest), separately for intra/inter and DC/AC, plus a
+/// skip bias (see `DequantValues::skip_bias`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeadZone {
+    pub intra_dc: u8,
+    pub intra_ac: u8,
+    pub inter_dc: u8,
+    pub inter_ac: u8,
+    pub skip_bias: u8,
+}
+
+impl Default for DeadZone {
+    fn default() -> Self {
+        Self {
+            intra_dc: 128,
+            intra_ac: 128,
+            inter_dc: 128,
+            inter_ac: 128,
+            skip_bias: 0,
+        }
+    }
+}
+
+impl DequantValues {
+    pub fn with_dead_zone(mut self, dead_zone: &DeadZone, intra: bool) -> Self {
+        let (dc_frac, ac_frac) = if intra {
+            (dead_zone.intra_dc, dead_zone.intra_ac)
+        } else {
+            (dead_zone.inter_dc, dead_zone.inter_ac)
+        };
+        self.dc_round = (self.dc * dc_frac as u32) >> 8;
+        self.ac_round = (self.ac * ac_frac as u32) >> 8;
+        for plane in &mut self.chroma {
+            plane.dc_round = (plane.dc * dc_frac as u32) >> 8;
+            plane.ac_round = (plane.ac * ac_frac as u32) >> 8;
+        }
+        self.skip_bias = dead_zone.skip_bias as u32;
+        self
+    }
+
+    /// Values to quantize `plane` (0 = Y, 1 = U, 2 = V) with.
+    pub fn for_plane(&self, plane: usize) -> Self {
+        if plane == 0 {
+            return *self;
+        }
+        let q = self.chroma[plane - 1];
+        Self {
+            dc: q.dc,
+            ac: q.ac,
+            dc_round: q.dc_round,
+            ac_round: q.ac_round,
+            ..*self
+        }
+    }
 }
 
 const DQ_TABLE_8BIT: [[u16; 2]; 256] = [
@@ -265,17 +370,311 @@ const DQ_TABLE_8BIT: [[u16; 2]; 256] = [
     [1336, 1828],
 ];
 
+const DQ_TABLE_10BIT: [[u16; 2]; 256] = [
+    [4, 4],
+    [9, 9],
+    [10, 11],
+    [13, 13],
+    [15, 16],
+    [17, 18],
+    [20, 21],
+    [22, 24],
+    [25, 27],
+    [28, 30],
+    [31, 33],
+    [34, 37],
+    [37, 40],
+    [40, 44],
+    [43, 48],
+    [47, 51],
+    [50, 55],
+    [53, 59],
+    [57, 63],
+    [60, 67],
+    [64, 71],
+    [68, 75],
+    [71, 79],
+    [75, 83],
+    [78, 88],
+    [82, 92],
+    [86, 96],
+    [90, 100],
+    [93, 105],
+    [97, 109],
+    [101, 114],
+    [105, 118],
+    [109, 122],
+    [113, 127],
+    [116, 131],
+    [120, 136],
+    [124, 140],
+    [128, 145],
+    [132, 149],
+    [136, 154],
+    [140, 158],
+    [143, 163],
+    [147, 168],
+    [151, 172],
+    [155, 177],
+    [159, 181],
+    [163, 186],
+    [166, 190],
+    [170, 195],
+    [174, 199],
+    [178, 204],
+    [182, 208],
+    [185, 213],
+    [189, 217],
+    [193, 222],
+    [197, 226],
+    [200, 231],
+    [204, 235],
+    [208, 240],
+    [212, 244],
+    [215, 249],
+    [219, 253],
+    [223, 258],
+    [226, 262],
+    [230, 267],
+    [233, 271],
+    [237, 275],
+    [241, 280],
+    [244, 284],
+    [248, 289],
+    [251, 293],
+    [255, 297],
+    [259, 302],
+    [262, 306],
+    [266, 311],
+    [269, 315],
+    [273, 319],
+    [276, 324],
+    [280, 328],
+    [283, 332],
+    [287, 337],
+    [290, 341],
+    [293, 345],
+    [297, 349],
+    [300, 354],
+    [304, 358],
+    [307, 362],
+    [310, 367],
+    [314, 371],
+    [317, 375],
+    [321, 379],
+    [324, 384],
+    [327, 388],
+    [331, 392],
+    [334, 396],
+    [337, 401],
+    [343, 409],
+    [350, 417],
+    [356, 425],
+    [362, 433],
+    [369, 441],
+    [375, 449],
+    [381, 458],
+    [387, 466],
+    [394, 474],
+    [400, 482],
+    [406, 490],
+    [412, 498],
+    [418, 506],
+    [424, 514],
+    [430, 523],
+    [436, 531],
+    [442, 539],
+    [448, 547],
+    [454, 555],
+    [460, 563],
+    [466, 571],
+    [472, 579],
+    [478, 588],
+    [484, 596],
+    [490, 604],
+    [499, 616],
+    [507, 628],
+    [516, 640],
+    [525, 652],
+    [533, 664],
+    [542, 676],
+    [550, 688],
+    [559, 700],
+    [567, 713],
+    [576, 725],
+    [584, 737],
+    [592, 749],
+    [601, 761],
+    [609, 773],
+    [617, 785],
+    [625, 797],
+    [634, 809],
+    [644, 825],
+    [655, 841],
+    [666, 857],
+    [676, 873],
+    [687, 889],
+    [698, 905],
+    [708, 922],
+    [718, 938],
+    [729, 954],
+    [739, 970],
+    [749, 986],
+    [759, 1002],
+    [770, 1018],
+    [782, 1038],
+    [795, 1058],
+    [807, 1078],
+    [819, 1098],
+    [831, 1118],
+    [844, 1138],
+    [856, 1158],
+    [868, 1178],
+    [880, 1198],
+    [891, 1218],
+    [906, 1242],
+    [920, 1266],
+    [933, 1290],
+    [947, 1314],
+    [961, 1338],
+    [975, 1362],
+    [988, 1386],
+    [1001, 1411],
+    [1015, 1435],
+    [1030, 1463],
+    [1045, 1491],
+    [1061, 1519],
+    [1076, 1547],
+    [1090, 1575],
+    [1105, 1603],
+    [1120, 1631],
+    [1137, 1663],
+    [1153, 1695],
+    [1170, 1727],
+    [1186, 1759],
+    [1202, 1791],
+    [1218, 1823],
+    [1236, 1859],
+    [1253, 1895],
+    [1271, 1931],
+    [1288, 1967],
+    [1306, 2003],
+    [1323, 2039],
+    [1342, 2079],
+    [1361, 2119],
+    [1379, 2159],
+    [1398, 2199],
+    [1416, 2239],
+    [1436, 2283],
+    [1456, 2327],
+    [1476, 2371],
+    [1496, 2415],
+    [1516, 2459],
+    [1537, 2507],
+    [1559, 2555],
+    [1580, 2603],
+    [1601, 2651],
+    [1624, 2703],
+    [1647, 2755],
+    [1670, 2807],
+    [1692, 2859],
+    [1717, 2915],
+    [1741, 2971],
+    [1766, 3027],
+    [1791, 3083],
+    [1817, 3143],
+    [1844, 3203],
+    [1871, 3263],
+    [1900, 3327],
+    [1929, 3391],
+    [1958, 3455],
+    [1990, 3523],
+    [2021, 3591],
+    [2054, 3659],
+    [2088, 3731],
+    [2123, 3803],
+    [2159, 3876],
+    [2197, 3952],
+    [2236, 4028],
+    [2276, 4104],
+    [2319, 4184],
+    [2363, 4264],
+    [2410, 4348],
+    [2458, 4432],
+    [2508, 4516],
+    [2561, 4604],
+    [2616, 4692],
+    [2675, 4784],
+    [2737, 4876],
+    [2802, 4972],
+    [2871, 5068],
+    [2944, 5168],
+    [3020, 5268],
+    [3102, 5372],
+    [3188, 5476],
+    [3280, 5584],
+    [3375, 5692],
+    [3478, 5804],
+    [3586, 5916],
+    [3702, 6032],
+    [3823, 6148],
+    [3953, 6268],
+    [4089, 6388],
+    [4236, 6512],
+    [4394, 6640],
+    [4559, 6768],
+    [4737, 6900],
+    [4929, 7036],
+    [5130, 7172],
+    [5347, 7312],
+];
+
+/// Whether a frame coded with these parameters is lossless (`CodedLossless`):
+/// qindex 0 with no chroma offsets, which implies 4x4 Walsh-Hadamard
+/// transforms and disables the loop filter and CDEF.
+pub fn is_lossless(base_q_idx: u8, delta_q: &ChromaDeltaQ) -> bool {
+    base_q_idx == 0 && *delta_q == ChromaDeltaQ::default()
+}
+
 pub fn lookup_dequant(base_q_idx: u8, bit_depth: BitDepth) -> DequantValues {
-    let entry = DQ_TABLE_8BIT[base_q_idx as usize];
-    let mut out = DequantValues {
-        dc: entry[0] as u32,
-        ac: entry[1] as u32,
+    lookup_dequant_with_delta(base_q_idx, bit_depth, ChromaDeltaQ::default())
+}
+
+pub fn lookup_dequant_with_delta(
+    base_q_idx: u8,
+    bit_depth: BitDepth,
+    delta_q: ChromaDeltaQ,
+) -> DequantValues {
+    let table = match bit_depth {
+        BitDepth::Eight => &DQ_TABLE_8BIT,
+        BitDepth::Ten => &DQ_TABLE_10BIT,
+    };
+    let step = |delta: i8, col: usize| {
+        let q_idx = (base_q_idx as i32 + delta as i32).clamp(0, 255) as usize;
+        table[q_idx][col] as u32
     };
-    if bit_depth == BitDepth::Ten {
-        out.dc *= 4;
-        out.ac *= 4;
+    let plane = |dc_delta: i8, ac_delta: i8| {
+        let (dc, ac) = (step(dc_delta, 0), step(ac_delta, 1));
+        PlaneQuant {
+            dc,
+            ac,
+            dc_round: dc / 2,
+            ac_round: ac / 2,
+        }
+    };
+    let luma = plane(0, 0);
+    DequantValues {
+        dc: luma.dc,
+        ac: luma.ac,
+        dc_round: luma.dc_round,
+        ac_round: luma.ac_round,
+        skip_bias: 0,
+        delta_q,
+        chroma: [
+            plane(delta_q.u_dc, delta_q.u_ac),
+            plane(delta_q.v_dc, delta_q.v_ac),
+        ],
     }
-    out
 }
 
 #[cfg(test)]
@@ -289,6 +688,32 @@ mod tests {
         assert_eq!(dq.ac, 176);
     }
 
+    #[test]
+    fn default_dead_zone_is_round_to_nearest() {
+        let dq = lookup_dequant(128, BitDepth::Eight);
+        let dz = dq.with_dead_zone(&DeadZone::default(), true);
+        assert_eq!((dz.dc_round, dz.ac_round), (dq.dc / 2, dq.ac / 2));
+        assert_eq!(dz.skip_bias, 0);
+    }
+
+    #[test]
+    fn dead_zone_differs_for_intra_and_inter() {
+        let dead_zone = DeadZone {
+            intra_dc: 128,
+            intra_ac: 96,
+            inter_dc: 96,
+            inter_ac: 64,
+            skip_bias: 2,
+        };
+        let dq = lookup_dequant(128, BitDepth::Eight);
+        let intra = dq.with_dead_zone(&dead_zone, true);
+        let inter = dq.with_dead_zone(&dead_zone, false);
+        assert_eq!(intra.ac_round, dq.ac * 96 / 256);
+        assert_eq!(inter.dc_round, dq.dc * 96 / 256);
+        assert_eq!(inter.ac_round, dq.ac / 4);
+        assert_eq!(inter.skip_bias, 2);
+    }
+
     #[test]
     fn q0_is_lowest_dequant() {
         let dq = lookup_dequant(0, BitDepth::Eight);
@@ -314,10 +739,59 @@ mod tests {
     }
 
     #[test]
-    fn ten_bit_scales_up_from_eight_bit() {
+    fn ten_bit_uses_its_own_table() {
         let dq8 = lookup_dequant(128, BitDepth::Eight);
         let dq10 = lookup_dequant(128, BitDepth::Ten);
-        assert_eq!(dq10.dc, dq8.dc * 4);
-        assert_eq!(dq10.ac, dq8.ac * 4);
+        assert_eq!((dq10.dc, dq10.ac), (559, 700));
+        assert_ne!(dq10.dc, dq8.dc * 4);
+        let dq10 = lookup_dequant(255, BitDepth::Ten);
+        assert_eq!((dq10.dc, dq10.ac), (5347, 7312));
+    }
+
+    #[test]
+    fn chroma_deltas_offset_the_plane_q_index() {
+        let delta = ChromaDeltaQ {
+            u_dc: -8,
+            u_ac: 4,
+            v_dc: 0,
+            v_ac: -64,
+        };
+        let dq = lookup_dequant_with_delta(128, BitDepth::Eight, delta);
+        let luma = lookup_dequant(128, BitDepth::Eight);
+        assert_eq!((dq.dc, dq.ac), (luma.dc, luma.ac));
+        let u = dq.for_plane(1);
+        assert_eq!(u.dc, lookup_dequant(120, BitDepth::Eight).dc);
+        assert_eq!(u.ac, lookup_dequant(132, BitDepth::Eight).ac);
+        let v = dq.for_plane(2);
+        assert_eq!(v.dc, luma.dc);
+        assert_eq!(v.ac, lookup_dequant(64, BitDepth::Eight).ac);
+        assert_eq!(v.ac_round, v.ac / 2);
+    }
+
+    #[test]
+    fn chroma_delta_q_index_is_clamped() {
+        let delta = ChromaDeltaQ {
+            u_dc: 63,
+            u_ac: 63,
+            v_dc: -64,
+            v_ac: -64,
+        };
+        let dq = lookup_dequant_with_delta(250, BitDepth::Eight, delta);
+        assert_eq!(dq.for_plane(1).ac, lookup_dequant(255, BitDepth::Eight).ac);
+        let dq = lookup_dequant_with_delta(10, BitDepth::Eight, delta);
+        assert_eq!(dq.for_plane(2).dc, lookup_dequant(0, BitDepth::Eight).dc);
+    }
+
+    #[test]
+    fn zero_chroma_delta_matches_luma() {
+        let dq = lookup_dequant(200, BitDepth::Ten).with_dead_zone(&DeadZone::default(), false);
+        for plane in 1..=2 {
+            let c = dq.for_plane(plane);
+            assert_eq!(
+                (c.dc, c.ac, c.dc_round, c.ac_round),
+                (dq.dc, dq.ac, dq.dc_round, dq.ac_round)
+            );
+        }
+        assert!(!ChromaDeltaQ::default().separate_uv());
     }
 }
@@ -1,18 +1,31 @@
+use std::borrow::Cow;
+
 use crate::EncodeConfig;
-use crate::dequant;
+use crate::analysis::{self, FrameComplexity};
+use crate::aq::{self, AqMode, CyclicRefresh};
+use crate::dequant::{self, ChromaDeltaQ, DeadZone};
 use crate::error::EncoderError;
 use crate::fps::Fps;
 use crate::frame;
-use crate::metadata;
+use crate::limits;
+use crate::metadata::{self, MetadataCadence};
+use crate::metrics::{self, DistortionMap};
 use crate::obu;
-use crate::packet::{FrameType, Packet};
-use crate::rc::RateControl;
+use crate::packet::{FrameType, Packet, PacketFrame, PacketPool, PacketPoolStats};
+use crate::pixel_format::{self, PixelFormat};
+use crate::preset::Tune;
+use crate::preview;
+use crate::projection::{self, Projection};
+use crate::rc::{FrameBudget, RateControl, RateController, RcFrame};
 use crate::sequence;
+use crate::temporal_filter;
+use crate::tile;
+use crate::timebase::CaptureClock;
+use crate::two_pass::{FirstPassStats, TwoPass};
 use crate::video::{ContentLightLevel, MasteringDisplayMetadata, VideoSignal};
+use crate::warm_start::WarmStartState;
 use crate::y4m::FramePixels;
 
-const MAX_AV1_FRAME_DIMENSION: u32 = 1 << 16;
-
 #[derive(Debug, Clone)]
 pub struct EncoderConfig {
     pub base_q_idx: u8,
@@ -21,9 +34,105 @@ pub struct EncoderConfig {
     pub fps: Fps,
     pub b_frames: bool,
     pub gop_size: usize,
+    /// With `b_frames`, end the mini-GOP before each keyframe so no frame
+    /// references across it (the default; HLS and most players only seek
+    /// to such keyframes). Off, keyframes from `keyint` close a mini-GOP
+    /// instead: they are coded hidden, the frames displayed before them
+    /// predict from both sides, and a `show_existing_frame` displays them
+    /// and resets every reference. See `FrameType::ForwardKey`.
+    pub closed_gop: bool,
     pub video_signal: VideoSignal,
     pub content_light: Option<ContentLightLevel>,
     pub mastering_display: Option<MasteringDisplayMetadata>,
+    /// Minimum number of tile columns (rounded up to a power of two);
+    /// 0 uses only the tiles the frame size requires.
+    pub tile_columns: usize,
+    /// Minimum number of tile rows (rounded up to a power of two); 0 uses
+    /// only the tiles the frame size requires. With `frame_slices` the
+    /// larger of the two applies.
+    pub tile_rows: usize,
+    /// Threads coding the tiles of each frame at once; 0 or 1 codes them in
+    /// turn. Only frames split into several tiles gain from it, and the
+    /// output is the same for any count.
+    pub threads: usize,
+    pub dead_zone: DeadZone,
+    /// Compute per-frame complexity scores on the source frames, available
+    /// through `Encoder::receive_frame_complexity`.
+    pub complexity_analysis: bool,
+    /// Measure each coded frame's luma distortion per superblock against
+    /// the picture it was coded from, available through
+    /// `Encoder::receive_distortion_map`.
+    pub distortion_maps: bool,
+    /// Keep the coded motion vectors of each inter frame, available through
+    /// `Encoder::receive_motion_field`.
+    pub motion_fields: bool,
+    /// Downscale factor in `1..=analysis::MAX_FIRST_PASS_SCALE` for the
+    /// complexity analysis behind `complexity_analysis` and rate control; 1
+    /// analyses at full resolution. Scores stay in source units.
+    pub first_pass_scale: u32,
+    /// Chroma quantizer index offsets relative to the frame's `base_q_idx`.
+    pub chroma_delta_q: ChromaDeltaQ,
+    /// Choose CDEF strength per superblock row by comparing filtered and
+    /// unfiltered reconstruction with the source. Costs up to four extra
+    /// filter passes and one extra tile encode per frame.
+    pub cdef_search: bool,
+    /// Wiener loop restoration of the CDEF output, per restoration unit.
+    pub loop_restoration: bool,
+    /// Full-pel motion search radius against a reference one frame away.
+    /// Frames predicted across a longer display-order distance (with
+    /// B-frames, the hidden frame ending each mini-GOP) search a window
+    /// scaled by that distance, up to `tile::MAX_MV_SEARCH_RANGE`.
+    pub mv_search_range: u32,
+    /// Run the full-pel motion searches on a 3x3 blur of the source and
+    /// reference luma (`tile::smooth_luma`), so grain in high-ISO footage
+    /// does not pull the vectors off the motion. Prediction, residuals and
+    /// sub-pixel refinement still use the pictures themselves.
+    pub me_prefilter: bool,
+    /// Which temporal units repeat the HDR metadata OBUs.
+    pub metadata_cadence: MetadataCadence,
+    /// Write a user-private metadata OBU holding `Encoder::build_info` into
+    /// every temporal unit that starts with a keyframe. Off by default, as
+    /// the text changes with the encoder version.
+    pub build_info: bool,
+    /// Speed setting in `0..=tile::MAX_SPEED`: how aggressively block
+    /// searches stop once a candidate is good enough for the quantizer.
+    /// 0 runs every search. Also sets how many source frames are blended
+    /// into the hidden frame ending each B-frame mini-GOP
+    /// (`temporal_filter::frames_for_speed`).
+    pub speed: u8,
+    /// Intra-only mode with a size cap: every frame is a keyframe whose
+    /// packet is at most this many bytes, with the quantizer adapted in a
+    /// single pass starting from `base_q_idx`. Frames that do not fit even
+    /// at qindex 255 are emitted at 255. Excludes `target_bitrate` and
+    /// `frame_slices`.
+    pub intra_frame_budget: Option<u32>,
+    /// Hard cap on every packet, headers included, for transports that
+    /// cannot carry larger ones. A frame over it is re-encoded at a coarser
+    /// quantizer, whatever chose its own; one that does not fit at qindex
+    /// 255 is replaced by a repeat of the previous frame (keyframes: a flat
+    /// picture of the source's mean colour). Counted in
+    /// `Encoder::frame_size_cap_stats`. Requires B-frames and frame slices
+    /// off.
+    pub max_frame_bytes: Option<u32>,
+    /// Content the block decisions are biased for.
+    pub tune: Tune,
+    /// Split each frame into at least this many tile rows (rounded up to a
+    /// power of two) and emit every row as its own `partial` packet as soon
+    /// as it is coded, to the hook set by `Encoder::set_slice_hook` while
+    /// the rest of the frame is still being coded. 0 or 1 emits whole
+    /// frames. Requires B-frames off.
+    pub frame_slices: usize,
+    /// Per-superblock q index variation inside inter frames.
+    pub aq_mode: AqMode,
+    /// Source projection; equirectangular frames vary the q index by
+    /// superblock row, keyframes included.
+    pub projection: Projection,
+    /// Also code each frame at half the width and height into a preview
+    /// stream at this q index, taken with `Encoder::receive_preview_packet`.
+    /// See [`crate::preview`]. Must be non-zero.
+    pub preview_q_idx: Option<u8>,
+    /// First or second pass of a two-pass encode. Cannot change mid-stream.
+    pub two_pass: TwoPass,
 }
 
 impl From<&EncodeConfig> for EncoderConfig {
@@ -35,27 +144,224 @@ impl From<&EncodeConfig> for EncoderConfig {
             fps: c.fps,
             b_frames: c.b_frames,
             gop_size: c.gop_size,
+            closed_gop: c.closed_gop,
             video_signal: c.video_signal,
             content_light: c.content_light,
             mastering_display: c.mastering_display,
+            tile_columns: c.tile_columns,
+            tile_rows: c.tile_rows,
+            threads: c.threads,
+            dead_zone: c.dead_zone,
+            complexity_analysis: c.complexity_analysis,
+            distortion_maps: c.distortion_maps,
+            motion_fields: c.motion_fields,
+            first_pass_scale: c.first_pass_scale,
+            chroma_delta_q: c.chroma_delta_q,
+            cdef_search: c.cdef_search,
+            loop_restoration: c.loop_restoration,
+            mv_search_range: c.mv_search_range,
+            me_prefilter: c.me_prefilter,
+            metadata_cadence: c.metadata_cadence,
+            build_info: c.build_info,
+            speed: c.speed,
+            intra_frame_budget: c.intra_frame_budget,
+            max_frame_bytes: c.max_frame_bytes,
+            tune: c.tune,
+            frame_slices: c.frame_slices,
+            aq_mode: c.aq_mode,
+            projection: c.projection,
+            preview_q_idx: c.preview_q_idx,
+            two_pass: c.two_pass.clone(),
         }
     }
 }
 
+/// What [`EncoderConfig::max_frame_bytes`] did to keep packets under the
+/// cap, from [`Encoder::frame_size_cap_stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameSizeCapStats {
+    /// Frames re-encoded at a coarser quantizer, and then fitting.
+    pub requantized: u64,
+    /// Inter frames that did not fit at qindex 255 and were coded as a
+    /// repeat of the previous frame instead.
+    pub repeated: u64,
+    /// Keyframes that did not fit at qindex 255, and inter frames that did
+    /// not fit as a repeat either, coded as a flat keyframe of the source's
+    /// mean colour.
+    pub flattened: u64,
+}
+
+/// Lifecycle of an [`Encoder`], as reported by [`Encoder::state`].
+///
+/// `send_frame` moves `Idle` to `Encoding`; `flush` moves either to
+/// `Draining`, which becomes `Finished` once the last packet is received.
+/// `abort` goes straight to `Finished`. Once `flush` or `abort` has been
+/// called, `send_frame` and `reconfigure` fail with
+/// [`EncoderError::InvalidState`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncoderState {
+    /// No frame has been sent yet.
+    Idle,
+    /// Frames are being accepted; packets may be buffered in a mini-GOP.
+    Encoding,
+    /// `flush` has encoded every buffered frame; packets remain to be received.
+    Draining,
+    /// Every packet has been received, or the encode was aborted.
+    Finished,
+}
+
+impl std::fmt::Display for EncoderState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            EncoderState::Idle => "idle",
+            EncoderState::Encoding => "encoding",
+            EncoderState::Draining => "draining",
+            EncoderState::Finished => "finished",
+        })
+    }
+}
+
+/// Per-frame options for [`Encoder::send_frame_with_options`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameOptions {
+    /// Opaque value returned in `Packet::user_data` on the packet that
+    /// displays this frame.
+    pub user_data: Option<u64>,
+    /// Base q index for this frame, replacing the one rate control, the
+    /// intra frame budget or the B-frame offset would choose. Frame types,
+    /// references and keyframe placement are unchanged, and rate control
+    /// still accounts for the frame's size. Must be non-zero, and lossless
+    /// encoders reject it.
+    pub base_q_idx: Option<u8>,
+    /// When the frame was captured, in nanoseconds on a monotonic clock
+    /// (e.g. `CLOCK_MONOTONIC`). Mapped to `Packet::pts` and returned in
+    /// `Packet::capture_time_ns`.
+    pub capture_time_ns: Option<u64>,
+}
+
+/// A host-supplied [`RateController`]; `Debug` only names it.
+struct ExternalRateController(Box<dyn RateController>);
+
+impl std::fmt::Debug for ExternalRateController {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("RateController")
+    }
+}
+
+/// A host-supplied pre-encode hook; `Debug` only names it.
+struct PreEncodeHook(Box<dyn FnMut(&mut FramePixels) + Send>);
+
+impl std::fmt::Debug for PreEncodeHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PreEncodeHook")
+    }
+}
+
+/// A host-supplied hook taking the partial packets of sliced frames;
+/// `Debug` only names it.
+struct SliceHook(Box<dyn FnMut(Packet) + Send>);
+
+impl std::fmt::Debug for SliceHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SliceHook")
+    }
+}
+
+/// Reference slot GOLDEN is kept in.
+const GOLDEN_SLOT: u8 = 2;
+
+/// Frames between GOLDEN refreshes in the P-only path.
+const GOLDEN_FRAME_INTERVAL: u64 = 16;
+
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "inter"), allow(dead_code))]
+struct PooledReference {
+    index: u64,
+    slot: u8,
+    pixels: FramePixels,
+}
+
+/// LAST2 and GOLDEN of the P-only path.
+#[derive(Debug, Clone, Default)]
+struct ReferencePool {
+    last2: Option<PooledReference>,
+    golden: Option<PooledReference>,
+}
+
+impl ReferencePool {
+    #[cfg(feature = "inter")]
+    fn alternates(&self, last_index: u64) -> Vec<frame::AltReference<'_>> {
+        let mut offered = vec![last_index];
+        [
+            (tile::RefFrame::Last2, &self.last2),
+            (tile::RefFrame::Golden, &self.golden),
+        ]
+        .into_iter()
+        .filter_map(|(ref_frame, pooled)| {
+            let pooled = pooled.as_ref()?;
+            if offered.contains(&pooled.index) {
+                return None;
+            }
+            offered.push(pooled.index);
+            Some(frame::AltReference {
+                ref_frame,
+                slot: pooled.slot,
+                pixels: &pooled.pixels,
+            })
+        })
+        .collect()
+    }
+
+    fn golden_due(&self, index: u64) -> bool {
+        self.golden
+            .as_ref()
+            .is_none_or(|golden| index >= golden.index + GOLDEN_FRAME_INTERVAL)
+    }
+}
+
+/// Resources an encoder with given settings needs, from
+/// [`Encoder::preflight`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PreflightReport {
+    /// Worst-case bytes held in frame buffers at once: queued source
+    /// frames, references, the reconstruction in progress, and the copies
+    /// kept for CDEF search and complexity analysis when enabled.
+    pub memory_bytes: u64,
+    /// Frames that must be sent after a frame before its packet can be
+    /// received: `gop_size - 1` with B-frames, otherwise 0.
+    pub latency_frames: usize,
+    /// Size of [`Encoder::headers`]: the sequence header OBU plus any HDR
+    /// metadata OBUs.
+    pub header_bytes: usize,
+}
+
 #[derive(Debug)]
 pub struct Encoder {
     config: EncoderConfig,
     width: u32,
     height: u32,
     sequence_level_idx: u8,
+    tile_plan: frame::TilePlan,
     frame_index: u64,
     rate_ctrl: Option<RateControl>,
+    frame_budget: Option<FrameBudget>,
+    rate_controller: Option<ExternalRateController>,
+    pre_encode_hook: Option<PreEncodeHook>,
+    slice_hook: Option<SliceHook>,
+    #[cfg(feature = "mode-hook")]
+    intra_mode_hook: Option<crate::mode_hook::IntraModeHookRef>,
     reference: Option<FramePixels>,
+    // Display index of the frame held in `reference`
+    reference_index: u64,
+    // Motion vectors of the last inter frame and its signed display-order
+    // distance to its reference, seeding the next inter frame's search
+    previous_mvs: Option<(tile::MvField, i64)>,
 
     // Tracks monotonically increasing IVF timestamps
 
     // Ping-pong buffer index for reference frames (0 and 1)
     base_slot: u8,
+    ref_pool: ReferencePool,
 
     // Mini-GOP Buffering
     // Stores (frame_index, frame_pixels)
@@ -63,54 +369,321 @@ pub struct Encoder {
 
     // Output queue
     pending_packets: std::collections::VecDeque<Packet>,
+
+    // Buffers of recycled packets, reused for the next temporal units
+    packet_pool: PacketPool,
+
+    // Caller-supplied user data keyed by display frame index
+    frame_user_data: std::collections::HashMap<u64, u64>,
+
+    // Caller-supplied q index overrides keyed by display frame index
+    frame_q_idx: std::collections::HashMap<u64, u8>,
+
+    // Capture timestamps mapped onto the time base, and (pts, capture time)
+    // keyed by display frame index
+    capture_clock: CaptureClock,
+    frame_timing: std::collections::HashMap<u64, (u64, Option<u64>)>,
+
+    // Complexity analysis: previous source frame and scores not yet taken
+    last_source: Option<FramePixels>,
+    complexity_queue: std::collections::VecDeque<FrameComplexity>,
+    distortion_queue: std::collections::VecDeque<DistortionMap>,
+    motion_queue: std::collections::VecDeque<tile::MotionField>,
+
+    // Encoder of the downscaled source under `config.preview_q_idx`
+    preview: Option<Box<Encoder>>,
+
+    // Costs of the frames sent so far under `TwoPass::FirstPass`
+    first_pass: Option<FirstPassStats>,
+
+    // Lossless streams code every frame as a keyframe at qindex 0
+    lossless: bool,
+
+    // Source frames blended into the hidden frame ending each mini-GOP,
+    // following `config.speed`
+    temporal_filter_frames: usize,
+
+    // Display index forced to a keyframe because `reconfigure` changed the
+    // sequence header; that keyframe's packet carries the new header
+    sequence_header_change: Option<u64>,
+
+    // Display index of the next frame to code as an intra-only refresh
+    intra_refresh: Option<u64>,
+
+    // Superblocks the next inter frame boosts under `AqMode::CyclicRefresh`
+    cyclic_refresh: CyclicRefresh,
+
+    // Whether a temporal unit has carried the metadata OBUs yet
+    metadata_emitted: bool,
+
+    // Frames `config.max_frame_bytes` has re-encoded or replaced
+    frame_size_cap: FrameSizeCapStats,
+
+    // Set by `flush` or `abort`: no more frames will be encoded
+    end_of_stream: bool,
 }
 
 impl Encoder {
     pub fn new(width: u32, height: u32, config: EncoderConfig) -> Result<Self, EncoderError> {
-        if width == 0
-            || height == 0
-            || width > MAX_AV1_FRAME_DIMENSION
-            || height > MAX_AV1_FRAME_DIMENSION
-        {
-            return Err(EncoderError::InvalidDimensions { width, height });
-        }
+        limits::check_dimensions(width, height)?;
 
         preflight_frame_buffer_reserve(width, height)?;
 
-        if (config.content_light.is_some() || config.mastering_display.is_some())
-            && config.video_signal.bit_depth.bits() != 10
-        {
-            return Err(EncoderError::InvalidHdrMetadata {
-                reason: "HDR metadata requires 10-bit signal",
-            });
-        }
-
-        if (config.content_light.is_some() || config.mastering_display.is_some())
-            && config.video_signal.color_description.is_none()
+        validate_config(&config)?;
+        if let TwoPass::SecondPass(stats) = &config.two_pass
+            && (stats.width, stats.height) != (width, height)
         {
-            return Err(EncoderError::InvalidHdrMetadata {
-                reason: "HDR metadata requires color description signaling",
+            return Err(EncoderError::InvalidConfig {
+                reason: "first-pass stats are for a different frame size",
             });
         }
 
-        let rate_ctrl = config
-            .target_bitrate
-            .map(|bitrate| RateControl::new(bitrate, config.fps, width, height, config.keyint));
+        let rate_ctrl = builtin_rate_control(&config, width, height);
+        let frame_budget = config
+            .intra_frame_budget
+            .map(|bytes| FrameBudget::new(bytes, config.base_q_idx));
+        let lossless = rate_ctrl.is_none()
+            && frame_budget.is_none()
+            && config.max_frame_bytes.is_none()
+            && dequant::is_lossless(config.base_q_idx, &config.chroma_delta_q);
+        let temporal_filter_frames = temporal_filter::frames_for_speed(config.speed);
+        let preview = config
+            .preview_q_idx
+            .map(|q_idx| {
+                let (width, height) = preview::preview_dimensions(width, height);
+                Self::new(width, height, preview::preview_config(&config, q_idx)).map(Box::new)
+            })
+            .transpose()?;
+        let first_pass =
+            (config.two_pass == TwoPass::FirstPass).then(|| FirstPassStats::new(width, height));
 
-        Ok(Self {
+        let encoder = Self {
             sequence_level_idx: sequence::derive_sequence_level_idx(width, height, config.fps),
+            tile_plan: tile_plan(width, height, &config),
+            capture_clock: CaptureClock::new(config.fps),
             config,
             width,
             height,
             frame_index: 0,
             rate_ctrl,
+            frame_budget,
+            rate_controller: None,
+            pre_encode_hook: None,
+            slice_hook: None,
+            #[cfg(feature = "mode-hook")]
+            intra_mode_hook: None,
             reference: None,
+            reference_index: 0,
+            previous_mvs: None,
             base_slot: 0,
+            ref_pool: ReferencePool::default(),
             gop_queue: Vec::with_capacity(4),
             pending_packets: std::collections::VecDeque::new(),
+            packet_pool: PacketPool::default(),
+            frame_user_data: std::collections::HashMap::new(),
+            frame_q_idx: std::collections::HashMap::new(),
+            frame_timing: std::collections::HashMap::new(),
+            last_source: None,
+            complexity_queue: std::collections::VecDeque::new(),
+            distortion_queue: std::collections::VecDeque::new(),
+            motion_queue: std::collections::VecDeque::new(),
+            preview,
+            first_pass,
+            lossless,
+            temporal_filter_frames,
+            sequence_header_change: None,
+            intra_refresh: None,
+            cyclic_refresh: CyclicRefresh::default(),
+            metadata_emitted: false,
+            frame_size_cap: FrameSizeCapStats::default(),
+            end_of_stream: false,
+        };
+        if let Some(max_frame_bytes) = encoder.config.max_frame_bytes {
+            let min_bytes = encoder.smallest_keyframe_packet();
+            if (max_frame_bytes as usize) < min_bytes {
+                return Err(EncoderError::FrameSizeCapTooSmall {
+                    max_frame_bytes,
+                    min_bytes,
+                });
+            }
+        }
+        Ok(encoder)
+    }
+
+    /// Applies `config` to frames sent from now on.
+    ///
+    /// Frames still buffered for a B-frame mini-GOP are encoded first with
+    /// the old settings. The video signal (bit depth, range, colour
+    /// description) is fixed for the stream. If the new settings change the
+    /// sequence header (e.g. a new level from a different frame rate, or
+    /// separate chroma delta-q), the next frame is coded as a keyframe and its
+    /// packet carries the new header in `Packet::sequence_header`. Rate
+    /// control restarts when the bitrate, frame rate or keyframe interval
+    /// changes.
+    pub fn reconfigure(&mut self, config: EncoderConfig) -> Result<(), EncoderError> {
+        self.ensure_accepting_frames("reconfigure")?;
+        validate_config(&config)?;
+        if self.rate_controller.is_some() {
+            validate_external_rate_control(&config)?;
+        }
+        if config.video_signal != self.config.video_signal {
+            return Err(EncoderError::InvalidConfig {
+                reason: "video signal cannot change mid-stream",
+            });
+        }
+        if config.two_pass != self.config.two_pass {
+            return Err(EncoderError::InvalidConfig {
+                reason: "two-pass mode cannot change mid-stream",
+            });
+        }
+        if config.preview_q_idx.is_some() != self.preview.is_some() {
+            return Err(EncoderError::InvalidConfig {
+                reason: "preview stream cannot be turned on or off mid-stream",
+            });
+        }
+        if config.max_frame_bytes.is_some() {
+            // Checks the cap against the new headers and tile layout.
+            Self::new(self.width, self.height, config.clone())?;
+        }
+        if let (Some(preview), Some(q_idx)) = (&mut self.preview, config.preview_q_idx) {
+            preview.reconfigure(preview::preview_config(&config, q_idx))?;
+        }
+
+        self.encode_gop();
+
+        let old_header = self.sequence_header(false);
+        let restart_rc = config.target_bitrate != self.config.target_bitrate
+            || config.fps != self.config.fps
+            || config.keyint != self.config.keyint;
+        if restart_rc {
+            self.rate_ctrl = builtin_rate_control(&config, self.width, self.height);
+        }
+        self.sequence_level_idx =
+            sequence::derive_sequence_level_idx(self.width, self.height, config.fps);
+        self.capture_clock.set_fps(config.fps);
+        self.tile_plan = tile_plan(self.width, self.height, &config);
+        if config.intra_frame_budget != self.config.intra_frame_budget {
+            self.frame_budget = config
+                .intra_frame_budget
+                .map(|bytes| FrameBudget::new(bytes, config.base_q_idx));
+        }
+        self.lossless = self.rate_ctrl.is_none()
+            && self.frame_budget.is_none()
+            && config.max_frame_bytes.is_none()
+            && self.rate_controller.is_none()
+            && dequant::is_lossless(config.base_q_idx, &config.chroma_delta_q);
+        self.temporal_filter_frames = temporal_filter::frames_for_speed(config.speed);
+        self.config = config;
+
+        if self.sequence_header(false) != old_header {
+            self.sequence_header_change = Some(self.frame_index);
+            if let Some(preview) = &mut self.preview {
+                preview.sequence_header_change = Some(preview.frame_index);
+            }
+        }
+        Ok(())
+    }
+
+    /// Codes the next frame sent as an intra-only frame: it is predicted
+    /// from nothing, like a keyframe, but only replaces the references the
+    /// following frames predict from. The decoder keeps its state and the
+    /// stream gains no random access point, so this is the cheap way to
+    /// recover quality mid-GOP, e.g. after packet loss reported by the
+    /// receiver. A frame that is a keyframe anyway stays one.
+    ///
+    /// Requires `b_frames` off, where each frame references the ones before.
+    pub fn request_intra_refresh(&mut self) -> Result<(), EncoderError> {
+        self.ensure_accepting_frames("request_intra_refresh")?;
+        if self.config.b_frames {
+            return Err(EncoderError::InvalidConfig {
+                reason: "intra refresh requires b_frames off",
+            });
+        }
+        self.intra_refresh = Some(self.frame_index);
+        if let Some(preview) = &mut self.preview {
+            preview.intra_refresh = Some(preview.frame_index);
+        }
+        Ok(())
+    }
+
+    /// Checks `width`, `height` and `config` as [`Encoder::new`] would,
+    /// without keeping an encoder, and reports what one would need.
+    pub fn preflight(
+        width: u32,
+        height: u32,
+        config: &EncoderConfig,
+    ) -> Result<PreflightReport, EncoderError> {
+        let encoder = Self::new(width, height, config.clone())?;
+        let luma = u64::from(width) * u64::from(height);
+        let chroma = u64::from(width.div_ceil(2)) * u64::from(height.div_ceil(2));
+        let frame_bytes = (luma + 2 * chroma) * std::mem::size_of::<u16>() as u64;
+        let (queued, references) = if config.b_frames {
+            (config.gop_size as u64, 2)
+        } else if cfg!(feature = "inter") {
+            (1, 3)
+        } else {
+            (1, 1)
+        };
+        let frames = queued
+            + references
+            + 1
+            + u64::from(config.tune.cdef_search(config.cdef_search))
+            + 2 * u64::from(config.loop_restoration)
+            + u64::from(config.complexity_analysis || config.two_pass == TwoPass::FirstPass);
+        let preview_bytes = match config.preview_q_idx {
+            Some(q_idx) => {
+                let (width, height) = preview::preview_dimensions(width, height);
+                Self::preflight(width, height, &preview::preview_config(config, q_idx))?
+                    .memory_bytes
+            }
+            None => 0,
+        };
+        Ok(PreflightReport {
+            memory_bytes: frames * frame_bytes + preview_bytes,
+            latency_frames: if config.b_frames {
+                config.gop_size.saturating_sub(1)
+            } else {
+                0
+            },
+            header_bytes: encoder.headers().len(),
         })
     }
 
+    /// Creates an encoder that reconstructs every frame bit-exactly: qindex 0
+    /// with no chroma offsets or rate control, intra-only.
+    pub fn new_lossless(
+        width: u32,
+        height: u32,
+        mut config: EncoderConfig,
+    ) -> Result<Self, EncoderError> {
+        config.base_q_idx = 0;
+        config.target_bitrate = None;
+        config.chroma_delta_q = ChromaDeltaQ::default();
+        Self::new(width, height, config)
+    }
+
+    pub fn is_lossless(&self) -> bool {
+        self.lossless
+    }
+
+    fn is_keyframe_index(&self, index: u64) -> bool {
+        !cfg!(feature = "inter")
+            || index == 0
+            || (self.config.keyint > 0 && index.is_multiple_of(self.config.keyint as u64))
+            || self.reference.is_none()
+            || self.lossless
+            || self.frame_budget.is_some()
+            || self.sequence_header_change == Some(index)
+    }
+
+    /// Whether frame `index` is a keyframe known when it is sent, before
+    /// any frame of its mini-GOP is coded.
+    fn scheduled_keyframe(&self, index: u64) -> bool {
+        index > 0
+            && ((self.config.keyint > 0 && index.is_multiple_of(self.config.keyint as u64))
+                || self.sequence_header_change == Some(index))
+    }
+
     pub fn width(&self) -> u32 {
         self.width
     }
@@ -119,30 +692,47 @@ impl Encoder {
         self.height
     }
 
+    /// The input layouts this encoder accepts, given its video signal.
+    /// Frames built from any of them pass [`Encoder::send_frame`]'s checks.
+    pub fn supported_input_formats(&self) -> Vec<PixelFormat> {
+        pixel_format::supported_input_formats(&self.config.video_signal)
+    }
+
+    /// Configuration OBUs: the sequence header followed by the CLL and MDCV
+    /// metadata OBUs when configured. This is what an `av1C` record carries
+    /// after its fixed fields, and what an AVIF item starts with.
     pub fn headers(&self) -> Vec<u8> {
         self.headers_with_mode(false)
     }
 
+    /// [`Encoder::headers`] with the sequence header's `still_picture` flag
+    /// set, for single-image AVIF items.
     pub fn headers_still_picture(&self) -> Vec<u8> {
         self.headers_with_mode(true)
     }
 
+    /// Ready-made `AV1CodecConfigurationRecord` (the `av1C` box payload, or
+    /// Matroska `CodecPrivate`): profile, level, tier, bit depth and chroma
+    /// fields matching the sequence header, followed by [`Encoder::headers`].
+    pub fn av1c(&self) -> Vec<u8> {
+        sequence::codec_configuration_record(
+            &self.config.video_signal,
+            self.sequence_level_idx,
+            &self.headers(),
+        )
+    }
+
+    /// [`Encoder::av1c`] around [`Encoder::headers_still_picture`].
+    pub fn av1c_still_picture(&self) -> Vec<u8> {
+        sequence::codec_configuration_record(
+            &self.config.video_signal,
+            self.sequence_level_idx,
+            &self.headers_still_picture(),
+        )
+    }
+
     fn headers_with_mode(&self, still_picture: bool) -> Vec<u8> {
-        let seq_payload = if still_picture {
-            sequence::encode_still_picture_sequence_header_with_level(
-                self.width,
-                self.height,
-                &self.config.video_signal,
-                self.sequence_level_idx,
-            )
-        } else {
-            sequence::encode_sequence_header_with_level(
-                self.width,
-                self.height,
-                &self.config.video_signal,
-                self.sequence_level_idx,
-            )
-        };
+        let seq_payload = self.sequence_header(still_picture);
         let mut out = obu::obu_wrap(obu::ObuType::SequenceHeader, &seq_payload);
         for m in self.metadata_obus() {
             out.extend_from_slice(&m);
@@ -150,80 +740,273 @@ impl Encoder {
         out
     }
 
+    fn sequence_header(&self, still_picture: bool) -> Vec<u8> {
+        sequence_header(
+            self.width,
+            self.height,
+            &self.config,
+            self.sequence_level_idx,
+            still_picture,
+        )
+    }
+
     fn metadata_obus(&self) -> Vec<Vec<u8>> {
-        let mut out = Vec::new();
-        if let Some(cll) = self.config.content_light {
-            let payload = metadata::encode_hdr_cll(&cll);
-            out.push(obu::obu_wrap(obu::ObuType::Metadata, &payload));
-        }
-        if let Some(mdcv) = self.config.mastering_display {
-            let payload = metadata::encode_hdr_mdcv(&mdcv);
-            out.push(obu::obu_wrap(obu::ObuType::Metadata, &payload));
-        }
-        out
+        metadata_obus(&self.config)
     }
 
-    fn temporal_unit_headers(&self) -> Vec<u8> {
+    /// Temporal delimiter, sequence header and, per the metadata cadence,
+    /// the metadata OBUs, for a temporal unit whose first frame is a keyframe
+    /// when `keyframe` is set.
+    fn temporal_unit_headers(&mut self, keyframe: bool) -> Vec<u8> {
         let td = obu::obu_wrap(obu::ObuType::TemporalDelimiter, &[]);
-        let seq = obu::obu_wrap(
-            obu::ObuType::SequenceHeader,
-            &sequence::encode_sequence_header_with_level(
-                self.width,
-                self.height,
-                &self.config.video_signal,
-                self.sequence_level_idx,
-            ),
-        );
-        let mut out = Vec::new();
+        let seq = obu::obu_wrap(obu::ObuType::SequenceHeader, &self.sequence_header(false));
+        let mut out = self.packet_pool.take();
         out.extend_from_slice(&td);
         out.extend_from_slice(&seq);
-        for m in self.metadata_obus() {
-            out.extend_from_slice(&m);
+        let emit_metadata = match self.config.metadata_cadence {
+            MetadataCadence::EveryFrame => true,
+            MetadataCadence::Keyframes => keyframe,
+            MetadataCadence::Once => !self.metadata_emitted,
+        };
+        if emit_metadata {
+            for m in self.metadata_obus() {
+                out.extend_from_slice(&m);
+                self.metadata_emitted = true;
+            }
+        }
+        if keyframe && self.config.build_info {
+            let payload = metadata::encode_build_info(&self.build_info());
+            out.extend_from_slice(&obu::obu_wrap(obu::ObuType::Metadata, &payload));
         }
         out
     }
 
+    /// Bytes of the smallest packet a keyframe can take: a flat mid-grey
+    /// picture at qindex 255, after every header a keyframe temporal unit
+    /// may carry. The last resort under `EncoderConfig::max_frame_bytes`.
+    fn smallest_keyframe_packet(&self) -> usize {
+        let mut headers = obu::obu_wrap(obu::ObuType::TemporalDelimiter, &[]).len()
+            + obu::obu_wrap(obu::ObuType::SequenceHeader, &self.sequence_header(false)).len()
+            + self.metadata_obus().iter().map(Vec::len).sum::<usize>();
+        if self.config.build_info {
+            let payload = metadata::encode_build_info(&self.build_info());
+            headers += obu::obu_wrap(obu::ObuType::Metadata, &payload).len();
+        }
+        let (coded, _) = self.encode_keyframe(&self.grey_frame(), 255, None, None);
+        headers + self.frame_obus(&coded).iter().map(Vec::len).sum::<usize>()
+    }
+
+    fn grey_frame(&self) -> FramePixels {
+        let bit_depth = self.config.video_signal.bit_depth;
+        let mid = bit_depth.mid_value();
+        FramePixels::solid_with_bit_depth(
+            self.width,
+            self.height,
+            mid,
+            mid,
+            mid,
+            bit_depth,
+            self.config.video_signal.color_range,
+        )
+    }
+
+    /// The encoder name, version and the settings that shape the stream,
+    /// e.g. `wav1c 0.1.0 - AV1 encoder - options: q=128 bitrate=0 ...`.
+    /// Written into keyframe temporal units with `EncoderConfig::build_info`.
+    pub fn build_info(&self) -> String {
+        let c = &self.config;
+        format!(
+            "wav1c {} - AV1 encoder - options: q={} bitrate={} fps={}/{} keyint={} \
+             bframes={} gop={} closed_gop={} speed={} tune={} aq={} cdef_search={} \
+             loop_restoration={} mv_range={} tile_columns={} tile_rows={} frame_slices={} \
+             projection={} size={}x{} bit_depth={}",
+            env!("CARGO_PKG_VERSION"),
+            c.base_q_idx,
+            c.target_bitrate.unwrap_or(0),
+            c.fps.num,
+            c.fps.den,
+            c.keyint,
+            u8::from(c.b_frames),
+            c.gop_size,
+            u8::from(c.closed_gop),
+            c.speed,
+            c.tune.name(),
+            c.aq_mode.name(),
+            u8::from(c.cdef_search),
+            u8::from(c.loop_restoration),
+            c.mv_search_range,
+            c.tile_columns,
+            c.tile_rows,
+            c.frame_slices,
+            c.projection.name(),
+            self.width,
+            self.height,
+            self.config.video_signal.bit_depth.bits(),
+        )
+    }
+
     pub fn send_frame(&mut self, pixels: &FramePixels) -> Result<(), EncoderError> {
-        if pixels.width != self.width || pixels.height != self.height {
-            return Err(EncoderError::DimensionMismatch {
-                expected_w: self.width,
-                expected_h: self.height,
-                got_w: pixels.width,
-                got_h: pixels.height,
-            });
+        self.send_frame_with_options(pixels, FrameOptions::default())
+    }
+
+    /// Like `send_frame`, but attaches an opaque value that is returned in
+    /// `Packet::user_data` on the packet that displays this frame.
+    pub fn send_frame_with_user_data(
+        &mut self,
+        pixels: &FramePixels,
+        user_data: u64,
+    ) -> Result<(), EncoderError> {
+        self.send_frame_with_options(
+            pixels,
+            FrameOptions {
+                user_data: Some(user_data),
+                ..FrameOptions::default()
+            },
+        )
+    }
+
+    /// Like `send_frame`, with the per-frame settings in `options`.
+    pub fn send_frame_with_options(
+        &mut self,
+        pixels: &FramePixels,
+        options: FrameOptions,
+    ) -> Result<(), EncoderError> {
+        self.push_frame(Cow::Borrowed(pixels), options)
+    }
+
+    /// Like `send_frame_with_options`, but takes ownership of the frame so
+    /// the encoder keeps it without copying the planes. A pre-encode hook
+    /// edits the frame in place.
+    pub fn send_owned_frame(
+        &mut self,
+        pixels: FramePixels,
+        options: FrameOptions,
+    ) -> Result<(), EncoderError> {
+        self.push_frame(Cow::Owned(pixels), options)
+    }
+
+    /// Runs `hook` on every later frame after it is validated and before it is
+    /// analyzed or encoded, e.g. to burn in a timestamp or watermark. The hook
+    /// works on the copy the encoder keeps anyway (or on the frame itself with
+    /// `send_owned_frame`), so it costs no extra copy. It must keep the frame's
+    /// dimensions and bit depth; samples it writes are range-checked like any
+    /// other input.
+    pub fn set_pre_encode_hook(&mut self, hook: impl FnMut(&mut FramePixels) + Send + 'static) {
+        self.pre_encode_hook = Some(PreEncodeHook(Box::new(hook)));
+    }
+
+    /// Removes the hook set by `set_pre_encode_hook`.
+    pub fn clear_pre_encode_hook(&mut self) {
+        self.pre_encode_hook = None;
+    }
+
+    /// With `frame_slices`, hands every `partial` packet to `hook` while the
+    /// rest of its frame is still being coded, instead of queuing it for
+    /// `receive_packet`. The last slice of each frame is still received as
+    /// usual.
+    pub fn set_slice_hook(&mut self, hook: impl FnMut(Packet) + Send + 'static) {
+        self.slice_hook = Some(SliceHook(Box::new(hook)));
+    }
+
+    /// Removes the hook set by `set_slice_hook`.
+    pub fn clear_slice_hook(&mut self) {
+        self.slice_hook = None;
+    }
+
+    fn push_frame(
+        &mut self,
+        pixels: Cow<'_, FramePixels>,
+        options: FrameOptions,
+    ) -> Result<(), EncoderError> {
+        self.ensure_accepting_frames("send a frame")?;
+        if let Some(q) = options.base_q_idx {
+            if self.lossless {
+                return Err(EncoderError::InvalidConfig {
+                    reason: "per-frame q index cannot be set on a lossless encoder",
+                });
+            }
+            if dequant::is_lossless(q, &self.config.chroma_delta_q) {
+                return Err(EncoderError::InvalidConfig {
+                    reason: "per-frame q index would make the frame lossless",
+                });
+            }
         }
-        let expected = self.config.video_signal.bit_depth.bits();
-        let got = pixels.bit_depth.bits();
-        if expected != got {
-            return Err(EncoderError::FrameBitDepthMismatch { expected, got });
+        self.check_frame_layout(&pixels)?;
+        let mut pixels = pixels.into_owned();
+        if let Some(hook) = &mut self.pre_encode_hook {
+            (hook.0)(&mut pixels);
+            self.check_frame_layout(&pixels)?;
         }
-        let max_value = self.config.video_signal.bit_depth.max_value();
-        if let Some(sample) = pixels
-            .y
-            .iter()
-            .chain(pixels.u.iter())
-            .chain(pixels.v.iter())
-            .copied()
-            .find(|&s| s > max_value)
-        {
-            return Err(EncoderError::SampleOutOfRange {
-                bit_depth: expected,
-                sample,
-            });
+        check_sample_range(&self.config, &pixels)?;
+        if let Some(stats) = &mut self.first_pass {
+            let complexity = analysis::analyze_frame_scaled(
+                self.frame_index,
+                &pixels,
+                self.last_source.as_ref(),
+                self.config.first_pass_scale,
+            );
+            stats.frames.push(complexity);
+            if self.config.complexity_analysis {
+                self.complexity_queue.push_back(complexity);
+            }
+            self.last_source = Some(pixels);
+            self.frame_index += 1;
+            return Ok(());
+        }
+        if let Some(preview) = &mut self.preview {
+            preview.push_frame(
+                Cow::Owned(preview::downscale(&pixels)),
+                FrameOptions {
+                    base_q_idx: None,
+                    ..options
+                },
+            )?;
         }
 
-        self.gop_queue.push((self.frame_index, pixels.clone()));
+        if let Some(user_data) = options.user_data {
+            self.frame_user_data.insert(self.frame_index, user_data);
+        }
+        if let Some(q) = options.base_q_idx {
+            self.frame_q_idx.insert(self.frame_index, q);
+        }
+        let pts = self.capture_clock.next_pts(options.capture_time_ns);
+        self.frame_timing
+            .insert(self.frame_index, (pts, options.capture_time_ns));
+        if self.config.complexity_analysis {
+            let complexity = analysis::analyze_frame_scaled(
+                self.frame_index,
+                &pixels,
+                self.last_source.as_ref(),
+                self.config.first_pass_scale,
+            );
+            self.complexity_queue.push_back(complexity);
+            self.last_source = Some(pixels.clone());
+        }
+        // A keyframe starts the next mini-GOP, or in an open GOP ends the
+        // current one as its hidden future reference.
+        let keyframe = self.config.b_frames && self.scheduled_keyframe(self.frame_index);
+        let forward_keyframe =
+            keyframe && !self.config.closed_gop && self.sequence_header_change.is_none();
+        if keyframe && !forward_keyframe {
+            self.encode_gop();
+        }
+        self.gop_queue.push((self.frame_index, pixels));
         self.frame_index += 1;
 
         // When B-frames are disabled, encode each frame immediately (lowest latency).
         // When B-frames are enabled, batch into mini-GOPs of gop_size.
-        if !self.config.b_frames || self.gop_queue.len() >= self.config.gop_size {
+        if !self.config.b_frames || self.gop_queue.len() >= self.config.gop_size || forward_keyframe
+        {
             self.encode_gop();
         }
 
         Ok(())
     }
 
+    fn check_frame_layout(&self, pixels: &FramePixels) -> Result<(), EncoderError> {
+        check_frame_layout(self.width, self.height, &self.config, pixels)
+    }
+
     #[allow(clippy::too_many_arguments)]
     fn encode_single_frame(
         &mut self,
@@ -261,104 +1044,543 @@ impl Encoder {
         override_q_idx: Option<u8>,
         emit_tu_headers: bool,
     ) -> (Packet, FramePixels) {
-        let is_keyframe = index == 0
-            || (self.config.keyint > 0 && index.is_multiple_of(self.config.keyint as u64))
-            || self.reference.is_none();
+        let is_keyframe = self.is_keyframe_index(index);
+        let intra_only = !is_keyframe && !self.config.b_frames && self.intra_refresh == Some(index);
+        if self.intra_refresh.is_some_and(|refresh| refresh <= index) {
+            self.intra_refresh = None;
+        }
+
+        if let Some(rc) = &mut self.rate_ctrl
+            && is_keyframe
+            && rc.needs_initial_estimate()
+        {
+            let complexity =
+                analysis::analyze_frame_scaled(index, pixels, None, self.config.first_pass_scale);
+            let block_pixels =
+                (analysis::ANALYSIS_BLOCK_SIZE * analysis::ANALYSIS_BLOCK_SIZE) as u64;
+            rc.estimate_initial_qp(
+                complexity.intra_cost,
+                complexity.blocks as u64 * block_pixels,
+                self.config.video_signal.bit_depth,
+            );
+        }
 
-        let base_q_idx = if let Some(q) = override_q_idx {
+        let rc_frame = self.rate_controller.as_ref().map(|_| RcFrame {
+            frame_number: index,
+            is_keyframe,
+            bit_depth: self.config.video_signal.bit_depth,
+            complexity: analysis::analyze_frame_scaled(
+                index,
+                pixels,
+                self.reference.as_ref().filter(|_| !is_keyframe),
+                self.config.first_pass_scale,
+            ),
+        });
+
+        let caller_q_idx = self.frame_q_idx.remove(&index);
+        let mut base_q_idx = if let Some(q) = caller_q_idx.or(override_q_idx) {
             q
+        } else if let (Some(rc), Some(frame)) = (&mut self.rate_controller, &rc_frame) {
+            rc.0.on_frame_start(frame).max(1)
+        } else if let Some(budget) = &self.frame_budget {
+            budget.qp()
         } else {
             match &mut self.rate_ctrl {
-                Some(rc) => rc.compute_qp(is_keyframe),
+                Some(rc) => rc.compute_frame_qp(index, is_keyframe),
                 None => self.config.base_q_idx,
             }
         };
-        let dq = dequant::lookup_dequant(base_q_idx, self.config.video_signal.bit_depth);
+        let headers = if emit_tu_headers {
+            self.temporal_unit_headers(is_keyframe)
+        } else {
+            self.packet_pool.take()
+        };
+        // What coding an inter frame changes, restored before each re-encode
+        // under `max_frame_bytes`.
+        let inter_state = self.config.max_frame_bytes.map(|_| {
+            (
+                self.cyclic_refresh.clone(),
+                self.previous_mvs.clone(),
+                self.motion_queue.len(),
+            )
+        });
+
+        let mut frame_type = if is_keyframe && !show_frame {
+            FrameType::ForwardKey
+        } else if is_keyframe {
+            FrameType::Key
+        } else if intra_only {
+            FrameType::IntraOnly
+        } else {
+            FrameType::Inter
+        };
+        let sequence_header = if is_keyframe && self.sequence_header_change == Some(index) {
+            self.sequence_header_change = None;
+            Some(obu::obu_wrap(
+                obu::ObuType::SequenceHeader,
+                &self.sequence_header(false),
+            ))
+        } else {
+            None
+        };
 
-        let (frame_payload, recon) = if is_keyframe {
-            frame::encode_frame_with_recon(pixels, base_q_idx, dq)
+        let sliced = self.config.frame_slices > 1 && self.tile_plan.tile_rows > 1;
+        let mut slice_hook = self.slice_hook.take();
+        let mut sliced_frame = sliced.then(|| {
+            let (pts, capture_time_ns) = self
+                .frame_timing
+                .get(&index)
+                .copied()
+                .unwrap_or((index, None));
+            SlicedFrame {
+                plan: self.tile_plan.clone(),
+                packet: Packet {
+                    frames: vec![PacketFrame {
+                        range: headers.len()..headers.len(),
+                        frame_type: frame_type.clone(),
+                        frame_number: index,
+                        shown: show_frame,
+                    }],
+                    data: headers.clone(),
+                    frame_type: frame_type.clone(),
+                    frame_number: index,
+                    is_reference: refresh_frame_flags != 0 || frame_type == FrameType::Key,
+                    shown: show_frame,
+                    user_data: self.frame_user_data.get(&index).copied(),
+                    sequence_header: sequence_header.clone(),
+                    partial: true,
+                    pts,
+                    capture_time_ns,
+                },
+                tiles: Vec::new(),
+                hook: slice_hook.as_mut(),
+                queued: Vec::new(),
+            }
+        });
+        let mut push_part = sliced_frame
+            .as_mut()
+            .map(|sliced| move |part: frame::FramePart<'_>| sliced.push(part));
+        let parts = push_part.as_mut().map(|push| push as frame::FrameSink<'_>);
+
+        let forward_refresh = (!show_frame).then_some(refresh_frame_flags);
+        let (mut frame_payload, mut recon) = if is_keyframe {
+            self.previous_mvs = None;
+            self.cyclic_refresh.restart();
+            let mut coded = self.encode_keyframe(pixels, base_q_idx, forward_refresh, parts);
+            if let Some(mut budget) = self.frame_budget.take() {
+                let packet_len = |coded: &frame::CodedFrame| {
+                    headers.len() + self.frame_obus(coded).iter().map(Vec::len).sum::<usize>()
+                };
+                let mut attempt = 1;
+                while caller_q_idx.is_none()
+                    && let Some(q) = budget.retry_qp(base_q_idx, packet_len(&coded.0), attempt)
+                {
+                    base_q_idx = q;
+                    coded = self.encode_keyframe(pixels, base_q_idx, forward_refresh, None);
+                    attempt += 1;
+                }
+                budget.update(base_q_idx, packet_len(&coded.0));
+                self.frame_budget = Some(budget);
+            }
+            coded
+        } else if intra_only {
+            self.encode_intra_only_frame(pixels, base_q_idx, refresh_frame_flags, parts)
         } else {
-            frame::encode_inter_frame_with_recon(
+            self.encode_inter_frame(
+                index,
                 pixels,
-                self.reference.as_ref().unwrap(),
                 fwd_ref,
                 refresh_frame_flags,
                 ref_slot,
                 bwd_ref_slot,
                 show_frame,
                 base_q_idx,
-                dq,
+                parts,
             )
         };
-        let frm = obu::obu_wrap(obu::ObuType::Frame, &frame_payload);
+        let partials = sliced_frame.map_or_else(Vec::new, |sliced| sliced.queued);
+        self.slice_hook = slice_hook;
 
-        if let Some(rc) = &mut self.rate_ctrl {
-            rc.update((frm.len() * 8) as u64, base_q_idx);
+        if let Some(cap) = self.config.max_frame_bytes {
+            let packet_len = |this: &Self, coded: &frame::CodedFrame| {
+                headers.len() + this.frame_obus(coded).iter().map(Vec::len).sum::<usize>()
+            };
+            let restore = |this: &mut Self| {
+                if let Some((cyclic_refresh, previous_mvs, motion_fields)) = &inter_state {
+                    this.cyclic_refresh = cyclic_refresh.clone();
+                    this.previous_mvs = previous_mvs.clone();
+                    this.motion_queue.truncate(*motion_fields);
+                }
+            };
+            let mut budget = FrameBudget::new(cap, base_q_idx);
+            let mut attempt = 1;
+            while let Some(q) =
+                budget.retry_qp(base_q_idx, packet_len(self, &frame_payload), attempt)
+            {
+                base_q_idx = q;
+                restore(self);
+                (frame_payload, recon) = if is_keyframe {
+                    self.encode_keyframe(pixels, q, forward_refresh, None)
+                } else if intra_only {
+                    self.encode_intra_only_frame(pixels, q, refresh_frame_flags, None)
+                } else {
+                    self.encode_inter_frame(
+                        index,
+                        pixels,
+                        fwd_ref,
+                        refresh_frame_flags,
+                        ref_slot,
+                        bwd_ref_slot,
+                        show_frame,
+                        q,
+                        None,
+                    )
+                };
+                attempt += 1;
+            }
+            if packet_len(self, &frame_payload) > cap as usize {
+                // Even qindex 255 is too large: repeat the reference, which
+                // costs next to nothing, or code a flat keyframe.
+                restore(self);
+                let repeated = !is_keyframe && {
+                    let reference = self.reference.clone().unwrap();
+                    (frame_payload, recon) = self.encode_inter_frame(
+                        index,
+                        &reference,
+                        fwd_ref,
+                        refresh_frame_flags,
+                        ref_slot,
+                        bwd_ref_slot,
+                        show_frame,
+                        255,
+                        None,
+                    );
+                    packet_len(self, &frame_payload) <= cap as usize
+                };
+                if repeated {
+                    self.frame_size_cap.repeated += 1;
+                    if intra_only {
+                        frame_type = FrameType::Inter;
+                        self.intra_refresh = Some(index + 1);
+                    }
+                } else {
+                    restore(self);
+                    self.previous_mvs = None;
+                    self.cyclic_refresh.restart();
+                    (frame_payload, recon) =
+                        self.encode_keyframe(&flat_mean_frame(pixels), 255, forward_refresh, None);
+                    if packet_len(self, &frame_payload) > cap as usize {
+                        // `Encoder::new` checked that a grey keyframe fits.
+                        (frame_payload, recon) =
+                            self.encode_keyframe(&self.grey_frame(), 255, forward_refresh, None);
+                    }
+                    self.frame_size_cap.flattened += 1;
+                    if !is_keyframe {
+                        frame_type = FrameType::Key;
+                    }
+                }
+                base_q_idx = 255;
+            } else if attempt > 1 {
+                self.frame_size_cap.requantized += 1;
+            }
         }
 
-        let mut data = Vec::new();
-        if emit_tu_headers {
-            data.extend_from_slice(&self.temporal_unit_headers());
+        let mut slices = self.frame_obus(&frame_payload);
+        let frame_len: usize = slices.iter().map(Vec::len).sum();
+
+        if let Some(rc) = &mut self.rate_ctrl {
+            rc.update_frame(index, (frame_len * 8) as u64, base_q_idx, is_keyframe);
+        }
+        if let (Some(rc), Some(frame)) = (&mut self.rate_controller, &rc_frame) {
+            rc.0.on_frame_done(frame, base_q_idx, frame_len);
         }
-        data.extend_from_slice(&frm);
 
+        // Sliced frames: every row but the last went out as it was coded,
+        // and the last one is returned like a whole frame.
+        for packet in partials {
+            self.push_packet(packet);
+        }
+        let (frame_start, mut data, sequence_header) = if sliced {
+            (0, Vec::new(), None)
+        } else {
+            (headers.len(), headers, sequence_header)
+        };
+        data.extend_from_slice(&slices.pop().unwrap());
+        let is_reference = refresh_frame_flags != 0 || frame_type == FrameType::Key;
         let packet = Packet {
+            frames: vec![PacketFrame {
+                range: frame_start..data.len(),
+                frame_type: frame_type.clone(),
+                frame_number: index,
+                shown: show_frame,
+            }],
             data,
-            frame_type: if is_keyframe {
-                FrameType::Key
-            } else {
-                FrameType::Inter
-            },
+            frame_type,
             frame_number: index,
+            is_reference,
+            shown: show_frame,
+            user_data: None,
+            sequence_header,
+            partial: false,
+            pts: 0,
+            capture_time_ns: None,
         };
+        if self.config.distortion_maps {
+            self.distortion_queue
+                .push_back(metrics::distortion_map(index, pixels, &recon));
+        }
 
         (packet, recon)
     }
 
-    fn encode_gop(&mut self) {
-        if self.gop_queue.is_empty() {
-            return;
-        }
-
-        // P-only fast path: when B-frames are disabled, encode each frame
-        // as a standard shown P-frame (or keyframe) with refresh_frame_flags=0xFF
-        if !self.config.b_frames {
-            while !self.gop_queue.is_empty() {
-                let (idx, pixels) = self.gop_queue.remove(0);
-                let (mut pkt, recon) =
-                    self.encode_single_frame(idx, &pixels, None, 0xFF, 0, 0, true);
-                self.reference = Some(recon);
+    /// The superblock q indices of a frame at `base_q_idx`. Inter frames
+    /// add cyclic refresh on top of the projection's; it pauses while rate
+    /// control is over budget, and the cycle resumes where it stopped.
+    fn sb_delta_q(&mut self, base_q_idx: u8, is_intra: bool) -> tile::SbDeltaQ {
+        let over_budget = self
+            .rate_ctrl
+            .as_ref()
+            .is_some_and(|rc| rc.buffer_fullness_pct() >= aq::CYCLIC_REFRESH_MAX_BUFFER_PCT);
+        let refresh = !is_intra
+            && self.config.aq_mode == AqMode::CyclicRefresh
+            && aq::boost_q_idx(base_q_idx) != base_q_idx
+            && !over_budget;
+        let boosted = refresh.then(|| {
+            let plan = &self.tile_plan;
+            self.cyclic_refresh
+                .next_map((plan.sb_cols * plan.sb_rows) as usize)
+        });
+        sb_delta_q(
+            &self.config,
+            &self.tile_plan,
+            self.height,
+            base_q_idx,
+            is_intra,
+            boosted.as_deref(),
+        )
+    }
 
-                // P-Only Output: Map exactly to the frame index (PTS)
-                pkt.frame_number = idx;
+    fn frame_obus(&self, coded: &frame::CodedFrame) -> Vec<Vec<u8>> {
+        frame_obus(&self.config, &self.tile_plan, coded)
+    }
 
-                self.pending_packets.push_back(pkt);
-            }
-            return;
+    fn early_exit(&self) -> tile::EarlyExit {
+        #[allow(unused_mut)]
+        let mut early_exit = self.config.tune.early_exit(self.config.speed);
+        #[cfg(feature = "mode-hook")]
+        {
+            early_exit.intra_mode_hook = self.intra_mode_hook;
         }
+        early_exit
+    }
 
-        if self.gop_queue.len() == 1 {
-            let (idx, pixels) = self.gop_queue.remove(0);
-            self.base_slot = 0;
-            let (mut pkt, recon) =
-                self.encode_single_frame(idx, &pixels, None, 1 << self.base_slot, 0, 0, true);
-            self.reference = Some(recon);
+    fn frame_dequant(&self, base_q_idx: u8, is_keyframe: bool) -> dequant::DequantValues {
+        frame_dequant(&self.config, base_q_idx, is_keyframe)
+    }
 
-            // Single Fragment Output: Map exactly to the frame index (PTS)
-            pkt.frame_number = idx;
+    /// Codes an intra-only frame refreshing only the slots in
+    /// `refresh_frame_flags`.
+    fn encode_intra_only_frame(
+        &mut self,
+        pixels: &FramePixels,
+        base_q_idx: u8,
+        refresh_frame_flags: u8,
+        parts: Option<frame::FrameSink<'_>>,
+    ) -> (frame::CodedFrame, FramePixels) {
+        self.previous_mvs = None;
+        self.cyclic_refresh.restart();
+        let delta_q = self.sb_delta_q(base_q_idx, true);
+        frame::encode_intra_only_frame_with_tile_plan(
+            pixels,
+            base_q_idx,
+            self.frame_dequant(base_q_idx, true),
+            &self.tile_plan,
+            self.early_exit(),
+            self.config.tune.cdef_search(self.config.cdef_search),
+            self.config.loop_restoration,
+            &delta_q,
+            refresh_frame_flags,
+            parts,
+        )
+    }
 
-            self.pending_packets.push_back(pkt);
-            return;
+    /// Codes an inter frame predicting from `self.reference`, the pooled
+    /// LAST2 and GOLDEN frames (and `fwd_ref`, for B-frames).
+    #[cfg(feature = "inter")]
+    #[allow(clippy::too_many_arguments)]
+    fn encode_inter_frame(
+        &mut self,
+        index: u64,
+        pixels: &FramePixels,
+        fwd_ref: Option<&FramePixels>,
+        refresh_frame_flags: u8,
+        ref_slot: u8,
+        bwd_ref_slot: u8,
+        show_frame: bool,
+        base_q_idx: u8,
+        parts: Option<frame::FrameSink<'_>>,
+    ) -> (frame::CodedFrame, FramePixels) {
+        let distance = index as i64 - self.reference_index as i64;
+        let delta_q = self.sb_delta_q(base_q_idx, false);
+        let colocated = self
+            .previous_mvs
+            .as_ref()
+            .map(|(mvs, previous_distance)| mvs.rescaled(distance, *previous_distance));
+        let alternates = self.ref_pool.alternates(self.reference_index);
+        let (payload, recon, motion) = frame::encode_inter_frame_with_recon(
+            pixels,
+            self.reference.as_ref().unwrap(),
+            &alternates,
+            fwd_ref,
+            refresh_frame_flags,
+            ref_slot,
+            bwd_ref_slot,
+            show_frame,
+            base_q_idx,
+            self.frame_dequant(base_q_idx, false),
+            &self.tile_plan,
+            tile::MotionParams {
+                integer_mv: self.config.tune.integer_mv(),
+                prefilter: self.config.me_prefilter,
+                ..tile::MotionParams::for_distance(
+                    self.config.mv_search_range,
+                    index.abs_diff(self.reference_index),
+                )
+            },
+            self.early_exit(),
+            colocated.as_ref(),
+            self.config.tune.cdef_search(self.config.cdef_search),
+            self.config.loop_restoration,
+            &delta_q,
+            parts,
+        );
+        if self.config.motion_fields {
+            self.motion_queue.push_back(tile::MotionField {
+                frame_number: index,
+                reference_frame_number: self.reference_index,
+                coded: motion.coded,
+                estimates: motion.estimates.clone(),
+            });
+        }
+        self.previous_mvs = Some((motion.estimates, distance));
+        (payload, recon)
+    }
+
+    /// Every frame is a keyframe without the `inter` feature.
+    #[cfg(not(feature = "inter"))]
+    #[allow(clippy::too_many_arguments)]
+    fn encode_inter_frame(
+        &mut self,
+        _index: u64,
+        _pixels: &FramePixels,
+        _fwd_ref: Option<&FramePixels>,
+        _refresh_frame_flags: u8,
+        _ref_slot: u8,
+        _bwd_ref_slot: u8,
+        _show_frame: bool,
+        _base_q_idx: u8,
+        _parts: Option<frame::FrameSink<'_>>,
+    ) -> (frame::CodedFrame, FramePixels) {
+        unreachable!("inter frames need the `inter` feature")
+    }
+
+    /// Codes a keyframe, hidden and refreshing only `forward_refresh` when
+    /// it is given.
+    fn encode_keyframe(
+        &self,
+        pixels: &FramePixels,
+        base_q_idx: u8,
+        forward_refresh: Option<u8>,
+        parts: Option<frame::FrameSink<'_>>,
+    ) -> (frame::CodedFrame, FramePixels) {
+        let delta_q = sb_delta_q(
+            &self.config,
+            &self.tile_plan,
+            self.height,
+            base_q_idx,
+            true,
+            None,
+        );
+        let dq = self.frame_dequant(base_q_idx, true);
+        let cdef_search = self.config.tune.cdef_search(self.config.cdef_search);
+        match forward_refresh {
+            None => frame::encode_frame_with_tile_plan(
+                pixels,
+                base_q_idx,
+                dq,
+                &self.tile_plan,
+                self.early_exit(),
+                cdef_search,
+                self.config.loop_restoration,
+                &delta_q,
+                parts,
+            ),
+            Some(refresh_frame_flags) => frame::encode_forward_keyframe_with_tile_plan(
+                pixels,
+                base_q_idx,
+                dq,
+                &self.tile_plan,
+                self.early_exit(),
+                cdef_search,
+                self.config.loop_restoration,
+                &delta_q,
+                refresh_frame_flags,
+                parts,
+            ),
+        }
+    }
+
+    fn encode_gop(&mut self) {
+        if self.gop_queue.is_empty() {
+            return;
+        }
+
+        // P-only fast path: when B-frames are disabled, encode each frame
+        // as a shown P-frame (or keyframe) predicting from LAST, LAST2 and
+        // GOLDEN. Without the `inter` feature every frame is a keyframe.
+        if !cfg!(feature = "inter") || !self.config.b_frames {
+            while !self.gop_queue.is_empty() {
+                let (idx, pixels) = self.gop_queue.remove(0);
+                let next_slot = 1 - self.base_slot;
+                let refresh_golden =
+                    self.ref_pool.golden_due(idx) || self.intra_refresh == Some(idx);
+                let refresh = (1 << next_slot) | (u8::from(refresh_golden) << GOLDEN_SLOT);
+                let (mut pkt, recon) =
+                    self.encode_single_frame(idx, &pixels, None, refresh, self.base_slot, 0, true);
+                if cfg!(feature = "inter") {
+                    self.update_ref_pool(idx, pkt.frame_type.clone(), refresh, &recon);
+                }
+                self.reference = Some(recon);
+                self.reference_index = idx;
+
+                // P-Only Output: Map exactly to the frame index (PTS)
+                pkt.frame_number = idx;
+
+                self.push_packet(pkt);
+            }
+            return;
+        }
+
+        if self.gop_queue.len() == 1 {
+            let (idx, pixels) = self.gop_queue.remove(0);
+            self.base_slot = 0;
+            let (mut pkt, recon) =
+                self.encode_single_frame(idx, &pixels, None, 1 << self.base_slot, 0, 0, true);
+            self.reference = Some(recon);
+            self.reference_index = idx;
+
+            // Single Fragment Output: Map exactly to the frame index (PTS)
+            pkt.frame_number = idx;
+
+            self.push_packet(pkt);
+            return;
         }
 
         // If the gop_queue begins with a keyframe or a frame where self.reference is missing,
         // we MUST encode it first to establish the baseline reference for the rest of the GOP!
         let mut base_packets = Vec::new();
         while !self.gop_queue.is_empty() {
-            let (first_idx, _) = &self.gop_queue[0];
-            let is_keyframe = *first_idx == 0
-                || (self.config.keyint > 0 && first_idx.is_multiple_of(self.config.keyint as u64))
-                || self.reference.is_none();
+            let is_keyframe = self.is_keyframe_index(self.gop_queue[0].0);
 
             if is_keyframe {
                 let (idx, pixels) = self.gop_queue.remove(0);
@@ -366,6 +1588,7 @@ impl Encoder {
                 let (mut pkt, recon) =
                     self.encode_single_frame(idx, &pixels, None, 1 << self.base_slot, 0, 0, true);
                 self.reference = Some(recon);
+                self.reference_index = idx;
 
                 // Keyframe Output: Map exactly to the frame index (PTS)
                 pkt.frame_number = idx;
@@ -378,7 +1601,7 @@ impl Encoder {
 
         // Output the base packets (e.g. keyframes) that were just encoded
         for pkt in base_packets {
-            self.pending_packets.push_back(pkt);
+            self.push_packet(pkt);
         }
 
         if self.gop_queue.is_empty() {
@@ -387,7 +1610,29 @@ impl Encoder {
 
         // For the remaining GOP frames, encode the LAST frame (future reference) as a standard P-Frame
         let last_idx = self.gop_queue.len() - 1;
-        let (f_idx, f_pixels) = self.gop_queue.remove(last_idx);
+        let (f_idx, mut f_pixels) = self.gop_queue.remove(last_idx);
+        // Slower speeds denoise it with the source frames before it, since
+        // every B-frame here and the next mini-GOP predict from it.
+        if self.temporal_filter_frames > 0 {
+            let neighbours: Vec<(&FramePixels, u32)> = self
+                .gop_queue
+                .iter()
+                .rev()
+                .take(self.temporal_filter_frames)
+                .map(|(idx, pixels)| (pixels, (f_idx - idx) as u32))
+                .collect();
+            let q_idx = self
+                .frame_q_idx
+                .get(&f_idx)
+                .copied()
+                .unwrap_or(self.config.base_q_idx);
+            f_pixels = temporal_filter::filter_frame(
+                &f_pixels,
+                &neighbours,
+                self.config.mv_search_range,
+                self.frame_dequant(q_idx, false).ac,
+            );
+        }
 
         // P-Frame writes to the alt slot
         let alt_slot = 1 - self.base_slot;
@@ -435,6 +1680,8 @@ impl Encoder {
         // We *MUST* write P then B, and then write an empty `show_existing_frame=P` packet.
         // For now, to keep the test simple and valid, we will output in strict decode order.
 
+        // Displaying a hidden keyframe refreshes every slot.
+        let shows_keyframe = p_pkt.frame_type == FrameType::ForwardKey;
         if !b_packets.is_empty() {
             let mut first_b = b_packets.remove(0);
 
@@ -442,24 +1689,43 @@ impl Encoder {
             // The B-frame has NO TU headers (because we passed emit_tu_headers=false).
             // We concatenate P-frame data with B-frame data, creating a single chunk (TU) with TWO frames!
             let mut combined_data = p_pkt.data;
+            let offset = combined_data.len();
             combined_data.extend_from_slice(&first_b.data);
+            let mut frames = p_pkt.frames;
+            frames.extend(first_b.frames.drain(..).map(|frame| PacketFrame {
+                range: frame.range.start + offset..frame.range.end + offset,
+                ..frame
+            }));
+            first_b.frames = frames;
 
-            first_b.data = combined_data;
+            let b_data = std::mem::replace(&mut first_b.data, combined_data);
+            self.packet_pool.give(b_data);
+            if shows_keyframe {
+                first_b.frame_type = FrameType::ForwardKey;
+            }
+            first_b.is_reference = true;
             // The display order is first_b.frame_number. So this combined packet has the DTS/PTS of the B-frame!
-            self.pending_packets.push_back(first_b);
+            self.push_packet(first_b);
         } else {
-            // If no B-frames (e.g. gop size was reached exactly?), just push the P-frame.
-            // But P-frames are only created if gop_queue.is_empty() is false, so B-frames exist.
-            self.pending_packets.push_back(p_pkt);
+            // A mini-GOP of one frame after a keyframe: the hidden P-frame
+            // goes out alone, `shown` clear, and the `ShowExisting` packet
+            // below completes its temporal unit.
+            self.push_packet(p_pkt);
         }
 
         // Then output remaining B-frames with their original display-order indices
         // Since they had emit_tu_headers=false, we MUST prepend TU headers to them!
         for mut b_pkt in b_packets {
-            let mut tu_data = self.temporal_unit_headers();
+            let keyframe = b_pkt.frame_type == FrameType::Key;
+            let mut tu_data = self.temporal_unit_headers(keyframe);
+            let offset = tu_data.len();
             tu_data.extend_from_slice(&b_pkt.data);
-            b_pkt.data = tu_data;
-            self.pending_packets.push_back(b_pkt);
+            for frame in &mut b_pkt.frames {
+                frame.range = frame.range.start + offset..frame.range.end + offset;
+            }
+            let b_data = std::mem::replace(&mut b_pkt.data, tu_data);
+            self.packet_pool.give(b_data);
+            self.push_packet(b_pkt);
         }
 
         // Output show_existing_frame to display the hidden P-frame at its correct position
@@ -468,601 +1734,3609 @@ impl Encoder {
             &frame::encode_show_existing_frame(alt_slot),
         );
 
-        let mut show_pkt_data = self.temporal_unit_headers();
+        let mut show_pkt_data = self.temporal_unit_headers(false);
+        let show_start = show_pkt_data.len();
         show_pkt_data.extend_from_slice(&show_hdr);
 
         let show_pkt = Packet {
+            frames: vec![PacketFrame {
+                range: show_start..show_pkt_data.len(),
+                frame_type: FrameType::ShowExisting,
+                frame_number: f_idx,
+                shown: true,
+            }],
             data: show_pkt_data,
-            frame_type: FrameType::Inter,
+            frame_type: FrameType::ShowExisting,
             frame_number: f_idx, // Same display time as the P-frame it reveals
+            is_reference: shows_keyframe,
+            shown: true,
+            user_data: None,
+            sequence_header: None,
+            partial: false,
+            pts: 0,
+            capture_time_ns: None,
         };
-        self.pending_packets.push_back(show_pkt);
+        self.push_packet(show_pkt);
 
         self.reference = Some(fwd_recon);
+        self.reference_index = f_idx;
         // The newly encoded P-frame becomes the base for the next GOP
         self.base_slot = alt_slot;
     }
 
-    pub fn receive_packet(&mut self) -> Option<Packet> {
-        self.pending_packets.pop_front()
+    /// Moves the P-only references on past frame `index`, whose `recon`
+    /// becomes LAST.
+    fn update_ref_pool(
+        &mut self,
+        index: u64,
+        frame_type: FrameType,
+        refresh: u8,
+        recon: &FramePixels,
+    ) {
+        let golden = || PooledReference {
+            index,
+            slot: GOLDEN_SLOT,
+            pixels: recon.clone(),
+        };
+        match frame_type {
+            FrameType::Key => {
+                self.base_slot = 0;
+                self.ref_pool = ReferencePool {
+                    last2: None,
+                    golden: Some(golden()),
+                };
+            }
+            FrameType::IntraOnly => {
+                self.base_slot = 1 - self.base_slot;
+                self.ref_pool = ReferencePool {
+                    last2: None,
+                    golden: Some(golden()),
+                };
+            }
+            _ => {
+                self.ref_pool.last2 = self.reference.take().map(|pixels| PooledReference {
+                    index: self.reference_index,
+                    slot: self.base_slot,
+                    pixels,
+                });
+                if refresh & (1 << GOLDEN_SLOT) != 0 {
+                    self.ref_pool.golden = Some(golden());
+                }
+                self.base_slot = 1 - self.base_slot;
+            }
+        }
     }
 
-    pub fn flush(&mut self) {
-        self.encode_gop();
+    fn push_packet(&mut self, mut pkt: Packet) {
+        // Packets ahead of the one showing the frame leave its timing there.
+        let timing = if pkt.partial || !pkt.shown {
+            pkt.user_data = self.frame_user_data.get(&pkt.frame_number).copied();
+            self.frame_timing.get(&pkt.frame_number).copied()
+        } else {
+            pkt.user_data = self.frame_user_data.remove(&pkt.frame_number);
+            self.frame_timing.remove(&pkt.frame_number)
+        };
+        (pkt.pts, pkt.capture_time_ns) = timing.unwrap_or((pkt.frame_number, None));
+        self.pending_packets.push_back(pkt);
     }
 
-    pub fn rate_control_stats(&self) -> Option<crate::rc::RateControlStats> {
-        self.rate_ctrl.as_ref().map(|rc| rc.stats())
+    pub fn receive_packet(&mut self) -> Option<Packet> {
+        self.pending_packets.pop_front()
     }
-}
-
-fn preflight_frame_buffer_reserve(width: u32, height: u32) -> Result<(), EncoderError> {
-    let fail = |reason: String| EncoderError::AllocationPreflightFailed {
-        width,
-        height,
-        reason,
-    };
-
-    let luma_samples = width
-        .checked_mul(height)
-        .ok_or_else(|| fail("luma sample count overflow".to_owned()))?;
-    let chroma_samples = width
-        .div_ceil(2)
-        .checked_mul(height.div_ceil(2))
-        .ok_or_else(|| fail("chroma sample count overflow".to_owned()))?;
-    let total_samples_per_frame = u64::from(luma_samples) + 2 * u64::from(chroma_samples);
-    let total_samples_reserve = total_samples_per_frame
-        .checked_mul(2)
-        .ok_or_else(|| fail("frame reserve sample count overflow".to_owned()))?;
-    let reserve_elems = usize::try_from(total_samples_reserve)
-        .map_err(|_| fail("frame reserve sample count does not fit platform usize".to_owned()))?;
 
-    let mut preflight = Vec::<u16>::new();
-    preflight.try_reserve_exact(reserve_elems).map_err(|e| {
-        fail(format!(
-            "unable to reserve {} u16 samples for frame buffers: {}",
-            reserve_elems, e
-        ))
-    })?;
+    /// Takes the next packet of the preview stream, in the same order and
+    /// with the same timestamps and user data as the main stream's packets.
+    /// `None` without `EncoderConfig::preview_q_idx`.
+    pub fn receive_preview_packet(&mut self) -> Option<Packet> {
+        self.preview.as_mut()?.receive_packet()
+    }
 
-    Ok(())
-}
+    /// The encoder behind the preview stream, for its dimensions and the
+    /// headers a muxer needs (`av1c`, `headers`).
+    pub fn preview(&self) -> Option<&Encoder> {
+        self.preview.as_deref()
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Costs of every frame sent so far under `TwoPass::FirstPass`.
+    pub fn first_pass_stats(&self) -> Option<&FirstPassStats> {
+        self.first_pass.as_ref()
+    }
 
-    #[test]
-    fn new_valid_dimensions() {
-        let config = EncoderConfig {
-            base_q_idx: 128,
-            keyint: 25,
-            target_bitrate: None,
-            fps: Fps::default(),
-            b_frames: false,
-            gop_size: 3,
-            video_signal: VideoSignal::default(),
-            content_light: None,
-            mastering_display: None,
-        };
-        let enc = Encoder::new(64, 64, config);
-        assert!(enc.is_ok());
-        let enc = enc.unwrap();
-        assert_eq!(enc.width(), 64);
-        assert_eq!(enc.height(), 64);
+    /// Hands a received packet's buffers back for the encoder to build later
+    /// packets in, so a long-running encode stops allocating per packet. At
+    /// most `packet::PACKET_POOL_CAPACITY` buffers are kept.
+    pub fn recycle_packet(&mut self, packet: Packet) {
+        self.packet_pool.give(packet.data);
+        if let Some(sequence_header) = packet.sequence_header {
+            self.packet_pool.give(sequence_header);
+        }
     }
 
-    #[test]
-    fn new_min_dimensions() {
-        let config = EncoderConfig {
-            base_q_idx: 128,
-            keyint: 25,
-            target_bitrate: None,
-            fps: Fps::default(),
-            b_frames: false,
-            gop_size: 3,
-            video_signal: VideoSignal::default(),
-            content_light: None,
-            mastering_display: None,
-        };
-        assert!(Encoder::new(1, 1, config).is_ok());
+    /// How often packets were built in a recycled buffer rather than a new
+    /// one.
+    pub fn packet_pool_stats(&self) -> PacketPoolStats {
+        self.packet_pool.stats()
     }
 
-    #[test]
-    fn new_above_old_dimension_cap_is_valid() {
-        let config = EncoderConfig {
-            base_q_idx: 128,
-            keyint: 25,
-            target_bitrate: None,
-            fps: Fps::default(),
-            b_frames: false,
-            gop_size: 3,
-            video_signal: VideoSignal::default(),
-            content_light: None,
-            mastering_display: None,
-        };
-        assert!(Encoder::new(4097, 2305, config).is_ok());
+    /// Frames `EncoderConfig::max_frame_bytes` has re-encoded or replaced
+    /// so far.
+    pub fn frame_size_cap_stats(&self) -> FrameSizeCapStats {
+        self.frame_size_cap
     }
 
-    #[test]
-    fn new_invalid_width_zero() {
-        let config = EncoderConfig {
-            base_q_idx: 128,
-            keyint: 25,
-            target_bitrate: None,
-            fps: Fps::default(),
-            b_frames: false,
-            gop_size: 3,
-            video_signal: VideoSignal::default(),
-            content_light: None,
-            mastering_display: None,
-        };
-        let result = Encoder::new(0, 64, config);
-        assert!(result.is_err());
-        match result.unwrap_err() {
-            EncoderError::InvalidDimensions { width, height } => {
-                assert_eq!(width, 0);
-                assert_eq!(height, 64);
-            }
-            _ => panic!("expected InvalidDimensions"),
+    /// Encodes every buffered frame and marks the end of the stream.
+    ///
+    /// A partially filled B-frame mini-GOP is coded as a shorter mini-GOP,
+    /// so every frame sent is output exactly once. Calling `flush` again, or
+    /// after `abort`, produces no further packets.
+    pub fn flush(&mut self) {
+        self.encode_gop();
+        if let Some(preview) = &mut self.preview {
+            preview.flush();
         }
+        self.end_of_stream = true;
     }
 
-    #[test]
-    fn new_extremely_large_dimensions_fail_preflight() {
-        let config = EncoderConfig {
-            base_q_idx: 128,
-            keyint: 25,
-            target_bitrate: None,
-            fps: Fps::default(),
-            b_frames: false,
-            gop_size: 3,
-            video_signal: VideoSignal::default(),
-            content_light: None,
-            mastering_display: None,
-        };
-        let result = Encoder::new(MAX_AV1_FRAME_DIMENSION, MAX_AV1_FRAME_DIMENSION, config);
-        assert!(result.is_err());
-        match result.unwrap_err() {
-            EncoderError::AllocationPreflightFailed { .. } => {}
-            other => panic!("expected AllocationPreflightFailed, got {other:?}"),
+    /// Ends the stream without emitting anything more: frames buffered for
+    /// the current mini-GOP and packets not yet received are discarded.
+    pub fn abort(&mut self) {
+        for (idx, _) in self.gop_queue.drain(..) {
+            self.frame_user_data.remove(&idx);
+            self.frame_q_idx.remove(&idx);
+            self.frame_timing.remove(&idx);
         }
+        self.pending_packets.clear();
+        self.complexity_queue.clear();
+        self.distortion_queue.clear();
+        self.motion_queue.clear();
+        if let Some(preview) = &mut self.preview {
+            preview.abort();
+        }
+        self.end_of_stream = true;
     }
 
-    #[test]
-    fn new_width_above_av1_sequence_header_limit_is_invalid() {
-        let config = EncoderConfig {
-            base_q_idx: 128,
-            keyint: 25,
-            target_bitrate: None,
-            fps: Fps::default(),
-            b_frames: false,
-            gop_size: 3,
-            video_signal: VideoSignal::default(),
-            content_light: None,
-            mastering_display: None,
-        };
-        let result = Encoder::new(MAX_AV1_FRAME_DIMENSION + 1, 64, config);
-        assert!(result.is_err());
-        match result.unwrap_err() {
-            EncoderError::InvalidDimensions { width, height } => {
-                assert_eq!(width, MAX_AV1_FRAME_DIMENSION + 1);
-                assert_eq!(height, 64);
-            }
-            other => panic!("expected InvalidDimensions, got {other:?}"),
+    fn ensure_accepting_frames(&self, operation: &'static str) -> Result<(), EncoderError> {
+        if self.end_of_stream {
+            return Err(EncoderError::InvalidState {
+                operation,
+                state: self.state(),
+            });
         }
+        Ok(())
     }
 
-    #[test]
-    fn new_height_above_av1_sequence_header_limit_is_invalid() {
-        let config = EncoderConfig {
-            base_q_idx: 128,
-            keyint: 25,
-            target_bitrate: None,
-            fps: Fps::default(),
-            b_frames: false,
-            gop_size: 3,
-            video_signal: VideoSignal::default(),
-            content_light: None,
-            mastering_display: None,
-        };
-        let result = Encoder::new(64, MAX_AV1_FRAME_DIMENSION + 1, config);
-        assert!(result.is_err());
-        match result.unwrap_err() {
-            EncoderError::InvalidDimensions { width, height } => {
-                assert_eq!(width, 64);
-                assert_eq!(height, MAX_AV1_FRAME_DIMENSION + 1);
-            }
-            other => panic!("expected InvalidDimensions, got {other:?}"),
+    pub fn state(&self) -> EncoderState {
+        let drained = self.pending_packets.is_empty()
+            && self
+                .preview
+                .as_ref()
+                .is_none_or(|preview| preview.pending_packets.is_empty());
+        match (self.end_of_stream, drained) {
+            (true, true) => EncoderState::Finished,
+            (true, false) => EncoderState::Draining,
+            (false, _) if self.frame_index == 0 => EncoderState::Idle,
+            (false, _) => EncoderState::Encoding,
         }
     }
 
-    #[test]
-    fn new_invalid_height_zero() {
-        let config = EncoderConfig {
-            base_q_idx: 128,
-            keyint: 25,
-            target_bitrate: None,
-            fps: Fps::default(),
-            b_frames: false,
-            gop_size: 3,
-            video_signal: VideoSignal::default(),
-            content_light: None,
-            mastering_display: None,
-        };
-        assert!(Encoder::new(64, 0, config).is_err());
+    /// Number of frames accepted by `send_frame` that have not been encoded
+    /// yet (buffered for the current B-frame mini-GOP).
+    pub fn frames_in_flight(&self) -> usize {
+        self.gop_queue.len()
     }
 
-    #[test]
-    fn new_height_above_old_cap_is_valid() {
-        let config = EncoderConfig {
-            base_q_idx: 128,
-            keyint: 25,
-            target_bitrate: None,
-            fps: Fps::default(),
-            b_frames: false,
-            gop_size: 3,
-            video_signal: VideoSignal::default(),
-            content_light: None,
-            mastering_display: None,
-        };
-        assert!(Encoder::new(64, 2305, config).is_ok());
+    /// Number of encoded packets waiting to be taken with `receive_packet`.
+    pub fn pending_packets(&self) -> usize {
+        self.pending_packets.len()
     }
 
-    #[test]
-    fn send_frame_receive_packet_lifecycle() {
-        let config = EncoderConfig {
-            base_q_idx: 128,
-            keyint: 25,
-            target_bitrate: None,
-            fps: Fps::default(),
-            b_frames: false,
-            gop_size: 3,
-            video_signal: VideoSignal::default(),
-            content_light: None,
-            mastering_display: None,
-        };
-        let mut enc = Encoder::new(64, 64, config).unwrap();
-        let frame = FramePixels::solid(64, 64, 128, 128, 128);
-
-        assert!(enc.receive_packet().is_none());
-
-        enc.send_frame(&frame).unwrap();
-        enc.flush();
-        let packet = enc.receive_packet().unwrap();
-
-        assert_eq!(packet.frame_type, FrameType::Key);
-        assert_eq!(packet.frame_number, 0);
-        assert!(!packet.data.is_empty());
+    pub fn rate_control_stats(&self) -> Option<crate::rc::RateControlStats> {
+        self.rate_ctrl.as_ref().map(|rc| rc.stats())
+    }
 
-        assert!(enc.receive_packet().is_none());
+    /// Snapshot of the state a later encoder can be primed with through
+    /// [`Encoder::warm_start`]. Only rate-controlled encodes learn a model;
+    /// for fixed-quantizer encodes `frames_observed` is 0.
+    pub fn warm_start_state(&self) -> WarmStartState {
+        let (frames_observed, (avg_qp, bits_per_pixel)) = match &self.rate_ctrl {
+            Some(rc) => (rc.stats().frames_encoded, rc.model()),
+            None => (0, (self.config.base_q_idx as f64, 0.0)),
+        };
+        WarmStartState {
+            bit_depth: self.config.video_signal.bit_depth,
+            frames_observed,
+            avg_qp,
+            bits_per_pixel,
+        }
+    }
+
+    /// Primes a fresh encoder with state from a previous encode. Must be
+    /// called before the first `send_frame`; the bit depth must match. Has no
+    /// effect on fixed-quantizer encodes or when the state observed no frames.
+    pub fn warm_start(&mut self, state: &WarmStartState) -> Result<(), EncoderError> {
+        if self.frame_index != 0 {
+            return Err(EncoderError::InvalidConfig {
+                reason: "warm start must happen before the first frame",
+            });
+        }
+        if state.bit_depth != self.config.video_signal.bit_depth {
+            return Err(EncoderError::InvalidConfig {
+                reason: "warm-start state bit depth differs from the encoder",
+            });
+        }
+        if state.frames_observed == 0 {
+            return Ok(());
+        }
+        if let Some(rc) = &mut self.rate_ctrl {
+            rc.prime(state.avg_qp, state.bits_per_pixel);
+        }
+        Ok(())
+    }
+
+    /// A copy of the encoder at this point of the stream: queued frames,
+    /// references, rate control, pending packets and all. The two then
+    /// diverge independently, e.g. with a `reconfigure` applied to one of
+    /// them to A/B a setting mid-stream; fed the same frames and settings,
+    /// both produce the same packets.
+    ///
+    /// Host callbacks cannot be copied, so encoders with a
+    /// [`RateController`], a pre-encode hook or a slice hook are rejected.
+    pub fn try_clone(&self) -> Result<Self, EncoderError> {
+        if self.rate_controller.is_some()
+            || self.pre_encode_hook.is_some()
+            || self.slice_hook.is_some()
+        {
+            return Err(EncoderError::InvalidConfig {
+                reason: "encoders with a rate controller, pre-encode hook or slice hook cannot be cloned",
+            });
+        }
+        Ok(Self {
+            config: self.config.clone(),
+            width: self.width,
+            height: self.height,
+            sequence_level_idx: self.sequence_level_idx,
+            tile_plan: self.tile_plan.clone(),
+            frame_index: self.frame_index,
+            rate_ctrl: self.rate_ctrl.clone(),
+            frame_budget: self.frame_budget.clone(),
+            rate_controller: None,
+            pre_encode_hook: None,
+            slice_hook: None,
+            #[cfg(feature = "mode-hook")]
+            intra_mode_hook: self.intra_mode_hook,
+            reference: self.reference.clone(),
+            reference_index: self.reference_index,
+            previous_mvs: self.previous_mvs.clone(),
+            base_slot: self.base_slot,
+            ref_pool: self.ref_pool.clone(),
+            gop_queue: self.gop_queue.clone(),
+            pending_packets: self.pending_packets.clone(),
+            packet_pool: PacketPool::default(),
+            frame_user_data: self.frame_user_data.clone(),
+            frame_q_idx: self.frame_q_idx.clone(),
+            capture_clock: self.capture_clock.clone(),
+            frame_timing: self.frame_timing.clone(),
+            last_source: self.last_source.clone(),
+            complexity_queue: self.complexity_queue.clone(),
+            distortion_queue: self.distortion_queue.clone(),
+            motion_queue: self.motion_queue.clone(),
+            preview: self
+                .preview
+                .as_ref()
+                .map(|preview| preview.try_clone().map(Box::new))
+                .transpose()?,
+            first_pass: self.first_pass.clone(),
+            lossless: self.lossless,
+            temporal_filter_frames: self.temporal_filter_frames,
+            sequence_header_change: self.sequence_header_change,
+            intra_refresh: self.intra_refresh,
+            cyclic_refresh: self.cyclic_refresh.clone(),
+            metadata_emitted: self.metadata_emitted,
+            frame_size_cap: self.frame_size_cap,
+            end_of_stream: self.end_of_stream,
+        })
+    }
+
+    /// Hands quantizer selection to `controller` for the rest of the stream.
+    /// Must be called before the first `send_frame`, on an encoder without
+    /// `target_bitrate` or `intra_frame_budget`; `config.base_q_idx` then only
+    /// sets the B-frame quantizer, and the encoder is never lossless.
+    pub fn set_rate_controller(
+        &mut self,
+        controller: impl RateController + 'static,
+    ) -> Result<(), EncoderError> {
+        if self.frame_index != 0 {
+            return Err(EncoderError::InvalidConfig {
+                reason: "rate controller must be set before the first frame",
+            });
+        }
+        validate_external_rate_control(&self.config)?;
+        self.rate_controller = Some(ExternalRateController(Box::new(controller)));
+        self.lossless = false;
+        Ok(())
+    }
+
+    /// Routes the intra mode candidates of every later keyframe block through
+    /// `hook`, or restores the built-in ranking with `None`. See
+    /// [`crate::mode_hook`].
+    #[cfg(feature = "mode-hook")]
+    pub fn set_intra_mode_hook(
+        &mut self,
+        hook: Option<&'static dyn crate::mode_hook::IntraModeHook>,
+    ) {
+        self.intra_mode_hook = hook.map(crate::mode_hook::IntraModeHookRef);
+    }
+
+    /// Reconstruction of the most recently encoded reference frame, i.e. what
+    /// a decoder outputs for it. `None` before the first frame is encoded.
+    pub fn last_reconstruction(&self) -> Option<&FramePixels> {
+        self.reference.as_ref()
+    }
+
+    /// Takes the next per-frame complexity score, in submission order.
+    ///
+    /// Scores are computed in `send_frame` when
+    /// `EncoderConfig::complexity_analysis` is set, so they are available
+    /// before the frame's packet is produced.
+    pub fn receive_frame_complexity(&mut self) -> Option<FrameComplexity> {
+        self.complexity_queue.pop_front()
+    }
+
+    /// Takes the next per-superblock distortion map, in coding order.
+    ///
+    /// Maps are recorded when `EncoderConfig::distortion_maps` is set, one
+    /// per coded frame (hidden frames included), by the time the frame's
+    /// packet is available.
+    pub fn receive_distortion_map(&mut self) -> Option<DistortionMap> {
+        self.distortion_queue.pop_front()
+    }
+
+    /// Takes the coded motion of the next inter frame, in coding order.
+    ///
+    /// Fields are recorded when `EncoderConfig::motion_fields` is set;
+    /// keyframes and intra-only frames have none.
+    pub fn receive_motion_field(&mut self) -> Option<tile::MotionField> {
+        self.motion_queue.pop_front()
+    }
+}
+
+/// The tile layout `config` asks for at this frame size: `tile_columns`,
+/// and `tile_rows` or `frame_slices` rows, coded on `threads` threads.
+fn tile_plan(width: u32, height: u32, config: &EncoderConfig) -> frame::TilePlan {
+    frame::build_tile_plan_with_layout(
+        width,
+        height,
+        config.tile_columns,
+        config.tile_rows.max(config.frame_slices),
+    )
+    .with_threads(config.threads)
+}
+
+/// Encodes `frame` on its own as a still picture: the still-picture
+/// sequence header, the CLL and MDCV metadata OBUs when configured, and the
+/// keyframe, i.e. the item data of a single-image AVIF. The leading OBUs up
+/// to the frame are the configuration OBUs of the matching `av1C`.
+///
+/// Unlike [`Encoder`] this keeps no state and allocates no reference frames
+/// or GOP queue, so it can be called from any number of threads at once.
+/// The frame is coded exactly as an [`Encoder`]'s first keyframe would be,
+/// including the `intra_frame_budget` retries. `target_bitrate` needs a
+/// stream to control and is rejected, as are bit depth or layout mismatches
+/// between `frame` and `config`.
+pub fn encode_still(frame: &FramePixels, config: &EncoderConfig) -> Result<Vec<u8>, EncoderError> {
+    let (width, height) = (frame.width, frame.height);
+    limits::check_dimensions(width, height)?;
+    validate_config(config)?;
+    if config.target_bitrate.is_some() {
+        return Err(EncoderError::InvalidConfig {
+            reason: "a still picture has no bitrate; use intra frame budget",
+        });
+    }
+    check_frame_layout(width, height, config, frame)?;
+    check_sample_range(config, frame)?;
+
+    let level = sequence::derive_sequence_level_idx(width, height, config.fps);
+    let tile_plan = tile_plan(width, height, config);
+    let mut out = obu::obu_wrap(
+        obu::ObuType::SequenceHeader,
+        &sequence_header(width, height, config, level, true),
+    );
+    for m in metadata_obus(config) {
+        out.extend_from_slice(&m);
+    }
+
+    let encode = |base_q_idx: u8| {
+        let (coded, _recon) = frame::encode_frame_with_tile_plan(
+            frame,
+            base_q_idx,
+            frame_dequant(config, base_q_idx, true),
+            &tile_plan,
+            config.tune.early_exit(config.speed),
+            config.tune.cdef_search(config.cdef_search),
+            config.loop_restoration,
+            &sb_delta_q(config, &tile_plan, frame.height, base_q_idx, true, None),
+            None,
+        );
+        frame_obus(config, &tile_plan, &coded).concat()
+    };
+    let mut budget = config
+        .intra_frame_budget
+        .map(|bytes| FrameBudget::new(bytes, config.base_q_idx));
+    let mut base_q_idx = budget.as_ref().map_or(config.base_q_idx, FrameBudget::qp);
+    let mut coded = encode(base_q_idx);
+    if let Some(budget) = &mut budget {
+        // Sized like an `Encoder` packet, temporal delimiter included.
+        let td_len = obu::obu_wrap(obu::ObuType::TemporalDelimiter, &[]).len();
+        let packet_len = |coded: &[u8]| td_len + out.len() + coded.len();
+        let mut attempt = 1;
+        while let Some(q) = budget.retry_qp(base_q_idx, packet_len(&coded), attempt) {
+            base_q_idx = q;
+            coded = encode(base_q_idx);
+            attempt += 1;
+        }
+    }
+    out.extend_from_slice(&coded);
+    Ok(out)
+}
+
+fn check_frame_layout(
+    width: u32,
+    height: u32,
+    config: &EncoderConfig,
+    pixels: &FramePixels,
+) -> Result<(), EncoderError> {
+    if pixels.width != width || pixels.height != height {
+        return Err(EncoderError::DimensionMismatch {
+            expected_w: width,
+            expected_h: height,
+            got_w: pixels.width,
+            got_h: pixels.height,
+        });
+    }
+    let luma = (width * height) as usize;
+    let chroma = (width.div_ceil(2) * height.div_ceil(2)) as usize;
+    for (plane, expected, got) in [
+        ("y", luma, pixels.y.len()),
+        ("u", chroma, pixels.u.len()),
+        ("v", chroma, pixels.v.len()),
+    ] {
+        if got != expected {
+            return Err(EncoderError::PlaneSizeMismatch {
+                plane,
+                expected,
+                got,
+            });
+        }
+    }
+    let expected = config.video_signal.bit_depth.bits();
+    let got = pixels.bit_depth.bits();
+    if expected != got {
+        return Err(EncoderError::FrameBitDepthMismatch { expected, got });
+    }
+    Ok(())
+}
+
+fn check_sample_range(config: &EncoderConfig, pixels: &FramePixels) -> Result<(), EncoderError> {
+    let max_value = config.video_signal.bit_depth.max_value();
+    if let Some(sample) = pixels
+        .y
+        .iter()
+        .chain(pixels.u.iter())
+        .chain(pixels.v.iter())
+        .copied()
+        .find(|&s| s > max_value)
+    {
+        return Err(EncoderError::SampleOutOfRange {
+            bit_depth: config.video_signal.bit_depth.bits(),
+            sample,
+        });
+    }
+    Ok(())
+}
+
+fn sequence_header(
+    width: u32,
+    height: u32,
+    config: &EncoderConfig,
+    sequence_level_idx: u8,
+    still_picture: bool,
+) -> Vec<u8> {
+    let options = sequence::SequenceOptions {
+        still_picture,
+        separate_uv_delta_q: config.chroma_delta_q.separate_uv(),
+        enable_restoration: config.loop_restoration,
+    };
+    sequence::encode_sequence_header_with_options(
+        width,
+        height,
+        &config.video_signal,
+        sequence_level_idx,
+        &options,
+    )
+}
+
+/// The built-in controller for `config.target_bitrate`, if any.
+#[cfg(feature = "rate-control")]
+fn builtin_rate_control(config: &EncoderConfig, width: u32, height: u32) -> Option<RateControl> {
+    config.target_bitrate.map(|bitrate| {
+        let mut rc = RateControl::new(bitrate, config.fps, width, height, config.keyint);
+        if let TwoPass::SecondPass(stats) = &config.two_pass {
+            rc.plan_from_first_pass(stats.clone(), config.video_signal.bit_depth);
+        }
+        rc
+    })
+}
+
+/// `validate_config` rejects `target_bitrate` without the feature.
+#[cfg(not(feature = "rate-control"))]
+fn builtin_rate_control(_config: &EncoderConfig, _width: u32, _height: u32) -> Option<RateControl> {
+    None
+}
+
+#[cfg(feature = "hdr")]
+fn metadata_obus(config: &EncoderConfig) -> Vec<Vec<u8>> {
+    let mut out = Vec::new();
+    if let Some(cll) = config.content_light {
+        let payload = crate::metadata::encode_hdr_cll(&cll);
+        out.push(obu::obu_wrap(obu::ObuType::Metadata, &payload));
+    }
+    if let Some(mdcv) = config.mastering_display {
+        let payload = crate::metadata::encode_hdr_mdcv(&mdcv);
+        out.push(obu::obu_wrap(obu::ObuType::Metadata, &payload));
+    }
+    out
+}
+
+/// `validate_config` rejects HDR metadata without the feature.
+#[cfg(not(feature = "hdr"))]
+fn metadata_obus(_config: &EncoderConfig) -> Vec<Vec<u8>> {
+    Vec::new()
+}
+
+/// The packets of a frame coded with `frame_slices`, built from its parts
+/// as they are coded. Every tile row but the last becomes a `partial`
+/// packet as soon as it is complete, handed to `hook` or else queued; the
+/// first also carries the temporal unit's headers and the frame header.
+struct SlicedFrame<'a> {
+    plan: frame::TilePlan,
+    /// The next partial packet, filled up to its tile group.
+    packet: Packet,
+    tiles: Vec<Vec<u8>>,
+    hook: Option<&'a mut SliceHook>,
+    queued: Vec<Packet>,
+}
+
+impl SlicedFrame<'_> {
+    fn push(&mut self, part: frame::FramePart<'_>) {
+        let (i, tile) = match part {
+            frame::FramePart::Header(header) => {
+                let header = obu::obu_wrap(obu::ObuType::FrameHeader, header);
+                self.packet.data.extend_from_slice(&header);
+                return;
+            }
+            frame::FramePart::Tile(i, tile) => (i, tile),
+        };
+        self.tiles.push(tile.to_vec());
+        let cols = self.plan.tile_cols as usize;
+        let row = (i / cols) as u32;
+        if (i + 1) % cols != 0 || row + 1 == self.plan.tile_rows {
+            return;
+        }
+        let group = frame::tile_row_group(&self.plan, row..row + 1, &self.tiles);
+        self.packet
+            .data
+            .extend_from_slice(&obu::obu_wrap(obu::ObuType::TileGroup, &group));
+        let next = Packet {
+            frames: vec![PacketFrame {
+                range: 0..0,
+                ..self.packet.frames[0].clone()
+            }],
+            data: Vec::new(),
+            frame_type: self.packet.frame_type.clone(),
+            sequence_header: None,
+            ..self.packet
+        };
+        let mut packet = std::mem::replace(&mut self.packet, next);
+        packet.frames[0].range.end = packet.data.len();
+        match &mut self.hook {
+            Some(hook) => (hook.0)(packet),
+            None => self.queued.push(packet),
+        }
+    }
+}
+
+/// The OBUs coding `coded`: a single OBU_FRAME, or with `frame_slices`
+/// an OBU_FRAME_HEADER plus one OBU_TILE_GROUP per tile row, grouped
+/// into one element per packet.
+fn frame_obus(
+    config: &EncoderConfig,
+    plan: &frame::TilePlan,
+    coded: &frame::CodedFrame,
+) -> Vec<Vec<u8>> {
+    if config.frame_slices <= 1 || plan.tile_rows <= 1 {
+        return vec![obu::obu_wrap(obu::ObuType::Frame, &coded.payload())];
+    }
+    let mut slices: Vec<Vec<u8>> = (0..plan.tile_rows)
+        .map(|row| {
+            obu::obu_wrap(
+                obu::ObuType::TileGroup,
+                &coded.tile_row_group(plan, row..row + 1),
+            )
+        })
+        .collect();
+    let mut first = obu::obu_wrap(obu::ObuType::FrameHeader, &coded.header_obu);
+    first.extend_from_slice(&slices[0]);
+    slices[0] = first;
+    slices
+}
+
+fn frame_dequant(
+    config: &EncoderConfig,
+    base_q_idx: u8,
+    is_keyframe: bool,
+) -> dequant::DequantValues {
+    dequant::lookup_dequant_with_delta(
+        base_q_idx,
+        config.video_signal.bit_depth,
+        config.chroma_delta_q,
+    )
+    .with_dead_zone(&config.tune.dead_zone(config.dead_zone), is_keyframe)
+}
+
+/// Per-superblock q indices for a frame at `base_q_idx`: the projection's
+/// row weighting, with the superblocks flagged in `boosted` refined by
+/// cyclic refresh. Delta q is left off when every superblock would use the
+/// frame's q index, and at q index 0, where the header cannot signal it.
+fn sb_delta_q(
+    config: &EncoderConfig,
+    plan: &frame::TilePlan,
+    height: u32,
+    base_q_idx: u8,
+    is_intra: bool,
+    boosted: Option<&[bool]>,
+) -> tile::SbDeltaQ {
+    if base_q_idx == 0 {
+        return tile::SbDeltaQ::default();
+    }
+    let (sb_cols, sb_rows) = (plan.sb_cols, plan.sb_rows);
+    let row_q_idx = match config.projection {
+        Projection::Flat => vec![base_q_idx; sb_rows as usize],
+        Projection::Equirect => projection::equirect_row_q_idx(base_q_idx, height, sb_rows),
+    };
+    let mut q_idx: Vec<u8> = row_q_idx
+        .iter()
+        .flat_map(|&q| std::iter::repeat_n(q, sb_cols as usize))
+        .collect();
+    if let Some(boosted) = boosted {
+        for (q, _) in q_idx.iter_mut().zip(boosted).filter(|(_, b)| **b) {
+            *q = aq::boost_q_idx(*q);
+        }
+    }
+    if q_idx.iter().all(|&q| q == base_q_idx) {
+        return tile::SbDeltaQ::default();
+    }
+    let mut dequant: Vec<(u8, dequant::DequantValues)> = Vec::new();
+    for &q in &q_idx {
+        if dequant.iter().all(|&(dq_q, _)| dq_q != q) {
+            dequant.push((q, frame_dequant(config, q, is_intra)));
+        }
+    }
+    tile::SbDeltaQ {
+        sb_cols,
+        q_idx,
+        dequant,
+    }
+}
+
+fn validate_external_rate_control(config: &EncoderConfig) -> Result<(), EncoderError> {
+    if config.target_bitrate.is_some() || config.intra_frame_budget.is_some() {
+        return Err(EncoderError::InvalidConfig {
+            reason: "a rate controller replaces target bitrate and intra frame budget",
+        });
+    }
+    Ok(())
+}
+
+fn validate_config(config: &EncoderConfig) -> Result<(), EncoderError> {
+    if !cfg!(feature = "inter") && config.b_frames {
+        return Err(EncoderError::InvalidConfig {
+            reason: "b-frames require the `inter` feature",
+        });
+    }
+
+    if !cfg!(feature = "rate-control") && config.target_bitrate.is_some() {
+        return Err(EncoderError::InvalidConfig {
+            reason: "target bitrate requires the `rate-control` feature",
+        });
+    }
+
+    if !cfg!(feature = "hdr")
+        && (config.content_light.is_some() || config.mastering_display.is_some())
+    {
+        return Err(EncoderError::InvalidHdrMetadata {
+            reason: "HDR metadata requires the `hdr` feature",
+        });
+    }
+
+    if !config.chroma_delta_q.is_valid() {
+        return Err(EncoderError::InvalidConfig {
+            reason: "chroma delta q must be in -64..=63",
+        });
+    }
+
+    if config.mv_search_range == 0 || config.mv_search_range > tile::MAX_MV_SEARCH_RANGE {
+        return Err(EncoderError::InvalidConfig {
+            reason: "mv search range must be in 1..=256",
+        });
+    }
+
+    if config.intra_frame_budget == Some(0) {
+        return Err(EncoderError::InvalidConfig {
+            reason: "intra frame budget must be at least one byte",
+        });
+    }
+
+    if config.intra_frame_budget.is_some() && config.target_bitrate.is_some() {
+        return Err(EncoderError::InvalidConfig {
+            reason: "intra frame budget and target bitrate are mutually exclusive",
+        });
+    }
+
+    if config.max_frame_bytes == Some(0) {
+        return Err(EncoderError::InvalidConfig {
+            reason: "max frame bytes must be at least one byte",
+        });
+    }
+
+    if config.max_frame_bytes.is_some() && (config.b_frames || config.frame_slices > 1) {
+        return Err(EncoderError::InvalidConfig {
+            reason: "max frame bytes requires b_frames and frame slices off",
+        });
+    }
+
+    if config.frame_slices > 1 && config.b_frames {
+        return Err(EncoderError::InvalidConfig {
+            reason: "frame slices require b_frames off",
+        });
+    }
+
+    if config.frame_slices > 1 && config.intra_frame_budget.is_some() {
+        return Err(EncoderError::InvalidConfig {
+            reason: "frame slices and intra frame budget are mutually exclusive",
+        });
+    }
+
+    if config.first_pass_scale == 0 || config.first_pass_scale > analysis::MAX_FIRST_PASS_SCALE {
+        return Err(EncoderError::InvalidConfig {
+            reason: "first pass scale must be in 1..=8",
+        });
+    }
+
+    if matches!(config.two_pass, TwoPass::SecondPass(_)) && config.target_bitrate.is_none() {
+        return Err(EncoderError::InvalidConfig {
+            reason: "a second pass requires a target bitrate",
+        });
+    }
+
+    if config.two_pass == TwoPass::FirstPass && config.preview_q_idx.is_some() {
+        return Err(EncoderError::InvalidConfig {
+            reason: "a first pass codes no preview stream",
+        });
+    }
+
+    if config.preview_q_idx == Some(0) {
+        return Err(EncoderError::InvalidConfig {
+            reason: "preview q index must be non-zero",
+        });
+    }
+
+    if config.speed > tile::MAX_SPEED {
+        return Err(EncoderError::InvalidConfig {
+            reason: "speed must be in 0..=2",
+        });
+    }
+
+    if (config.content_light.is_some() || config.mastering_display.is_some())
+        && config.video_signal.bit_depth.bits() != 10
+    {
+        return Err(EncoderError::InvalidHdrMetadata {
+            reason: "HDR metadata requires 10-bit signal",
+        });
+    }
+
+    if (config.content_light.is_some() || config.mastering_display.is_some())
+        && config.video_signal.color_description.is_none()
+    {
+        return Err(EncoderError::InvalidHdrMetadata {
+            reason: "HDR metadata requires color description signaling",
+        });
+    }
+
+    Ok(())
+}
+
+/// A frame of `pixels`' size filled with the mean of each of its planes.
+fn flat_mean_frame(pixels: &FramePixels) -> FramePixels {
+    let mean = |plane: &[u16]| {
+        (plane.iter().map(|&s| s as u64).sum::<u64>() / plane.len().max(1) as u64) as u16
+    };
+    FramePixels::solid_with_bit_depth(
+        pixels.width,
+        pixels.height,
+        mean(&pixels.y),
+        mean(&pixels.u),
+        mean(&pixels.v),
+        pixels.bit_depth,
+        pixels.color_range,
+    )
+}
+
+fn preflight_frame_buffer_reserve(width: u32, height: u32) -> Result<(), EncoderError> {
+    let fail = |reason: String| EncoderError::AllocationPreflightFailed {
+        width,
+        height,
+        reason,
+    };
+
+    let luma_samples = width
+        .checked_mul(height)
+        .ok_or_else(|| fail("luma sample count overflow".to_owned()))?;
+    let chroma_samples = width
+        .div_ceil(2)
+        .checked_mul(height.div_ceil(2))
+        .ok_or_else(|| fail("chroma sample count overflow".to_owned()))?;
+    let total_samples_per_frame = u64::from(luma_samples) + 2 * u64::from(chroma_samples);
+    let total_samples_reserve = total_samples_per_frame
+        .checked_mul(2)
+        .ok_or_else(|| fail("frame reserve sample count overflow".to_owned()))?;
+    let reserve_elems = usize::try_from(total_samples_reserve)
+        .map_err(|_| fail("frame reserve sample count does not fit platform usize".to_owned()))?;
+
+    let mut preflight = Vec::<u16>::new();
+    preflight.try_reserve_exact(reserve_elems).map_err(|e| {
+        fail(format!(
+            "unable to reserve {} u16 samples for frame buffers: {}",
+            reserve_elems, e
+        ))
+    })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_valid_dimensions() {
+        let config = EncoderConfig {
+            base_q_idx: 128,
+            keyint: 25,
+            ..EncoderConfig::from(&EncodeConfig::default())
+        };
+        let enc = Encoder::new(64, 64, config);
+        assert!(enc.is_ok());
+        let enc = enc.unwrap();
+        assert_eq!(enc.width(), 64);
+        assert_eq!(enc.height(), 64);
+    }
+
+    #[test]
+    fn new_min_dimensions() {
+        let config = EncoderConfig {
+            base_q_idx: 128,
+            keyint: 25,
+            ..EncoderConfig::from(&EncodeConfig::default())
+        };
+        assert!(Encoder::new(1, 1, config).is_ok());
+    }
+
+    #[test]
+    fn new_above_old_dimension_cap_is_valid() {
+        let config = EncoderConfig {
+            base_q_idx: 128,
+            keyint: 25,
+            ..EncoderConfig::from(&EncodeConfig::default())
+        };
+        assert!(Encoder::new(4097, 2305, config).is_ok());
+    }
+
+    #[test]
+    fn new_invalid_width_zero() {
+        let config = EncoderConfig {
+            base_q_idx: 128,
+            keyint: 25,
+            ..EncoderConfig::from(&EncodeConfig::default())
+        };
+        let result = Encoder::new(0, 64, config);
+        assert!(result.is_err());
+        match result.unwrap_err() {
+            EncoderError::InvalidDimensions { width, height } => {
+                assert_eq!(width, 0);
+                assert_eq!(height, 64);
+            }
+            _ => panic!("expected InvalidDimensions"),
+        }
+    }
+
+    #[test]
+    fn new_extremely_large_dimensions_fail_preflight() {
+        let config = EncoderConfig {
+            base_q_idx: 128,
+            keyint: 25,
+            ..EncoderConfig::from(&EncodeConfig::default())
+        };
+        let result = Encoder::new(
+            limits::MAX_FRAME_DIMENSION,
+            limits::MAX_FRAME_DIMENSION,
+            config,
+        );
+        assert!(result.is_err());
+        match result.unwrap_err() {
+            EncoderError::AllocationPreflightFailed { .. } => {}
+            other => panic!("expected AllocationPreflightFailed, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn new_width_above_av1_sequence_header_limit_is_invalid() {
+        let config = EncoderConfig {
+            base_q_idx: 128,
+            keyint: 25,
+            ..EncoderConfig::from(&EncodeConfig::default())
+        };
+        let result = Encoder::new(limits::MAX_FRAME_DIMENSION + 1, 64, config);
+        assert!(result.is_err());
+        match result.unwrap_err() {
+            EncoderError::InvalidDimensions { width, height } => {
+                assert_eq!(width, limits::MAX_FRAME_DIMENSION + 1);
+                assert_eq!(height, 64);
+            }
+            other => panic!("expected InvalidDimensions, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn new_height_above_av1_sequence_header_limit_is_invalid() {
+        let config = EncoderConfig {
+            base_q_idx: 128,
+            keyint: 25,
+            ..EncoderConfig::from(&EncodeConfig::default())
+        };
+        let result = Encoder::new(64, limits::MAX_FRAME_DIMENSION + 1, config);
+        assert!(result.is_err());
+        match result.unwrap_err() {
+            EncoderError::InvalidDimensions { width, height } => {
+                assert_eq!(width, 64);
+                assert_eq!(height, limits::MAX_FRAME_DIMENSION + 1);
+            }
+            other => panic!("expected InvalidDimensions, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn new_invalid_height_zero() {
+        let config = EncoderConfig {
+            base_q_idx: 128,
+            keyint: 25,
+            ..EncoderConfig::from(&EncodeConfig::default())
+        };
+        assert!(Encoder::new(64, 0, config).is_err());
+    }
+
+    #[test]
+    fn new_height_above_old_cap_is_valid() {
+        let config = EncoderConfig {
+            base_q_idx: 128,
+            keyint: 25,
+            ..EncoderConfig::from(&EncodeConfig::default())
+        };
+        assert!(Encoder::new(64, 2305, config).is_ok());
+    }
+
+    #[test]
+    fn send_frame_receive_packet_lifecycle() {
+        let config = EncoderConfig {
+            base_q_idx: 128,
+            keyint: 25,
+            ..EncoderConfig::from(&EncodeConfig::default())
+        };
+        let mut enc = Encoder::new(64, 64, config).unwrap();
+        let frame = FramePixels::solid(64, 64, 128, 128, 128);
+
+        assert!(enc.receive_packet().is_none());
+
+        enc.send_frame(&frame).unwrap();
+        enc.flush();
+        let packet = enc.receive_packet().unwrap();
+
+        assert_eq!(packet.frame_type, FrameType::Key);
+        assert_eq!(packet.frame_number, 0);
+        assert!(!packet.data.is_empty());
+
+        assert!(enc.receive_packet().is_none());
+    }
+
+    #[test]
+    fn first_frame_is_keyframe() {
+        let config = EncoderConfig {
+            base_q_idx: 128,
+            keyint: 25,
+            ..EncoderConfig::from(&EncodeConfig::default())
+        };
+        let mut enc = Encoder::new(64, 64, config).unwrap();
+        let frame = FramePixels::solid(64, 64, 128, 128, 128);
+
+        enc.send_frame(&frame).unwrap();
+        enc.flush();
+        let packet = enc.receive_packet().unwrap();
+        assert_eq!(packet.frame_type, FrameType::Key);
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn second_frame_is_inter() {
+        let config = EncoderConfig {
+            base_q_idx: 128,
+            keyint: 25,
+            ..EncoderConfig::from(&EncodeConfig::default())
+        };
+        let mut enc = Encoder::new(64, 64, config).unwrap();
+        let frame = FramePixels::solid(64, 64, 128, 128, 128);
+
+        enc.send_frame(&frame).unwrap();
+        enc.send_frame(&frame).unwrap();
+        enc.flush();
+
+        // We expect Frame 0 (Key), then Frame 1 (P-frame, emitted as end of GOP=1)
+        let _key_packet = enc.receive_packet().unwrap();
+        let packet = enc.receive_packet().unwrap();
+        assert_eq!(packet.frame_type, FrameType::Inter);
+        assert_eq!(packet.frame_number, 1);
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn keyint_triggers_new_keyframe() {
+        let config = EncoderConfig {
+            base_q_idx: 128,
+            keyint: 3,
+            ..EncoderConfig::from(&EncodeConfig::default())
+        };
+        let mut enc = Encoder::new(64, 64, config).unwrap();
+        let frame = FramePixels::solid(64, 64, 128, 128, 128);
+
+        let expected_types = [
+            FrameType::Key,
+            FrameType::Inter,
+            FrameType::Inter,
+            FrameType::Key,
+            FrameType::Inter,
+        ];
+
+        for _ in 0..expected_types.len() {
+            enc.send_frame(&frame).unwrap();
+        }
+        enc.flush();
+
+        // Drain the output block
+        let mut actual_types = Vec::new();
+        while let Some(packet) = enc.receive_packet() {
+            actual_types.push((packet.frame_number, packet.frame_type));
+        }
+        // Since GOP emits out of order (P then B), we sort by frame number to verify correctness
+        actual_types.sort_by_key(|a| a.0);
+
+        for (i, expected) in expected_types.iter().enumerate() {
+            assert_eq!(&actual_types[i].1, expected);
+        }
+    }
+
+    #[test]
+    fn dimension_mismatch_error() {
+        let config = EncoderConfig {
+            base_q_idx: 128,
+            keyint: 25,
+            ..EncoderConfig::from(&EncodeConfig::default())
+        };
+        let mut enc = Encoder::new(64, 64, config).unwrap();
+        let wrong_frame = FramePixels::solid(128, 128, 128, 128, 128);
+
+        let result = enc.send_frame(&wrong_frame);
+        assert!(result.is_err());
+        match result.unwrap_err() {
+            EncoderError::DimensionMismatch {
+                expected_w,
+                expected_h,
+                got_w,
+                got_h,
+            } => {
+                assert_eq!(expected_w, 64);
+                assert_eq!(expected_h, 64);
+                assert_eq!(got_w, 128);
+                assert_eq!(got_h, 128);
+            }
+            _ => panic!("expected DimensionMismatch"),
+        }
+    }
+
+    #[test]
+    fn send_frame_rejects_wrong_bit_depth_and_short_planes() {
+        let mut config = EncoderConfig::from(&EncodeConfig::default());
+        config.video_signal.bit_depth = crate::BitDepth::Ten;
+        let mut enc = Encoder::new(64, 64, config).unwrap();
+
+        let eight_bit = FramePixels::solid(64, 64, 128, 128, 128);
+        let err = enc.send_frame(&eight_bit).unwrap_err();
+        assert!(matches!(
+            err,
+            EncoderError::FrameBitDepthMismatch {
+                expected: 10,
+                got: 8
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "frame bit-depth mismatch: expected 10-bit, got 8-bit"
+        );
+
+        let mut short = FramePixels::solid_with_bit_depth(
+            64,
+            64,
+            512,
+            512,
+            512,
+            crate::BitDepth::Ten,
+            crate::ColorRange::Limited,
+        );
+        short.v.truncate(100);
+        let err = enc.send_frame(&short).unwrap_err();
+        assert!(matches!(
+            err,
+            EncoderError::PlaneSizeMismatch {
+                plane: "v",
+                expected: 1024,
+                got: 100
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "frame v plane size mismatch: expected 1024 samples, got 100"
+        );
+        assert_eq!(enc.frames_in_flight(), 0);
+        assert!(enc.receive_packet().is_none());
+    }
+
+    #[test]
+    fn flush_is_callable() {
+        let config = EncoderConfig {
+            base_q_idx: 128,
+            keyint: 25,
+            ..EncoderConfig::from(&EncodeConfig::default())
+        };
+        let mut enc = Encoder::new(64, 64, config).unwrap();
+        enc.flush();
+        assert!(enc.receive_packet().is_none());
+    }
+
+    #[test]
+    fn headers_returns_sequence_header_obu() {
+        let config = EncoderConfig {
+            base_q_idx: 128,
+            keyint: 25,
+            ..EncoderConfig::from(&EncodeConfig::default())
+        };
+        let enc = Encoder::new(64, 64, config).unwrap();
+        let headers = enc.headers();
+        assert_eq!(headers[0], 0x0A);
+        assert!(!headers.is_empty());
+    }
+
+    #[cfg(feature = "hdr")]
+    #[test]
+    fn headers_and_av1c_carry_metadata_obus_and_sequence_level() {
+        let config = EncoderConfig {
+            content_light: Some(ContentLightLevel {
+                max_content_light_level: 1000,
+                max_frame_average_light_level: 400,
+            }),
+            video_signal: VideoSignal::hdr10(crate::ColorRange::Limited),
+            fps: Fps::from_int(300).unwrap(),
+            ..EncoderConfig::from(&crate::EncodeConfig::default())
+        };
+        let enc = Encoder::new(1920, 1080, config).unwrap();
+        let headers = enc.headers();
+        let sequence_header_len = 2 + enc.sequence_header(false).len();
+        assert_eq!(
+            headers[sequence_header_len] >> 3,
+            obu::ObuType::Metadata as u8
+        );
+
+        let av1c = enc.av1c();
+        assert_eq!(av1c[0], 0x81);
+        assert_eq!(av1c[1] & 0x1f, enc.sequence_level_idx);
+        assert_eq!(av1c[2] & 0x40, 0x40);
+        assert!(enc.sequence_level_idx > sequence::SEQ_LEVEL_IDX_5_1);
+        assert_eq!(&av1c[4..], &headers[..]);
+        assert_eq!(
+            &enc.av1c_still_picture()[4..],
+            &enc.headers_still_picture()[..]
+        );
+    }
+
+    #[test]
+    fn headers_still_picture_sets_still_flag_without_reduced_header() {
+        let config = EncoderConfig {
+            base_q_idx: 128,
+            keyint: 25,
+            ..EncoderConfig::from(&EncodeConfig::default())
+        };
+        let enc = Encoder::new(64, 64, config).unwrap();
+        let headers = enc.headers_still_picture();
+
+        assert_eq!(headers[0], 0x0A);
+        assert_eq!(headers[2] & 0b0001_0000, 0b0001_0000);
+        assert_eq!(headers[2] & 0b0000_1000, 0);
+    }
+
+    #[test]
+    fn packet_data_starts_with_temporal_delimiter() {
+        let config = EncoderConfig {
+            base_q_idx: 128,
+            keyint: 25,
+            ..EncoderConfig::from(&EncodeConfig::default())
+        };
+        let mut enc = Encoder::new(64, 64, config).unwrap();
+        let frame = FramePixels::solid(64, 64, 128, 128, 128);
+
+        enc.send_frame(&frame).unwrap();
+        enc.flush();
+        let packet = enc.receive_packet().unwrap();
+
+        assert_eq!(packet.data[0], 0x12);
+        assert_eq!(packet.data[1], 0x00);
+    }
+
+    #[cfg(feature = "rate-control")]
+    #[test]
+    fn encoder_with_rate_control() {
+        let config = EncoderConfig {
+            base_q_idx: 128,
+            keyint: 25,
+            target_bitrate: Some(500_000),
+            ..EncoderConfig::from(&EncodeConfig::default())
+        };
+        let mut enc = Encoder::new(64, 64, config).unwrap();
+        let frame = FramePixels::solid(64, 64, 128, 128, 128);
+
+        enc.send_frame(&frame).unwrap();
+        enc.flush();
+        let packet = enc.receive_packet().unwrap();
+        assert!(!packet.data.is_empty());
+
+        let stats = enc.rate_control_stats();
+        assert!(stats.is_some());
+    }
+
+    #[test]
+    fn frame_numbers_increment() {
+        let config = EncoderConfig {
+            base_q_idx: 128,
+            keyint: 25,
+            ..EncoderConfig::from(&EncodeConfig::default())
+        };
+        let mut enc = Encoder::new(64, 64, config).unwrap();
+        let frame = FramePixels::solid(64, 64, 128, 128, 128);
+
+        for _ in 0..5 {
+            enc.send_frame(&frame).unwrap();
+        }
+        enc.flush();
+
+        let mut actual_nums = Vec::new();
+        while let Some(packet) = enc.receive_packet() {
+            actual_nums.push(packet.frame_number);
+        }
+        actual_nums.sort_unstable();
+
+        for expected_num in 0..5u64 {
+            assert_eq!(actual_nums[expected_num as usize], expected_num);
+        }
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn queue_accessors_track_buffered_frames_and_ready_packets() {
+        let config = EncoderConfig {
+            base_q_idx: 128,
+            keyint: 25,
+            b_frames: true,
+            ..EncoderConfig::from(&EncodeConfig::default())
+        };
+        let mut enc = Encoder::new(64, 64, config).unwrap();
+        let frame = FramePixels::solid(64, 64, 128, 128, 128);
+        assert_eq!(enc.frames_in_flight(), 0);
+        assert_eq!(enc.pending_packets(), 0);
+
+        enc.send_frame(&frame).unwrap();
+        enc.send_frame(&frame).unwrap();
+        assert_eq!(enc.frames_in_flight(), 2);
+        assert_eq!(enc.pending_packets(), 0);
+
+        enc.send_frame(&frame).unwrap();
+        assert_eq!(enc.frames_in_flight(), 0);
+        assert_eq!(enc.pending_packets(), 3);
+
+        enc.send_frame(&frame).unwrap();
+        assert_eq!(enc.frames_in_flight(), 1);
+        enc.flush();
+        assert_eq!(enc.frames_in_flight(), 0);
+        assert_eq!(enc.pending_packets(), 4);
+
+        while enc.receive_packet().is_some() {}
+        assert_eq!(enc.pending_packets(), 0);
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn complexity_scores_are_queued_per_submitted_frame() {
+        let config = EncoderConfig {
+            base_q_idx: 128,
+            keyint: 25,
+            b_frames: true,
+            complexity_analysis: true,
+            ..EncoderConfig::from(&EncodeConfig::default())
+        };
+        let mut enc = Encoder::new(64, 64, config).unwrap();
+        let flat = FramePixels::solid(64, 64, 128, 128, 128);
+        let mut textured = flat.clone();
+        for (i, s) in textured.y.iter_mut().enumerate() {
+            *s = ((i * 37 + (i / 64) * 91) % 200 + 20) as u16;
+        }
+
+        enc.send_frame(&flat).unwrap();
+        enc.send_frame(&textured).unwrap();
+        assert!(enc.frames_in_flight() > 0);
+
+        let first = enc.receive_frame_complexity().unwrap();
+        assert_eq!(first.frame_number, 0);
+        assert_eq!(first.intra_cost, 0);
+        assert_eq!(first.inter_cost, None);
+
+        let second = enc.receive_frame_complexity().unwrap();
+        assert_eq!(second.frame_number, 1);
+        assert!(second.intra_cost > 0);
+        assert!(second.inter_cost.unwrap() > 0);
+        assert!(enc.receive_frame_complexity().is_none());
+    }
+
+    #[test]
+    fn complexity_analysis_is_off_by_default() {
+        let config = EncoderConfig::from(&EncodeConfig::default());
+        let mut enc = Encoder::new(64, 64, config).unwrap();
+        enc.send_frame(&FramePixels::solid(64, 64, 128, 128, 128))
+            .unwrap();
+        assert!(enc.receive_frame_complexity().is_none());
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn distortion_maps_follow_coding_order() {
+        let mut config = EncoderConfig::from(&EncodeConfig::default());
+        config.distortion_maps = true;
+        let mut enc = Encoder::new(160, 64, config.clone()).unwrap();
+        let first = panning_frame(160, 64, 0);
+        enc.send_frame(&first).unwrap();
+        let map = enc.receive_distortion_map().unwrap();
+        assert_eq!((map.frame_number, map.sb_cols, map.sb_rows), (0, 3, 1));
+        let recon = enc.last_reconstruction().unwrap();
+        let sse: u64 = first
+            .y
+            .iter()
+            .zip(&recon.y)
+            .map(|(&a, &b)| (a as i64 - b as i64).pow(2) as u64)
+            .sum();
+        assert_eq!(map.sse.iter().sum::<u64>(), sse);
+        assert_eq!(map.samples, [64 * 64, 64 * 64, 32 * 64]);
+
+        config.b_frames = true;
+        config.gop_size = 3;
+        let mut enc = Encoder::new(160, 64, config).unwrap();
+        for i in 0..4 {
+            enc.send_frame(&panning_frame(160, 64, i * 2)).unwrap();
+        }
+        // The hidden frame ending the mini-GOP is coded before the frames
+        // it predicts.
+        let order: Vec<u64> = std::iter::from_fn(|| enc.receive_distortion_map())
+            .map(|m| m.frame_number)
+            .collect();
+        assert_eq!(order, [0, 2, 1]);
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn motion_fields_report_coded_and_searched_motion() {
+        let mut config = EncoderConfig::from(&EncodeConfig::default());
+        config.motion_fields = true;
+        let mut enc = Encoder::new(128, 64, config).unwrap();
+        // Smooth texture moving 2 pixels left per frame.
+        let frame = |offset: u32| {
+            let mut frame = FramePixels::solid(128, 64, 0, 128, 128);
+            for (i, s) in frame.y.iter_mut().enumerate() {
+                let (x, y) = ((i % 128) as f64 + offset as f64, (i / 128) as f64);
+                let v = 128.0
+                    + 45.0 * (x * 0.13).sin() * (y * 0.11).cos()
+                    + 35.0 * (x * 0.047 + y * 0.071).sin();
+                *s = v.round() as u16;
+            }
+            frame
+        };
+        for i in 0..3 {
+            enc.send_frame(&frame(i * 2)).unwrap();
+        }
+        let fields: Vec<_> = std::iter::from_fn(|| enc.receive_motion_field()).collect();
+        assert_eq!(fields.len(), 2, "the keyframe has no motion");
+        for (field, frame) in fields.iter().zip(1..) {
+            assert_eq!(field.frame_number, frame);
+            assert_eq!(field.reference_frame_number, frame - 1);
+            let units = |mvs: &tile::MvField| -> Vec<Option<(i32, i32)>> {
+                assert_eq!((mvs.mi_cols(), mvs.mi_rows()), (32, 16));
+                (0..16)
+                    .flat_map(|y| (0..32).map(move |x| mvs.get(x, y)))
+                    .collect()
+            };
+            // Units of blocks coded intra have no vector; the texture is
+            // predicted across frames nearly everywhere.
+            let coded = units(&field.coded);
+            let inter_units = coded.iter().filter(|mv| mv.is_some()).count();
+            assert!(inter_units * 8 > coded.len() * 7, "{coded:?}");
+            // 2 pixels is 16 in 1/8 pel. The rightmost blocks have no match
+            // inside the reference.
+            let searched: Vec<_> = units(&field.estimates)
+                .into_iter()
+                .enumerate()
+                .filter(|(unit, _)| unit % 32 < 30)
+                .filter_map(|(_, mv)| mv)
+                .collect();
+            let exact = searched.iter().filter(|&&mv| mv == (16, 0)).count();
+            assert!(exact * 2 > searched.len(), "{searched:?}");
+        }
+        enc.flush();
+        assert!(enc.receive_motion_field().is_none());
+    }
+
+    fn chroma_gradient_frame() -> FramePixels {
+        let mut frame = FramePixels::solid(64, 64, 128, 128, 128);
+        for (i, s) in frame.u.iter_mut().enumerate() {
+            *s = (((i % 32) * 7 + (i / 32) * 3) % 240) as u16;
+        }
+        for (i, s) in frame.v.iter_mut().enumerate() {
+            *s = (250 - (i % 32) * 5 - (i / 32) * 2) as u16;
+        }
+        frame
+    }
+
+    fn encode_keyframe_with(chroma_delta_q: ChromaDeltaQ) -> (Packet, FramePixels) {
+        let mut config = EncoderConfig::from(&EncodeConfig::default());
+        config.chroma_delta_q = chroma_delta_q;
+        let mut enc = Encoder::new(64, 64, config).unwrap();
+        enc.send_frame(&chroma_gradient_frame()).unwrap();
+        enc.flush();
+        let packet = enc.receive_packet().unwrap();
+        (packet, enc.last_reconstruction().unwrap().clone())
+    }
+
+    #[test]
+    fn negative_chroma_delta_q_improves_chroma_fidelity() {
+        let source = chroma_gradient_frame();
+        let (plain_packet, plain) = encode_keyframe_with(ChromaDeltaQ::default());
+        let (fine_packet, fine) = encode_keyframe_with(ChromaDeltaQ {
+            u_dc: -48,
+            u_ac: -48,
+            v_dc: -48,
+            v_ac: -48,
+        });
+        let plain_psnr = crate::metrics::frame_psnr(&source, &plain);
+        let fine_psnr = crate::metrics::frame_psnr(&source, &fine);
+        assert!(fine_psnr.u > plain_psnr.u);
+        assert!(fine_psnr.v > plain_psnr.v);
+        assert_eq!(fine_psnr.y, plain_psnr.y);
+        assert!(fine_packet.data.len() > plain_packet.data.len());
+    }
+
+    #[test]
+    fn separate_v_delta_q_only_changes_v() {
+        let source = chroma_gradient_frame();
+        let (_, plain) = encode_keyframe_with(ChromaDeltaQ::default());
+        let (_, v_only) = encode_keyframe_with(ChromaDeltaQ {
+            v_dc: -48,
+            v_ac: -48,
+            ..ChromaDeltaQ::default()
+        });
+        assert_eq!(v_only.u, plain.u);
+        assert!(
+            crate::metrics::frame_psnr(&source, &v_only).v
+                > crate::metrics::frame_psnr(&source, &plain).v
+        );
+    }
+
+    #[test]
+    fn out_of_range_chroma_delta_q_is_rejected() {
+        let mut config = EncoderConfig::from(&EncodeConfig::default());
+        config.chroma_delta_q.u_ac = 64;
+        assert!(matches!(
+            Encoder::new(64, 64, config),
+            Err(EncoderError::InvalidConfig { .. })
+        ));
+    }
+
+    #[test]
+    fn speed_above_max_is_rejected() {
+        let mut config = EncoderConfig::from(&EncodeConfig::default());
+        config.speed = tile::MAX_SPEED + 1;
+        assert!(matches!(
+            Encoder::new(64, 64, config),
+            Err(EncoderError::InvalidConfig { .. })
+        ));
+    }
+
+    #[test]
+    fn faster_speeds_stay_close_to_exhaustive_search() {
+        let frames: Vec<FramePixels> = (0..4).map(|i| panning_frame(128, 64, i * 2)).collect();
+        let encode = |speed: u8| {
+            let mut config = EncoderConfig::from(&EncodeConfig::default());
+            config.speed = speed;
+            let mut enc = Encoder::new(128, 64, config).unwrap();
+            let (mut bytes, mut psnr) = (0, 0.0);
+            for frame in &frames {
+                enc.send_frame(frame).unwrap();
+                bytes += enc.receive_packet().unwrap().data.len();
+                psnr += crate::metrics::frame_psnr(frame, enc.last_reconstruction().unwrap()).y;
+            }
+            (bytes, psnr / frames.len() as f64)
+        };
+        let (base_bytes, base_psnr) = encode(0);
+        for speed in 1..=tile::MAX_SPEED {
+            let (bytes, psnr) = encode(speed);
+            assert!(
+                psnr > base_psnr - 1.0,
+                "speed {speed}: {psnr} vs {base_psnr}"
+            );
+            assert!(
+                bytes * 10 < base_bytes * 12,
+                "speed {speed}: {bytes} vs {base_bytes} bytes"
+            );
+        }
+    }
+
+    #[test]
+    fn intra_frame_budget_caps_every_keyframe() {
+        let frames: Vec<FramePixels> = (0..6).map(|i| panning_frame(128, 64, i * 5)).collect();
+        let mut config = EncoderConfig::from(&EncodeConfig::default());
+        config.base_q_idx = 20;
+        let mut free = Encoder::new(128, 64, config.clone()).unwrap();
+        free.send_frame(&frames[0]).unwrap();
+        let unconstrained = free.receive_packet().unwrap().data.len();
+
+        let cap = unconstrained / 3;
+        config.intra_frame_budget = Some(cap as u32);
+        let mut enc = Encoder::new(128, 64, config).unwrap();
+        for frame in &frames {
+            enc.send_frame(frame).unwrap();
+            let packet = enc.receive_packet().unwrap();
+            assert_eq!(packet.frame_type, FrameType::Key);
+            assert!(packet.data.len() <= cap, "{} > {cap}", packet.data.len());
+            assert!(
+                packet.data.len() * 3 > cap,
+                "{} bytes leaves most of {cap} unused",
+                packet.data.len()
+            );
+        }
+        let budget = enc.frame_budget.as_ref().unwrap();
+        assert!(budget.qp() > 20);
+        assert_eq!(budget.overshoots(), 0);
+    }
+
+    #[test]
+    fn intra_frame_budget_rejects_zero_and_bitrate() {
+        let mut config = EncoderConfig::from(&EncodeConfig::default());
+        config.intra_frame_budget = Some(0);
+        assert!(matches!(
+            Encoder::new(64, 64, config.clone()),
+            Err(EncoderError::InvalidConfig { .. })
+        ));
+        config.intra_frame_budget = Some(4000);
+        config.target_bitrate = Some(500_000);
+        assert!(matches!(
+            Encoder::new(64, 64, config),
+            Err(EncoderError::InvalidConfig { .. })
+        ));
+    }
+
+    /// Noise that changes every frame, which no quantizer codes small.
+    fn churning_frames(count: u32) -> Vec<FramePixels> {
+        (0..count)
+            .map(|i| {
+                let mut frame = noisy_frame(128, 64, crate::BitDepth::Eight);
+                for (j, s) in frame.y.iter_mut().enumerate() {
+                    *s = (*s + (j as u32 * (i + 1) * 37 % 61) as u16) % 256;
+                }
+                frame
+            })
+            .collect()
+    }
+
+    #[test]
+    fn max_frame_bytes_caps_every_packet() {
+        let frames = churning_frames(5);
+        let mut config = EncoderConfig::from(&EncodeConfig::default());
+        config.base_q_idx = 20;
+        let mut free = Encoder::new(128, 64, config.clone()).unwrap();
+        let mut sizes = Vec::new();
+        for frame in &frames {
+            free.send_frame(frame).unwrap();
+            sizes.push(free.receive_packet().unwrap().data.len());
+        }
+
+        let cap = sizes[1] / 2;
+        config.max_frame_bytes = Some(cap as u32);
+        let mut enc = Encoder::new(128, 64, config).unwrap();
+        for frame in &frames {
+            enc.send_frame(frame).unwrap();
+            let packet = enc.receive_packet().unwrap();
+            assert!(packet.data.len() <= cap, "{} > {cap}", packet.data.len());
+        }
+        let stats = enc.frame_size_cap_stats();
+        assert_eq!(stats.requantized, frames.len() as u64);
+        assert_eq!((stats.repeated, stats.flattened), (0, 0));
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn max_frame_bytes_repeats_or_flattens_frames_too_large_at_255() {
+        let frames = churning_frames(4);
+        let mut config = EncoderConfig::from(&EncodeConfig::default());
+        let floor = Encoder::new(128, 64, config.clone())
+            .unwrap()
+            .smallest_keyframe_packet();
+        // Room for a repeat, not for any detail.
+        config.max_frame_bytes = Some(floor as u32 + 16);
+        let mut enc = Encoder::new(128, 64, config).unwrap();
+        let mut previous: Option<FramePixels> = None;
+        for (i, frame) in frames.iter().enumerate() {
+            enc.send_frame(frame).unwrap();
+            let packet = enc.receive_packet().unwrap();
+            assert!(packet.data.len() <= floor + 16);
+            let recon = enc.last_reconstruction().unwrap().clone();
+            if i == 0 {
+                assert_eq!(packet.frame_type, FrameType::Key);
+                let mean = recon.y.iter().map(|&s| s as u64).sum::<u64>() / recon.y.len() as u64;
+                assert!(recon.y.iter().all(|&s| s.abs_diff(mean as u16) <= 2));
+            } else if packet.frame_type == FrameType::Inter {
+                let psnr = crate::metrics::frame_psnr(previous.as_ref().unwrap(), &recon).y;
+                assert!(psnr > 40.0, "frame {i} repeats at {psnr} dB");
+            }
+            previous = Some(recon);
+        }
+        let stats = enc.frame_size_cap_stats();
+        assert_eq!(stats.requantized, 0);
+        assert_eq!((stats.repeated, stats.flattened), (3, 1));
+    }
+
+    #[test]
+    fn max_frame_bytes_rejects_what_it_cannot_hold() {
+        let mut config = EncoderConfig::from(&EncodeConfig::default());
+        config.max_frame_bytes = Some(0);
+        assert!(matches!(
+            Encoder::new(64, 64, config.clone()),
+            Err(EncoderError::InvalidConfig { .. })
+        ));
+        config.max_frame_bytes = Some(20);
+        assert!(matches!(
+            Encoder::new(64, 64, config.clone()),
+            Err(EncoderError::FrameSizeCapTooSmall {
+                max_frame_bytes: 20,
+                ..
+            })
+        ));
+        config.max_frame_bytes = Some(4000);
+        config.frame_slices = 2;
+        assert!(matches!(
+            Encoder::new(64, 64, config.clone()),
+            Err(EncoderError::InvalidConfig { .. })
+        ));
+        config.frame_slices = 0;
+        let mut enc = Encoder::new(64, 64, config.clone()).unwrap();
+        config.max_frame_bytes = Some(20);
+        assert!(matches!(
+            enc.reconfigure(config),
+            Err(EncoderError::FrameSizeCapTooSmall { .. })
+        ));
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn inter_frames_keep_motion_estimates_for_the_next_frame() {
+        let mut config = EncoderConfig::from(&EncodeConfig::default());
+        config.keyint = 3;
+        let mut enc = Encoder::new(128, 64, config).unwrap();
+        enc.send_frame(&panning_frame(128, 64, 0)).unwrap();
+        assert!(enc.previous_mvs.is_none());
+        enc.send_frame(&panning_frame(128, 64, 6)).unwrap();
+        let (mvs, distance) = enc.previous_mvs.as_ref().unwrap();
+        assert_eq!(distance, &1);
+        assert_eq!((mvs.mi_cols(), mvs.mi_rows()), (32, 16));
+        enc.send_frame(&panning_frame(128, 64, 12)).unwrap();
+        assert!(enc.previous_mvs.is_some());
+        enc.send_frame(&panning_frame(128, 64, 18)).unwrap();
+        assert!(enc.previous_mvs.is_none(), "keyframes drop the field");
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn screen_tune_keeps_motion_on_whole_pixels() {
+        let fractional_mvs = |tune: Tune| {
+            let mut config = EncoderConfig::from(&EncodeConfig::default());
+            config.tune = tune;
+            let mut enc = Encoder::new(128, 64, config).unwrap();
+            // Detailed texture that moves by half a pixel.
+            let first = noisy_frame(128, 64, crate::BitDepth::Eight);
+            let mut second = first.clone();
+            for (i, s) in second.y.iter_mut().enumerate() {
+                let next = first.y[(i + 1).min(first.y.len() - 1)];
+                *s = (first.y[i] + next).div_ceil(2);
+            }
+            enc.send_frame(&first).unwrap();
+            enc.send_frame(&second).unwrap();
+            let (mvs, _) = enc.previous_mvs.as_ref().unwrap();
+            let mut count = 0;
+            for by in 0..mvs.mi_rows() {
+                for bx in 0..mvs.mi_cols() {
+                    if let Some((x, y)) = mvs.get(bx, by) {
+                        count += usize::from(x % 8 != 0 || y % 8 != 0);
+                    }
+                }
+            }
+            count
+        };
+        assert!(fractional_mvs(Tune::Default) > 0);
+        assert_eq!(fractional_mvs(Tune::Screen), 0);
+    }
+
+    fn panning_frame(width: u32, height: u32, offset: u32) -> FramePixels {
+        let mut frame = FramePixels::solid(width, height, 0, 128, 128);
+        for (i, s) in frame.y.iter_mut().enumerate() {
+            let (x, y) = (i as u32 % width + offset, i as u32 / width);
+            *s = ((x * 13 + y * 7 + (x * y) % 17) % 200 + 20) as u16;
+        }
+        frame
+    }
+
+    fn noisy_frame(width: u32, height: u32, bit_depth: crate::BitDepth) -> FramePixels {
+        let max = bit_depth.max_value() as u32;
+        let mut frame = FramePixels::solid_with_bit_depth(
+            width,
+            height,
+            0,
+            0,
+            0,
+            bit_depth,
+            crate::ColorRange::Full,
+        );
+        let mut state = 0x1234_5678u32;
+        for plane in [&mut frame.y, &mut frame.u, &mut frame.v] {
+            for (i, s) in plane.iter_mut().enumerate() {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                let ramp = (i as u32 * 3) % (max + 1);
+                *s = ((ramp + (state >> 16) % 64) % (max + 1)) as u16;
+            }
+        }
+        frame
+    }
+
+    fn assert_lossless_roundtrip(width: u32, height: u32, bit_depth: crate::BitDepth) {
+        let mut config = EncoderConfig::from(&EncodeConfig::default());
+        config.video_signal.bit_depth = bit_depth;
+        let mut enc = Encoder::new_lossless(width, height, config).unwrap();
+        assert!(enc.is_lossless());
+        let source = noisy_frame(width, height, bit_depth);
+        enc.send_frame(&source).unwrap();
+        enc.flush();
+        assert!(enc.receive_packet().is_some());
+        let recon = enc.last_reconstruction().unwrap();
+        assert_eq!(recon.y, source.y);
+        assert_eq!(recon.u, source.u);
+        assert_eq!(recon.v, source.v);
+    }
+
+    #[test]
+    fn lossless_reconstruction_is_bit_exact() {
+        assert_lossless_roundtrip(64, 64, crate::BitDepth::Eight);
+    }
+
+    #[test]
+    fn lossless_reconstruction_is_bit_exact_at_10_bit() {
+        assert_lossless_roundtrip(64, 64, crate::BitDepth::Ten);
+    }
+
+    #[test]
+    fn lossless_reconstruction_is_bit_exact_for_odd_dimensions() {
+        assert_lossless_roundtrip(75, 37, crate::BitDepth::Eight);
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn lossless_streams_are_intra_only() {
+        let mut config = EncoderConfig::from(&EncodeConfig::default());
+        config.b_frames = true;
+        let mut enc = Encoder::new_lossless(64, 64, config).unwrap();
+        let frame = FramePixels::solid(64, 64, 90, 120, 140);
+        for _ in 0..5 {
+            enc.send_frame(&frame).unwrap();
+        }
+        enc.flush();
+        let mut count = 0;
+        while let Some(packet) = enc.receive_packet() {
+            assert_eq!(packet.frame_type, FrameType::Key);
+            count += 1;
+        }
+        assert_eq!(count, 5);
+    }
+
+    #[test]
+    fn nonzero_qindex_is_not_lossless() {
+        let config = EncoderConfig::from(&EncodeConfig::default());
+        assert!(!Encoder::new(64, 64, config).unwrap().is_lossless());
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn keyframes_close_or_end_b_frame_mini_gops() {
+        let packet_order = |closed_gop: bool| {
+            let mut config = EncoderConfig::from(&EncodeConfig {
+                b_frames: true,
+                gop_size: 3,
+                keyint: 5,
+                closed_gop,
+                ..EncodeConfig::default()
+            });
+            config.base_q_idx = 128;
+            let mut enc = Encoder::new(64, 64, config).unwrap();
+            let frame = FramePixels::solid(64, 64, 128, 128, 128);
+            for _ in 0..7 {
+                enc.send_frame(&frame).unwrap();
+            }
+            enc.flush();
+            std::iter::from_fn(|| enc.receive_packet())
+                .map(|p| (p.frame_type, p.frame_number, p.is_reference))
+                .collect::<Vec<_>>()
+        };
+        use FrameType::*;
+        // Keyframe 5 would sit inside the mini-GOP 3..=5.
+        assert_eq!(
+            packet_order(true),
+            [
+                (Key, 0, true),
+                (Inter, 1, true),
+                (ShowExisting, 2, false),
+                (Inter, 3, true),
+                (ShowExisting, 4, false),
+                (Key, 5, true),
+                (Inter, 6, true),
+                (ShowExisting, 6, false),
+            ]
+        );
+        assert_eq!(
+            packet_order(false),
+            [
+                (Key, 0, true),
+                (Inter, 1, true),
+                (ShowExisting, 2, false),
+                (ForwardKey, 3, true),
+                (Inter, 4, false),
+                // Showing the hidden keyframe resets every reference.
+                (ShowExisting, 5, true),
+                (Inter, 6, true),
+            ]
+        );
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn hidden_frames_join_the_next_shown_packet() {
+        let mut config = EncoderConfig::from(&EncodeConfig {
+            b_frames: true,
+            gop_size: 3,
+            keyint: 5,
+            ..EncodeConfig::default()
+        });
+        config.base_q_idx = 128;
+        let mut enc = Encoder::new(64, 64, config).unwrap();
+        let frame = FramePixels::solid(64, 64, 128, 128, 128);
+        for _ in 0..7 {
+            enc.send_frame(&frame).unwrap();
+        }
+        enc.flush();
+        let packets: Vec<Packet> = std::iter::from_fn(|| enc.receive_packet()).collect();
+        let obu_type =
+            |packet: &Packet, frame: &PacketFrame| (packet.data[frame.range.start] >> 3) & 0xf;
+        for packet in &packets {
+            for frame in &packet.frames {
+                let expected = if frame.frame_type == FrameType::ShowExisting {
+                    obu::ObuType::FrameHeader
+                } else {
+                    obu::ObuType::Frame
+                };
+                assert_eq!(obu_type(packet, frame), expected as u8);
+            }
+            assert_eq!(packet.frames.last().unwrap().shown, packet.shown);
+        }
+        // The P-frame after keyframe 5 has no B-frame to ride with.
+        let hidden = &packets[6];
+        assert_eq!((hidden.frame_number, hidden.shown), (6, false));
+
+        let mut grouper = crate::packet::TemporalUnitGrouper::new();
+        let units: Vec<Packet> = packets
+            .into_iter()
+            .filter_map(|packet| grouper.push(packet))
+            .collect();
+        assert!(grouper.finish().is_none());
+        assert_eq!(units.len(), 7);
+        for unit in &units {
+            assert_eq!(unit.frames.iter().filter(|frame| frame.shown).count(), 1);
+            assert_eq!(
+                unit.data[0],
+                obu::obu_wrap(obu::ObuType::TemporalDelimiter, &[])[0]
+            );
+            for frame in &unit.frames {
+                assert!(frame.range.start > 0 && frame.range.end <= unit.data.len());
+            }
+        }
+        let joined = &units[6];
+        assert_eq!(
+            joined
+                .frames
+                .iter()
+                .map(|frame| (frame.frame_type.clone(), frame.shown))
+                .collect::<Vec<_>>(),
+            [(FrameType::Inter, false), (FrameType::ShowExisting, true)]
+        );
+        assert_eq!(joined.frame_type, FrameType::Inter);
+        assert_eq!((joined.frame_number, joined.pts), (6, 6));
+        assert!(joined.is_reference);
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn user_data_follows_frames_through_b_frame_reordering() {
+        let config = EncoderConfig {
+            base_q_idx: 128,
+            keyint: 25,
+            b_frames: true,
+            ..EncoderConfig::from(&EncodeConfig::default())
+        };
+        let mut enc = Encoder::new(64, 64, config).unwrap();
+        let frame = FramePixels::solid(64, 64, 128, 128, 128);
+
+        for i in 0..7u64 {
+            enc.send_frame_with_user_data(&frame, 1000 + i).unwrap();
+        }
+        enc.send_frame(&frame).unwrap();
+        enc.flush();
+
+        let mut seen = Vec::new();
+        while let Some(packet) = enc.receive_packet() {
+            match packet.user_data {
+                Some(v) => {
+                    assert_eq!(v, 1000 + packet.frame_number);
+                    seen.push(packet.frame_number);
+                }
+                None => assert_eq!(packet.frame_number, 7),
+            }
+        }
+        seen.sort_unstable();
+        assert_eq!(seen, (0..7).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn encoder_config_from_encode_config() {
+        let ec = EncodeConfig {
+            base_q_idx: 100,
+            keyint: 10,
+            target_bitrate: Some(1_000_000),
+            fps: Fps::from_int(30).unwrap(),
+            ..EncodeConfig::default()
+        };
+        let config: EncoderConfig = (&ec).into();
+        assert_eq!(config.base_q_idx, 100);
+        assert_eq!(config.keyint, 10);
+        assert_eq!(config.target_bitrate, Some(1_000_000));
+        assert_eq!(config.fps, Fps::from_int(30).unwrap());
     }
 
     #[test]
-    fn first_frame_is_keyframe() {
+    fn hdr_metadata_requires_10bit_signal() {
+        let config = EncoderConfig {
+            base_q_idx: 128,
+            keyint: 25,
+            content_light: Some(ContentLightLevel {
+                max_content_light_level: 1000,
+                max_frame_average_light_level: 400,
+            }),
+            ..EncoderConfig::from(&EncodeConfig::default())
+        };
+        let err = Encoder::new(64, 64, config).unwrap_err();
+        assert!(matches!(err, EncoderError::InvalidHdrMetadata { .. }));
+    }
+
+    #[test]
+    fn hdr_metadata_requires_color_description() {
         let config = EncoderConfig {
             base_q_idx: 128,
             keyint: 25,
-            target_bitrate: None,
-            fps: Fps::default(),
-            b_frames: false,
+            video_signal: VideoSignal {
+                bit_depth: crate::BitDepth::Ten,
+                color_range: crate::ColorRange::Limited,
+                color_description: None,
+                ..VideoSignal::default()
+            },
+            content_light: Some(ContentLightLevel {
+                max_content_light_level: 1000,
+                max_frame_average_light_level: 400,
+            }),
+            ..EncoderConfig::from(&EncodeConfig::default())
+        };
+        let err = Encoder::new(64, 64, config).unwrap_err();
+        assert!(matches!(err, EncoderError::InvalidHdrMetadata { .. }));
+    }
+
+    fn rate_controlled_config() -> EncoderConfig {
+        EncoderConfig::from(&crate::EncodeConfig {
+            target_bitrate: Some(200_000),
+            ..crate::EncodeConfig::default()
+        })
+    }
+
+    #[test]
+    fn slim_builds_reject_what_they_compile_out() {
+        let default = || EncoderConfig::from(&crate::EncodeConfig::default());
+        let b_frames = EncoderConfig {
+            b_frames: true,
+            ..default()
+        };
+        let hdr = EncoderConfig {
+            video_signal: VideoSignal {
+                bit_depth: crate::BitDepth::Ten,
+                color_range: crate::ColorRange::Limited,
+                color_description: Some(crate::ColorDescription {
+                    color_primaries: 9,
+                    transfer_characteristics: 16,
+                    matrix_coefficients: 9,
+                }),
+                ..VideoSignal::default()
+            },
+            content_light: Some(ContentLightLevel {
+                max_content_light_level: 1000,
+                max_frame_average_light_level: 400,
+            }),
+            ..default()
+        };
+        assert_eq!(
+            Encoder::new(64, 64, b_frames).is_ok(),
+            cfg!(feature = "inter")
+        );
+        assert_eq!(
+            Encoder::new(64, 64, rate_controlled_config()).is_ok(),
+            cfg!(feature = "rate-control")
+        );
+        assert_eq!(Encoder::new(64, 64, hdr).is_ok(), cfg!(feature = "hdr"));
+
+        // Without inter coding every frame is a keyframe.
+        let mut enc = Encoder::new(64, 64, default()).unwrap();
+        for y in [80, 90, 100] {
+            enc.send_frame(&FramePixels::solid(64, 64, y, 128, 128))
+                .unwrap();
+        }
+        enc.flush();
+        let types: Vec<FrameType> = std::iter::from_fn(|| enc.receive_packet())
+            .map(|p| p.frame_type)
+            .collect();
+        let later = if cfg!(feature = "inter") {
+            FrameType::Inter
+        } else {
+            FrameType::Key
+        };
+        assert_eq!(types, [FrameType::Key, later.clone(), later]);
+    }
+
+    #[test]
+    fn pre_encode_hook_edits_frames_before_encoding() {
+        fn burn_in(frame: &mut FramePixels) {
+            for row in frame.y.chunks_mut(frame.width as usize).take(8) {
+                row[..16].fill(235);
+            }
+        }
+        let config = EncoderConfig::from(&EncodeConfig::default());
+        let frames: Vec<FramePixels> = (0..3).map(|i| panning_frame(64, 64, i * 2)).collect();
+
+        let mut expected = Encoder::new(64, 64, config.clone()).unwrap();
+        let mut hooked = Encoder::new(64, 64, config.clone()).unwrap();
+        hooked.set_pre_encode_hook(burn_in);
+        for (i, frame) in frames.iter().enumerate() {
+            let mut burned = frame.clone();
+            burn_in(&mut burned);
+            expected.send_frame(&burned).unwrap();
+            if i % 2 == 0 {
+                hooked.send_frame(frame).unwrap();
+            } else {
+                hooked
+                    .send_owned_frame(frame.clone(), FrameOptions::default())
+                    .unwrap();
+            }
+            assert_eq!(
+                hooked.receive_packet().unwrap().data,
+                expected.receive_packet().unwrap().data
+            );
+        }
+
+        let mut broken = Encoder::new(64, 64, config).unwrap();
+        broken.set_pre_encode_hook(|frame| frame.y[0] = 300);
+        assert!(matches!(
+            broken.send_frame(&frames[0]),
+            Err(EncoderError::SampleOutOfRange { sample: 300, .. })
+        ));
+        broken.set_pre_encode_hook(|frame| {
+            frame.u.pop();
+        });
+        assert!(matches!(
+            broken.send_frame(&frames[0]),
+            Err(EncoderError::PlaneSizeMismatch { plane: "u", .. })
+        ));
+        broken.clear_pre_encode_hook();
+        broken.send_frame(&frames[0]).unwrap();
+        assert_eq!(broken.frames_in_flight() + broken.pending_packets(), 1);
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn preflight_reports_what_new_would_build() {
+        let config = EncoderConfig::from(&EncodeConfig::default());
+        let report = Encoder::preflight(64, 48, &config).unwrap();
+        let encoder = Encoder::new(64, 48, config.clone()).unwrap();
+        assert_eq!(report.header_bytes, encoder.headers().len());
+        assert_eq!(report.latency_frames, 0);
+        // Source, LAST, LAST2, GOLDEN and reconstruction, 64x48 4:2:0 in
+        // u16 samples.
+        assert_eq!(report.memory_bytes, 5 * (64 * 48 + 2 * 32 * 24) * 2);
+
+        let b_frames = EncoderConfig {
+            b_frames: true,
+            gop_size: 4,
+            closed_gop: true,
+            ..config.clone()
+        };
+        let report = Encoder::preflight(64, 48, &b_frames).unwrap();
+        assert_eq!(report.latency_frames, 3);
+        assert_eq!(report.memory_bytes, 7 * (64 * 48 + 2 * 32 * 24) * 2);
+
+        assert!(matches!(
+            Encoder::preflight(0, 48, &config),
+            Err(EncoderError::InvalidDimensions { .. })
+        ));
+        let sliced = EncoderConfig {
+            frame_slices: 2,
+            ..b_frames
+        };
+        assert!(matches!(
+            Encoder::preflight(64, 48, &sliced),
+            Err(EncoderError::InvalidConfig { .. })
+        ));
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn capture_times_follow_frames_through_b_frame_reordering() {
+        let config = EncoderConfig {
+            b_frames: true,
             gop_size: 3,
-            video_signal: VideoSignal::default(),
-            content_light: None,
-            mastering_display: None,
+            closed_gop: true,
+            ..EncoderConfig::from(&EncodeConfig::default())
         };
         let mut enc = Encoder::new(64, 64, config).unwrap();
-        let frame = FramePixels::solid(64, 64, 128, 128, 128);
+        // 25 fps capture with jitter and one dropped frame.
+        let captures = [
+            1_000_000_000u64,
+            1_043_000_000,
+            1_078_000_000,
+            1_161_000_000,
+        ];
+        for (i, &ns) in captures.iter().enumerate() {
+            let options = FrameOptions {
+                capture_time_ns: Some(ns),
+                ..FrameOptions::default()
+            };
+            enc.send_frame_with_options(&panning_frame(64, 64, i as u32), options)
+                .unwrap();
+        }
+        enc.flush();
+        let mut timing = std::collections::BTreeMap::new();
+        while let Some(packet) = enc.receive_packet() {
+            timing.insert(packet.frame_number, (packet.pts, packet.capture_time_ns));
+        }
+        let expected: Vec<(u64, Option<u64>)> =
+            [0, 1, 2, 4].into_iter().zip(captures.map(Some)).collect();
+        assert_eq!(timing.into_values().collect::<Vec<_>>(), expected);
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn me_prefilter_tracks_motion_through_grain() {
+        // A faint pattern panning 3 pixels right and 1 down per frame, under
+        // fresh grain of +-20 in every frame.
+        let (width, height) = (128u32, 96u32);
+        let grainy = |frame: u32| {
+            let mut pixels = FramePixels::solid(width, height, 0, 128, 128);
+            let mut state = 0x9e37_79b9u32 ^ frame.wrapping_mul(0x85eb_ca6b);
+            for (i, s) in pixels.y.iter_mut().enumerate() {
+                let x = (i as u32 % width + frame * 3) as f64;
+                let y = (i as u32 / width + frame) as f64;
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                let grain = ((state >> 16) % 41) as f64 - 20.0;
+                let pattern = 12.0 * (x / 9.0).sin() * (y / 7.0).cos();
+                *s = (128.0 + pattern + grain).round() as u16;
+            }
+            pixels
+        };
+        let frames: Vec<FramePixels> = (0..6).map(grainy).collect();
+        let encode = |me_prefilter| {
+            let config = EncodeConfig {
+                me_prefilter,
+                motion_fields: true,
+                distortion_maps: true,
+                ..EncodeConfig::default()
+            };
+            let mut enc = Encoder::new(width, height, EncoderConfig::from(&config)).unwrap();
+            let mut inter_bytes = 0u64;
+            for frame in &frames {
+                enc.send_frame(frame).unwrap();
+            }
+            enc.flush();
+            while let Some(packet) = enc.receive_packet() {
+                if packet.frame_type != FrameType::Key {
+                    inter_bytes += packet.data.len() as u64;
+                }
+            }
+            let inter_sse: u64 = std::iter::from_fn(|| enc.receive_distortion_map())
+                .filter(|map| map.frame_number > 0)
+                .map(|map| map.sse.iter().sum::<u64>())
+                .sum();
+            // Searched vectors within a pixel of the true (24, 8) eighths.
+            let mut on_track = 0;
+            while let Some(field) = enc.receive_motion_field() {
+                let estimates = &field.estimates;
+                for by in 0..estimates.mi_rows() {
+                    for bx in 0..estimates.mi_cols() {
+                        on_track += estimates
+                            .get(bx, by)
+                            .filter(|&(x, y)| (x - 24).abs() <= 8 && (y - 8).abs() <= 8)
+                            .is_some() as usize;
+                    }
+                }
+            }
+            (on_track, inter_sse, inter_bytes)
+        };
+        let (raw_on_track, raw_sse, raw_bytes) = encode(false);
+        let (filtered_on_track, filtered_sse, filtered_bytes) = encode(true);
+        assert!(
+            filtered_on_track > raw_on_track * 2,
+            "{filtered_on_track} vs {raw_on_track}"
+        );
+        // About the same distortion and size: where the raw search loses
+        // the motion, blocks fall back to intra rather than smear grain,
+        // and CDEF smooths the grain out of both.
+        assert!(
+            filtered_sse * 100 < raw_sse * 108,
+            "{filtered_sse} vs {raw_sse}"
+        );
+        assert!(
+            filtered_bytes * 100 < raw_bytes * 101,
+            "{filtered_bytes} vs {raw_bytes}"
+        );
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn tile_threads_code_the_same_stream() {
+        let frames: Vec<FramePixels> = (0..4).map(|i| panning_frame(256, 128, i * 3)).collect();
+        let config = |threads| EncodeConfig {
+            tile_columns: 2,
+            tile_rows: 2,
+            threads,
+            b_frames: true,
+            ..EncodeConfig::default()
+        };
+        let plan = tile_plan(256, 128, &EncoderConfig::from(&config(3)));
+        assert_eq!((plan.tile_cols, plan.tile_rows, plan.threads), (2, 2, 3));
+
+        let encode = |threads| {
+            let packets = crate::encode_packets(&frames, &config(threads));
+            assert!(packets.iter().all(|p| !p.partial));
+            packets
+                .into_iter()
+                .flat_map(|p| p.data)
+                .collect::<Vec<u8>>()
+        };
+        let single = encode(1);
+        assert_eq!(encode(3), single);
+        assert_eq!(encode(4), single);
+    }
+
+    #[test]
+    fn frame_slices_emit_one_packet_per_tile_row() {
+        fn obu_types(mut data: &[u8]) -> Vec<u8> {
+            let mut types = Vec::new();
+            while let Some((&header, rest)) = data.split_first() {
+                let (mut size, mut len) = (0usize, 0);
+                while rest[len] & 0x80 != 0 {
+                    size |= ((rest[len] & 0x7f) as usize) << (7 * len);
+                    len += 1;
+                }
+                size |= (rest[len] as usize) << (7 * len);
+                types.push(header >> 3);
+                data = &rest[len + 1 + size..];
+            }
+            types
+        }
+        let mut config = EncoderConfig::from(&EncodeConfig::default());
+        config.frame_slices = 2;
+        let mut enc = Encoder::new(64, 128, config.clone()).unwrap();
+        let mut packets = Vec::new();
+        for i in 0..3u32 {
+            let options = FrameOptions {
+                user_data: Some(i as u64),
+                ..FrameOptions::default()
+            };
+            enc.send_frame_with_options(&panning_frame(64, 128, i * 2), options)
+                .unwrap();
+            while let Some(packet) = enc.receive_packet() {
+                packets.push(packet);
+            }
+        }
+        assert_eq!(packets.len(), 6);
+        for (i, pair) in packets.chunks(2).enumerate() {
+            assert!(pair[0].partial && !pair[1].partial);
+            assert!(pair.iter().all(|p| p.frame_number == i as u64));
+            assert!(pair.iter().all(|p| p.user_data == Some(i as u64)));
+            // The first slice opens the temporal unit and carries the frame
+            // header with the first tile row; the second is a lone tile group.
+            let first = obu_types(&pair[0].data);
+            assert_eq!(first[0], obu::ObuType::TemporalDelimiter as u8);
+            assert_eq!(
+                first[first.len() - 2..],
+                [
+                    obu::ObuType::FrameHeader as u8,
+                    obu::ObuType::TileGroup as u8
+                ]
+            );
+            assert_eq!(obu_types(&pair[1].data), [obu::ObuType::TileGroup as u8]);
+        }
+
+        config.b_frames = true;
+        assert!(matches!(
+            Encoder::new(64, 128, config.clone()),
+            Err(EncoderError::InvalidConfig { .. })
+        ));
+        config.b_frames = false;
+        config.intra_frame_budget = Some(4000);
+        assert!(matches!(
+            Encoder::new(64, 128, config),
+            Err(EncoderError::InvalidConfig { .. })
+        ));
+    }
+
+    #[test]
+    fn slice_hook_takes_rows_before_the_frame_is_received() {
+        let mut config = EncoderConfig::from(&EncodeConfig::default());
+        config.frame_slices = 4;
+        config.threads = 2;
+        let frames: Vec<_> = (0..3).map(|i| panning_frame(64, 256, i * 2)).collect();
+        let options = |i: usize| FrameOptions {
+            user_data: Some(i as u64),
+            ..FrameOptions::default()
+        };
+
+        let mut queued = Encoder::new(64, 256, config.clone()).unwrap();
+        let mut expected = Vec::new();
+        for (i, frame) in frames.iter().enumerate() {
+            queued.send_frame_with_options(frame, options(i)).unwrap();
+            expected.extend(std::iter::from_fn(|| queued.receive_packet()));
+        }
+
+        let hooked_rows = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut hooked = Encoder::new(64, 256, config).unwrap();
+        let rows = hooked_rows.clone();
+        hooked.set_slice_hook(move |packet| rows.lock().unwrap().push(packet));
+        let mut packets = Vec::new();
+        for (i, frame) in frames.iter().enumerate() {
+            hooked.send_frame_with_options(frame, options(i)).unwrap();
+            assert_eq!(hooked_rows.lock().unwrap().len(), 3);
+            packets.append(&mut hooked_rows.lock().unwrap());
+            let last = hooked.receive_packet().unwrap();
+            assert!(!last.partial);
+            assert!(hooked.receive_packet().is_none());
+            packets.push(last);
+        }
+
+        assert_eq!(packets.len(), expected.len());
+        for (packet, expected) in packets.iter().zip(&expected) {
+            assert_eq!(packet.data, expected.data);
+            assert_eq!(packet.frames, expected.frames);
+            assert_eq!(packet.partial, expected.partial);
+            assert_eq!(
+                (packet.pts, packet.user_data),
+                (expected.pts, expected.user_data)
+            );
+        }
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn build_info_rides_on_keyframe_temporal_units_only() {
+        let mut config = EncoderConfig::from(&EncodeConfig::default());
+        config.keyint = 2;
+        let encode = |config: &EncoderConfig| {
+            let mut enc = Encoder::new(64, 64, config.clone()).unwrap();
+            let mut packets = Vec::new();
+            for i in 0..3 {
+                enc.send_frame(&panning_frame(64, 64, i * 2)).unwrap();
+                while let Some(packet) = enc.receive_packet() {
+                    packets.push(packet.data);
+                }
+            }
+            (enc.build_info(), packets)
+        };
+        let (_, plain) = encode(&config);
+        config.build_info = true;
+        let (info, tagged) = encode(&config);
+        assert!(info.starts_with(concat!("wav1c ", env!("CARGO_PKG_VERSION"), " - ")));
+        assert!(info.contains(" keyint=2 ") && info.ends_with("size=64x64 bit_depth=8"));
+
+        let obu = obu::obu_wrap(obu::ObuType::Metadata, &metadata::encode_build_info(&info));
+        let holds = |data: &[u8]| data.windows(obu.len()).any(|w| w == obu);
+        assert_eq!(
+            tagged.iter().map(|d| holds(d)).collect::<Vec<_>>(),
+            [true, false, true]
+        );
+        // The OBU is all that changes.
+        assert_eq!(tagged[1], plain[1]);
+        assert_eq!(tagged[0].len(), plain[0].len() + obu.len());
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn recycled_packets_stop_packet_allocations() {
+        for b_frames in [false, true] {
+            let mut config = EncoderConfig::from(&EncodeConfig::default());
+            config.b_frames = b_frames;
+            config.keyint = 8;
+            let frames: Vec<FramePixels> = (0..24).map(|i| panning_frame(64, 64, i)).collect();
+            let encode = |recycle: bool| {
+                let mut enc = Encoder::new(64, 64, config.clone()).unwrap();
+                let mut data = Vec::new();
+                let mut drain = |enc: &mut Encoder| {
+                    while let Some(packet) = enc.receive_packet() {
+                        data.push(packet.data.clone());
+                        if recycle {
+                            enc.recycle_packet(packet);
+                        }
+                    }
+                };
+                for frame in &frames {
+                    enc.send_frame(frame).unwrap();
+                    drain(&mut enc);
+                }
+                enc.flush();
+                drain(&mut enc);
+                (data, enc.packet_pool_stats())
+            };
+            let (fresh, fresh_stats) = encode(false);
+            let (recycled, stats) = encode(true);
+            assert_eq!(recycled, fresh);
+            // Only the first mini-GOP starts in new buffers.
+            assert!(stats.allocated <= 8, "{stats:?}");
+            assert!(fresh_stats.allocated >= 24, "{fresh_stats:?}");
+            assert_eq!(
+                stats.allocated + stats.reused,
+                fresh_stats.allocated + fresh_stats.reused
+            );
+            assert!(stats.pooled <= crate::packet::PACKET_POOL_CAPACITY);
+        }
+    }
+
+    #[cfg(all(feature = "inter", feature = "rate-control"))]
+    #[test]
+    fn per_frame_q_idx_overrides_rate_control() {
+        let frames: Vec<FramePixels> = (0..3).map(|i| panning_frame(64, 64, i * 3)).collect();
+        let with_q = |q| FrameOptions {
+            base_q_idx: Some(q),
+            ..FrameOptions::default()
+        };
+        let mut fixed_config = rate_controlled_config();
+        fixed_config.target_bitrate = None;
+        fixed_config.base_q_idx = 60;
+        let mut fixed = Encoder::new(64, 64, fixed_config).unwrap();
+        fixed.send_frame(&frames[0]).unwrap();
+
+        let mut enc = Encoder::new(64, 64, rate_controlled_config()).unwrap();
+        enc.send_frame_with_options(&frames[0], with_q(60)).unwrap();
+        assert_eq!(
+            enc.receive_packet().unwrap().data,
+            fixed.receive_packet().unwrap().data
+        );
+        enc.send_frame(&frames[1]).unwrap();
+        let controlled = enc.receive_packet().unwrap();
+        enc.send_frame_with_options(&frames[2], with_q(250))
+            .unwrap();
+        let coarse = enc.receive_packet().unwrap();
+        assert_eq!(coarse.frame_type, FrameType::Inter);
+        assert!(coarse.data.len() < controlled.data.len());
+
+        let stats = enc.rate_control_stats().unwrap();
+        assert_eq!(stats.frames_encoded, 3);
+        assert_eq!(
+            stats.gops[0].qp_histogram[250 / crate::rc::QP_HISTOGRAM_BUCKET],
+            1
+        );
+    }
+
+    #[cfg(feature = "rate-control")]
+    #[test]
+    fn per_frame_q_idx_rejects_lossless() {
+        let frame = panning_frame(64, 64, 0);
+        let options = |q| FrameOptions {
+            base_q_idx: Some(q),
+            ..FrameOptions::default()
+        };
+        let mut lossy = Encoder::new(64, 64, rate_controlled_config()).unwrap();
+        assert!(matches!(
+            lossy.send_frame_with_options(&frame, options(0)),
+            Err(EncoderError::InvalidConfig { .. })
+        ));
+        let mut lossless = Encoder::new_lossless(64, 64, rate_controlled_config()).unwrap();
+        assert!(matches!(
+            lossless.send_frame_with_options(&frame, options(80)),
+            Err(EncoderError::InvalidConfig { .. })
+        ));
+        assert_eq!(lossy.frames_in_flight() + lossless.frames_in_flight(), 0);
+    }
+
+    #[cfg(all(feature = "inter", feature = "rate-control"))]
+    #[test]
+    fn builtin_rate_control_through_the_trait_matches_target_bitrate() {
+        let frames: Vec<FramePixels> = (0..7).map(|i| panning_frame(64, 64, i * 3)).collect();
+        let encode = |enc: &mut Encoder| -> Vec<Vec<u8>> {
+            for frame in &frames {
+                enc.send_frame(frame).unwrap();
+            }
+            enc.flush();
+            std::iter::from_fn(|| enc.receive_packet())
+                .map(|p| p.data)
+                .collect()
+        };
+        for b_frames in [false, true] {
+            let mut config = rate_controlled_config();
+            config.b_frames = b_frames;
+            config.gop_size = 3;
+            config.keyint = 5;
+            let mut builtin = Encoder::new(64, 64, config.clone()).unwrap();
+
+            let rc = RateControl::new(200_000, config.fps, 64, 64, config.keyint);
+            config.target_bitrate = None;
+            let mut plugged = Encoder::new(64, 64, config).unwrap();
+            plugged.set_rate_controller(rc).unwrap();
+            assert_eq!(encode(&mut plugged), encode(&mut builtin));
+        }
+    }
 
+    #[cfg(all(feature = "inter", feature = "rate-control"))]
+    /// Frame number, keyframe flag, q index and bytes of each coded frame.
+    type Coded = Vec<(u64, bool, u8, usize)>;
+
+    #[cfg(all(feature = "inter", feature = "rate-control"))]
+    #[derive(Default)]
+    struct Log(std::sync::Arc<std::sync::Mutex<Coded>>);
+
+    #[cfg(all(feature = "inter", feature = "rate-control"))]
+    impl RateController for Log {
+        fn on_frame_start(&mut self, frame: &RcFrame) -> u8 {
+            assert_eq!(frame.complexity.frame_number, frame.frame_number);
+            assert_eq!(frame.complexity.inter_cost.is_some(), !frame.is_keyframe);
+            if frame.is_keyframe { 40 } else { 0 }
+        }
+
+        fn on_frame_done(&mut self, frame: &RcFrame, q_idx: u8, bytes: usize) {
+            let entry = (frame.frame_number, frame.is_keyframe, q_idx, bytes);
+            self.0.lock().unwrap().push(entry);
+        }
+    }
+
+    #[cfg(all(feature = "inter", feature = "rate-control"))]
+    #[test]
+    fn rate_controller_picks_each_frame_q_idx() {
+        let log = Log::default();
+        let seen = log.0.clone();
+        let mut enc = Encoder::new(64, 64, EncoderConfig::from(&EncodeConfig::default())).unwrap();
+        enc.set_rate_controller(log).unwrap();
+        for i in 0..3 {
+            enc.send_frame(&panning_frame(64, 64, i)).unwrap();
+        }
+        let done = seen.lock().unwrap().clone();
+        // Zero is clamped: the controller cannot make a frame lossless.
+        assert_eq!(
+            done.iter()
+                .map(|&(n, key, q, _)| (n, key, q))
+                .collect::<Vec<_>>(),
+            [(0, true, 40), (1, false, 1), (2, false, 1)]
+        );
+        assert!(done.iter().all(|&(_, _, _, bytes)| bytes > 0));
+
+        let mut late = Encoder::new(64, 64, rate_controlled_config()).unwrap();
+        assert!(matches!(
+            late.set_rate_controller(Log::default()),
+            Err(EncoderError::InvalidConfig { .. })
+        ));
+        let fixed = EncoderConfig::from(&EncodeConfig::default());
+        let mut late = Encoder::new(64, 64, fixed.clone()).unwrap();
+        late.send_frame(&panning_frame(64, 64, 0)).unwrap();
+        assert!(late.set_rate_controller(Log::default()).is_err());
+        assert!(enc.reconfigure(rate_controlled_config()).is_err());
+        assert!(enc.reconfigure(fixed).is_ok());
+    }
+
+    #[cfg(feature = "rate-control")]
+    #[test]
+    fn first_keyframe_fits_its_budget_on_detailed_content() {
+        // The bits-per-pixel table alone starts these at 6x and 2.7x the
+        // keyframe budget (4 frames' worth, capped at half the buffer).
+        for (size, bitrate) in [(128, 100_000u64), (256, 1_000_000)] {
+            let config = EncoderConfig::from(&crate::EncodeConfig {
+                target_bitrate: Some(bitrate),
+                ..crate::EncodeConfig::default()
+            });
+            let mut enc = Encoder::new(size, size, config).unwrap();
+            enc.send_frame(&noisy_frame(size, size, crate::BitDepth::Eight))
+                .unwrap();
+            let bits = (enc.receive_packet().unwrap().data.len() * 8) as f64;
+            let budget = (bitrate as f64 / 25.0 * 4.0).min(bitrate as f64 * 0.5);
+            assert!(bits < budget * 1.25, "{size}px: {bits} bits for {budget}");
+        }
+    }
+
+    #[cfg(feature = "rate-control")]
+    #[test]
+    fn warm_start_seeds_rate_control_from_previous_encode() {
+        let frame = noisy_frame(64, 64, crate::BitDepth::Eight);
+        let mut first = Encoder::new(64, 64, rate_controlled_config()).unwrap();
+        for _ in 0..6 {
+            first.send_frame(&frame).unwrap();
+        }
+        first.flush();
+        let state = first.warm_start_state();
+        assert_eq!(state.frames_observed, 6);
+        let state = WarmStartState::from_bytes(&state.to_bytes()).unwrap();
+
+        let mut cold = Encoder::new(64, 64, rate_controlled_config()).unwrap();
+        let mut warm = Encoder::new(64, 64, rate_controlled_config()).unwrap();
+        warm.warm_start(&state).unwrap();
+        assert_eq!(
+            warm.rate_control_stats().unwrap().avg_qp,
+            state.avg_qp.round() as u8
+        );
+        assert_ne!(
+            warm.rate_control_stats().unwrap().avg_qp,
+            cold.rate_control_stats().unwrap().avg_qp
+        );
+
+        cold.send_frame(&frame).unwrap();
+        warm.send_frame(&frame).unwrap();
+        let cold_pkt = cold.receive_packet().unwrap();
+        let warm_pkt = warm.receive_packet().unwrap();
+        assert_eq!(warm_pkt.frame_type, FrameType::Key);
+        assert_ne!(cold_pkt.data, warm_pkt.data);
+    }
+
+    #[cfg(feature = "rate-control")]
+    #[test]
+    fn warm_start_rejects_late_or_mismatched_state() {
+        let frame = noisy_frame(64, 64, crate::BitDepth::Eight);
+        let mut enc = Encoder::new(64, 64, rate_controlled_config()).unwrap();
+        let state = enc.warm_start_state();
         enc.send_frame(&frame).unwrap();
+        assert!(matches!(
+            enc.warm_start(&state),
+            Err(EncoderError::InvalidConfig { .. })
+        ));
+
+        let mut ten_bit = WarmStartState {
+            bit_depth: crate::BitDepth::Ten,
+            ..state
+        };
+        let mut fresh = Encoder::new(64, 64, rate_controlled_config()).unwrap();
+        assert!(fresh.warm_start(&ten_bit).is_err());
+        ten_bit.bit_depth = crate::BitDepth::Eight;
+        assert!(fresh.warm_start(&ten_bit).is_ok());
+    }
+
+    #[test]
+    fn warm_start_is_a_no_op_for_fixed_quantizer() {
+        let frame = noisy_frame(64, 64, crate::BitDepth::Eight);
+        let config = || EncoderConfig::from(&crate::EncodeConfig::default());
+        let state = WarmStartState {
+            bit_depth: crate::BitDepth::Eight,
+            frames_observed: 10,
+            avg_qp: 40.0,
+            bits_per_pixel: 0.5,
+        };
+        let mut cold = Encoder::new(64, 64, config()).unwrap();
+        let mut warm = Encoder::new(64, 64, config()).unwrap();
+        warm.warm_start(&state).unwrap();
+        cold.send_frame(&frame).unwrap();
+        warm.send_frame(&frame).unwrap();
+        assert_eq!(
+            cold.receive_packet().unwrap().data,
+            warm.receive_packet().unwrap().data
+        );
+    }
+
+    #[test]
+    fn mv_search_range_must_fit_the_window_limit() {
+        for range in [0, tile::MAX_MV_SEARCH_RANGE + 1] {
+            let mut config = EncoderConfig::from(&EncodeConfig::default());
+            config.mv_search_range = range;
+            assert!(matches!(
+                Encoder::new(64, 64, config),
+                Err(EncoderError::InvalidConfig { .. })
+            ));
+        }
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn b_frame_mini_gop_tracks_reference_distance() {
+        let mut config = EncoderConfig::from(&EncodeConfig::default());
+        config.b_frames = true;
+        config.gop_size = 4;
+        config.mv_search_range = 8;
+        let mut enc = Encoder::new(64, 64, config).unwrap();
+        let frames: Vec<_> = (0..4).map(|i| panning_frame(64, 64, i * 5)).collect();
+        for frame in &frames {
+            enc.send_frame(frame).unwrap();
+        }
         enc.flush();
-        let packet = enc.receive_packet().unwrap();
-        assert_eq!(packet.frame_type, FrameType::Key);
+        // Keyframe 0, then the hidden frame 3 predicts across three frames.
+        assert_eq!(enc.reference_index, 3);
+        let mut packets = 0;
+        while enc.receive_packet().is_some() {
+            packets += 1;
+        }
+        assert_eq!(packets, 4);
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn slow_speeds_denoise_the_hidden_frame_of_each_mini_gop() {
+        let clean = panning_frame(64, 64, 0);
+        let noisy = |seed: u32| {
+            let mut frame = clean.clone();
+            let mut state = seed.wrapping_mul(0x9e37_79b9) | 1;
+            for s in frame.y.iter_mut() {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                *s = (*s as i32 + (state % 13) as i32 - 6) as u16;
+            }
+            frame
+        };
+        let hidden_frame = |filter: bool| {
+            let mut config = EncoderConfig::from(&EncodeConfig::default());
+            config.b_frames = true;
+            config.gop_size = 4;
+            config.base_q_idx = 64;
+            let mut enc = Encoder::new(64, 64, config).unwrap();
+            assert_eq!(enc.temporal_filter_frames, 3);
+            if !filter {
+                enc.temporal_filter_frames = 0;
+            }
+            for i in 0..4 {
+                enc.send_frame(&noisy(i + 1)).unwrap();
+            }
+            enc.flush();
+            assert_eq!(enc.reference_index, 3);
+            let bytes: usize = drain(&mut enc).iter().map(|p| p.data.len()).sum();
+            let recon = enc.reference.as_ref().unwrap();
+            (crate::metrics::plane_psnr(&recon.y, &clean.y, 255), bytes)
+        };
+        let (filtered_psnr, filtered_bytes) = hidden_frame(true);
+        let (psnr, bytes) = hidden_frame(false);
+        assert!(filtered_psnr > psnr, "{filtered_psnr} vs {psnr} dB");
+        assert!(filtered_bytes <= bytes, "{filtered_bytes} vs {bytes} bytes");
+        let fast = EncoderConfig {
+            speed: tile::MAX_SPEED,
+            ..EncoderConfig::from(&EncodeConfig::default())
+        };
+        assert_eq!(
+            Encoder::new(64, 64, fast).unwrap().temporal_filter_frames,
+            0
+        );
+    }
+
+    fn drain(enc: &mut Encoder) -> Vec<Packet> {
+        std::iter::from_fn(|| enc.receive_packet()).collect()
+    }
+
+    #[test]
+    fn preview_stream_mirrors_main_packets_at_quarter_size() {
+        let mut config = EncoderConfig::from(&EncodeConfig::default());
+        config.keyint = 3;
+        config.preview_q_idx = Some(200);
+        let mut enc = Encoder::new(96, 66, config).unwrap();
+        let preview = enc.preview().unwrap();
+        assert_eq!((preview.width(), preview.height()), (48, 33));
+        assert_ne!(preview.av1c(), enc.av1c());
+
+        for i in 0..5 {
+            let options = FrameOptions {
+                user_data: Some(100 + i as u64),
+                capture_time_ns: Some(1_000_000 + i as u64 * 40_000_000),
+                ..FrameOptions::default()
+            };
+            enc.send_frame_with_options(&panning_frame(96, 66, i * 2), options)
+                .unwrap();
+        }
+        enc.flush();
+        let main = drain(&mut enc);
+        assert_eq!(enc.state(), EncoderState::Draining);
+        let preview: Vec<_> = std::iter::from_fn(|| enc.receive_preview_packet()).collect();
+        assert_eq!(enc.state(), EncoderState::Finished);
+
+        assert_eq!(preview.len(), main.len());
+        for (p, m) in preview.iter().zip(&main) {
+            assert_eq!(
+                (p.frame_number, &p.frame_type, p.pts, p.user_data),
+                (m.frame_number, &m.frame_type, m.pts, m.user_data)
+            );
+            assert!(p.data.len() < m.data.len());
+        }
+        assert_eq!(main[3].frame_type, FrameType::Key);
+    }
+
+    #[test]
+    fn preview_cannot_be_toggled_or_coded_lossless() {
+        let mut config = EncoderConfig::from(&EncodeConfig::default());
+        config.preview_q_idx = Some(0);
+        assert!(matches!(
+            Encoder::new(64, 64, config.clone()),
+            Err(EncoderError::InvalidConfig { .. })
+        ));
+
+        config.preview_q_idx = None;
+        let mut enc = Encoder::new(64, 64, config.clone()).unwrap();
+        config.preview_q_idx = Some(200);
+        assert!(matches!(
+            enc.reconfigure(config),
+            Err(EncoderError::InvalidConfig { .. })
+        ));
+        assert!(enc.receive_preview_packet().is_none());
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn flush_mid_gop_outputs_every_frame_once_and_is_idempotent() {
+        let mut config = EncoderConfig::from(&EncodeConfig::default());
+        config.b_frames = true;
+        config.gop_size = 3;
+        let mut enc = Encoder::new(64, 64, config).unwrap();
+        assert_eq!(enc.state(), EncoderState::Idle);
+
+        for i in 0..5 {
+            enc.send_frame(&panning_frame(64, 64, i * 2)).unwrap();
+        }
+        assert_eq!(enc.state(), EncoderState::Encoding);
+        assert!(enc.frames_in_flight() > 0);
+
+        enc.flush();
+        assert_eq!(enc.frames_in_flight(), 0);
+        assert_eq!(enc.state(), EncoderState::Draining);
+        let mut shown: Vec<u64> = drain(&mut enc).iter().map(|p| p.frame_number).collect();
+        shown.sort_unstable();
+        shown.dedup();
+        assert_eq!(shown, (0..5).collect::<Vec<_>>());
+        assert_eq!(enc.state(), EncoderState::Finished);
+
+        enc.flush();
+        assert!(enc.receive_packet().is_none());
+        assert_eq!(enc.state(), EncoderState::Finished);
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn abort_discards_buffered_frames_and_unreceived_packets() {
+        let mut config = EncoderConfig::from(&EncodeConfig::default());
+        config.b_frames = true;
+        config.gop_size = 3;
+        let mut enc = Encoder::new(64, 64, config).unwrap();
+        for i in 0..5 {
+            enc.send_frame_with_user_data(&panning_frame(64, 64, i), i as u64)
+                .unwrap();
+        }
+        assert!(enc.frames_in_flight() > 0);
+
+        enc.abort();
+        assert_eq!(enc.frames_in_flight(), 0);
+        assert_eq!(enc.pending_packets(), 0);
+        assert_eq!(enc.state(), EncoderState::Finished);
+        assert!(enc.frame_user_data.is_empty());
+        assert!(enc.frame_q_idx.is_empty());
+        assert!(enc.frame_timing.is_empty());
+
+        enc.flush();
+        assert!(enc.receive_packet().is_none());
+    }
+
+    #[test]
+    fn send_frame_and_reconfigure_after_end_of_stream_are_rejected() {
+        let config = EncoderConfig::from(&EncodeConfig::default());
+        let frame = panning_frame(64, 64, 0);
+        for abort in [false, true] {
+            let mut enc = Encoder::new(64, 64, config.clone()).unwrap();
+            enc.send_frame(&frame).unwrap();
+            if abort {
+                enc.abort();
+            } else {
+                enc.flush();
+            }
+            let err = enc.send_frame(&frame).unwrap_err();
+            assert!(matches!(err, EncoderError::InvalidState { .. }), "{err}");
+            assert!(matches!(
+                enc.reconfigure(config.clone()),
+                Err(EncoderError::InvalidState { .. })
+            ));
+            drain(&mut enc);
+            assert_eq!(
+                enc.send_frame(&frame).unwrap_err().to_string(),
+                "cannot send a frame while the encoder is finished"
+            );
+        }
     }
 
+    #[cfg(feature = "inter")]
     #[test]
-    fn second_frame_is_inter() {
-        let config = EncoderConfig {
-            base_q_idx: 128,
-            keyint: 25,
-            target_bitrate: None,
-            fps: Fps::default(),
-            b_frames: false,
-            gop_size: 3,
-            video_signal: VideoSignal::default(),
-            content_light: None,
-            mastering_display: None,
-        };
-        let mut enc = Encoder::new(64, 64, config).unwrap();
-        let frame = FramePixels::solid(64, 64, 128, 128, 128);
+    fn reconfigure_that_changes_sequence_header_starts_signalled_keyframe() {
+        let config = EncoderConfig::from(&EncodeConfig::default());
+        let mut enc = Encoder::new(64, 64, config.clone()).unwrap();
+        let frames: Vec<_> = (0..4).map(|i| panning_frame(64, 64, i * 2)).collect();
+        enc.send_frame(&frames[0]).unwrap();
+        enc.send_frame(&frames[1]).unwrap();
+        let before = drain(&mut enc);
+        assert!(before.iter().all(|p| p.sequence_header.is_none()));
 
-        enc.send_frame(&frame).unwrap();
-        enc.send_frame(&frame).unwrap();
-        enc.flush();
+        let old_headers = enc.headers();
+        enc.reconfigure(EncoderConfig {
+            chroma_delta_q: ChromaDeltaQ {
+                u_dc: 0,
+                u_ac: 4,
+                v_dc: 0,
+                v_ac: -4,
+            },
+            ..config
+        })
+        .unwrap();
+        assert_ne!(enc.headers(), old_headers);
+        enc.send_frame(&frames[2]).unwrap();
+        enc.send_frame(&frames[3]).unwrap();
+        let after = drain(&mut enc);
 
-        // We expect Frame 0 (Key), then Frame 1 (P-frame, emitted as end of GOP=1)
-        let _key_packet = enc.receive_packet().unwrap();
-        let packet = enc.receive_packet().unwrap();
-        assert_eq!(packet.frame_type, FrameType::Inter);
-        assert_eq!(packet.frame_number, 1);
+        assert_eq!(after[0].frame_type, FrameType::Key);
+        let header = after[0].sequence_header.as_ref().unwrap();
+        assert!(enc.headers().starts_with(header));
+        // The keyframe's temporal unit itself carries the new header.
+        assert!(
+            after[0]
+                .data
+                .windows(header.len())
+                .any(|w| w == &header[..])
+        );
+        assert_eq!(after[1].frame_type, FrameType::Inter);
+        assert!(after[1].sequence_header.is_none());
     }
 
+    #[cfg(feature = "inter")]
     #[test]
-    fn keyint_triggers_new_keyframe() {
-        let config = EncoderConfig {
-            base_q_idx: 128,
-            keyint: 3,
-            target_bitrate: None,
-            fps: Fps::default(),
-            b_frames: false,
-            gop_size: 3,
-            video_signal: VideoSignal::default(),
-            content_light: None,
-            mastering_display: None,
-        };
-        let mut enc = Encoder::new(64, 64, config).unwrap();
-        let frame = FramePixels::solid(64, 64, 128, 128, 128);
-
-        let expected_types = [
-            FrameType::Key,
-            FrameType::Inter,
-            FrameType::Inter,
-            FrameType::Key,
-            FrameType::Inter,
-        ];
+    fn reconfigure_without_header_change_keeps_predicting() {
+        let config = EncoderConfig::from(&EncodeConfig::default());
+        let mut enc = Encoder::new(64, 64, config.clone()).unwrap();
+        enc.send_frame(&panning_frame(64, 64, 0)).unwrap();
+        enc.reconfigure(EncoderConfig {
+            base_q_idx: 200,
+            ..config
+        })
+        .unwrap();
+        enc.send_frame(&panning_frame(64, 64, 2)).unwrap();
+        let packets = drain(&mut enc);
+        assert_eq!(packets[1].frame_type, FrameType::Inter);
+        assert!(packets.iter().all(|p| p.sequence_header.is_none()));
+    }
 
-        for _ in 0..expected_types.len() {
-            enc.send_frame(&frame).unwrap();
+    #[cfg(feature = "inter")]
+    #[test]
+    fn reconfigure_encodes_buffered_mini_gop_first() {
+        let mut config = EncoderConfig::from(&EncodeConfig::default());
+        config.b_frames = true;
+        config.gop_size = 4;
+        let mut enc = Encoder::new(64, 64, config.clone()).unwrap();
+        for i in 0..3 {
+            enc.send_frame(&panning_frame(64, 64, i * 2)).unwrap();
         }
-        enc.flush();
+        assert_eq!(enc.frames_in_flight(), 3);
+        enc.reconfigure(EncoderConfig {
+            gop_size: 2,
+            closed_gop: true,
+            ..config
+        })
+        .unwrap();
+        assert_eq!(enc.frames_in_flight(), 0);
+        assert_eq!(enc.pending_packets(), 3);
+    }
 
-        // Drain the output block
-        let mut actual_types = Vec::new();
-        while let Some(packet) = enc.receive_packet() {
-            actual_types.push((packet.frame_number, packet.frame_type));
-        }
-        // Since GOP emits out of order (P then B), we sort by frame number to verify correctness
-        actual_types.sort_by_key(|a| a.0);
+    #[test]
+    fn reconfigure_rejects_video_signal_change() {
+        let config = EncoderConfig::from(&EncodeConfig::default());
+        let mut enc = Encoder::new(64, 64, config.clone()).unwrap();
+        let mut ten_bit = config;
+        ten_bit.video_signal.bit_depth = crate::BitDepth::Ten;
+        assert!(matches!(
+            enc.reconfigure(ten_bit),
+            Err(EncoderError::InvalidConfig { .. })
+        ));
+    }
 
-        for (i, expected) in expected_types.iter().enumerate() {
-            assert_eq!(&actual_types[i].1, expected);
+    #[cfg(all(feature = "inter", feature = "rate-control"))]
+    #[test]
+    fn try_clone_continues_identically_until_settings_diverge() {
+        let mut config = EncoderConfig::from(&EncodeConfig::default());
+        config.b_frames = true;
+        config.gop_size = 8;
+        config.target_bitrate = Some(200_000);
+        let mut enc = Encoder::new(64, 64, config.clone()).unwrap();
+        for i in 0..3 {
+            enc.send_frame(&panning_frame(64, 64, i * 2)).unwrap();
         }
+        // Mid mini-GOP, with frames queued and rate control under way.
+        assert!(enc.frames_in_flight() > 0);
+        let mut same = enc.try_clone().unwrap();
+        let mut coarse = enc.try_clone().unwrap();
+        coarse
+            .reconfigure(EncoderConfig {
+                target_bitrate: Some(20_000),
+                ..config
+            })
+            .unwrap();
+
+        let finish = |enc: &mut Encoder| {
+            for i in 3..8 {
+                enc.send_frame(&panning_frame(64, 64, i * 2)).unwrap();
+            }
+            enc.flush();
+            drain(enc).into_iter().map(|p| p.data).collect::<Vec<_>>()
+        };
+        let original = finish(&mut enc);
+        assert_eq!(finish(&mut same), original);
+        let coarse = finish(&mut coarse);
+        assert_eq!(coarse.len(), original.len());
+        assert!(coarse.concat().len() < original.concat().len());
     }
 
     #[test]
-    fn dimension_mismatch_error() {
-        let config = EncoderConfig {
-            base_q_idx: 128,
-            keyint: 25,
-            target_bitrate: None,
-            fps: Fps::default(),
-            b_frames: false,
-            gop_size: 3,
-            video_signal: VideoSignal::default(),
-            content_light: None,
-            mastering_display: None,
-        };
+    fn try_clone_rejects_host_callbacks() {
+        let config = EncoderConfig::from(&EncodeConfig::default());
         let mut enc = Encoder::new(64, 64, config).unwrap();
-        let wrong_frame = FramePixels::solid(128, 128, 128, 128, 128);
+        enc.set_pre_encode_hook(|_| {});
+        assert!(matches!(
+            enc.try_clone(),
+            Err(EncoderError::InvalidConfig { .. })
+        ));
+        enc.clear_pre_encode_hook();
+        enc.set_slice_hook(|_| {});
+        assert!(matches!(
+            enc.try_clone(),
+            Err(EncoderError::InvalidConfig { .. })
+        ));
+        enc.clear_slice_hook();
+        assert!(enc.try_clone().is_ok());
+    }
 
-        let result = enc.send_frame(&wrong_frame);
-        assert!(result.is_err());
-        match result.unwrap_err() {
-            EncoderError::DimensionMismatch {
-                expected_w,
-                expected_h,
-                got_w,
-                got_h,
-            } => {
-                assert_eq!(expected_w, 64);
-                assert_eq!(expected_h, 64);
-                assert_eq!(got_w, 128);
-                assert_eq!(got_h, 128);
+    #[cfg(feature = "inter")]
+    #[test]
+    fn cyclic_refresh_spends_more_on_inter_frames_only() {
+        let encode = |aq_mode| {
+            let config = EncoderConfig {
+                aq_mode,
+                ..EncoderConfig::from(&EncodeConfig::default())
+            };
+            let mut enc = Encoder::new(320, 128, config).unwrap();
+            for i in 0..4 {
+                enc.send_frame(&panning_frame(320, 128, i * 2)).unwrap();
             }
-            _ => panic!("expected DimensionMismatch"),
+            drain(&mut enc)
+        };
+        let off = encode(AqMode::Off);
+        let cyclic = encode(AqMode::CyclicRefresh);
+        assert_eq!(off[0].data, cyclic[0].data, "keyframes use one q index");
+        for (plain, refreshed) in off[1..].iter().zip(&cyclic[1..]) {
+            assert!(refreshed.data.len() > plain.data.len());
         }
     }
 
     #[test]
-    fn flush_is_callable() {
-        let config = EncoderConfig {
-            base_q_idx: 128,
-            keyint: 25,
-            target_bitrate: None,
-            fps: Fps::default(),
-            b_frames: false,
-            gop_size: 3,
-            video_signal: VideoSignal::default(),
-            content_light: None,
-            mastering_display: None,
+    fn equirect_projection_codes_polar_rows_coarser() {
+        let encode = |projection, height| {
+            let config = EncoderConfig {
+                projection,
+                ..EncoderConfig::from(&EncodeConfig::default())
+            };
+            let mut enc = Encoder::new(128, height, config).unwrap();
+            for i in 0..3 {
+                enc.send_frame(&panning_frame(128, height, i * 2)).unwrap();
+            }
+            drain(&mut enc)
         };
-        let mut enc = Encoder::new(64, 64, config).unwrap();
-        enc.flush();
-        assert!(enc.receive_packet().is_none());
+        let flat = encode(Projection::Flat, 320);
+        let equirect = encode(Projection::Equirect, 320);
+        for (plain, weighted) in flat.iter().zip(&equirect) {
+            assert!(weighted.data.len() < plain.data.len());
+        }
+        // A single superblock row sits on the equator.
+        let flat = encode(Projection::Flat, 64);
+        let equirect = encode(Projection::Equirect, 64);
+        assert!(flat.iter().zip(&equirect).all(|(a, b)| a.data == b.data));
     }
 
+    #[cfg(feature = "inter")]
     #[test]
-    fn headers_returns_sequence_header_obu() {
-        let config = EncoderConfig {
-            base_q_idx: 128,
-            keyint: 25,
-            target_bitrate: None,
-            fps: Fps::default(),
-            b_frames: false,
-            gop_size: 3,
-            video_signal: VideoSignal::default(),
-            content_light: None,
-            mastering_display: None,
-        };
-        let enc = Encoder::new(64, 64, config).unwrap();
-        let headers = enc.headers();
-        assert_eq!(headers[0], 0x0A);
-        assert!(!headers.is_empty());
+    fn intra_refresh_codes_one_intra_only_frame_and_keeps_predicting() {
+        let config = EncoderConfig::from(&EncodeConfig::default());
+        let mut enc = Encoder::new(64, 64, config).unwrap();
+        for i in 0..5 {
+            if i == 0 || i == 2 {
+                enc.request_intra_refresh().unwrap();
+            }
+            enc.send_frame(&panning_frame(64, 64, i * 2)).unwrap();
+            if i == 2 {
+                assert!(enc.previous_mvs.is_none());
+            }
+        }
+        let types: Vec<_> = drain(&mut enc).into_iter().map(|p| p.frame_type).collect();
+        // A refresh requested for a keyframe leaves it a keyframe.
+        assert_eq!(
+            types,
+            [
+                FrameType::Key,
+                FrameType::Inter,
+                FrameType::IntraOnly,
+                FrameType::Inter,
+                FrameType::Inter,
+            ]
+        );
+        assert!(!FrameType::IntraOnly.is_sync());
     }
 
+    #[cfg(feature = "inter")]
     #[test]
-    fn headers_still_picture_sets_still_flag_without_reduced_header() {
-        let config = EncoderConfig {
-            base_q_idx: 128,
-            keyint: 25,
-            target_bitrate: None,
-            fps: Fps::default(),
-            b_frames: false,
-            gop_size: 3,
-            video_signal: VideoSignal::default(),
-            content_light: None,
-            mastering_display: None,
-        };
-        let enc = Encoder::new(64, 64, config).unwrap();
-        let headers = enc.headers_still_picture();
-
-        assert_eq!(headers[0], 0x0A);
-        assert_eq!(headers[2] & 0b0001_0000, 0b0001_0000);
-        assert_eq!(headers[2] & 0b0000_1000, 0);
+    fn p_frames_predict_from_last2_and_golden() {
+        let config = EncoderConfig::from(&EncodeConfig::default());
+        let mut enc = Encoder::new(64, 64, config).unwrap();
+        let scene = panning_frame(64, 64, 0);
+        let cut = noisy_frame(64, 64, crate::BitDepth::Eight);
+        for frame in [&scene, &cut, &scene, &cut] {
+            enc.send_frame(frame).unwrap();
+        }
+        let sizes: Vec<_> = drain(&mut enc).iter().map(|p| p.data.len()).collect();
+        assert!(sizes[2] * 4 < sizes[1], "{sizes:?}");
+        assert!(sizes[3] * 4 < sizes[1], "{sizes:?}");
     }
 
+    #[cfg(feature = "inter")]
     #[test]
-    fn packet_data_starts_with_temporal_delimiter() {
-        let config = EncoderConfig {
-            base_q_idx: 128,
-            keyint: 25,
-            target_bitrate: None,
-            fps: Fps::default(),
-            b_frames: false,
-            gop_size: 3,
-            video_signal: VideoSignal::default(),
-            content_light: None,
-            mastering_display: None,
-        };
+    fn reference_pool_refreshes_golden_periodically_and_on_intra_refresh() {
+        let config = EncoderConfig::from(&EncodeConfig::default());
         let mut enc = Encoder::new(64, 64, config).unwrap();
-        let frame = FramePixels::solid(64, 64, 128, 128, 128);
+        for i in 0..GOLDEN_FRAME_INTERVAL as u32 + 2 {
+            enc.send_frame(&panning_frame(64, 64, i)).unwrap();
+        }
+        let golden = enc.ref_pool.golden.as_ref().unwrap();
+        assert_eq!(
+            (golden.index, golden.slot),
+            (GOLDEN_FRAME_INTERVAL, GOLDEN_SLOT)
+        );
+        let last2 = enc.ref_pool.last2.as_ref().unwrap();
+        assert_eq!(last2.index, GOLDEN_FRAME_INTERVAL);
+        assert_eq!(last2.slot, 1 - enc.base_slot);
 
-        enc.send_frame(&frame).unwrap();
+        enc.request_intra_refresh().unwrap();
+        enc.send_frame(&panning_frame(64, 64, 0)).unwrap();
+        assert!(enc.ref_pool.last2.is_none());
+        assert_eq!(
+            enc.ref_pool.golden.as_ref().unwrap().index,
+            GOLDEN_FRAME_INTERVAL + 2
+        );
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn intra_refresh_rejects_b_frames_and_finished_encoders() {
+        let mut config = EncoderConfig::from(&EncodeConfig::default());
+        let mut enc = Encoder::new(64, 64, config.clone()).unwrap();
         enc.flush();
-        let packet = enc.receive_packet().unwrap();
+        assert!(matches!(
+            enc.request_intra_refresh(),
+            Err(EncoderError::InvalidState { .. })
+        ));
 
-        assert_eq!(packet.data[0], 0x12);
-        assert_eq!(packet.data[1], 0x00);
+        config.b_frames = true;
+        let mut enc = Encoder::new(64, 64, config).unwrap();
+        assert!(matches!(
+            enc.request_intra_refresh(),
+            Err(EncoderError::InvalidConfig { .. })
+        ));
     }
 
     #[test]
-    fn encoder_with_rate_control() {
-        let config = EncoderConfig {
-            base_q_idx: 128,
-            keyint: 25,
-            target_bitrate: Some(500_000),
-            fps: Fps::default(),
-            b_frames: false,
-            gop_size: 3,
-            video_signal: VideoSignal::default(),
-            content_light: None,
-            mastering_display: None,
+    fn cdef_search_never_increases_reconstruction_error() {
+        let mut base = crate::EncodeConfig {
+            base_q_idx: 160,
+            ..crate::EncodeConfig::default()
         };
-        let mut enc = Encoder::new(64, 64, config).unwrap();
-        let frame = FramePixels::solid(64, 64, 128, 128, 128);
+        let frames = [
+            noisy_frame(128, 128, crate::BitDepth::Eight),
+            noisy_frame(128, 128, crate::BitDepth::Eight),
+        ];
+        let encode = |config: &crate::EncodeConfig| {
+            let mut enc = Encoder::new(128, 128, EncoderConfig::from(config)).unwrap();
+            let mut out = Vec::new();
+            for frame in &frames {
+                enc.send_frame(frame).unwrap();
+                let pkt = enc.receive_packet().unwrap();
+                let recon = enc.last_reconstruction().unwrap();
+                let sse: u64 = [
+                    (&recon.y, &frame.y),
+                    (&recon.u, &frame.u),
+                    (&recon.v, &frame.v),
+                ]
+                .into_iter()
+                .flat_map(|(a, b)| a.iter().zip(b.iter()))
+                .map(|(&a, &b)| (a as i64 - b as i64).pow(2) as u64)
+                .sum();
+                out.push((pkt.frame_type, sse));
+            }
+            out
+        };
+        let global = encode(&base);
+        base.cdef_search = true;
+        let searched = encode(&base);
+        for ((global_type, global_sse), (searched_type, searched_sse)) in
+            global.iter().zip(&searched)
+        {
+            assert_eq!(global_type, searched_type);
+            assert!(searched_sse <= global_sse, "{searched_sse} > {global_sse}");
+        }
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn loop_restoration_filters_inter_frames_closer_to_the_source() {
+        let rippled = |phase: f64| {
+            let mut frame = FramePixels::solid(192, 128, 0, 128, 128);
+            let mut state = 0x1234_5678u32;
+            for (i, s) in frame.y.iter_mut().enumerate() {
+                let (x, y) = ((i % 192) as f64 + phase, (i / 192) as f64);
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                let grain = ((state >> 16) % 9) as f64 - 4.0;
+                let ripple = 60.0 * (x / 5.0).sin() * (y / 7.0).cos();
+                *s = (128.0 + ripple + 30.0 * ((x + y) / 23.0).sin() + grain) as u16;
+            }
+            frame
+        };
+        let frames = [rippled(0.0), rippled(1.0)];
+        let encode = |loop_restoration: bool| {
+            let config = crate::EncodeConfig {
+                base_q_idx: 160,
+                loop_restoration,
+                ..crate::EncodeConfig::default()
+            };
+            let mut enc = Encoder::new(192, 128, EncoderConfig::from(&config)).unwrap();
+            let mut sse = Vec::new();
+            for frame in &frames {
+                enc.send_frame(frame).unwrap();
+                enc.receive_packet().unwrap();
+                let recon = enc.last_reconstruction().unwrap();
+                sse.push(
+                    recon
+                        .y
+                        .iter()
+                        .zip(&frame.y)
+                        .map(|(&a, &b)| (a as i64 - b as i64).pow(2) as u64)
+                        .sum::<u64>(),
+                );
+            }
+            (enc.headers(), sse)
+        };
+        let (plain_headers, plain_sse) = encode(false);
+        let (headers, sse) = encode(true);
+        assert_ne!(headers, plain_headers, "enable_restoration is signalled");
+        assert_eq!(sse[0], plain_sse[0]);
+        assert!(sse[1] < plain_sse[1], "{} >= {}", sse[1], plain_sse[1]);
+    }
 
+    #[cfg(feature = "inter")]
+    #[test]
+    fn cdef_leaves_skipped_blocks_of_a_static_scene_alone() {
+        // Every block of the repeats skips, so a decoder filters nothing and
+        // shows the keyframe again; filtering them anyway would blur the
+        // reference a little more each frame.
+        let frame = panning_frame(96, 64, 0);
+        let mut enc = Encoder::new(96, 64, EncoderConfig::from(&EncodeConfig::default())).unwrap();
         enc.send_frame(&frame).unwrap();
+        enc.receive_packet().unwrap();
+        let keyframe = enc.last_reconstruction().unwrap().clone();
+        assert_ne!(keyframe.y, frame.y);
+        for _ in 0..3 {
+            enc.send_frame(&frame).unwrap();
+            assert_eq!(enc.receive_packet().unwrap().frame_type, FrameType::Inter);
+            let recon = enc.last_reconstruction().unwrap();
+            assert_eq!(
+                (&recon.y, &recon.u, &recon.v),
+                (&keyframe.y, &keyframe.u, &keyframe.v)
+            );
+        }
+    }
+
+    #[cfg(feature = "hdr")]
+    /// The first packet of a one-frame [`Encoder`] stream rewritten to
+    /// `encode_still`'s layout: still-picture headers, no temporal delimiter.
+    fn encoder_still(frame: &FramePixels, config: &EncoderConfig) -> Vec<u8> {
+        let mut enc = Encoder::new(frame.width, frame.height, config.clone()).unwrap();
+        enc.send_frame(frame).unwrap();
         enc.flush();
         let packet = enc.receive_packet().unwrap();
-        assert!(!packet.data.is_empty());
+        let td_len = obu::obu_wrap(obu::ObuType::TemporalDelimiter, &[]).len();
+        let mut out = enc.headers_still_picture();
+        out.extend_from_slice(&packet.data[td_len + enc.headers().len()..]);
+        out
+    }
 
-        let stats = enc.rate_control_stats();
-        assert!(stats.is_some());
+    #[cfg(feature = "hdr")]
+    #[test]
+    fn encode_still_matches_encoder_keyframe() {
+        let frame = panning_frame(96, 64, 3);
+        let mut config = EncoderConfig::from(&EncodeConfig::default());
+        assert_eq!(
+            encode_still(&frame, &config).unwrap(),
+            encoder_still(&frame, &config)
+        );
+
+        config.base_q_idx = 20;
+        config.speed = 2;
+        config.cdef_search = true;
+        config.intra_frame_budget = Some(300);
+        let capped = encode_still(&frame, &config).unwrap();
+        assert_eq!(capped, encoder_still(&frame, &config));
+
+        let hdr = noisy_frame(64, 64, crate::BitDepth::Ten);
+        let mut config = EncoderConfig::from(&EncodeConfig::default());
+        config.video_signal = VideoSignal::hdr10(crate::ColorRange::Limited);
+        config.content_light = Some(ContentLightLevel {
+            max_content_light_level: 1000,
+            max_frame_average_light_level: 400,
+        });
+        let still = encode_still(&hdr, &config).unwrap();
+        assert_eq!(still, encoder_still(&hdr, &config));
+        let enc = Encoder::new(64, 64, config).unwrap();
+        assert!(still.starts_with(&enc.headers_still_picture()));
     }
 
     #[test]
-    fn frame_numbers_increment() {
-        let config = EncoderConfig {
-            base_q_idx: 128,
-            keyint: 25,
-            target_bitrate: None,
-            fps: Fps::default(),
-            b_frames: false,
-            gop_size: 3,
-            video_signal: VideoSignal::default(),
-            content_light: None,
-            mastering_display: None,
-        };
-        let mut enc = Encoder::new(64, 64, config).unwrap();
+    fn encode_still_is_reentrant() {
+        let frames: Vec<FramePixels> = (0..8).map(|i| panning_frame(64, 64, i * 7)).collect();
+        let config = EncoderConfig::from(&EncodeConfig::default());
+        let sequential: Vec<Vec<u8>> = frames
+            .iter()
+            .map(|f| encode_still(f, &config).unwrap())
+            .collect();
+        let parallel: Vec<Vec<u8>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = frames
+                .iter()
+                .map(|f| scope.spawn(|| encode_still(f, &config).unwrap()))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        assert_eq!(parallel, sequential);
+    }
+
+    #[test]
+    fn encode_still_rejects_bitrate_and_bad_frames() {
         let frame = FramePixels::solid(64, 64, 128, 128, 128);
+        let mut config = EncoderConfig::from(&EncodeConfig::default());
+        config.target_bitrate = Some(500_000);
+        assert!(matches!(
+            encode_still(&frame, &config),
+            Err(EncoderError::InvalidConfig { .. })
+        ));
 
-        for _ in 0..5 {
-            enc.send_frame(&frame).unwrap();
+        let config = EncoderConfig::from(&EncodeConfig::default());
+        let mut short = frame.clone();
+        short.u.pop();
+        assert!(matches!(
+            encode_still(&short, &config),
+            Err(EncoderError::PlaneSizeMismatch { plane: "u", .. })
+        ));
+        let mut hot = frame.clone();
+        hot.y[5] = 300;
+        assert!(matches!(
+            encode_still(&hot, &config),
+            Err(EncoderError::SampleOutOfRange { sample: 300, .. })
+        ));
+        let empty = FramePixels::solid(0, 0, 0, 0, 0);
+        assert!(matches!(
+            encode_still(&empty, &config),
+            Err(EncoderError::InvalidDimensions { .. })
+        ));
+    }
+
+    /// Still-batch throughput of `encode_still` against one [`Encoder`] per
+    /// image, sequential and across all cores. Run with
+    /// `cargo test --release -p wav1c bench_encode_still -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_encode_still() {
+        use std::time::Instant;
+
+        let frames: Vec<FramePixels> = (0..32).map(|i| panning_frame(256, 256, i * 5)).collect();
+        let config = EncoderConfig::from(&EncodeConfig::default());
+        let threads = std::thread::available_parallelism().map_or(4, |n| n.get());
+
+        fn report(name: &str, images: usize, run: impl Fn()) {
+            run();
+            let start = Instant::now();
+            run();
+            let ms = start.elapsed().as_secs_f64() * 1000.0;
+            println!(
+                "{name:<28} {ms:>9.1} ms ({:.2} ms/image)",
+                ms / images as f64
+            );
         }
-        enc.flush();
 
-        let mut actual_nums = Vec::new();
-        while let Some(packet) = enc.receive_packet() {
-            actual_nums.push(packet.frame_number);
+        fn encoder_path(frame: &FramePixels, config: &EncoderConfig) -> usize {
+            let mut enc = Encoder::new(frame.width, frame.height, config.clone()).unwrap();
+            enc.send_frame(frame).unwrap();
+            enc.flush();
+            std::iter::from_fn(|| enc.receive_packet())
+                .map(|p| p.data.len())
+                .sum()
         }
-        actual_nums.sort_unstable();
 
-        for expected_num in 0..5u64 {
-            assert_eq!(actual_nums[expected_num as usize], expected_num);
+        fn parallel(frames: &[FramePixels], threads: usize, encode: impl Fn(&FramePixels) + Sync) {
+            let next = std::sync::atomic::AtomicUsize::new(0);
+            std::thread::scope(|scope| {
+                for _ in 0..threads {
+                    scope.spawn(|| {
+                        while let Some(frame) =
+                            frames.get(next.fetch_add(1, std::sync::atomic::Ordering::Relaxed))
+                        {
+                            encode(frame);
+                        }
+                    });
+                }
+            });
         }
-    }
 
-    #[test]
-    fn encoder_config_from_encode_config() {
-        let ec = EncodeConfig {
-            base_q_idx: 100,
-            keyint: 10,
-            target_bitrate: Some(1_000_000),
-            fps: Fps::from_int(30).unwrap(),
-            b_frames: false,
-            gop_size: 3,
-            video_signal: VideoSignal::default(),
-            content_light: None,
-            mastering_display: None,
-        };
-        let config: EncoderConfig = (&ec).into();
-        assert_eq!(config.base_q_idx, 100);
-        assert_eq!(config.keyint, 10);
-        assert_eq!(config.target_bitrate, Some(1_000_000));
-        assert_eq!(config.fps, Fps::from_int(30).unwrap());
+        let n = frames.len();
+        report("Encoder, sequential", n, || {
+            for f in &frames {
+                std::hint::black_box(encoder_path(f, &config));
+            }
+        });
+        report("encode_still, sequential", n, || {
+            for f in &frames {
+                std::hint::black_box(encode_still(f, &config).unwrap());
+            }
+        });
+        report(&format!("Encoder, {threads} threads"), n, || {
+            parallel(&frames, threads, |f| {
+                std::hint::black_box(encoder_path(f, &config));
+            })
+        });
+        report(&format!("encode_still, {threads} threads"), n, || {
+            parallel(&frames, threads, |f| {
+                std::hint::black_box(encode_still(f, &config).unwrap());
+            })
+        });
     }
 
+    #[cfg(feature = "rate-control")]
     #[test]
-    fn hdr_metadata_requires_10bit_signal() {
-        let config = EncoderConfig {
-            base_q_idx: 128,
-            keyint: 25,
-            target_bitrate: None,
-            fps: Fps::default(),
-            b_frames: false,
-            gop_size: 3,
-            video_signal: VideoSignal::default(),
-            content_light: Some(ContentLightLevel {
-                max_content_light_level: 1000,
-                max_frame_average_light_level: 400,
-            }),
-            mastering_display: None,
+    fn second_pass_lands_closer_to_the_target_than_a_single_pass() {
+        let frames: Vec<FramePixels> = (0..8u32)
+            .map(|i| panning_frame(64, 64, i.saturating_sub(3) * 9))
+            .collect();
+        let mut config = rate_controlled_config();
+        config.keyint = 8;
+        config.two_pass = TwoPass::FirstPass;
+        let mut first = Encoder::new(64, 64, config.clone()).unwrap();
+        for frame in &frames {
+            first.send_frame(frame).unwrap();
+        }
+        first.flush();
+        assert!(drain(&mut first).is_empty());
+        assert_eq!(first.state(), EncoderState::Finished);
+        let blob = first.first_pass_stats().unwrap().to_bytes();
+        let stats = FirstPassStats::from_bytes(&blob).unwrap();
+        assert_eq!(stats.frames.len(), 8);
+        assert_eq!(stats.frames[2].inter_cost, Some(0));
+        assert!(stats.frames[5].inter_cost.unwrap() > 0);
+
+        let encode = |config: EncoderConfig| {
+            let mut enc = Encoder::new(64, 64, config).unwrap();
+            for frame in &frames {
+                enc.send_frame(frame).unwrap();
+            }
+            enc.flush();
+            let sizes: Vec<usize> = drain(&mut enc).iter().map(|p| p.data.len()).collect();
+            sizes
         };
-        let err = Encoder::new(64, 64, config).unwrap_err();
-        assert!(matches!(err, EncoderError::InvalidHdrMetadata { .. }));
+        let single = encode(EncoderConfig {
+            keyint: 8,
+            ..rate_controlled_config()
+        });
+        config.two_pass = TwoPass::SecondPass(std::sync::Arc::new(stats));
+        let two_pass = encode(config);
+        assert_eq!(two_pass.len(), 8);
+        // 200 kbit/s at 30000/1001 fps is 834 bytes a frame.
+        let budget = 8 * 834;
+        let miss = |sizes: &[usize]| sizes.iter().sum::<usize>().abs_diff(budget);
+        assert!(
+            miss(&two_pass) < miss(&single),
+            "{two_pass:?} vs {single:?}"
+        );
+        assert!(two_pass[1..4].iter().all(|&size| size < two_pass[4] / 10));
     }
 
+    #[cfg(feature = "rate-control")]
     #[test]
-    fn hdr_metadata_requires_color_description() {
-        let config = EncoderConfig {
-            base_q_idx: 128,
-            keyint: 25,
-            target_bitrate: None,
-            fps: Fps::default(),
-            b_frames: false,
-            gop_size: 3,
-            video_signal: VideoSignal {
-                bit_depth: crate::BitDepth::Ten,
-                color_range: crate::ColorRange::Limited,
-                color_description: None,
-            },
-            content_light: Some(ContentLightLevel {
-                max_content_light_level: 1000,
-                max_frame_average_light_level: 400,
-            }),
-            mastering_display: None,
-        };
-        let err = Encoder::new(64, 64, config).unwrap_err();
-        assert!(matches!(err, EncoderError::InvalidHdrMetadata { .. }));
+    fn two_pass_rejects_mismatched_settings() {
+        let mut config = EncoderConfig::from(&EncodeConfig::default());
+        config.two_pass = TwoPass::SecondPass(std::sync::Arc::new(FirstPassStats::new(64, 64)));
+        assert!(matches!(
+            Encoder::new(64, 64, config.clone()),
+            Err(EncoderError::InvalidConfig { .. })
+        ));
+        config.target_bitrate = Some(200_000);
+        assert!(matches!(
+            Encoder::new(64, 48, config.clone()),
+            Err(EncoderError::InvalidConfig { .. })
+        ));
+        let mut enc = Encoder::new(64, 64, config.clone()).unwrap();
+        assert!(enc.first_pass_stats().is_none());
+        config.two_pass = TwoPass::Off;
+        assert!(matches!(
+            enc.reconfigure(config.clone()),
+            Err(EncoderError::InvalidConfig { .. })
+        ));
+
+        config.two_pass = TwoPass::FirstPass;
+        config.preview_q_idx = Some(200);
+        assert!(matches!(
+            Encoder::new(64, 64, config),
+            Err(EncoderError::InvalidConfig { .. })
+        ));
     }
 }
@@ -1,5 +1,7 @@
 use std::fmt;
 
+use crate::encoder::EncoderState;
+
 #[derive(Debug)]
 pub enum EncoderError {
     InvalidDimensions {
@@ -17,9 +19,26 @@ pub enum EncoderError {
         got_w: u32,
         got_h: u32,
     },
+    /// A plane of the frame holds a different number of samples than its
+    /// width and height require.
+    PlaneSizeMismatch {
+        plane: &'static str,
+        expected: usize,
+        got: usize,
+    },
+    /// A plane's row pitch is shorter than one row of samples.
+    InvalidStride {
+        plane: &'static str,
+        stride: usize,
+        min: usize,
+    },
     UnsupportedBitDepth {
         bit_depth: u8,
     },
+    /// RGB input cannot be converted to 4:2:0 with this matrix.
+    UnsupportedMatrix {
+        matrix_coefficients: u8,
+    },
     FrameBitDepthMismatch {
         expected: u8,
         got: u8,
@@ -31,6 +50,21 @@ pub enum EncoderError {
     InvalidHdrMetadata {
         reason: &'static str,
     },
+    InvalidConfig {
+        reason: &'static str,
+    },
+    /// `max_frame_bytes` cannot hold the smallest keyframe packet the
+    /// stream's headers and frame size allow.
+    FrameSizeCapTooSmall {
+        max_frame_bytes: u32,
+        min_bytes: usize,
+    },
+    /// The call is not allowed in the encoder's current lifecycle state,
+    /// e.g. `send_frame` after `flush`.
+    InvalidState {
+        operation: &'static str,
+        state: EncoderState,
+    },
 }
 
 impl fmt::Display for EncoderError {
@@ -66,6 +100,33 @@ impl fmt::Display for EncoderError {
                     expected_w, expected_h, got_w, got_h
                 )
             }
+            EncoderError::PlaneSizeMismatch {
+                plane,
+                expected,
+                got,
+            } => {
+                write!(
+                    f,
+                    "frame {} plane size mismatch: expected {} samples, got {}",
+                    plane, expected, got
+                )
+            }
+            EncoderError::InvalidStride { plane, stride, min } => {
+                write!(
+                    f,
+                    "frame {} plane stride {} is shorter than its {} sample row",
+                    plane, stride, min
+                )
+            }
+            EncoderError::UnsupportedMatrix {
+                matrix_coefficients,
+            } => {
+                write!(
+                    f,
+                    "RGB input cannot be converted with matrix coefficients {}",
+                    matrix_coefficients
+                )
+            }
             EncoderError::UnsupportedBitDepth { bit_depth } => {
                 write!(f, "unsupported bit depth: {}", bit_depth)
             }
@@ -86,6 +147,22 @@ impl fmt::Display for EncoderError {
             EncoderError::InvalidHdrMetadata { reason } => {
                 write!(f, "invalid HDR metadata: {}", reason)
             }
+            EncoderError::InvalidConfig { reason } => {
+                write!(f, "invalid encoder config: {}", reason)
+            }
+            EncoderError::FrameSizeCapTooSmall {
+                max_frame_bytes,
+                min_bytes,
+            } => {
+                write!(
+                    f,
+                    "max frame bytes {} is below the {} bytes of the smallest keyframe packet",
+                    max_frame_bytes, min_bytes
+                )
+            }
+            EncoderError::InvalidState { operation, state } => {
+                write!(f, "cannot {} while the encoder is {}", operation, state)
+            }
         }
     }
 }
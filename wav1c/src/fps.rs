@@ -43,6 +43,14 @@ impl Fps {
     pub fn as_f64(self) -> f64 {
         self.num as f64 / self.den as f64
     }
+
+    /// Frames shown during `duration`, rounded to the nearest frame and at
+    /// least one; e.g. a keyframe interval given in seconds.
+    pub fn frames_in(self, duration: std::time::Duration) -> usize {
+        let den_ns = self.den as u128 * 1_000_000_000;
+        let frames = (duration.as_nanos() * self.num as u128 + den_ns / 2) / den_ns;
+        usize::try_from(frames).unwrap_or(usize::MAX).max(1)
+    }
 }
 
 impl Default for Fps {
@@ -83,6 +91,16 @@ mod tests {
         assert_eq!(err, FpsError::ZeroDen);
     }
 
+    #[test]
+    fn frames_in_rounds_to_nearest_frame() {
+        use std::time::Duration;
+
+        let ntsc = Fps::new(30000, 1001).unwrap();
+        assert_eq!(ntsc.frames_in(Duration::from_secs(2)), 60);
+        assert_eq!(Fps::default().frames_in(Duration::from_millis(500)), 13);
+        assert_eq!(Fps::from_int(24).unwrap().frames_in(Duration::ZERO), 1);
+    }
+
     #[test]
     fn new_normalizes_ratio() {
         let fps = Fps::new(60, 2).unwrap();
@@ -1,9 +1,13 @@
 use crate::bitwriter::BitWriter;
-use crate::dequant::DequantValues;
+use crate::dequant::{ChromaDeltaQ, DequantValues};
+use crate::restoration::RestorationPlan;
+use crate::tile::{CdefSignal, EarlyExit, SbDeltaQ, SkipMap, TileSink};
+#[cfg(feature = "inter")]
+use crate::tile::{FrameMotion, MotionParams, MvField, RefFrame};
 use crate::y4m::FramePixels;
 
-const MAX_TILE_COLS: u32 = 64;
-const MAX_TILE_ROWS: u32 = 64;
+const MAX_TILE_COLS: u32 = crate::limits::MAX_TILE_COLUMNS;
+const MAX_TILE_ROWS: u32 = crate::limits::MAX_TILE_ROWS;
 const MAX_TILE_WIDTH_SB: u32 = 4096 / 64;
 const MAX_TILE_AREA_SB: u32 = 4096 * 2304 / (64 * 64);
 
@@ -24,6 +28,17 @@ pub struct TilePlan {
     pub tile_cols: u32,
     pub tile_rows: u32,
     pub tiles: Vec<TileRect>,
+    /// Threads coding the tiles of a frame at once; 1 codes them in turn.
+    /// Tiles are independent, so the output does not depend on it.
+    pub threads: usize,
+}
+
+impl TilePlan {
+    /// This layout coded on up to `threads` threads (0 counts as 1).
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
 }
 
 fn tile_log2(blk_size: u32, target: u32) -> u32 {
@@ -47,15 +62,42 @@ fn uniform_tile_starts(sb_extent: u32, log2_tiles: u32) -> Vec<u32> {
 }
 
 pub fn build_tile_plan(width: u32, height: u32) -> TilePlan {
+    build_tile_plan_with_columns(width, height, 0)
+}
+
+/// Splits the frame into uniformly spaced tile columns, at least
+/// `tile_columns` of them (rounded up to a power of two and clamped to what
+/// the frame size allows). Rows are only added when the spec requires them
+/// for tile area, so each column can be handed to its own decoder thread.
+/// `tile_columns == 0` uses the minimum layout.
+pub fn build_tile_plan_with_columns(width: u32, height: u32, tile_columns: usize) -> TilePlan {
+    build_tile_plan_with_layout(width, height, tile_columns, 0)
+}
+
+/// Like [`build_tile_plan_with_columns`], with at least `tile_rows` uniformly
+/// spaced tile rows as well (rounded up to a power of two and clamped to the
+/// superblock rows).
+pub fn build_tile_plan_with_layout(
+    width: u32,
+    height: u32,
+    tile_columns: usize,
+    tile_rows: usize,
+) -> TilePlan {
     let sb_cols = width.div_ceil(64);
     let sb_rows = height.div_ceil(64);
 
     let min_log2_cols = tile_log2(MAX_TILE_WIDTH_SB, sb_cols);
+    let max_log2_cols = tile_log2(1, sb_cols.min(MAX_TILE_COLS));
+    let requested_log2_cols = tile_log2(1, tile_columns.min(MAX_TILE_COLS as usize) as u32);
+    let log2_cols = requested_log2_cols.clamp(min_log2_cols, max_log2_cols.max(min_log2_cols));
     let min_log2_tiles = tile_log2(MAX_TILE_AREA_SB, sb_rows * sb_cols).max(min_log2_cols);
-    let min_log2_rows = min_log2_tiles.saturating_sub(min_log2_cols);
+    let min_log2_rows = min_log2_tiles.saturating_sub(log2_cols);
+    let max_log2_rows = tile_log2(1, sb_rows.min(MAX_TILE_ROWS));
+    let requested_log2_rows = tile_log2(1, tile_rows.min(MAX_TILE_ROWS as usize) as u32);
+    let log2_rows = requested_log2_rows.clamp(min_log2_rows, max_log2_rows.max(min_log2_rows));
 
-    let col_starts = uniform_tile_starts(sb_cols, min_log2_cols);
-    let row_starts = uniform_tile_starts(sb_rows, min_log2_rows);
+    let col_starts = uniform_tile_starts(sb_cols, log2_cols);
+    let row_starts = uniform_tile_starts(sb_rows, log2_rows);
 
     let mut tiles = Vec::with_capacity((col_starts.len() - 1) * (row_starts.len() - 1));
     for row in 0..(row_starts.len() - 1) {
@@ -72,11 +114,12 @@ pub fn build_tile_plan(width: u32, height: u32) -> TilePlan {
     TilePlan {
         sb_cols,
         sb_rows,
-        tile_cols_log2: min_log2_cols,
-        tile_rows_log2: min_log2_rows,
+        tile_cols_log2: log2_cols,
+        tile_rows_log2: log2_rows,
         tile_cols: (col_starts.len() - 1) as u32,
         tile_rows: (row_starts.len() - 1) as u32,
         tiles,
+        threads: 1,
     }
 }
 
@@ -90,47 +133,244 @@ pub fn encode_frame_with_recon(
     base_q_idx: u8,
     dq: DequantValues,
 ) -> (Vec<u8>, FramePixels) {
-    let mut w = BitWriter::new();
-
     let tile_plan = build_tile_plan(pixels.width, pixels.height);
+    let (coded, recon) = encode_frame_with_tile_plan(
+        pixels,
+        base_q_idx,
+        dq,
+        &tile_plan,
+        EarlyExit::default(),
+        false,
+        false,
+        &SbDeltaQ::default(),
+        None,
+    );
+    (coded.payload(), recon)
+}
 
-    w.write_bit(false);
-    w.write_bits(0, 2);
-    w.write_bit(true);
-    w.write_bit(false);
-    w.write_bit(false);
-    w.write_bit(false);
+/// Encodes a keyframe. With `cdef_search` the CDEF strength is chosen per
+/// superblock row against the source instead of from the quantizer alone.
+/// `restoration` must match the sequence header's `enable_restoration`.
+/// `parts` is handed the header and then every tile as soon as each is
+/// final, while the rest of the frame is still being coded.
+#[allow(clippy::too_many_arguments)]
+pub fn encode_frame_with_tile_plan(
+    pixels: &FramePixels,
+    base_q_idx: u8,
+    dq: DequantValues,
+    tile_plan: &TilePlan,
+    early_exit: EarlyExit,
+    cdef_search: bool,
+    restoration: bool,
+    delta_q: &SbDeltaQ,
+    parts: Option<FrameSink<'_>>,
+) -> (CodedFrame, FramePixels) {
+    encode_intra_frame(
+        pixels,
+        base_q_idx,
+        dq,
+        tile_plan,
+        early_exit,
+        cdef_search,
+        restoration,
+        delta_q,
+        IntraFrameKind::Key,
+        parts,
+    )
+}
 
-    w.write_bit(false);
+/// Encodes a hidden keyframe for open GOPs: it is only stored in the slots
+/// of `refresh_frame_flags`, so the leading frames coded after it can still
+/// predict from the pictures before it, and a later `show_existing_frame`
+/// displays it and resets every reference.
+#[allow(clippy::too_many_arguments)]
+pub fn encode_forward_keyframe_with_tile_plan(
+    pixels: &FramePixels,
+    base_q_idx: u8,
+    dq: DequantValues,
+    tile_plan: &TilePlan,
+    early_exit: EarlyExit,
+    cdef_search: bool,
+    restoration: bool,
+    delta_q: &SbDeltaQ,
+    refresh_frame_flags: u8,
+    parts: Option<FrameSink<'_>>,
+) -> (CodedFrame, FramePixels) {
+    encode_intra_frame(
+        pixels,
+        base_q_idx,
+        dq,
+        tile_plan,
+        early_exit,
+        cdef_search,
+        restoration,
+        delta_q,
+        IntraFrameKind::ForwardKey {
+            refresh_frame_flags,
+        },
+        parts,
+    )
+}
+
+/// Encodes an INTRA_ONLY frame: coded like a keyframe, but it only
+/// replaces the reference slots in `refresh_frame_flags` and leaves the
+/// decoder's other state alone. Coding every slot is reserved for
+/// keyframes.
+#[allow(clippy::too_many_arguments)]
+pub fn encode_intra_only_frame_with_tile_plan(
+    pixels: &FramePixels,
+    base_q_idx: u8,
+    dq: DequantValues,
+    tile_plan: &TilePlan,
+    early_exit: EarlyExit,
+    cdef_search: bool,
+    restoration: bool,
+    delta_q: &SbDeltaQ,
+    refresh_frame_flags: u8,
+    parts: Option<FrameSink<'_>>,
+) -> (CodedFrame, FramePixels) {
+    assert_ne!(
+        refresh_frame_flags, 0xFF,
+        "intra-only frames must not refresh every slot"
+    );
+    encode_intra_frame(
+        pixels,
+        base_q_idx,
+        dq,
+        tile_plan,
+        early_exit,
+        cdef_search,
+        restoration,
+        delta_q,
+        IntraFrameKind::IntraOnly {
+            refresh_frame_flags,
+        },
+        parts,
+    )
+}
 
-    write_tile_info(&mut w, &tile_plan);
-    write_quant_params(&mut w, base_q_idx);
+/// The intra frame headers, which share the tile syntax.
+enum IntraFrameKind {
+    /// A shown keyframe, refreshing every slot.
+    Key,
+    /// A hidden keyframe refreshing these slots until it is shown.
+    ForwardKey { refresh_frame_flags: u8 },
+    /// A shown INTRA_ONLY frame refreshing these slots.
+    IntraOnly { refresh_frame_flags: u8 },
+}
 
-    w.write_bit(false);
+#[allow(clippy::too_many_arguments)]
+fn encode_intra_frame(
+    pixels: &FramePixels,
+    base_q_idx: u8,
+    dq: DequantValues,
+    tile_plan: &TilePlan,
+    early_exit: EarlyExit,
+    cdef_search: bool,
+    restoration: bool,
+    delta_q: &SbDeltaQ,
+    kind: IntraFrameKind,
+    parts: Option<FrameSink<'_>>,
+) -> (CodedFrame, FramePixels) {
+    // Lossless frames carry no loop filter, CDEF or tx_mode syntax; every
+    // block uses 4x4 Walsh-Hadamard transforms.
+    let lossless = crate::dequant::is_lossless(base_q_idx, &dq.delta_q);
+    let header = |cdef: &CdefPlan, lr: &RestorationPlan| {
+        let mut w = BitWriter::new();
+
+        match &kind {
+            IntraFrameKind::Key => {
+                w.write_bit(false);
+                w.write_bits(0, 2);
+                w.write_bit(true);
+                w.write_bit(false);
+                w.write_bit(false);
+                w.write_bit(false);
+
+                w.write_bit(false);
+            }
+            IntraFrameKind::ForwardKey {
+                refresh_frame_flags,
+            } => {
+                w.write_bit(false); // show_existing_frame
+                w.write_bits(0, 2); // frame_type: KEY_FRAME
+                w.write_bit(false); // show_frame
+                w.write_bit(true); // showable_frame
+                w.write_bit(false); // error_resilient_mode
+                w.write_bit(false); // disable_cdf_update
+                w.write_bit(false); // frame_size_override_flag
+                w.write_bits(*refresh_frame_flags as u64, 8);
+                w.write_bit(false); // render_and_frame_size_different
+                w.write_bit(false); // disable_frame_end_update_cdf
+            }
+            IntraFrameKind::IntraOnly {
+                refresh_frame_flags,
+            } => {
+                w.write_bit(false); // show_existing_frame
+                w.write_bits(2, 2); // frame_type: INTRA_ONLY_FRAME
+                w.write_bit(true); // show_frame
+                w.write_bit(false); // error_resilient_mode
+                w.write_bit(false); // disable_cdf_update
+                w.write_bit(false); // frame_size_override_flag
+                w.write_bits(*refresh_frame_flags as u64, 8);
+                w.write_bit(false); // render_and_frame_size_different
+                w.write_bit(false); // disable_frame_end_update_cdf
+            }
+        }
 
-    w.write_bit(false);
+        write_tile_info(&mut w, tile_plan);
+        write_quant_params(&mut w, base_q_idx, &dq.delta_q);
 
-    write_loopfilter_params(&mut w, base_q_idx);
-    write_cdef_params(&mut w, base_q_idx);
+        w.write_bit(false);
 
-    w.write_bit(false);
-    w.write_bit(true);
+        if base_q_idx > 0 {
+            w.write_bit(delta_q.is_enabled()); // delta_q_present
+            if delta_q.is_enabled() {
+                w.write_bits(0, 2); // delta_q_res
+                w.write_bit(false); // delta_lf_present
+            }
+        }
 
-    let mut header_bytes = w.finalize();
-    let (tile_payloads, mut recon) =
-        crate::tile::encode_tiles_with_recon(pixels, dq, base_q_idx, &tile_plan);
-    let tile_group_payload = build_tile_group_payload(&tile_payloads);
-
-    let (damping_minus_3, y_strength, _uv_strength) = cdef_strength_for_qidx(base_q_idx);
-    crate::cdef::apply_cdef_frame(
-        &mut recon,
-        (y_strength >> 2) as i32,
-        (y_strength & 3) as i32,
-        (damping_minus_3 + 3) as i32,
+        if !lossless {
+            write_loopfilter_params(&mut w, base_q_idx);
+            write_cdef_params(&mut w, cdef);
+            if restoration {
+                write_lr_params(&mut w, lr);
+            }
+            w.write_bit(false); // tx_mode_select
+        }
+        w.write_bit(true);
+        w
+    };
+    let encode_tiles = |cdef: &CdefSignal, lr: &RestorationPlan, on_tile: Option<TileSink<'_>>| {
+        let (payloads, recon, skips) = crate::tile::encode_tiles_with_recon(
+            pixels, dq, base_q_idx, tile_plan, early_exit, cdef, lr, delta_q, on_tile,
+        );
+        (payloads, recon, skips, ())
+    };
+    if lossless {
+        let cdef = CdefPlan::for_qidx(base_q_idx);
+        let lr = RestorationPlan::default();
+        let w = header(&cdef, &lr);
+        let mut parts = parts;
+        emit_header(&mut parts, &w);
+        let (payloads, recon, _, ()) = with_tile_parts(&mut parts, |on_tile| {
+            encode_tiles(&cdef.signal, &lr, on_tile)
+        });
+        let recon = crate::tile::crop_block_grid(&recon, pixels.width, pixels.height);
+        return (CodedFrame::new(w, payloads), recon);
+    }
+    let (coded, recon, ()) = encode_tiles_with_filters(
+        pixels,
+        base_q_idx,
+        &dq,
+        cdef_search,
+        restoration,
+        header,
+        encode_tiles,
+        parts,
     );
-
-    header_bytes.extend_from_slice(&tile_group_payload);
-    (header_bytes, recon)
+    (coded, recon)
 }
 
 fn write_tile_info(w: &mut BitWriter, plan: &TilePlan) {
@@ -140,7 +380,10 @@ fn write_tile_info(w: &mut BitWriter, plan: &TilePlan) {
     let max_log2_cols = tile_log2(1, plan.sb_cols.min(MAX_TILE_COLS));
     let log2_cols = plan.tile_cols_log2;
 
-    if min_log2_cols < max_log2_cols {
+    for _ in min_log2_cols..log2_cols {
+        w.write_bit(true); // increment_tile_cols_log2
+    }
+    if log2_cols < max_log2_cols {
         w.write_bit(false);
     }
 
@@ -149,7 +392,10 @@ fn write_tile_info(w: &mut BitWriter, plan: &TilePlan) {
     let min_log2_rows = min_log2_tiles.saturating_sub(log2_cols);
     let max_log2_rows = tile_log2(1, plan.sb_rows.min(MAX_TILE_ROWS));
 
-    if min_log2_rows < max_log2_rows {
+    for _ in min_log2_rows..plan.tile_rows_log2 {
+        w.write_bit(true); // increment_tile_rows_log2
+    }
+    if plan.tile_rows_log2 < max_log2_rows {
         w.write_bit(false);
     }
 
@@ -159,15 +405,87 @@ fn write_tile_info(w: &mut BitWriter, plan: &TilePlan) {
     }
 }
 
+/// A coded frame before it is packed into OBUs.
+#[derive(Debug, Clone)]
+pub struct CodedFrame {
+    /// Uncompressed header bits closed by `byte_alignment`, as they open an
+    /// OBU_FRAME.
+    pub header: Vec<u8>,
+    /// The same bits closed by `trailing_bits`, as a standalone
+    /// OBU_FRAME_HEADER payload.
+    pub header_obu: Vec<u8>,
+    /// Entropy-coded tiles in raster order.
+    pub tiles: Vec<Vec<u8>>,
+}
+
+/// A part of a frame handed out while it is coded, so it can be sent
+/// before the rest is: the header, then every tile in raster order.
+#[derive(Debug, Clone, Copy)]
+pub enum FramePart<'a> {
+    /// The OBU_FRAME_HEADER payload.
+    Header(&'a [u8]),
+    /// A tile's index and entropy-coded bytes.
+    Tile(usize, &'a [u8]),
+}
+
+/// Takes the parts of a frame as they are coded.
+pub type FrameSink<'a> = &'a mut dyn FnMut(FramePart<'_>);
+
+impl CodedFrame {
+    fn new(header: BitWriter, tiles: Vec<Vec<u8>>) -> Self {
+        Self {
+            header: header.clone().finalize(),
+            header_obu: header.trailing_bits(),
+            tiles,
+        }
+    }
+
+    /// OBU_FRAME payload: the header followed by one tile group holding
+    /// every tile.
+    pub fn payload(&self) -> Vec<u8> {
+        let mut out = self.header.clone();
+        out.extend_from_slice(&build_tile_group_payload(&self.tiles));
+        out
+    }
+
+    /// OBU_TILE_GROUP payload for the tiles of rows `rows` of `plan`, with
+    /// explicit start and end tile indices. The frame must have more than
+    /// one tile.
+    pub fn tile_row_group(&self, plan: &TilePlan, rows: std::ops::Range<u32>) -> Vec<u8> {
+        tile_row_group(plan, rows, &self.tiles)
+    }
+}
+
+/// [`CodedFrame::tile_row_group`] from `tiles`, the frame's tiles in raster
+/// order up to at least the end of `rows`.
+pub fn tile_row_group(plan: &TilePlan, rows: std::ops::Range<u32>, tiles: &[Vec<u8>]) -> Vec<u8> {
+    let tile_bits = (plan.tile_cols_log2 + plan.tile_rows_log2) as u8;
+    let first = (rows.start * plan.tile_cols) as usize;
+    let last = (rows.end * plan.tile_cols) as usize - 1;
+    let mut w = BitWriter::new();
+    w.write_bit(true); // tile_start_and_end_present_flag
+    w.write_bits(first as u64, tile_bits);
+    w.write_bits(last as u64, tile_bits);
+    let mut out = w.finalize();
+    out.extend_from_slice(&tile_group_body(&tiles[first..=last]));
+    out
+}
+
 fn build_tile_group_payload(tile_payloads: &[Vec<u8>]) -> Vec<u8> {
     assert!(!tile_payloads.is_empty(), "tile payloads must not be empty");
     if tile_payloads.len() == 1 {
         return tile_payloads[0].clone();
     }
 
-    let mut out = Vec::new();
-    out.push(0x00); // tile_start_and_end_present_flag=0 + byte alignment
+    let mut out = vec![0x00]; // tile_start_and_end_present_flag=0 + byte alignment
+    out.extend_from_slice(&tile_group_body(tile_payloads));
+    out
+}
 
+/// Tiles of one tile group, each but the last preceded by its size in
+/// `TileSizeBytes` (4) bytes.
+fn tile_group_body(tile_payloads: &[Vec<u8>]) -> Vec<u8> {
+    let mut out = Vec::new();
     for (idx, payload) in tile_payloads.iter().enumerate() {
         if idx + 1 != tile_payloads.len() {
             let tile_size_minus_1 = payload
@@ -184,16 +502,32 @@ fn build_tile_group_payload(tile_payloads: &[Vec<u8>]) -> Vec<u8> {
     out
 }
 
-fn write_quant_params(w: &mut BitWriter, base_q_idx: u8) {
+fn write_quant_params(w: &mut BitWriter, base_q_idx: u8, delta_q: &ChromaDeltaQ) {
     w.write_bits(base_q_idx as u64, 8);
-    w.write_bit(false);
-    w.write_bit(false);
-    w.write_bit(false);
-    w.write_bit(false);
+    write_delta_q(w, 0); // DeltaQYDc
+    // The sequence header sets separate_uv_delta_q exactly when U and V differ.
+    let diff_uv_delta = delta_q.separate_uv();
+    if diff_uv_delta {
+        w.write_bit(true);
+    }
+    write_delta_q(w, delta_q.u_dc);
+    write_delta_q(w, delta_q.u_ac);
+    if diff_uv_delta {
+        write_delta_q(w, delta_q.v_dc);
+        write_delta_q(w, delta_q.v_ac);
+    }
+    w.write_bit(false); // using_qmatrix
+}
+
+fn write_delta_q(w: &mut BitWriter, delta: i8) {
+    w.write_bit(delta != 0); // delta_coded
+    if delta != 0 {
+        w.write_bits((delta as u8 & 0x7F) as u64, 7); // su(1+6)
+    }
 }
 
 fn cdef_strength_for_qidx(base_q_idx: u8) -> (u8, u8, u8) {
-    if base_q_idx < 64 {
+    if base_q_idx < 32 {
         (0, 0, 0)
     } else {
         let pri = (base_q_idx as u32 / 16).clamp(1, 15) as u8;
@@ -203,12 +537,184 @@ fn cdef_strength_for_qidx(base_q_idx: u8) -> (u8, u8, u8) {
     }
 }
 
-fn write_cdef_params(w: &mut BitWriter, base_q_idx: u8) {
-    let (damping_minus_3, y_strength, uv_strength) = cdef_strength_for_qidx(base_q_idx);
-    w.write_bits(damping_minus_3 as u64, 2);
-    w.write_bits(0, 2);
-    w.write_bits(y_strength as u64, 6);
-    w.write_bits(uv_strength as u64, 6);
+/// CDEF strengths written in the frame header (`pri << 2 | sec`, shared by
+/// luma and chroma) and which one each superblock row uses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CdefPlan {
+    pub damping_minus_3: u8,
+    pub strengths: Vec<u8>,
+    pub signal: CdefSignal,
+}
+
+impl CdefPlan {
+    fn for_qidx(base_q_idx: u8) -> Self {
+        let (damping_minus_3, y_strength, _uv_strength) = cdef_strength_for_qidx(base_q_idx);
+        Self {
+            damping_minus_3,
+            strengths: vec![y_strength],
+            signal: CdefSignal::default(),
+        }
+    }
+
+    /// Tries no filtering, half, the quantizer default and one and a half
+    /// times its primary strength on every superblock row of `recon`, and
+    /// signals only the strengths some row picked.
+    fn search(recon: &FramePixels, skips: &SkipMap, source: &FramePixels, base_q_idx: u8) -> Self {
+        const DAMPING_MINUS_3: u8 = 2;
+        let pri = (base_q_idx as u32 / 16).clamp(1, 15) as u8;
+        let mut candidates = vec![0, pri / 2, pri, (pri + pri / 2).min(15)];
+        candidates.dedup();
+        let trials: Vec<(i32, i32)> = candidates.iter().map(|&p| (p as i32, 0)).collect();
+        let choice = crate::cdef::search_sb_row_strengths(
+            recon,
+            source,
+            &trials,
+            (DAMPING_MINUS_3 + 3) as i32,
+            skips,
+        );
+
+        let mut used = choice.clone();
+        used.sort_unstable();
+        used.dedup();
+        let bits = used.len().next_power_of_two().trailing_zeros() as u8;
+        let mut strengths: Vec<u8> = used.iter().map(|&c| candidates[c] << 2).collect();
+        strengths.resize(1 << bits, *strengths.last().unwrap());
+        let sb_row_idx = choice
+            .iter()
+            .map(|c| used.iter().position(|u| u == c).unwrap() as u8)
+            .collect();
+        Self {
+            damping_minus_3: DAMPING_MINUS_3,
+            strengths,
+            signal: CdefSignal { bits, sb_row_idx },
+        }
+    }
+
+    /// Filters `recon` as a decoder does, leaving the blocks `skips` marks
+    /// alone.
+    fn apply(&self, recon: &mut FramePixels, skips: &SkipMap) {
+        let sb_rows = recon.height.div_ceil(64) as usize;
+        let rows: Vec<(i32, i32)> = (0..sb_rows)
+            .map(|row| {
+                let idx = self.signal.sb_row_idx.get(row).copied().unwrap_or(0);
+                let strength = self.strengths[idx as usize];
+                ((strength >> 2) as i32, (strength & 3) as i32)
+            })
+            .collect();
+        crate::cdef::apply_cdef_sb_rows(recon, &rows, (self.damping_minus_3 + 3) as i32, skips);
+    }
+}
+
+/// Codes the tiles, re-coding them only when the CDEF and restoration
+/// choices need signalling in them. `parts` gets the tiles of the pass
+/// that is kept.
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+fn encode_tiles_with_filters<T>(
+    pixels: &FramePixels,
+    base_q_idx: u8,
+    dq: &DequantValues,
+    cdef_search: bool,
+    restoration: bool,
+    header: impl Fn(&CdefPlan, &RestorationPlan) -> BitWriter,
+    encode_tiles: impl Fn(
+        &CdefSignal,
+        &RestorationPlan,
+        Option<TileSink<'_>>,
+    ) -> (Vec<Vec<u8>>, FramePixels, SkipMap, T),
+    mut parts: Option<FrameSink<'_>>,
+) -> (CodedFrame, FramePixels, T) {
+    let default_plan = CdefPlan::for_qidx(base_q_idx);
+    let searched = cdef_search || restoration;
+    let (tile_payloads, mut recon, skips, side) = if searched {
+        encode_tiles(&default_plan.signal, &RestorationPlan::default(), None)
+    } else {
+        emit_header(
+            &mut parts,
+            &header(&default_plan, &RestorationPlan::default()),
+        );
+        with_tile_parts(&mut parts, |on_tile| {
+            encode_tiles(&default_plan.signal, &RestorationPlan::default(), on_tile)
+        })
+    };
+    let cdef = if cdef_search {
+        CdefPlan::search(&recon, &skips, pixels, base_q_idx)
+    } else {
+        default_plan
+    };
+    let (width, height) = (pixels.width, pixels.height);
+    let deblocked = restoration.then(|| crate::tile::crop_block_grid(&recon, width, height));
+    cdef.apply(&mut recon, &skips);
+    let mut recon = crate::tile::crop_block_grid(&recon, width, height);
+    let lr = match &deblocked {
+        Some(deblocked) => {
+            let lambdas =
+                [dq.ac, dq.chroma[0].ac, dq.chroma[1].ac].map(crate::rdo::lambda_for_ac_dq);
+            let lr = RestorationPlan::search(deblocked, &recon, pixels, lambdas);
+            lr.apply(deblocked, &mut recon);
+            lr
+        }
+        None => RestorationPlan::default(),
+    };
+    let w = header(&cdef, &lr);
+    if !searched {
+        return (CodedFrame::new(w, tile_payloads), recon, side);
+    }
+    emit_header(&mut parts, &w);
+    if cdef.signal.bits == 0 && !lr.is_enabled() {
+        if let Some(parts) = &mut parts {
+            for (i, tile) in tile_payloads.iter().enumerate() {
+                parts(FramePart::Tile(i, tile));
+            }
+        }
+        return (CodedFrame::new(w, tile_payloads), recon, side);
+    }
+    let (tile_payloads, _, _, side) = with_tile_parts(&mut parts, |on_tile| {
+        encode_tiles(&cdef.signal, &lr, on_tile)
+    });
+    (CodedFrame::new(w, tile_payloads), recon, side)
+}
+
+/// Hands `w` to `parts` as an OBU_FRAME_HEADER payload.
+fn emit_header(parts: &mut Option<FrameSink<'_>>, w: &BitWriter) {
+    if let Some(parts) = parts {
+        parts(FramePart::Header(&w.clone().trailing_bits()));
+    }
+}
+
+/// Runs a coding pass that hands each tile to `parts` as it is coded.
+fn with_tile_parts<R>(
+    parts: &mut Option<FrameSink<'_>>,
+    pass: impl FnOnce(Option<TileSink<'_>>) -> R,
+) -> R {
+    match parts {
+        Some(parts) => pass(Some(&mut |i, tile| parts(FramePart::Tile(i, tile)))),
+        None => pass(None),
+    }
+}
+
+fn write_cdef_params(w: &mut BitWriter, cdef: &CdefPlan) {
+    w.write_bits(cdef.damping_minus_3 as u64, 2);
+    w.write_bits(cdef.signal.bits as u64, 2);
+    for &strength in &cdef.strengths {
+        w.write_bits(strength as u64, 6); // cdef_y_{pri,sec}_strength
+        w.write_bits(strength as u64, 6); // cdef_uv_{pri,sec}_strength
+    }
+}
+
+fn write_lr_params(w: &mut BitWriter, lr: &RestorationPlan) {
+    for plane in &lr.planes {
+        w.write_bits(if plane.is_some() { 2 } else { 0 }, 2); // lr_type: WIENER or NONE
+    }
+    if !lr.is_enabled() {
+        return;
+    }
+    w.write_bit(lr.unit_shift > 0); // lr_unit_shift
+    if lr.unit_shift > 0 {
+        w.write_bit(lr.unit_shift > 1); // lr_unit_extra_shift
+    }
+    if lr.planes[1].is_some() || lr.planes[2].is_some() {
+        w.write_bit(true); // lr_uv_shift
+    }
 }
 
 fn loop_filter_level_for_qidx(_base_q_idx: u8) -> u8 {
@@ -228,6 +734,7 @@ fn write_loopfilter_params(w: &mut BitWriter, base_q_idx: u8) {
     w.write_bit(false);
 }
 
+#[cfg(feature = "inter")]
 pub fn encode_inter_frame(
     pixels: &FramePixels,
     reference: &FramePixels,
@@ -239,6 +746,7 @@ pub fn encode_inter_frame(
     encode_inter_frame_with_recon(
         pixels,
         reference,
+        &[],
         None,
         refresh_frame_flags,
         ref_slot,
@@ -246,8 +754,17 @@ pub fn encode_inter_frame(
         show_frame,
         crate::DEFAULT_BASE_Q_IDX,
         dq,
+        &build_tile_plan(pixels.width, pixels.height),
+        MotionParams::default(),
+        EarlyExit::default(),
+        None,
+        false,
+        false,
+        &SbDeltaQ::default(),
+        None,
     )
     .0
+    .payload()
 }
 
 pub fn encode_show_existing_frame(slot: u8) -> Vec<u8> {
@@ -257,10 +774,21 @@ pub fn encode_show_existing_frame(slot: u8) -> Vec<u8> {
     w.trailing_bits()
 }
 
+/// A reference other than LAST and the slot it is read from.
+#[cfg(feature = "inter")]
+#[derive(Debug, Clone, Copy)]
+pub struct AltReference<'a> {
+    pub ref_frame: RefFrame,
+    pub slot: u8,
+    pub pixels: &'a FramePixels,
+}
+
+#[cfg(feature = "inter")]
 #[allow(clippy::too_many_arguments)]
 pub fn encode_inter_frame_with_recon(
     pixels: &FramePixels,
     reference: &FramePixels,
+    alternates: &[AltReference],
     forward_reference: Option<&FramePixels>,
     refresh_frame_flags: u8,
     ref_slot: u8,
@@ -268,96 +796,182 @@ pub fn encode_inter_frame_with_recon(
     show_frame: bool,
     base_q_idx: u8,
     dq: DequantValues,
-) -> (Vec<u8>, FramePixels) {
-    let mut w = BitWriter::new();
-
-    let tile_plan = build_tile_plan(pixels.width, pixels.height);
-
-    w.write_bit(false); // show_existing_frame
-    w.write_bits(1, 2); // frame_type
-    w.write_bit(show_frame); // show_frame
-    if !show_frame {
-        w.write_bit(true); // showable_frame
-    }
-    w.write_bit(true); // error_resilient_mode
-    w.write_bit(true); // disable_cdf_update
-    w.write_bit(false); // allow_high_precision_mv
-
-    w.write_bits(refresh_frame_flags as u64, 8);
-
-    // Write the 7 reference frame indices. Ref 0 is LAST_FRAME, Ref 1 is LAST2_FRAME... Ref 6 is ALTREF_FRAME
-    // AV1 Ref frames:
-    // 0: LAST_FRAME
-    // 1: LAST2_FRAME
-    // 2: LAST3_FRAME
-    // 3: GOLDEN_FRAME
-    // 4: BWDREF_FRAME
-    // 5: ALTREF2_FRAME
-    // 6: ALTREF_FRAME
-    for i in 0..7 {
-        if i >= 4 {
-            w.write_bits(bwd_ref_slot as u64, 3);
-        } else {
-            w.write_bits(ref_slot as u64, 3);
+    tile_plan: &TilePlan,
+    motion: MotionParams,
+    early_exit: EarlyExit,
+    colocated: Option<&MvField>,
+    cdef_search: bool,
+    restoration: bool,
+    delta_q: &SbDeltaQ,
+    parts: Option<FrameSink<'_>>,
+) -> (CodedFrame, FramePixels, FrameMotion) {
+    let alternate_pixels: Vec<_> = alternates.iter().map(|a| (a.ref_frame, a.pixels)).collect();
+    let encode_tiles = |cdef: &CdefSignal, lr: &RestorationPlan, on_tile: Option<TileSink<'_>>| {
+        crate::tile::encode_inter_tiles_with_recon(
+            pixels,
+            reference,
+            &alternate_pixels,
+            forward_reference,
+            dq,
+            base_q_idx,
+            tile_plan,
+            motion,
+            early_exit,
+            colocated,
+            cdef,
+            lr,
+            delta_q,
+            on_tile,
+        )
+    };
+    let header = |cdef: &CdefPlan, lr: &RestorationPlan| {
+        let mut w = BitWriter::new();
+
+        w.write_bit(false); // show_existing_frame
+        w.write_bits(1, 2); // frame_type
+        w.write_bit(show_frame); // show_frame
+        if !show_frame {
+            w.write_bit(true); // showable_frame
+        }
+        w.write_bit(true); // error_resilient_mode
+        w.write_bit(true); // disable_cdf_update
+        w.write_bit(false); // allow_high_precision_mv
+
+        w.write_bits(refresh_frame_flags as u64, 8);
+
+        // Write the 7 reference frame indices. Ref 0 is LAST_FRAME, Ref 1 is LAST2_FRAME... Ref 6 is ALTREF_FRAME
+        // AV1 Ref frames:
+        // 0: LAST_FRAME
+        // 1: LAST2_FRAME
+        // 2: LAST3_FRAME
+        // 3: GOLDEN_FRAME
+        // 4: BWDREF_FRAME
+        // 5: ALTREF2_FRAME
+        // 6: ALTREF_FRAME
+        for i in 0..7 {
+            if i >= 4 {
+                w.write_bits(bwd_ref_slot as u64, 3);
+            } else {
+                let slot = alternates
+                    .iter()
+                    .find(|a| a.ref_frame as usize == i)
+                    .map_or(ref_slot, |a| a.slot);
+                w.write_bits(slot as u64, 3);
+            }
         }
-    }
-
-    w.write_bit(false); // frame_size_override_flag
 
-    w.write_bit(false); // render_and_frame_size_different
-    w.write_bit(false); // is_filter_switchable
-    w.write_bits(0, 2); // interpolation_filter
-    w.write_bit(false); // is_motion_mode_switchable
+        w.write_bit(false); // frame_size_override_flag
 
-    write_tile_info(&mut w, &tile_plan);
+        w.write_bit(false); // render_and_frame_size_different
+        w.write_bit(false); // is_filter_switchable
+        w.write_bits(0, 2); // interpolation_filter
+        w.write_bit(false); // is_motion_mode_switchable
 
-    write_quant_params(&mut w, base_q_idx);
+        write_tile_info(&mut w, tile_plan);
 
-    w.write_bit(false);
+        write_quant_params(&mut w, base_q_idx, &dq.delta_q);
 
-    w.write_bit(false);
+        w.write_bit(false); // segmentation_enabled
 
-    write_loopfilter_params(&mut w, base_q_idx);
-    write_cdef_params(&mut w, base_q_idx);
+        w.write_bit(delta_q.is_enabled()); // delta_q_present
+        if delta_q.is_enabled() {
+            w.write_bits(0, 2); // delta_q_res
+            w.write_bit(false); // delta_lf_present
+        }
 
-    w.write_bit(false);
-    w.write_bit(false);
-    w.write_bit(true);
+        write_loopfilter_params(&mut w, base_q_idx);
+        write_cdef_params(&mut w, cdef);
+        if restoration {
+            write_lr_params(&mut w, lr);
+        }
 
-    for _ in 0..7 {
-        w.write_bit(false);
-    }
+        w.write_bit(false); // tx_mode_select
+        w.write_bit(forward_reference.is_some()); // reference_select
+        w.write_bit(true); // reduced_tx_set
 
-    let mut header_bytes = w.finalize();
-    let (tile_payloads, mut recon) = crate::tile::encode_inter_tiles_with_recon(
+        for _ in 0..7 {
+            w.write_bit(false);
+        }
+        w
+    };
+    encode_tiles_with_filters(
         pixels,
-        reference,
-        forward_reference,
-        dq,
         base_q_idx,
-        &tile_plan,
-    );
-    let tile_group_payload = build_tile_group_payload(&tile_payloads);
-
-    let (damping_minus_3, y_strength, _uv_strength) = cdef_strength_for_qidx(base_q_idx);
-    crate::cdef::apply_cdef_frame(
-        &mut recon,
-        (y_strength >> 2) as i32,
-        (y_strength & 3) as i32,
-        (damping_minus_3 + 3) as i32,
-    );
-
-    header_bytes.extend_from_slice(&tile_group_payload);
-    (header_bytes, recon)
+        &dq,
+        cdef_search,
+        restoration,
+        header,
+        encode_tiles,
+        parts,
+    )
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// Textured top superblock row, smooth bottom row; `recon` keeps the
+    /// texture exactly but adds small ripples to the smooth row.
+    fn detailed_and_rippled_rows() -> (FramePixels, FramePixels) {
+        let mut source = FramePixels::solid(64, 128, 0, 128, 128);
+        for (i, s) in source.y.iter_mut().enumerate() {
+            let (x, y) = (i % 64, i / 64);
+            *s = if y < 64 {
+                ((x * 37 + y * 91 + x * y) % 200 + 28) as u16
+            } else {
+                (60 + x + y / 4) as u16
+            };
+        }
+        let mut recon = source.clone();
+        let mut state = 0x2545_f491u32;
+        for s in recon.y.iter_mut().skip(64 * 64) {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            *s = (*s as i32 + ((state >> 16) % 7) as i32 - 3) as u16;
+        }
+        (source, recon)
+    }
+
+    #[test]
+    fn cdef_search_picks_strength_per_superblock_row() {
+        let (source, recon) = detailed_and_rippled_rows();
+        let skips = SkipMap::none_skipped(64, 128);
+        let plan = CdefPlan::search(&recon, &skips, &source, 128);
+        assert_eq!(plan.signal.bits, 1);
+        assert_eq!(plan.strengths.len(), 2);
+        assert_eq!(plan.strengths[0], 0);
+        assert!(plan.strengths[1] > 0);
+        assert_eq!(plan.signal.sb_row_idx, vec![0, 1]);
+
+        let sse = |frame: &FramePixels| -> u64 {
+            frame
+                .y
+                .iter()
+                .zip(&source.y)
+                .map(|(&a, &b)| (a as i64 - b as i64).pow(2) as u64)
+                .sum()
+        };
+        let mut searched = recon.clone();
+        plan.apply(&mut searched, &skips);
+        let mut global = recon.clone();
+        CdefPlan::for_qidx(128).apply(&mut global, &skips);
+        assert!(sse(&searched) < sse(&recon));
+        assert!(sse(&searched) < sse(&global));
+    }
+
+    #[test]
+    fn cdef_search_signals_nothing_when_rows_agree() {
+        let (source, _) = detailed_and_rippled_rows();
+        let plan = CdefPlan::search(&source, &SkipMap::none_skipped(64, 128), &source, 128);
+        assert_eq!(plan.signal.bits, 0);
+        assert_eq!(plan.strengths, vec![0]);
+        assert!(plan.signal.sb_row_idx.iter().all(|&idx| idx == 0));
+    }
+
     #[test]
     fn cdef_strength_mapping() {
         assert_eq!(cdef_strength_for_qidx(0), (0, 0, 0));
+        assert_eq!(cdef_strength_for_qidx(31), (0, 0, 0));
+        assert_eq!(cdef_strength_for_qidx(32), (2, 2 << 2, 2 << 2));
         assert_ne!(cdef_strength_for_qidx(128), (0, 0, 0));
     }
 
@@ -395,6 +1009,66 @@ mod tests {
         assert!(plan.tile_cols > 1 || plan.tile_rows > 1);
     }
 
+    #[test]
+    fn tile_plan_requested_columns_without_extra_rows() {
+        let plan = build_tile_plan_with_columns(3840, 2160, 4);
+        assert_eq!(plan.tile_cols_log2, 2);
+        assert_eq!(plan.tile_cols, 4);
+        assert_eq!(plan.tile_rows, 1);
+        assert_eq!(plan.tiles.len(), 4);
+        assert_eq!(plan.tiles[0].sb_col_start, 0);
+        assert_eq!(plan.tiles[3].sb_col_end, plan.sb_cols);
+    }
+
+    #[test]
+    fn tile_plan_columns_clamped_to_frame_width() {
+        let plan = build_tile_plan_with_columns(128, 64, 8);
+        assert_eq!(plan.tile_cols_log2, 1);
+        assert_eq!(plan.tile_cols, 2);
+
+        let plan = build_tile_plan_with_columns(320, 240, 3);
+        assert_eq!(plan.tile_cols_log2, 2);
+    }
+
+    #[test]
+    fn tile_plan_requested_rows_clamped_to_frame_height() {
+        let plan = build_tile_plan_with_layout(128, 256, 0, 3);
+        assert_eq!(plan.tile_cols, 1);
+        assert_eq!(plan.tile_rows_log2, 2);
+        assert_eq!(plan.tile_rows, 4);
+        assert_eq!(plan.tiles[3].sb_row_end, plan.sb_rows);
+
+        let plan = build_tile_plan_with_layout(128, 64, 0, 4);
+        assert_eq!(plan.tile_rows, 1);
+    }
+
+    #[test]
+    fn tile_row_group_signals_tile_range() {
+        let plan = build_tile_plan_with_layout(128, 128, 2, 2);
+        let coded = CodedFrame {
+            header: Vec::new(),
+            header_obu: Vec::new(),
+            tiles: vec![vec![1], vec![2, 2], vec![3], vec![4, 4, 4]],
+        };
+        let group = coded.tile_row_group(&plan, 1..2);
+        // tile_start_and_end_present_flag=1, tg_start=2, tg_end=3 in two bits
+        // each, then byte alignment
+        assert_eq!(group[0], 0b1101_1000);
+        assert_eq!(&group[1..5], &0u32.to_le_bytes());
+        assert_eq!(&group[5..], &[3, 4, 4, 4]);
+    }
+
+    #[test]
+    fn tile_info_signals_requested_column_increments() {
+        let plan = build_tile_plan_with_columns(256, 64, 4);
+        let mut w = BitWriter::new();
+        write_tile_info(&mut w, &plan);
+        // uniform=1, two increments (no stop bit at the 4-column maximum),
+        // no row bits (one SB row), context_update_tile_id=0, tile_size_bytes=3
+        let bytes = w.finalize();
+        assert_eq!(bytes[0], 0b1110_0110);
+    }
+
     #[test]
     fn multi_tile_payload_has_tile_size_fields() {
         let payload = build_tile_group_payload(&[vec![1, 2, 3], vec![4, 5]]);
@@ -531,6 +1205,7 @@ mod tests {
         assert_eq!(&bytes[..expected_header.len()], &expected_header[..]);
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn inter_frame_header_64x64_bit_layout() {
         let pixels = FramePixels::solid(64, 64, 128, 128, 128);
@@ -594,6 +1269,7 @@ mod tests {
         assert_eq!(&bytes[..expected_header.len()], &expected_header[..]);
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn inter_frame_header_differs_from_keyframe() {
         let pixels = FramePixels::solid(64, 64, 128, 128, 128);
@@ -603,6 +1279,7 @@ mod tests {
         assert_ne!(key_bytes, inter_bytes);
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn inter_frame_header_ref_slot_encoded() {
         let pixels = FramePixels::solid(64, 64, 128, 128, 128);
@@ -612,6 +1289,7 @@ mod tests {
         assert_ne!(bytes_slot0, bytes_slot3);
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn inter_frame_header_refresh_flags_encoded() {
         let pixels = FramePixels::solid(64, 64, 128, 128, 128);
@@ -621,6 +1299,7 @@ mod tests {
         assert_ne!(bytes_01, bytes_ff);
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn inter_frame_starts_with_show_existing_frame_false() {
         let pixels = FramePixels::solid(64, 64, 128, 128, 128);
@@ -629,6 +1308,7 @@ mod tests {
         assert_eq!(bytes[0] & 0x80, 0);
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn inter_frame_has_frame_type_1() {
         let pixels = FramePixels::solid(64, 64, 128, 128, 128);
@@ -0,0 +1,621 @@
+//! IVF container: writing encoder packets, reading files back, and joining
+//! segments from independent encodes into one stream.
+
+use std::io::{self, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+use crate::obu::ObuType;
+
+pub fn write_ivf_header<W: Write>(
+    writer: &mut W,
+    width: u32,
+    height: u32,
+    num_frames: u32,
+    fps_num: u32,
+    fps_den: u32,
+) -> io::Result<()> {
+    let width_u16 = u16::try_from(width).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "IVF width {} exceeds 16-bit container limit (max {})",
+                width,
+                u16::MAX
+            ),
+        )
+    })?;
+    let height_u16 = u16::try_from(height).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "IVF height {} exceeds 16-bit container limit (max {})",
+                height,
+                u16::MAX
+            ),
+        )
+    })?;
+
+    writer.write_all(b"DKIF")?;
+    writer.write_all(&0u16.to_le_bytes())?;
+    writer.write_all(&32u16.to_le_bytes())?;
+    writer.write_all(b"AV01")?;
+    writer.write_all(&width_u16.to_le_bytes())?;
+    writer.write_all(&height_u16.to_le_bytes())?;
+    writer.write_all(&fps_num.to_le_bytes())?;
+    writer.write_all(&fps_den.to_le_bytes())?;
+    writer.write_all(&num_frames.to_le_bytes())?;
+    writer.write_all(&0u32.to_le_bytes())?;
+    Ok(())
+}
+
+pub fn write_ivf_frame<W: Write>(
+    writer: &mut W,
+    timestamp: u64,
+    frame_data: &[u8],
+) -> io::Result<()> {
+    writer.write_all(&(frame_data.len() as u32).to_le_bytes())?;
+    writer.write_all(&timestamp.to_le_bytes())?;
+    writer.write_all(frame_data)?;
+    Ok(())
+}
+
+const IVF_HEADER_LEN: usize = 32;
+const IVF_FRAME_HEADER_LEN: usize = 12;
+const IVF_FRAME_COUNT_OFFSET: u64 = 24;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IvfError {
+    NotIvf,
+    Truncated,
+    /// A segment holds no frames.
+    EmptySegment {
+        segment: usize,
+    },
+    /// A segment's dimensions or frame rate differ from the first segment's.
+    FormatMismatch {
+        segment: usize,
+    },
+    /// A segment's first packet carries no sequence header.
+    MissingSequenceHeader {
+        segment: usize,
+    },
+    /// A segment's sequence header differs from the first segment's, so a
+    /// decoder would have to be reset between them.
+    SequenceHeaderMismatch {
+        segment: usize,
+    },
+    /// Joining at `frame` of `segment` would cut into a GOP.
+    NotKeyframe {
+        segment: usize,
+        frame: usize,
+    },
+    CutOutOfRange {
+        cut: Range<usize>,
+        frames: usize,
+    },
+}
+
+impl std::fmt::Display for IvfError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IvfError::NotIvf => write!(f, "Not an AV1 IVF file"),
+            IvfError::Truncated => write!(f, "Truncated IVF frame"),
+            IvfError::EmptySegment { segment } => write!(f, "Segment {segment} has no frames"),
+            IvfError::FormatMismatch { segment } => write!(
+                f,
+                "Segment {segment} differs from segment 0 in dimensions or frame rate"
+            ),
+            IvfError::MissingSequenceHeader { segment } => {
+                write!(f, "Segment {segment} does not start with a sequence header")
+            }
+            IvfError::SequenceHeaderMismatch { segment } => write!(
+                f,
+                "Segment {segment} has a different sequence header than segment 0"
+            ),
+            IvfError::NotKeyframe { segment, frame } => {
+                write!(f, "Frame {frame} of segment {segment} is not a keyframe")
+            }
+            IvfError::CutOutOfRange { cut, frames } => write!(
+                f,
+                "Cut {}..{} is outside the {frames} frames of the file",
+                cut.start, cut.end
+            ),
+        }
+    }
+}
+
+impl std::error::Error for IvfError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IvfFrame {
+    pub timestamp: u64,
+    pub data: Vec<u8>,
+}
+
+/// An IVF file held in memory, frames in file (decode) order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IvfFile {
+    pub width: u16,
+    pub height: u16,
+    pub fps_num: u32,
+    pub fps_den: u32,
+    pub frames: Vec<IvfFrame>,
+}
+
+impl IvfFile {
+    /// Parses an AV1 IVF file. The header frame count is ignored in favour
+    /// of the frames actually present.
+    pub fn parse(data: &[u8]) -> Result<Self, IvfError> {
+        if data.len() < IVF_HEADER_LEN || &data[0..4] != b"DKIF" || &data[8..12] != b"AV01" {
+            return Err(IvfError::NotIvf);
+        }
+        let le16 = |pos: usize| u16::from_le_bytes([data[pos], data[pos + 1]]);
+        let le32 = |pos: usize| {
+            u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+        };
+        let header_len = usize::from(le16(6)).max(IVF_HEADER_LEN);
+
+        let mut frames = Vec::new();
+        let mut pos = header_len;
+        while pos < data.len() {
+            let header = data
+                .get(pos..pos + IVF_FRAME_HEADER_LEN)
+                .ok_or(IvfError::Truncated)?;
+            let size = le32(pos) as usize;
+            let mut timestamp = [0u8; 8];
+            timestamp.copy_from_slice(&header[4..]);
+            let start = pos + IVF_FRAME_HEADER_LEN;
+            let frame = data.get(start..start + size).ok_or(IvfError::Truncated)?;
+            frames.push(IvfFrame {
+                timestamp: u64::from_le_bytes(timestamp),
+                data: frame.to_vec(),
+            });
+            pos = start + size;
+        }
+
+        Ok(Self {
+            width: le16(12),
+            height: le16(14),
+            fps_num: le32(16),
+            fps_den: le32(20),
+            frames,
+        })
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_ivf_header(
+            writer,
+            u32::from(self.width),
+            u32::from(self.height),
+            self.frames.len() as u32,
+            self.fps_num,
+            self.fps_den,
+        )?;
+        for frame in &self.frames {
+            write_ivf_frame(writer, frame.timestamp, &frame.data)?;
+        }
+        Ok(())
+    }
+
+    fn clone_header(&self) -> Self {
+        Self {
+            frames: Vec::new(),
+            ..*self
+        }
+    }
+}
+
+/// Timestamps `frames` cover: from the earliest to one past the latest,
+/// since B-frame GOPs store frames out of display order.
+fn timeline(frames: &[IvfFrame]) -> Range<u64> {
+    let start = frames.iter().map(|f| f.timestamp).min().unwrap_or(0);
+    let end = frames.iter().map(|f| f.timestamp + 1).max().unwrap_or(0);
+    start..end
+}
+
+/// Joins `segments` from independent encodes of consecutive parts of one
+/// clip into a single stream. Every segment must start with a keyframe whose
+/// sequence header matches the first segment's, so a decoder can play across
+/// the joins. Timestamps are rebased so each segment follows on from the
+/// previous one's last frame.
+pub fn concat(segments: &[IvfFile]) -> Result<IvfFile, IvfError> {
+    let first = segments
+        .first()
+        .ok_or(IvfError::EmptySegment { segment: 0 })?;
+    let sequence_header = segment_sequence_header(first, 0)?;
+    let mut frames = Vec::new();
+    let mut next = timeline(&first.frames).start;
+    for (segment, file) in segments.iter().enumerate() {
+        check_compatible(first, sequence_header, file, segment)?;
+        let span = timeline(&file.frames);
+        frames.extend(file.frames.iter().map(|f| IvfFrame {
+            timestamp: f.timestamp - span.start + next,
+            data: f.data.clone(),
+        }));
+        next += span.end - span.start;
+    }
+    Ok(IvfFile {
+        frames,
+        ..first.clone_header()
+    })
+}
+
+/// Replaces the frames `cut` of `base` with `insert`, shifting the
+/// timestamps of the frames after the cut by the difference in length. The
+/// cut must start and end at keyframes (or the end of `base`), and `insert`
+/// has to be compatible with `base` as for [`concat`]. An empty `insert`
+/// just removes the cut.
+pub fn splice(base: &IvfFile, cut: Range<usize>, insert: &IvfFile) -> Result<IvfFile, IvfError> {
+    if cut.start > cut.end || cut.end > base.frames.len() {
+        return Err(IvfError::CutOutOfRange {
+            cut,
+            frames: base.frames.len(),
+        });
+    }
+    let sequence_header = segment_sequence_header(base, 0)?;
+    if !insert.frames.is_empty() {
+        check_compatible(base, sequence_header, insert, 1)?;
+    }
+    let reduced_still = reduced_still_picture_header(sequence_header);
+    for frame in [cut.start, cut.end] {
+        if frame < base.frames.len() && !is_keyframe(&base.frames[frame].data, reduced_still) {
+            return Err(IvfError::NotKeyframe { segment: 0, frame });
+        }
+    }
+
+    let removed = timeline(&base.frames[cut.clone()]);
+    let at = match base.frames.get(cut.start) {
+        Some(frame) => frame.timestamp,
+        None => timeline(&base.frames).end,
+    };
+    let removed_len = removed.end - removed.start;
+    let inserted = timeline(&insert.frames);
+    let inserted_len = inserted.end - inserted.start;
+
+    let mut frames = base.frames[..cut.start].to_vec();
+    frames.extend(insert.frames.iter().map(|f| IvfFrame {
+        timestamp: f.timestamp - inserted.start + at,
+        data: f.data.clone(),
+    }));
+    frames.extend(base.frames[cut.end..].iter().map(|f| IvfFrame {
+        timestamp: (f.timestamp + inserted_len).saturating_sub(removed_len),
+        data: f.data.clone(),
+    }));
+    Ok(IvfFile {
+        frames,
+        ..base.clone_header()
+    })
+}
+
+fn check_compatible(
+    first: &IvfFile,
+    sequence_header: &[u8],
+    file: &IvfFile,
+    segment: usize,
+) -> Result<(), IvfError> {
+    if (file.width, file.height, file.fps_num, file.fps_den)
+        != (first.width, first.height, first.fps_num, first.fps_den)
+    {
+        return Err(IvfError::FormatMismatch { segment });
+    }
+    if segment_sequence_header(file, segment)? != sequence_header {
+        return Err(IvfError::SequenceHeaderMismatch { segment });
+    }
+    Ok(())
+}
+
+/// The sequence header of a segment that can be decoded on its own: one
+/// whose first packet is a keyframe carrying a sequence header.
+fn segment_sequence_header(file: &IvfFile, segment: usize) -> Result<&[u8], IvfError> {
+    let first = file
+        .frames
+        .first()
+        .ok_or(IvfError::EmptySegment { segment })?;
+    let sequence_header = obus(&first.data)
+        .find(|(obu_type, _)| *obu_type == ObuType::SequenceHeader as u8)
+        .map(|(_, payload)| payload)
+        .ok_or(IvfError::MissingSequenceHeader { segment })?;
+    if !is_keyframe(&first.data, reduced_still_picture_header(sequence_header)) {
+        return Err(IvfError::NotKeyframe { segment, frame: 0 });
+    }
+    Ok(sequence_header)
+}
+
+/// `(obu_type, payload)` of each OBU in a temporal unit, stopping at the
+/// first malformed one.
+fn obus(data: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        let header = *data.get(pos)?;
+        let obu_type = header >> 3 & 0xf;
+        let mut start = pos + 1 + usize::from(header >> 2 & 1);
+        let size = if header & 0x02 != 0 {
+            let mut size = 0usize;
+            let mut shift = 0;
+            loop {
+                let byte = *data.get(start)?;
+                start += 1;
+                size |= usize::from(byte & 0x7f).checked_shl(shift)?;
+                shift += 7;
+                if byte & 0x80 == 0 {
+                    break;
+                }
+            }
+            size
+        } else {
+            data.len().checked_sub(start)?
+        };
+        let payload = data.get(start..start.checked_add(size)?)?;
+        pos = start + size;
+        Some((obu_type, payload))
+    })
+}
+
+/// `reduced_still_picture_header`, after `seq_profile` and `still_picture`.
+fn reduced_still_picture_header(sequence_header: &[u8]) -> bool {
+    sequence_header.first().is_some_and(|b| b & 0x08 != 0)
+}
+
+/// Whether the first frame in `temporal_unit` is a keyframe. Without a
+/// reduced still-picture header the frame header starts with
+/// `show_existing_frame` followed by the two-bit `frame_type`, 0 for KEY.
+fn is_keyframe(temporal_unit: &[u8], reduced_still_picture: bool) -> bool {
+    obus(temporal_unit)
+        .find(|(obu_type, _)| {
+            *obu_type == ObuType::Frame as u8 || *obu_type == ObuType::FrameHeader as u8
+        })
+        .and_then(|(_, payload)| payload.first().copied())
+        .is_some_and(|byte| reduced_still_picture || byte >> 5 == 0)
+}
+
+/// Streaming IVF writer that rewrites the header frame count on `sync`, so a
+/// file cut short by a crash still reports the frames it holds.
+pub struct IvfWriter<W: Write + Seek> {
+    writer: W,
+    frames: u32,
+}
+
+impl<W: Write + Seek> IvfWriter<W> {
+    pub fn new(
+        mut writer: W,
+        width: u32,
+        height: u32,
+        fps_num: u32,
+        fps_den: u32,
+    ) -> io::Result<Self> {
+        write_ivf_header(&mut writer, width, height, 0, fps_num, fps_den)?;
+        Ok(Self { writer, frames: 0 })
+    }
+
+    pub fn write_frame(&mut self, timestamp: u64, frame_data: &[u8]) -> io::Result<()> {
+        write_ivf_frame(&mut self.writer, timestamp, frame_data)?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    pub fn sync(&mut self) -> io::Result<()> {
+        let end = self.writer.stream_position()?;
+        self.writer.seek(SeekFrom::Start(IVF_FRAME_COUNT_OFFSET))?;
+        self.writer.write_all(&self.frames.to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(end))?;
+        self.writer.flush()
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.sync()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_width_above_u16_limit() {
+        let mut out = Vec::new();
+        let err =
+            write_ivf_header(&mut out, 70_000, 1_000, 1, 25, 1).expect_err("expected rejection");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn rejects_height_above_u16_limit() {
+        let mut out = Vec::new();
+        let err =
+            write_ivf_header(&mut out, 1_000, 70_000, 1, 25, 1).expect_err("expected rejection");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn header_and_frame_fields_are_little_endian() {
+        let mut out = Vec::new();
+        write_ivf_header(&mut out, 0x0102, 0x0304, 0x0506_0708, 30_000, 1_001).unwrap();
+        write_ivf_frame(&mut out, 0x1122_3344_5566_7788, &[0xaa, 0xbb]).unwrap();
+        assert_eq!(
+            out,
+            [
+                b'D', b'K', b'I', b'F', 0, 0, 32, 0, b'A', b'V', b'0', b'1', 0x02, 0x01, 0x04,
+                0x03, 0x30, 0x75, 0, 0, 0xe9, 0x03, 0, 0, 0x08, 0x07, 0x06, 0x05, 0, 0, 0, 0,
+                // Frame header: size, then the 64-bit timestamp.
+                2, 0, 0, 0, 0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, 0xaa, 0xbb,
+            ]
+        );
+    }
+
+    #[test]
+    fn writes_configured_fps_num_den() {
+        let mut out = Vec::new();
+        write_ivf_header(&mut out, 64, 64, 1, 30_000, 1_001).expect("header should write");
+        assert_eq!(
+            u32::from_le_bytes([out[16], out[17], out[18], out[19]]),
+            30_000
+        );
+        assert_eq!(
+            u32::from_le_bytes([out[20], out[21], out[22], out[23]]),
+            1_001
+        );
+    }
+
+    #[test]
+    fn writer_keeps_header_frame_count_current_after_sync() {
+        let frame_count = |out: &[u8]| u32::from_le_bytes([out[24], out[25], out[26], out[27]]);
+        let mut writer = IvfWriter::new(io::Cursor::new(Vec::new()), 64, 64, 25, 1).unwrap();
+        writer.write_frame(0, &[1, 2, 3]).unwrap();
+        writer.write_frame(1, &[4, 5]).unwrap();
+        assert_eq!(frame_count(writer.writer.get_ref()), 0);
+
+        writer.sync().unwrap();
+        assert_eq!(frame_count(writer.writer.get_ref()), 2);
+
+        writer.write_frame(2, &[6]).unwrap();
+        let out = writer.finish().unwrap().into_inner();
+        assert_eq!(frame_count(&out), 3);
+        assert_eq!(out.len(), 32 + 3 * 12 + 6);
+    }
+
+    #[cfg(feature = "inter")]
+    fn file(frames: Vec<IvfFrame>) -> IvfFile {
+        IvfFile {
+            width: 64,
+            height: 64,
+            fps_num: 25,
+            fps_den: 1,
+            frames,
+        }
+    }
+
+    #[cfg(feature = "inter")]
+    /// Encodes `count` frames of a moving gradient into an IVF segment.
+    fn segment(count: u64, keyint: usize, base_q_idx: u8) -> IvfFile {
+        let frames: Vec<_> = (0..count)
+            .map(|i| {
+                let mut frame = crate::y4m::FramePixels::solid(64, 64, 128, 128, 128);
+                for (j, s) in frame.y.iter_mut().enumerate() {
+                    *s = ((j % 64) * 3 + (j / 64) + i as usize * 5) as u16 % 220 + 16;
+                }
+                frame
+            })
+            .collect();
+        let config = crate::EncodeConfig {
+            keyint,
+            base_q_idx,
+            ..crate::EncodeConfig::default()
+        };
+        file(
+            crate::encode_packets(&frames, &config)
+                .into_iter()
+                .map(|p| IvfFrame {
+                    timestamp: p.frame_number,
+                    data: p.data,
+                })
+                .collect(),
+        )
+    }
+
+    #[cfg(feature = "inter")]
+    fn timestamps(file: &IvfFile) -> Vec<u64> {
+        file.frames.iter().map(|f| f.timestamp).collect()
+    }
+
+    #[test]
+    fn parse_returns_what_write_to_wrote() {
+        let original = IvfFile {
+            width: 64,
+            height: 48,
+            fps_num: 30_000,
+            fps_den: 1_001,
+            frames: vec![
+                IvfFrame {
+                    timestamp: 0,
+                    data: vec![1, 2, 3],
+                },
+                IvfFrame {
+                    timestamp: 7,
+                    data: vec![4],
+                },
+            ],
+        };
+        let mut out = Vec::new();
+        original.write_to(&mut out).unwrap();
+        assert_eq!(IvfFile::parse(&out), Ok(original));
+
+        out.pop();
+        assert_eq!(IvfFile::parse(&out), Err(IvfError::Truncated));
+        assert_eq!(IvfFile::parse(b"RIFF"), Err(IvfError::NotIvf));
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn concat_rebases_each_segment_after_the_previous_one() {
+        let joined = concat(&[segment(3, 25, 128), segment(2, 25, 128)]).unwrap();
+        assert_eq!(timestamps(&joined), [0, 1, 2, 3, 4]);
+        assert!(is_keyframe(&joined.frames[3].data, false));
+        assert!(!is_keyframe(&joined.frames[4].data, false));
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn concat_rejects_incompatible_segments() {
+        let first = segment(2, 25, 128);
+        let mut resized = first.clone();
+        resized.width = 32;
+        assert_eq!(
+            concat(&[first.clone(), resized]),
+            Err(IvfError::FormatMismatch { segment: 1 })
+        );
+
+        let mut no_keyframe = first.clone();
+        no_keyframe.frames.remove(0);
+        assert_eq!(
+            concat(&[first.clone(), no_keyframe]),
+            Err(IvfError::NotKeyframe {
+                segment: 1,
+                frame: 0
+            })
+        );
+
+        let mut other_header = first.clone();
+        let data = &mut other_header.frames[0].data;
+        let (_, payload) = obus(data).nth(1).unwrap();
+        let offset = payload.as_ptr() as usize - data.as_ptr() as usize;
+        data[offset + 1] ^= 0x01;
+        assert_eq!(
+            concat(&[first, other_header]),
+            Err(IvfError::SequenceHeaderMismatch { segment: 1 })
+        );
+        assert_eq!(concat(&[]), Err(IvfError::EmptySegment { segment: 0 }));
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn splice_replaces_a_gop_and_shifts_what_follows() {
+        let base = segment(6, 2, 128);
+        let insert = segment(3, 25, 90);
+
+        let spliced = splice(&base, 2..4, &insert).unwrap();
+        assert_eq!(timestamps(&spliced), [0, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(spliced.frames[2].data, insert.frames[0].data);
+        assert_eq!(spliced.frames[5].data, base.frames[4].data);
+
+        let removed = splice(&base, 2..4, &file(Vec::new())).unwrap();
+        assert_eq!(timestamps(&removed), [0, 1, 2, 3]);
+        let appended = splice(&base, 6..6, &insert).unwrap();
+        assert_eq!(timestamps(&appended)[6..], [6, 7, 8]);
+
+        assert_eq!(
+            splice(&base, 1..4, &insert),
+            Err(IvfError::NotKeyframe {
+                segment: 0,
+                frame: 1
+            })
+        );
+        assert_eq!(
+            splice(&base, 4..7, &insert),
+            Err(IvfError::CutOutOfRange {
+                cut: 4..7,
+                frames: 6
+            })
+        );
+    }
+}
@@ -1,37 +1,82 @@
 #![forbid(unsafe_code)]
 
+pub mod analysis;
+pub mod aq;
 pub mod bitwriter;
 pub mod cdef;
 pub mod cdf;
 pub mod cdf_coef;
+pub mod cpu;
 pub mod dequant;
 pub mod encoder;
 pub mod error;
 pub mod fps;
 pub mod frame;
+pub mod ivf;
+pub mod limits;
 pub mod metadata;
+pub mod metrics;
+#[cfg(feature = "mode-hook")]
+pub mod mode_hook;
 pub mod msac;
 pub mod obu;
 pub mod packet;
+pub mod pixel_format;
+pub mod preset;
+pub mod preview;
+pub mod projection;
 pub mod rc;
 pub mod rdo;
+pub mod restoration;
 pub mod satd;
 pub mod sequence;
+pub mod simd;
+pub mod temporal_filter;
 pub mod tile;
+pub mod timebase;
+pub mod two_pass;
 pub mod video;
+pub mod warm_start;
 pub mod y4m;
 
-pub use encoder::{Encoder, EncoderConfig};
+pub use analysis::{
+    FrameComplexity, NoiseStats, SignalMismatch, SuggestedSettings, check_signal, estimate_noise,
+    scene_cuts, suggest_settings,
+};
+pub use aq::AqMode;
+pub use dequant::{ChromaDeltaQ, DeadZone};
+pub use encoder::{
+    Encoder, EncoderConfig, EncoderState, FrameOptions, FrameSizeCapStats, PreflightReport,
+    encode_still,
+};
 pub use error::EncoderError;
 pub use fps::{Fps, FpsError};
-pub use packet::{FrameType, Packet};
+pub use metadata::MetadataCadence;
+pub use packet::{FrameType, Packet, PacketFrame, PacketPoolStats, TemporalUnitGrouper};
+pub use pixel_format::{PixelFormat, RgbFormat, SemiPlanarFormat};
+pub use preset::{Preset, Tune};
+pub use projection::Projection;
+pub use rc::{RateController, RcFrame};
+pub use timebase::CaptureClock;
+pub use two_pass::{FirstPassStats, TwoPass};
 pub use video::{
-    BitDepth, ColorDescription, ColorRange, ContentLightLevel, MasteringDisplayMetadata,
-    VideoSignal,
+    BitDepth, ChromaSamplePosition, CicpField, ColorDescription, ColorRange, ContentLightLevel,
+    MasteringDisplayMetadata, UnknownSignalPreset, VideoSignal,
 };
+pub use warm_start::WarmStartState;
 
 pub const DEFAULT_BASE_Q_IDX: u8 = 128;
+/// Keyframe interval at the default 25 fps: [`DEFAULT_KEYINT_DURATION`]
+/// of frames.
 pub const DEFAULT_KEYINT: usize = 25;
+/// Time between keyframes unless a keyframe interval is given.
+pub const DEFAULT_KEYINT_DURATION: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Keyframe interval in frames at `fps` for [`DEFAULT_KEYINT_DURATION`],
+/// so a clip gets a keyframe every second whatever its frame rate.
+pub fn default_keyint(fps: Fps) -> usize {
+    fps.frames_in(DEFAULT_KEYINT_DURATION)
+}
 
 #[derive(Clone)]
 pub struct EncodeConfig {
@@ -41,23 +86,73 @@ pub struct EncodeConfig {
     pub fps: Fps,
     pub b_frames: bool,
     pub gop_size: usize,
+    pub closed_gop: bool,
     pub video_signal: VideoSignal,
     pub content_light: Option<ContentLightLevel>,
     pub mastering_display: Option<MasteringDisplayMetadata>,
+    pub tile_columns: usize,
+    pub tile_rows: usize,
+    pub threads: usize,
+    pub dead_zone: DeadZone,
+    pub complexity_analysis: bool,
+    pub distortion_maps: bool,
+    pub motion_fields: bool,
+    pub first_pass_scale: u32,
+    pub chroma_delta_q: ChromaDeltaQ,
+    pub cdef_search: bool,
+    pub loop_restoration: bool,
+    pub mv_search_range: u32,
+    pub me_prefilter: bool,
+    pub metadata_cadence: MetadataCadence,
+    pub build_info: bool,
+    pub speed: u8,
+    pub intra_frame_budget: Option<u32>,
+    pub max_frame_bytes: Option<u32>,
+    pub tune: Tune,
+    pub frame_slices: usize,
+    pub aq_mode: AqMode,
+    pub projection: Projection,
+    pub preview_q_idx: Option<u8>,
+    pub two_pass: TwoPass,
 }
 
 impl Default for EncodeConfig {
     fn default() -> Self {
         Self {
             base_q_idx: DEFAULT_BASE_Q_IDX,
-            keyint: DEFAULT_KEYINT,
+            keyint: default_keyint(Fps::default()),
             target_bitrate: None,
             fps: Fps::default(),
             b_frames: false,
             gop_size: 3,
+            closed_gop: true,
             video_signal: VideoSignal::default(),
             content_light: None,
             mastering_display: None,
+            tile_columns: 0,
+            tile_rows: 0,
+            threads: 0,
+            dead_zone: DeadZone::default(),
+            complexity_analysis: false,
+            distortion_maps: false,
+            motion_fields: false,
+            first_pass_scale: 1,
+            chroma_delta_q: ChromaDeltaQ::default(),
+            cdef_search: false,
+            loop_restoration: false,
+            mv_search_range: tile::DEFAULT_MV_SEARCH_RANGE,
+            me_prefilter: false,
+            metadata_cadence: MetadataCadence::EveryFrame,
+            build_info: false,
+            speed: 0,
+            intra_frame_budget: None,
+            max_frame_bytes: None,
+            tune: Tune::Default,
+            frame_slices: 0,
+            aq_mode: AqMode::Off,
+            projection: Projection::Flat,
+            preview_q_idx: None,
+            two_pass: TwoPass::Off,
         }
     }
 }
@@ -104,6 +199,68 @@ pub fn encode_packets(frames: &[y4m::FramePixels], config: &EncodeConfig) -> Vec
     packets
 }
 
+/// Outcome of [`search_q_for_psnr`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QSearchResult {
+    pub base_q_idx: u8,
+    /// Luma-weighted PSNR of the reconstruction at `base_q_idx`.
+    pub psnr: f64,
+    pub bytes: usize,
+}
+
+/// Finds the coarsest `base_q_idx` whose still-frame encode still reaches
+/// `target_psnr` (6:1:1 weighted), by bisection over the quantizer range.
+/// Falls back to `base_q_idx` 0 when no quantizer reaches the target.
+pub fn search_q_for_psnr(
+    frame: &y4m::FramePixels,
+    config: &EncodeConfig,
+    target_psnr: f64,
+) -> Result<QSearchResult, EncoderError> {
+    let probe = |base_q_idx: u8| -> Result<QSearchResult, EncoderError> {
+        let mut probe_config = config.clone();
+        probe_config.base_q_idx = base_q_idx;
+        probe_config.target_bitrate = None;
+        probe_config.b_frames = false;
+        let mut enc = Encoder::new(
+            frame.width,
+            frame.height,
+            EncoderConfig::from(&probe_config),
+        )?;
+        enc.send_frame(frame)?;
+        enc.flush();
+        let mut bytes = 0;
+        while let Some(packet) = enc.receive_packet() {
+            bytes += packet.data.len();
+        }
+        let recon = enc
+            .last_reconstruction()
+            .expect("a flushed encoder holds the frame's reconstruction");
+        Ok(QSearchResult {
+            base_q_idx,
+            psnr: metrics::frame_psnr(frame, recon).weighted(),
+            bytes,
+        })
+    };
+
+    let (mut lo, mut hi) = (0i32, 255i32);
+    let mut best: Option<QSearchResult> = None;
+    let mut finest: Option<QSearchResult> = None;
+    while lo <= hi {
+        let mid = (lo + hi) / 2;
+        let result = probe(mid as u8)?;
+        if finest.is_none_or(|f| result.base_q_idx < f.base_q_idx) {
+            finest = Some(result);
+        }
+        if result.psnr >= target_psnr {
+            best = Some(result);
+            lo = mid + 1;
+        } else {
+            hi = mid - 1;
+        }
+    }
+    Ok(best.or(finest).expect("at least one quantizer is probed"))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -204,4 +361,37 @@ mod tests {
         assert_eq!(packets.len(), 2);
         assert!(!packets[0].data.is_empty());
     }
+
+    fn textured_frame() -> y4m::FramePixels {
+        let mut frame = y4m::FramePixels::solid(64, 64, 128, 128, 128);
+        for (i, s) in frame.y.iter_mut().enumerate() {
+            *s = ((i * 37 + (i / 64) * 91 + (i * i) % 29) % 200 + 20) as u16;
+        }
+        frame
+    }
+
+    #[test]
+    fn default_keyint_is_one_second_of_frames() {
+        assert_eq!(default_keyint(Fps::default()), DEFAULT_KEYINT);
+        assert_eq!(EncodeConfig::default().keyint, DEFAULT_KEYINT);
+        assert_eq!(default_keyint(Fps::new(60000, 1001).unwrap()), 60);
+    }
+
+    #[test]
+    fn q_search_meets_target_with_coarsest_quantizer() {
+        let frame = textured_frame();
+        let config = EncodeConfig::default();
+        let result = search_q_for_psnr(&frame, &config, 36.0).unwrap();
+        assert!(result.psnr >= 36.0);
+        let coarser = search_q_for_psnr(&frame, &config, 30.0).unwrap();
+        assert!(coarser.base_q_idx >= result.base_q_idx);
+        assert!(coarser.bytes <= result.bytes);
+    }
+
+    #[test]
+    fn q_search_falls_back_to_finest_quantizer() {
+        let result =
+            search_q_for_psnr(&textured_frame(), &EncodeConfig::default(), 1000.0).unwrap();
+        assert_eq!(result.base_q_idx, 0);
+    }
 }
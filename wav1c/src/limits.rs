@@ -0,0 +1,228 @@
+//! Hard limits of the encoder and of the AV1 levels it signals, so
+//! front-ends can check user input before constructing an
+//! [`Encoder`](crate::Encoder) and explain what is out of range.
+//!
+//! `Encoder::new`, `encode_still` and `Encoder::preflight` enforce these
+//! same values; [`check_dimensions`] is the size check they run.
+
+use crate::error::EncoderError;
+use crate::fps::Fps;
+use crate::video::BitDepth;
+
+/// Largest frame width or height, the range of AV1's 16-bit frame size
+/// fields.
+pub const MAX_FRAME_DIMENSION: u32 = 1 << 16;
+
+/// Most tile columns AV1 allows in a frame.
+pub const MAX_TILE_COLUMNS: u32 = 64;
+
+/// Most tile rows AV1 allows in a frame.
+pub const MAX_TILE_ROWS: u32 = 64;
+
+/// References an inter block chooses between: LAST, LAST2, GOLDEN and, in
+/// B-frames, ALTREF.
+pub const MAX_REFERENCES: u32 = 4;
+
+/// Largest quantizer index (`EncoderConfig::base_q_idx`); 0 is lossless.
+pub const MAX_Q_IDX: u8 = 255;
+
+/// Largest `EncoderConfig::speed`.
+pub const MAX_SPEED: u8 = crate::tile::MAX_SPEED;
+
+/// Largest `EncoderConfig::mv_search_range`.
+pub const MAX_MV_SEARCH_RANGE: u32 = crate::tile::MAX_MV_SEARCH_RANGE;
+
+/// Largest `EncoderConfig::first_pass_scale`.
+pub const MAX_FIRST_PASS_SCALE: u32 = crate::analysis::MAX_FIRST_PASS_SCALE;
+
+/// Bit depths frames can be coded at.
+pub const SUPPORTED_BIT_DEPTHS: [BitDepth; 2] = [BitDepth::Eight, BitDepth::Ten];
+
+/// `seq_level_idx` written when no level's limits hold the stream
+/// ("maximum parameters").
+pub const SEQ_LEVEL_IDX_MAX_PARAMETERS: u8 = 31;
+
+/// The picture size and sample rate limits of one AV1 level (spec annex
+/// A.3).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LevelLimits {
+    /// `seq_level_idx`: `(major - 2) * 4 + minor`, e.g. 13 for 5.1.
+    pub seq_level_idx: u8,
+    pub max_pic_size: u64,
+    pub max_width: u32,
+    pub max_height: u32,
+    /// Luma samples shown per second.
+    pub max_display_rate: u64,
+    /// Luma samples decoded per second.
+    pub max_decode_rate: u64,
+}
+
+impl LevelLimits {
+    /// The level as `major.minor`, e.g. `(5, 1)`.
+    pub fn version(&self) -> (u8, u8) {
+        (2 + self.seq_level_idx / 4, self.seq_level_idx % 4)
+    }
+
+    /// Whether a `width`x`height` stream at `fps` fits this level.
+    pub fn admits(&self, width: u32, height: u32, fps: Fps) -> bool {
+        let pic_size = width as u64 * height as u64;
+        let rate_num = pic_size as u128 * fps.num as u128;
+        let rate_den = fps.den as u128;
+        width <= self.max_width
+            && height <= self.max_height
+            && pic_size <= self.max_pic_size
+            && rate_num <= self.max_display_rate as u128 * rate_den
+            && rate_num <= self.max_decode_rate as u128 * rate_den
+    }
+}
+
+/// The levels streams are signalled at, lowest first. Smaller streams are
+/// still signalled as 5.1, the lowest level hardware decoders commonly
+/// advertise.
+pub const LEVELS: [LevelLimits; 7] = [
+    LevelLimits {
+        seq_level_idx: 13, // 5.1
+        max_pic_size: 8_912_896,
+        max_width: 8_192,
+        max_height: 4_352,
+        max_display_rate: 534_773_760,
+        max_decode_rate: 547_430_400,
+    },
+    LevelLimits {
+        seq_level_idx: 14, // 5.2
+        max_pic_size: 8_912_896,
+        max_width: 8_192,
+        max_height: 4_352,
+        max_display_rate: 1_069_547_520,
+        max_decode_rate: 1_094_860_800,
+    },
+    LevelLimits {
+        seq_level_idx: 15, // 5.3
+        max_pic_size: 8_912_896,
+        max_width: 8_192,
+        max_height: 4_352,
+        max_display_rate: 1_069_547_520,
+        max_decode_rate: 1_176_502_272,
+    },
+    LevelLimits {
+        seq_level_idx: 16, // 6.0
+        max_pic_size: 35_651_584,
+        max_width: 16_384,
+        max_height: 8_704,
+        max_display_rate: 1_069_547_520,
+        max_decode_rate: 1_176_502_272,
+    },
+    LevelLimits {
+        seq_level_idx: 17, // 6.1
+        max_pic_size: 35_651_584,
+        max_width: 16_384,
+        max_height: 8_704,
+        max_display_rate: 2_139_095_040,
+        max_decode_rate: 2_189_721_600,
+    },
+    LevelLimits {
+        seq_level_idx: 18, // 6.2
+        max_pic_size: 35_651_584,
+        max_width: 16_384,
+        max_height: 8_704,
+        max_display_rate: 4_278_190_080,
+        max_decode_rate: 4_379_443_200,
+    },
+    LevelLimits {
+        seq_level_idx: 19, // 6.3
+        max_pic_size: 35_651_584,
+        max_width: 16_384,
+        max_height: 8_704,
+        max_display_rate: 4_278_190_080,
+        max_decode_rate: 4_706_009_088,
+    },
+];
+
+/// The limits of `seq_level_idx`, if it is one of [`LEVELS`].
+pub fn level_limits(seq_level_idx: u8) -> Option<LevelLimits> {
+    LEVELS
+        .iter()
+        .find(|level| level.seq_level_idx == seq_level_idx)
+        .copied()
+}
+
+/// The largest width and height `seq_level_idx` allows, if it is one of
+/// [`LEVELS`]. Both at once may exceed the level's picture size.
+pub fn max_dimensions(seq_level_idx: u8) -> Option<(u32, u32)> {
+    level_limits(seq_level_idx).map(|level| (level.max_width, level.max_height))
+}
+
+/// The `seq_level_idx` a `width`x`height` stream at `fps` is signalled
+/// with: the lowest of [`LEVELS`] that admits it, or
+/// [`SEQ_LEVEL_IDX_MAX_PARAMETERS`] past all of them.
+pub fn level_for(width: u32, height: u32, fps: Fps) -> u8 {
+    LEVELS
+        .iter()
+        .find(|level| level.admits(width, height, fps))
+        .map_or(SEQ_LEVEL_IDX_MAX_PARAMETERS, |level| level.seq_level_idx)
+}
+
+/// Whether frames can be coded at `bits` bits per sample.
+pub fn supports_bit_depth(bits: u8) -> bool {
+    SUPPORTED_BIT_DEPTHS
+        .iter()
+        .any(|depth| depth.bits() == bits)
+}
+
+/// The fewest and most tile columns a `width`-pixel frame is coded with:
+/// the fewest AV1's 4096-pixel tile width limit allows, and what the
+/// largest `EncoderConfig::tile_columns` gives, about one column per 64x64
+/// superblock up to [`MAX_TILE_COLUMNS`].
+pub fn tile_column_range(width: u32) -> (u32, u32) {
+    let columns = |requested| {
+        crate::frame::build_tile_plan_with_columns(width, 64, requested as usize).tile_cols
+    };
+    (columns(0), columns(MAX_TILE_COLUMNS))
+}
+
+/// Rejects frame sizes the encoder cannot code with
+/// `EncoderError::InvalidDimensions`: zero, or above
+/// [`MAX_FRAME_DIMENSION`].
+pub fn check_dimensions(width: u32, height: u32) -> Result<(), EncoderError> {
+    if width == 0 || height == 0 || width > MAX_FRAME_DIMENSION || height > MAX_FRAME_DIMENSION {
+        return Err(EncoderError::InvalidDimensions { width, height });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_pick_the_lowest_that_fits() {
+        assert_eq!(level_for(1920, 1080, Fps::default()), 13);
+        assert_eq!(level_for(7680, 4320, Fps::default()), 16);
+        assert_eq!(
+            level_for(16_385, 64, Fps::default()),
+            SEQ_LEVEL_IDX_MAX_PARAMETERS
+        );
+        assert_eq!(level_limits(16).unwrap().version(), (6, 0));
+        assert_eq!(max_dimensions(13), Some((8_192, 4_352)));
+        assert_eq!(max_dimensions(12), None);
+        assert!(
+            LEVELS
+                .windows(2)
+                .all(|w| w[0].seq_level_idx < w[1].seq_level_idx)
+        );
+    }
+
+    #[test]
+    fn input_checks_match_the_encoder() {
+        assert!(check_dimensions(1, 1).is_ok());
+        assert!(check_dimensions(MAX_FRAME_DIMENSION, MAX_FRAME_DIMENSION).is_ok());
+        assert!(check_dimensions(0, 64).is_err());
+        assert!(check_dimensions(64, MAX_FRAME_DIMENSION + 1).is_err());
+        assert!(supports_bit_depth(10) && !supports_bit_depth(12));
+
+        assert_eq!(tile_column_range(64), (1, 1));
+        assert_eq!(tile_column_range(1920), (1, 30));
+        assert_eq!(tile_column_range(4097), (2, 33));
+        assert_eq!(tile_column_range(MAX_FRAME_DIMENSION), (16, 64));
+    }
+}
@@ -4,6 +4,22 @@ use crate::video::{ContentLightLevel, MasteringDisplayMetadata};
 
 const OBU_META_HDR_CLL: u64 = 1;
 const OBU_META_HDR_MDCV: u64 = 2;
+/// First of the unregistered user-private metadata types (6..=31).
+pub const OBU_META_BUILD_INFO: u64 = 6;
+
+/// Which temporal units repeat the HDR metadata OBUs (CLL, MDCV). They always
+/// follow the sequence header and precede the frame OBU, and
+/// `Encoder::headers` always includes them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MetadataCadence {
+    /// Every temporal unit, so any packet is a valid entry point.
+    #[default]
+    EveryFrame,
+    /// Only temporal units that start with a keyframe.
+    Keyframes,
+    /// Only the first temporal unit of the stream.
+    Once,
+}
 
 pub fn encode_hdr_cll(cll: &ContentLightLevel) -> Vec<u8> {
     let mut payload = obu::leb128_encode(OBU_META_HDR_CLL);
@@ -31,6 +47,16 @@ pub fn encode_hdr_mdcv(mdcv: &MasteringDisplayMetadata) -> Vec<u8> {
     payload
 }
 
+/// A user-private metadata payload carrying `info` as NUL-terminated UTF-8,
+/// the AV1 counterpart of x264's settings SEI. Decoders skip it.
+pub fn encode_build_info(info: &str) -> Vec<u8> {
+    let mut payload = obu::leb128_encode(OBU_META_BUILD_INFO);
+    payload.extend_from_slice(info.as_bytes());
+    payload.push(0);
+    payload.extend_from_slice(&BitWriter::new().trailing_bits());
+    payload
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -56,4 +82,12 @@ mod tests {
         assert_eq!(payload[0], 2);
         assert_eq!(payload.len(), 1 + 24 + 1);
     }
+
+    #[test]
+    fn build_info_payload_shape() {
+        let payload = encode_build_info("wav1c 1.0");
+        assert_eq!(payload[0], 6);
+        assert_eq!(&payload[1..10], b"wav1c 1.0");
+        assert_eq!(&payload[10..], &[0, 0x80]);
+    }
 }
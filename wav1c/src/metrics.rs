@@ -0,0 +1,333 @@
+use crate::y4m::FramePixels;
+
+/// PSNR reported for identical planes, where the true value is infinite.
+pub const PSNR_IDENTICAL: f64 = 100.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FramePsnr {
+    pub y: f64,
+    pub u: f64,
+    pub v: f64,
+}
+
+impl FramePsnr {
+    /// 6:1:1 luma-weighted average, the usual single-number summary for 4:2:0.
+    pub fn weighted(&self) -> f64 {
+        (6.0 * self.y + self.u + self.v) / 8.0
+    }
+}
+
+pub fn plane_psnr(a: &[u16], b: &[u16], max_value: u16) -> f64 {
+    assert_eq!(a.len(), b.len(), "planes must have the same size");
+    if a.is_empty() {
+        return PSNR_IDENTICAL;
+    }
+    let sse: u64 = a
+        .iter()
+        .zip(b)
+        .map(|(&x, &y)| {
+            let d = x as i64 - y as i64;
+            (d * d) as u64
+        })
+        .sum();
+    sse_psnr(sse, a.len() as u64, max_value)
+}
+
+fn sse_psnr(sse: u64, samples: u64, max_value: u16) -> f64 {
+    if sse == 0 || samples == 0 {
+        return PSNR_IDENTICAL;
+    }
+    let mse = sse as f64 / samples as f64;
+    let peak = max_value as f64;
+    (10.0 * (peak * peak / mse).log10()).min(PSNR_IDENTICAL)
+}
+
+pub fn frame_psnr(a: &FramePixels, b: &FramePixels) -> FramePsnr {
+    assert!(
+        a.width == b.width && a.height == b.height && a.bit_depth == b.bit_depth,
+        "frames must have the same dimensions and bit depth"
+    );
+    let max_value = a.bit_depth.max_value();
+    FramePsnr {
+        y: plane_psnr(&a.y, &b.y, max_value),
+        u: plane_psnr(&a.u, &b.u, max_value),
+        v: plane_psnr(&a.v, &b.v, max_value),
+    }
+}
+
+/// Spherical PSNR of an equirectangular plane (WS-PSNR): each row's error
+/// counts by the cosine of its latitude, so the stretched rows near the
+/// poles weigh as much as the sphere area they cover.
+pub fn plane_ws_psnr(a: &[u16], b: &[u16], width: usize, height: usize, max_value: u16) -> f64 {
+    assert_eq!(a.len(), b.len(), "planes must have the same size");
+    assert_eq!(
+        a.len(),
+        width * height,
+        "plane size must match its dimensions"
+    );
+    if a.is_empty() {
+        return PSNR_IDENTICAL;
+    }
+    let (mut weighted_sse, mut total_weight) = (0.0, 0.0);
+    for (row, (ra, rb)) in a.chunks(width).zip(b.chunks(width)).enumerate() {
+        let latitude =
+            (row as f64 + 0.5 - height as f64 / 2.0) * std::f64::consts::PI / height as f64;
+        let weight = latitude.cos();
+        let sse: u64 = ra
+            .iter()
+            .zip(rb)
+            .map(|(&x, &y)| {
+                let d = x as i64 - y as i64;
+                (d * d) as u64
+            })
+            .sum();
+        weighted_sse += weight * sse as f64;
+        total_weight += weight * width as f64;
+    }
+    if weighted_sse == 0.0 {
+        return PSNR_IDENTICAL;
+    }
+    let peak = max_value as f64;
+    (10.0 * (peak * peak * total_weight / weighted_sse).log10()).min(PSNR_IDENTICAL)
+}
+
+pub fn frame_ws_psnr(a: &FramePixels, b: &FramePixels) -> FramePsnr {
+    assert!(
+        a.width == b.width && a.height == b.height && a.bit_depth == b.bit_depth,
+        "frames must have the same dimensions and bit depth"
+    );
+    let max_value = a.bit_depth.max_value();
+    let (w, h) = (a.width as usize, a.height as usize);
+    let (cw, ch) = (w.div_ceil(2), h.div_ceil(2));
+    FramePsnr {
+        y: plane_ws_psnr(&a.y, &b.y, w, h, max_value),
+        u: plane_ws_psnr(&a.u, &b.u, cw, ch, max_value),
+        v: plane_ws_psnr(&a.v, &b.v, cw, ch, max_value),
+    }
+}
+
+/// Side of the square regions a [`DistortionMap`] measures.
+pub const DISTORTION_MAP_BLOCK: usize = 64;
+
+/// Luma distortion of a coded frame against its source, per 64x64
+/// superblock in raster order. Superblocks along the right and bottom
+/// edges only count the samples inside the frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DistortionMap {
+    /// Display index of the frame.
+    pub frame_number: u64,
+    pub sb_cols: usize,
+    pub sb_rows: usize,
+    /// Sum of squared luma errors of each superblock.
+    pub sse: Vec<u64>,
+    /// Luma samples inside each superblock.
+    pub samples: Vec<u32>,
+    pub max_value: u16,
+}
+
+impl DistortionMap {
+    /// Luma PSNR of each superblock, capped at [`PSNR_IDENTICAL`].
+    pub fn psnr(&self) -> Vec<f64> {
+        self.sse
+            .iter()
+            .zip(&self.samples)
+            .map(|(&sse, &n)| sse_psnr(sse, n as u64, self.max_value))
+            .collect()
+    }
+}
+
+pub fn distortion_map(
+    frame_number: u64,
+    source: &FramePixels,
+    recon: &FramePixels,
+) -> DistortionMap {
+    assert!(
+        source.width == recon.width
+            && source.height == recon.height
+            && source.bit_depth == recon.bit_depth,
+        "frames must have the same dimensions and bit depth"
+    );
+    let (w, h) = (source.width as usize, source.height as usize);
+    let sb_cols = w.div_ceil(DISTORTION_MAP_BLOCK);
+    let sb_rows = h.div_ceil(DISTORTION_MAP_BLOCK);
+    let mut sse = vec![0u64; sb_cols * sb_rows];
+    let mut samples = vec![0u32; sb_cols * sb_rows];
+    for (y, (ra, rb)) in source.y.chunks(w).zip(recon.y.chunks(w)).enumerate() {
+        let row = (y / DISTORTION_MAP_BLOCK) * sb_cols;
+        for (x, (&a, &b)) in ra.iter().zip(rb).enumerate() {
+            let d = a as i64 - b as i64;
+            sse[row + x / DISTORTION_MAP_BLOCK] += (d * d) as u64;
+            samples[row + x / DISTORTION_MAP_BLOCK] += 1;
+        }
+    }
+    DistortionMap {
+        frame_number,
+        sb_cols,
+        sb_rows,
+        sse,
+        samples,
+        max_value: source.bit_depth.max_value(),
+    }
+}
+
+/// SSIM window side and the step between windows, as in libvpx's `vpx_ssim`.
+const SSIM_WINDOW: usize = 8;
+const SSIM_STEP: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameSsim {
+    pub y: f64,
+    pub u: f64,
+    pub v: f64,
+}
+
+impl FrameSsim {
+    /// 6:1:1 luma-weighted average, matching [`FramePsnr::weighted`].
+    pub fn weighted(&self) -> f64 {
+        (6.0 * self.y + self.u + self.v) / 8.0
+    }
+}
+
+/// Mean SSIM over 8x8 windows every 4 samples, with unweighted window
+/// statistics. Planes smaller than a window are scored as one window.
+pub fn plane_ssim(a: &[u16], b: &[u16], width: usize, height: usize, max_value: u16) -> f64 {
+    assert_eq!(a.len(), b.len(), "planes must have the same size");
+    assert_eq!(
+        a.len(),
+        width * height,
+        "plane size must match its dimensions"
+    );
+    if a.is_empty() {
+        return 1.0;
+    }
+    let peak = max_value as f64;
+    let c1 = (0.01 * peak) * (0.01 * peak);
+    let c2 = (0.03 * peak) * (0.03 * peak);
+    let window = |x0: usize, y0: usize, w: usize, h: usize| {
+        let (mut sa, mut sb, mut saa, mut sbb, mut sab) = (0u64, 0u64, 0u64, 0u64, 0u64);
+        for y in y0..y0 + h {
+            for x in x0..x0 + w {
+                let (pa, pb) = (a[y * width + x] as u64, b[y * width + x] as u64);
+                sa += pa;
+                sb += pb;
+                saa += pa * pa;
+                sbb += pb * pb;
+                sab += pa * pb;
+            }
+        }
+        let n = (w * h) as f64;
+        let (ma, mb) = (sa as f64 / n, sb as f64 / n);
+        let va = saa as f64 / n - ma * ma;
+        let vb = sbb as f64 / n - mb * mb;
+        let cov = sab as f64 / n - ma * mb;
+        ((2.0 * ma * mb + c1) * (2.0 * cov + c2)) / ((ma * ma + mb * mb + c1) * (va + vb + c2))
+    };
+    let (w, h) = (SSIM_WINDOW.min(width), SSIM_WINDOW.min(height));
+    let mut total = 0.0;
+    let mut windows = 0u32;
+    for y0 in (0..=height - h).step_by(SSIM_STEP) {
+        for x0 in (0..=width - w).step_by(SSIM_STEP) {
+            total += window(x0, y0, w, h);
+            windows += 1;
+        }
+    }
+    total / windows as f64
+}
+
+pub fn frame_ssim(a: &FramePixels, b: &FramePixels) -> FrameSsim {
+    assert!(
+        a.width == b.width && a.height == b.height && a.bit_depth == b.bit_depth,
+        "frames must have the same dimensions and bit depth"
+    );
+    let max_value = a.bit_depth.max_value();
+    let (w, h) = (a.width as usize, a.height as usize);
+    let (cw, ch) = (w.div_ceil(2), h.div_ceil(2));
+    FrameSsim {
+        y: plane_ssim(&a.y, &b.y, w, h, max_value),
+        u: plane_ssim(&a.u, &b.u, cw, ch, max_value),
+        v: plane_ssim(&a.v, &b.v, cw, ch, max_value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identical_planes_report_the_cap() {
+        assert_eq!(plane_psnr(&[1, 2, 3], &[1, 2, 3], 255), PSNR_IDENTICAL);
+    }
+
+    #[test]
+    fn single_step_error_matches_closed_form() {
+        let psnr = plane_psnr(&[10, 10], &[11, 11], 255);
+        assert!((psnr - 20.0 * 255f64.log10()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn distortion_map_splits_luma_error_by_superblock() {
+        let source = FramePixels::solid(100, 70, 100, 128, 128);
+        let mut recon = source.clone();
+        // One sample off by 2 in the bottom-right superblock (36x6 samples).
+        recon.y[69 * 100 + 99] = 102;
+        recon.u[0] = 0;
+        let map = distortion_map(7, &source, &recon);
+        assert_eq!((map.frame_number, map.sb_cols, map.sb_rows), (7, 2, 2));
+        assert_eq!(map.sse, [0, 0, 0, 4]);
+        assert_eq!(map.samples, [64 * 64, 36 * 64, 64 * 6, 36 * 6]);
+        let psnr = map.psnr();
+        assert_eq!(psnr[..3], [PSNR_IDENTICAL; 3]);
+        let expected = 10.0 * (255.0f64 * 255.0 / (4.0 / 216.0)).log10();
+        assert!((psnr[3] - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn weighted_favors_luma() {
+        let psnr = FramePsnr {
+            y: 40.0,
+            u: 32.0,
+            v: 32.0,
+        };
+        assert_eq!(psnr.weighted(), 38.0);
+    }
+
+    #[test]
+    fn ws_psnr_discounts_errors_near_the_poles() {
+        let a = vec![100u16; 8 * 8];
+        let mut polar = a.clone();
+        polar[0] = 110;
+        let mut equator = a.clone();
+        equator[4 * 8] = 110;
+        let flat = plane_psnr(&a, &polar, 255);
+        assert_eq!(flat, plane_psnr(&a, &equator, 255));
+        assert!(plane_ws_psnr(&a, &polar, 8, 8, 255) > flat);
+        assert!(plane_ws_psnr(&a, &equator, 8, 8, 255) < flat);
+        assert_eq!(plane_ws_psnr(&a, &a, 8, 8, 255), PSNR_IDENTICAL);
+    }
+
+    #[test]
+    fn ssim_is_one_for_identical_planes_and_drops_with_noise() {
+        let a: Vec<u16> = (0..16 * 12).map(|i| (i * 7 % 200) as u16).collect();
+        assert!((plane_ssim(&a, &a, 16, 12, 255) - 1.0).abs() < 1e-12);
+        let noisy: Vec<u16> = a
+            .iter()
+            .enumerate()
+            .map(|(i, &s)| s + (i % 5) as u16 * 4)
+            .collect();
+        let heavy: Vec<u16> = a
+            .iter()
+            .enumerate()
+            .map(|(i, &s)| s + (i % 5) as u16 * 12)
+            .collect();
+        let light = plane_ssim(&a, &noisy, 16, 12, 255);
+        assert!(light < 1.0);
+        assert!(plane_ssim(&a, &heavy, 16, 12, 255) < light);
+    }
+
+    #[test]
+    fn ssim_scores_planes_smaller_than_a_window() {
+        let a = [10u16, 20, 30, 40, 50, 60];
+        assert!((plane_ssim(&a, &a, 3, 2, 255) - 1.0).abs() < 1e-12);
+        assert!(plane_ssim(&a, &[60, 50, 40, 30, 20, 10], 3, 2, 255) < 0.0);
+    }
+}
@@ -0,0 +1,114 @@
+//! Research hook on intra mode decisions, behind the `mode-hook` feature.
+//!
+//! An [`IntraModeHook`] sees the candidate luma modes of every intra block
+//! after they are ranked by SATD and may reorder or drop them, e.g. to try a
+//! learned mode chooser. The encoder then gives the first
+//! `EarlyExit::intra_rd_candidates` survivors an exact RD cost and codes the
+//! cheapest, so any order the hook returns still produces a valid stream.
+//! Without the feature none of this is compiled and the search is unchanged.
+
+/// Source and neighbouring samples of a luma block being mode-searched.
+#[derive(Debug, Clone, Copy)]
+pub struct IntraBlock<'a> {
+    /// Source samples, `width * height` in raster order.
+    pub source: &'a [u16],
+    /// Reconstructed row above the block (edge-extended where unavailable).
+    pub above: &'a [u16],
+    /// Reconstructed column left of the block (edge-extended likewise).
+    pub left: &'a [u16],
+    pub have_above: bool,
+    pub have_left: bool,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// One candidate luma mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IntraCandidate {
+    /// AV1 `y_mode`: 0 DC, 1 V, 2 H, 9 SMOOTH, 10 SMOOTH_V, 11 SMOOTH_H or
+    /// 12 PAETH.
+    pub mode: u8,
+    /// SATD of the prediction plus its estimated mode cost.
+    pub cost: u64,
+}
+
+/// Reorders or vetoes intra mode candidates per block.
+pub trait IntraModeHook: Send + Sync {
+    /// `candidates` arrive in ascending `cost` order. Entries removed are not
+    /// coded; entries added with a mode that was not offered are ignored. An
+    /// empty list falls back to DC. Blocks that the speed setting codes as DC
+    /// before any search are not offered.
+    fn rank(&self, block: &IntraBlock<'_>, candidates: &mut Vec<IntraCandidate>);
+}
+
+/// A registered hook, compared by address so [`crate::tile::EarlyExit`]
+/// keeps its `Copy` and `Eq`.
+#[derive(Clone, Copy)]
+pub struct IntraModeHookRef(pub &'static dyn IntraModeHook);
+
+impl std::fmt::Debug for IntraModeHookRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("IntraModeHook")
+    }
+}
+
+impl PartialEq for IntraModeHookRef {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::addr_eq(self.0, other.0)
+    }
+}
+
+impl Eq for IntraModeHookRef {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::y4m::FramePixels;
+    use crate::{EncodeConfig, Encoder, EncoderConfig};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct Keep {
+        only_dc: bool,
+        calls: AtomicUsize,
+    }
+
+    impl IntraModeHook for Keep {
+        fn rank(&self, block: &IntraBlock<'_>, candidates: &mut Vec<IntraCandidate>) {
+            assert_eq!(block.source.len(), block.width * block.height);
+            assert!(candidates.is_sorted_by_key(|c| c.cost));
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            if self.only_dc {
+                candidates.retain(|c| c.mode == 0);
+            }
+        }
+    }
+
+    fn encode(hook: Option<&'static Keep>) -> Vec<u8> {
+        let mut frame = FramePixels::solid(64, 64, 0, 128, 128);
+        for (i, s) in frame.y.iter_mut().enumerate() {
+            let (x, y) = (i as u32 % 64, i as u32 / 64);
+            *s = ((x * 7 + y * 13 + (x * y) % 31) % 200 + 20) as u16;
+        }
+        let config = EncoderConfig::from(&EncodeConfig::default());
+        let mut enc = Encoder::new(64, 64, config).unwrap();
+        enc.set_intra_mode_hook(hook.map(|h| h as &'static dyn IntraModeHook));
+        enc.send_frame(&frame).unwrap();
+        enc.receive_packet().unwrap().data
+    }
+
+    #[test]
+    fn hook_sees_every_search_and_can_veto_modes() {
+        let keep_all: &'static Keep = Box::leak(Box::new(Keep {
+            only_dc: false,
+            calls: AtomicUsize::new(0),
+        }));
+        let only_dc: &'static Keep = Box::leak(Box::new(Keep {
+            only_dc: true,
+            calls: AtomicUsize::new(0),
+        }));
+        let builtin = encode(None);
+        assert_eq!(encode(Some(keep_all)), builtin);
+        assert!(keep_all.calls.load(Ordering::Relaxed) > 0);
+        assert_ne!(encode(Some(only_dc)), builtin);
+    }
+}
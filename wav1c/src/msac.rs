@@ -1,8 +1,22 @@
+//! Multi-symbol arithmetic encoder, the counterpart of the AV1 symbol
+//! decoder (spec section 8.2) and dav1d's `msac`. Output bytes are
+//! assembled from the 16-bit precarry words with shifts, never by
+//! reinterpreting memory, so the stream is the same on any host byte order.
+
 const EC_PROB_SHIFT: u32 = 6;
 const EC_MIN_PROB: u32 = 4;
 
 type EcWindow = u32;
 
+/// Encoder state to return to after a trial encode.
+#[derive(Debug, Clone, Copy)]
+pub struct MsacCheckpoint {
+    low: EcWindow,
+    rng: u16,
+    cnt: i16,
+    len: usize,
+}
+
 pub struct MsacEncoder {
     low: EcWindow,
     rng: u16,
@@ -22,6 +36,29 @@ impl MsacEncoder {
         }
     }
 
+    pub fn checkpoint(&self) -> MsacCheckpoint {
+        MsacCheckpoint {
+            low: self.low,
+            rng: self.rng,
+            cnt: self.cnt,
+            len: self.precarry.len(),
+        }
+    }
+
+    /// Forgets everything coded since `checkpoint`, CDFs excepted.
+    pub fn rollback(&mut self, checkpoint: MsacCheckpoint) {
+        self.low = checkpoint.low;
+        self.rng = checkpoint.rng;
+        self.cnt = checkpoint.cnt;
+        self.precarry.truncate(checkpoint.len);
+    }
+
+    /// Bits coded so far in 1/256 bit units, up to a constant offset.
+    pub fn tell_q8(&self) -> u64 {
+        let whole = 8 * self.precarry.len() as u64 + (self.cnt + 25) as u64;
+        (whole << 8) - ((self.rng as u64 - 0x8000) >> 7)
+    }
+
     fn compute_bounds(&self, fl: u16, fh: u16, nms: u16) -> (EcWindow, u16) {
         let r = self.rng as u32;
         let mut u = (((r >> 8) * ((fl as u32) >> EC_PROB_SHIFT)) >> (7 - EC_PROB_SHIFT))
@@ -568,4 +605,61 @@ mod tests {
         assert_eq!(cdf3_enc, cdf3_dec, "CDF3 mismatch");
         assert_eq!(cdf_bool_enc, cdf_bool_dec, "CDF bool mismatch");
     }
+
+    #[test]
+    fn rollback_forgets_the_trial() {
+        let (mut plain_cdf, mut tried_cdf) = ([20000u16, 8000, 0], [20000u16, 8000, 0]);
+        let mut plain = MsacEncoder::new();
+        let mut tried = MsacEncoder::new();
+        for i in 0..40u32 {
+            plain.encode_symbol(i % 3, &mut plain_cdf, 2);
+            tried.encode_symbol(i % 3, &mut tried_cdf, 2);
+        }
+        let before = tried.tell_q8();
+        let checkpoint = tried.checkpoint();
+        for _ in 0..100 {
+            tried.encode_bool_equi(true);
+        }
+        assert!((tried.tell_q8() - before).abs_diff(100 << 8) < 256);
+        tried.rollback(checkpoint);
+        assert_eq!(tried.tell_q8(), before);
+        for enc in [&mut plain, &mut tried] {
+            enc.encode_golomb(9);
+        }
+        assert_eq!(plain.finalize(), tried.finalize());
+    }
+
+    /// The coded bytes for a fixed mix of symbols, adaptive and equiprobable
+    /// bools and Exp-Golomb values, with enough ones to ripple carries back
+    /// through several bytes.
+    fn mixed_sequence() -> Vec<u8> {
+        let mut enc = MsacEncoder::new();
+        let mut cdf = [24576u16, 16384, 8192, 0];
+        let mut bool_cdf = [16384u16, 0];
+        for i in 0..64u32 {
+            enc.encode_symbol(i * 7 % 4 % 3, &mut cdf, 3);
+            enc.encode_bool(i % 5 != 0, &mut bool_cdf);
+            enc.encode_bool_equi(i % 3 == 0);
+            if i % 8 == 0 {
+                enc.encode_golomb(i * 37);
+            }
+        }
+        for _ in 0..24 {
+            enc.encode_bool_equi(true);
+        }
+        enc.finalize()
+    }
+
+    #[test]
+    fn output_bytes_do_not_depend_on_host_byte_order() {
+        // Pinned on little-endian hosts; CI checks the same bytes on s390x.
+        assert_eq!(
+            mixed_sequence(),
+            [
+                25, 138, 11, 3, 253, 111, 164, 97, 214, 111, 64, 120, 54, 110, 124, 37, 220, 151,
+                19, 112, 220, 47, 102, 254, 245, 172, 138, 89, 147, 10, 90, 94, 217, 206, 77, 253,
+                36, 85, 159, 203, 29, 5, 160, 89, 228, 189, 135, 127, 255, 160,
+            ]
+        );
+    }
 }
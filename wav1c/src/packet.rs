@@ -1,12 +1,223 @@
+use std::ops::Range;
+
+/// What a packet's frame is, from its frame header.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum FrameType {
+    /// KEY_FRAME: resets every reference; decoding can start here.
     Key,
+    /// INTER_FRAME predicted from earlier references.
     Inter,
+    /// INTRA_ONLY_FRAME: coded without prediction, but the decoder must
+    /// already hold the references that later frames use.
+    IntraOnly,
+    /// SWITCH_FRAME (S-frame), for switching between streams.
+    Switch,
+    /// `show_existing_frame` header displaying a frame decoded earlier.
+    ShowExisting,
+    /// Hidden KEY_FRAME of an open GOP, followed by the first of its leading
+    /// frames. Decoding can start here if the leading frames are dropped,
+    /// up to the `ShowExisting` packet that displays the keyframe.
+    ForwardKey,
+}
+
+impl FrameType {
+    /// Whether decoding can start at this packet: the sync samples of MP4
+    /// and the points an IVF writer may resume from.
+    pub fn is_sync(&self) -> bool {
+        matches!(self, FrameType::Key)
+    }
+
+    /// Short upper-case name for logs.
+    pub fn name(&self) -> &'static str {
+        match self {
+            FrameType::Key => "KEY",
+            FrameType::Inter => "INTER",
+            FrameType::IntraOnly => "INTRA_ONLY",
+            FrameType::Switch => "SWITCH",
+            FrameType::ShowExisting => "SHOW_EXISTING",
+            FrameType::ForwardKey => "FORWARD_KEY",
+        }
+    }
+}
+
+/// One frame of a packet's temporal unit: a coded frame, or the
+/// `show_existing_frame` header displaying one decoded earlier.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacketFrame {
+    /// Its OBUs in `Packet::data`, after the temporal delimiter, sequence
+    /// header and metadata OBUs that start the temporal unit.
+    pub range: Range<usize>,
+    pub frame_type: FrameType,
+    /// Display index of the frame.
+    pub frame_number: u64,
+    /// `show_frame`: displayed once decoded. Hidden frames (the forward
+    /// reference of a B-frame mini-GOP) are shown later by a
+    /// `ShowExisting` frame.
+    pub shown: bool,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Packet {
     pub data: Vec<u8>,
     pub frame_type: FrameType,
     pub frame_number: u64,
+    /// A later frame may predict from a frame in this packet. Clear for
+    /// B-frames and for `ShowExisting` packets displaying a non-key frame:
+    /// a player can drop those and every other frame still decodes.
+    pub is_reference: bool,
+    /// The packet ends with a shown frame, the one `frame_number` and `pts`
+    /// describe. Clear for a packet holding only a hidden frame, as at the
+    /// end of a stream without B-frames to carry it: an IVF frame or MP4
+    /// sample must hold exactly one shown frame, so containers store it
+    /// with the next packet ([`TemporalUnitGrouper`]).
+    pub shown: bool,
+    /// The frames in `data`, in decode order.
+    pub frames: Vec<PacketFrame>,
+    /// Opaque value passed to `Encoder::send_frame_with_user_data` for the
+    /// frame this packet displays. Survives B-frame reordering.
+    pub user_data: Option<u64>,
+    /// Set on the first keyframe coded after `Encoder::reconfigure` changed
+    /// the sequence header: the new sequence header OBU, for muxers that
+    /// keep it in extradata (e.g. the `av1C` box).
+    pub sequence_header: Option<Vec<u8>>,
+    /// More packets of the same frame follow: with `frame_slices`, each
+    /// tile row is emitted as soon as it is coded and only the last one is
+    /// not partial.
+    pub partial: bool,
+    /// Presentation time of the displayed frame in frame periods of the
+    /// configured `Fps`, mapped from its capture timestamp by
+    /// [`crate::CaptureClock`]. Equal to `frame_number` while no capture
+    /// timestamps are given.
+    pub pts: u64,
+    /// Capture timestamp given in `FrameOptions::capture_time_ns` for the
+    /// displayed frame.
+    pub capture_time_ns: Option<u64>,
+}
+
+/// Holds back packets with no shown frame and joins each to the next shown
+/// packet, so every packet it returns is one temporal unit with one shown
+/// frame, as IVF frames and MP4 samples must be.
+#[derive(Debug, Default)]
+pub struct TemporalUnitGrouper {
+    hidden: Option<Packet>,
+}
+
+impl TemporalUnitGrouper {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The temporal unit `packet` completes, if it is shown. A shown packet
+    /// after hidden ones keeps its timing, user data and frame type, except
+    /// that a hidden `ForwardKey` or a `ShowExisting` packet takes the
+    /// hidden frame's type.
+    pub fn push(&mut self, packet: Packet) -> Option<Packet> {
+        let packet = match self.hidden.take() {
+            Some(hidden) => join(hidden, packet),
+            None => packet,
+        };
+        if packet.shown {
+            Some(packet)
+        } else {
+            self.hidden = Some(packet);
+            None
+        }
+    }
+
+    /// A hidden packet left over at the end of the stream. The encoder
+    /// always shows its hidden frames, so this is `None` after a flush.
+    pub fn finish(self) -> Option<Packet> {
+        self.hidden
+    }
+
+    /// A whole stream's `packets`, e.g. from `encode_packets`, as temporal
+    /// units.
+    pub fn group(packets: Vec<Packet>) -> Vec<Packet> {
+        let mut grouper = Self::new();
+        let mut units: Vec<Packet> = packets
+            .into_iter()
+            .filter_map(|packet| grouper.push(packet))
+            .collect();
+        units.extend(grouper.finish());
+        units
+    }
+}
+
+/// `hidden` followed by the frames of `next`, as one temporal unit. The
+/// temporal delimiter and headers starting `next` are dropped: those of
+/// `hidden` come first, and AV1 allows them only there.
+fn join(mut hidden: Packet, mut next: Packet) -> Packet {
+    let skip = next.frames.first().map_or(0, |frame| frame.range.start);
+    let offset = hidden.data.len();
+    hidden.data.extend_from_slice(&next.data[skip..]);
+    for frame in &mut next.frames {
+        frame.range = frame.range.start + offset - skip..frame.range.end + offset - skip;
+    }
+    hidden.frames.append(&mut next.frames);
+    if next.frame_type == FrameType::ShowExisting || hidden.frame_type == FrameType::ForwardKey {
+        next.frame_type = hidden.frame_type;
+    }
+    Packet {
+        data: hidden.data,
+        frames: hidden.frames,
+        is_reference: hidden.is_reference || next.is_reference,
+        sequence_header: hidden.sequence_header.or(next.sequence_header),
+        ..next
+    }
+}
+
+/// Most buffers the packet pool keeps; recycled packets beyond it are
+/// dropped.
+pub const PACKET_POOL_CAPACITY: usize = 16;
+
+/// Packet buffer reuse, from [`crate::Encoder::packet_pool_stats`]. Once
+/// every received packet goes back through `Encoder::recycle_packet`,
+/// `allocated` stops growing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PacketPoolStats {
+    /// Packets started in a new buffer because the pool was empty.
+    pub allocated: u64,
+    /// Packets started in a buffer taken from the pool.
+    pub reused: u64,
+    /// Buffers waiting in the pool.
+    pub pooled: usize,
+}
+
+/// Emptied packet buffers, handed out again with their capacity intact.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PacketPool {
+    buffers: Vec<Vec<u8>>,
+    allocated: u64,
+    reused: u64,
+}
+
+impl PacketPool {
+    pub(crate) fn take(&mut self) -> Vec<u8> {
+        match self.buffers.pop() {
+            Some(buffer) => {
+                self.reused += 1;
+                buffer
+            }
+            None => {
+                self.allocated += 1;
+                Vec::new()
+            }
+        }
+    }
+
+    pub(crate) fn give(&mut self, mut buffer: Vec<u8>) {
+        if buffer.capacity() > 0 && self.buffers.len() < PACKET_POOL_CAPACITY {
+            buffer.clear();
+            self.buffers.push(buffer);
+        }
+    }
+
+    pub(crate) fn stats(&self) -> PacketPoolStats {
+        PacketPoolStats {
+            allocated: self.allocated,
+            reused: self.reused,
+            pooled: self.buffers.len(),
+        }
+    }
 }
@@ -0,0 +1,469 @@
+//! Input layouts other than planar 4:2:0, converted into [`FramePixels`].
+
+use crate::error::EncoderError;
+use crate::video::{BitDepth, ColorRange, VideoSignal};
+use crate::y4m::FramePixels;
+
+/// 8-bit 4:2:0 with a luma plane followed by one plane of interleaved
+/// chroma pairs, as delivered by most capture hardware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SemiPlanarFormat {
+    /// Chroma pairs stored U then V.
+    Nv12,
+    /// Chroma pairs stored V then U (Android camera default).
+    Nv21,
+}
+
+/// Byte order of 8-bit packed RGB pixels, four bytes each. Alpha is
+/// ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RgbFormat {
+    Rgba,
+    /// Windows and most GPU readback surfaces.
+    Bgra,
+}
+
+impl RgbFormat {
+    /// Byte offsets of red, green and blue within a pixel.
+    fn offsets(self) -> [usize; 3] {
+        match self {
+            RgbFormat::Rgba => [0, 1, 2],
+            RgbFormat::Bgra => [2, 1, 0],
+        }
+    }
+}
+
+/// A frame layout the encoder accepts, for callers negotiating formats
+/// (GStreamer caps, FFmpeg `pix_fmts`) instead of hardcoding them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// 8-bit planar 4:2:0.
+    I420,
+    /// 10-bit planar 4:2:0, one `u16` per sample.
+    I420P10,
+    /// See [`SemiPlanarFormat::Nv12`].
+    Nv12,
+    /// See [`SemiPlanarFormat::Nv21`].
+    Nv21,
+    /// See [`RgbFormat::Rgba`].
+    Rgba,
+    /// See [`RgbFormat::Bgra`].
+    Bgra,
+}
+
+impl PixelFormat {
+    pub const ALL: [PixelFormat; 6] = [
+        PixelFormat::I420,
+        PixelFormat::I420P10,
+        PixelFormat::Nv12,
+        PixelFormat::Nv21,
+        PixelFormat::Rgba,
+        PixelFormat::Bgra,
+    ];
+
+    /// The FFmpeg `pix_fmt` name.
+    pub fn name(self) -> &'static str {
+        match self {
+            PixelFormat::I420 => "yuv420p",
+            PixelFormat::I420P10 => "yuv420p10le",
+            PixelFormat::Nv12 => "nv12",
+            PixelFormat::Nv21 => "nv21",
+            PixelFormat::Rgba => "rgba",
+            PixelFormat::Bgra => "bgra",
+        }
+    }
+
+    /// Bits per input sample.
+    pub fn bit_depth(self) -> BitDepth {
+        match self {
+            PixelFormat::I420P10 => BitDepth::Ten,
+            _ => BitDepth::Eight,
+        }
+    }
+
+    /// Horizontal and vertical chroma subsampling shifts, or `None` for
+    /// RGB, which the encoder subsamples itself.
+    pub fn chroma_subsampling(self) -> Option<(u8, u8)> {
+        match self {
+            PixelFormat::Rgba | PixelFormat::Bgra => None,
+            _ => Some((1, 1)),
+        }
+    }
+
+    pub fn is_rgb(self) -> bool {
+        self.chroma_subsampling().is_none()
+    }
+}
+
+/// The formats an encoder for `signal` accepts: YUV at exactly the signal's
+/// bit depth, and RGB whenever the signal's matrix can convert it.
+pub fn supported_input_formats(signal: &VideoSignal) -> Vec<PixelFormat> {
+    let rgb_ok = luma_weights(signal).is_ok();
+    PixelFormat::ALL
+        .into_iter()
+        .filter(|f| {
+            if f.is_rgb() {
+                rgb_ok
+            } else {
+                f.bit_depth() == signal.bit_depth
+            }
+        })
+        .collect()
+}
+
+/// Fractional bits of the RGB to YUV coefficients.
+const RGB_SHIFT: u32 = 16;
+
+/// Luma weights of red and blue, in 1/10000, for the matrices RGB input
+/// can be converted with. Unspecified (or no colour description) converts
+/// as BT.601, which is what players assume for untagged streams.
+fn luma_weights(signal: &VideoSignal) -> Result<(i64, i64), EncoderError> {
+    let matrix = signal
+        .color_description
+        .map_or(2, |d| d.matrix_coefficients);
+    match matrix {
+        1 => Ok((2126, 722)),
+        2 | 5 | 6 => Ok((2990, 1140)),
+        4 => Ok((3000, 1100)),
+        7 => Ok((2120, 870)),
+        9 => Ok((2627, 593)),
+        _ => Err(EncoderError::UnsupportedMatrix {
+            matrix_coefficients: matrix,
+        }),
+    }
+}
+
+fn div_round(n: i64, d: i64) -> i64 {
+    (2 * n + n.signum() * d) / (2 * d)
+}
+
+/// Fixed-point rows mapping 8-bit `[r, g, b]` to Y, Cb and Cr without
+/// their offsets, scaled to the signal's bit depth and range.
+fn rgb_to_yuv_matrix(signal: &VideoSignal) -> Result<[[i64; 3]; 3], EncoderError> {
+    let (kr, kb) = luma_weights(signal)?;
+    let kg = 10_000 - kr - kb;
+    let extra = signal.bit_depth.bits() as i64 - 8;
+    let (y_scale, c_scale) = match signal.color_range {
+        ColorRange::Limited => (219 << extra, 224 << extra),
+        ColorRange::Full => {
+            let max = signal.bit_depth.max_value() as i64;
+            (max, max)
+        }
+    };
+    let one = 1i64 << RGB_SHIFT;
+    let luma = |k: i64| div_round(y_scale * k * one, 255 * 10_000);
+    let cb_den = 255 * 2 * (10_000 - kb);
+    let cr_den = 255 * 2 * (10_000 - kr);
+    let half = div_round(c_scale * one, 2 * 255);
+    Ok([
+        [luma(kr), luma(kg), luma(kb)],
+        [
+            -div_round(c_scale * kr * one, cb_den),
+            -div_round(c_scale * kg * one, cb_den),
+            half,
+        ],
+        [
+            half,
+            -div_round(c_scale * kg * one, cr_den),
+            -div_round(c_scale * kb * one, cr_den),
+        ],
+    ])
+}
+
+impl FramePixels {
+    /// Builds a frame from packed 8-bit RGB pixels, `height` rows of
+    /// `width` pixels `stride` bytes apart (0 means packed). The samples are
+    /// converted with the matrix, range and bit depth of `signal`, and each
+    /// 2x2 block's chroma is averaged down to 4:2:0.
+    pub fn from_rgba(
+        format: RgbFormat,
+        width: u32,
+        height: u32,
+        data: &[u8],
+        stride: usize,
+        signal: &VideoSignal,
+    ) -> Result<Self, EncoderError> {
+        if width == 0 || height == 0 {
+            return Err(EncoderError::InvalidDimensions { width, height });
+        }
+        let [m_y, m_cb, m_cr] = rgb_to_yuv_matrix(signal)?;
+        let (w, h) = (width as usize, height as usize);
+        let (cw, ch) = (w.div_ceil(2), h.div_ceil(2));
+        let stride = plane_stride("rgba", stride, 4 * w, h, data.len())?;
+
+        let bit_depth = signal.bit_depth;
+        let max = bit_depth.max_value() as i64;
+        let extra = bit_depth.bits() - 8;
+        let (y_offset, c_offset) = match signal.color_range {
+            ColorRange::Limited => (16i64 << extra, bit_depth.mid_value() as i64),
+            ColorRange::Full => (0, bit_depth.mid_value() as i64),
+        };
+        let round = 1i64 << (RGB_SHIFT - 1);
+        let dot = |m: &[i64; 3], rgb: [i64; 3]| m[0] * rgb[0] + m[1] * rgb[1] + m[2] * rgb[2];
+
+        let [r_at, g_at, b_at] = format.offsets();
+        let mut y_plane = Vec::with_capacity(w * h);
+        let mut cb_sums = vec![0i64; cw * ch];
+        let mut cr_sums = vec![0i64; cw * ch];
+        for (row, line) in data.chunks(stride).take(h).enumerate() {
+            let sums_row = row / 2 * cw;
+            for (col, px) in line[..4 * w].chunks_exact(4).enumerate() {
+                let rgb = [px[r_at] as i64, px[g_at] as i64, px[b_at] as i64];
+                let luma = y_offset + ((dot(&m_y, rgb) + round) >> RGB_SHIFT);
+                y_plane.push(luma.clamp(0, max) as u16);
+                cb_sums[sums_row + col / 2] += dot(&m_cb, rgb);
+                cr_sums[sums_row + col / 2] += dot(&m_cr, rgb);
+            }
+        }
+
+        let mut u_plane = Vec::with_capacity(cw * ch);
+        let mut v_plane = Vec::with_capacity(cw * ch);
+        for (i, (&cb, &cr)) in cb_sums.iter().zip(&cr_sums).enumerate() {
+            let (col, row) = (i % cw, i / cw);
+            let count = ((w - 2 * col).min(2) * (h - 2 * row).min(2)) as i64;
+            let chroma = |sum: i64| {
+                let value = c_offset + div_round(sum, count << RGB_SHIFT);
+                value.clamp(0, max) as u16
+            };
+            u_plane.push(chroma(cb));
+            v_plane.push(chroma(cr));
+        }
+
+        Ok(Self {
+            y: y_plane,
+            u: u_plane,
+            v: v_plane,
+            width,
+            height,
+            bit_depth,
+            color_range: signal.color_range,
+        })
+    }
+
+    /// Builds an 8-bit frame from a semi-planar buffer. `y` holds `height`
+    /// rows of `width` samples `y_stride` bytes apart; `uv` holds
+    /// `ceil(height / 2)` rows of `ceil(width / 2)` chroma pairs
+    /// `uv_stride` bytes apart. A stride of 0 means rows are packed.
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_semi_planar(
+        format: SemiPlanarFormat,
+        width: u32,
+        height: u32,
+        y: &[u8],
+        y_stride: usize,
+        uv: &[u8],
+        uv_stride: usize,
+        color_range: ColorRange,
+    ) -> Result<Self, EncoderError> {
+        if width == 0 || height == 0 {
+            return Err(EncoderError::InvalidDimensions { width, height });
+        }
+        let (w, h) = (width as usize, height as usize);
+        let (cw, ch) = (w.div_ceil(2), h.div_ceil(2));
+        let y_stride = plane_stride("y", y_stride, w, h, y.len())?;
+        let uv_stride = plane_stride("uv", uv_stride, 2 * cw, ch, uv.len())?;
+
+        let y_plane = y
+            .chunks(y_stride)
+            .take(h)
+            .flat_map(|row| row[..w].iter().map(|&s| s as u16))
+            .collect();
+        let (u_offset, v_offset) = match format {
+            SemiPlanarFormat::Nv12 => (0, 1),
+            SemiPlanarFormat::Nv21 => (1, 0),
+        };
+        let mut u_plane = Vec::with_capacity(cw * ch);
+        let mut v_plane = Vec::with_capacity(cw * ch);
+        for row in uv.chunks(uv_stride).take(ch) {
+            for pair in row[..2 * cw].chunks_exact(2) {
+                u_plane.push(pair[u_offset] as u16);
+                v_plane.push(pair[v_offset] as u16);
+            }
+        }
+
+        Ok(Self {
+            y: y_plane,
+            u: u_plane,
+            v: v_plane,
+            width,
+            height,
+            bit_depth: BitDepth::Eight,
+            color_range,
+        })
+    }
+}
+
+/// The row pitch of a plane of `rows` rows of `row_len` samples, checking
+/// that a buffer of `len` holds them. The last row needs no padding.
+fn plane_stride(
+    plane: &'static str,
+    stride: usize,
+    row_len: usize,
+    rows: usize,
+    len: usize,
+) -> Result<usize, EncoderError> {
+    let stride = if stride == 0 { row_len } else { stride };
+    if stride < row_len {
+        return Err(EncoderError::InvalidStride {
+            plane,
+            stride,
+            min: row_len,
+        });
+    }
+    let expected = (rows - 1) * stride + row_len;
+    if len < expected {
+        return Err(EncoderError::PlaneSizeMismatch {
+            plane,
+            expected,
+            got: len,
+        });
+    }
+    Ok(stride)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn semi_planar_chroma_is_deinterleaved_in_order() {
+        // 3x3 luma with a padded stride, 2x2 chroma pairs.
+        let y = [1, 2, 3, 0, 4, 5, 6, 0, 7, 8, 9];
+        let uv = [10, 20, 11, 21, 12, 22, 13, 23];
+        let nv12 = FramePixels::from_semi_planar(
+            SemiPlanarFormat::Nv12,
+            3,
+            3,
+            &y,
+            4,
+            &uv,
+            0,
+            ColorRange::Full,
+        )
+        .unwrap();
+        assert_eq!(nv12.y, [1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        assert_eq!(nv12.u, [10, 11, 12, 13]);
+        assert_eq!(nv12.v, [20, 21, 22, 23]);
+
+        let nv21 = FramePixels::from_semi_planar(
+            SemiPlanarFormat::Nv21,
+            3,
+            3,
+            &y,
+            4,
+            &uv,
+            0,
+            ColorRange::Full,
+        )
+        .unwrap();
+        assert_eq!((nv21.u, nv21.v), (nv12.v, nv12.u));
+    }
+
+    #[test]
+    fn semi_planar_rejects_short_planes_and_strides() {
+        let y = [0u8; 16];
+        let uv = [128u8; 8];
+        let build = |y_stride, uv: &[u8]| {
+            FramePixels::from_semi_planar(
+                SemiPlanarFormat::Nv12,
+                4,
+                4,
+                &y,
+                y_stride,
+                uv,
+                0,
+                ColorRange::Limited,
+            )
+        };
+        assert!(build(0, &uv).is_ok());
+        assert!(matches!(
+            build(3, &uv),
+            Err(EncoderError::InvalidStride { plane: "y", .. })
+        ));
+        assert!(matches!(
+            build(0, &uv[..7]),
+            Err(EncoderError::PlaneSizeMismatch {
+                plane: "uv",
+                expected: 8,
+                got: 7
+            })
+        ));
+    }
+
+    #[test]
+    fn rgba_primaries_land_on_their_nominal_yuv_values() {
+        let pixels = |rgb: [u8; 3]| [rgb[0], rgb[1], rgb[2], 255].repeat(4);
+        let convert = |rgb, signal: &VideoSignal| {
+            let frame =
+                FramePixels::from_rgba(RgbFormat::Rgba, 2, 2, &pixels(rgb), 0, signal).unwrap();
+            (frame.y[0], frame.u[0], frame.v[0])
+        };
+
+        let sdr = VideoSignal::sdr709();
+        assert_eq!(convert([0, 0, 0], &sdr), (16, 128, 128));
+        assert_eq!(convert([255, 255, 255], &sdr), (235, 128, 128));
+        // BT.709 red: Y 63, Cb 102, Cr 240.
+        assert_eq!(convert([255, 0, 0], &sdr), (63, 102, 240));
+
+        let full = VideoSignal::srgb_full();
+        assert_eq!(convert([255, 255, 255], &full), (255, 128, 128));
+        // BT.601 blue: Cb at the top of the range.
+        assert_eq!(convert([0, 0, 255], &full).1, 255);
+
+        let hdr = VideoSignal::hdr10(ColorRange::Limited);
+        assert_eq!(convert([255, 255, 255], &hdr), (940, 512, 512));
+    }
+
+    #[test]
+    fn rgba_chroma_averages_each_block_and_reads_bgra_order() {
+        // 3x1: red and blue share a chroma sample, black is alone at the edge.
+        let signal = VideoSignal::sdr709();
+        let rgba = [255, 0, 0, 0, 0, 0, 255, 0, 0, 0, 0, 0];
+        let frame = FramePixels::from_rgba(RgbFormat::Rgba, 3, 1, &rgba, 0, &signal).unwrap();
+        assert_eq!(frame.u.len(), 2);
+        // Red (102, 240) and blue (240, 118) meet halfway.
+        assert_eq!((frame.u[0], frame.v[0]), (171, 179));
+        assert_eq!((frame.u[1], frame.v[1]), (128, 128));
+
+        let bgra: Vec<u8> = rgba
+            .chunks(4)
+            .flat_map(|p| [p[2], p[1], p[0], p[3]])
+            .collect();
+        let swapped = FramePixels::from_rgba(RgbFormat::Bgra, 3, 1, &bgra, 0, &signal).unwrap();
+        assert_eq!((swapped.y, swapped.u), (frame.y, frame.u));
+
+        let mut ycgco = signal;
+        ycgco
+            .color_description
+            .as_mut()
+            .unwrap()
+            .matrix_coefficients = 8;
+        assert!(matches!(
+            FramePixels::from_rgba(RgbFormat::Rgba, 3, 1, &rgba, 0, &ycgco),
+            Err(EncoderError::UnsupportedMatrix {
+                matrix_coefficients: 8
+            })
+        ));
+    }
+
+    #[test]
+    fn supported_formats_follow_bit_depth_and_matrix() {
+        use PixelFormat::*;
+        assert_eq!(
+            supported_input_formats(&VideoSignal::sdr709()),
+            [I420, Nv12, Nv21, Rgba, Bgra]
+        );
+        let hdr = VideoSignal::hdr10(ColorRange::Limited);
+        assert_eq!(supported_input_formats(&hdr), [I420P10, Rgba, Bgra]);
+
+        let mut ictcp = hdr;
+        ictcp
+            .color_description
+            .as_mut()
+            .unwrap()
+            .matrix_coefficients = 14;
+        assert_eq!(supported_input_formats(&ictcp), [I420P10]);
+        assert_eq!(Nv12.name(), "nv12");
+        assert_eq!(I420P10.chroma_subsampling(), Some((1, 1)));
+    }
+}
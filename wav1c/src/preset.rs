@@ -0,0 +1,347 @@
+use crate::EncodeConfig;
+use crate::dequant::{ChromaDeltaQ, DeadZone};
+use crate::tile::EarlyExit;
+
+/// Named bundles of encoder settings for common use cases, so callers do not
+/// have to pick raw quantizer indices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    /// Visually lossless stills: a fine quantizer, chroma quantized finer
+    /// still, round-to-nearest quantization with no skip bias, and intra-only
+    /// coding. Intra edge filtering stays off, so directional prediction keeps
+    /// edges sharp.
+    PhotoLosslessIsh,
+}
+
+impl Preset {
+    pub const ALL: [Preset; 1] = [Preset::PhotoLosslessIsh];
+
+    /// Quantizer index used by [`Preset::PhotoLosslessIsh`].
+    pub const PHOTO_LOSSLESS_ISH_Q_IDX: u8 = 24;
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::PhotoLosslessIsh => "photo-lossless-ish",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.name() == name)
+    }
+
+    /// Overwrites the settings this preset controls; everything else in
+    /// `config` (signal, metadata, tiling) is left alone.
+    pub fn apply(self, config: &mut EncodeConfig) {
+        match self {
+            Preset::PhotoLosslessIsh => {
+                config.base_q_idx = Self::PHOTO_LOSSLESS_ISH_Q_IDX;
+                config.target_bitrate = None;
+                config.keyint = 1;
+                config.b_frames = false;
+                config.dead_zone = DeadZone::default();
+                config.chroma_delta_q = ChromaDeltaQ {
+                    u_dc: -8,
+                    u_ac: -8,
+                    v_dc: -8,
+                    v_ac: -8,
+                };
+            }
+        }
+    }
+}
+
+/// The kind of content the block decisions are tuned for. Unlike a
+/// [`Preset`] it leaves every `EncodeConfig` setting alone and only changes
+/// choices made inside the encoder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Tune {
+    /// Camera footage; no biases.
+    #[default]
+    Default,
+    /// Screen captures (remote desktop, slides, UI): whole-pixel motion
+    /// vectors, and near-static blocks kept on the zero-motion skip path at
+    /// every speed. wav1c has no palette or intra block copy coding yet, so
+    /// the sequence header still leaves the screen content tools off. The
+    /// intra transform search already weighs the identity transform on RD
+    /// cost; biasing it further made text larger and blurrier.
+    Screen,
+    /// Animation and cartoons: flat-shaded areas behind hard outlines. A
+    /// wider AC dead zone drops the small coefficients that only carry
+    /// noise in flat areas, and the per-row CDEF search runs even without
+    /// `cdef_search`, so rows with ringing outlines can take up to one and
+    /// a half times the default strength. wav1c has no deblocking
+    /// filter or palette coding, and the intra edge filter is always off,
+    /// so there is nothing to strengthen, bias or sharpen there; as with
+    /// screen content, the identity transform is left to the RD search.
+    Animation,
+}
+
+impl Tune {
+    pub const ALL: [Tune; 3] = [Tune::Default, Tune::Screen, Tune::Animation];
+
+    /// AC rounding (of 256) used by [`Tune::Animation`] at most, x264's
+    /// inter dead zone: coefficients below about two thirds of a step are
+    /// zeroed.
+    pub const ANIMATION_AC_ROUNDING: u8 = 88;
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Tune::Default => "default",
+            Tune::Screen => "screen",
+            Tune::Animation => "animation",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| t.name() == name)
+    }
+
+    /// Whether motion search stops at whole pixels.
+    pub fn integer_mv(self) -> bool {
+        self == Tune::Screen
+    }
+
+    /// The quantizer dead zone used in place of the configured one.
+    pub fn dead_zone(self, dead_zone: DeadZone) -> DeadZone {
+        if self != Tune::Animation {
+            return dead_zone;
+        }
+        DeadZone {
+            intra_ac: dead_zone.intra_ac.min(Self::ANIMATION_AC_ROUNDING),
+            inter_ac: dead_zone.inter_ac.min(Self::ANIMATION_AC_ROUNDING),
+            ..dead_zone
+        }
+    }
+
+    /// Whether CDEF strengths are searched per superblock row, given the
+    /// configured `cdef_search`.
+    pub fn cdef_search(self, cdef_search: bool) -> bool {
+        cdef_search || self == Tune::Animation
+    }
+
+    /// The early-exit thresholds for `speed`. Screen content turns on the
+    /// zero-motion skip rules even at speed 0: unchanged regions are exact
+    /// copies of the reference, and searching them only risks noise.
+    pub fn early_exit(self, speed: u8) -> EarlyExit {
+        let mut early_exit = EarlyExit::for_speed(speed);
+        if self == Tune::Screen {
+            early_exit.inter_none_mse_q4 = early_exit.inter_none_mse_q4.max(1);
+            early_exit.inter_zero_mv_mse_q4 = early_exit.inter_zero_mv_mse_q4.max(1);
+        }
+        early_exit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::y4m::FramePixels;
+    use crate::{Encoder, EncoderConfig};
+
+    fn textured_frame() -> FramePixels {
+        let mut frame = FramePixels::solid(64, 64, 0, 0, 0);
+        for (i, s) in frame.y.iter_mut().enumerate() {
+            let (x, y) = (i % 64, i / 64);
+            *s = ((x * 7 + y * 3 + (x * y) % 23) % 220 + 16) as u16;
+        }
+        for (i, s) in frame.u.iter_mut().enumerate() {
+            *s = ((i % 32) * 5 + 40) as u16;
+        }
+        for (i, s) in frame.v.iter_mut().enumerate() {
+            *s = (200 - (i / 32) * 4) as u16;
+        }
+        frame
+    }
+
+    fn encode(config: &EncodeConfig, frame: &FramePixels) -> FramePixels {
+        let mut enc = Encoder::new(64, 64, EncoderConfig::from(config)).unwrap();
+        enc.send_frame(frame).unwrap();
+        enc.flush();
+        assert!(enc.receive_packet().is_some());
+        enc.last_reconstruction().unwrap().clone()
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for preset in Preset::ALL {
+            assert_eq!(Preset::from_name(preset.name()), Some(preset));
+        }
+        assert_eq!(Preset::from_name("photo"), None);
+    }
+
+    #[test]
+    fn photo_lossless_ish_keeps_signal_settings() {
+        let mut config = EncodeConfig {
+            target_bitrate: Some(500_000),
+            tile_columns: 2,
+            ..EncodeConfig::default()
+        };
+        config.dead_zone.skip_bias = 3;
+        Preset::PhotoLosslessIsh.apply(&mut config);
+        assert_eq!(config.base_q_idx, Preset::PHOTO_LOSSLESS_ISH_Q_IDX);
+        assert_eq!(config.target_bitrate, None);
+        assert_eq!(config.keyint, 1);
+        assert_eq!(config.dead_zone, DeadZone::default());
+        assert!(config.chroma_delta_q.is_valid());
+        assert_eq!(config.tile_columns, 2);
+    }
+
+    #[test]
+    fn photo_lossless_ish_is_higher_fidelity_than_default() {
+        let frame = textured_frame();
+        let default = encode(&EncodeConfig::default(), &frame);
+        let mut config = EncodeConfig::default();
+        Preset::PhotoLosslessIsh.apply(&mut config);
+        let tuned = encode(&config, &frame);
+
+        let default_psnr = crate::metrics::frame_psnr(&frame, &default);
+        let tuned_psnr = crate::metrics::frame_psnr(&frame, &tuned);
+        assert!(tuned_psnr.y > default_psnr.y);
+        assert!(tuned_psnr.u > default_psnr.u);
+        assert!(tuned_psnr.v > default_psnr.v);
+        assert!(tuned_psnr.y > 40.0, "luma PSNR {}", tuned_psnr.y);
+    }
+
+    #[test]
+    fn photo_lossless_ish_codes_every_frame_as_a_keyframe() {
+        let mut config = EncodeConfig::default();
+        Preset::PhotoLosslessIsh.apply(&mut config);
+        let mut enc = Encoder::new(64, 64, EncoderConfig::from(&config)).unwrap();
+        for _ in 0..3 {
+            enc.send_frame(&textured_frame()).unwrap();
+        }
+        enc.flush();
+        let types: Vec<_> = std::iter::from_fn(|| enc.receive_packet())
+            .map(|p| p.frame_type)
+            .collect();
+        assert_eq!(types, vec![crate::FrameType::Key; 3]);
+    }
+
+    /// Black text-like glyph rows on white, with a window that scrolls
+    /// by `offset` pixels.
+    fn screen_frame(offset: u32) -> FramePixels {
+        let mut frame = FramePixels::solid(128, 64, 235, 128, 128);
+        for (i, s) in frame.y.iter_mut().enumerate() {
+            let (x, y) = (i as u32 % 128, i as u32 / 128);
+            let scrolled = if x < 64 { y + offset } else { y };
+            let glyph = (x / 3 * 7 + scrolled / 6 * 13) % 5 < 2;
+            if scrolled % 6 < 4 && x % 3 != 2 && glyph {
+                *s = 16;
+            }
+        }
+        frame
+    }
+
+    #[test]
+    fn tune_names_round_trip() {
+        for tune in Tune::ALL {
+            assert_eq!(Tune::from_name(tune.name()), Some(tune));
+        }
+        assert_eq!(Tune::from_name("desktop"), None);
+        assert_eq!(EncodeConfig::default().tune, Tune::Default);
+    }
+
+    #[test]
+    fn default_tune_changes_no_decision() {
+        for speed in 0..=crate::tile::MAX_SPEED {
+            assert_eq!(Tune::Default.early_exit(speed), EarlyExit::for_speed(speed));
+        }
+        assert!(!Tune::Default.integer_mv());
+        assert_eq!(
+            Tune::Default.dead_zone(DeadZone::default()),
+            DeadZone::default()
+        );
+        assert!(!Tune::Default.cdef_search(false));
+        let animation = Tune::Animation.dead_zone(DeadZone::default());
+        assert_eq!(animation.intra_ac, Tune::ANIMATION_AC_ROUNDING);
+        assert_eq!(animation.intra_dc, DeadZone::default().intra_dc);
+        assert!(Tune::Animation.cdef_search(false));
+        let screen = Tune::Screen.early_exit(0);
+        assert!(screen.inter_none_mse_q4 > 0 && screen.inter_zero_mv_mse_q4 > 0);
+    }
+
+    #[test]
+    fn screen_tune_is_no_worse_on_screen_content() {
+        let encode = |tune: Tune| {
+            let config = EncodeConfig {
+                tune,
+                ..EncodeConfig::default()
+            };
+            let mut enc = Encoder::new(128, 64, EncoderConfig::from(&config)).unwrap();
+            let (mut bytes, mut psnr) = (0, 0.0);
+            for offset in 0..4 {
+                let frame = screen_frame(offset * 2);
+                enc.send_frame(&frame).unwrap();
+                bytes += enc.receive_packet().unwrap().data.len();
+                psnr += crate::metrics::frame_psnr(&frame, enc.last_reconstruction().unwrap()).y;
+            }
+            (bytes, psnr / 4.0)
+        };
+        let (default_bytes, default_psnr) = encode(Tune::Default);
+        let (screen_bytes, screen_psnr) = encode(Tune::Screen);
+        assert!(
+            screen_bytes * 50 <= default_bytes * 51,
+            "{screen_bytes} vs {default_bytes} bytes"
+        );
+        assert!(
+            screen_psnr > default_psnr - 0.2,
+            "{screen_psnr} vs {default_psnr}"
+        );
+    }
+
+    #[cfg(feature = "inter")]
+    /// Flat-shaded discs with dark outlines on a flat sky, plus a little
+    /// noise, moving `offset` pixels to the right.
+    fn cartoon_frame(offset: i32, noisy: bool) -> FramePixels {
+        let mut frame = FramePixels::solid(128, 64, 180, 150, 110);
+        for (i, s) in frame.y.iter_mut().enumerate() {
+            let (x, y) = (i as i32 % 128, i as i32 / 128);
+            for (cx, cy, r, shade) in [(40 + offset, 30, 20, 90), (96, 36, 16, 220)] {
+                let d2 = (x - cx).pow(2) + (y - cy).pow(2);
+                if d2 <= r * r {
+                    *s = shade;
+                }
+                if (d2 - r * r).abs() <= 2 * r {
+                    *s = 20;
+                }
+            }
+            if noisy {
+                *s += ((x * 31 + y * 17 + x * y) % 5) as u16;
+            }
+        }
+        frame
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn animation_tune_is_smaller_on_cartoon_content() {
+        let encode = |tune: Tune| {
+            let config = EncodeConfig {
+                tune,
+                base_q_idx: 96,
+                ..EncodeConfig::default()
+            };
+            let mut enc = Encoder::new(128, 64, EncoderConfig::from(&config)).unwrap();
+            let (mut bytes, mut psnr) = (0, 0.0);
+            for offset in 0..4 {
+                enc.send_frame(&cartoon_frame(offset * 3, true)).unwrap();
+                bytes += enc.receive_packet().unwrap().data.len();
+                let clean = cartoon_frame(offset * 3, false);
+                psnr += crate::metrics::frame_psnr(&clean, enc.last_reconstruction().unwrap()).y;
+            }
+            (bytes, psnr / 4.0)
+        };
+        // Scored against the noise-free drawing: the noise dropped by the
+        // dead zone is not part of the picture.
+        let (default_bytes, default_psnr) = encode(Tune::Default);
+        let (animation_bytes, animation_psnr) = encode(Tune::Animation);
+        assert!(
+            animation_bytes * 100 <= default_bytes * 97,
+            "{animation_bytes} vs {default_bytes} bytes"
+        );
+        assert!(
+            animation_psnr > default_psnr - 0.5,
+            "{animation_psnr} vs {default_psnr}"
+        );
+    }
+}
@@ -0,0 +1,111 @@
+//! Quarter-resolution preview stream coded alongside the main encode.
+//!
+//! Monitoring dashboards want a cheap thumbnail of what is being encoded.
+//! Rather than running a second encoder over the same input, an
+//! [`Encoder`](crate::Encoder) with `EncoderConfig::preview_q_idx` halves
+//! each validated source frame in both directions and codes it into a
+//! second packet queue, taken with `Encoder::receive_preview_packet`. The
+//! preview is a low-latency P-frame stream at a fixed quantizer and the
+//! fastest speed; it keeps the main stream's frame rate, keyframe interval,
+//! video signal and timestamps, and follows its forced keyframes and intra
+//! refreshes.
+
+use crate::aq::AqMode;
+use crate::dequant::ChromaDeltaQ;
+use crate::encoder::EncoderConfig;
+use crate::tile;
+use crate::two_pass::TwoPass;
+use crate::y4m::FramePixels;
+
+/// Preview quantizer the CLI uses unless told otherwise: coarse enough
+/// that a preview costs a few percent of a typical main stream.
+pub const DEFAULT_PREVIEW_Q_IDX: u8 = 200;
+
+/// Preview dimensions for a `width` x `height` source: half in each
+/// direction, rounded up.
+pub fn preview_dimensions(width: u32, height: u32) -> (u32, u32) {
+    (width.div_ceil(2), height.div_ceil(2))
+}
+
+/// `pixels` at [`preview_dimensions`], every sample the rounded mean of the
+/// 2x2 block it covers. Blocks cut by an odd edge repeat the last row or
+/// column.
+pub fn downscale(pixels: &FramePixels) -> FramePixels {
+    let (width, height) = preview_dimensions(pixels.width, pixels.height);
+    let (chroma_width, chroma_height) = (pixels.width.div_ceil(2), pixels.height.div_ceil(2));
+    FramePixels {
+        y: halve_plane(&pixels.y, pixels.width, pixels.height),
+        u: halve_plane(&pixels.u, chroma_width, chroma_height),
+        v: halve_plane(&pixels.v, chroma_width, chroma_height),
+        width,
+        height,
+        ..*pixels
+    }
+}
+
+fn halve_plane(plane: &[u16], width: u32, height: u32) -> Vec<u16> {
+    let (width, height) = (width as usize, height as usize);
+    let (out_width, out_height) = (width.div_ceil(2), height.div_ceil(2));
+    let mut out = Vec::with_capacity(out_width * out_height);
+    for y in 0..out_height {
+        let rows = [2 * y, (2 * y + 1).min(height - 1)];
+        for x in 0..out_width {
+            let cols = [2 * x, (2 * x + 1).min(width - 1)];
+            let sum: u32 = rows
+                .iter()
+                .flat_map(|&r| cols.iter().map(move |&c| plane[r * width + c] as u32))
+                .sum();
+            out.push(((sum + 2) / 4) as u16);
+        }
+    }
+    out
+}
+
+/// Settings of the preview encoder for a main encoder configured with
+/// `config`, coding at `q_idx`.
+pub(crate) fn preview_config(config: &EncoderConfig, q_idx: u8) -> EncoderConfig {
+    EncoderConfig {
+        base_q_idx: q_idx,
+        target_bitrate: None,
+        b_frames: false,
+        closed_gop: true,
+        tile_columns: 0,
+        tile_rows: 0,
+        complexity_analysis: false,
+        distortion_maps: false,
+        motion_fields: false,
+        first_pass_scale: 1,
+        chroma_delta_q: ChromaDeltaQ::default(),
+        cdef_search: false,
+        loop_restoration: false,
+        mv_search_range: config.mv_search_range.div_ceil(2),
+        build_info: false,
+        speed: tile::MAX_SPEED,
+        intra_frame_budget: None,
+        max_frame_bytes: None,
+        frame_slices: 0,
+        aq_mode: AqMode::Off,
+        preview_q_idx: None,
+        two_pass: TwoPass::Off,
+        ..config.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn downscale_averages_blocks_and_repeats_odd_edges() {
+        let mut frame = FramePixels::solid(5, 3, 0, 128, 128);
+        for (i, s) in frame.y.iter_mut().enumerate() {
+            *s = i as u16;
+        }
+        let small = downscale(&frame);
+        assert_eq!((small.width, small.height), (3, 2));
+        // Rows 0-1: (0+1+5+6)/4, (2+3+7+8)/4, (4+4+9+9)/4; row 2 repeats.
+        assert_eq!(small.y, vec![3, 5, 7, 11, 13, 14]);
+        assert_eq!(small.u.len(), 2);
+        assert!(small.u.iter().chain(&small.v).all(|&s| s == 128));
+    }
+}
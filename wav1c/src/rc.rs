@@ -1,145 +1,693 @@
+#[cfg(feature = "rate-control")]
+use std::sync::Arc;
+
+use crate::analysis::FrameComplexity;
+#[cfg(feature = "rate-control")]
+use crate::dequant;
+#[cfg(feature = "rate-control")]
 use crate::fps::Fps;
+#[cfg(feature = "rate-control")]
+use crate::two_pass::FirstPassStats;
+use crate::video::BitDepth;
+
+/// Number of quantizer indices per [`GopReport::qp_histogram`] bucket.
+pub const QP_HISTOGRAM_BUCKET: usize = 32;
+
+/// Fractional bits of the fixed-point averages below. Rate control runs on
+/// integers only, so the quantizers it picks (and with them the bitstream)
+/// are the same on every platform and optimization level.
+pub(crate) const FRAC_BITS: u32 = 16;
+const ONE: i64 = 1 << FRAC_BITS;
+#[cfg(feature = "rate-control")]
+const KEYFRAME_BOOST: i64 = 4;
+/// Keyframes are coded this many qindex steps below the running average.
+#[cfg(feature = "rate-control")]
+const KEYFRAME_QP_OFFSET: i64 = 15;
+/// Per-pixel syntax cost of a keyframe that codes no residual, in
+/// `FRAC_BITS` fixed point (0.03 bits).
+const KEYFRAME_SYNTAX_BPP: i64 = 1966;
 
-#[derive(Debug)]
+#[cfg(feature = "rate-control")]
+#[derive(Debug, Clone)]
 pub struct RateControl {
     target_bitrate: u64,
-    buffer_size: f64,
-    buffer_fullness: f64,
-    target_bits_per_frame: f64,
-    avg_frame_bits: f64,
-    avg_qp: f64,
+    /// Buffer size and fullness and the per-frame figures are in bits.
+    buffer_size: i64,
+    buffer_fullness: i64,
+    target_bits_per_frame: i64,
+    avg_frame_bits: i64,
+    /// Running average quantizer in `FRAC_BITS` fixed point.
+    avg_qp: i64,
     frames_encoded: u64,
     keyint: usize,
-    keyframe_boost: f64,
+    pixels: i64,
+    primed: bool,
+    initial_keyframe_qp: Option<u8>,
+    gops: Vec<GopReport>,
+    plan: Option<TwoPassPlan>,
 }
 
-fn initial_qp_from_bitrate(target_bitrate: u64, fps: Fps, width: u32, height: u32) -> u8 {
-    let fps_f64 = fps.as_f64();
-    let bpp = target_bitrate as f64 / (fps_f64 * width as f64 * height as f64);
-    if bpp > 1.0 {
-        40
-    } else if bpp > 0.5 {
-        80
-    } else if bpp > 0.2 {
-        120
-    } else if bpp > 0.1 {
-        160
-    } else if bpp > 0.05 {
-        200
-    } else {
-        230
+#[cfg(feature = "rate-control")]
+#[derive(Debug, Clone)]
+struct TwoPassPlan {
+    stats: Arc<FirstPassStats>,
+    bit_depth: BitDepth,
+    targets: Vec<i64>,
+    correction: [i64; 2],
+    drift: i64,
+    keyframe_qp: u8,
+}
+
+#[cfg(feature = "rate-control")]
+const TWO_PASS_MIN_WEIGHT: u64 = 32;
+
+#[cfg(feature = "rate-control")]
+impl TwoPassPlan {
+    fn new(
+        stats: Arc<FirstPassStats>,
+        bits_per_frame: i64,
+        keyint: usize,
+        bit_depth: BitDepth,
+    ) -> Self {
+        let weights: Vec<u64> = (0..stats.frames.len())
+            .map(|i| {
+                let is_keyframe = i == 0 || (keyint > 0 && i.is_multiple_of(keyint));
+                let frame = &stats.frames[i];
+                let pixels = pixels_analyzed(frame).max(1);
+                let weight = ((planned_cost(frame, is_keyframe) << FRAC_BITS) / pixels).isqrt()
+                    + TWO_PASS_MIN_WEIGHT;
+                if is_keyframe {
+                    weight * KEYFRAME_BOOST as u64
+                } else {
+                    weight
+                }
+            })
+            .collect();
+        let total_weight = weights.iter().sum::<u64>().max(1) as i128;
+        let total_bits = bits_per_frame as i128 * weights.len() as i128;
+        Self {
+            targets: weights
+                .iter()
+                .map(|&w| (total_bits * w as i128 / total_weight) as i64)
+                .collect(),
+            stats,
+            bit_depth,
+            correction: [ONE; 2],
+            drift: 0,
+            keyframe_qp: 1,
+        }
+    }
+
+    fn modelled_bits(
+        &self,
+        frame_number: u64,
+        is_keyframe: bool,
+        qp: u8,
+        pixels: i64,
+    ) -> Option<i64> {
+        let frame = self.stats.frames.get(frame_number as usize)?;
+        let analyzed = pixels_analyzed(frame);
+        if analyzed == 0 {
+            return None;
+        }
+        let qstep = dequant::lookup_dequant(qp, self.bit_depth).ac;
+        let bpp = keyframe_bits_per_pixel(planned_cost(frame, is_keyframe), analyzed, qstep);
+        Some(((bpp as i128 * pixels as i128) >> FRAC_BITS).max(1) as i64)
+    }
+
+    fn qp(&self, frame_number: u64, is_keyframe: bool, pixels: i64, keyint: usize) -> Option<u8> {
+        let i = frame_number as usize;
+        self.modelled_bits(frame_number, is_keyframe, 255, pixels)?;
+        let remaining = (self.targets.len() - i).min(keyint.max(1)) as i64;
+        let target = (self.targets[i] - self.drift / remaining).max(self.targets[i] / 4);
+        let correction = self.correction[usize::from(!is_keyframe)] as i128;
+        let finest = if is_keyframe { 1 } else { self.keyframe_qp };
+        Some(
+            (finest..=255u8)
+                .find(|&q| {
+                    let bits = self
+                        .modelled_bits(frame_number, is_keyframe, q, pixels)
+                        .unwrap();
+                    (bits as i128 * correction) >> FRAC_BITS <= target as i128
+                })
+                .unwrap_or(255),
+        )
+    }
+
+    fn update(&mut self, frame_number: u64, is_keyframe: bool, actual: i64, qp: u8, pixels: i64) {
+        let Some(modelled) = self.modelled_bits(frame_number, is_keyframe, qp, pixels) else {
+            return;
+        };
+        let ratio = (((actual as i128) << FRAC_BITS) / modelled as i128)
+            .clamp(ONE as i128 / 16, 16 * ONE as i128) as i64;
+        if is_keyframe {
+            self.keyframe_qp = qp;
+        }
+        let correction = &mut self.correction[usize::from(!is_keyframe)];
+        *correction = (*correction + ratio) / 2;
+        self.drift += actual - self.targets[frame_number as usize];
+    }
+}
+
+#[cfg(feature = "rate-control")]
+fn planned_cost(frame: &FrameComplexity, is_keyframe: bool) -> u64 {
+    match frame.inter_cost {
+        Some(inter) if !is_keyframe => inter.min(frame.intra_cost),
+        _ => frame.intra_cost,
     }
 }
 
+#[cfg(feature = "rate-control")]
+fn pixels_analyzed(frame: &FrameComplexity) -> u64 {
+    frame.blocks as u64 * crate::analysis::ANALYSIS_BLOCK_SIZE.pow(2) as u64
+}
+
+/// `log2(x / ONE)` in `FRAC_BITS` fixed point, for `x >= ONE`. Bit-by-bit
+/// square-and-compare, so unlike `f64::log2` it does not depend on libm.
+fn log2_fixed(x: u64) -> i64 {
+    let int_part = (63 - x.leading_zeros()).saturating_sub(FRAC_BITS);
+    let mut y = x >> int_part;
+    let mut result = (int_part as i64) << FRAC_BITS;
+    for bit in (0..FRAC_BITS).rev() {
+        y = (y * y) >> FRAC_BITS;
+        if y >= 2 << FRAC_BITS {
+            y >>= 1;
+            result |= 1 << bit;
+        }
+    }
+    result
+}
+
+/// Expected keyframe cost in bits per luma pixel (`FRAC_BITS` fixed point)
+/// for content whose intra SATD totals `intra_cost` over `pixels_analyzed`
+/// pixels, coded at quantizer step `qstep`. Quadratic for coarse steps,
+/// logarithmic for fine ones; fitted on wav1c's own keyframes, with a floor
+/// for the syntax every block pays.
+pub(crate) fn keyframe_bits_per_pixel(intra_cost: u64, pixels_analyzed: u64, qstep: u32) -> i64 {
+    let ratio =
+        ((20 * intra_cost as u128) << FRAC_BITS) / (pixels_analyzed as u128 * qstep as u128);
+    let energy = (ONE as u128 + ((ratio * ratio) >> FRAC_BITS)).min(u64::MAX as u128) as u64;
+    3 * log2_fixed(energy) / 4 + KEYFRAME_SYNTAX_BPP
+}
+
+/// `(value - target) / target` clamped to [-1, 1], in `FRAC_BITS` fixed point.
+#[cfg(feature = "rate-control")]
+fn relative_error(value: i64, target: i64) -> i64 {
+    if target <= 0 {
+        return 0;
+    }
+    let error = ((value as i128 - target as i128) << FRAC_BITS) / target as i128;
+    error.clamp(-ONE as i128, ONE as i128) as i64
+}
+
+fn round_fixed(value: i64) -> i64 {
+    (value + ONE / 2) >> FRAC_BITS
+}
+
+#[cfg(feature = "rate-control")]
+fn initial_qp_from_bitrate(target_bitrate: u64, fps: Fps, width: u32, height: u32) -> u8 {
+    // Bits per pixel per frame against thresholds in 1/20 bit:
+    // bitrate * den / (num * pixels) > k / 20.
+    let scaled_rate = target_bitrate as u128 * fps.den as u128 * 20;
+    let pixel_rate = fps.num as u128 * width as u128 * height as u128;
+    [(20, 40), (10, 80), (4, 120), (2, 160), (1, 200)]
+        .into_iter()
+        .find(|&(k, _)| scaled_rate > pixel_rate * k)
+        .map_or(230, |(_, qp)| qp)
+}
+
+#[cfg(feature = "rate-control")]
 impl RateControl {
     pub fn new(target_bitrate: u64, fps: Fps, width: u32, height: u32, keyint: usize) -> Self {
         let initial_qp = initial_qp_from_bitrate(target_bitrate, fps, width, height);
-        let target_bits_per_frame = target_bitrate as f64 / fps.as_f64();
-        let buffer_size = target_bitrate as f64;
+        let target_bits_per_frame =
+            (target_bitrate as u128 * fps.den as u128 / fps.num.max(1) as u128) as i64;
+        let buffer_size = target_bitrate.min(i64::MAX as u64 / 2) as i64;
 
         Self {
             target_bitrate,
             buffer_size,
-            buffer_fullness: buffer_size / 2.0,
+            buffer_fullness: buffer_size / 2,
             target_bits_per_frame,
             avg_frame_bits: target_bits_per_frame,
-            avg_qp: initial_qp as f64,
+            avg_qp: initial_qp as i64 * ONE,
             frames_encoded: 0,
             keyint,
-            keyframe_boost: 4.0,
+            pixels: width as i64 * height as i64,
+            primed: false,
+            initial_keyframe_qp: None,
+            gops: Vec::new(),
+            plan: None,
         }
     }
 
-    fn target_bits_for_frame(&self, is_keyframe: bool) -> f64 {
+    /// Allocates the stream's bits per frame from first-pass `stats`.
+    pub fn plan_from_first_pass(&mut self, stats: Arc<FirstPassStats>, bit_depth: BitDepth) {
+        self.plan = Some(TwoPassPlan::new(
+            stats,
+            self.target_bits_per_frame,
+            self.keyint,
+            bit_depth,
+        ));
+    }
+
+    /// Whether the first frame's quantizer still comes from the bitrate table
+    /// alone, i.e. neither [`RateControl::prime`] nor
+    /// [`RateControl::estimate_initial_qp`] has run and nothing was coded.
+    pub fn needs_initial_estimate(&self) -> bool {
+        self.frames_encoded == 0
+            && !self.primed
+            && self.initial_keyframe_qp.is_none()
+            && self.plan.is_none()
+    }
+
+    /// Picks the first keyframe's quantizer from its intra complexity (SATD
+    /// `intra_cost` summed over `pixels_analyzed` pixels): the finest qindex
+    /// whose modelled size fits the keyframe budget (which is capped at half
+    /// the buffer). Inter frames that follow start 15 above it, the usual
+    /// keyframe offset. Without this the first keyframe starts from a
+    /// bits-per-pixel table and often overshoots on detailed content.
+    pub fn estimate_initial_qp(
+        &mut self,
+        intra_cost: u64,
+        pixels_analyzed: u64,
+        bit_depth: BitDepth,
+    ) {
+        if pixels_analyzed == 0 {
+            return;
+        }
+        let budget = self.target_bits_for_frame(true) as i128;
+        let qp = (1..=255u8)
+            .find(|&q| {
+                let qstep = dequant::lookup_dequant(q, bit_depth).ac;
+                let bpp = keyframe_bits_per_pixel(intra_cost, pixels_analyzed, qstep);
+                (bpp as i128 * self.pixels as i128) >> FRAC_BITS <= budget
+            })
+            .unwrap_or(255);
+        self.initial_keyframe_qp = Some(qp);
+        self.avg_qp = (qp as i64 + KEYFRAME_QP_OFFSET).min(255) * ONE;
+    }
+
+    /// Average quantizer and average coded bits per luma pixel seen so far,
+    /// for seeding a later encode with [`RateControl::prime`].
+    pub fn model(&self) -> (f64, f64) {
+        (
+            self.avg_qp as f64 / ONE as f64,
+            self.avg_frame_bits as f64 / self.pixels as f64,
+        )
+    }
+
+    /// Replaces the bitrate-table guess with a model learned on a previous
+    /// encode, so the first frames start near a realistic quantizer. Frame
+    /// size is rescaled to this encode's resolution.
+    pub fn prime(&mut self, avg_qp: f64, bits_per_pixel: f64) {
+        self.avg_qp = (avg_qp.clamp(1.0, 255.0) * ONE as f64) as i64;
+        self.avg_frame_bits = (bits_per_pixel * self.pixels as f64) as i64;
+        self.primed = true;
+    }
+
+    fn target_bits_for_frame(&self, is_keyframe: bool) -> i64 {
         let base = self.target_bits_per_frame;
         if is_keyframe {
-            let boosted = base * self.keyframe_boost;
-            boosted.min(self.buffer_size * 0.5)
+            (base * KEYFRAME_BOOST).min(self.buffer_size / 2)
         } else {
-            let overspend = base * (self.keyframe_boost - 1.0);
-            let reduction = overspend / (self.keyint as f64 - 1.0).max(1.0);
-            (base - reduction).max(base * 0.3)
+            let overspend = base * (KEYFRAME_BOOST - 1);
+            let reduction = overspend / (self.keyint as i64 - 1).max(1);
+            (base - reduction).max(base * 3 / 10)
         }
     }
 
     pub fn compute_qp(&mut self, is_keyframe: bool) -> u8 {
-        if self.frames_encoded == 0 {
-            let qp = self.avg_qp as u8;
+        if self.frames_encoded == 0 && !self.primed {
+            if let (Some(qp), true) = (self.initial_keyframe_qp, is_keyframe) {
+                return qp;
+            }
+            let qp = self.avg_qp >> FRAC_BITS;
             return if is_keyframe {
-                (qp as i32 - 15).clamp(1, 255) as u8
+                (qp - KEYFRAME_QP_OFFSET).clamp(1, 255) as u8
             } else {
-                qp
+                qp.clamp(1, 255) as u8
             };
         }
 
         let target_bits = self.target_bits_for_frame(is_keyframe);
-
-        let buffer_target = self.buffer_size / 2.0;
-        let buffer_error =
-            ((self.buffer_fullness - buffer_target) / buffer_target).clamp(-1.0, 1.0);
-
-        let rate_error = if self.avg_frame_bits > 0.0 {
-            ((self.avg_frame_bits - target_bits) / target_bits).clamp(-1.0, 1.0)
+        let buffer_error = relative_error(self.buffer_fullness, self.buffer_size / 2);
+        let rate_error = if self.avg_frame_bits > 0 {
+            relative_error(self.avg_frame_bits, target_bits)
         } else {
-            0.0
+            0
         };
 
-        let combined = 0.6 * buffer_error + 0.4 * rate_error;
-        let qp_delta = combined * 30.0;
-
+        // 60% buffer and 40% rate error, up to 30 qindex steps, and never
+        // more than 10 steps from the running average.
+        let qp_delta = ((6 * buffer_error + 4 * rate_error) * 3).clamp(-10 * ONE, 10 * ONE);
         let mut new_qp = self.avg_qp + qp_delta;
-        new_qp = new_qp.clamp(self.avg_qp - 10.0, self.avg_qp + 10.0);
-
         if is_keyframe {
-            new_qp -= 15.0;
+            new_qp -= KEYFRAME_QP_OFFSET * ONE;
         }
 
-        (new_qp.round() as i32).clamp(1, 255) as u8
+        round_fixed(new_qp).clamp(1, 255) as u8
     }
 
-    pub fn update(&mut self, actual_bits: u64, qp_used: u8) {
-        self.buffer_fullness += actual_bits as f64;
-        self.buffer_fullness -= self.target_bits_per_frame;
-        self.buffer_fullness = self.buffer_fullness.clamp(0.0, self.buffer_size);
+    pub fn compute_frame_qp(&mut self, frame_number: u64, is_keyframe: bool) -> u8 {
+        let planned = self
+            .plan
+            .as_ref()
+            .and_then(|plan| plan.qp(frame_number, is_keyframe, self.pixels, self.keyint));
+        planned.unwrap_or_else(|| self.compute_qp(is_keyframe))
+    }
+
+    pub fn update_frame(
+        &mut self,
+        frame_number: u64,
+        actual_bits: u64,
+        qp_used: u8,
+        is_keyframe: bool,
+    ) {
+        if let Some(plan) = &mut self.plan {
+            let actual = actual_bits.min(i64::MAX as u64 / 8) as i64;
+            plan.update(frame_number, is_keyframe, actual, qp_used, self.pixels);
+        }
+        self.update(actual_bits, qp_used, is_keyframe);
+    }
+
+    pub fn update(&mut self, actual_bits: u64, qp_used: u8, is_keyframe: bool) {
+        let actual = actual_bits.min(i64::MAX as u64 / 8) as i64;
+        self.buffer_fullness =
+            (self.buffer_fullness + actual - self.target_bits_per_frame).clamp(0, self.buffer_size);
+
+        if is_keyframe || self.gops.is_empty() {
+            self.gops.push(GopReport {
+                first_frame: self.frames_encoded,
+                ..GopReport::default()
+            });
+        }
+        let buffer_pct = self.buffer_fullness_pct();
+        let gop = self.gops.last_mut().unwrap();
+        if is_keyframe {
+            gop.keyframes += 1;
+            gop.keyframe_bits += actual_bits;
+        } else {
+            gop.inter_frames += 1;
+            gop.inter_bits += actual_bits;
+        }
+        gop.peak_frame_bits = gop.peak_frame_bits.max(actual_bits);
+        gop.qp_histogram[qp_used as usize / QP_HISTOGRAM_BUCKET] += 1;
+        gop.buffer_trajectory.push(buffer_pct);
 
-        let alpha = 0.2;
-        self.avg_frame_bits = alpha * actual_bits as f64 + (1.0 - alpha) * self.avg_frame_bits;
-        self.avg_qp = alpha * qp_used as f64 + (1.0 - alpha) * self.avg_qp;
+        // Exponential averages with weight 1/5 on the newest frame.
+        self.avg_frame_bits = (4 * self.avg_frame_bits + actual) / 5;
+        self.avg_qp = (4 * self.avg_qp + qp_used as i64 * ONE) / 5;
 
         self.frames_encoded += 1;
     }
 
+    pub(crate) fn buffer_fullness_pct(&self) -> u32 {
+        if self.buffer_size == 0 {
+            return 0;
+        }
+        (self.buffer_fullness as i128 * 100 / self.buffer_size as i128) as u32
+    }
+
     pub fn stats(&self) -> RateControlStats {
         RateControlStats {
             target_bitrate: self.target_bitrate,
             frames_encoded: self.frames_encoded,
-            buffer_fullness_pct: (self.buffer_fullness / self.buffer_size * 100.0) as u32,
-            avg_qp: self.avg_qp.round() as u8,
+            buffer_fullness_pct: self.buffer_fullness_pct(),
+            avg_qp: round_fixed(self.avg_qp).clamp(0, 255) as u8,
+            gops: self.gops.clone(),
         }
     }
 }
 
+/// Stand-in for the built-in controller without the `rate-control`
+/// feature. It has no values, so an encoder never holds one and the
+/// bitrate-targeting paths compile away.
+#[cfg(not(feature = "rate-control"))]
+#[derive(Debug, Clone)]
+pub enum RateControl {}
+
+#[cfg(not(feature = "rate-control"))]
+impl RateControl {
+    pub fn needs_initial_estimate(&self) -> bool {
+        match *self {}
+    }
+
+    pub fn estimate_initial_qp(&mut self, _: u64, _: u64, _: BitDepth) {
+        match *self {}
+    }
+
+    pub fn model(&self) -> (f64, f64) {
+        match *self {}
+    }
+
+    pub fn prime(&mut self, _: f64, _: f64) {
+        match *self {}
+    }
+
+    pub fn compute_qp(&mut self, _: bool) -> u8 {
+        match *self {}
+    }
+
+    pub fn compute_frame_qp(&mut self, _: u64, _: bool) -> u8 {
+        match *self {}
+    }
+
+    pub fn update(&mut self, _: u64, _: u8, _: bool) {
+        match *self {}
+    }
+
+    pub fn update_frame(&mut self, _: u64, _: u64, _: u8, _: bool) {
+        match *self {}
+    }
+
+    pub(crate) fn buffer_fullness_pct(&self) -> u32 {
+        match *self {}
+    }
+
+    pub fn stats(&self) -> RateControlStats {
+        match *self {}
+    }
+}
+
+/// A frame about to be coded, as seen by a [`RateController`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RcFrame {
+    /// Display index of the frame, matching `Packet::frame_number`.
+    pub frame_number: u64,
+    pub is_keyframe: bool,
+    pub bit_depth: BitDepth,
+    /// Source luma complexity. Inter frames are scored against the
+    /// reconstruction of the frame they predict from.
+    pub complexity: FrameComplexity,
+}
+
+/// Quantizer selection plugged into an [`crate::Encoder`] with
+/// `set_rate_controller`, in place of the built-in [`RateControl`].
+///
+/// `on_frame_start` is asked for the base q index of every frame whose
+/// quantizer the encoder does not already have (B-frames use a fixed offset
+/// and `FrameOptions::base_q_idx` overrides win); `on_frame_done` then sees
+/// every coded frame in coding order, with the q index used and the size of
+/// its frame OBU in bytes (temporal delimiter, sequence header and metadata
+/// excluded). Returned q indices are clamped to `1..=255`.
+pub trait RateController: Send {
+    fn on_frame_start(&mut self, frame: &RcFrame) -> u8;
+    fn on_frame_done(&mut self, frame: &RcFrame, q_idx: u8, bytes: usize);
+}
+
+/// The built-in CBR controller, so it can be wrapped or used as-is through
+/// the trait. Frame OBU sizes are what the built-in path counts too, so this
+/// reproduces a `target_bitrate` encode exactly.
+#[cfg(feature = "rate-control")]
+impl RateController for RateControl {
+    fn on_frame_start(&mut self, frame: &RcFrame) -> u8 {
+        if frame.is_keyframe && self.needs_initial_estimate() {
+            let block_pixels = crate::analysis::ANALYSIS_BLOCK_SIZE.pow(2) as u64;
+            self.estimate_initial_qp(
+                frame.complexity.intra_cost,
+                frame.complexity.blocks as u64 * block_pixels,
+                frame.bit_depth,
+            );
+        }
+        self.compute_frame_qp(frame.frame_number, frame.is_keyframe)
+    }
+
+    fn on_frame_done(&mut self, frame: &RcFrame, q_idx: u8, bytes: usize) {
+        self.update_frame(
+            frame.frame_number,
+            (bytes * 8) as u64,
+            q_idx,
+            frame.is_keyframe,
+        );
+    }
+}
+
+/// Initial estimate of the qindex steps that halve a keyframe's size, for
+/// the [`FrameBudget`] corrections. Replaced by the slope measured whenever a
+/// frame is re-encoded.
+const BUDGET_STEPS_PER_OCTAVE: i64 = 24;
+/// Bounds on the measured slope.
+const BUDGET_SLOPE_RANGE: (i64, i64) = (8, 160);
+/// Re-encodes of one frame before [`FrameBudget`] falls back to qindex 255.
+const BUDGET_RETRIES: u32 = 3;
+/// Largest drop in the starting quantizer between frames, so one easy frame
+/// does not set up an overshoot on the next.
+const BUDGET_MAX_RELAX: i64 = 8;
+
+/// Single-pass quantizer control for a per-frame size cap on intra-only
+/// streams.
+///
+/// Each frame starts from the quantizer the previous one settled on, moved
+/// by the log ratio of its size to seven eighths of the cap times the
+/// qindex steps per halving. A frame that still overshoots is re-encoded at
+/// a quantizer raised by the same model, at most [`BUDGET_RETRIES`] times
+/// and then at qindex 255; every re-encode re-measures the steps per halving
+/// from the last two sizes. The cap holds for every frame that fits at
+/// qindex 255.
+#[derive(Debug, Clone)]
+pub struct FrameBudget {
+    max_bytes: u64,
+    qp: u8,
+    /// Qindex steps per halving of the frame size.
+    steps_per_octave: i64,
+    /// Quantizer and size of the frame's previous attempt.
+    last_attempt: Option<(u8, u64)>,
+    overshoots: u64,
+}
+
+impl FrameBudget {
+    pub fn new(max_bytes: u32, initial_qp: u8) -> Self {
+        Self {
+            max_bytes: max_bytes.max(1) as u64,
+            qp: initial_qp.max(1),
+            steps_per_octave: BUDGET_STEPS_PER_OCTAVE,
+            last_attempt: None,
+            overshoots: 0,
+        }
+    }
+
+    /// Quantizer to code the next frame with first.
+    pub fn qp(&self) -> u8 {
+        self.qp
+    }
+
+    /// Quantizer for re-encode number `attempt` (from 1) of a frame that
+    /// coded to `bytes` at `qp`, or `None` when it fits or cannot go coarser.
+    pub fn retry_qp(&mut self, qp: u8, bytes: usize, attempt: u32) -> Option<u8> {
+        let bytes = bytes as u64;
+        if let Some((last_qp, last_bytes)) = self.last_attempt.take()
+            && attempt > 1
+            && qp > last_qp
+            && bytes < last_bytes
+        {
+            let octaves = log2_fixed((last_bytes << FRAC_BITS) / bytes);
+            if octaves > 0 {
+                let (min, max) = BUDGET_SLOPE_RANGE;
+                self.steps_per_octave = (((qp - last_qp) as i64) << FRAC_BITS) / octaves;
+                self.steps_per_octave = self.steps_per_octave.clamp(min, max);
+            }
+        }
+        if bytes <= self.max_bytes || qp == 255 {
+            return None;
+        }
+        if attempt > BUDGET_RETRIES {
+            return Some(255);
+        }
+        self.last_attempt = Some((qp, bytes));
+        let step = self.steps_to(bytes, self.target()).max(4);
+        Some((qp as i64 + step).min(255) as u8)
+    }
+
+    /// Records the size of a finished frame and its final quantizer.
+    pub fn update(&mut self, qp: u8, bytes: usize) {
+        let bytes = bytes as u64;
+        self.last_attempt = None;
+        if bytes > self.max_bytes {
+            self.overshoots += 1;
+        }
+        let target = self.target();
+        let next = if bytes > target {
+            qp as i64 + self.steps_to(bytes, target).max(1)
+        } else {
+            qp as i64 - (self.steps_to(target, bytes.max(1)) / 2).min(BUDGET_MAX_RELAX)
+        };
+        self.qp = next.clamp(1, 255) as u8;
+    }
+
+    /// Frames that exceeded the cap even at qindex 255.
+    pub fn overshoots(&self) -> u64 {
+        self.overshoots
+    }
+
+    /// Size each frame aims for, leaving headroom under the cap.
+    fn target(&self) -> u64 {
+        (self.max_bytes * 7 / 8).max(1)
+    }
+
+    /// Qindex steps expected to shrink a frame from `from` to `to` bytes,
+    /// `from >= to`.
+    fn steps_to(&self, from: u64, to: u64) -> i64 {
+        let ratio = (from.min(u64::MAX >> FRAC_BITS) << FRAC_BITS) / to.max(1);
+        round_fixed(log2_fixed(ratio.max(ONE as u64)) * self.steps_per_octave)
+    }
+}
+
 pub struct RateControlStats {
     pub target_bitrate: u64,
     pub frames_encoded: u64,
     pub buffer_fullness_pct: u32,
     pub avg_qp: u8,
+    /// Per-GOP bit allocation in coding order; the last entry is the GOP
+    /// still being encoded.
+    pub gops: Vec<GopReport>,
+}
+
+/// Bit allocation of one GOP: a keyframe and the frames coded before the
+/// next one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GopReport {
+    /// Coding-order index of the GOP's first frame.
+    pub first_frame: u64,
+    pub keyframes: u32,
+    pub keyframe_bits: u64,
+    pub inter_frames: u32,
+    pub inter_bits: u64,
+    pub peak_frame_bits: u64,
+    /// Frame count per quantizer range of [`QP_HISTOGRAM_BUCKET`] indices
+    /// (0-31, 32-63, ..., 224-255).
+    pub qp_histogram: [u32; 256 / QP_HISTOGRAM_BUCKET],
+    /// Buffer fullness in percent after each frame.
+    pub buffer_trajectory: Vec<u32>,
+}
+
+impl GopReport {
+    pub fn frames(&self) -> u32 {
+        self.keyframes + self.inter_frames
+    }
+
+    pub fn total_bits(&self) -> u64 {
+        self.keyframe_bits + self.inter_bits
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[cfg(feature = "rate-control")]
     #[test]
     fn initial_qp_high_bitrate() {
         assert!(initial_qp_from_bitrate(10_000_000, Fps::default(), 320, 240) <= 80);
     }
 
+    #[cfg(feature = "rate-control")]
     #[test]
     fn initial_qp_low_bitrate() {
         assert!(initial_qp_from_bitrate(50_000, Fps::default(), 640, 480) >= 200);
     }
 
+    #[cfg(feature = "rate-control")]
     #[test]
     fn first_frame_uses_initial_qp() {
         let mut rc = RateControl::new(500_000, Fps::default(), 320, 240, 25);
@@ -147,48 +695,188 @@ mod tests {
         assert!(qp > 0 && qp < 255);
     }
 
+    #[cfg(feature = "rate-control")]
     #[test]
     fn qp_increases_when_over_budget() {
         let mut rc = RateControl::new(500_000, Fps::default(), 320, 240, 25);
         let initial_qp = rc.compute_qp(true);
-        rc.update(100_000, initial_qp);
+        rc.update(100_000, initial_qp, true);
 
         let target = rc.target_bits_per_frame as u64;
         for _ in 0..5 {
             let qp = rc.compute_qp(false);
-            rc.update(target * 3, qp);
+            rc.update(target * 3, qp, false);
         }
         let qp_after = rc.compute_qp(false);
         assert!(qp_after > initial_qp);
     }
 
+    #[cfg(feature = "rate-control")]
     #[test]
     fn qp_decreases_when_under_budget() {
         let mut rc = RateControl::new(500_000, Fps::default(), 320, 240, 25);
         let initial_qp = rc.compute_qp(true);
-        rc.update(1000, initial_qp);
+        rc.update(1000, initial_qp, true);
 
         for _ in 0..5 {
             let qp = rc.compute_qp(false);
-            rc.update(100, qp);
+            rc.update(100, qp, false);
         }
         let qp_after = rc.compute_qp(false);
         assert!(qp_after < initial_qp);
     }
 
+    #[cfg(feature = "rate-control")]
     #[test]
     fn keyframe_gets_lower_qp() {
         let mut rc = RateControl::new(500_000, Fps::default(), 320, 240, 25);
         rc.compute_qp(true);
-        rc.update(20_000, 120);
+        rc.update(20_000, 120, true);
 
         let inter_qp = rc.compute_qp(false);
-        rc.update(20_000, inter_qp);
+        rc.update(20_000, inter_qp, false);
 
         let key_qp = rc.compute_qp(true);
         assert!(key_qp < inter_qp);
     }
 
+    #[cfg(feature = "rate-control")]
+    #[test]
+    fn primed_first_frame_follows_learned_model() {
+        let mut cold = RateControl::new(500_000, Fps::default(), 320, 240, 25);
+        let mut warm = RateControl::new(500_000, Fps::default(), 320, 240, 25);
+        let target_bpp = warm.target_bits_per_frame as f64 / warm.pixels as f64;
+        warm.prime(60.0, target_bpp);
+        let (avg_qp, bpp) = warm.model();
+        assert_eq!(avg_qp, 60.0);
+        assert!((bpp - target_bpp).abs() < 1e-3);
+        let cold_qp = cold.compute_qp(false);
+        let warm_qp = warm.compute_qp(false);
+        assert_ne!(cold_qp, warm_qp);
+        assert!((warm_qp as i32 - 60).abs() <= 10);
+    }
+
+    #[cfg(feature = "rate-control")]
+    #[test]
+    fn initial_keyframe_qp_follows_first_frame_complexity() {
+        let pixels = 320 * 240;
+        let mut flat = RateControl::new(500_000, Fps::default(), 320, 240, 25);
+        let mut detailed = RateControl::new(500_000, Fps::default(), 320, 240, 25);
+        assert!(flat.needs_initial_estimate());
+        flat.estimate_initial_qp(pixels, pixels, BitDepth::Eight);
+        detailed.estimate_initial_qp(40 * pixels, pixels, BitDepth::Eight);
+        assert!(!flat.needs_initial_estimate());
+        let flat_qp = flat.compute_qp(true);
+        let detailed_qp = detailed.compute_qp(true);
+        assert!(detailed_qp > flat_qp + 40, "{flat_qp} vs {detailed_qp}");
+
+        let budget = detailed.target_bits_for_frame(true);
+        let bits = |q| {
+            let qstep = dequant::lookup_dequant(q, BitDepth::Eight).ac;
+            (keyframe_bits_per_pixel(40 * pixels, pixels, qstep) * pixels as i64) >> FRAC_BITS
+        };
+        assert!(bits(detailed_qp) <= budget);
+        assert!(bits(detailed_qp - 1) > budget);
+    }
+
+    #[cfg(feature = "rate-control")]
+    #[test]
+    fn initial_estimate_is_bit_depth_invariant() {
+        let pixels = 320 * 240;
+        let mut eight = RateControl::new(500_000, Fps::default(), 320, 240, 25);
+        let mut ten = RateControl::new(500_000, Fps::default(), 320, 240, 25);
+        eight.estimate_initial_qp(12 * pixels, pixels, BitDepth::Eight);
+        ten.estimate_initial_qp(48 * pixels, pixels, BitDepth::Ten);
+        let (eight_qp, ten_qp) = (eight.compute_qp(true), ten.compute_qp(true));
+        assert!(
+            (eight_qp as i32 - ten_qp as i32).abs() <= 4,
+            "{eight_qp} vs {ten_qp}"
+        );
+    }
+
+    #[cfg(feature = "rate-control")]
+    #[test]
+    fn second_pass_allots_bits_by_cost_and_pays_back_overshoot() {
+        let pixels = 320 * 240;
+        let frame = |frame_number, inter_cost| FrameComplexity {
+            frame_number,
+            intra_cost: 20 * pixels,
+            inter_cost,
+            motion_magnitude: None,
+            blocks: (pixels / 256) as u32,
+        };
+        let mut stats = FirstPassStats::new(320, 240);
+        stats.frames = vec![
+            frame(0, None),
+            frame(1, Some(pixels)),
+            frame(2, Some(16 * pixels)),
+            frame(3, Some(pixels)),
+        ];
+        let mut rc = RateControl::new(500_000, Fps::default(), 320, 240, 25);
+        rc.plan_from_first_pass(Arc::new(stats), BitDepth::Eight);
+        assert!(!rc.needs_initial_estimate());
+        let targets = rc.plan.as_ref().unwrap().targets.clone();
+        assert!(targets[0] > targets[2] && targets[2] > targets[1]);
+        let total: i64 = targets.iter().sum();
+        assert!((total - 4 * rc.target_bits_per_frame).abs() <= 4);
+
+        let key_qp = rc.compute_frame_qp(0, true);
+        rc.update_frame(0, targets[0] as u64, key_qp, true);
+        let easy_qp = rc.compute_frame_qp(1, false);
+        assert!(easy_qp >= key_qp);
+        let mut overshot = rc.clone();
+        overshot.update_frame(1, 4 * targets[1] as u64, easy_qp, false);
+        rc.update_frame(1, targets[1] as u64, easy_qp, false);
+        assert!(overshot.compute_frame_qp(2, false) > rc.compute_frame_qp(2, false));
+
+        assert_eq!(rc.compute_frame_qp(9, false), rc.clone().compute_qp(false));
+    }
+
+    #[test]
+    fn fixed_point_log2_matches_reference_values() {
+        assert_eq!(log2_fixed(ONE as u64), 0);
+        assert_eq!(log2_fixed(2 * ONE as u64), ONE);
+        assert_eq!(log2_fixed(1024 * ONE as u64), 10 * ONE);
+        for (x, expected) in [
+            (3.0f64, 1.584_962_5),
+            (10.0, std::f64::consts::LOG2_10),
+            (1.5, 0.584_962_5),
+        ] {
+            let got = log2_fixed((x * ONE as f64) as u64) as f64 / ONE as f64;
+            assert!((got - expected).abs() < 1e-4, "log2({x}) = {got}");
+        }
+    }
+
+    #[test]
+    fn frame_budget_retries_coarser_until_a_frame_fits() {
+        let mut budget = FrameBudget::new(8_000, 60);
+        assert_eq!(budget.qp(), 60);
+        assert_eq!(budget.retry_qp(60, 8_000, 1), None);
+        let q = budget.retry_qp(60, 14_000, 1).unwrap();
+        assert_eq!(q, 60 + BUDGET_STEPS_PER_OCTAVE as u8);
+        // 24 steps shrank the frame by well under half: the next jump uses
+        // the measured slope of about 49 steps per halving.
+        assert_eq!(budget.retry_qp(q, 10_000, 2), Some(q + 25));
+        assert_eq!(budget.steps_per_octave, 49);
+        assert_eq!(budget.retry_qp(60, 20_000, BUDGET_RETRIES + 1), Some(255));
+        assert_eq!(budget.retry_qp(255, 20_000, 1), None);
+    }
+
+    #[test]
+    fn frame_budget_tracks_frame_sizes() {
+        let mut budget = FrameBudget::new(8_000, 100);
+        budget.update(100, 7_000);
+        assert_eq!(budget.qp(), 100);
+        budget.update(100, 14_000);
+        assert_eq!(budget.qp(), 124);
+        assert_eq!(budget.overshoots(), 1);
+        budget.update(124, 100);
+        assert_eq!(budget.qp(), 124 - BUDGET_MAX_RELAX as u8);
+        budget.update(1, 10);
+        assert_eq!(budget.qp(), 1);
+    }
+
+    #[cfg(feature = "rate-control")]
     #[test]
     fn buffer_stays_in_range() {
         let mut rc = RateControl::new(500_000, Fps::default(), 320, 240, 25);
@@ -196,9 +884,41 @@ mod tests {
             let is_key = i % 25 == 0;
             let qp = rc.compute_qp(is_key);
             let bits = if is_key { 80_000 } else { 15_000 };
-            rc.update(bits, qp);
+            rc.update(bits, qp, is_key);
             let stats = rc.stats();
             assert!(stats.buffer_fullness_pct <= 100);
         }
     }
+
+    #[cfg(feature = "rate-control")]
+    #[test]
+    fn gop_reports_split_at_keyframes() {
+        let mut rc = RateControl::new(500_000, Fps::default(), 320, 240, 4);
+        for i in 0..10 {
+            let is_key = i % 4 == 0;
+            let qp = if is_key { 40 } else { 100 };
+            let bits = if is_key { 60_000 } else { 10_000 + i as u64 };
+            rc.update(bits, qp, is_key);
+        }
+        let gops = rc.stats().gops;
+        assert_eq!(gops.len(), 3);
+        assert_eq!(
+            gops.iter().map(|g| g.first_frame).collect::<Vec<_>>(),
+            vec![0, 4, 8]
+        );
+        assert_eq!(gops[0].keyframes, 1);
+        assert_eq!(gops[0].inter_frames, 3);
+        assert_eq!(gops[0].keyframe_bits, 60_000);
+        assert_eq!(gops[0].inter_bits, 30_006);
+        assert_eq!(gops[0].peak_frame_bits, 60_000);
+        assert_eq!(gops[0].qp_histogram[1], 1);
+        assert_eq!(gops[0].qp_histogram[3], 3);
+        assert_eq!(gops[0].buffer_trajectory.len(), 4);
+        assert_eq!(gops[2].frames(), 2);
+        assert_eq!(gops[2].total_bits(), 60_000 + 10_009);
+        assert_eq!(
+            *gops[2].buffer_trajectory.last().unwrap(),
+            rc.stats().buffer_fullness_pct
+        );
+    }
 }
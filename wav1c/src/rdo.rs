@@ -49,3 +49,114 @@ pub fn estimate_tx_type_bits(tx_type: TxType) -> u32 {
 pub fn estimate_partition_bits(is_split: bool) -> u32 {
     if is_split { 12 } else { 4 }
 }
+
+/// Bit costs below are fixed point with `COST_SHIFT` fractional bits.
+pub const COST_SHIFT: u32 = 8;
+
+/// Cost of coding an event of probability `p / 32768`, i.e. `-log2(p / 32768)`
+/// in 1/256 bit units.
+pub fn prob_cost(p: u32) -> u32 {
+    let p = p.clamp(1, 32768);
+    let msb = 31 - p.leading_zeros();
+    // Normalize to Q15 in [1, 2) and extract 8 fractional bits of log2 by
+    // repeated squaring.
+    let mut x = p << (15 - msb);
+    let mut frac = 0u32;
+    for bit in (0..COST_SHIFT).rev() {
+        x = (x * x) >> 15;
+        if x >= 2 << 15 {
+            x >>= 1;
+            frac |= 1 << bit;
+        }
+    }
+    (15 << COST_SHIFT) - ((msb << COST_SHIFT) + frac)
+}
+
+/// Cost of `MsacEncoder::encode_bool` with the CDF's current state.
+pub fn bool_cost(cdf: &[u16], val: bool) -> u32 {
+    let f = cdf[0] as u32;
+    prob_cost(if val { f } else { 32768 - f })
+}
+
+/// Cost of `MsacEncoder::encode_symbol` with the CDF's current state.
+pub fn symbol_cost(cdf: &[u16], symbol: u32, n_symbols: u32) -> u32 {
+    let s = symbol as usize;
+    let fl = if s > 0 { cdf[s - 1] as u32 } else { 32768 };
+    let fh = if symbol < n_symbols { cdf[s] as u32 } else { 0 };
+    prob_cost(fl.saturating_sub(fh))
+}
+
+/// Approximate cost of a quantized coefficient level (magnitude + sign) in
+/// 1/256 bit units, modelled on the base/BR/Golomb level coding.
+const COEFF_LEVEL_COST: [u32; 15] = [
+    0, 640, 896, 1152, 1280, 1408, 1472, 1536, 1600, 1664, 1728, 1792, 1856, 1920, 1984,
+];
+
+pub fn estimate_coeff_cost(level: u32) -> u32 {
+    match COEFF_LEVEL_COST.get(level as usize) {
+        Some(&cost) => cost,
+        None => {
+            let golomb = level - (COEFF_LEVEL_COST.len() as u32 - 1);
+            COEFF_LEVEL_COST[COEFF_LEVEL_COST.len() - 1] + ((2 * golomb.ilog2() + 1) << COST_SHIFT)
+        }
+    }
+}
+
+/// Cost of signaling a motion vector component difference (in 1/8 pel).
+pub fn estimate_mv_component_cost(diff: i32) -> u32 {
+    if diff == 0 {
+        1 << COST_SHIFT
+    } else {
+        (2 * (diff.unsigned_abs() + 1).ilog2() + 3) << COST_SHIFT
+    }
+}
+
+/// Lagrangian multiplier for pixel-domain SSE against 1/256 bit costs.
+///
+/// The quantizer step in the pixel domain is about `ac_dq / 8`, and the
+/// classic high-rate model gives `lambda ~= 0.2 * step^2` per bit.
+pub fn lambda_for_ac_dq(ac_dq: u32) -> u64 {
+    let ac = ac_dq as u64;
+    ((ac * ac * 3) >> 10).max(1)
+}
+
+/// `J = D + lambda * R` with `R` in 1/256 bit units, returned in the same
+/// fixed-point scale (distortion is shifted up by `COST_SHIFT`).
+pub fn rd_cost_q8(distortion: u64, cost: u64, lambda: u64) -> u64 {
+    (distortion << COST_SHIFT) + lambda * cost
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prob_cost_matches_log2() {
+        assert_eq!(prob_cost(32768), 0);
+        assert_eq!(prob_cost(16384), 1 << COST_SHIFT);
+        assert_eq!(prob_cost(8192), 2 << COST_SHIFT);
+        // -log2(0.75) ~= 0.415 bits
+        let cost = prob_cost(24576);
+        assert!((104..=108).contains(&cost), "cost={cost}");
+        assert_eq!(prob_cost(0), prob_cost(1));
+    }
+
+    #[test]
+    fn bool_cost_follows_cdf_probability() {
+        // cdf[0] is the probability of `true` in 1/32768 units.
+        let cdf = [28672u16, 0];
+        assert!(bool_cost(&cdf, true) < bool_cost(&cdf, false));
+        assert_eq!(bool_cost(&cdf, false), 3 << COST_SHIFT);
+    }
+
+    #[test]
+    fn coeff_cost_grows_with_level() {
+        assert_eq!(estimate_coeff_cost(0), 0);
+        let mut prev = 0;
+        for level in 1..64 {
+            let cost = estimate_coeff_cost(level);
+            assert!(cost >= prev, "level {level}");
+            prev = cost;
+        }
+    }
+}
@@ -0,0 +1,739 @@
+//! Wiener loop restoration (spec 7.17).
+
+use crate::frame::TileRect;
+use crate::msac::MsacEncoder;
+use crate::y4m::FramePixels;
+
+const WIENER_TAPS_MIN: [i32; 3] = [-5, -23, -17];
+const WIENER_TAPS_MAX: [i32; 3] = [10, 8, 46];
+const WIENER_TAPS_K: [u32; 3] = [1, 2, 3];
+const WIENER_TAPS_MID: [i32; 3] = [3, -7, 15];
+
+/// `InterRound0` and `InterRound1` of 8- and 10-bit Wiener filtering.
+const ROUND0: u32 = 3;
+const ROUND1: u32 = 11;
+
+const STRIPE_HEIGHT: usize = 64;
+const STRIPE_OFFSET: usize = 8;
+
+/// Outer taps of the vertical and horizontal passes, outermost first.
+pub type WienerTaps = [[i32; 3]; 2];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlaneUnits {
+    pub size: usize,
+    pub cols: usize,
+    pub rows: usize,
+    pub taps: Vec<Option<WienerTaps>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RestorationPlan {
+    pub unit_shift: u8,
+    pub planes: [Option<PlaneUnits>; 3],
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PlaneGeometry {
+    width: usize,
+    height: usize,
+    sub_y: u32,
+    unit_size: usize,
+    cols: usize,
+    rows: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct StripeArea {
+    x0: usize,
+    x1: usize,
+    y0: usize,
+    y1: usize,
+    top: isize,
+    bottom: isize,
+}
+
+fn count_units(unit_size: usize, plane_size: usize) -> usize {
+    ((plane_size + unit_size / 2) / unit_size).max(1)
+}
+
+impl PlaneGeometry {
+    fn new(frame: &FramePixels, plane: usize, unit_shift: u8) -> Self {
+        let luma_unit = 64usize << unit_shift;
+        let (width, height, sub_y, unit_size) = if plane == 0 {
+            (frame.width as usize, frame.height as usize, 0, luma_unit)
+        } else {
+            (
+                frame.width.div_ceil(2) as usize,
+                frame.height.div_ceil(2) as usize,
+                1,
+                luma_unit >> 1,
+            )
+        };
+        Self {
+            width,
+            height,
+            sub_y,
+            unit_size,
+            cols: count_units(unit_size, width),
+            rows: count_units(unit_size, height),
+        }
+    }
+
+    fn unit_stripes(&self, row: usize, col: usize) -> Vec<StripeArea> {
+        let offset = STRIPE_OFFSET >> self.sub_y;
+        let stripe_height = STRIPE_HEIGHT >> self.sub_y;
+        let x0 = col * self.unit_size;
+        let x1 = if col + 1 == self.cols {
+            self.width
+        } else {
+            x0 + self.unit_size
+        };
+        let mut y = (row * self.unit_size).saturating_sub(offset);
+        let end = if row + 1 == self.rows {
+            self.height
+        } else {
+            (row + 1) * self.unit_size - offset
+        };
+        let mut areas = Vec::new();
+        while y < end {
+            let top = ((y + offset) / stripe_height * stripe_height) as isize - offset as isize;
+            let bottom = top + stripe_height as isize - 1;
+            let y1 = ((bottom + 1) as usize).min(end);
+            areas.push(StripeArea {
+                x0,
+                x1,
+                y0: y,
+                y1,
+                top,
+                bottom,
+            });
+            y = y1;
+        }
+        areas
+    }
+}
+
+fn plane_samples(frame: &FramePixels, plane: usize) -> &[u16] {
+    match plane {
+        0 => &frame.y,
+        1 => &frame.u,
+        _ => &frame.v,
+    }
+}
+
+fn plane_samples_mut(frame: &mut FramePixels, plane: usize) -> &mut [u16] {
+    match plane {
+        0 => &mut frame.y,
+        1 => &mut frame.u,
+        _ => &mut frame.v,
+    }
+}
+
+fn full_filter(outer: [i32; 3]) -> [i32; 7] {
+    let [a, b, c] = outer;
+    [a, b, c, 128 - 2 * (a + b + c), c, b, a]
+}
+
+fn round2(x: i32, n: u32) -> i32 {
+    (x + (1 << (n - 1))) >> n
+}
+
+fn wiener_stripe(
+    out: &mut [u16],
+    cdef: &[u16],
+    deblocked: &[u16],
+    geometry: &PlaneGeometry,
+    area: StripeArea,
+    taps: &WienerTaps,
+    max_value: u16,
+) {
+    let width = geometry.width;
+    let w = area.x1 - area.x0;
+    let h = area.y1 - area.y0;
+    let vfilter = full_filter(taps[0]);
+    let hfilter = full_filter(taps[1]);
+    let bit_depth = 16 - max_value.leading_zeros() as i32;
+    let offset = 1 << (bit_depth + 7 - ROUND0 as i32 - 1);
+    let limit = (1 << (bit_depth + 1 + 7 - ROUND0 as i32)) - 1;
+
+    let mut intermediate = vec![0i32; (h + 6) * w];
+    for r in 0..h + 6 {
+        let y = (area.y0 as isize + r as isize - 3).clamp(0, geometry.height as isize - 1);
+        let row = if y < area.top {
+            &deblocked[(y.max(area.top - 2) as usize) * width..]
+        } else if y > area.bottom {
+            &deblocked[(y.min(area.bottom + 2) as usize) * width..]
+        } else {
+            &cdef[y as usize * width..]
+        };
+        for c in 0..w {
+            let mut s = 0i32;
+            for (t, &f) in hfilter.iter().enumerate() {
+                let x = (area.x0 + c + t).saturating_sub(3).min(width - 1);
+                s += f * row[x] as i32;
+            }
+            intermediate[r * w + c] = round2(s, ROUND0).clamp(-offset, limit - offset);
+        }
+    }
+    for r in 0..h {
+        let out_row = &mut out[(area.y0 + r) * width + area.x0..][..w];
+        for (c, sample) in out_row.iter_mut().enumerate() {
+            let s: i32 = vfilter
+                .iter()
+                .enumerate()
+                .map(|(t, &f)| f * intermediate[(r + t) * w + c])
+                .sum();
+            *sample = round2(s, ROUND1).clamp(0, max_value as i32) as u16;
+        }
+    }
+}
+
+fn area_sse(a: &[u16], b: &[u16], width: usize, areas: &[StripeArea]) -> u64 {
+    let mut sse = 0u64;
+    for area in areas {
+        for y in area.y0..area.y1 {
+            let row = y * width;
+            sse += a[row + area.x0..row + area.x1]
+                .iter()
+                .zip(&b[row + area.x0..row + area.x1])
+                .map(|(&x, &y)| (x as i64 - y as i64).pow(2) as u64)
+                .sum::<u64>();
+        }
+    }
+    sse
+}
+
+fn determinant(m: &[[i128; 3]; 3], n: usize) -> i128 {
+    if n == 2 {
+        return m[0][0] * m[1][1] - m[0][1] * m[1][0];
+    }
+    m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+        - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+}
+
+fn div_round(n: i128, d: i128) -> i128 {
+    let (n, d) = if d < 0 { (-n, -d) } else { (n, d) };
+    if n >= 0 {
+        (n + d / 2) / d
+    } else {
+        -((-n + d / 2) / d)
+    }
+}
+
+#[derive(Default)]
+struct TapStats {
+    a: [[i64; 3]; 3],
+    b: [i64; 3],
+}
+
+impl TapStats {
+    fn add(&mut self, pairs: [i64; 3], centre: i64, target: i64) {
+        let d = pairs.map(|p| p - 2 * centre);
+        let e = target - centre;
+        for i in 0..3 {
+            for j in 0..3 {
+                self.a[i][j] += d[i] * d[j];
+            }
+            self.b[i] += d[i] * e;
+        }
+    }
+
+    fn solve(&self, first: usize) -> [i32; 3] {
+        let n = 3 - first;
+        let mut m = [[0i128; 3]; 3];
+        let mut v = [0i128; 3];
+        for (i, (row, value)) in m.iter_mut().zip(&mut v).take(n).enumerate() {
+            for (j, x) in row.iter_mut().take(n).enumerate() {
+                *x = self.a[first + i][first + j] as i128;
+            }
+            *value = self.b[first + i] as i128;
+        }
+        let largest = m.iter().flatten().chain(&v).map(|x| x.unsigned_abs()).max();
+        let shift = (128 - largest.unwrap_or(0).leading_zeros()).saturating_sub(36);
+        for x in m.iter_mut().flatten().chain(v.iter_mut()) {
+            *x >>= shift;
+        }
+        let det = determinant(&m, n);
+        let mut taps = [0i32; 3];
+        if det == 0 {
+            return taps;
+        }
+        for i in 0..n {
+            let mut mi = m;
+            for (row, &value) in mi.iter_mut().zip(&v).take(n) {
+                row[i] = value;
+            }
+            let j = first + i;
+            let tap = div_round(128 * determinant(&mi, n), det);
+            taps[j] = tap.clamp(WIENER_TAPS_MIN[j] as i128, WIENER_TAPS_MAX[j] as i128) as i32;
+        }
+        taps
+    }
+}
+
+fn solve_taps(
+    cdef: &[u16],
+    source: &[u16],
+    geometry: &PlaneGeometry,
+    areas: &[StripeArea],
+    first: usize,
+) -> WienerTaps {
+    let (x0, x1) = (areas[0].x0, areas[0].x1);
+    let (y0, y1) = (areas[0].y0, areas[areas.len() - 1].y1);
+    let (w, h) = (x1 - x0, y1 - y0);
+    let ew = w + 6;
+    let sample = |x: usize, y: usize| -> i64 {
+        let x = x.saturating_sub(3).min(geometry.width - 1);
+        let y = y.saturating_sub(3).min(geometry.height - 1);
+        cdef[y * geometry.width + x] as i64
+    };
+    let extended: Vec<i64> = (0..h + 6)
+        .flat_map(|r| (0..ew).map(move |c| (r, c)))
+        .map(|(r, c)| sample(x0 + c, y0 + r))
+        .collect();
+    let target = |r: usize, c: usize| 128 * source[(y0 + r) * geometry.width + x0 + c] as i64;
+
+    let mut taps = [[0i32; 3]; 2];
+    for _ in 0..2 {
+        let vfilter = full_filter(taps[0]).map(i64::from);
+        let vertical: Vec<i64> = (0..h)
+            .flat_map(|r| (0..ew).map(move |c| (r, c)))
+            .map(|(r, c)| {
+                (0..7)
+                    .map(|t| vfilter[t] * extended[(r + t) * ew + c])
+                    .sum()
+            })
+            .collect();
+        let mut stats = TapStats::default();
+        for r in 0..h {
+            let row = &vertical[r * ew..][..ew];
+            for c in 0..w {
+                let pairs = [0, 1, 2].map(|i| row[c + i] + row[c + 6 - i]);
+                stats.add(pairs, row[c + 3], target(r, c));
+            }
+        }
+        taps[1] = stats.solve(first);
+
+        let hfilter = full_filter(taps[1]).map(i64::from);
+        let horizontal: Vec<i64> = (0..h + 6)
+            .flat_map(|r| (0..w).map(move |c| (r, c)))
+            .map(|(r, c)| (0..7).map(|t| hfilter[t] * extended[r * ew + c + t]).sum())
+            .collect();
+        let mut stats = TapStats::default();
+        for r in 0..h {
+            for c in 0..w {
+                let at = |dy: usize| horizontal[(r + dy) * w + c];
+                let pairs = [0, 1, 2].map(|i| at(i) + at(6 - i));
+                stats.add(pairs, at(3), target(r, c));
+            }
+        }
+        taps[0] = stats.solve(first);
+    }
+    taps
+}
+
+fn recenter(reference: u32, value: u32) -> u32 {
+    if value > 2 * reference {
+        value
+    } else if value >= reference {
+        (value - reference) * 2
+    } else {
+        (reference - value) * 2 - 1
+    }
+}
+
+fn signed_subexp_literals(
+    low: i32,
+    high: i32,
+    k: u32,
+    reference: i32,
+    value: i32,
+) -> Vec<(u32, u32)> {
+    let mx = (high - low) as u32;
+    let r = (reference - low) as u32;
+    let v = (value - low) as u32;
+    let x = if r * 2 <= mx {
+        recenter(r, v)
+    } else {
+        recenter(mx - 1 - r, mx - 1 - v)
+    };
+    let mut literals = Vec::new();
+    let mut i = 0;
+    let mut mk = 0;
+    loop {
+        let b2 = if i > 0 { k + i - 1 } else { k };
+        let a = 1 << b2;
+        if mx <= mk + 3 * a {
+            let n = mx - mk;
+            let w = 32 - n.leading_zeros();
+            let m = (1 << w) - n;
+            let v = x - mk;
+            if v < m {
+                literals.push((v, w - 1));
+            } else {
+                literals.push(((v + m) >> 1, w - 1));
+                literals.push(((v + m) & 1, 1));
+            }
+            return literals;
+        }
+        let more = x >= mk + a;
+        literals.push((more as u32, 1));
+        if !more {
+            literals.push((x - mk, b2));
+            return literals;
+        }
+        i += 1;
+        mk += a;
+    }
+}
+
+fn taps_literals(taps: &WienerTaps, reference: &WienerTaps, first: usize) -> Vec<(u32, u32)> {
+    let mut literals = Vec::new();
+    for pass in 0..2 {
+        for j in first..3 {
+            literals.extend(signed_subexp_literals(
+                WIENER_TAPS_MIN[j],
+                WIENER_TAPS_MAX[j] + 1,
+                WIENER_TAPS_K[j],
+                reference[pass][j],
+                taps[pass][j],
+            ));
+        }
+    }
+    literals
+}
+
+fn taps_bits(taps: &WienerTaps, reference: &WienerTaps, first: usize) -> u32 {
+    taps_literals(taps, reference, first)
+        .iter()
+        .map(|&(_, bits)| bits)
+        .sum()
+}
+
+const REFERENCE_TAPS: WienerTaps = [WIENER_TAPS_MID; 2];
+
+impl RestorationPlan {
+    /// Filters a unit only where the error it saves against `source`
+    /// outweighs `lambdas[plane]` times the bits of its taps.
+    pub fn search(
+        deblocked: &FramePixels,
+        cdef: &FramePixels,
+        source: &FramePixels,
+        lambdas: [u64; 3],
+    ) -> Self {
+        let unit_shift = if deblocked.width * deblocked.height > 352 * 288 {
+            2
+        } else {
+            1
+        };
+        let max_value = cdef.bit_depth.max_value();
+        let flag_cost =
+            |on: bool| crate::rdo::bool_cost(&crate::cdf::DEFAULT_USE_WIENER_CDF, on) as u64;
+        let mut planes: [Option<PlaneUnits>; 3] = [None, None, None];
+        for (plane, units) in planes.iter_mut().enumerate() {
+            let geometry = PlaneGeometry::new(cdef, plane, unit_shift);
+            let first = usize::from(plane > 0);
+            let cdef_plane = plane_samples(cdef, plane);
+            let deblocked_plane = plane_samples(deblocked, plane);
+            let source_plane = plane_samples(source, plane);
+            let mut scratch = cdef_plane.to_vec();
+            let mut reference = REFERENCE_TAPS.map(|pass| {
+                let mut pass = pass;
+                pass[..first].fill(0);
+                pass
+            });
+            let mut taps = Vec::with_capacity(geometry.rows * geometry.cols);
+            for row in 0..geometry.rows {
+                for col in 0..geometry.cols {
+                    let areas = geometry.unit_stripes(row, col);
+                    let unfiltered = area_sse(cdef_plane, source_plane, geometry.width, &areas);
+                    let mut best = (
+                        crate::rdo::rd_cost_q8(unfiltered, flag_cost(false), lambdas[plane]),
+                        None,
+                    );
+                    let solved = solve_taps(cdef_plane, source_plane, &geometry, &areas, first);
+                    let mut candidates = vec![solved];
+                    if reference != solved {
+                        candidates.push(reference);
+                    }
+                    for candidate in candidates {
+                        for &area in &areas {
+                            wiener_stripe(
+                                &mut scratch,
+                                cdef_plane,
+                                deblocked_plane,
+                                &geometry,
+                                area,
+                                &candidate,
+                                max_value,
+                            );
+                        }
+                        let sse = area_sse(&scratch, source_plane, geometry.width, &areas);
+                        let bits = taps_bits(&candidate, &reference, first) as u64;
+                        let cost = flag_cost(true) + (bits << crate::rdo::COST_SHIFT);
+                        let rd = crate::rdo::rd_cost_q8(sse, cost, lambdas[plane]);
+                        if rd < best.0 {
+                            best = (rd, Some(candidate));
+                        }
+                    }
+                    if let Some(chosen) = best.1 {
+                        reference = chosen;
+                    }
+                    taps.push(best.1);
+                }
+            }
+            if taps.iter().any(Option::is_some) {
+                *units = Some(PlaneUnits {
+                    size: geometry.unit_size,
+                    cols: geometry.cols,
+                    rows: geometry.rows,
+                    taps,
+                });
+            }
+        }
+        Self { unit_shift, planes }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.planes.iter().any(Option::is_some)
+    }
+
+    /// `deblocked` is the reconstruction before CDEF.
+    pub fn apply(&self, deblocked: &FramePixels, frame: &mut FramePixels) {
+        let max_value = frame.bit_depth.max_value();
+        for (plane, units) in self.planes.iter().enumerate() {
+            let Some(units) = units else {
+                continue;
+            };
+            let geometry = PlaneGeometry::new(frame, plane, self.unit_shift);
+            let cdef_plane = plane_samples(frame, plane).to_vec();
+            let out = plane_samples_mut(frame, plane);
+            for row in 0..units.rows {
+                for col in 0..units.cols {
+                    let Some(taps) = &units.taps[row * units.cols + col] else {
+                        continue;
+                    };
+                    for area in geometry.unit_stripes(row, col) {
+                        wiener_stripe(
+                            out,
+                            &cdef_plane,
+                            plane_samples(deblocked, plane),
+                            &geometry,
+                            area,
+                            taps,
+                            max_value,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RestorationSignal {
+    plan: RestorationPlan,
+    sb_col_start: u32,
+    sb_row_start: u32,
+    reference: [WienerTaps; 3],
+}
+
+impl RestorationSignal {
+    pub fn for_tile(plan: &RestorationPlan, rect: &TileRect) -> Self {
+        Self {
+            plan: plan.clone(),
+            sb_col_start: rect.sb_col_start,
+            sb_row_start: rect.sb_row_start,
+            reference: [REFERENCE_TAPS; 3],
+        }
+    }
+
+    pub fn write_superblock(
+        &mut self,
+        enc: &mut MsacEncoder,
+        cdf: &mut [u16],
+        sb_col: u32,
+        sb_row: u32,
+    ) {
+        for (plane, units) in self.plan.planes.iter().enumerate() {
+            let Some(units) = units else {
+                continue;
+            };
+            let sub = usize::from(plane > 0);
+            let first = sub;
+            let sb_size = 64 >> sub;
+            let starts = |sb: u32, count: usize| {
+                let start = (sb as usize * sb_size).div_ceil(units.size);
+                let end = ((sb as usize + 1) * sb_size)
+                    .div_ceil(units.size)
+                    .min(count);
+                start..end
+            };
+            let rows = starts(self.sb_row_start + sb_row, units.rows);
+            let cols = starts(self.sb_col_start + sb_col, units.cols);
+            for row in rows {
+                for col in cols.clone() {
+                    let taps = &units.taps[row * units.cols + col];
+                    enc.encode_bool(taps.is_some(), cdf);
+                    let Some(taps) = taps else {
+                        continue;
+                    };
+                    for (value, bits) in taps_literals(taps, &self.reference[plane], first) {
+                        for bit in (0..bits).rev() {
+                            enc.encode_bool_equi((value >> bit) & 1 == 1);
+                        }
+                    }
+                    self.reference[plane] = *taps;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_signed_subexp(
+        literals: &[(u32, u32)],
+        low: i32,
+        high: i32,
+        k: u32,
+        reference: i32,
+    ) -> i32 {
+        let mut bits = literals
+            .iter()
+            .flat_map(|&(value, n)| (0..n).rev().map(move |b| (value >> b) & 1));
+        let mut read = |n: u32| (0..n).fold(0u32, |x, _| (x << 1) | bits.next().unwrap());
+        let mx = (high - low) as u32;
+        let r = (reference - low) as u32;
+        let (mut i, mut mk) = (0, 0);
+        let v = loop {
+            let b2 = if i > 0 { k + i - 1 } else { k };
+            let a = 1 << b2;
+            if mx <= mk + 3 * a {
+                let n = mx - mk;
+                let w = 32 - n.leading_zeros();
+                let m = (1 << w) - n;
+                let v = read(w - 1);
+                break if v < m { v } else { (v << 1) - m + read(1) } + mk;
+            }
+            if read(1) == 0 {
+                break read(b2) + mk;
+            }
+            i += 1;
+            mk += a;
+        };
+        let inverse_recenter = |r: u32, v: u32| {
+            if v > 2 * r {
+                v
+            } else if v & 1 == 1 {
+                r - ((v + 1) >> 1)
+            } else {
+                r + (v >> 1)
+            }
+        };
+        let x = if r * 2 <= mx {
+            inverse_recenter(r, v)
+        } else {
+            mx - 1 - inverse_recenter(mx - 1 - r, v)
+        };
+        x as i32 + low
+    }
+
+    #[test]
+    fn subexp_literals_decode_to_every_tap_value() {
+        for j in 0..3 {
+            let (low, high) = (WIENER_TAPS_MIN[j], WIENER_TAPS_MAX[j] + 1);
+            for reference in low..high {
+                for value in low..high {
+                    let literals =
+                        signed_subexp_literals(low, high, WIENER_TAPS_K[j], reference, value);
+                    let decoded =
+                        decode_signed_subexp(&literals, low, high, WIENER_TAPS_K[j], reference);
+                    assert_eq!(decoded, value, "tap {j} reference {reference}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn identity_taps_leave_the_frame_unchanged() {
+        let mut frame = FramePixels::solid(80, 72, 0, 0, 0);
+        for (i, s) in frame.y.iter_mut().enumerate() {
+            *s = ((i * 37 + i / 80 * 11) % 220 + 16) as u16;
+        }
+        let mut filtered = frame.clone();
+        let units = |size, cols, rows| PlaneUnits {
+            size,
+            cols,
+            rows,
+            taps: vec![Some([[0; 3]; 2]); cols * rows],
+        };
+        let plan = RestorationPlan {
+            unit_shift: 0,
+            planes: [Some(units(64, 1, 1)), Some(units(32, 1, 1)), None],
+        };
+        plan.apply(&frame.clone(), &mut filtered);
+        assert_eq!(filtered.y, frame.y);
+        assert_eq!(filtered.u, frame.u);
+    }
+
+    #[test]
+    fn unit_stripes_cover_each_plane_row_once() {
+        let frame = FramePixels::solid(200, 300, 0, 0, 0);
+        for plane in 0..3 {
+            let geometry = PlaneGeometry::new(&frame, plane, 1);
+            let mut covered = vec![0u32; geometry.width * geometry.height];
+            for row in 0..geometry.rows {
+                for col in 0..geometry.cols {
+                    for area in geometry.unit_stripes(row, col) {
+                        assert!(
+                            area.top <= area.y0 as isize && area.y1 as isize <= area.bottom + 1
+                        );
+                        for y in area.y0..area.y1 {
+                            for x in area.x0..area.x1 {
+                                covered[y * geometry.width + x] += 1;
+                            }
+                        }
+                    }
+                }
+            }
+            assert!(covered.iter().all(|&n| n == 1), "plane {plane}");
+        }
+    }
+
+    #[test]
+    fn search_filters_a_blurred_frame_towards_its_source() {
+        let mut source = FramePixels::solid(128, 128, 0, 128, 128);
+        let mut state = 0x1234_5678u32;
+        for s in source.y.iter_mut() {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            *s = 60 + ((state >> 16) % 120) as u16;
+        }
+        let mut blurred = source.clone();
+        for y in 0..128 {
+            for x in 0..128 {
+                let at = |x: usize| source.y[y * 128 + x.min(127)] as u32;
+                blurred.y[y * 128 + x] =
+                    ((at(x.saturating_sub(1)) + 6 * at(x) + at(x + 1) + 4) / 8) as u16;
+            }
+        }
+        let plan = RestorationPlan::search(&blurred, &blurred, &source, [1; 3]);
+        assert!(plan.planes[0].is_some());
+        assert!(plan.planes[1].is_none(), "flat chroma needs no filter");
+        let mut restored = blurred.clone();
+        plan.apply(&blurred, &mut restored);
+        let sse = |frame: &FramePixels| -> u64 {
+            frame
+                .y
+                .iter()
+                .zip(&source.y)
+                .map(|(&a, &b)| (a as i64 - b as i64).pow(2) as u64)
+                .sum()
+        };
+        assert!(sse(&restored) < sse(&blurred) * 3 / 4);
+    }
+}
@@ -22,6 +22,29 @@ pub const DEFAULT_SCAN_16X16: [u16; 256] = [
     222, 207, 223, 238, 253, 254, 239, 255,
 ];
 
+/// The zig-zag order of every square default scan.
+const fn zigzag_scan<const LEN: usize>(n: usize) -> [u16; LEN] {
+    let mut scan = [0u16; LEN];
+    let mut i = 0;
+    let mut d = 0;
+    while d < 2 * n - 1 {
+        let first = if d >= n { d - n + 1 } else { 0 };
+        let last = if d < n { d } else { n - 1 };
+        let mut k = 0;
+        while k <= last - first {
+            let r = if d % 2 == 1 { last - k } else { first + k };
+            scan[i] = (r * n + d - r) as u16;
+            i += 1;
+            k += 1;
+        }
+        d += 1;
+    }
+    scan
+}
+
+/// Also the scan of the 32x32 coefficients coded for a 64x64 transform.
+pub const DEFAULT_SCAN_32X32: [u16; 1024] = zigzag_scan(32);
+
 pub const LO_CTX_OFFSETS_2D: [[u8; 5]; 5] = [
     [0, 1, 6, 6, 21],
     [1, 6, 6, 21, 21],
@@ -96,4 +119,17 @@ mod tests {
         }
         assert!(seen.iter().all(|&s| s));
     }
+
+    #[test]
+    fn zigzag_generator_matches_the_literal_scans() {
+        assert_eq!(zigzag_scan::<16>(4), DEFAULT_SCAN_4X4);
+        assert_eq!(zigzag_scan::<64>(8), DEFAULT_SCAN_8X8);
+        assert_eq!(zigzag_scan::<256>(16), DEFAULT_SCAN_16X16);
+        assert_eq!(&DEFAULT_SCAN_32X32[..6], &[0, 32, 1, 2, 33, 64]);
+        let mut seen = [false; 1024];
+        for &pos in &DEFAULT_SCAN_32X32 {
+            assert!(!seen[pos as usize]);
+            seen[pos as usize] = true;
+        }
+    }
 }
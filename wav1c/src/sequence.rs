@@ -3,76 +3,7 @@ use crate::fps::Fps;
 use crate::video::{BitDepth, ColorRange, VideoSignal};
 
 pub const SEQ_LEVEL_IDX_5_1: u8 = 13;
-pub const SEQ_LEVEL_IDX_MAX_PARAMETERS: u8 = 31;
-
-#[derive(Clone, Copy)]
-struct LevelConstraint {
-    seq_level_idx: u8,
-    max_pic_size: u64,
-    max_h_size: u32,
-    max_v_size: u32,
-    max_display_rate: u64,
-    max_decode_rate: u64,
-}
-
-const LEVEL_CONSTRAINTS: [LevelConstraint; 7] = [
-    LevelConstraint {
-        seq_level_idx: 13, // 5.1
-        max_pic_size: 8_912_896,
-        max_h_size: 8_192,
-        max_v_size: 4_352,
-        max_display_rate: 534_773_760,
-        max_decode_rate: 547_430_400,
-    },
-    LevelConstraint {
-        seq_level_idx: 14, // 5.2
-        max_pic_size: 8_912_896,
-        max_h_size: 8_192,
-        max_v_size: 4_352,
-        max_display_rate: 1_069_547_520,
-        max_decode_rate: 1_094_860_800,
-    },
-    LevelConstraint {
-        seq_level_idx: 15, // 5.3
-        max_pic_size: 8_912_896,
-        max_h_size: 8_192,
-        max_v_size: 4_352,
-        max_display_rate: 1_069_547_520,
-        max_decode_rate: 1_176_502_272,
-    },
-    LevelConstraint {
-        seq_level_idx: 16, // 6.0
-        max_pic_size: 35_651_584,
-        max_h_size: 16_384,
-        max_v_size: 8_704,
-        max_display_rate: 1_069_547_520,
-        max_decode_rate: 1_176_502_272,
-    },
-    LevelConstraint {
-        seq_level_idx: 17, // 6.1
-        max_pic_size: 35_651_584,
-        max_h_size: 16_384,
-        max_v_size: 8_704,
-        max_display_rate: 2_139_095_040,
-        max_decode_rate: 2_189_721_600,
-    },
-    LevelConstraint {
-        seq_level_idx: 18, // 6.2
-        max_pic_size: 35_651_584,
-        max_h_size: 16_384,
-        max_v_size: 8_704,
-        max_display_rate: 4_278_190_080,
-        max_decode_rate: 4_379_443_200,
-    },
-    LevelConstraint {
-        seq_level_idx: 19, // 6.3
-        max_pic_size: 35_651_584,
-        max_h_size: 16_384,
-        max_v_size: 8_704,
-        max_display_rate: 4_278_190_080,
-        max_decode_rate: 4_706_009_088,
-    },
-];
+pub use crate::limits::SEQ_LEVEL_IDX_MAX_PARAMETERS;
 
 fn bits_needed(v: u32) -> u8 {
     if v == 0 {
@@ -83,24 +14,45 @@ fn bits_needed(v: u32) -> u8 {
 }
 
 pub fn derive_sequence_level_idx(width: u32, height: u32, fps: Fps) -> u8 {
-    let pic_size = width as u64 * height as u64;
-    let display_rate_num = pic_size as u128 * fps.num as u128;
-    let display_rate_den = fps.den as u128;
-
-    for level in LEVEL_CONSTRAINTS {
-        let max_display_rate = level.max_display_rate as u128;
-        let max_decode_rate = level.max_decode_rate as u128;
-        if width <= level.max_h_size
-            && height <= level.max_v_size
-            && pic_size <= level.max_pic_size
-            && display_rate_num <= max_display_rate * display_rate_den
-            && display_rate_num <= max_decode_rate * display_rate_den
-        {
-            return level.seq_level_idx.max(SEQ_LEVEL_IDX_5_1);
-        }
-    }
+    crate::limits::level_for(width, height, fps)
+}
 
-    SEQ_LEVEL_IDX_MAX_PARAMETERS
+/// Builds an `AV1CodecConfigurationRecord` (the payload of an ISOBMFF
+/// `av1C` box, also Matroska's `CodecPrivate`) for a stream whose sequence
+/// headers are written by [`encode_sequence_header_with_options`] with
+/// `signal` and `seq_level_idx`. `config_obus` follows the four fixed bytes.
+pub fn codec_configuration_record(
+    signal: &VideoSignal,
+    seq_level_idx: u8,
+    config_obus: &[u8],
+) -> Vec<u8> {
+    let mut w = BitWriter::new();
+    let seq_profile = 0u64;
+    let seq_tier = false;
+    let twelve_bit = false;
+    let mono_chrome = false;
+    let (chroma_subsampling_x, chroma_subsampling_y) = (true, true);
+    let chroma_sample_position = signal.chroma_sample_position.code() as u64;
+    let initial_presentation_delay_present = false;
+
+    w.write_bit(true);
+    w.write_bits(1, 7);
+    w.write_bits(seq_profile, 3);
+    w.write_bits(seq_level_idx as u64, 5);
+    w.write_bit(seq_tier);
+    w.write_bit(signal.bit_depth == BitDepth::Ten);
+    w.write_bit(twelve_bit);
+    w.write_bit(mono_chrome);
+    w.write_bit(chroma_subsampling_x);
+    w.write_bit(chroma_subsampling_y);
+    w.write_bits(chroma_sample_position, 2);
+    w.write_bits(0, 3);
+    w.write_bit(initial_presentation_delay_present);
+    w.write_bits(0, 4);
+
+    let mut record = w.finalize();
+    record.extend_from_slice(config_obus);
+    record
 }
 
 pub fn encode_sequence_header(width: u32, height: u32, signal: &VideoSignal) -> Vec<u8> {
@@ -117,13 +69,29 @@ pub fn encode_still_picture_sequence_header(
     encode_still_picture_sequence_header_with_level(width, height, signal, seq_level_idx)
 }
 
+/// Sequence-level coding tool switches that are not part of the signal.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SequenceOptions {
+    pub still_picture: bool,
+    /// Lets frame headers code separate V-plane delta q.
+    pub separate_uv_delta_q: bool,
+    /// Lets frame headers code loop restoration (`lr_params`).
+    pub enable_restoration: bool,
+}
+
 pub fn encode_sequence_header_with_level(
     width: u32,
     height: u32,
     signal: &VideoSignal,
     seq_level_idx: u8,
 ) -> Vec<u8> {
-    encode_sequence_header_with_level_impl(width, height, signal, seq_level_idx, false)
+    encode_sequence_header_with_options(
+        width,
+        height,
+        signal,
+        seq_level_idx,
+        &SequenceOptions::default(),
+    )
 }
 
 pub fn encode_still_picture_sequence_header_with_level(
@@ -132,20 +100,24 @@ pub fn encode_still_picture_sequence_header_with_level(
     signal: &VideoSignal,
     seq_level_idx: u8,
 ) -> Vec<u8> {
-    encode_sequence_header_with_level_impl(width, height, signal, seq_level_idx, true)
+    let options = SequenceOptions {
+        still_picture: true,
+        ..SequenceOptions::default()
+    };
+    encode_sequence_header_with_options(width, height, signal, seq_level_idx, &options)
 }
 
-fn encode_sequence_header_with_level_impl(
+pub fn encode_sequence_header_with_options(
     width: u32,
     height: u32,
     signal: &VideoSignal,
     seq_level_idx: u8,
-    still_picture_mode: bool,
+    options: &SequenceOptions,
 ) -> Vec<u8> {
     let mut w = BitWriter::new();
 
     let seq_profile = 0u64;
-    let still_picture = still_picture_mode;
+    let still_picture = options.still_picture;
     let reduced_still_picture_header = false;
     let timing_info_present = false;
     let initial_display_delay_present = false;
@@ -180,7 +152,7 @@ fn encode_sequence_header_with_level_impl(
     let enable_intra_edge_filter = false;
     let enable_superres = false;
     let enable_cdef = true;
-    let enable_restoration = false;
+    let enable_restoration = options.enable_restoration;
     if reduced_still_picture_header {
         w.write_bit(use_128x128_superblock);
         w.write_bit(enable_filter_intra);
@@ -218,8 +190,8 @@ fn encode_sequence_header_with_level_impl(
     let mono_chrome = false;
     let color_description_present = signal.color_description.is_some();
     let color_range = signal.color_range == ColorRange::Full;
-    let chroma_sample_position = 0u64;
-    let separate_uv_delta_q = false;
+    let chroma_sample_position = signal.chroma_sample_position.code() as u64;
+    let separate_uv_delta_q = options.separate_uv_delta_q;
     let film_grain_params_present = false;
 
     w.write_bit(high_bitdepth);
@@ -244,6 +216,7 @@ fn encode_sequence_header_with_level_impl(
 mod tests {
     use super::*;
     use crate::bitwriter::BitWriter;
+    use crate::video::ChromaSamplePosition;
 
     #[test]
     fn bits_needed_zero() {
@@ -380,6 +353,46 @@ mod tests {
         assert_ne!(regular, still);
     }
 
+    #[test]
+    fn codec_configuration_record_carries_level_and_bit_depth() {
+        let record = codec_configuration_record(&VideoSignal::default(), 13, &[0x0A, 0x00]);
+        assert_eq!(record, [0x81, 0x0D, 0x0C, 0x00, 0x0A, 0x00]);
+
+        let hdr10 = VideoSignal::hdr10(ColorRange::Limited);
+        let record = codec_configuration_record(&hdr10, SEQ_LEVEL_IDX_MAX_PARAMETERS, &[]);
+        assert_eq!(record, [0x81, 0x1F, 0x4C, 0x00]);
+
+        let colocated = VideoSignal {
+            chroma_sample_position: ChromaSamplePosition::Colocated,
+            ..hdr10
+        };
+        let record = codec_configuration_record(&colocated, SEQ_LEVEL_IDX_MAX_PARAMETERS, &[]);
+        assert_eq!(record, [0x81, 0x1F, 0x4E, 0x00]);
+    }
+
+    #[test]
+    fn chroma_sample_position_sets_two_color_config_bits() {
+        let level = derive_sequence_level_idx(64, 64, Fps::default());
+        let unknown = encode_sequence_header_with_level(64, 64, &VideoSignal::sdr709(), level);
+        let vertical = VideoSignal {
+            chroma_sample_position: ChromaSamplePosition::Vertical,
+            ..VideoSignal::sdr709()
+        };
+        let colocated = VideoSignal {
+            chroma_sample_position: ChromaSamplePosition::Colocated,
+            ..VideoSignal::sdr709()
+        };
+        let vertical = encode_sequence_header_with_level(64, 64, &vertical, level);
+        let colocated = encode_sequence_header_with_level(64, 64, &colocated, level);
+        let differing_bits = |a: &[u8], b: &[u8]| -> u32 {
+            a.iter().zip(b).map(|(a, b)| (a ^ b).count_ones()).sum()
+        };
+        assert_eq!(unknown.len(), vertical.len());
+        assert_eq!(differing_bits(&unknown, &vertical), 1);
+        assert_eq!(differing_bits(&unknown, &colocated), 1);
+        assert_eq!(differing_bits(&vertical, &colocated), 2);
+    }
+
     #[test]
     fn derive_level_small_frames_floor_to_5_1() {
         let level = derive_sequence_level_idx(320, 240, Fps::default());
@@ -397,4 +410,42 @@ mod tests {
         let level = derive_sequence_level_idx(20_000, 20_000, Fps::from_int(30).unwrap());
         assert_eq!(level, SEQ_LEVEL_IDX_MAX_PARAMETERS);
     }
+
+    #[test]
+    fn separate_uv_delta_q_sets_one_color_config_bit() {
+        let signal = VideoSignal::default();
+        let level = derive_sequence_level_idx(64, 64, Fps::default());
+        let plain = encode_sequence_header_with_level(64, 64, &signal, level);
+        let options = SequenceOptions {
+            separate_uv_delta_q: true,
+            ..SequenceOptions::default()
+        };
+        let separate = encode_sequence_header_with_options(64, 64, &signal, level, &options);
+        assert_eq!(plain.len(), separate.len());
+        let differing_bits: u32 = plain
+            .iter()
+            .zip(&separate)
+            .map(|(a, b)| (a ^ b).count_ones())
+            .sum();
+        assert_eq!(differing_bits, 1);
+    }
+
+    #[test]
+    fn enable_restoration_sets_one_sequence_bit() {
+        let signal = VideoSignal::default();
+        let level = derive_sequence_level_idx(64, 64, Fps::default());
+        let plain = encode_sequence_header_with_level(64, 64, &signal, level);
+        let options = SequenceOptions {
+            enable_restoration: true,
+            ..SequenceOptions::default()
+        };
+        let restored = encode_sequence_header_with_options(64, 64, &signal, level, &options);
+        assert_eq!(plain.len(), restored.len());
+        let differing_bits: u32 = plain
+            .iter()
+            .zip(&restored)
+            .map(|(a, b)| (a ^ b).count_ones())
+            .sum();
+        assert_eq!(differing_bits, 1);
+    }
 }
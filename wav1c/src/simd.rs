@@ -0,0 +1,346 @@
+//! Portable lane-parallel kernels for the encoder's hot loops.
+//!
+//! The crate forbids `unsafe`, so there are no `core::arch` intrinsics here.
+//! Each kernel instead works on fixed-width chunks with one accumulator per
+//! lane and no cross-lane dependency inside the chunk loop, which LLVM lowers
+//! to NEON on aarch64 (always available there), SSE2/AVX2 on x86_64 and
+//! SIMD128 on wasm32 built with `-C target-feature=+simd128`. Every kernel is
+//! bit-exact with the scalar definition it replaces.
+
+/// Samples per chunk: eight 16-bit samples fill a 128-bit NEON/SSE register.
+pub const LANES: usize = 8;
+
+/// Sum of absolute differences over a `width`x`height` block.
+pub fn sad(
+    source: &[u16],
+    src_stride: usize,
+    reference: &[u16],
+    ref_stride: usize,
+    width: usize,
+    height: usize,
+) -> u32 {
+    let mut acc = [0u32; LANES];
+    let mut tail = 0u32;
+    for row in 0..height {
+        let src = &source[row * src_stride..][..width];
+        let refr = &reference[row * ref_stride..][..width];
+        let mut src_chunks = src.chunks_exact(LANES);
+        let mut ref_chunks = refr.chunks_exact(LANES);
+        for (s, r) in (&mut src_chunks).zip(&mut ref_chunks) {
+            for lane in 0..LANES {
+                acc[lane] += (s[lane] as i32 - r[lane] as i32).unsigned_abs();
+            }
+        }
+        for (&s, &r) in src_chunks.remainder().iter().zip(ref_chunks.remainder()) {
+            tail += (s as i32 - r as i32).unsigned_abs();
+        }
+    }
+    acc.iter().sum::<u32>() + tail
+}
+
+/// Sum of the absolute 4x4 Hadamard coefficients of `source - prediction`
+/// for the block at the start of both slices.
+///
+/// The vertical butterflies run first, four columns at a time; the transform
+/// is separable and exact in integers, so the coefficients are the same as
+/// with the horizontal pass first.
+pub fn hadamard_4x4_abs_sum(
+    source: &[u16],
+    src_stride: usize,
+    prediction: &[u16],
+    pred_stride: usize,
+) -> u32 {
+    let row = |k: usize| -> [i32; 4] {
+        let s = &source[k * src_stride..][..4];
+        let p = &prediction[k * pred_stride..][..4];
+        std::array::from_fn(|x| s[x] as i32 - p[x] as i32)
+    };
+    let (d0, d1, d2, d3) = (row(0), row(1), row(2), row(3));
+    let lanes = |f: &dyn Fn(usize) -> i32| -> [i32; 4] { std::array::from_fn(f) };
+    let a = lanes(&|x| d0[x] + d1[x]);
+    let b = lanes(&|x| d0[x] - d1[x]);
+    let c = lanes(&|x| d2[x] + d3[x]);
+    let d = lanes(&|x| d2[x] - d3[x]);
+    let vertical = [
+        lanes(&|x| a[x] + c[x]),
+        lanes(&|x| b[x] + d[x]),
+        lanes(&|x| a[x] - c[x]),
+        lanes(&|x| b[x] - d[x]),
+    ];
+
+    let mut sum = 0u32;
+    for v in vertical {
+        let (t0, t1) = (v[0] + v[1], v[0] - v[1]);
+        let (t2, t3) = (v[2] + v[3], v[2] - v[3]);
+        sum += (t0 + t2).unsigned_abs()
+            + (t1 + t3).unsigned_abs()
+            + (t0 - t2).unsigned_abs()
+            + (t1 - t3).unsigned_abs();
+    }
+    sum
+}
+
+/// `out[i] = sum(taps[t] * src[i + t])` for an 8-tap filter; `src` holds
+/// `out.len() + 7` samples.
+pub fn convolve_8tap(src: &[i32], taps: &[i8; 8], out: &mut [i32]) {
+    let n = out.len();
+    out.fill(0);
+    for (t, &tap) in taps.iter().enumerate() {
+        let tap = tap as i32;
+        for (o, &s) in out.iter_mut().zip(&src[t..t + n]) {
+            *o += tap * s;
+        }
+    }
+}
+
+/// Vertical counterpart of [`convolve_8tap`]: `rows` holds `out_rows + 7`
+/// rows of `width` samples, and each output row is the tap-weighted sum of
+/// the eight rows starting at the same index.
+pub fn convolve_8tap_rows(rows: &[i32], width: usize, taps: &[i8; 8], out: &mut [i32]) {
+    out.fill(0);
+    for (r, out_row) in out.chunks_exact_mut(width).enumerate() {
+        for (t, &tap) in taps.iter().enumerate() {
+            let tap = tap as i32;
+            let src = &rows[(r + t) * width..][..width];
+            for (o, &s) in out_row.iter_mut().zip(src) {
+                *o += tap * s;
+            }
+        }
+    }
+}
+
+/// Forward 8x8 DCT, bit-exact with the separable row-then-column transform
+/// in `tile::dct`. Both passes run on eight columns at once; transposing the
+/// input turns the row transforms into column transforms.
+pub fn forward_dct_8x8(residual: &[i32; 64]) -> [i32; 64] {
+    let transpose = |m: &[[i32; LANES]; 8]| -> [[i32; LANES]; 8] {
+        std::array::from_fn(|r| std::array::from_fn(|c| m[c][r]))
+    };
+    let rows: [[i32; LANES]; 8] =
+        std::array::from_fn(|r| std::array::from_fn(|c| residual[r * 8 + c] << 2));
+    let mut rows_done = dct8_lanes(&transpose(&rows));
+    for v in rows_done.iter_mut().flatten() {
+        *v = (*v + 1) >> 1;
+    }
+    let coeffs = dct8_lanes(&transpose(&rows_done));
+    std::array::from_fn(|i| coeffs[i % 8][i / 8])
+}
+
+/// The 8-point forward DCT of `tile::dct` applied to every lane of `v`.
+fn dct8_lanes(v: &[[i32; LANES]; 8]) -> [[i32; LANES]; 8] {
+    let lanes = |f: &dyn Fn(usize) -> i32| -> [i32; LANES] { std::array::from_fn(f) };
+    let clip = |x: i32| x.clamp(-32768, 32767);
+    let s0 = lanes(&|x| v[0][x] + v[7][x]);
+    let s1 = lanes(&|x| v[1][x] + v[6][x]);
+    let s2 = lanes(&|x| v[2][x] + v[5][x]);
+    let s3 = lanes(&|x| v[3][x] + v[4][x]);
+    let s4 = lanes(&|x| v[3][x] - v[4][x]);
+    let s5 = lanes(&|x| v[2][x] - v[5][x]);
+    let s6 = lanes(&|x| v[1][x] - v[6][x]);
+    let s7 = lanes(&|x| v[0][x] - v[7][x]);
+
+    let e0 = lanes(&|x| s0[x] + s3[x]);
+    let e1 = lanes(&|x| s1[x] + s2[x]);
+    let e2 = lanes(&|x| s1[x] - s2[x]);
+    let e3 = lanes(&|x| s0[x] - s3[x]);
+
+    let t5 = lanes(&|x| ((s6[x] - s5[x]) * 181 + 128) >> 8);
+    let t6 = lanes(&|x| ((s6[x] + s5[x]) * 181 + 128) >> 8);
+    let t4a = lanes(&|x| clip(s4[x] + t5[x]));
+    let t5a = lanes(&|x| clip(s4[x] - t5[x]));
+    let t7a = lanes(&|x| clip(s7[x] + t6[x]));
+    let t6a = lanes(&|x| clip(s7[x] - t6[x]));
+
+    [
+        lanes(&|x| ((e0[x] + e1[x]) * 181 + 128) >> 8),
+        lanes(&|x| ((t7a[x] * (4017 - 4096) + t4a[x] * 799 + 2048) >> 12) + t7a[x]),
+        lanes(&|x| ((e3[x] * (3784 - 4096) + e2[x] * 1567 + 2048) >> 12) + e3[x]),
+        lanes(&|x| (t6a[x] * 1703 - t5a[x] * 1138 + 1024) >> 11),
+        lanes(&|x| ((e0[x] - e1[x]) * 181 + 128) >> 8),
+        lanes(&|x| (t5a[x] * 1703 + t6a[x] * 1138 + 1024) >> 11),
+        lanes(&|x| ((e3[x] * 1567 - e2[x] * (3784 - 4096) + 2048) >> 12) - e2[x]),
+        lanes(&|x| ((t7a[x] * 799 - t4a[x] * (4017 - 4096) + 2048) >> 12) - t4a[x]),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(len: usize, seed: u32, max: u32) -> Vec<u16> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                ((state >> 16) % (max + 1)) as u16
+            })
+            .collect()
+    }
+
+    fn scalar_sad(a: &[u16], sa: usize, b: &[u16], sb: usize, w: usize, h: usize) -> u32 {
+        let mut sum = 0;
+        for y in 0..h {
+            for x in 0..w {
+                sum += (a[y * sa + x] as i32 - b[y * sb + x] as i32).unsigned_abs();
+            }
+        }
+        sum
+    }
+
+    fn scalar_hadamard_abs_sum(residual: &[i32; 16]) -> u32 {
+        let mut temp = [0i32; 16];
+        for i in 0..4 {
+            let r = &residual[i * 4..i * 4 + 4];
+            let (t0, t1) = (r[0] + r[1], r[0] - r[1]);
+            let (t2, t3) = (r[2] + r[3], r[2] - r[3]);
+            temp[i * 4..i * 4 + 4].copy_from_slice(&[t0 + t2, t1 + t3, t0 - t2, t1 - t3]);
+        }
+        let mut sum = 0;
+        for j in 0..4 {
+            let (t0, t1) = (temp[j] + temp[4 + j], temp[j] - temp[4 + j]);
+            let (t2, t3) = (temp[8 + j] + temp[12 + j], temp[8 + j] - temp[12 + j]);
+            for v in [t0 + t2, t1 + t3, t0 - t2, t1 - t3] {
+                sum += v.unsigned_abs();
+            }
+        }
+        sum
+    }
+
+    #[test]
+    fn sad_matches_scalar_for_all_widths() {
+        let a = pattern(40 * 9, 1, 1023);
+        let b = pattern(40 * 9, 2, 1023);
+        for width in [1, 4, 7, 8, 9, 16, 23, 32] {
+            assert_eq!(
+                sad(&a, 40, &b, 36, width, 9),
+                scalar_sad(&a, 40, &b, 36, width, 9),
+                "width {width}"
+            );
+        }
+    }
+
+    #[test]
+    fn hadamard_matches_row_first_transform() {
+        for seed in 0..32 {
+            let src = pattern(24, seed, 1023);
+            let pred = pattern(24, seed + 100, 1023);
+            let residual: [i32; 16] = std::array::from_fn(|i| {
+                src[(i / 4) * 6 + i % 4] as i32 - pred[(i / 4) * 6 + i % 4] as i32
+            });
+            assert_eq!(
+                hadamard_4x4_abs_sum(&src, 6, &pred, 6),
+                scalar_hadamard_abs_sum(&residual)
+            );
+        }
+    }
+
+    #[test]
+    fn convolutions_match_direct_sums() {
+        let taps = [-1i8, 3, -10, 122, 18, -6, 2, 0];
+        let src: Vec<i32> = pattern(13 * 20, 7, 255)
+            .into_iter()
+            .map(i32::from)
+            .collect();
+
+        let mut out = vec![0; 13];
+        convolve_8tap(&src[..20], &taps, &mut out);
+        for (i, &o) in out.iter().enumerate() {
+            let direct: i32 = (0..8).map(|t| taps[t] as i32 * src[i + t]).sum();
+            assert_eq!(o, direct);
+        }
+
+        let mut rows_out = vec![0; 6 * 20];
+        convolve_8tap_rows(&src, 20, &taps, &mut rows_out);
+        for r in 0..6 {
+            for c in 0..20 {
+                let direct: i32 = (0..8).map(|t| taps[t] as i32 * src[(r + t) * 20 + c]).sum();
+                assert_eq!(rows_out[r * 20 + c], direct);
+            }
+        }
+    }
+
+    #[test]
+    fn forward_dct_matches_separable_transform() {
+        for seed in 0..32 {
+            let max = if seed < 16 { 255 } else { 1023 };
+            let src = pattern(64, seed, max);
+            let pred = pattern(64, seed + 100, max);
+            let residual: [i32; 64] = std::array::from_fn(|i| src[i] as i32 - pred[i] as i32);
+            assert_eq!(
+                forward_dct_8x8(&residual),
+                crate::tile::dct::forward_separable_8x8(
+                    &residual,
+                    crate::tile::dct::TxType::DctDct
+                )
+            );
+        }
+    }
+
+    /// Microbenchmarks for the kernels above and the transforms and
+    /// interpolation built on them. Run with
+    /// `cargo test --release -p wav1c bench_kernels -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_kernels() {
+        use std::hint::black_box;
+        use std::time::Instant;
+
+        fn report(name: &str, iterations: u32, run: impl Fn()) {
+            run();
+            let start = Instant::now();
+            for _ in 0..iterations {
+                run();
+            }
+            let ns = start.elapsed().as_nanos() as f64 / iterations as f64;
+            println!("{name:<28} {ns:>10.1} ns/call");
+        }
+
+        let src = pattern(64 * 64, 3, 255);
+        let pred = pattern(64 * 64, 4, 255);
+        for size in [8usize, 16, 64] {
+            report(&format!("sad {size}x{size}"), 20_000, || {
+                black_box(sad(black_box(&src), 64, black_box(&pred), 64, size, size));
+            });
+        }
+        report("satd 16x16", 20_000, || {
+            black_box(crate::satd::compute_satd(
+                black_box(&src),
+                black_box(&pred),
+                16,
+                16,
+                64,
+                64,
+            ));
+        });
+        let residual: [i32; 64] = std::array::from_fn(|i| src[i] as i32 - pred[i] as i32);
+        report("forward dct 8x8", 20_000, || {
+            black_box(crate::tile::dct::forward_dct_8x8(black_box(&residual)));
+        });
+        report("inverse dct 8x8", 20_000, || {
+            black_box(crate::tile::dct::inverse_dct_8x8(
+                black_box(&residual),
+                crate::BitDepth::Eight,
+            ));
+        });
+        #[cfg(feature = "inter")]
+        for (name, phase_x, phase_y) in [
+            ("interpolate 8x8 h", 3, 0),
+            ("interpolate 8x8 v", 0, 5),
+            ("interpolate 8x8 hv", 3, 5),
+        ] {
+            report(name, 20_000, || {
+                black_box(crate::tile::interpolate_block(
+                    black_box(&src),
+                    64,
+                    64,
+                    20,
+                    20,
+                    phase_x,
+                    phase_y,
+                    8,
+                    255,
+                ));
+            });
+        }
+    }
+}
@@ -0,0 +1,325 @@
+//! Motion-compensated temporal filtering of the hidden frame that ends each
+//! B-frame mini-GOP.
+//!
+//! That frame is the backward reference of every B-frame in its mini-GOP and
+//! the base of the next one, so noise coded into it costs bits once and then
+//! degrades every prediction made from it. As libaom does for its altref
+//! frames, the source is averaged with motion-compensated neighbouring
+//! source frames before it is coded. Blocks that motion compensation does
+//! not explain get little or no weight, so moving content stays sharp.
+
+use crate::tile::{MAX_MV_SEARCH_RANGE, motion_search_block};
+use crate::y4m::FramePixels;
+
+/// Luma block size motion is searched and weighted on.
+pub const BLOCK_SIZE: u32 = 16;
+
+/// Edge extension around the searched luma planes.
+const PADDING: u32 = BLOCK_SIZE;
+
+/// Weight of the target frame; neighbour blocks get at most this much.
+const WEIGHT_ONE: u64 = 16;
+
+/// How many preceding source frames are blended into a mini-GOP's hidden
+/// frame at `speed`. Speed 2 codes the source unfiltered.
+pub fn frames_for_speed(speed: u8) -> usize {
+    match speed {
+        0 => 3,
+        1 => 1,
+        _ => 0,
+    }
+}
+
+/// Blends `target` with `neighbours`, each given with its display-order
+/// distance from `target`, nearest first. Every 16x16 luma block is matched
+/// in each neighbour by a full-pel search of `search_range * distance`
+/// pixels, and chroma follows the luma vector.
+///
+/// A neighbour sample's weight halves for every eighth of the squared
+/// quantizer step (`ac_dq / 8`) of error against the target, measured as
+/// the mean of its block's and its 3x3 window's mean squared error. Coarse
+/// quantizers filter harder and fine ones barely at all, and content that
+/// moved in from outside the neighbour drops out sample by sample.
+pub fn filter_frame(
+    target: &FramePixels,
+    neighbours: &[(&FramePixels, u32)],
+    search_range: u32,
+    ac_dq: u32,
+) -> FramePixels {
+    let (width, height) = (target.width, target.height);
+    let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+    let step = (ac_dq as u64 / 8).max(1);
+    let decay = (step * step / 8).max(1);
+
+    let mut y = Accumulator::new(&target.y);
+    let mut u = Accumulator::new(&target.u);
+    let mut v = Accumulator::new(&target.v);
+
+    // Searched on edge-extended planes, so vectors of border blocks may
+    // point past the frame edge and partial blocks are searched like whole
+    // ones.
+    let padded_width = width + 2 * PADDING;
+    let padded_height = height + 2 * PADDING;
+    let padded_target = pad_plane(&target.y, width, height);
+    let blocks_x = width.div_ceil(BLOCK_SIZE);
+    let blocks_y = height.div_ceil(BLOCK_SIZE);
+    let mut previous: Vec<Option<((i32, i32), u32)>> = vec![None; (blocks_x * blocks_y) as usize];
+
+    for &(neighbour, distance) in neighbours {
+        let padded_neighbour = pad_plane(&neighbour.y, width, height);
+        let range =
+            (search_range as u64 * distance as u64).clamp(1, MAX_MV_SEARCH_RANGE as u64) as i32;
+        for (block, seed) in previous.iter_mut().enumerate() {
+            let bx = block as u32 % blocks_x * BLOCK_SIZE;
+            let by = block as u32 / blocks_x * BLOCK_SIZE;
+            let block_w = BLOCK_SIZE.min(width - bx);
+            let block_h = BLOCK_SIZE.min(height - by);
+
+            let seeds: Vec<(i32, i32)> = seed
+                .map(|((dx, dy), d)| {
+                    let d = d.max(1) as i32;
+                    (dx * distance as i32 / d, dy * distance as i32 / d)
+                })
+                .into_iter()
+                .collect();
+            let (dx, dy) = motion_search_block(
+                &padded_target,
+                &padded_neighbour,
+                padded_width,
+                padded_height,
+                bx + PADDING,
+                by + PADDING,
+                BLOCK_SIZE,
+                &seeds,
+                range,
+            );
+            *seed = Some(((dx, dy), distance));
+
+            let mut sse = 0u64;
+            for row in by..by + block_h {
+                for col in bx..bx + block_w {
+                    let t = target.y[(row * width + col) as usize] as i64;
+                    let r = neighbour.y[ref_index(col, row, dx, dy, width, height)] as i64;
+                    sse += ((t - r) * (t - r)) as u64;
+                }
+            }
+            let mse = sse / (block_w * block_h) as u64;
+            if weight(mse / 2, decay) == 0 {
+                continue;
+            }
+
+            let luma = Block {
+                x: bx,
+                y: by,
+                w: block_w,
+                h: block_h,
+                mv: (dx, dy),
+                plane_width: width,
+                plane_height: height,
+            };
+            y.add(&luma, &neighbour.y, mse, decay);
+
+            let chroma = Block {
+                x: bx / 2,
+                y: by / 2,
+                w: (bx + block_w).div_ceil(2).min(chroma_width) - bx / 2,
+                h: (by + block_h).div_ceil(2).min(chroma_height) - by / 2,
+                mv: (dx >> 1, dy >> 1),
+                plane_width: chroma_width,
+                plane_height: chroma_height,
+            };
+            u.add(&chroma, &neighbour.u, mse, decay);
+            v.add(&chroma, &neighbour.v, mse, decay);
+        }
+    }
+
+    FramePixels {
+        y: y.finish(),
+        u: u.finish(),
+        v: v.finish(),
+        ..target.clone()
+    }
+}
+
+/// Weight of a neighbour sample with mean squared error `mse`: halved for
+/// every `decay` of error.
+fn weight(mse: u64, decay: u64) -> u64 {
+    WEIGHT_ONE.checked_shr((mse / decay) as u32).unwrap_or(0)
+}
+
+/// Copies `plane` with `PADDING` samples of edge extension on every side.
+fn pad_plane(plane: &[u16], width: u32, height: u32) -> Vec<u16> {
+    let padded_width = width + 2 * PADDING;
+    let mut padded = Vec::with_capacity((padded_width * (height + 2 * PADDING)) as usize);
+    for row in 0..height + 2 * PADDING {
+        let y = row.saturating_sub(PADDING).min(height - 1);
+        let line = &plane[(y * width) as usize..((y + 1) * width) as usize];
+        padded.extend(std::iter::repeat_n(line[0], PADDING as usize));
+        padded.extend_from_slice(line);
+        padded.extend(std::iter::repeat_n(
+            line[width as usize - 1],
+            PADDING as usize,
+        ));
+    }
+    padded
+}
+
+/// Index of the sample `(dx, dy)` away from `(x, y)`, clamped to the plane.
+fn ref_index(x: u32, y: u32, dx: i32, dy: i32, width: u32, height: u32) -> usize {
+    let rx = (x as i32 + dx).clamp(0, width as i32 - 1) as usize;
+    let ry = (y as i32 + dy).clamp(0, height as i32 - 1) as usize;
+    ry * width as usize + rx
+}
+
+struct Block {
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+    mv: (i32, i32),
+    plane_width: u32,
+    plane_height: u32,
+}
+
+/// Weighted sample sums of one plane, seeded with the target at full weight.
+struct Accumulator<'a> {
+    target: &'a [u16],
+    sums: Vec<u64>,
+    weights: Vec<u64>,
+}
+
+impl<'a> Accumulator<'a> {
+    fn new(target: &'a [u16]) -> Self {
+        Self {
+            target,
+            sums: target.iter().map(|&s| s as u64 * WEIGHT_ONE).collect(),
+            weights: vec![WEIGHT_ONE; target.len()],
+        }
+    }
+
+    /// Adds `block` of `reference`, each sample weighted by the mean of the
+    /// block's luma error `block_mse` and the squared error over the 3x3
+    /// window around it.
+    fn add(&mut self, block: &Block, reference: &[u16], block_mse: u64, decay: u64) {
+        let (dx, dy) = block.mv;
+        let (width, height) = (block.plane_width, block.plane_height);
+        for row in block.y..block.y + block.h {
+            for col in block.x..block.x + block.w {
+                let mut window_sse = 0u64;
+                for (wx, wy) in (-1..=1).flat_map(|wy| (-1..=1).map(move |wx| (wx, wy))) {
+                    let t = self.target[ref_index(col, row, wx, wy, width, height)] as i64;
+                    let x = (col as i32 + wx).clamp(0, width as i32 - 1) as u32;
+                    let y = (row as i32 + wy).clamp(0, height as i32 - 1) as u32;
+                    let r = reference[ref_index(x, y, dx, dy, width, height)] as i64;
+                    window_sse += ((t - r) * (t - r)) as u64;
+                }
+                let w = weight((window_sse / 9 + block_mse) / 2, decay);
+                if w > 0 {
+                    let i = (row * width + col) as usize;
+                    let r = reference[ref_index(col, row, dx, dy, width, height)];
+                    self.sums[i] += w * r as u64;
+                    self.weights[i] += w;
+                }
+            }
+        }
+    }
+
+    fn finish(self) -> Vec<u16> {
+        self.sums
+            .iter()
+            .zip(&self.weights)
+            .map(|(&sum, &weight)| ((sum + weight / 2) / weight) as u16)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tile::DEFAULT_MV_SEARCH_RANGE;
+
+    const AC_DQ: u32 = 160;
+
+    /// Smooth value noise shifted by `(sx, sy)` plus deterministic noise that
+    /// differs per `seed`.
+    fn frame(sx: u32, sy: u32, seed: u32) -> (FramePixels, FramePixels) {
+        let (width, height) = (128, 96);
+        let mut clean = FramePixels::solid(width, height, 0, 128, 128);
+        for (i, s) in clean.y.iter_mut().enumerate() {
+            let (x, y) = (i as u32 % width + sx, i as u32 / width + sy);
+            let lattice = |i: u32, j: u32| {
+                (i.wrapping_mul(73_856_093) ^ j.wrapping_mul(19_349_663)) % 160 + 40
+            };
+            let (i, j, fx, fy) = (x / 8, y / 8, x % 8, y % 8);
+            let top = lattice(i, j) * (8 - fx) + lattice(i + 1, j) * fx;
+            let bottom = lattice(i, j + 1) * (8 - fx) + lattice(i + 1, j + 1) * fx;
+            *s = ((top * (8 - fy) + bottom * fy) / 64) as u16;
+        }
+        let mut noisy = clean.clone();
+        let mut state = seed.wrapping_mul(0x9e37_79b9) | 1;
+        for s in noisy.y.iter_mut() {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            *s = (*s as i32 + (state % 9) as i32 - 4) as u16;
+        }
+        (clean, noisy)
+    }
+
+    fn sse(a: &[u16], b: &[u16]) -> u64 {
+        a.iter()
+            .zip(b)
+            .map(|(&a, &b)| ((a as i64 - b as i64) * (a as i64 - b as i64)) as u64)
+            .sum()
+    }
+
+    #[test]
+    fn slower_speeds_blend_more_frames() {
+        assert_eq!(frames_for_speed(0), 3);
+        assert_eq!(frames_for_speed(1), 1);
+        assert_eq!(frames_for_speed(crate::tile::MAX_SPEED), 0);
+    }
+
+    #[test]
+    fn identical_neighbours_leave_the_frame_unchanged() {
+        let (_, target) = frame(0, 0, 1);
+        let filtered = filter_frame(
+            &target,
+            &[(&target, 1), (&target, 2)],
+            DEFAULT_MV_SEARCH_RANGE,
+            AC_DQ,
+        );
+        assert_eq!(filtered.y, target.y);
+        assert_eq!(filtered.u, target.u);
+    }
+
+    #[test]
+    fn moving_neighbours_are_motion_compensated_to_remove_noise() {
+        let (clean, target) = frame(6, 4, 1);
+        let neighbours: Vec<FramePixels> =
+            (1..=3).map(|d| frame(6 - 2 * d, 4 - d, d + 1).1).collect();
+        let refs: Vec<(&FramePixels, u32)> = neighbours.iter().zip(1..).collect();
+        let filtered = filter_frame(&target, &refs, DEFAULT_MV_SEARCH_RANGE, AC_DQ);
+        let before = sse(&target.y, &clean.y);
+        let after = sse(&filtered.y, &clean.y);
+        assert!(after * 2 < before, "{after} vs {before}");
+    }
+
+    #[test]
+    fn unrelated_neighbours_get_no_weight() {
+        let (_, target) = frame(0, 0, 1);
+        let other = FramePixels::solid(128, 96, 16, 60, 200);
+        let filtered = filter_frame(&target, &[(&other, 1)], DEFAULT_MV_SEARCH_RANGE, AC_DQ);
+        assert_eq!(filtered.y, target.y);
+        assert_eq!(filtered.u, target.u);
+    }
+
+    #[test]
+    fn fine_quantizers_barely_filter() {
+        let (_, target) = frame(0, 0, 1);
+        let (_, neighbour) = frame(0, 0, 2);
+        let coarse = filter_frame(&target, &[(&neighbour, 1)], DEFAULT_MV_SEARCH_RANGE, AC_DQ);
+        let fine = filter_frame(&target, &[(&neighbour, 1)], DEFAULT_MV_SEARCH_RANGE, 8);
+        assert!(sse(&fine.y, &target.y) < sse(&coarse.y, &target.y));
+    }
+}
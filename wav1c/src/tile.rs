@@ -1,7 +1,9 @@
 use crate::cdf::CdfContext;
+use crate::cpu;
 use crate::dequant::DequantValues;
 use crate::frame::{TilePlan, TileRect};
-use crate::msac::MsacEncoder;
+use crate::msac::{MsacCheckpoint, MsacEncoder};
+use crate::restoration::{RestorationPlan, RestorationSignal};
 use crate::y4m::FramePixels;
 use std::cmp::min;
 
@@ -10,12 +12,24 @@ pub mod dct;
 #[path = "scan.rs"]
 mod scan;
 
-use scan::{DEFAULT_SCAN_4X4, DEFAULT_SCAN_8X8, DEFAULT_SCAN_16X16, LO_CTX_OFFSETS_2D};
+use scan::{
+    DEFAULT_SCAN_4X4, DEFAULT_SCAN_8X8, DEFAULT_SCAN_16X16, DEFAULT_SCAN_32X32, LO_CTX_OFFSETS_2D,
+};
 
 const PARTITION_CTX_NONE: [u8; 5] = [0, 0x10, 0x18, 0x1c, 0x1e];
 
+const PARTITION_NONE: u32 = 0;
+const PARTITION_HORZ: u32 = 1;
+const PARTITION_VERT: u32 = 2;
+const PARTITION_SPLIT: u32 = 3;
+
 const PARTITION_NSYMS: [u32; 5] = [9, 9, 9, 9, 3];
 
+#[cfg(feature = "inter")]
+const COMPOUND_MODES: u32 = 8;
+#[cfg(feature = "inter")]
+const GLOBAL_GLOBALMV: u32 = 6;
+
 const INTRA_MODE_CONTEXT: [usize; 13] = [0, 1, 2, 3, 4, 4, 4, 4, 3, 0, 1, 2, 0];
 
 #[rustfmt::skip]
@@ -80,6 +94,7 @@ const TXTP_INTRA2_MAP: [dct::TxType; 5] = [
     dct::TxType::DctAdst,
 ];
 
+#[cfg(feature = "inter")]
 #[rustfmt::skip]
 const SUBPEL_FILTER_8TAP: [[i8; 8]; 15] = [
     [  0,  1, -3, 63,  4, -1,  0,  0],
@@ -99,6 +114,7 @@ const SUBPEL_FILTER_8TAP: [[i8; 8]; 15] = [
     [  0,  0, -1,  4, 63, -3,  1,  0],
 ];
 
+#[cfg(feature = "inter")]
 #[rustfmt::skip]
 const SUBPEL_FILTER_4TAP: [[i8; 8]; 15] = [
     [  0,  0, -2, 63,  4, -1,  0,  0],
@@ -150,7 +166,9 @@ fn eob_to_bin(eob: usize) -> usize {
         16..=31 => 5,
         32..=63 => 6,
         64..=127 => 7,
-        _ => 8,
+        128..=255 => 8,
+        256..=511 => 9,
+        _ => 10,
     }
 }
 
@@ -321,8 +339,9 @@ fn predict_directional_z1(above: &[u16], w: usize, h: usize, dx: i32, max_value:
     for y in 0..h {
         let xpos_row = dx * (y as i32 + 1);
         let frac = xpos_row & 0x3E;
-        let mut base = (xpos_row >> 6) as usize;
+        let base_start = (xpos_row >> 6) as usize;
         for x in 0..w {
+            let base = base_start + x;
             if base < max_base_x {
                 let v = above[base] as i32 * (64 - frac) + above[base + 1] as i32 * frac;
                 out[y * w + x] = clip_sample((v + 32) >> 6, max_value);
@@ -332,7 +351,6 @@ fn predict_directional_z1(above: &[u16], w: usize, h: usize, dx: i32, max_value:
                 }
                 break;
             }
-            base += 1;
         }
     }
     out
@@ -344,8 +362,9 @@ fn predict_directional_z3(left: &[u16], w: usize, h: usize, dy: i32, max_value:
     for x in 0..w {
         let ypos_col = dy * (x as i32 + 1);
         let frac = ypos_col & 0x3E;
-        let mut base = (ypos_col >> 6) as usize;
+        let base_start = (ypos_col >> 6) as usize;
         for y in 0..h {
+            let base = base_start + y;
             if base < max_base_y {
                 let v = left[base] as i32 * (64 - frac) + left[base + 1] as i32 * frac;
                 out[y * w + x] = clip_sample((v + 32) >> 6, max_value);
@@ -355,7 +374,6 @@ fn predict_directional_z3(left: &[u16], w: usize, h: usize, dy: i32, max_value:
                 }
                 break;
             }
-            base += 1;
         }
     }
     out
@@ -386,9 +404,10 @@ fn predict_directional_z2(
     for y in 0..h {
         let xpos_row = 64_i32 - dx * (y as i32 + 1);
         let frac_x = xpos_row & 0x3E;
-        let mut base_x = xpos_row >> 6;
+        let base_x_start = xpos_row >> 6;
 
         for x in 0..w {
+            let base_x = base_x_start + x as i32;
             let v;
             if base_x >= 0 {
                 let bx = base_x as usize;
@@ -417,7 +436,6 @@ fn predict_directional_z2(
                 }
             }
             out[y * w + x] = clip_sample((v + 32) >> 6, max_value);
-            base_x += 1;
         }
     }
     out
@@ -487,11 +505,8 @@ fn generate_prediction(
 
 #[allow(dead_code)]
 fn compute_sad(source: &[u16], prediction: &[u16]) -> u32 {
-    source
-        .iter()
-        .zip(prediction.iter())
-        .map(|(&s, &p)| (s as i32 - p as i32).unsigned_abs())
-        .sum()
+    let len = source.len().min(prediction.len());
+    (cpu::kernels().sad)(source, len, prediction, len, len, 1)
 }
 
 fn compute_rd_cost(
@@ -500,8 +515,9 @@ fn compute_rd_cost(
     dc_dq: u32,
     ac_dq: u32,
     tx_type: dct::TxType,
-    max_value: u16,
+    bit_depth: crate::BitDepth,
 ) -> u64 {
+    let max_value = bit_depth.max_value();
     let mut residual = [0i32; 64];
     for i in 0..64 {
         residual[i] = source[i] as i32 - prediction[i] as i32;
@@ -512,7 +528,7 @@ fn compute_rd_cost(
     let deq = dequantize_coeffs(&quant, 64, dc_dq, ac_dq);
     let mut deq_arr = [0i32; 64];
     deq_arr.copy_from_slice(&deq);
-    let recon_residual = dct::inverse_transform_8x8(&deq_arr, tx_type);
+    let recon_residual = dct::inverse_transform_8x8(&deq_arr, tx_type, bit_depth);
 
     let mut sse: u64 = 0;
     for i in 0..64 {
@@ -527,6 +543,81 @@ fn compute_rd_cost(
     sse + lambda * nz_count
 }
 
+/// Positions of the four 4x4 luma transform blocks of an 8x8 block, in
+/// coding order.
+const LOSSLESS_SUB_BLOCKS: [(usize, usize); 4] = [(0, 0), (4, 0), (0, 4), (4, 4)];
+
+/// Predicts the 4x4 luma sub-block at `(sx, sy)` of a lossless 8x8 block.
+/// Edges outside the block come from `above`/`left`/`top_left`; edges inside
+/// it come from the already reconstructed samples in `inner` (8x8, row-major).
+#[allow(clippy::too_many_arguments)]
+fn predict_sub_block_4x4(
+    mode: u8,
+    sx: usize,
+    sy: usize,
+    above: &[u16; 8],
+    left: &[u16; 8],
+    top_left: u16,
+    have_above: bool,
+    have_left: bool,
+    inner: &[u16; 64],
+    mid_value: u16,
+    max_value: u16,
+) -> Vec<u16> {
+    let sub_above: Vec<u16> = (0..4)
+        .map(|i| {
+            if sy == 0 {
+                above[sx + i]
+            } else {
+                inner[(sy - 1) * 8 + sx + i]
+            }
+        })
+        .collect();
+    let sub_left: Vec<u16> = (0..4)
+        .map(|i| {
+            if sx == 0 {
+                left[sy + i]
+            } else {
+                inner[(sy + i) * 8 + sx - 1]
+            }
+        })
+        .collect();
+    let sub_top_left = match (sx, sy) {
+        (0, 0) => top_left,
+        (_, 0) => above[sx - 1],
+        (0, _) => left[sy - 1],
+        _ => inner[(sy - 1) * 8 + sx - 1],
+    };
+    generate_prediction(
+        mode,
+        0,
+        &sub_above,
+        &sub_left,
+        sub_top_left,
+        have_above || sy > 0,
+        have_left || sx > 0,
+        4,
+        4,
+        mid_value,
+        max_value,
+    )
+}
+
+/// Codes a 4x4 residual losslessly: a Walsh-Hadamard transform whose output
+/// is an exact multiple of the qindex-0 quantizer step, which is the same at
+/// every bit depth.
+fn lossless_quantize(residual: &[i32; 16]) -> Vec<i32> {
+    let step = dct::WHT_UNIT_QUANT as u32;
+    quantize_coeffs(&dct::forward_wht_4x4(residual), 16, step, step)
+}
+
+fn lossless_reconstruct(quantized: &[i32]) -> [i32; 16] {
+    let step = dct::WHT_UNIT_QUANT as u32;
+    let mut coeffs = [0i32; 16];
+    coeffs.copy_from_slice(&dequantize_coeffs(quantized, 16, step, step));
+    dct::inverse_wht_4x4(&coeffs)
+}
+
 #[allow(clippy::too_many_arguments)]
 fn select_best_intra_mode(
     source: &[u16],
@@ -539,9 +630,11 @@ fn select_best_intra_mode(
     h: usize,
     dc_dq: u32,
     ac_dq: u32,
-    mid_value: u16,
-    max_value: u16,
+    bit_depth: crate::BitDepth,
+    early_exit: &EarlyExit,
 ) -> (u8, i8) {
+    let mid_value = bit_depth.mid_value();
+    let max_value = bit_depth.max_value();
     let dc = predict_dc(above, left, have_above, have_left, w, h, mid_value);
     let best_delta = 0i8;
 
@@ -552,6 +645,9 @@ fn select_best_intra_mode(
     let mut candidates = Vec::new();
 
     let dc_satd = crate::satd::compute_satd(source, &dc, w, h, w, w);
+    if early_exit.intra_dc_is_good_enough(dc_satd, (w * h) as u64, ac_dq) {
+        return (0, best_delta);
+    }
     candidates.push((
         0,
         dc,
@@ -639,14 +735,46 @@ fn select_best_intra_mode(
     // Sort by Fast RDO cost
     candidates.sort_by_key(|&(_, _, cost)| cost);
 
-    // True RDO Refinement on Top 2 (or all if very few valid modes exist)
-    let best_n = std::cmp::min(2, candidates.len());
+    #[cfg(feature = "mode-hook")]
+    if let Some(hook) = early_exit.intra_mode_hook {
+        use crate::mode_hook::{IntraBlock, IntraCandidate};
+        let block = IntraBlock {
+            source,
+            above,
+            left,
+            have_above,
+            have_left,
+            width: w,
+            height: h,
+        };
+        let mut ranked: Vec<IntraCandidate> = candidates
+            .iter()
+            .map(|&(mode, _, cost)| IntraCandidate { mode, cost })
+            .collect();
+        hook.0.rank(&block, &mut ranked);
+        let mut offered = std::mem::take(&mut candidates);
+        for c in ranked {
+            if let Some(i) = offered.iter().position(|&(mode, _, _)| mode == c.mode) {
+                candidates.push(offered.swap_remove(i));
+            }
+        }
+        if candidates.is_empty() {
+            return (0, best_delta);
+        }
+    }
+
+    // True RDO refinement on the top candidates (or all if very few valid
+    // modes exist); a single candidate needs no refinement.
+    let best_n = std::cmp::min(early_exit.intra_rd_candidates, candidates.len());
+    if best_n <= 1 {
+        return (candidates[0].0, best_delta);
+    }
     let mut exact_best_mode = candidates[0].0;
     let mut exact_best_cost = u64::MAX;
 
     for candidate in candidates.iter().take(best_n) {
         let (mode, ref pred, _) = *candidate;
-        let real_cost = compute_rd_cost(source, pred, dc_dq, ac_dq, dct::TxType::DctDct, max_value);
+        let real_cost = compute_rd_cost(source, pred, dc_dq, ac_dq, dct::TxType::DctDct, bit_depth);
         if real_cost < exact_best_cost {
             exact_best_cost = real_cost;
             exact_best_mode = mode;
@@ -661,7 +789,8 @@ fn select_best_txtype(
     prediction: &[u16],
     dc_dq: u32,
     ac_dq: u32,
-    max_value: u16,
+    bit_depth: crate::BitDepth,
+    early_exit: &EarlyExit,
 ) -> dct::TxType {
     let mut best_type = dct::TxType::DctDct;
     let mut best_cost = compute_rd_cost(
@@ -670,14 +799,17 @@ fn select_best_txtype(
         dc_dq,
         ac_dq,
         dct::TxType::DctDct,
-        max_value,
+        bit_depth,
     );
+    if early_exit.intra_dct_is_good_enough(best_cost, 64, ac_dq) {
+        return best_type;
+    }
 
     for &tx in &TXTP_INTRA2_MAP {
         if tx == dct::TxType::DctDct {
             continue;
         }
-        let cost = compute_rd_cost(source, prediction, dc_dq, ac_dq, tx, max_value);
+        let cost = compute_rd_cost(source, prediction, dc_dq, ac_dq, tx, bit_depth);
         if cost < best_cost {
             best_cost = cost;
             best_type = tx;
@@ -700,6 +832,37 @@ fn encode_transform_block(
     dc_sign_ctx: usize,
     y_mode: u8,
     tx_type: dct::TxType,
+) -> (u8, bool, bool) {
+    encode_coefficients(
+        enc,
+        cdf,
+        coeffs,
+        scan_table,
+        is_chroma,
+        is_inter,
+        t_dim_ctx,
+        txb_skip_ctx,
+        dc_sign_ctx,
+        y_mode,
+        Some(tx_type),
+    )
+}
+
+/// Codes one transform block's coefficients. `tx_type` is `None` where the
+/// transform is implied and no luma type is signalled.
+#[allow(clippy::too_many_arguments)]
+fn encode_coefficients(
+    enc: &mut MsacEncoder,
+    cdf: &mut CdfContext,
+    coeffs: &[i32],
+    scan_table: &[u16],
+    is_chroma: bool,
+    is_inter: bool,
+    t_dim_ctx: usize,
+    txb_skip_ctx: usize,
+    dc_sign_ctx: usize,
+    y_mode: u8,
+    tx_type: Option<dct::TxType>,
 ) -> (u8, bool, bool) {
     let chroma_idx = if is_chroma { 1 } else { 0 };
     let n = scan_table.len();
@@ -707,6 +870,7 @@ fn encode_transform_block(
         16 => 4usize,
         64 => 8,
         256 => 16,
+        1024 => 32,
         _ => 8,
     };
 
@@ -725,7 +889,7 @@ fn encode_transform_block(
 
     enc.encode_bool(false, &mut cdf.txb_skip[t_dim_ctx][txb_skip_ctx]);
 
-    if !is_chroma {
+    if let (false, Some(tx_type)) = (is_chroma, tx_type) {
         if is_inter {
             enc.encode_bool(true, &mut cdf.txtp_inter);
         } else {
@@ -748,6 +912,7 @@ fn encode_transform_block(
         16 => (4u32, &mut cdf.eob_bin_16[chroma_idx][0] as &mut [u16]),
         64 => (6u32, &mut cdf.eob_bin_64[chroma_idx][0] as &mut [u16]),
         256 => (8u32, &mut cdf.eob_bin_256[chroma_idx][0] as &mut [u16]),
+        1024 => (10u32, &mut cdf.eob_bin_1024[chroma_idx] as &mut [u16]),
         _ => (6u32, &mut cdf.eob_bin_64[chroma_idx][0] as &mut [u16]),
     };
     enc.encode_symbol(eob_bin as u32, eob_cdf, n_eob_syms);
@@ -772,6 +937,7 @@ fn encode_transform_block(
         16 => 0usize,
         64 => 2,
         256 => 4,
+        1024 => 6,
         _ => 2,
     };
     let eob_ctx = if eob == 0 {
@@ -916,6 +1082,29 @@ fn quantize_coeffs(dct_coeffs: &[i32], n: usize, dc_dq: u32, ac_dq: u32) -> Vec<
     quantized
 }
 
+/// Quantizes a transform block for coding, applying the dead-zone rounding
+/// and skip bias carried in `dq`.
+fn quantize_block(dct_coeffs: &[i32], n: usize, dq: &DequantValues) -> Vec<i32> {
+    let mut quantized = vec![0i32; n];
+    for i in 0..n {
+        let (step, round) = if i == 0 {
+            (dq.dc, dq.dc_round)
+        } else {
+            (dq.ac, dq.ac_round)
+        };
+        let tok = ((dct_coeffs[i].unsigned_abs() + round) / step) as i32;
+        quantized[i] = if dct_coeffs[i] < 0 { -tok } else { tok };
+    }
+
+    if dq.skip_bias > 0 && quantized.iter().all(|q| q.unsigned_abs() <= 1) {
+        let ones = quantized.iter().filter(|&&q| q != 0).count() as u32;
+        if ones <= dq.skip_bias {
+            quantized.fill(0);
+        }
+    }
+    quantized
+}
+
 fn dequantize_coeffs(quantized: &[i32], n: usize, dc_dq: u32, ac_dq: u32) -> Vec<i32> {
     let mut dequantized = vec![0i32; n];
     for i in 0..n {
@@ -925,6 +1114,66 @@ fn dequantize_coeffs(quantized: &[i32], n: usize, dc_dq: u32, ac_dq: u32) -> Vec
     dequantized
 }
 
+/// Right shift the decoder applies to `n x n` dequantized coefficients.
+fn tx_dq_shift(n: usize) -> u32 {
+    match n {
+        64 => 2,
+        32 => 1,
+        _ => 0,
+    }
+}
+
+fn quantize_square(coeffs: &[i32], n: usize, dq: &DequantValues) -> Vec<i32> {
+    let shift = tx_dq_shift(n);
+    let scaled: Vec<i32> = coeffs.iter().map(|&c| c << shift).collect();
+    quantize_block(&scaled, scaled.len(), dq)
+}
+
+fn dequantize_square(quantized: &[i32], n: usize, dq: &DequantValues) -> Vec<i32> {
+    let shift = tx_dq_shift(n);
+    let mut deq = dequantize_coeffs(quantized, quantized.len(), dq.dc, dq.ac);
+    for v in &mut deq {
+        *v = v.signum() * (v.abs() >> shift);
+    }
+    deq
+}
+
+fn forward_square_dct(residual: &[i32], n: usize) -> Vec<i32> {
+    match n {
+        8 => dct::forward_dct_8x8(residual.try_into().unwrap()).to_vec(),
+        16 => dct::forward_transform_16x16(residual.try_into().unwrap()).to_vec(),
+        32 => dct::forward_transform_32x32(residual.try_into().unwrap()).to_vec(),
+        _ => {
+            let full = dct::forward_transform_64x64(residual.try_into().unwrap());
+            (0..1024).map(|i| full[(i / 32) * 64 + i % 32]).collect()
+        }
+    }
+}
+
+fn inverse_square_dct(coeffs: &[i32], n: usize, bit_depth: crate::BitDepth) -> Vec<i32> {
+    match n {
+        8 => dct::inverse_dct_8x8(coeffs.try_into().unwrap(), bit_depth).to_vec(),
+        16 => dct::inverse_transform_16x16(coeffs.try_into().unwrap(), bit_depth).to_vec(),
+        32 => dct::inverse_transform_32x32(coeffs.try_into().unwrap(), bit_depth).to_vec(),
+        _ => {
+            let mut full = [0i32; 4096];
+            for (i, &c) in coeffs.iter().enumerate() {
+                full[(i / 32) * 64 + i % 32] = c;
+            }
+            dct::inverse_transform_64x64(&full, bit_depth).to_vec()
+        }
+    }
+}
+
+fn square_tx_scan(n: usize) -> (&'static [u16], usize) {
+    match n {
+        8 => (&DEFAULT_SCAN_8X8, 1),
+        16 => (&DEFAULT_SCAN_16X16, 2),
+        32 => (&DEFAULT_SCAN_32X32, 3),
+        _ => (&DEFAULT_SCAN_32X32, 4),
+    }
+}
+
 fn gather_top_partition_prob(pc: &[u16], bl: usize) -> u16 {
     let mut out = pc[1].wrapping_sub(pc[4]);
     out = out.wrapping_add(pc[5]);
@@ -964,7 +1213,32 @@ fn extract_block(
 }
 
 #[allow(clippy::too_many_arguments)]
-fn interpolate_block(
+fn store_block(
+    plane: &mut [u16],
+    plane_stride: u32,
+    frame_h: u32,
+    px_x: u32,
+    px_y: u32,
+    n: usize,
+    block: &[u16],
+) {
+    for r in 0..n {
+        let y = px_y + r as u32;
+        if y >= frame_h {
+            break;
+        }
+        for c in 0..n {
+            let x = px_x + c as u32;
+            if x < plane_stride {
+                plane[(y * plane_stride + x) as usize] = block[r * n + c];
+            }
+        }
+    }
+}
+
+#[cfg(feature = "inter")]
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn interpolate_block(
     reference: &[u16],
     width: u32,
     height: u32,
@@ -988,73 +1262,68 @@ fn interpolate_block(
         &SUBPEL_FILTER_4TAP
     };
 
-    let ref_pixel = |sx: i32, sy: i32| -> i32 {
-        let cx = sx.clamp(0, w - 1) as u32;
-        let cy = sy.clamp(0, h - 1) as u32;
-        reference[(cy * width + cx) as usize] as i32
+    // Edge-clamped reference samples for row `sy`, columns `x0..x0 + out.len()`.
+    let fetch_row = |x0: i32, sy: i32, out: &mut [i32]| {
+        let row = &reference[(sy.clamp(0, h - 1) * w) as usize..][..width as usize];
+        for (i, o) in out.iter_mut().enumerate() {
+            *o = row[(x0 + i as i32).clamp(0, w - 1) as usize] as i32;
+        }
+    };
+    let round_into = |sums: &[i32], offset: i32, shift: u32, out: &mut [u16]| {
+        for (o, &sum) in out.iter_mut().zip(sums) {
+            *o = ((sum + offset) >> shift).clamp(0, max_value as i32) as u16;
+        }
     };
 
+    let kernels = cpu::kernels();
+    let mut line = vec![0i32; bs + 7];
     if mx == 0 && my == 0 {
         for r in 0..bs {
-            for c in 0..bs {
-                output[r * bs + c] = ref_pixel(int_x + c as i32, int_y + r as i32) as u16;
+            fetch_row(int_x, int_y + r as i32, &mut line[..bs]);
+            for (o, &p) in output[r * bs..][..bs].iter_mut().zip(&line) {
+                *o = p as u16;
             }
         }
     } else if mx != 0 && my == 0 {
         let fh = &filter_table[(mx - 1) as usize];
+        let mut sums = vec![0i32; bs];
         for r in 0..bs {
-            let sy = int_y + r as i32;
-            for c in 0..bs {
-                let mut sum = 0i32;
-                for t in 0..8i32 {
-                    sum += fh[t as usize] as i32 * ref_pixel(int_x + c as i32 + t - 3, sy);
-                }
-                output[r * bs + c] = ((sum + 34) >> 6).clamp(0, max_value as i32) as u16;
-            }
+            fetch_row(int_x - 3, int_y + r as i32, &mut line);
+            (kernels.convolve_8tap)(&line, fh, &mut sums);
+            round_into(&sums, 34, 6, &mut output[r * bs..][..bs]);
         }
     } else if mx == 0 {
         let fv = &filter_table[(my - 1) as usize];
-        for r in 0..bs {
-            for c in 0..bs {
-                let sx = int_x + c as i32;
-                let mut sum = 0i32;
-                for t in 0..8i32 {
-                    sum += fv[t as usize] as i32 * ref_pixel(sx, int_y + r as i32 + t - 3);
-                }
-                output[r * bs + c] = ((sum + 32) >> 6).clamp(0, max_value as i32) as u16;
-            }
+        let mut rows = vec![0i32; (bs + 7) * bs];
+        for (r, row) in rows.chunks_exact_mut(bs).enumerate() {
+            fetch_row(int_x, int_y + r as i32 - 3, row);
         }
+        let mut sums = vec![0i32; bs * bs];
+        (kernels.convolve_8tap_rows)(&rows, bs, fv, &mut sums);
+        round_into(&sums, 32, 6, &mut output);
     } else {
         let fh = &filter_table[(mx - 1) as usize];
         let fv = &filter_table[(my - 1) as usize];
         let mid_rows = bs + 7;
-        let mut mid = vec![0i16; mid_rows * bs];
+        let mut mid = vec![0i32; mid_rows * bs];
 
-        for r in 0..mid_rows {
-            let sy = int_y + r as i32 - 3;
-            for c in 0..bs {
-                let mut sum = 0i32;
-                for t in 0..8i32 {
-                    sum += fh[t as usize] as i32 * ref_pixel(int_x + c as i32 + t - 3, sy);
-                }
-                mid[r * bs + c] = ((sum + 2) >> 2) as i16;
+        for (r, mid_row) in mid.chunks_exact_mut(bs).enumerate() {
+            fetch_row(int_x - 3, int_y + r as i32 - 3, &mut line);
+            (kernels.convolve_8tap)(&line, fh, mid_row);
+            for m in mid_row.iter_mut() {
+                *m = ((*m + 2) >> 2) as i16 as i32;
             }
         }
 
-        for r in 0..bs {
-            for c in 0..bs {
-                let mut sum = 0i32;
-                for t in 0..8 {
-                    sum += fv[t] as i32 * mid[(r + t) * bs + c] as i32;
-                }
-                output[r * bs + c] = ((sum + 512) >> 10).clamp(0, max_value as i32) as u16;
-            }
-        }
+        let mut sums = vec![0i32; bs * bs];
+        (kernels.convolve_8tap_rows)(&mid, bs, fv, &mut sums);
+        round_into(&sums, 512, 10, &mut output);
     }
 
     output
 }
 
+#[cfg(feature = "inter")]
 #[allow(clippy::too_many_arguments)]
 fn subpel_refine(
     source: &[u16],
@@ -1129,6 +1398,236 @@ fn subpel_refine(
     (bx, by)
 }
 
+/// Per-superblock `cdef_idx` signalling. With `bits > 0` the index of each
+/// 64x64 superblock is written as a literal at its first non-skip block.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CdefSignal {
+    pub bits: u8,
+    /// Strength index for each superblock row (frame rows at the frame level,
+    /// tile rows once narrowed with `for_tile`).
+    pub sb_row_idx: Vec<u8>,
+}
+
+impl CdefSignal {
+    fn for_tile(&self, rect: &TileRect) -> CdefSignal {
+        let row_idx = |row: u32| self.sb_row_idx.get(row as usize).copied().unwrap_or(0);
+        CdefSignal {
+            bits: self.bits,
+            sb_row_idx: (rect.sb_row_start..rect.sb_row_end).map(row_idx).collect(),
+        }
+    }
+
+    fn write_idx(&self, enc: &mut MsacEncoder, pending: &mut bool, by: u32) {
+        if self.bits == 0 || !*pending {
+            return;
+        }
+        *pending = false;
+        let idx = self.sb_row_idx.get(by as usize / 16).copied().unwrap_or(0);
+        for bit in (0..self.bits).rev() {
+            enc.encode_bool_equi((idx >> bit) & 1 == 1);
+        }
+    }
+}
+
+/// Which 8x8 luma blocks were coded with `skip` (the tile's while it is
+/// encoded, the frame's once tiles are stitched). CDEF leaves the blocks
+/// whose 4x4 units all skip unfiltered, and blocks are never smaller than
+/// 8x8, so one flag per 8x8 says it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkipMap {
+    cols: u32,
+    rows: u32,
+    skip: Vec<bool>,
+}
+
+impl SkipMap {
+    fn new(width: u32, height: u32, skip: bool) -> Self {
+        let (cols, rows) = (width.div_ceil(8), height.div_ceil(8));
+        Self {
+            cols,
+            rows,
+            skip: vec![skip; (cols * rows) as usize],
+        }
+    }
+
+    /// A `width`x`height` frame whose blocks all skip, as before any is
+    /// coded.
+    pub fn all_skipped(width: u32, height: u32) -> Self {
+        Self::new(width, height, true)
+    }
+
+    /// A `width`x`height` frame with every block coded, so CDEF filters
+    /// all of it.
+    pub fn none_skipped(width: u32, height: u32) -> Self {
+        Self::new(width, height, false)
+    }
+
+    /// Marks the `bl` block at 4x4 unit `(bx, by)` as coded with or
+    /// without `skip`, as far as it lies inside the map.
+    pub fn fill(&mut self, bx: u32, by: u32, bl: usize, skip: bool) {
+        let side = 1u32 << (4 - bl);
+        let (x8, y8) = (bx / 2, by / 2);
+        for row in y8..y8.saturating_add(side).min(self.rows) {
+            for col in x8..x8.saturating_add(side).min(self.cols) {
+                self.skip[(row * self.cols + col) as usize] = skip;
+            }
+        }
+    }
+
+    /// Whether the 8x8 block at `(x8, y8)` skipped; blocks outside the map
+    /// count as skipped.
+    pub fn is_skipped(&self, x8: u32, y8: u32) -> bool {
+        x8 >= self.cols || y8 >= self.rows || self.skip[(y8 * self.cols + x8) as usize]
+    }
+
+    fn paste(&mut self, tile: &SkipMap, rect: &TileRect) {
+        let col0 = rect.sb_col_start * 8;
+        let row0 = rect.sb_row_start * 8;
+        for row in 0..tile.rows.min(self.rows.saturating_sub(row0)) {
+            for col in 0..tile.cols.min(self.cols.saturating_sub(col0)) {
+                self.skip[((row0 + row) * self.cols + col0 + col) as usize] =
+                    tile.is_skipped(col, row);
+            }
+        }
+    }
+}
+
+/// Per-superblock quantizer changes coded with `delta_q_present`. Each
+/// 64x64 superblock is quantized at its entry in `q_idx`; an empty map
+/// leaves delta q off in the frame header.
+#[derive(Clone, Default)]
+pub struct SbDeltaQ {
+    /// Superblock columns of the area `q_idx` covers (the frame at the
+    /// frame level, the tile once narrowed with `for_tile`).
+    pub sb_cols: u32,
+    /// One q index per superblock in raster order.
+    pub q_idx: Vec<u8>,
+    /// The dequantizers of every q index in `q_idx`.
+    pub dequant: Vec<(u8, DequantValues)>,
+}
+
+impl SbDeltaQ {
+    pub fn is_enabled(&self) -> bool {
+        !self.q_idx.is_empty()
+    }
+
+    fn for_tile(&self, rect: &TileRect) -> SbDeltaQ {
+        if !self.is_enabled() {
+            return SbDeltaQ::default();
+        }
+        let q_idx = (rect.sb_row_start..rect.sb_row_end)
+            .flat_map(|row| (rect.sb_col_start..rect.sb_col_end).map(move |col| (row, col)))
+            .map(|(row, col)| self.q_idx[(row * self.sb_cols + col) as usize])
+            .collect();
+        SbDeltaQ {
+            sb_cols: rect.sb_col_end - rect.sb_col_start,
+            q_idx,
+            dequant: self.dequant.clone(),
+        }
+    }
+
+    fn superblock(&self, sb_col: u32, sb_row: u32) -> Option<(u8, DequantValues)> {
+        let q = *self.q_idx.get((sb_row * self.sb_cols + sb_col) as usize)?;
+        self.dequant.iter().find(|&&(dq_q, _)| dq_q == q).copied()
+    }
+}
+
+#[derive(Clone, Copy)]
+struct DeltaQMark {
+    current_q_idx: u8,
+    pending: bool,
+}
+
+/// A tile's walk through its `SbDeltaQ`: each superblock's first coded
+/// block moves the decoder's running q index to that superblock's.
+struct DeltaQCursor {
+    map: SbDeltaQ,
+    frame: (u8, DequantValues),
+    current_q_idx: u8,
+    sb_q_idx: u8,
+    pending: bool,
+}
+
+impl DeltaQCursor {
+    fn new(map: SbDeltaQ, base_q_idx: u8, dq: DequantValues) -> Self {
+        Self {
+            map,
+            frame: (base_q_idx, dq),
+            current_q_idx: base_q_idx,
+            sb_q_idx: base_q_idx,
+            pending: false,
+        }
+    }
+
+    /// The dequantizers of the superblock about to be coded.
+    fn start_superblock(&mut self, sb_col: u32, sb_row: u32) -> DequantValues {
+        let (q, dq) = self.map.superblock(sb_col, sb_row).unwrap_or(self.frame);
+        self.sb_q_idx = q;
+        self.pending = self.map.is_enabled();
+        dq
+    }
+
+    /// Codes the superblock's q index at its first block. The decoder skips
+    /// the delta for a superblock coded as one skipped block, and nothing
+    /// in it depends on the q index then.
+    fn write(&mut self, enc: &mut MsacEncoder, cdf: &mut [u16], whole_sb_skip: bool) {
+        if !self.pending {
+            return;
+        }
+        self.pending = false;
+        if whole_sb_skip {
+            return;
+        }
+        write_delta_q_index(enc, cdf, self.sb_q_idx as i32 - self.current_q_idx as i32);
+        self.current_q_idx = self.sb_q_idx;
+    }
+
+    fn mark(&self) -> DeltaQMark {
+        DeltaQMark {
+            current_q_idx: self.current_q_idx,
+            pending: self.pending,
+        }
+    }
+
+    fn reset(&mut self, mark: DeltaQMark) {
+        self.current_q_idx = mark.current_q_idx;
+        self.pending = mark.pending;
+    }
+}
+
+/// Writes one `delta_qindex` (with `delta_q_res` 0): a small magnitude as a
+/// symbol, larger ones as an escape plus literal bits, then the sign.
+fn write_delta_q_index(enc: &mut MsacEncoder, cdf: &mut [u16], delta: i32) {
+    const DELTA_Q_SMALL: u32 = 3;
+    let abs = delta.unsigned_abs();
+    enc.encode_symbol(abs.min(DELTA_Q_SMALL), cdf, DELTA_Q_SMALL);
+    if abs >= DELTA_Q_SMALL {
+        let rem_bits = 31 - (abs - 1).leading_zeros();
+        for bit in (0..3).rev() {
+            enc.encode_bool_equi(((rem_bits - 1) >> bit) & 1 == 1);
+        }
+        let abs_bits = abs - 1 - (1 << rem_bits);
+        for bit in (0..rem_bits).rev() {
+            enc.encode_bool_equi((abs_bits >> bit) & 1 == 1);
+        }
+    }
+    if abs > 0 {
+        enc.encode_bool_equi(delta < 0);
+    }
+}
+
+/// The state of a `TileEncoder` before a trial-coded partition choice.
+struct TrialStart {
+    enc: MsacCheckpoint,
+    bits_q8: u64,
+    cdf: CdfContext,
+    ctx: TileContext,
+    px: (u32, u32),
+    cdef_pending: bool,
+    delta_q: DeltaQMark,
+    recon: [Vec<u16>; 3],
+}
+
 struct TileEncoder<'a> {
     enc: MsacEncoder,
     cdf: CdfContext,
@@ -1137,9 +1636,18 @@ struct TileEncoder<'a> {
     mi_rows: u32,
     pixels: &'a FramePixels,
     dq: DequantValues,
+    lossless: bool,
     recon: FramePixels,
+    early_exit: EarlyExit,
+    cdef: CdefSignal,
+    cdef_pending: bool,
+    restoration: RestorationSignal,
+    skips: SkipMap,
+    /// Sets `dq` per superblock.
+    delta_q: DeltaQCursor,
 }
 
+#[derive(Clone)]
 struct TileContext {
     above_partition: Vec<u8>,
     above_skip: Vec<u8>,
@@ -1155,12 +1663,14 @@ struct TileContext {
     left_lcoef: [u8; 32],
     above_ccoef: [Vec<u8>; 2],
     left_ccoef: [[u8; 16]; 2],
+    #[cfg_attr(not(feature = "inter"), allow(dead_code))]
     above_intra: Vec<bool>,
     left_intra: [bool; 32],
     above_mode: Vec<u8>,
     left_mode: [u8; 32],
-    above_newmv: Vec<bool>,
-    left_newmv: [bool; 32],
+    #[cfg_attr(not(feature = "inter"), allow(dead_code))]
+    above_ref: Vec<[i8; 2]>,
+    left_ref: [[i8; 2]; 32],
     mid_value: u16,
 }
 
@@ -1195,8 +1705,8 @@ impl TileContext {
             left_intra: [false; 32],
             above_mode: vec![0u8; mi_cols as usize + 32],
             left_mode: [0u8; 32],
-            above_newmv: vec![false; above_inter_size],
-            left_newmv: [false; 32],
+            above_ref: vec![[0, -1]; above_inter_size],
+            left_ref: [[0, -1]; 32],
             mid_value,
         };
         s.left_recon_y.fill(mid_value);
@@ -1215,7 +1725,7 @@ impl TileContext {
         self.left_ccoef = [[0x40u8; 16]; 2];
         self.left_intra = [false; 32];
         self.left_mode = [0u8; 32];
-        self.left_newmv = [false; 32];
+        self.left_ref = [[0, -1]; 32];
     }
 
     fn partition_ctx(&self, bx: u32, by: u32, bl: usize) -> usize {
@@ -1233,13 +1743,34 @@ impl TileContext {
     }
 
     fn update_partition_ctx(&mut self, bx: u32, by: u32, bl: usize, mi_cols: u32, mi_rows: u32) {
+        self.update_partition_ctx_for(bx, by, bl, PARTITION_NONE, mi_cols, mi_rows);
+    }
+
+    /// Records the block sizes a `partition` of a `bl` block leaves for the
+    /// partition context of later blocks: the width of the blocks along the
+    /// bottom edge and the height of those along the right edge. HORZ and
+    /// VERT halve one of them; SPLIT is recorded by its sub-blocks instead.
+    fn update_partition_ctx_for(
+        &mut self,
+        bx: u32,
+        by: u32,
+        bl: usize,
+        partition: u32,
+        mi_cols: u32,
+        mi_rows: u32,
+    ) {
         let bx8 = (bx >> 1) as usize;
         let by8 = ((by & 31) >> 1) as usize;
         let hsz = 16usize >> bl;
         let aw = min(hsz, (mi_cols - bx).div_ceil(2) as usize);
         let lh = min(hsz, (mi_rows - by).div_ceil(2) as usize);
-        let above_val = PARTITION_CTX_NONE[bl];
-        let left_val = PARTITION_CTX_NONE[bl];
+        let (above_bl, left_bl) = match partition {
+            PARTITION_HORZ => (bl, bl + 1),
+            PARTITION_VERT => (bl + 1, bl),
+            _ => (bl, bl),
+        };
+        let above_val = PARTITION_CTX_NONE[above_bl];
+        let left_val = PARTITION_CTX_NONE[left_bl];
         for i in 0..aw {
             if bx8 + i < self.above_partition.len() {
                 self.above_partition[bx8 + i] = above_val;
@@ -1385,6 +1916,20 @@ impl TileContext {
             }
         }
 
+        self.update_chroma_coef_ctx(bx, by, bl, mi_cols, mi_rows, u_ctx, v_ctx);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn update_chroma_coef_ctx(
+        &mut self,
+        bx: u32,
+        by: u32,
+        bl: usize,
+        mi_cols: u32,
+        mi_rows: u32,
+        u_ctx: u8,
+        v_ctx: u8,
+    ) {
         let cbx4 = (bx / 2) as usize;
         let cby4 = ((by & 31) / 2) as usize;
         let cw4 = (16usize >> bl).max(1);
@@ -1406,34 +1951,124 @@ impl TileContext {
         }
     }
 
-    fn dc_prediction(&self, bx: u32, by: u32, bl: usize, plane: usize) -> u16 {
-        let have_top = by > 0;
-        let have_left = bx > 0;
-
-        if !have_top && !have_left {
-            return self.mid_value;
+    /// `all_zero` context of a 4x4 luma transform block inside a larger
+    /// block, from the coded levels of its above and left neighbours.
+    fn luma_txb_skip_ctx_4x4(&self, x4: u32, y4: u32) -> usize {
+        let top = self.above_lcoef.get(x4 as usize).map_or(0, |&c| c & 0x3F);
+        let left = self.left_lcoef[(y4 & 31) as usize] & 0x3F;
+        let (min_level, max_level) = (top.min(left), top.max(left));
+        if max_level == 0 {
+            1
+        } else if min_level == 0 {
+            2 + usize::from(max_level > 3)
+        } else if max_level <= 3 {
+            4
+        } else if min_level <= 3 {
+            5
+        } else {
+            6
         }
+    }
 
-        let (above_recon, left_recon, px_x, left_local_py, block_pixels) = if plane == 0 {
-            let bp = 1usize << (7 - bl);
-            (
-                &self.above_recon_y[..],
-                &self.left_recon_y[..],
-                (bx * 4) as usize,
-                ((by & 15) * 4) as usize,
-                bp,
-            )
+    fn luma_dc_sign_ctx_4x4(&self, x4: u32, y4: u32) -> usize {
+        let above = self.above_lcoef.get(x4 as usize).copied().unwrap_or(0x40);
+        let left = self.left_lcoef[(y4 & 31) as usize];
+        let s = (above >> 6) as i32 + (left >> 6) as i32 - 2;
+        if s < 0 {
+            1
+        } else if s > 0 {
+            2
         } else {
-            let bp = 1usize << (6 - bl);
-            let above = if plane == 1 {
-                &self.above_recon_u[..]
-            } else {
-                &self.above_recon_v[..]
-            };
-            let left = if plane == 1 {
-                &self.left_recon_u[..]
-            } else {
-                &self.left_recon_v[..]
+            0
+        }
+    }
+
+    fn update_luma_coef_ctx_4x4(&mut self, x4: u32, y4: u32, ctx: u8) {
+        if let Some(above) = self.above_lcoef.get_mut(x4 as usize) {
+            *above = ctx;
+        }
+        self.left_lcoef[(y4 & 31) as usize] = ctx;
+    }
+
+    /// The above and left luma edges an 8x8 intra block at `(bx, by)`
+    /// predicts from, 16 samples each with the last in-frame sample
+    /// repeated past the end of the row or column, and the top-left sample
+    /// from `recon_y`.
+    fn luma_edges_8x8(
+        &self,
+        bx: u32,
+        by: u32,
+        recon_y: &[u16],
+        width: u32,
+    ) -> (Vec<u16>, Vec<u16>, u16) {
+        let px_x = bx * 4;
+        let px_y = by * 4;
+        let have_above = by > 0;
+        let have_left = bx > 0;
+
+        let above_y: Vec<u16> = (0..16)
+            .map(|i| {
+                let idx = px_x as usize + i;
+                if have_above && idx < self.above_recon_y.len() {
+                    self.above_recon_y[idx]
+                } else if have_above {
+                    self.above_recon_y[(px_x as usize + 7).min(self.above_recon_y.len() - 1)]
+                } else {
+                    self.mid_value
+                }
+            })
+            .collect();
+
+        let left_local_py = ((by & 15) * 4) as usize;
+        let left_y: Vec<u16> = (0..16)
+            .map(|i| {
+                let idx = left_local_py + i;
+                if have_left && idx < self.left_recon_y.len() {
+                    self.left_recon_y[idx]
+                } else if have_left {
+                    self.left_recon_y[(left_local_py + 7).min(self.left_recon_y.len() - 1)]
+                } else {
+                    self.mid_value
+                }
+            })
+            .collect();
+
+        let top_left_y = if have_above && have_left {
+            recon_y[((px_y - 1) * width + (px_x - 1)) as usize]
+        } else {
+            self.mid_value
+        };
+        (above_y, left_y, top_left_y)
+    }
+
+    fn dc_prediction(&self, bx: u32, by: u32, bl: usize, plane: usize) -> u16 {
+        let have_top = by > 0;
+        let have_left = bx > 0;
+
+        if !have_top && !have_left {
+            return self.mid_value;
+        }
+
+        let (above_recon, left_recon, px_x, left_local_py, block_pixels) = if plane == 0 {
+            let bp = 1usize << (7 - bl);
+            (
+                &self.above_recon_y[..],
+                &self.left_recon_y[..],
+                (bx * 4) as usize,
+                ((by & 15) * 4) as usize,
+                bp,
+            )
+        } else {
+            let bp = 1usize << (6 - bl);
+            let above = if plane == 1 {
+                &self.above_recon_u[..]
+            } else {
+                &self.above_recon_v[..]
+            };
+            let left = if plane == 1 {
+                &self.left_recon_u[..]
+            } else {
+                &self.left_recon_v[..]
             };
             (above, left, (bx * 2) as usize, ((by & 15) * 2) as usize, bp)
         };
@@ -1528,52 +2163,173 @@ impl TileContext {
         }
     }
 
-    fn ref_ctx(&self, bx: u32, by: u32) -> usize {
-        let bx4 = bx as usize;
-        let by4 = (by & 31) as usize;
-        let have_top = by > 0;
-        let have_left = bx > 0;
-
-        let above_inter = have_top && bx4 < self.above_intra.len() && !self.above_intra[bx4];
-        let left_inter = have_left && !self.left_intra[by4.min(31)];
-
-        if above_inter || left_inter { 2 } else { 1 }
+    /// The bools selecting `refs` for the block at `(bx, by)`, in coding order.
+    #[cfg(feature = "inter")]
+    fn ref_symbols(
+        &self,
+        bx: u32,
+        by: u32,
+        refs: BlockRefs,
+        comp_mode_coded: bool,
+    ) -> Vec<(RefCdf, usize, bool)> {
+        let mut symbols = Vec::with_capacity(5);
+        if comp_mode_coded {
+            let compound = matches!(refs, BlockRefs::Compound(..));
+            symbols.push((RefCdf::CompMode, self.comp_mode_ctx(bx, by), compound));
+        }
+        match refs {
+            BlockRefs::Single(ref_frame) => symbols.extend(
+                self.single_ref_bits(bx, by, ref_frame)
+                    .into_iter()
+                    .map(|(bit, ctx, value)| (RefCdf::SingleRef(bit), ctx, value)),
+            ),
+            BlockRefs::Compound(forward, backward) => {
+                debug_assert_eq!(backward, RefFrame::Altref);
+                symbols.push((RefCdf::CompRefType, self.comp_ref_type_ctx(bx, by), true));
+                let (pair, within_pair) = self.forward_ref_bits(bx, by, forward);
+                for (bit, ctx, value) in [pair, within_pair] {
+                    symbols.push((RefCdf::CompRef(bit - 2), ctx, value));
+                }
+                let ctx = self.single_ref_ctx(bx, by, &[4, 5], &[6]);
+                symbols.push((RefCdf::CompBwdref(0), ctx, true));
+            }
+        }
+        symbols
     }
 
-    fn has_inter_neighbor(&self, bx: u32, by: u32) -> bool {
+    #[cfg(feature = "inter")]
+    fn neighbor_refs(&self, bx: u32, by: u32) -> [Option<Option<[i8; 2]>>; 2] {
         let bx4 = bx as usize;
-        let by4 = (by & 31) as usize;
-        let have_top = by > 0;
-        let have_left = bx > 0;
-
-        let above_inter = have_top && bx4 < self.above_intra.len() && !self.above_intra[bx4];
-        let left_inter = have_left && !self.left_intra[by4.min(31)];
-
-        above_inter || left_inter
+        let by4 = ((by & 31) as usize).min(31);
+        let above = (by > 0 && bx4 < self.above_intra.len())
+            .then(|| (!self.above_intra[bx4]).then(|| self.above_ref[bx4]));
+        let left = (bx > 0).then(|| (!self.left_intra[by4]).then(|| self.left_ref[by4]));
+        [above, left]
+    }
+
+    #[cfg(feature = "inter")]
+    fn single_ref_ctx(&self, bx: u32, by: u32, a: &[i8], b: &[i8]) -> usize {
+        let neighbors = self.neighbor_refs(bx, by);
+        let count = |refs: &[i8]| {
+            neighbors
+                .iter()
+                .flatten()
+                .flatten()
+                .flatten()
+                .filter(|&&r| refs.contains(&r))
+                .count()
+        };
+        match count(a).cmp(&count(b)) {
+            std::cmp::Ordering::Less => 0,
+            std::cmp::Ordering::Equal => 1,
+            std::cmp::Ordering::Greater => 2,
+        }
+    }
+
+    /// dav1d `get_comp_ctx`.
+    #[cfg(feature = "inter")]
+    fn comp_mode_ctx(&self, bx: u32, by: u32) -> usize {
+        let compound = |n: Option<[i8; 2]>| n.is_some_and(|r| r[1] >= 0);
+        let backward = |n: Option<[i8; 2]>, intra: bool| n.map_or(intra, |r| r[0] >= 4);
+        match self.neighbor_refs(bx, by) {
+            [Some(a), Some(l)] => match (compound(a), compound(l)) {
+                (true, true) => 4,
+                (true, false) => 2 + usize::from(backward(l, true)),
+                (false, true) => 2 + usize::from(backward(a, true)),
+                (false, false) => usize::from(backward(a, false) ^ backward(l, false)),
+            },
+            [Some(n), None] | [None, Some(n)] => {
+                if compound(n) {
+                    3
+                } else {
+                    usize::from(backward(n, false))
+                }
+            }
+            [None, None] => 1,
+        }
     }
 
-    fn newmv_ctx(&self, bx: u32, by: u32) -> usize {
-        let bx4 = bx as usize;
-        let by4 = (by & 31) as usize;
-        let have_top = by > 0;
-        let have_left = bx > 0;
-
-        let above_inter = have_top && bx4 < self.above_intra.len() && !self.above_intra[bx4];
-        let left_inter = have_left && !self.left_intra[by4.min(31)];
-
-        let above_is_newmv = above_inter && bx4 < self.above_newmv.len() && self.above_newmv[bx4];
-        let left_is_newmv = left_inter && self.left_newmv[by4.min(31)];
-        let have_newmv = (above_is_newmv || left_is_newmv) as u32;
+    /// dav1d `get_comp_dir_ctx`.
+    #[cfg(feature = "inter")]
+    fn comp_ref_type_ctx(&self, bx: u32, by: u32) -> usize {
+        let compound = |r: [i8; 2]| r[1] >= 0;
+        let unidir = |r: [i8; 2]| (r[0] < 4) == (r[1] < 4);
+        match self.neighbor_refs(bx, by) {
+            [Some(None), Some(None)] => 2,
+            [Some(Some(n)), Some(None)] | [Some(None), Some(Some(n))] if compound(n) => {
+                1 + 2 * usize::from(unidir(n))
+            }
+            [Some(Some(a)), Some(Some(l))] => {
+                let same_dir = (a[0] >= 4) == (l[0] >= 4);
+                match (compound(a), compound(l)) {
+                    (false, false) => 1 + 2 * usize::from(same_dir),
+                    (true, false) | (false, true) => {
+                        let n = if compound(a) { a } else { l };
+                        if unidir(n) {
+                            3 + usize::from(same_dir)
+                        } else {
+                            1
+                        }
+                    }
+                    (true, true) => match (unidir(a), unidir(l)) {
+                        (false, false) => 0,
+                        (true, true) => 3 + usize::from((a[0] == 4) == (l[0] == 4)),
+                        _ => 2,
+                    },
+                }
+            }
+            [None, Some(Some(n))] | [Some(Some(n)), None] if compound(n) => {
+                4 * usize::from(unidir(n))
+            }
+            _ => 2,
+        }
+    }
 
-        let nearest_match = above_inter as u32 + left_inter as u32;
-        match nearest_match {
-            0 => 0,
-            1 => (3 - have_newmv) as usize,
-            2 => (5 - have_newmv) as usize,
-            _ => unreachable!(),
+    /// `(bit, context, value)` of the single reference bits of `ref_frame`.
+    #[cfg(feature = "inter")]
+    fn single_ref_bits(&self, bx: u32, by: u32, ref_frame: RefFrame) -> Vec<(usize, usize, bool)> {
+        const FORWARD: &[i8] = &[0, 1, 2, 3];
+        const BACKWARD: &[i8] = &[4, 5, 6];
+        let direction = (
+            0,
+            self.single_ref_ctx(bx, by, FORWARD, BACKWARD),
+            ref_frame == RefFrame::Altref,
+        );
+        if ref_frame == RefFrame::Altref {
+            return vec![
+                direction,
+                (1, self.single_ref_ctx(bx, by, &[4, 5], &[6]), true),
+            ];
         }
+        let (pair, within_pair) = self.forward_ref_bits(bx, by, ref_frame);
+        vec![direction, pair, within_pair]
+    }
+
+    /// `(bit, context, value)` of the forward reference bits of `ref_frame`.
+    #[cfg(feature = "inter")]
+    fn forward_ref_bits(
+        &self,
+        bx: u32,
+        by: u32,
+        ref_frame: RefFrame,
+    ) -> ((usize, usize, bool), (usize, usize, bool)) {
+        let pair = (
+            2,
+            self.single_ref_ctx(bx, by, &[0, 1], &[2, 3]),
+            ref_frame == RefFrame::Golden,
+        );
+        let within_pair = match ref_frame {
+            RefFrame::Golden => (4, self.single_ref_ctx(bx, by, &[2], &[3]), true),
+            _ => (
+                3,
+                self.single_ref_ctx(bx, by, &[0], &[1]),
+                ref_frame == RefFrame::Last2,
+            ),
+        };
+        (pair, within_pair)
     }
 
+    #[cfg(feature = "inter")]
     fn is_inter_ctx(&self, bx: u32, by: u32) -> usize {
         let bx4 = bx as usize;
         let by4 = (by & 31) as usize;
@@ -1605,6 +2361,7 @@ impl TileContext {
         }
     }
 
+    #[cfg(feature = "inter")]
     fn update_intra_ctx(
         &mut self,
         bx: u32,
@@ -1631,14 +2388,15 @@ impl TileContext {
         }
     }
 
-    fn update_newmv_flag(
+    #[cfg(feature = "inter")]
+    fn update_ref_ctx(
         &mut self,
         bx: u32,
         by: u32,
         bl: usize,
         mi_cols: u32,
         mi_rows: u32,
-        is_newmv: bool,
+        refs: BlockRefs,
     ) {
         let bx4 = bx as usize;
         let by4 = (by & 31) as usize;
@@ -1646,13 +2404,13 @@ impl TileContext {
         let aw = min(bw4, (mi_cols - bx) as usize);
         let lh = min(bw4, (mi_rows - by) as usize);
         for i in 0..aw {
-            if bx4 + i < self.above_newmv.len() {
-                self.above_newmv[bx4 + i] = is_newmv;
+            if bx4 + i < self.above_ref.len() {
+                self.above_ref[bx4 + i] = refs.pair();
             }
         }
         for i in 0..lh {
             if by4 + i < 32 {
-                self.left_newmv[by4 + i] = is_newmv;
+                self.left_ref[by4 + i] = refs.pair();
             }
         }
     }
@@ -1723,6 +2481,7 @@ impl<'a> TileEncoder<'a> {
             mi_rows,
             pixels,
             dq,
+            lossless: crate::dequant::is_lossless(base_q_idx, &dq.delta_q),
             recon: FramePixels {
                 width: pixels.width,
                 height: pixels.height,
@@ -1732,9 +2491,26 @@ impl<'a> TileEncoder<'a> {
                 u: vec![mid_value; (cw * ch) as usize],
                 v: vec![mid_value; (cw * ch) as usize],
             },
+            early_exit: EarlyExit::default(),
+            cdef: CdefSignal::default(),
+            cdef_pending: false,
+            restoration: RestorationSignal::default(),
+            skips: SkipMap::all_skipped(pixels.width, pixels.height),
+            delta_q: DeltaQCursor::new(SbDeltaQ::default(), base_q_idx, dq),
         }
     }
 
+    fn start_superblock(&mut self, sb_col: u32, sb_row: u32) {
+        self.dq = self.delta_q.start_superblock(sb_col, sb_row);
+        self.restoration
+            .write_superblock(&mut self.enc, &mut self.cdf.use_wiener, sb_col, sb_row);
+    }
+
+    fn write_delta_q(&mut self, whole_sb_skip: bool) {
+        self.delta_q
+            .write(&mut self.enc, &mut self.cdf.delta_q, whole_sb_skip);
+    }
+
     fn encode_block(&mut self, bx: u32, by: u32, bl: usize) {
         let px_x = bx * 4;
         let px_y = by * 4;
@@ -1751,45 +2527,23 @@ impl<'a> TileEncoder<'a> {
         let mid_value = self.pixels.bit_depth.mid_value();
         let max_value = self.pixels.bit_depth.max_value();
 
-        let above_y: Vec<u16> = (0..16)
-            .map(|i| {
-                let idx = px_x as usize + i;
-                if have_above && idx < self.ctx.above_recon_y.len() {
-                    self.ctx.above_recon_y[idx]
-                } else if have_above {
-                    self.ctx.above_recon_y
-                        [(px_x as usize + 7).min(self.ctx.above_recon_y.len() - 1)]
-                } else {
-                    mid_value
-                }
-            })
-            .collect();
-
-        let left_local_py = ((by & 15) * 4) as usize;
-        let left_y: Vec<u16> = (0..16)
-            .map(|i| {
-                let idx = left_local_py + i;
-                if have_left && idx < self.ctx.left_recon_y.len() {
-                    self.ctx.left_recon_y[idx]
-                } else if have_left {
-                    self.ctx.left_recon_y[(left_local_py + 7).min(self.ctx.left_recon_y.len() - 1)]
-                } else {
-                    mid_value
-                }
-            })
-            .collect();
-
-        let top_left_y = if have_above && have_left {
-            self.recon.y[((px_y - 1) * w + (px_x - 1)) as usize]
-        } else {
-            mid_value
-        };
+        let (above_y, left_y, top_left_y) = self.ctx.luma_edges_8x8(bx, by, &self.recon.y, w);
 
         let y_block = extract_block(&self.pixels.y, w, px_x, px_y, 8, w, h);
 
         let (y_mode, y_angle_delta) = select_best_intra_mode(
-            &y_block, &above_y, &left_y, top_left_y, have_above, have_left, 8, 8, self.dq.dc,
-            self.dq.ac, mid_value, max_value,
+            &y_block,
+            &above_y,
+            &left_y,
+            top_left_y,
+            have_above,
+            have_left,
+            8,
+            8,
+            self.dq.dc,
+            self.dq.ac,
+            self.pixels.bit_depth,
+            &self.early_exit,
         );
         let y_pred_block = generate_prediction(
             y_mode,
@@ -1804,8 +2558,14 @@ impl<'a> TileEncoder<'a> {
             mid_value,
             max_value,
         );
-        let y_txtype =
-            select_best_txtype(&y_block, &y_pred_block, self.dq.dc, self.dq.ac, max_value);
+        let y_txtype = select_best_txtype(
+            &y_block,
+            &y_pred_block,
+            self.dq.dc,
+            self.dq.ac,
+            self.pixels.bit_depth,
+            &self.early_exit,
+        );
 
         let u_pred = self.ctx.dc_prediction(bx, by, bl, 1);
         let v_pred = self.ctx.dc_prediction(bx, by, bl, 2);
@@ -1818,21 +2578,23 @@ impl<'a> TileEncoder<'a> {
             y_residual[i] = y_block[i] as i32 - y_pred_block[i] as i32;
         }
         let y_dct = dct::forward_transform_8x8(&y_residual, y_txtype);
-        let y_quant = quantize_coeffs(&y_dct, 64, self.dq.dc, self.dq.ac);
+        let y_quant = quantize_block(&y_dct, 64, &self.dq);
 
         let mut u_residual = [0i32; 16];
         for i in 0..16 {
             u_residual[i] = u_block[i] as i32 - u_pred as i32;
         }
         let u_dct = dct::forward_dct_4x4(&u_residual);
-        let u_quant = quantize_coeffs(&u_dct, 16, self.dq.dc, self.dq.ac);
+        let u_dq = self.dq.for_plane(1);
+        let u_quant = quantize_block(&u_dct, 16, &u_dq);
 
         let mut v_residual = [0i32; 16];
         for i in 0..16 {
             v_residual[i] = v_block[i] as i32 - v_pred as i32;
         }
         let v_dct = dct::forward_dct_4x4(&v_residual);
-        let v_quant = quantize_coeffs(&v_dct, 16, self.dq.dc, self.dq.ac);
+        let v_dq = self.dq.for_plane(2);
+        let v_quant = quantize_block(&v_dct, 16, &v_dq);
 
         let is_skip = y_quant.iter().all(|&c| c == 0)
             && u_quant.iter().all(|&c| c == 0)
@@ -1841,6 +2603,11 @@ impl<'a> TileEncoder<'a> {
         let skip_ctx = self.ctx.skip_ctx(bx, by);
 
         self.enc.encode_bool(is_skip, &mut self.cdf.skip[skip_ctx]);
+        if !is_skip {
+            self.cdef
+                .write_idx(&mut self.enc, &mut self.cdef_pending, by);
+        }
+        self.write_delta_q(false);
 
         let (above_mode_ctx, left_mode_ctx) = self.ctx.mode_ctx(bx, by);
         self.enc.encode_symbol(
@@ -1942,7 +2709,8 @@ impl<'a> TileEncoder<'a> {
         let y_deq = dequantize_coeffs(&y_quant, 64, self.dq.dc, self.dq.ac);
         let mut y_deq_arr = [0i32; 64];
         y_deq_arr.copy_from_slice(&y_deq);
-        let y_recon_residual = dct::inverse_transform_8x8(&y_deq_arr, y_txtype);
+        let y_recon_residual =
+            dct::inverse_transform_8x8(&y_deq_arr, y_txtype, self.pixels.bit_depth);
 
         for r in 0..8u32 {
             for c in 0..8u32 {
@@ -1957,10 +2725,10 @@ impl<'a> TileEncoder<'a> {
             }
         }
 
-        let u_deq = dequantize_coeffs(&u_quant, 16, self.dq.dc, self.dq.ac);
+        let u_deq = dequantize_coeffs(&u_quant, 16, u_dq.dc, u_dq.ac);
         let mut u_deq_arr = [0i32; 16];
         u_deq_arr.copy_from_slice(&u_deq);
-        let u_recon_residual = dct::inverse_dct_4x4(&u_deq_arr);
+        let u_recon_residual = dct::inverse_dct_4x4(&u_deq_arr, self.pixels.bit_depth);
 
         for r in 0..4u32 {
             for c in 0..4u32 {
@@ -1974,10 +2742,10 @@ impl<'a> TileEncoder<'a> {
             }
         }
 
-        let v_deq = dequantize_coeffs(&v_quant, 16, self.dq.dc, self.dq.ac);
+        let v_deq = dequantize_coeffs(&v_quant, 16, v_dq.dc, v_dq.ac);
         let mut v_deq_arr = [0i32; 16];
         v_deq_arr.copy_from_slice(&v_deq);
-        let v_recon_residual = dct::inverse_dct_4x4(&v_deq_arr);
+        let v_recon_residual = dct::inverse_dct_4x4(&v_deq_arr, self.pixels.bit_depth);
 
         for r in 0..4u32 {
             for c in 0..4u32 {
@@ -2071,12 +2839,17 @@ impl<'a> TileEncoder<'a> {
             .update_partition_ctx(bx, by, bl, self.mi_cols, self.mi_rows);
         self.ctx
             .update_skip_ctx(bx, by, bl, self.mi_cols, self.mi_rows, is_skip);
+        self.skips.fill(bx, by, bl, is_skip);
         self.ctx
             .update_mode_ctx(bx, by, bl, self.mi_cols, self.mi_rows, y_mode);
     }
 
-    fn encode_block_16x16(&mut self, bx: u32, by: u32) {
-        let bl = 3;
+    /// Codes an 8x8 block of a lossless frame: four 4x4 luma and one 4x4
+    /// chroma Walsh-Hadamard transform per plane, each reconstructing the
+    /// source exactly. Samples past the visible frame edge are reconstructed
+    /// too because the decoder predicts from them.
+    fn encode_lossless_block(&mut self, bx: u32, by: u32) {
+        let bl = 4;
         let px_x = bx * 4;
         let px_y = by * 4;
         let w = self.pixels.width;
@@ -2088,98 +2861,117 @@ impl<'a> TileEncoder<'a> {
 
         let have_above = by > 0;
         let have_left = bx > 0;
+
         let mid_value = self.pixels.bit_depth.mid_value();
         let max_value = self.pixels.bit_depth.max_value();
 
-        let above_y: Vec<u16> = (0..32)
-            .map(|i| {
-                let idx = px_x as usize + i;
-                if have_above && idx < self.ctx.above_recon_y.len() {
-                    self.ctx.above_recon_y[idx]
-                } else if have_above {
-                    self.ctx.above_recon_y
-                        [(px_x as usize + 15).min(self.ctx.above_recon_y.len() - 1)]
-                } else {
-                    mid_value
-                }
-            })
-            .collect();
-
+        let mut above_y = [mid_value; 8];
+        let mut left_y = [mid_value; 8];
         let left_local_py = ((by & 15) * 4) as usize;
-        let left_y: Vec<u16> = (0..32)
-            .map(|i| {
-                let idx = left_local_py + i;
-                if have_left && idx < self.ctx.left_recon_y.len() {
-                    self.ctx.left_recon_y[idx]
-                } else if have_left {
-                    self.ctx.left_recon_y[(left_local_py + 15).min(self.ctx.left_recon_y.len() - 1)]
-                } else {
-                    mid_value
-                }
-            })
-            .collect();
-
+        for i in 0..8 {
+            if have_above {
+                above_y[i] = self.ctx.above_recon_y[px_x as usize + i];
+            }
+            if have_left {
+                left_y[i] = self.ctx.left_recon_y[left_local_py + i];
+            }
+        }
         let top_left_y = if have_above && have_left {
             self.recon.y[((px_y - 1) * w + (px_x - 1)) as usize]
         } else {
             mid_value
         };
 
-        let y_block = extract_block(&self.pixels.y, w, px_x, px_y, 16, w, h);
-
-        let (y_mode, y_angle_delta) = select_best_intra_mode(
-            &y_block, &above_y, &left_y, top_left_y, have_above, have_left, 16, 16, self.dq.dc,
-            self.dq.ac, mid_value, max_value,
-        );
-        let y_pred_block = generate_prediction(
-            y_mode,
-            y_angle_delta,
-            &above_y,
-            &left_y,
-            top_left_y,
-            have_above,
-            have_left,
-            16,
-            16,
-            mid_value,
-            max_value,
-        );
-        let y_txtype = dct::TxType::DctDct;
-
-        let u_pred = self.ctx.dc_prediction(bx, by, bl, 1);
-        let v_pred = self.ctx.dc_prediction(bx, by, bl, 2);
-
-        let u_block = extract_block(&self.pixels.u, cw, chroma_px_x, chroma_px_y, 8, cw, ch);
-        let v_block = extract_block(&self.pixels.v, cw, chroma_px_x, chroma_px_y, 8, cw, ch);
+        let mut y_block = [0u16; 64];
+        y_block.copy_from_slice(&extract_block(&self.pixels.y, w, px_x, px_y, 8, w, h));
 
-        let mut y_residual = [0i32; 256];
-        for i in 0..256 {
-            y_residual[i] = y_block[i] as i32 - y_pred_block[i] as i32;
+        let mut candidates = vec![0u8];
+        if have_above {
+            candidates.push(1);
         }
-        let y_dct = dct::forward_transform_16x16(&y_residual);
-        let y_quant = quantize_coeffs(&y_dct, 256, self.dq.dc, self.dq.ac);
-
-        let mut u_residual = [0i32; 64];
-        for i in 0..64 {
-            u_residual[i] = u_block[i] as i32 - u_pred as i32;
+        if have_left {
+            candidates.push(2);
+        }
+        if have_above && have_left {
+            candidates.extend_from_slice(&[9, 10, 11, 12]);
+        }
+        let y_mode = candidates
+            .into_iter()
+            .min_by_key(|&mode| {
+                LOSSLESS_SUB_BLOCKS
+                    .iter()
+                    .map(|&(sx, sy)| {
+                        let pred = predict_sub_block_4x4(
+                            mode, sx, sy, &above_y, &left_y, top_left_y, have_above, have_left,
+                            &y_block, mid_value, max_value,
+                        );
+                        crate::satd::compute_satd(&y_block[sy * 8 + sx..], &pred, 4, 4, 8, 4)
+                    })
+                    .sum::<u64>()
+            })
+            .unwrap_or(0);
+
+        let mut y_rec = [0u16; 64];
+        let mut y_quants = Vec::with_capacity(4);
+        for &(sx, sy) in &LOSSLESS_SUB_BLOCKS {
+            let pred = predict_sub_block_4x4(
+                y_mode, sx, sy, &above_y, &left_y, top_left_y, have_above, have_left, &y_rec,
+                mid_value, max_value,
+            );
+            let mut residual = [0i32; 16];
+            for r in 0..4 {
+                for c in 0..4 {
+                    residual[r * 4 + c] =
+                        y_block[(sy + r) * 8 + sx + c] as i32 - pred[r * 4 + c] as i32;
+                }
+            }
+            let quant = lossless_quantize(&residual);
+            let recon_residual = lossless_reconstruct(&quant);
+            for r in 0..4 {
+                for c in 0..4 {
+                    y_rec[(sy + r) * 8 + sx + c] =
+                        (pred[r * 4 + c] as i32 + recon_residual[r * 4 + c])
+                            .clamp(0, max_value as i32) as u16;
+                }
+            }
+            y_quants.push(quant);
         }
-        let u_dct = dct::forward_dct_8x8(&u_residual);
-        let u_quant = quantize_coeffs(&u_dct, 64, self.dq.dc, self.dq.ac);
 
-        let mut v_residual = [0i32; 64];
-        for i in 0..64 {
-            v_residual[i] = v_block[i] as i32 - v_pred as i32;
+        let mut chroma = Vec::with_capacity(2);
+        for plane in 1..=2 {
+            let source = if plane == 1 {
+                &self.pixels.u
+            } else {
+                &self.pixels.v
+            };
+            let block = extract_block(source, cw, chroma_px_x, chroma_px_y, 4, cw, ch);
+            let pred = self.ctx.dc_prediction(bx, by, bl, plane);
+            let mut residual = [0i32; 16];
+            for i in 0..16 {
+                residual[i] = block[i] as i32 - pred as i32;
+            }
+            let quant = lossless_quantize(&residual);
+            let recon_residual = lossless_reconstruct(&quant);
+            let mut rec = [0u16; 16];
+            for i in 0..16 {
+                rec[i] = (pred as i32 + recon_residual[i]).clamp(0, max_value as i32) as u16;
+            }
+            chroma.push((quant, rec));
         }
-        let v_dct = dct::forward_dct_8x8(&v_residual);
-        let v_quant = quantize_coeffs(&v_dct, 64, self.dq.dc, self.dq.ac);
+        let (u_quant, u_rec) = &chroma[0];
+        let (v_quant, v_rec) = &chroma[1];
 
-        let is_skip = y_quant.iter().all(|&c| c == 0)
+        let is_skip = y_quants.iter().flatten().all(|&c| c == 0)
             && u_quant.iter().all(|&c| c == 0)
             && v_quant.iter().all(|&c| c == 0);
 
         let skip_ctx = self.ctx.skip_ctx(bx, by);
-
         self.enc.encode_bool(is_skip, &mut self.cdf.skip[skip_ctx]);
+        if !is_skip {
+            self.cdef
+                .write_idx(&mut self.enc, &mut self.cdef_pending, by);
+        }
+        self.write_delta_q(false);
 
         let (above_mode_ctx, left_mode_ctx) = self.ctx.mode_ctx(bx, by);
         self.enc.encode_symbol(
@@ -2187,200 +2979,105 @@ impl<'a> TileEncoder<'a> {
             &mut self.cdf.kf_y_mode[above_mode_ctx][left_mode_ctx],
             12,
         );
-
         if (1..=8).contains(&y_mode) {
-            self.enc.encode_symbol(
-                (y_angle_delta + 3) as u32,
-                &mut self.cdf.angle_delta[(y_mode - 1) as usize],
-                6,
-            );
+            self.enc
+                .encode_symbol(3, &mut self.cdf.angle_delta[(y_mode - 1) as usize], 6);
         }
+        self.enc
+            .encode_symbol(0, &mut self.cdf.uv_mode[1][y_mode as usize], 13);
+
+        if is_skip {
+            let skip_cf_ctx = coef_ctx_value(0, false, true);
+            self.ctx.update_coef_ctx(
+                bx,
+                by,
+                bl,
+                self.mi_cols,
+                self.mi_rows,
+                skip_cf_ctx,
+                skip_cf_ctx,
+                skip_cf_ctx,
+            );
+        } else {
+            for (&(sx, sy), quant) in LOSSLESS_SUB_BLOCKS.iter().zip(&y_quants) {
+                let x4 = bx + (sx / 4) as u32;
+                let y4 = by + (sy / 4) as u32;
+                let txb_skip_ctx = self.ctx.luma_txb_skip_ctx_4x4(x4, y4);
+                let dc_sign_ctx = self.ctx.luma_dc_sign_ctx_4x4(x4, y4);
+                let (cul, dc_neg, dc_zero) = encode_coefficients(
+                    &mut self.enc,
+                    &mut self.cdf,
+                    quant,
+                    &DEFAULT_SCAN_4X4,
+                    false,
+                    false,
+                    0,
+                    txb_skip_ctx,
+                    dc_sign_ctx,
+                    y_mode,
+                    None,
+                );
+                self.ctx
+                    .update_luma_coef_ctx_4x4(x4, y4, coef_ctx_value(cul, dc_neg, dc_zero));
+            }
 
-        let cfl_allowed = bl >= 2;
-        let uv_n_syms = if cfl_allowed { 13 } else { 12 };
-        let cfl_idx = usize::from(cfl_allowed);
-        self.enc.encode_symbol(
-            0,
-            &mut self.cdf.uv_mode[cfl_idx][y_mode as usize],
-            uv_n_syms,
-        );
-
-        let (y_cul, y_dc_neg, y_dc_zero);
-        let (u_cul, u_dc_neg, u_dc_zero);
-        let (v_cul, v_dc_neg, v_dc_zero);
+            let mut chroma_ctx = [0u8; 2];
+            for (plane, quant) in [(1, u_quant), (2, v_quant)] {
+                let txb_skip_ctx = self.ctx.chroma_txb_skip_ctx(bx, by, bl, plane);
+                let dc_sign_ctx = self.ctx.dc_sign_ctx(bx, by, bl, plane);
+                let (cul, dc_neg, dc_zero) = encode_coefficients(
+                    &mut self.enc,
+                    &mut self.cdf,
+                    quant,
+                    &DEFAULT_SCAN_4X4,
+                    true,
+                    false,
+                    0,
+                    txb_skip_ctx,
+                    dc_sign_ctx,
+                    y_mode,
+                    None,
+                );
+                chroma_ctx[plane - 1] = coef_ctx_value(cul, dc_neg, dc_zero);
+            }
+            self.ctx.update_chroma_coef_ctx(
+                bx,
+                by,
+                bl,
+                self.mi_cols,
+                self.mi_rows,
+                chroma_ctx[0],
+                chroma_ctx[1],
+            );
+        }
 
-        if !is_skip {
-            let y_txb_skip_ctx = 0;
-            let y_dc_sign_ctx = self.ctx.dc_sign_ctx(bx, by, bl, 0);
-            let y_result = encode_transform_block(
-                &mut self.enc,
-                &mut self.cdf,
-                &y_quant,
-                &DEFAULT_SCAN_16X16,
-                false,
-                false,
-                2,
-                y_txb_skip_ctx,
-                y_dc_sign_ctx,
-                y_mode,
-                y_txtype,
-            );
-            y_cul = y_result.0;
-            y_dc_neg = y_result.1;
-            y_dc_zero = y_result.2;
-
-            let u_txb_skip_ctx = self.ctx.chroma_txb_skip_ctx(bx, by, bl, 1);
-            let u_dc_sign_ctx = self.ctx.dc_sign_ctx(bx, by, bl, 1);
-            let u_result = encode_transform_block(
-                &mut self.enc,
-                &mut self.cdf,
-                &u_quant,
-                &DEFAULT_SCAN_8X8,
-                true,
-                false,
-                1,
-                u_txb_skip_ctx,
-                u_dc_sign_ctx,
-                y_mode,
-                dct::TxType::DctDct,
-            );
-            u_cul = u_result.0;
-            u_dc_neg = u_result.1;
-            u_dc_zero = u_result.2;
-
-            let v_txb_skip_ctx = self.ctx.chroma_txb_skip_ctx(bx, by, bl, 2);
-            let v_dc_sign_ctx = self.ctx.dc_sign_ctx(bx, by, bl, 2);
-            let v_result = encode_transform_block(
-                &mut self.enc,
-                &mut self.cdf,
-                &v_quant,
-                &DEFAULT_SCAN_8X8,
-                true,
-                false,
-                1,
-                v_txb_skip_ctx,
-                v_dc_sign_ctx,
-                y_mode,
-                dct::TxType::DctDct,
-            );
-            v_cul = v_result.0;
-            v_dc_neg = v_result.1;
-            v_dc_zero = v_result.2;
-        } else {
-            y_cul = 0;
-            y_dc_neg = false;
-            y_dc_zero = true;
-            u_cul = 0;
-            u_dc_neg = false;
-            u_dc_zero = true;
-            v_cul = 0;
-            v_dc_neg = false;
-            v_dc_zero = true;
-        }
-
-        let y_deq = dequantize_coeffs(&y_quant, 256, self.dq.dc, self.dq.ac);
-        let mut y_deq_arr = [0i32; 256];
-        y_deq_arr.copy_from_slice(&y_deq);
-        let y_recon_residual = dct::inverse_transform_16x16(&y_deq_arr);
-
-        for r in 0..16u32 {
-            for c in 0..16u32 {
+        for r in 0..8u32 {
+            for c in 0..8u32 {
                 let dest_x = px_x + c;
                 let dest_y = px_y + r;
                 if dest_x < w && dest_y < h {
-                    let pixel = (y_pred_block[(r * 16 + c) as usize] as i32
-                        + y_recon_residual[(r * 16 + c) as usize])
-                        .clamp(0, max_value as i32) as u16;
-                    self.recon.y[(dest_y * w + dest_x) as usize] = pixel;
+                    self.recon.y[(dest_y * w + dest_x) as usize] = y_rec[(r * 8 + c) as usize];
                 }
             }
         }
-
-        let u_deq = dequantize_coeffs(&u_quant, 64, self.dq.dc, self.dq.ac);
-        let mut u_deq_arr = [0i32; 64];
-        u_deq_arr.copy_from_slice(&u_deq);
-        let u_recon_residual = dct::inverse_dct_8x8(&u_deq_arr);
-
-        for r in 0..8u32 {
-            for c in 0..8u32 {
-                let dest_x = chroma_px_x + c;
-                let dest_y = chroma_px_y + r;
-                if dest_x < cw && dest_y < ch {
-                    let pixel = (u_pred as i32 + u_recon_residual[(r * 8 + c) as usize])
-                        .clamp(0, max_value as i32) as u16;
-                    self.recon.u[(dest_y * cw + dest_x) as usize] = pixel;
-                }
-            }
-        }
-
-        let v_deq = dequantize_coeffs(&v_quant, 64, self.dq.dc, self.dq.ac);
-        let mut v_deq_arr = [0i32; 64];
-        v_deq_arr.copy_from_slice(&v_deq);
-        let v_recon_residual = dct::inverse_dct_8x8(&v_deq_arr);
-
-        for r in 0..8u32 {
-            for c in 0..8u32 {
+        for r in 0..4u32 {
+            for c in 0..4u32 {
                 let dest_x = chroma_px_x + c;
                 let dest_y = chroma_px_y + r;
                 if dest_x < cw && dest_y < ch {
-                    let pixel = (v_pred as i32 + v_recon_residual[(r * 8 + c) as usize])
-                        .clamp(0, max_value as i32) as u16;
-                    self.recon.v[(dest_y * cw + dest_x) as usize] = pixel;
+                    let idx = (dest_y * cw + dest_x) as usize;
+                    self.recon.u[idx] = u_rec[(r * 4 + c) as usize];
+                    self.recon.v[idx] = v_rec[(r * 4 + c) as usize];
                 }
             }
         }
 
-        let mut y_bottom_row = vec![mid_value; 16];
-        let mut y_right_col = vec![mid_value; 16];
-        for c in 0..16u32 {
-            let dest_x = px_x + c;
-            let dest_y = px_y + 15;
-            if dest_x < w && dest_y < h {
-                y_bottom_row[c as usize] = self.recon.y[(dest_y * w + dest_x) as usize];
-            }
-        }
-        for r in 0..16u32 {
-            let dest_x = px_x + 15;
-            let dest_y = px_y + r;
-            if dest_x < w && dest_y < h {
-                y_right_col[r as usize] = self.recon.y[(dest_y * w + dest_x) as usize];
-            }
-        }
-
-        let mut u_bottom_row = vec![mid_value; 8];
-        let mut u_right_col = vec![mid_value; 8];
-        for c in 0..8u32 {
-            let dest_x = chroma_px_x + c;
-            let dest_y = chroma_px_y + 7;
-            if dest_x < cw && dest_y < ch {
-                u_bottom_row[c as usize] = self.recon.u[(dest_y * cw + dest_x) as usize];
-            }
-        }
-        for r in 0..8u32 {
-            let dest_x = chroma_px_x + 7;
-            let dest_y = chroma_px_y + r;
-            if dest_x < cw && dest_y < ch {
-                u_right_col[r as usize] = self.recon.u[(dest_y * cw + dest_x) as usize];
-            }
-        }
-
-        let mut v_bottom_row = vec![mid_value; 8];
-        let mut v_right_col = vec![mid_value; 8];
-        for c in 0..8u32 {
-            let dest_x = chroma_px_x + c;
-            let dest_y = chroma_px_y + 7;
-            if dest_x < cw && dest_y < ch {
-                v_bottom_row[c as usize] = self.recon.v[(dest_y * cw + dest_x) as usize];
-            }
-        }
-        for r in 0..8u32 {
-            let dest_x = chroma_px_x + 7;
-            let dest_y = chroma_px_y + r;
-            if dest_x < cw && dest_y < ch {
-                v_right_col[r as usize] = self.recon.v[(dest_y * cw + dest_x) as usize];
-            }
-        }
-
+        let y_bottom_row: Vec<u16> = (0..8).map(|c| y_rec[56 + c]).collect();
+        let y_right_col: Vec<u16> = (0..8).map(|r| y_rec[r * 8 + 7]).collect();
+        let u_bottom_row: Vec<u16> = (0..4).map(|c| u_rec[12 + c]).collect();
+        let u_right_col: Vec<u16> = (0..4).map(|r| u_rec[r * 4 + 3]).collect();
+        let v_bottom_row: Vec<u16> = (0..4).map(|c| v_rec[12 + c]).collect();
+        let v_right_col: Vec<u16> = (0..4).map(|r| v_rec[r * 4 + 3]).collect();
         self.ctx.update_recon(
             bx,
             by,
@@ -2393,172 +3090,427 @@ impl<'a> TileEncoder<'a> {
             &v_bottom_row,
             &v_right_col,
         );
-        let y_cf_ctx = coef_ctx_value(y_cul, y_dc_neg, y_dc_zero);
-        let u_cf_ctx = coef_ctx_value(u_cul, u_dc_neg, u_dc_zero);
-        let v_cf_ctx = coef_ctx_value(v_cul, v_dc_neg, v_dc_zero);
-        self.ctx.update_coef_ctx(
-            bx,
-            by,
-            bl,
-            self.mi_cols,
-            self.mi_rows,
-            y_cf_ctx,
-            u_cf_ctx,
-            v_cf_ctx,
-        );
         self.ctx
             .update_partition_ctx(bx, by, bl, self.mi_cols, self.mi_rows);
         self.ctx
             .update_skip_ctx(bx, by, bl, self.mi_cols, self.mi_rows, is_skip);
+        self.skips.fill(bx, by, bl, is_skip);
         self.ctx
             .update_mode_ctx(bx, by, bl, self.mi_cols, self.mi_rows, y_mode);
     }
 
-    fn skip_mse(&self, bx: u32, by: u32, bl: usize) -> u64 {
+    fn encode_block_square(&mut self, bx: u32, by: u32, bl: usize) {
+        let n = 128usize >> bl;
+        let cn = n / 2;
         let px_x = bx * 4;
         let px_y = by * 4;
-        let block_size = 1u32 << (7 - bl);
-        let chroma_size = block_size / 2;
         let w = self.pixels.width;
         let h = self.pixels.height;
         let cw = w.div_ceil(2);
         let ch = h.div_ceil(2);
-        let cpx = px_x / 2;
-        let cpy = px_y / 2;
+        let chroma_px_x = px_x / 2;
+        let chroma_px_y = px_y / 2;
 
-        let y_pred = self.ctx.dc_prediction(bx, by, bl, 0) as i64;
-        let u_pred = self.ctx.dc_prediction(bx, by, bl, 1) as i64;
-        let v_pred = self.ctx.dc_prediction(bx, by, bl, 2) as i64;
+        let have_above = by > 0;
+        let have_left = bx > 0;
+        let mid_value = self.pixels.bit_depth.mid_value();
+        let max_value = self.pixels.bit_depth.max_value();
 
-        let mut y_sse = 0u64;
-        let mut y_count = 0u64;
-        for r in 0..block_size {
-            for c in 0..block_size {
-                let sy = min(px_y + r, h - 1);
-                let sx = min(px_x + c, w - 1);
-                let val = self.pixels.y[(sy * w + sx) as usize] as i64;
-                let diff = val - y_pred;
-                y_sse += (diff * diff) as u64;
-                y_count += 1;
-            }
-        }
+        let above_y: Vec<u16> = (0..2 * n)
+            .map(|i| {
+                if have_above {
+                    self.ctx.above_recon_y[px_x as usize + i.min(n - 1)]
+                } else {
+                    mid_value
+                }
+            })
+            .collect();
+        let left_local_py = ((by & 15) * 4) as usize;
+        let left_y: Vec<u16> = (0..2 * n)
+            .map(|i| {
+                if have_left {
+                    self.ctx.left_recon_y[left_local_py + i.min(n - 1)]
+                } else {
+                    mid_value
+                }
+            })
+            .collect();
+        let top_left_y = if have_above && have_left {
+            self.recon.y[((px_y - 1) * w + (px_x - 1)) as usize]
+        } else {
+            mid_value
+        };
 
-        let mut uv_sse = 0u64;
-        let mut uv_count = 0u64;
-        for r in 0..chroma_size {
-            for c in 0..chroma_size {
-                let sy = min(cpy + r, ch - 1);
-                let sx = min(cpx + c, cw - 1);
-                let u_val = self.pixels.u[(sy * cw + sx) as usize] as i64;
-                let v_val = self.pixels.v[(sy * cw + sx) as usize] as i64;
-                let u_diff = u_val - u_pred;
-                let v_diff = v_val - v_pred;
-                uv_sse += (u_diff * u_diff + v_diff * v_diff) as u64;
-                uv_count += 1;
-            }
-        }
+        let y_block = extract_block(&self.pixels.y, w, px_x, px_y, n, w, h);
+        let (y_mode, y_angle_delta) = select_best_intra_mode(
+            &y_block,
+            &above_y,
+            &left_y,
+            top_left_y,
+            have_above,
+            have_left,
+            n,
+            n,
+            self.dq.dc,
+            self.dq.ac,
+            self.pixels.bit_depth,
+            &self.early_exit,
+        );
+        let y_pred_block = generate_prediction(
+            y_mode,
+            y_angle_delta,
+            &above_y,
+            &left_y,
+            top_left_y,
+            have_above,
+            have_left,
+            n,
+            n,
+            mid_value,
+            max_value,
+        );
 
-        let y_mse = y_sse / y_count.max(1);
-        let uv_mse = uv_sse / uv_count.max(1);
-        y_mse + 8 * uv_mse
-    }
+        let u_pred = self.ctx.dc_prediction(bx, by, bl, 1);
+        let v_pred = self.ctx.dc_prediction(bx, by, bl, 2);
+        let u_block = extract_block(&self.pixels.u, cw, chroma_px_x, chroma_px_y, cn, cw, ch);
+        let v_block = extract_block(&self.pixels.v, cw, chroma_px_x, chroma_px_y, cn, cw, ch);
 
-    fn should_use_partition_none(&self, _bx: u32, _by: u32, _bl: usize) -> bool {
-        // Disable intra skip partitions
-        false
-    }
+        let y_residual: Vec<i32> = y_block
+            .iter()
+            .zip(&y_pred_block)
+            .map(|(&s, &p)| s as i32 - p as i32)
+            .collect();
+        let y_quant = quantize_square(&forward_square_dct(&y_residual, n), n, &self.dq);
 
-    fn encode_skip_block(&mut self, bx: u32, by: u32, bl: usize) {
-        let px_x = bx * 4;
-        let px_y = by * 4;
-        let block_size = 1u32 << (7 - bl);
-        let chroma_size = block_size / 2;
-        let w = self.pixels.width;
-        let h = self.pixels.height;
-        let cw = w.div_ceil(2);
-        let ch = h.div_ceil(2);
-        let cpx = px_x / 2;
-        let cpy = px_y / 2;
+        let u_residual: Vec<i32> = u_block.iter().map(|&s| s as i32 - u_pred as i32).collect();
+        let u_dq = self.dq.for_plane(1);
+        let u_quant = quantize_square(&forward_square_dct(&u_residual, cn), cn, &u_dq);
 
-        let y_pred = self.ctx.dc_prediction(bx, by, bl, 0);
-        let u_pred = self.ctx.dc_prediction(bx, by, bl, 1);
-        let v_pred = self.ctx.dc_prediction(bx, by, bl, 2);
+        let v_residual: Vec<i32> = v_block.iter().map(|&s| s as i32 - v_pred as i32).collect();
+        let v_dq = self.dq.for_plane(2);
+        let v_quant = quantize_square(&forward_square_dct(&v_residual, cn), cn, &v_dq);
+
+        let is_skip = y_quant.iter().all(|&c| c == 0)
+            && u_quant.iter().all(|&c| c == 0)
+            && v_quant.iter().all(|&c| c == 0);
 
         let skip_ctx = self.ctx.skip_ctx(bx, by);
 
-        self.enc.encode_bool(true, &mut self.cdf.skip[skip_ctx]);
+        self.enc.encode_bool(is_skip, &mut self.cdf.skip[skip_ctx]);
+        if !is_skip {
+            self.cdef
+                .write_idx(&mut self.enc, &mut self.cdef_pending, by);
+        }
+        self.write_delta_q(bl == 1 && is_skip);
 
         let (above_mode_ctx, left_mode_ctx) = self.ctx.mode_ctx(bx, by);
         self.enc.encode_symbol(
-            0,
+            y_mode as u32,
             &mut self.cdf.kf_y_mode[above_mode_ctx][left_mode_ctx],
             12,
         );
 
+        if (1..=8).contains(&y_mode) {
+            self.enc.encode_symbol(
+                (y_angle_delta + 3) as u32,
+                &mut self.cdf.angle_delta[(y_mode - 1) as usize],
+                6,
+            );
+        }
+
         let cfl_allowed = bl >= 2;
         let uv_n_syms = if cfl_allowed { 13 } else { 12 };
         let cfl_idx = usize::from(cfl_allowed);
-        self.enc
-            .encode_symbol(0, &mut self.cdf.uv_mode[cfl_idx][0], uv_n_syms);
+        self.enc.encode_symbol(
+            0,
+            &mut self.cdf.uv_mode[cfl_idx][y_mode as usize],
+            uv_n_syms,
+        );
+
+        let (y_scan, y_t_dim_ctx) = square_tx_scan(n);
+        let (uv_scan, uv_t_dim_ctx) = square_tx_scan(cn);
+        let y_txtype = (n == 16).then_some(dct::TxType::DctDct);
+        let uncoded = (0, false, true);
+        let (y_coef, u_coef, v_coef) = if is_skip {
+            (uncoded, uncoded, uncoded)
+        } else {
+            let y_dc_sign_ctx = self.ctx.dc_sign_ctx(bx, by, bl, 0);
+            let y_result = encode_coefficients(
+                &mut self.enc,
+                &mut self.cdf,
+                &y_quant,
+                y_scan,
+                false,
+                false,
+                y_t_dim_ctx,
+                0,
+                y_dc_sign_ctx,
+                y_mode,
+                y_txtype,
+            );
+
+            let u_txb_skip_ctx = self.ctx.chroma_txb_skip_ctx(bx, by, bl, 1);
+            let u_dc_sign_ctx = self.ctx.dc_sign_ctx(bx, by, bl, 1);
+            let u_result = encode_transform_block(
+                &mut self.enc,
+                &mut self.cdf,
+                &u_quant,
+                uv_scan,
+                true,
+                false,
+                uv_t_dim_ctx,
+                u_txb_skip_ctx,
+                u_dc_sign_ctx,
+                y_mode,
+                dct::TxType::DctDct,
+            );
 
-        for r in 0..block_size {
-            for c in 0..block_size {
-                let dest_x = px_x + c;
-                let dest_y = px_y + r;
-                if dest_x < w && dest_y < h {
-                    self.recon.y[(dest_y * w + dest_x) as usize] = y_pred;
-                }
-            }
-        }
-        for r in 0..chroma_size {
-            for c in 0..chroma_size {
-                let dest_x = cpx + c;
-                let dest_y = cpy + r;
-                if dest_x < cw && dest_y < ch {
-                    self.recon.u[(dest_y * cw + dest_x) as usize] = u_pred;
-                    self.recon.v[(dest_y * cw + dest_x) as usize] = v_pred;
-                }
-            }
-        }
+            let v_txb_skip_ctx = self.ctx.chroma_txb_skip_ctx(bx, by, bl, 2);
+            let v_dc_sign_ctx = self.ctx.dc_sign_ctx(bx, by, bl, 2);
+            let v_result = encode_transform_block(
+                &mut self.enc,
+                &mut self.cdf,
+                &v_quant,
+                uv_scan,
+                true,
+                false,
+                uv_t_dim_ctx,
+                v_txb_skip_ctx,
+                v_dc_sign_ctx,
+                y_mode,
+                dct::TxType::DctDct,
+            );
+            (y_result, u_result, v_result)
+        };
 
-        let y_bp = block_size as usize;
-        let c_bp = chroma_size as usize;
-        let y_bottom = vec![y_pred; y_bp];
-        let y_right = vec![y_pred; y_bp];
-        let u_bottom = vec![u_pred; c_bp];
-        let u_right = vec![u_pred; c_bp];
-        let v_bottom = vec![v_pred; c_bp];
-        let v_right = vec![v_pred; c_bp];
+        let reconstruct = |pred: &[u16], quant: &[i32], size: usize, dq: &DequantValues| {
+            let residual = inverse_square_dct(
+                &dequantize_square(quant, size, dq),
+                size,
+                self.pixels.bit_depth,
+            );
+            pred.iter()
+                .zip(&residual)
+                .map(|(&p, &r)| (p as i32 + r).clamp(0, max_value as i32) as u16)
+                .collect::<Vec<u16>>()
+        };
+        let y_rec = reconstruct(&y_pred_block, &y_quant, n, &self.dq);
+        let u_rec = reconstruct(&vec![u_pred; cn * cn], &u_quant, cn, &u_dq);
+        let v_rec = reconstruct(&vec![v_pred; cn * cn], &v_quant, cn, &v_dq);
+        store_block(&mut self.recon.y, w, h, px_x, px_y, n, &y_rec);
+        store_block(
+            &mut self.recon.u,
+            cw,
+            ch,
+            chroma_px_x,
+            chroma_px_y,
+            cn,
+            &u_rec,
+        );
+        store_block(
+            &mut self.recon.v,
+            cw,
+            ch,
+            chroma_px_x,
+            chroma_px_y,
+            cn,
+            &v_rec,
+        );
 
+        let bottom_row = |rec: &[u16], size: usize| rec[(size - 1) * size..].to_vec();
+        let right_col = |rec: &[u16], size: usize| {
+            (0..size)
+                .map(|r| rec[r * size + size - 1])
+                .collect::<Vec<_>>()
+        };
         self.ctx.update_recon(
             bx,
             by,
             self.mi_cols,
             self.mi_rows,
-            &y_bottom,
-            &y_right,
-            &u_bottom,
-            &u_right,
-            &v_bottom,
-            &v_right,
+            &bottom_row(&y_rec, n),
+            &right_col(&y_rec, n),
+            &bottom_row(&u_rec, cn),
+            &right_col(&u_rec, cn),
+            &bottom_row(&v_rec, cn),
+            &right_col(&v_rec, cn),
         );
-        let skip_cf = coef_ctx_value(0, false, true);
+        let y_cf_ctx = coef_ctx_value(y_coef.0, y_coef.1, y_coef.2);
+        let u_cf_ctx = coef_ctx_value(u_coef.0, u_coef.1, u_coef.2);
+        let v_cf_ctx = coef_ctx_value(v_coef.0, v_coef.1, v_coef.2);
         self.ctx.update_coef_ctx(
             bx,
             by,
             bl,
             self.mi_cols,
             self.mi_rows,
-            skip_cf,
-            skip_cf,
-            skip_cf,
+            y_cf_ctx,
+            u_cf_ctx,
+            v_cf_ctx,
         );
         self.ctx
             .update_partition_ctx(bx, by, bl, self.mi_cols, self.mi_rows);
         self.ctx
-            .update_skip_ctx(bx, by, bl, self.mi_cols, self.mi_rows, true);
+            .update_skip_ctx(bx, by, bl, self.mi_cols, self.mi_rows, is_skip);
+        self.skips.fill(bx, by, bl, is_skip);
         self.ctx
-            .update_mode_ctx(bx, by, bl, self.mi_cols, self.mi_rows, 0);
+            .update_mode_ctx(bx, by, bl, self.mi_cols, self.mi_rows, y_mode);
+    }
+
+    fn can_code_whole(&self, bx: u32, by: u32, bl: usize) -> bool {
+        let bsz = 32u32 >> bl;
+        !self.lossless && bl >= 1 && bx + bsz <= self.mi_cols && by + bsz <= self.mi_rows
+    }
+
+    fn write_partition(&mut self, bl: usize, bx: u32, by: u32, partition: u32) {
+        let part_ctx = self.ctx.partition_ctx(bx, by, bl);
+        self.enc.encode_symbol(
+            partition,
+            &mut self.cdf.partition[bl][part_ctx],
+            PARTITION_NSYMS[bl],
+        );
+    }
+
+    fn encode_partition_none(&mut self, bl: usize, bx: u32, by: u32) {
+        self.write_partition(bl, bx, by, PARTITION_NONE);
+        if bl == 4 {
+            if self.lossless {
+                self.encode_lossless_block(bx, by);
+            } else {
+                self.encode_block(bx, by, bl);
+            }
+        } else {
+            self.encode_block_square(bx, by, bl);
+        }
+    }
+
+    fn encode_partition_split(&mut self, bl: usize, bx: u32, by: u32) {
+        let hsz = 16u32 >> bl;
+        self.write_partition(bl, bx, by, PARTITION_SPLIT);
+        self.encode_partition(bl + 1, bx, by);
+        self.encode_partition(bl + 1, bx + hsz, by);
+        self.encode_partition(bl + 1, bx, by + hsz);
+        self.encode_partition(bl + 1, bx + hsz, by + hsz);
+    }
+
+    /// Trial-codes a `bl` block whole and split, keeping the cheaper.
+    fn encode_partition_rd(&mut self, bl: usize, bx: u32, by: u32) {
+        let start = self.trial_start(bx, by, bl);
+        self.encode_partition_none(bl, bx, by);
+        let (none_sse, samples) = self.block_sse(bx, by, bl);
+        if self
+            .early_exit
+            .intra_none_is_good_enough(none_sse, samples, self.dq.ac)
+        {
+            return;
+        }
+        let lambda = crate::rdo::lambda_for_ac_dq(self.dq.ac);
+        let none_cost =
+            crate::rdo::rd_cost_q8(none_sse, self.enc.tell_q8() - start.bits_q8, lambda);
+
+        self.trial_rollback(&start);
+        self.encode_partition_split(bl, bx, by);
+        let (split_sse, _) = self.block_sse(bx, by, bl);
+        let split_cost =
+            crate::rdo::rd_cost_q8(split_sse, self.enc.tell_q8() - start.bits_q8, lambda);
+
+        if none_cost <= split_cost {
+            self.trial_rollback(&start);
+            self.encode_partition_none(bl, bx, by);
+        }
+    }
+
+    fn trial_start(&self, bx: u32, by: u32, bl: usize) -> TrialStart {
+        let n = 128usize >> bl;
+        let (w, h) = (self.pixels.width, self.pixels.height);
+        let (cw, ch) = (w.div_ceil(2), h.div_ceil(2));
+        let (px_x, px_y) = (bx * 4, by * 4);
+        TrialStart {
+            enc: self.enc.checkpoint(),
+            bits_q8: self.enc.tell_q8(),
+            cdf: self.cdf.clone(),
+            ctx: self.ctx.clone(),
+            px: (px_x, px_y),
+            cdef_pending: self.cdef_pending,
+            delta_q: self.delta_q.mark(),
+            recon: [
+                extract_block(&self.recon.y, w, px_x, px_y, n, w, h),
+                extract_block(&self.recon.u, cw, px_x / 2, px_y / 2, n / 2, cw, ch),
+                extract_block(&self.recon.v, cw, px_x / 2, px_y / 2, n / 2, cw, ch),
+            ],
+        }
+    }
+
+    fn trial_rollback(&mut self, start: &TrialStart) {
+        let n = start.recon[0].len().isqrt();
+        let (w, h) = (self.pixels.width, self.pixels.height);
+        let (cw, ch) = (w.div_ceil(2), h.div_ceil(2));
+        let (px_x, px_y) = (start.px.0, start.px.1);
+        self.enc.rollback(start.enc);
+        self.cdf.clone_from(&start.cdf);
+        self.ctx.clone_from(&start.ctx);
+        self.cdef_pending = start.cdef_pending;
+        self.delta_q.reset(start.delta_q);
+        store_block(&mut self.recon.y, w, h, px_x, px_y, n, &start.recon[0]);
+        store_block(
+            &mut self.recon.u,
+            cw,
+            ch,
+            px_x / 2,
+            px_y / 2,
+            n / 2,
+            &start.recon[1],
+        );
+        store_block(
+            &mut self.recon.v,
+            cw,
+            ch,
+            px_x / 2,
+            px_y / 2,
+            n / 2,
+            &start.recon[2],
+        );
+    }
+
+    fn block_sse(&self, bx: u32, by: u32, bl: usize) -> (u64, u64) {
+        let n = 128u32 >> bl;
+        let (w, h) = (self.pixels.width, self.pixels.height);
+        let (cw, ch) = (w.div_ceil(2), h.div_ceil(2));
+        let plane_sse =
+            |src: &[u16], rec: &[u16], stride: u32, x0: u32, y0: u32, x1: u32, y1: u32| {
+                let mut sse = 0u64;
+                for y in y0..y1 {
+                    for x in x0..x1 {
+                        let idx = (y * stride + x) as usize;
+                        let d = src[idx] as i64 - rec[idx] as i64;
+                        sse += (d * d) as u64;
+                    }
+                }
+                (sse, ((x1 - x0) * (y1 - y0)) as u64)
+            };
+        let (px_x, px_y) = (bx * 4, by * 4);
+        let (x1, y1) = (min(px_x + n, w), min(px_y + n, h));
+        let (cx1, cy1) = (min((px_x + n) / 2, cw), min((px_y + n) / 2, ch));
+        let (y_sse, y_samples) = plane_sse(&self.pixels.y, &self.recon.y, w, px_x, px_y, x1, y1);
+        let (u_sse, uv_samples) = plane_sse(
+            &self.pixels.u,
+            &self.recon.u,
+            cw,
+            px_x / 2,
+            px_y / 2,
+            cx1,
+            cy1,
+        );
+        let (v_sse, _) = plane_sse(
+            &self.pixels.v,
+            &self.recon.v,
+            cw,
+            px_x / 2,
+            px_y / 2,
+            cx1,
+            cy1,
+        );
+        (y_sse + u_sse + v_sse, y_samples + 2 * uv_samples)
     }
 
     fn encode_partition(&mut self, bl: usize, bx: u32, by: u32) {
@@ -2571,64 +3523,12 @@ impl<'a> TileEncoder<'a> {
         let have_v_split = self.mi_rows > by + hsz;
 
         if have_h_split && have_v_split {
-            let part_ctx = self.ctx.partition_ctx(bx, by, bl);
-            if bl == 1 {
-                self.enc.encode_symbol(
-                    3,
-                    &mut self.cdf.partition[bl][part_ctx],
-                    PARTITION_NSYMS[bl],
-                );
-                self.encode_partition(bl + 1, bx, by);
-                self.encode_partition(bl + 1, bx + hsz, by);
-                self.encode_partition(bl + 1, bx, by + hsz);
-                self.encode_partition(bl + 1, bx + hsz, by + hsz);
-            } else if bl == 2 {
-                if self.should_use_partition_none(bx, by, bl) {
-                    self.enc.encode_symbol(
-                        0,
-                        &mut self.cdf.partition[bl][part_ctx],
-                        PARTITION_NSYMS[bl],
-                    );
-                    self.encode_skip_block(bx, by, bl);
-                } else {
-                    self.enc.encode_symbol(
-                        3,
-                        &mut self.cdf.partition[bl][part_ctx],
-                        PARTITION_NSYMS[bl],
-                    );
-                    self.encode_partition(bl + 1, bx, by);
-                    self.encode_partition(bl + 1, bx + hsz, by);
-                    self.encode_partition(bl + 1, bx, by + hsz);
-                    self.encode_partition(bl + 1, bx + hsz, by + hsz);
-                }
-            } else if bl == 3 {
-                let base = self.dq.ac as u64 * self.dq.ac as u64;
-                let use_16x16 = self.skip_mse(bx, by, 3) <= base / 12;
-                if use_16x16 {
-                    self.enc.encode_symbol(
-                        0,
-                        &mut self.cdf.partition[bl][part_ctx],
-                        PARTITION_NSYMS[bl],
-                    );
-                    self.encode_block_16x16(bx, by);
-                } else {
-                    self.enc.encode_symbol(
-                        3,
-                        &mut self.cdf.partition[bl][part_ctx],
-                        PARTITION_NSYMS[bl],
-                    );
-                    self.encode_partition(bl + 1, bx, by);
-                    self.encode_partition(bl + 1, bx + hsz, by);
-                    self.encode_partition(bl + 1, bx, by + hsz);
-                    self.encode_partition(bl + 1, bx + hsz, by + hsz);
-                }
+            if bl == 4 {
+                self.encode_partition_none(bl, bx, by);
+            } else if self.can_code_whole(bx, by, bl) {
+                self.encode_partition_rd(bl, bx, by);
             } else {
-                self.enc.encode_symbol(
-                    0,
-                    &mut self.cdf.partition[bl][part_ctx],
-                    PARTITION_NSYMS[bl],
-                );
-                self.encode_block(bx, by, bl);
+                self.encode_partition_split(bl, bx, by);
             }
         } else if have_h_split {
             let part_ctx = self.ctx.partition_ctx(bx, by, bl);
@@ -2660,7 +3560,32 @@ pub fn encode_tile_with_recon(
     dq: DequantValues,
     base_q_idx: u8,
 ) -> (Vec<u8>, FramePixels) {
+    let (bytes, recon, _) = encode_tile_with_cdef(
+        pixels,
+        dq,
+        base_q_idx,
+        EarlyExit::default(),
+        CdefSignal::default(),
+        RestorationSignal::default(),
+        SbDeltaQ::default(),
+    );
+    (bytes, recon)
+}
+
+fn encode_tile_with_cdef(
+    pixels: &FramePixels,
+    dq: DequantValues,
+    base_q_idx: u8,
+    early_exit: EarlyExit,
+    cdef: CdefSignal,
+    restoration: RestorationSignal,
+    delta_q: SbDeltaQ,
+) -> (Vec<u8>, FramePixels, SkipMap) {
     let mut tile = TileEncoder::new(pixels, dq, base_q_idx);
+    tile.early_exit = early_exit;
+    tile.cdef = cdef;
+    tile.restoration = restoration;
+    tile.delta_q = DeltaQCursor::new(delta_q, base_q_idx, dq);
 
     let sb_cols = tile.mi_cols.div_ceil(16);
     let sb_rows = tile.mi_rows.div_ceil(16);
@@ -2670,11 +3595,13 @@ pub fn encode_tile_with_recon(
         for sb_col in 0..sb_cols {
             let bx = sb_col * 16;
             let by = sb_row * 16;
+            tile.cdef_pending = true;
+            tile.start_superblock(sb_col, sb_row);
             tile.encode_partition(1, bx, by);
         }
     }
 
-    (tile.enc.finalize(), tile.recon)
+    (tile.enc.finalize(), tile.recon, tile.skips)
 }
 
 fn empty_frame_like(pixels: &FramePixels) -> FramePixels {
@@ -2692,6 +3619,53 @@ fn empty_frame_like(pixels: &FramePixels) -> FramePixels {
     }
 }
 
+/// `frame` extended to whole 8x8 blocks by repeating its last column and
+/// row. Blocks are coded and reconstructed out to this size, and the
+/// decoder keeps those samples: intra edges and CDEF read them. Repeating
+/// the edge is also what prediction from a reference clamped to its
+/// visible size sees.
+pub(crate) fn pad_to_block_grid(frame: &FramePixels) -> FramePixels {
+    let (width, height) = (
+        frame.width.next_multiple_of(8),
+        frame.height.next_multiple_of(8),
+    );
+    if (width, height) == (frame.width, frame.height) {
+        return frame.clone();
+    }
+    let pad = |plane: &[u16], w: u32, h: u32, to_w: u32, to_h: u32| -> Vec<u16> {
+        (0..to_h)
+            .flat_map(|y| {
+                let row = &plane[(y.min(h - 1) * w) as usize..][..w as usize];
+                (0..to_w).map(move |x| row[x.min(w - 1) as usize])
+            })
+            .collect()
+    };
+    let (cw, ch) = (frame.width.div_ceil(2), frame.height.div_ceil(2));
+    FramePixels {
+        width,
+        height,
+        bit_depth: frame.bit_depth,
+        color_range: frame.color_range,
+        y: pad(&frame.y, frame.width, frame.height, width, height),
+        u: pad(&frame.u, cw, ch, width / 2, height / 2),
+        v: pad(&frame.v, cw, ch, width / 2, height / 2),
+    }
+}
+
+/// The top-left `width`x`height` of a frame from [`pad_to_block_grid`].
+pub(crate) fn crop_block_grid(padded: &FramePixels, width: u32, height: u32) -> FramePixels {
+    let (cw, ch) = (width.div_ceil(2), height.div_ceil(2));
+    FramePixels {
+        width,
+        height,
+        bit_depth: padded.bit_depth,
+        color_range: padded.color_range,
+        y: copy_plane_region(&padded.y, padded.width, 0, 0, width, height),
+        u: copy_plane_region(&padded.u, padded.width / 2, 0, 0, cw, ch),
+        v: copy_plane_region(&padded.v, padded.width / 2, 0, 0, cw, ch),
+    }
+}
+
 fn tile_rect_to_pixel_bounds(rect: &TileRect, width: u32, height: u32) -> (u32, u32, u32, u32) {
     let x0 = rect.sb_col_start * 64;
     let y0 = rect.sb_row_start * 64;
@@ -2793,30 +3767,128 @@ fn stitch_tile_region(destination: &mut FramePixels, source: &FramePixels, rect:
     );
 }
 
+/// Takes each coded tile's index and bytes, in raster order.
+pub type TileSink<'a> = &'a mut dyn FnMut(usize, &[u8]);
+
+/// Runs `encode` on every tile of `plan`, on up to `plan.threads` threads
+/// taking the next uncoded tile as they finish, and returns the results in
+/// tile order. `finished` sees each result on the calling thread as soon as
+/// it and every tile before it are coded.
+fn map_tiles<T: Send>(
+    plan: &TilePlan,
+    encode: impl Fn(&TileRect) -> T + Sync,
+    mut finished: impl FnMut(usize, &T),
+) -> Vec<T> {
+    let threads = plan.threads.clamp(1, plan.tiles.len().max(1));
+    if threads == 1 {
+        return plan
+            .tiles
+            .iter()
+            .enumerate()
+            .map(|(i, rect)| {
+                let result = encode(rect);
+                finished(i, &result);
+                result
+            })
+            .collect();
+    }
+    let next = std::sync::atomic::AtomicUsize::new(0);
+    let mut results: Vec<Option<T>> = plan.tiles.iter().map(|_| None).collect();
+    std::thread::scope(|scope| {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        for _ in 0..threads {
+            let (sender, next, encode) = (sender.clone(), &next, &encode);
+            scope.spawn(move || {
+                loop {
+                    let i = next.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    let Some(rect) = plan.tiles.get(i) else {
+                        return;
+                    };
+                    if sender.send((i, encode(rect))).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+        drop(sender);
+        let mut emitted = 0;
+        for (i, result) in receiver {
+            results[i] = Some(result);
+            while let Some(Some(result)) = results.get(emitted) {
+                finished(emitted, result);
+                emitted += 1;
+            }
+        }
+    });
+    results.into_iter().map(Option::unwrap).collect()
+}
+
+/// Codes every tile of `plan`. The reconstruction returned covers the
+/// frame padded by [`pad_to_block_grid`]. `on_tile` is handed each tile's
+/// bytes in raster order as soon as they and every earlier tile's are
+/// coded.
+#[allow(clippy::too_many_arguments)]
 pub fn encode_tiles_with_recon(
     pixels: &FramePixels,
     dq: DequantValues,
     base_q_idx: u8,
     plan: &TilePlan,
-) -> (Vec<Vec<u8>>, FramePixels) {
+    early_exit: EarlyExit,
+    cdef: &CdefSignal,
+    restoration: &RestorationPlan,
+    delta_q: &SbDeltaQ,
+    mut on_tile: Option<TileSink<'_>>,
+) -> (Vec<Vec<u8>>, FramePixels, SkipMap) {
+    let pixels = &pad_to_block_grid(pixels);
     if plan.tiles.len() == 1 {
-        let (bytes, recon) = encode_tile_with_recon(pixels, dq, base_q_idx);
-        return (vec![bytes], recon);
+        let (bytes, recon, skips) = encode_tile_with_cdef(
+            pixels,
+            dq,
+            base_q_idx,
+            early_exit,
+            cdef.clone(),
+            RestorationSignal::for_tile(restoration, &plan.tiles[0]),
+            delta_q.clone(),
+        );
+        if let Some(on_tile) = on_tile {
+            on_tile(0, &bytes);
+        }
+        return (vec![bytes], recon, skips);
     }
 
     let mut all_tiles = Vec::with_capacity(plan.tiles.len());
     let mut stitched_recon = empty_frame_like(pixels);
-
-    for rect in &plan.tiles {
-        let tile_pixels = crop_tile_region(pixels, rect);
-        let (tile_bytes, tile_recon) = encode_tile_with_recon(&tile_pixels, dq, base_q_idx);
+    let mut stitched_skips = SkipMap::all_skipped(pixels.width, pixels.height);
+
+    let encoded = map_tiles(
+        plan,
+        |rect| {
+            encode_tile_with_cdef(
+                &crop_tile_region(pixels, rect),
+                dq,
+                base_q_idx,
+                early_exit,
+                cdef.for_tile(rect),
+                RestorationSignal::for_tile(restoration, rect),
+                delta_q.for_tile(rect),
+            )
+        },
+        |i, (bytes, _, _)| {
+            if let Some(on_tile) = on_tile.as_mut() {
+                on_tile(i, bytes);
+            }
+        },
+    );
+    for (rect, (tile_bytes, tile_recon, tile_skips)) in plan.tiles.iter().zip(encoded) {
         stitch_tile_region(&mut stitched_recon, &tile_recon, rect);
+        stitched_skips.paste(&tile_skips, rect);
         all_tiles.push(tile_bytes);
     }
 
-    (all_tiles, stitched_recon)
+    (all_tiles, stitched_recon, stitched_skips)
 }
 
+#[cfg(feature = "inter")]
 struct InterTileEncoder<'a> {
     enc: MsacEncoder,
     cdf: CdfContext,
@@ -2825,16 +3897,82 @@ struct InterTileEncoder<'a> {
     mi_rows: u32,
     pixels: &'a FramePixels,
     reference: &'a FramePixels,
-    #[allow(dead_code)]
+    /// References other than LAST that blocks may predict from.
+    alternates: Vec<(RefFrame, &'a FramePixels)>,
     forward_reference: Option<&'a FramePixels>,
+    /// LAST and ALTREF averaged, in B-frames.
+    compound: Option<&'a FramePixels>,
     dq: DequantValues,
     #[allow(dead_code)]
     base_q_idx: u8,
     global_mv: (i32, i32),
     recon: FramePixels,
     block_mvs: Vec<BlockMv>,
+    /// Vectors of the previous inter frame, rescaled to this frame's
+    /// reference distance and cropped to the tile.
+    colocated: Option<MvField>,
+    /// What this frame's searches found, finest block last.
+    motion_estimates: MvField,
+    motion: MotionParams,
+    early_exit: EarlyExit,
+    /// Set while encoding the subblocks of a block that `early_exit` judged
+    /// certain to split.
+    split_is_settled: bool,
+    cdef: CdefSignal,
+    cdef_pending: bool,
+    restoration: RestorationSignal,
+    skips: SkipMap,
+    /// Sets `dq` per superblock.
+    delta_q: DeltaQCursor,
+    /// Smoothed luma the full-pel searches compare, with
+    /// `MotionParams::prefilter`.
+    search_luma: Option<SearchLuma>,
+    /// The frame the tile was cropped from, when it is one of several.
+    frame: Option<TileFrame<'a>>,
+}
+
+/// The whole references of a tile's frame and the tile's offset in them.
+/// A vector may point past the tile's edges, and the decoder then predicts
+/// from the rest of the frame, not from the tile's edge samples.
+#[cfg(feature = "inter")]
+struct TileFrame<'a> {
+    references: Vec<(RefFrame, &'a FramePixels)>,
+    x: u32,
+    y: u32,
+}
+
+/// The tile's source and reference luma through [`smooth_luma`].
+#[cfg(feature = "inter")]
+struct SearchLuma {
+    source: Vec<u16>,
+    /// LAST first, as `InterTileEncoder::references` lists them.
+    references: Vec<(RefFrame, Vec<u16>)>,
 }
 
+#[cfg(feature = "inter")]
+impl SearchLuma {
+    fn new(tile: &InterTileEncoder) -> Self {
+        let (width, height) = (tile.pixels.width, tile.pixels.height);
+        Self {
+            source: smooth_luma(&tile.pixels.y, width, height),
+            references: tile
+                .references()
+                .map(|(ref_frame, pixels)| (ref_frame, smooth_luma(&pixels.y, width, height)))
+                .collect(),
+        }
+    }
+
+    fn reference(&self, ref_frame: RefFrame) -> &[u16] {
+        let (_, luma) = self
+            .references
+            .iter()
+            .find(|(r, _)| *r == ref_frame)
+            .unwrap_or(&self.references[0]);
+        luma
+    }
+}
+
+#[cfg(feature = "inter")]
 impl<'a> InterTileEncoder<'a> {
     fn new(
         pixels: &'a FramePixels,
@@ -2859,7 +3997,9 @@ impl<'a> InterTileEncoder<'a> {
             mi_rows,
             pixels,
             reference,
+            alternates: Vec::new(),
             forward_reference,
+            compound: None,
             dq,
             base_q_idx,
             global_mv,
@@ -2873,10 +4013,46 @@ impl<'a> InterTileEncoder<'a> {
                 v: vec![mid_value; (cw * ch) as usize],
             },
             block_mvs: vec![BlockMv::default(); (mi_cols * mi_rows) as usize],
+            colocated: None,
+            motion_estimates: MvField::new(mi_cols, mi_rows),
+            motion: MotionParams::default(),
+            early_exit: EarlyExit::default(),
+            split_is_settled: false,
+            cdef: CdefSignal::default(),
+            cdef_pending: false,
+            restoration: RestorationSignal::default(),
+            skips: SkipMap::all_skipped(pixels.width, pixels.height),
+            delta_q: DeltaQCursor::new(SbDeltaQ::default(), base_q_idx, dq),
+            search_luma: None,
+            frame: None,
         }
     }
 
-    fn encode_inter_block(&mut self, bx: u32, by: u32, bl: usize) {
+    fn start_superblock(&mut self, sb_col: u32, sb_row: u32) {
+        self.dq = self.delta_q.start_superblock(sb_col, sb_row);
+        self.restoration
+            .write_superblock(&mut self.enc, &mut self.cdf.use_wiener, sb_col, sb_row);
+    }
+
+    fn write_delta_q(&mut self, whole_sb_skip: bool) {
+        self.delta_q
+            .write(&mut self.enc, &mut self.cdf.delta_q, whole_sb_skip);
+    }
+
+    /// The vectors the tile's blocks were coded with.
+    fn coded_mvs(&self) -> MvField {
+        MvField {
+            mi_cols: self.mi_cols,
+            mi_rows: self.mi_rows,
+            mvs: self
+                .block_mvs
+                .iter()
+                .map(|b| (b.ref_frame == RefFrame::Last as i8).then_some((b.mv_x, b.mv_y)))
+                .collect(),
+        }
+    }
+
+    fn encode_inter_block(&mut self, bx: u32, by: u32, bl: usize, has_top_right: bool) {
         let px_x = bx * 4;
         let px_y = by * 4;
         let w = self.pixels.width;
@@ -2892,56 +4068,80 @@ impl<'a> InterTileEncoder<'a> {
         let u_src = extract_block(&self.pixels.u, cw, chroma_px_x, chroma_px_y, 4, cw, ch);
         let v_src = extract_block(&self.pixels.v, cw, chroma_px_x, chroma_px_y, 4, cw, ch);
 
-        let (dx_pixels, dy_pixels) = motion_search_block(
-            &self.pixels.y,
-            &self.reference.y,
-            w,
-            h,
-            px_x,
-            px_y,
-            8,
-            self.global_mv.0,
-            self.global_mv.1,
-        );
-
-        let (refined_mv_x, refined_mv_y) = subpel_refine(
-            &self.pixels.y,
-            &self.reference.y,
-            w,
-            h,
-            px_x,
-            px_y,
-            8,
-            dx_pixels * 8,
-            dy_pixels * 8,
-            max_value,
-        );
-
-        let (pred_x, pred_y, mv_candidates) =
-            predict_mv(&self.block_mvs, self.mi_cols, self.mi_rows, bx, by);
-
-        let zero_y_ref = extract_block(&self.reference.y, w, px_x, px_y, 8, w, h);
-        let zero_u_ref = extract_block(&self.reference.u, cw, chroma_px_x, chroma_px_y, 4, cw, ch);
-        let zero_v_ref = extract_block(&self.reference.v, cw, chroma_px_x, chroma_px_y, 4, cw, ch);
-
-        let no_inter_neighbors = !self.ctx.has_inter_neighbor(bx, by);
+        let zero_mv = self.best_zero_mv_reference(bx, by, bl, PARTITION_NONE, has_top_right);
+        let ref_frame = zero_mv.refs.first();
+        let reference = self.reference_pixels(ref_frame);
+        let zero_y_ref = extract_block(&zero_mv.pixels.y, w, px_x, px_y, 8, w, h);
+        let zero_y_sse: u64 = y_src
+            .iter()
+            .zip(&zero_y_ref)
+            .map(|(&s, &r)| {
+                let d = s as i64 - r as i64;
+                (d * d) as u64
+            })
+            .sum();
 
-        let use_newmv = if no_inter_neighbors && (refined_mv_x != 0 || refined_mv_y != 0) {
-            let y_int_x = px_x as i32 + (refined_mv_x >> 3);
-            let y_int_y = px_y as i32 + (refined_mv_y >> 3);
-            let y_phase_x = (refined_mv_x & 7) as u32;
-            let y_phase_y = (refined_mv_y & 7) as u32;
-            let mc_y_ref = interpolate_block(
-                &self.reference.y,
+        // NEWMV is only coded in the tile's first block, whose MV stack is
+        // empty, so otherwise the search result would go unused. Intra
+        // neighbours do not empty the stack: the decoder also scans rows
+        // and columns farther out.
+        let no_coded_neighbors = bx == 0 && by == 0;
+        let (refined_mv_x, refined_mv_y) = if !no_coded_neighbors
+            || self
+                .early_exit
+                .inter_zero_mv_is_good_enough(zero_y_sse, 64, self.dq.ac)
+        {
+            (0, 0)
+        } else {
+            let (search_source, search_reference) = self.search_planes(ref_frame);
+            let (dx_pixels, dy_pixels) = motion_search_block(
+                search_source,
+                search_reference,
                 w,
                 h,
-                y_int_x,
-                y_int_y,
-                y_phase_x,
-                y_phase_y,
+                px_x,
+                px_y,
                 8,
-                max_value,
+                &self.search_seeds(bx, by, 4),
+                self.motion.search_range,
             );
+            let refined = if self.motion.integer_mv {
+                (dx_pixels * 8, dy_pixels * 8)
+            } else {
+                subpel_refine(
+                    &self.pixels.y,
+                    &reference.y,
+                    w,
+                    h,
+                    px_x,
+                    px_y,
+                    8,
+                    dx_pixels * 8,
+                    dy_pixels * 8,
+                    max_value,
+                )
+            };
+            if ref_frame == RefFrame::Last {
+                self.motion_estimates.fill(bx, by, bl, refined);
+            }
+            refined
+        };
+
+        let (pred_x, pred_y, mv_candidates) = predict_mv(
+            &self.block_mvs,
+            self.mi_cols,
+            self.mi_rows,
+            bx,
+            by,
+            ref_frame,
+        );
+
+        let zero_u_ref = extract_block(&zero_mv.pixels.u, cw, chroma_px_x, chroma_px_y, 4, cw, ch);
+        let zero_v_ref = extract_block(&zero_mv.pixels.v, cw, chroma_px_x, chroma_px_y, 4, cw, ch);
+
+        let use_newmv = if no_coded_neighbors && (refined_mv_x != 0 || refined_mv_y != 0) {
+            let mc_y_ref =
+                self.motion_compensate(ref_frame, 0, px_x, px_y, refined_mv_x, refined_mv_y, 8);
 
             let mut zero_energy = 0i64;
             let mut mc_energy = 0i64;
@@ -2956,54 +4156,30 @@ impl<'a> InterTileEncoder<'a> {
         } else {
             false
         };
+        let refs = if use_newmv {
+            BlockRefs::Single(ref_frame)
+        } else {
+            zero_mv.refs
+        };
 
         let (y_ref_block, u_ref_block, v_ref_block, final_mv_x, final_mv_y) = if use_newmv {
-            let y_int_x = px_x as i32 + (refined_mv_x >> 3);
-            let y_int_y = px_y as i32 + (refined_mv_y >> 3);
-            let y_phase_x = (refined_mv_x & 7) as u32;
-            let y_phase_y = (refined_mv_y & 7) as u32;
-
             let chroma_mv_x = refined_mv_x / 2;
             let chroma_mv_y = refined_mv_y / 2;
-            let c_int_x = chroma_px_x as i32 + (chroma_mv_x >> 3);
-            let c_int_y = chroma_px_y as i32 + (chroma_mv_y >> 3);
-            let c_phase_x = (chroma_mv_x & 7) as u32;
-            let c_phase_y = (chroma_mv_y & 7) as u32;
-
-            (
-                interpolate_block(
-                    &self.reference.y,
-                    w,
-                    h,
-                    y_int_x,
-                    y_int_y,
-                    y_phase_x,
-                    y_phase_y,
-                    8,
-                    max_value,
-                ),
-                interpolate_block(
-                    &self.reference.u,
-                    cw,
-                    ch,
-                    c_int_x,
-                    c_int_y,
-                    c_phase_x,
-                    c_phase_y,
+            let chroma_block = |plane| {
+                self.motion_compensate(
+                    ref_frame,
+                    plane,
+                    chroma_px_x,
+                    chroma_px_y,
+                    chroma_mv_x,
+                    chroma_mv_y,
                     4,
-                    max_value,
-                ),
-                interpolate_block(
-                    &self.reference.v,
-                    cw,
-                    ch,
-                    c_int_x,
-                    c_int_y,
-                    c_phase_x,
-                    c_phase_y,
-                    4,
-                    max_value,
-                ),
+                )
+            };
+            (
+                self.motion_compensate(ref_frame, 0, px_x, px_y, refined_mv_x, refined_mv_y, 8),
+                chroma_block(1),
+                chroma_block(2),
                 refined_mv_x,
                 refined_mv_y,
             )
@@ -3011,26 +4187,63 @@ impl<'a> InterTileEncoder<'a> {
             (zero_y_ref, zero_u_ref, zero_v_ref, 0, 0)
         };
 
+        let inter_rate = if use_newmv {
+            let newmv_ctx = self.newmv_ctx(bx, by, bl, PARTITION_NONE, ref_frame, has_top_right);
+            self.refs_rate(bx, by, bl, PARTITION_NONE, refs)
+                + crate::rdo::bool_cost(&self.cdf.newmv[newmv_ctx], false)
+                + crate::rdo::estimate_mv_component_cost(final_mv_x - pred_x)
+                + crate::rdo::estimate_mv_component_cost(final_mv_y - pred_y)
+        } else {
+            zero_mv.rate
+        };
+        let intra = self.intra_fallback(
+            bx,
+            by,
+            bl,
+            [&y_src, &u_src, &v_src],
+            [&y_ref_block, &u_ref_block, &v_ref_block],
+            inter_rate,
+        );
+        let (y_ref_block, u_ref_block, v_ref_block, y_txtype) = match &intra {
+            Some(leaf) => (
+                leaf.y_pred.clone(),
+                vec![leaf.u_pred; 16],
+                vec![leaf.v_pred; 16],
+                select_best_txtype(
+                    &y_src,
+                    &leaf.y_pred,
+                    self.dq.dc,
+                    self.dq.ac,
+                    self.pixels.bit_depth,
+                    &self.early_exit,
+                ),
+            ),
+            None => (y_ref_block, u_ref_block, v_ref_block, dct::TxType::DctDct),
+        };
+        let y_mode = intra.as_ref().map_or(0, |leaf| leaf.mode);
+
         let mut y_residual = [0i32; 64];
         for i in 0..64 {
             y_residual[i] = y_src[i] as i32 - y_ref_block[i] as i32;
         }
-        let y_dct = dct::forward_dct_8x8(&y_residual);
-        let y_quant = quantize_coeffs(&y_dct, 64, self.dq.dc, self.dq.ac);
+        let y_dct = dct::forward_transform_8x8(&y_residual, y_txtype);
+        let y_quant = quantize_block(&y_dct, 64, &self.dq);
 
         let mut u_residual = [0i32; 16];
         for i in 0..16 {
             u_residual[i] = u_src[i] as i32 - u_ref_block[i] as i32;
         }
         let u_dct = dct::forward_dct_4x4(&u_residual);
-        let u_quant = quantize_coeffs(&u_dct, 16, self.dq.dc, self.dq.ac);
+        let u_dq = self.dq.for_plane(1);
+        let u_quant = quantize_block(&u_dct, 16, &u_dq);
 
         let mut v_residual = [0i32; 16];
         for i in 0..16 {
             v_residual[i] = v_src[i] as i32 - v_ref_block[i] as i32;
         }
         let v_dct = dct::forward_dct_4x4(&v_residual);
-        let v_quant = quantize_coeffs(&v_dct, 16, self.dq.dc, self.dq.ac);
+        let v_dq = self.dq.for_plane(2);
+        let v_quant = quantize_block(&v_dct, 16, &v_dq);
 
         let is_skip = y_quant.iter().all(|&c| c == 0)
             && u_quant.iter().all(|&c| c == 0)
@@ -3038,26 +4251,21 @@ impl<'a> InterTileEncoder<'a> {
 
         let skip_ctx = self.ctx.skip_ctx(bx, by);
         self.enc.encode_bool(is_skip, &mut self.cdf.skip[skip_ctx]);
+        if !is_skip {
+            self.cdef
+                .write_idx(&mut self.enc, &mut self.cdef_pending, by);
+        }
+        self.write_delta_q(false);
 
         let is_inter_ctx = self.ctx.is_inter_ctx(bx, by);
         self.enc
-            .encode_bool(true, &mut self.cdf.is_inter[is_inter_ctx]);
-
-        let ref_ctx = self.ctx.ref_ctx(bx, by);
-
-        // Always encode LAST_FRAME (index 0) for now, even for B-frames, to see if
-        // dav1d decodes the bitstream without the MSAC probability tree desyncing.
-        self.enc
-            .encode_bool(false, &mut self.cdf.single_ref[ref_ctx][0]);
-        self.enc
-            .encode_bool(false, &mut self.cdf.single_ref[ref_ctx][2]);
-        self.enc
-            .encode_bool(false, &mut self.cdf.single_ref[ref_ctx][3]);
-        self.block_mvs[(by * self.mi_cols + bx) as usize].ref_frame = 0;
-
-        let newmv_ctx = self.ctx.newmv_ctx(bx, by);
+            .encode_bool(intra.is_none(), &mut self.cdf.is_inter[is_inter_ctx]);
 
-        if use_newmv {
+        if let Some(leaf) = &intra {
+            self.write_intra_modes(bl, leaf.mode, leaf.angle_delta);
+        } else if use_newmv {
+            self.write_refs(bx, by, bl, PARTITION_NONE, refs);
+            let newmv_ctx = self.newmv_ctx(bx, by, bl, PARTITION_NONE, ref_frame, has_top_right);
             self.enc.encode_bool(false, &mut self.cdf.newmv[newmv_ctx]);
 
             if mv_candidates.len() > 1 {
@@ -3069,10 +4277,8 @@ impl<'a> InterTileEncoder<'a> {
             let diff_y = final_mv_y - pred_y;
             encode_mv_residual(&mut self.enc, &mut self.cdf.mv, diff_y, diff_x);
         } else {
-            self.enc.encode_bool(true, &mut self.cdf.newmv[newmv_ctx]);
-            let zeromv_ctx = 0usize;
-            self.enc
-                .encode_bool(false, &mut self.cdf.zeromv[zeromv_ctx]);
+            self.write_refs(bx, by, bl, PARTITION_NONE, refs);
+            self.write_global_mode(bx, by, bl, PARTITION_NONE, refs, has_top_right);
         }
 
         let (y_cul, y_dc_neg, y_dc_zero);
@@ -3088,12 +4294,12 @@ impl<'a> InterTileEncoder<'a> {
                 &y_quant,
                 &DEFAULT_SCAN_8X8,
                 false,
-                true,
+                intra.is_none(),
                 1,
                 y_txb_skip_ctx,
                 y_dc_sign_ctx,
-                0,
-                dct::TxType::DctDct,
+                y_mode,
+                y_txtype,
             );
             y_cul = y_result.0;
             y_dc_neg = y_result.1;
@@ -3107,11 +4313,11 @@ impl<'a> InterTileEncoder<'a> {
                 &u_quant,
                 &DEFAULT_SCAN_4X4,
                 true,
-                true,
+                intra.is_none(),
                 0,
                 u_txb_skip_ctx,
                 u_dc_sign_ctx,
-                0,
+                y_mode,
                 dct::TxType::DctDct,
             );
             u_cul = u_result.0;
@@ -3126,11 +4332,11 @@ impl<'a> InterTileEncoder<'a> {
                 &v_quant,
                 &DEFAULT_SCAN_4X4,
                 true,
-                true,
+                intra.is_none(),
                 0,
                 v_txb_skip_ctx,
                 v_dc_sign_ctx,
-                0,
+                y_mode,
                 dct::TxType::DctDct,
             );
             v_cul = v_result.0;
@@ -3151,7 +4357,8 @@ impl<'a> InterTileEncoder<'a> {
         let y_deq = dequantize_coeffs(&y_quant, 64, self.dq.dc, self.dq.ac);
         let mut y_deq_arr = [0i32; 64];
         y_deq_arr.copy_from_slice(&y_deq);
-        let y_recon_residual = dct::inverse_dct_8x8(&y_deq_arr);
+        let y_recon_residual =
+            dct::inverse_transform_8x8(&y_deq_arr, y_txtype, self.pixels.bit_depth);
 
         for r in 0..8u32 {
             for c in 0..8u32 {
@@ -3166,10 +4373,10 @@ impl<'a> InterTileEncoder<'a> {
             }
         }
 
-        let u_deq = dequantize_coeffs(&u_quant, 16, self.dq.dc, self.dq.ac);
+        let u_deq = dequantize_coeffs(&u_quant, 16, u_dq.dc, u_dq.ac);
         let mut u_deq_arr = [0i32; 16];
         u_deq_arr.copy_from_slice(&u_deq);
-        let u_recon_residual = dct::inverse_dct_4x4(&u_deq_arr);
+        let u_recon_residual = dct::inverse_dct_4x4(&u_deq_arr, self.pixels.bit_depth);
 
         for r in 0..4u32 {
             for c in 0..4u32 {
@@ -3184,10 +4391,10 @@ impl<'a> InterTileEncoder<'a> {
             }
         }
 
-        let v_deq = dequantize_coeffs(&v_quant, 16, self.dq.dc, self.dq.ac);
+        let v_deq = dequantize_coeffs(&v_quant, 16, v_dq.dc, v_dq.ac);
         let mut v_deq_arr = [0i32; 16];
         v_deq_arr.copy_from_slice(&v_deq);
-        let v_recon_residual = dct::inverse_dct_4x4(&v_deq_arr);
+        let v_recon_residual = dct::inverse_dct_4x4(&v_deq_arr, self.pixels.bit_depth);
 
         for r in 0..4u32 {
             for c in 0..4u32 {
@@ -3217,156 +4424,862 @@ impl<'a> InterTileEncoder<'a> {
             if dest_x < w && dest_y < h {
                 y_right_col[r as usize] = self.recon.y[(dest_y * w + dest_x) as usize];
             }
-        }
+        }
+
+        let mut u_bottom_row = [mid_value; 4];
+        let mut u_right_col = [mid_value; 4];
+        for c in 0..4u32 {
+            let dest_x = chroma_px_x + c;
+            let dest_y = chroma_px_y + 3;
+            if dest_x < cw && dest_y < ch {
+                u_bottom_row[c as usize] = self.recon.u[(dest_y * cw + dest_x) as usize];
+            }
+        }
+        for r in 0..4u32 {
+            let dest_x = chroma_px_x + 3;
+            let dest_y = chroma_px_y + r;
+            if dest_x < cw && dest_y < ch {
+                u_right_col[r as usize] = self.recon.u[(dest_y * cw + dest_x) as usize];
+            }
+        }
+
+        let mut v_bottom_row = [mid_value; 4];
+        let mut v_right_col = [mid_value; 4];
+        for c in 0..4u32 {
+            let dest_x = chroma_px_x + c;
+            let dest_y = chroma_px_y + 3;
+            if dest_x < cw && dest_y < ch {
+                v_bottom_row[c as usize] = self.recon.v[(dest_y * cw + dest_x) as usize];
+            }
+        }
+        for r in 0..4u32 {
+            let dest_x = chroma_px_x + 3;
+            let dest_y = chroma_px_y + r;
+            if dest_x < cw && dest_y < ch {
+                v_right_col[r as usize] = self.recon.v[(dest_y * cw + dest_x) as usize];
+            }
+        }
+
+        let stored_mv = if intra.is_some() {
+            BlockMv::default()
+        } else {
+            let [stored_ref, second_ref] = refs.pair();
+            BlockMv {
+                mv_x: final_mv_x,
+                mv_y: final_mv_y,
+                ref_frame: stored_ref,
+                second_ref,
+                newmv: use_newmv,
+                bw4: 2,
+                bh4: 2,
+            }
+        };
+        for row in by..by.saturating_add(2).min(self.mi_rows) {
+            for col in bx..bx.saturating_add(2).min(self.mi_cols) {
+                self.block_mvs[(row * self.mi_cols + col) as usize] = stored_mv;
+            }
+        }
+
+        self.ctx.update_recon(
+            bx,
+            by,
+            self.mi_cols,
+            self.mi_rows,
+            &y_bottom_row,
+            &y_right_col,
+            &u_bottom_row,
+            &u_right_col,
+            &v_bottom_row,
+            &v_right_col,
+        );
+        let y_cf_ctx = coef_ctx_value(y_cul, y_dc_neg, y_dc_zero);
+        let u_cf_ctx = coef_ctx_value(u_cul, u_dc_neg, u_dc_zero);
+        let v_cf_ctx = coef_ctx_value(v_cul, v_dc_neg, v_dc_zero);
+        self.ctx.update_coef_ctx(
+            bx,
+            by,
+            bl,
+            self.mi_cols,
+            self.mi_rows,
+            y_cf_ctx,
+            u_cf_ctx,
+            v_cf_ctx,
+        );
+        self.ctx
+            .update_partition_ctx(bx, by, bl, self.mi_cols, self.mi_rows);
+        self.ctx
+            .update_skip_ctx(bx, by, bl, self.mi_cols, self.mi_rows, is_skip);
+        self.skips.fill(bx, by, bl, is_skip);
+        self.ctx
+            .update_intra_ctx(bx, by, bl, self.mi_cols, self.mi_rows, intra.is_some());
+        // Intra neighbours' references are never read.
+        if intra.is_none() {
+            self.ctx
+                .update_ref_ctx(bx, by, bl, self.mi_cols, self.mi_rows, refs);
+        }
+    }
+
+    /// The intra prediction the 8x8 leaf at `(bx, by)` is coded with in
+    /// place of `inter`, if intra predicts its luma with a lower SATD and
+    /// then wins on RD cost. Content none of the references hold, like the
+    /// part of a frame cut to a new scene, is otherwise smeared from
+    /// whatever motion fits it least badly.
+    fn intra_fallback(
+        &self,
+        bx: u32,
+        by: u32,
+        bl: usize,
+        source: [&[u16]; 3],
+        inter: [&[u16]; 3],
+        inter_rate: u32,
+    ) -> Option<IntraLeaf> {
+        let ac_dq = self.dq.ac;
+        let inter_satd = crate::satd::compute_satd(source[0], inter[0], 8, 8, 8, 8);
+        // The rule that keeps intra DC without trying the other modes also
+        // keeps an inter prediction without trying intra.
+        if self
+            .early_exit
+            .intra_dc_is_good_enough(inter_satd, 64, ac_dq)
+        {
+            return None;
+        }
+
+        let mid_value = self.pixels.bit_depth.mid_value();
+        let max_value = self.pixels.bit_depth.max_value();
+        let (have_above, have_left) = (by > 0, bx > 0);
+        let (above, left, top_left) =
+            self.ctx
+                .luma_edges_8x8(bx, by, &self.recon.y, self.pixels.width);
+        let (mode, angle_delta) = select_best_intra_mode(
+            source[0],
+            &above,
+            &left,
+            top_left,
+            have_above,
+            have_left,
+            8,
+            8,
+            self.dq.dc,
+            ac_dq,
+            self.pixels.bit_depth,
+            &self.early_exit,
+        );
+        let y_pred = generate_prediction(
+            mode,
+            angle_delta,
+            &above,
+            &left,
+            top_left,
+            have_above,
+            have_left,
+            8,
+            8,
+            mid_value,
+            max_value,
+        );
+        if crate::satd::compute_satd(source[0], &y_pred, 8, 8, 8, 8) >= inter_satd {
+            return None;
+        }
+        let leaf = IntraLeaf {
+            mode,
+            angle_delta,
+            y_pred,
+            u_pred: self.ctx.dc_prediction(bx, by, bl, 1),
+            v_pred: self.ctx.dc_prediction(bx, by, bl, 2),
+        };
+
+        // Chroma is priced by its prediction error alone, as the split side
+        // of `inter_skip_and_split_costs` does.
+        let chroma_sse = |pred: [&[u16]; 2]| -> u64 {
+            (0..2)
+                .map(|plane| {
+                    source[plane + 1]
+                        .iter()
+                        .zip(pred[plane])
+                        .map(|(&s, &p)| (s as i64 - p as i64).pow(2) as u64)
+                        .sum::<u64>()
+                })
+                .sum()
+        };
+        let w = self.pixels.width;
+        let h = self.pixels.height;
+        let rows = min(8, h - by * 4) as usize;
+        let cols = min(8, w - bx * 4) as usize;
+        let lambda = self
+            .motion
+            .scale_lambda(crate::rdo::lambda_for_ac_dq(ac_dq));
+        let is_inter_cdf = &self.cdf.is_inter[self.ctx.is_inter_ctx(bx, by)];
+        let rd_cost = |pred: [&[u16]; 3], mode_rate: u32| {
+            let (y_sse, coef_rate) = coded_8x8_cost(
+                source[0],
+                pred[0],
+                &self.dq,
+                self.pixels.bit_depth,
+                rows,
+                cols,
+            );
+            crate::rdo::rd_cost_q8(
+                y_sse + chroma_sse([pred[1], pred[2]]),
+                mode_rate as u64 + coef_rate,
+                lambda,
+            )
+        };
+        let inter_cost = rd_cost(
+            inter,
+            crate::rdo::bool_cost(is_inter_cdf, true) + inter_rate,
+        );
+        let (u_pred, v_pred) = ([leaf.u_pred; 16], [leaf.v_pred; 16]);
+        let intra_cost = rd_cost(
+            [&leaf.y_pred, &u_pred, &v_pred],
+            crate::rdo::bool_cost(is_inter_cdf, false)
+                + self.intra_modes_rate(bl, mode, angle_delta),
+        );
+        (intra_cost < inter_cost).then_some(leaf)
+    }
+
+    /// Rate of the luma and chroma mode symbols of an intra `bl` block
+    /// predicting luma with `mode` and chroma with DC.
+    fn intra_modes_rate(&self, bl: usize, mode: u8, angle_delta: i8) -> u32 {
+        let cfl_allowed = bl >= 2;
+        let mut rate =
+            crate::rdo::symbol_cost(&self.cdf.y_mode[y_mode_size_group(bl)], mode as u32, 12);
+        if (1..=8).contains(&mode) {
+            rate += crate::rdo::symbol_cost(
+                &self.cdf.angle_delta[(mode - 1) as usize],
+                (angle_delta + 3) as u32,
+                6,
+            );
+        }
+        rate + crate::rdo::symbol_cost(
+            &self.cdf.uv_mode[usize::from(cfl_allowed)][mode as usize],
+            0,
+            if cfl_allowed { 13 } else { 12 },
+        )
+    }
+
+    /// Writes the modes `intra_modes_rate` prices.
+    fn write_intra_modes(&mut self, bl: usize, mode: u8, angle_delta: i8) {
+        self.enc
+            .encode_symbol(mode as u32, &mut self.cdf.y_mode[y_mode_size_group(bl)], 12);
+        if (1..=8).contains(&mode) {
+            self.enc.encode_symbol(
+                (angle_delta + 3) as u32,
+                &mut self.cdf.angle_delta[(mode - 1) as usize],
+                6,
+            );
+        }
+        let cfl_allowed = bl >= 2;
+        self.enc.encode_symbol(
+            0,
+            &mut self.cdf.uv_mode[usize::from(cfl_allowed)][mode as usize],
+            if cfl_allowed { 13 } else { 12 },
+        );
+    }
+
+    /// Full-pel starting points for the motion search of the `bl` block at
+    /// `(bx, by)`: the frame's global motion, the spatial MV predictor, and
+    /// the previous inter frame's vector at the block centre. Fast pans then
+    /// stay inside the search window even where global motion misses them.
+    fn search_seeds(&self, bx: u32, by: u32, bl: usize) -> Vec<(i32, i32)> {
+        let (pred_x, pred_y, _) = predict_mv(
+            &self.block_mvs,
+            self.mi_cols,
+            self.mi_rows,
+            bx,
+            by,
+            RefFrame::Last,
+        );
+        let mut seeds = vec![self.global_mv, full_pel((pred_x, pred_y))];
+        let half = 1u32 << (4 - bl);
+        if let Some(mv) = self
+            .colocated
+            .as_ref()
+            .and_then(|field| field.get(bx + half, by + half))
+        {
+            seeds.push(full_pel(mv));
+        }
+        seeds
+    }
+
+    /// Rate-distortion choice between a zero-motion skip block and splitting.
+    ///
+    /// The skip side is costed with the exact symbols `encode_inter_skip_block`
+    /// would write under the current CDFs. The split side is estimated by
+    /// motion-compensating and transform-coding the block as 8x8 leaves and
+    /// pricing their coefficients and motion vectors with the rate tables in
+    /// `rdo`.
+    fn inter_partition_none_costs(
+        &self,
+        bx: u32,
+        by: u32,
+        bl: usize,
+        has_top_right: bool,
+    ) -> SkipOrSplit {
+        let part_ctx = self.ctx.partition_ctx(bx, by, bl);
+        let partition_cdf = &self.cdf.partition[bl][part_ctx];
+        self.inter_skip_and_split_costs(
+            bx,
+            by,
+            bl,
+            PARTITION_NONE,
+            has_top_right,
+            crate::rdo::symbol_cost(partition_cdf, PARTITION_NONE, PARTITION_NSYMS[bl]),
+            crate::rdo::symbol_cost(partition_cdf, PARTITION_SPLIT, PARTITION_NSYMS[bl]),
+        )
+    }
+
+    /// The reference to skip a frame-edge `bl` block from, or `None` to split.
+    fn inter_partition_edge_skip(
+        &mut self,
+        bx: u32,
+        by: u32,
+        bl: usize,
+        prob: u16,
+        has_top_right: bool,
+    ) -> Option<BlockRefs> {
+        let partition = if by + (16 >> bl) >= self.mi_rows {
+            PARTITION_HORZ
+        } else {
+            PARTITION_VERT
+        };
+        let costs = self.inter_skip_and_split_costs(
+            bx,
+            by,
+            bl,
+            partition,
+            has_top_right || partition == PARTITION_VERT,
+            crate::rdo::bool_cost(&[prob], false),
+            crate::rdo::bool_cost(&[prob], true),
+        );
+        self.record_motion_estimate(bx, by, bl, costs);
+        costs.skip_wins().then_some(costs.reference)
+    }
+
+    fn record_motion_estimate(&mut self, bx: u32, by: u32, bl: usize, costs: SkipOrSplit) {
+        if let Some((mv_x, mv_y)) = costs.motion {
+            self.motion_estimates.fill(bx, by, bl, (mv_x * 8, mv_y * 8));
+        }
+    }
+
+    /// RD costs of coding the in-frame part of a `bl` block as one
+    /// zero-motion skip block and of splitting it into coded 8x8 blocks,
+    /// given the partition signalling cost of either choice.
+    #[allow(clippy::too_many_arguments)]
+    fn inter_skip_and_split_costs(
+        &self,
+        bx: u32,
+        by: u32,
+        bl: usize,
+        partition: u32,
+        has_top_right: bool,
+        skip_partition_rate: u32,
+        split_partition_rate: u32,
+    ) -> SkipOrSplit {
+        let block_size = 1u32 << (7 - bl);
+        let px_x = bx * 4;
+        let px_y = by * 4;
+        let w = self.pixels.width;
+        let h = self.pixels.height;
+        let cw = w.div_ceil(2);
+        let ch = h.div_ceil(2);
+        let x_end = min(px_x + block_size, w);
+        let y_end = min(px_y + block_size, h);
+        let cx_end = min((px_x + block_size) / 2, cw);
+        let cy_end = min((px_y + block_size) / 2, ch);
+
+        let zero_mv = self.best_zero_mv_reference(bx, by, bl, partition, has_top_right);
+        let skip_y_sse = zero_mv.y_sse;
+        let skip_uv_sse = zero_mv.uv_sse;
+
+        // A perfect match (e.g. static or solid content) always skips.
+        if skip_y_sse + skip_uv_sse == 0 {
+            return SkipOrSplit::skip_uncosted(zero_mv.refs);
+        }
+        let samples = ((x_end - px_x) * (y_end - px_y)) as u64
+            + 2 * (cx_end.saturating_sub(px_x / 2) as u64)
+                * (cy_end.saturating_sub(px_y / 2) as u64);
+        if self
+            .early_exit
+            .inter_none_is_good_enough(skip_y_sse + skip_uv_sse, samples, self.dq.ac)
+        {
+            return SkipOrSplit::skip_uncosted(zero_mv.refs);
+        }
+
+        let skip_rate = skip_partition_rate
+            + crate::rdo::bool_cost(&self.cdf.skip[self.ctx.skip_ctx(bx, by)], true)
+            + crate::rdo::bool_cost(&self.cdf.is_inter[self.ctx.is_inter_ctx(bx, by)], true)
+            + zero_mv.rate;
+
+        let lambda = self
+            .motion
+            .scale_lambda(crate::rdo::lambda_for_ac_dq(self.dq.ac));
+        let skip_cost = crate::rdo::rd_cost_q8(skip_y_sse + skip_uv_sse, skip_rate as u64, lambda);
+
+        let (search_source, search_reference) = self.search_planes(RefFrame::Last);
+        let (mv_x, mv_y) = motion_search_block(
+            search_source,
+            search_reference,
+            w,
+            h,
+            px_x,
+            px_y,
+            block_size,
+            &self.search_seeds(bx, by, bl),
+            self.motion.search_range,
+        );
+        let (coded_y_sse, coded_rate) =
+            self.estimate_coded_luma(px_x, px_y, x_end, y_end, mv_x, mv_y);
+
+        // Chroma of the coded path carries at most the quantization noise of
+        // its (motion-compensated) prediction.
+        let step = (self.dq.ac as u64 / 8).max(1);
+        let uv_samples =
+            2 * (cx_end.saturating_sub(px_x / 2) as u64) * (cy_end.saturating_sub(px_y / 2) as u64);
+        let coded_uv_sse = skip_uv_sse.min(uv_samples * step * step / 12);
+
+        let split_rate = split_partition_rate as u64 + coded_rate;
+        let split_cost = crate::rdo::rd_cost_q8(coded_y_sse + coded_uv_sse, split_rate, lambda);
+
+        SkipOrSplit {
+            skip: skip_cost,
+            split: split_cost,
+            motion: Some((mv_x, mv_y)),
+            reference: zero_mv.refs,
+        }
+    }
+
+    /// LAST followed by the alternate references and, in B-frames, ALTREF.
+    fn references(&self) -> impl Iterator<Item = (RefFrame, &'a FramePixels)> + '_ {
+        std::iter::once((RefFrame::Last, self.reference))
+            .chain(self.alternates.iter().copied())
+            .chain(self.forward_reference.map(|fwd| (RefFrame::Altref, fwd)))
+    }
+
+    fn prediction_pixels(&self, refs: BlockRefs) -> &'a FramePixels {
+        match refs {
+            BlockRefs::Single(ref_frame) => self.reference_pixels(ref_frame),
+            BlockRefs::Compound(..) => self.compound.expect("compound blocks need a B-frame"),
+        }
+    }
+
+    fn comp_mode_coded(&self, bl: usize, partition: u32) -> bool {
+        let (bw4, bh4) = block_dims(bl, partition);
+        self.compound.is_some() && bw4.min(bh4) >= 2
+    }
+
+    fn reference_pixels(&self, ref_frame: RefFrame) -> &'a FramePixels {
+        self.references()
+            .find(|&(r, _)| r == ref_frame)
+            .map_or(self.reference, |(_, pixels)| pixels)
+    }
+
+    /// The `size` block of `plane` at `(px_x, px_y)` predicted from
+    /// `ref_frame` displaced by `(mv_x, mv_y)`, in 1/8 samples of the plane.
+    #[allow(clippy::too_many_arguments)]
+    fn motion_compensate(
+        &self,
+        ref_frame: RefFrame,
+        plane: usize,
+        px_x: u32,
+        px_y: u32,
+        mv_x: i32,
+        mv_y: i32,
+        size: u32,
+    ) -> Vec<u16> {
+        let (reference, x, y) = match &self.frame {
+            Some(frame) => {
+                let (_, reference) = frame
+                    .references
+                    .iter()
+                    .find(|(r, _)| *r == ref_frame)
+                    .unwrap_or(&frame.references[0]);
+                (*reference, frame.x, frame.y)
+            }
+            None => (self.reference_pixels(ref_frame), 0, 0),
+        };
+        let (samples, width, height, x, y) = match plane {
+            0 => (&reference.y, reference.width, reference.height, x, y),
+            _ => (
+                if plane == 1 {
+                    &reference.u
+                } else {
+                    &reference.v
+                },
+                reference.width.div_ceil(2),
+                reference.height.div_ceil(2),
+                x / 2,
+                y / 2,
+            ),
+        };
+        interpolate_block(
+            samples,
+            width,
+            height,
+            (x + px_x) as i32 + (mv_x >> 3),
+            (y + px_y) as i32 + (mv_y >> 3),
+            (mv_x & 7) as u32,
+            (mv_y & 7) as u32,
+            size,
+            self.pixels.bit_depth.max_value(),
+        )
+    }
+
+    /// The source and `ref_frame` luma the full-pel motion search compares:
+    /// smoothed with `MotionParams::prefilter`, else the pictures themselves.
+    fn search_planes(&self, ref_frame: RefFrame) -> (&[u16], &[u16]) {
+        match &self.search_luma {
+            Some(luma) => (&luma.source, luma.reference(ref_frame)),
+            None => (&self.pixels.y, &self.reference_pixels(ref_frame).y),
+        }
+    }
+
+    /// Cheapest zero-motion reference of a `bl` block, compound included.
+    fn best_zero_mv_reference(
+        &self,
+        bx: u32,
+        by: u32,
+        bl: usize,
+        partition: u32,
+        has_top_right: bool,
+    ) -> ZeroMvPrediction<'a> {
+        let block_size = 1u32 << (7 - bl);
+        let lambda = self
+            .motion
+            .scale_lambda(crate::rdo::lambda_for_ac_dq(self.dq.ac));
+        let compound = self
+            .compound
+            .filter(|_| self.comp_mode_coded(bl, partition))
+            .map(|pixels| {
+                (
+                    BlockRefs::Compound(RefFrame::Last, RefFrame::Altref),
+                    pixels,
+                )
+            });
+        self.references()
+            .map(|(ref_frame, pixels)| (BlockRefs::Single(ref_frame), pixels))
+            .chain(compound)
+            .map(|(refs, pixels)| {
+                let (y_sse, uv_sse) = self.zero_mv_sse(pixels, bx * 4, by * 4, block_size);
+                let rate = self.refs_rate(bx, by, bl, partition, refs)
+                    + self.global_mode_rate(bx, by, bl, partition, refs, has_top_right);
+                ZeroMvPrediction {
+                    refs,
+                    pixels,
+                    y_sse,
+                    uv_sse,
+                    rate,
+                }
+            })
+            .min_by_key(|p| crate::rdo::rd_cost_q8(p.y_sse + p.uv_sse, p.rate as u64, lambda))
+            .expect("LAST is always a reference")
+    }
+
+    fn zero_mv_sse(
+        &self,
+        reference: &FramePixels,
+        px_x: u32,
+        px_y: u32,
+        block_size: u32,
+    ) -> (u64, u64) {
+        let w = self.pixels.width;
+        let h = self.pixels.height;
+        let cw = w.div_ceil(2);
+        let ch = h.div_ceil(2);
+        let x_end = min(px_x + block_size, w);
+        let y_end = min(px_y + block_size, h);
+        let cx_end = min((px_x + block_size) / 2, cw);
+        let cy_end = min((px_y + block_size) / 2, ch);
+
+        let plane_sse = |src: &[u16], pred: &[u16], stride: u32, x0, y0, x1, y1| -> u64 {
+            let mut sse = 0u64;
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    let idx = (y * stride + x) as usize;
+                    let d = src[idx] as i64 - pred[idx] as i64;
+                    sse += (d * d) as u64;
+                }
+            }
+            sse
+        };
 
-        let mut u_bottom_row = [mid_value; 4];
-        let mut u_right_col = [mid_value; 4];
-        for c in 0..4u32 {
-            let dest_x = chroma_px_x + c;
-            let dest_y = chroma_px_y + 3;
-            if dest_x < cw && dest_y < ch {
-                u_bottom_row[c as usize] = self.recon.u[(dest_y * cw + dest_x) as usize];
-            }
-        }
-        for r in 0..4u32 {
-            let dest_x = chroma_px_x + 3;
-            let dest_y = chroma_px_y + r;
-            if dest_x < cw && dest_y < ch {
-                u_right_col[r as usize] = self.recon.u[(dest_y * cw + dest_x) as usize];
-            }
+        let y_sse = plane_sse(&self.pixels.y, &reference.y, w, px_x, px_y, x_end, y_end);
+        let uv_sse = plane_sse(
+            &self.pixels.u,
+            &reference.u,
+            cw,
+            px_x / 2,
+            px_y / 2,
+            cx_end,
+            cy_end,
+        ) + plane_sse(
+            &self.pixels.v,
+            &reference.v,
+            cw,
+            px_x / 2,
+            px_y / 2,
+            cx_end,
+            cy_end,
+        );
+        (y_sse, uv_sse)
+    }
+
+    fn write_refs(&mut self, bx: u32, by: u32, bl: usize, partition: u32, refs: BlockRefs) {
+        let comp_mode_coded = self.comp_mode_coded(bl, partition);
+        for (cdf, ctx, value) in self.ctx.ref_symbols(bx, by, refs, comp_mode_coded) {
+            self.enc
+                .encode_bool(value, cdf.select_mut(&mut self.cdf, ctx));
         }
+    }
 
-        let mut v_bottom_row = [mid_value; 4];
-        let mut v_right_col = [mid_value; 4];
-        for c in 0..4u32 {
-            let dest_x = chroma_px_x + c;
-            let dest_y = chroma_px_y + 3;
-            if dest_x < cw && dest_y < ch {
-                v_bottom_row[c as usize] = self.recon.v[(dest_y * cw + dest_x) as usize];
+    fn refs_rate(&self, bx: u32, by: u32, bl: usize, partition: u32, refs: BlockRefs) -> u32 {
+        self.ctx
+            .ref_symbols(bx, by, refs, self.comp_mode_coded(bl, partition))
+            .into_iter()
+            .map(|(cdf, ctx, value)| crate::rdo::bool_cost(cdf.select(&self.cdf, ctx), value))
+            .sum()
+    }
+
+    fn write_global_mode(
+        &mut self,
+        bx: u32,
+        by: u32,
+        bl: usize,
+        partition: u32,
+        refs: BlockRefs,
+        has_top_right: bool,
+    ) {
+        match refs {
+            BlockRefs::Single(ref_frame) => {
+                let newmv_ctx = self.newmv_ctx(bx, by, bl, partition, ref_frame, has_top_right);
+                self.enc.encode_bool(true, &mut self.cdf.newmv[newmv_ctx]);
+                let zeromv_ctx = 0usize;
+                self.enc
+                    .encode_bool(false, &mut self.cdf.zeromv[zeromv_ctx]);
             }
-        }
-        for r in 0..4u32 {
-            let dest_x = chroma_px_x + 3;
-            let dest_y = chroma_px_y + r;
-            if dest_x < cw && dest_y < ch {
-                v_right_col[r as usize] = self.recon.v[(dest_y * cw + dest_x) as usize];
+            BlockRefs::Compound(..) => {
+                let ctx = self.compound_mode_ctx(bx, by, bl, partition, refs, has_top_right);
+                self.enc.encode_symbol(
+                    GLOBAL_GLOBALMV,
+                    &mut self.cdf.compound_mode[ctx],
+                    COMPOUND_MODES - 1,
+                );
             }
         }
+    }
 
-        let stored_mv = BlockMv {
-            mv_x: final_mv_x,
-            mv_y: final_mv_y,
-            ref_frame: 0,
-        };
-        for row in by..by.saturating_add(2).min(self.mi_rows) {
-            for col in bx..bx.saturating_add(2).min(self.mi_cols) {
-                self.block_mvs[(row * self.mi_cols + col) as usize] = stored_mv;
+    fn global_mode_rate(
+        &self,
+        bx: u32,
+        by: u32,
+        bl: usize,
+        partition: u32,
+        refs: BlockRefs,
+        has_top_right: bool,
+    ) -> u32 {
+        match refs {
+            BlockRefs::Single(ref_frame) => {
+                let newmv_ctx = self.newmv_ctx(bx, by, bl, partition, ref_frame, has_top_right);
+                crate::rdo::bool_cost(&self.cdf.newmv[newmv_ctx], true)
+                    + crate::rdo::bool_cost(&self.cdf.zeromv[0], false)
+            }
+            BlockRefs::Compound(..) => {
+                let ctx = self.compound_mode_ctx(bx, by, bl, partition, refs, has_top_right);
+                crate::rdo::symbol_cost(
+                    &self.cdf.compound_mode[ctx],
+                    GLOBAL_GLOBALMV,
+                    COMPOUND_MODES - 1,
+                )
             }
         }
+    }
 
-        self.ctx.update_recon(
-            bx,
-            by,
-            self.mi_cols,
-            self.mi_rows,
-            &y_bottom_row,
-            &y_right_col,
-            &u_bottom_row,
-            &u_right_col,
-            &v_bottom_row,
-            &v_right_col,
-        );
-        let y_cf_ctx = coef_ctx_value(y_cul, y_dc_neg, y_dc_zero);
-        let u_cf_ctx = coef_ctx_value(u_cul, u_dc_neg, u_dc_zero);
-        let v_cf_ctx = coef_ctx_value(v_cul, v_dc_neg, v_dc_zero);
-        self.ctx.update_coef_ctx(
+    fn newmv_ctx(
+        &self,
+        bx: u32,
+        by: u32,
+        bl: usize,
+        partition: u32,
+        ref_frame: RefFrame,
+        has_top_right: bool,
+    ) -> usize {
+        self.mode_ctx(
             bx,
             by,
             bl,
-            self.mi_cols,
-            self.mi_rows,
-            y_cf_ctx,
-            u_cf_ctx,
-            v_cf_ctx,
-        );
-        self.ctx
-            .update_partition_ctx(bx, by, bl, self.mi_cols, self.mi_rows);
-        self.ctx
-            .update_skip_ctx(bx, by, bl, self.mi_cols, self.mi_rows, is_skip);
-        self.ctx
-            .update_intra_ctx(bx, by, bl, self.mi_cols, self.mi_rows, false);
-        self.ctx
-            .update_newmv_flag(bx, by, bl, self.mi_cols, self.mi_rows, use_newmv);
+            partition,
+            BlockRefs::Single(ref_frame),
+            has_top_right,
+        )
+        .0
     }
 
-    fn should_use_inter_partition_none(&self, bx: u32, by: u32, bl: usize) -> bool {
-        let block_size = 1u32 << (7 - bl);
-        let px_x = bx * 4;
-        let px_y = by * 4;
+    /// libaom `av1_mode_context_analyzer`.
+    fn compound_mode_ctx(
+        &self,
+        bx: u32,
+        by: u32,
+        bl: usize,
+        partition: u32,
+        refs: BlockRefs,
+        has_top_right: bool,
+    ) -> usize {
+        const COMPOUND_MODE_CTX_MAP: [[usize; 5]; 3] =
+            [[0, 1, 1, 1, 1], [1, 2, 3, 4, 4], [4, 4, 5, 6, 7]];
+        let (newmv_ctx, refmv_ctx) = self.mode_ctx(bx, by, bl, partition, refs, has_top_right);
+        COMPOUND_MODE_CTX_MAP[refmv_ctx >> 1][newmv_ctx.min(4)]
+    }
+
+    /// NEWMV and REFMV contexts of a `bl` block predicting from `refs`.
+    fn mode_ctx(
+        &self,
+        bx: u32,
+        by: u32,
+        bl: usize,
+        partition: u32,
+        refs: BlockRefs,
+        has_top_right: bool,
+    ) -> (usize, usize) {
+        let (bw4, bh4) = block_dims(bl, partition);
+        let w4 = bw4.min(16).min(self.mi_cols - bx);
+        let h4 = bh4.min(16).min(self.mi_rows - by);
+        let step = |side4: u32| if side4 >= 16 { 4 } else { 1 };
+        let max_rows = ((by + 1) >> 1).min(3);
+        let max_cols = ((bx + 1) >> 1).min(3);
+        let mut scan = RefMvScan {
+            block_mvs: &self.block_mvs,
+            mi_cols: self.mi_cols,
+            refs,
+            have_newmv: false,
+        };
+
+        let (mut n_rows, mut row_match) = (u32::MAX, false);
+        if by > 0 {
+            (n_rows, row_match) = scan.scan_row(by - 1, bx, bw4, w4, max_rows, step(bw4), true);
+        }
+        let (mut n_cols, mut col_match) = (u32::MAX, false);
+        if bx > 0 {
+            (n_cols, col_match) = scan.scan_col(by, bx - 1, bh4, h4, max_cols, step(bh4), true);
+        }
+        if by > 0 && has_top_right && bw4.max(bh4) <= 16 && bx + bw4 < self.mi_cols {
+            row_match |= scan.visit(bx + bw4, by - 1, true);
+        }
+        let nearest_match = row_match as u32 + col_match as u32;
+        let have_newmv = scan.have_newmv as usize;
+
+        if bx > 0 && by > 0 {
+            row_match |= scan.visit(bx - 1, by - 1, false);
+        }
+        for n in 2..=3 {
+            if n > n_rows && n <= max_rows {
+                let (rows, matched) = scan.scan_row(
+                    (by + 1 - 2 * n) | 1,
+                    bx | 1,
+                    bw4,
+                    w4,
+                    1 + max_rows - n,
+                    step(bw4),
+                    false,
+                );
+                n_rows += rows;
+                row_match |= matched;
+            }
+            if n > n_cols && n <= max_cols {
+                let (cols, matched) = scan.scan_col(
+                    by | 1,
+                    (bx + 1 - 2 * n) | 1,
+                    bh4,
+                    h4,
+                    1 + max_cols - n,
+                    step(bh4),
+                    false,
+                );
+                n_cols += cols;
+                col_match |= matched;
+            }
+        }
+
+        let total_match = row_match as usize + col_match as usize;
+        match nearest_match {
+            0 => (total_match.min(1), total_match),
+            1 => (3 - have_newmv, 2 + total_match),
+            _ => (5 - have_newmv, 5),
+        }
+    }
+
+    /// Estimated luma distortion and rate (1/256 bits) of coding the region
+    /// as 8x8 inter blocks predicted from `(mv_x, mv_y)` or zero motion,
+    /// whichever matches better per block.
+    fn estimate_coded_luma(
+        &self,
+        px_x: u32,
+        px_y: u32,
+        x_end: u32,
+        y_end: u32,
+        mv_x: i32,
+        mv_y: i32,
+    ) -> (u64, u64) {
+        // is_inter, reference, mode and skip flags of an 8x8 leaf.
+        const LEAF_HEADER_COST: u64 = 6 << crate::rdo::COST_SHIFT;
+
         let w = self.pixels.width;
         let h = self.pixels.height;
-        // Fast SATD extraction for inter blocks
-        let bs = block_size as usize;
-        let satd = if px_x + block_size <= w && px_y + block_size <= h {
-            // Zero-allocation fast path
-            let offset = (px_y * w + px_x) as usize;
-            if bs >= 4 && (bs == 4 || bs == 8 || bs == 16 || bs == 32 || bs == 64) {
-                crate::satd::compute_satd(
-                    &self.pixels.y[offset..],
-                    &self.reference.y[offset..],
-                    bs,
-                    bs,
-                    w as usize,
-                    w as usize,
-                )
-            } else {
-                let mut sad = 0u64;
-                for r in 0..bs {
-                    for c in 0..bs {
-                        let s = self.pixels.y[offset + r * w as usize + c] as i32;
-                        let ref_p = self.reference.y[offset + r * w as usize + c] as i32;
-                        sad += (s - ref_p).unsigned_abs() as u64;
+        let mut sse_total = 0u64;
+        let mut rate_total = 0u64;
+        let mut uses_mv = false;
+
+        for by8 in (px_y..y_end).step_by(8) {
+            for bx8 in (px_x..x_end).step_by(8) {
+                let src = extract_block(&self.pixels.y, w, bx8, by8, 8, w, h);
+                let zero_pred = extract_block(&self.reference.y, w, bx8, by8, 8, w, h);
+                let mut pred = zero_pred;
+                if mv_x != 0 || mv_y != 0 {
+                    let mc_pred =
+                        self.motion_compensate(RefFrame::Last, 0, bx8, by8, mv_x * 8, mv_y * 8, 8);
+                    if compute_sad(&src, &mc_pred) < compute_sad(&src, &pred) {
+                        pred = mc_pred;
+                        uses_mv = true;
                     }
                 }
-                sad
-            }
-        } else {
-            // Edge fallback path with clamping
-            let mut src_block = vec![0u16; bs * bs];
-            let mut ref_block = vec![0u16; bs * bs];
-            for r in 0..bs {
-                for c in 0..bs {
-                    let sy = std::cmp::min(px_y + r as u32, h - 1);
-                    let sx = std::cmp::min(px_x + c as u32, w - 1);
-                    let idx = (sy * w + sx) as usize;
-                    src_block[r * bs + c] = self.pixels.y[idx];
-                    ref_block[r * bs + c] = self.reference.y[idx];
-                }
-            }
-            if bs >= 4 && (bs == 4 || bs == 8 || bs == 16 || bs == 32 || bs == 64) {
-                crate::satd::compute_satd(&src_block, &ref_block, bs, bs, bs, bs)
-            } else {
-                let mut sad = 0u64;
-                for i in 0..bs * bs {
-                    sad += (src_block[i] as i32 - ref_block[i] as i32).unsigned_abs() as u64;
-                }
-                sad
-            }
-        };
 
-        let base = self.dq.ac as u64;
-        let threshold = match bl {
-            1 => base * 2,
-            2 => base * 4,
-            3 => base * 6,
-            _ => base * 8,
-        };
+                let (sse, rate) = coded_8x8_cost(
+                    &src,
+                    &pred,
+                    &self.dq,
+                    self.pixels.bit_depth,
+                    min(8, y_end - by8) as usize,
+                    min(8, x_end - bx8) as usize,
+                );
+                sse_total += sse;
+                rate_total += LEAF_HEADER_COST + rate;
+            }
+        }
 
-        // Zero SATD is a perfect match (e.g. solid colors), always skip.
-        if satd == 0 { true } else { satd <= threshold }
+        if uses_mv {
+            rate_total += (crate::rdo::estimate_mv_component_cost(mv_x * 8)
+                + crate::rdo::estimate_mv_component_cost(mv_y * 8))
+                as u64;
+        }
+        (sse_total, rate_total)
     }
 
-    fn encode_inter_skip_block(&mut self, bx: u32, by: u32, bl: usize) {
+    /// Codes a zero-motion skip block. `partition` is how the `bl` block was
+    /// partitioned: NONE, or HORZ/VERT for a block on the bottom/right frame
+    /// edge whose second half lies entirely outside the frame, so the
+    /// in-frame area of the half is the in-frame area of the whole block.
+    fn encode_inter_skip_block(
+        &mut self,
+        bx: u32,
+        by: u32,
+        bl: usize,
+        partition: u32,
+        refs: BlockRefs,
+        has_top_right: bool,
+    ) {
+        debug_assert!(match partition {
+            PARTITION_HORZ => by + (16 >> bl) >= self.mi_rows,
+            PARTITION_VERT => bx + (16 >> bl) >= self.mi_cols,
+            _ => partition == PARTITION_NONE,
+        });
         let px_x = bx * 4;
         let px_y = by * 4;
         let block_size = 1u32 << (7 - bl);
@@ -3381,33 +5294,23 @@ impl<'a> InterTileEncoder<'a> {
 
         let skip_ctx = self.ctx.skip_ctx(bx, by);
         self.enc.encode_bool(true, &mut self.cdf.skip[skip_ctx]);
+        self.write_delta_q(bl == 1 && partition == PARTITION_NONE);
 
         let is_inter_ctx = self.ctx.is_inter_ctx(bx, by);
         self.enc
             .encode_bool(true, &mut self.cdf.is_inter[is_inter_ctx]);
 
-        let ref_ctx = self.ctx.ref_ctx(bx, by);
-        self.enc
-            .encode_bool(false, &mut self.cdf.single_ref[ref_ctx][0]);
-        self.enc
-            .encode_bool(false, &mut self.cdf.single_ref[ref_ctx][2]);
-        self.enc
-            .encode_bool(false, &mut self.cdf.single_ref[ref_ctx][3]);
-
-        let newmv_ctx = self.ctx.newmv_ctx(bx, by);
-        self.enc.encode_bool(true, &mut self.cdf.newmv[newmv_ctx]);
-
-        let zeromv_ctx = 0usize;
-        self.enc
-            .encode_bool(false, &mut self.cdf.zeromv[zeromv_ctx]);
+        self.write_refs(bx, by, bl, partition, refs);
+        self.write_global_mode(bx, by, bl, partition, refs, has_top_right);
 
+        let reference = self.prediction_pixels(refs);
         for r in 0..block_size {
             for c in 0..block_size {
                 let dest_x = px_x + c;
                 let dest_y = px_y + r;
                 if dest_x < w && dest_y < h {
                     let idx = (dest_y * w + dest_x) as usize;
-                    self.recon.y[idx] = self.reference.y[idx];
+                    self.recon.y[idx] = reference.y[idx];
                 }
             }
         }
@@ -3417,8 +5320,8 @@ impl<'a> InterTileEncoder<'a> {
                 let dest_y = cpy + r;
                 if dest_x < cw && dest_y < ch {
                     let idx = (dest_y * cw + dest_x) as usize;
-                    self.recon.u[idx] = self.reference.u[idx];
-                    self.recon.v[idx] = self.reference.v[idx];
+                    self.recon.u[idx] = reference.u[idx];
+                    self.recon.v[idx] = reference.v[idx];
                 }
             }
         }
@@ -3483,18 +5386,25 @@ impl<'a> InterTileEncoder<'a> {
             skip_cf,
         );
         self.ctx
-            .update_partition_ctx(bx, by, bl, self.mi_cols, self.mi_rows);
+            .update_partition_ctx_for(bx, by, bl, partition, self.mi_cols, self.mi_rows);
         self.ctx
             .update_skip_ctx(bx, by, bl, self.mi_cols, self.mi_rows, true);
+        self.skips.fill(bx, by, bl, true);
         self.ctx
             .update_intra_ctx(bx, by, bl, self.mi_cols, self.mi_rows, false);
         self.ctx
-            .update_newmv_flag(bx, by, bl, self.mi_cols, self.mi_rows, false);
+            .update_ref_ctx(bx, by, bl, self.mi_cols, self.mi_rows, refs);
 
+        let (bw4, bh4) = block_dims(bl, partition);
+        let [ref_frame, second_ref] = refs.pair();
         let stored_mv = BlockMv {
             mv_x: 0,
             mv_y: 0,
-            ref_frame: 0,
+            ref_frame,
+            second_ref,
+            newmv: false,
+            bw4: bw4 as u8,
+            bh4: bh4 as u8,
         };
         let mi_per_side = 2u32 << (4 - bl);
         for row in by..by.saturating_add(mi_per_side).min(self.mi_rows) {
@@ -3504,7 +5414,7 @@ impl<'a> InterTileEncoder<'a> {
         }
     }
 
-    fn encode_inter_partition(&mut self, bl: usize, bx: u32, by: u32) {
+    fn encode_inter_partition(&mut self, bl: usize, bx: u32, by: u32, has_top_right: bool) {
         if bl > 4 {
             return;
         }
@@ -3516,58 +5426,96 @@ impl<'a> InterTileEncoder<'a> {
         if have_h_split && have_v_split {
             let part_ctx = self.ctx.partition_ctx(bx, by, bl);
             if bl < 4 {
-                if bl >= 2 && self.should_use_inter_partition_none(bx, by, bl) {
+                let costs = (bl >= 2 && !self.split_is_settled)
+                    .then(|| self.inter_partition_none_costs(bx, by, bl, has_top_right));
+                if let Some(costs) = costs {
+                    self.record_motion_estimate(bx, by, bl, costs);
+                }
+                if let Some(costs) = costs.filter(|c| c.skip_wins()) {
                     self.enc.encode_symbol(
-                        0,
+                        PARTITION_NONE,
                         &mut self.cdf.partition[bl][part_ctx],
                         PARTITION_NSYMS[bl],
                     );
-                    self.encode_inter_skip_block(bx, by, bl);
+                    self.encode_inter_skip_block(
+                        bx,
+                        by,
+                        bl,
+                        PARTITION_NONE,
+                        costs.reference,
+                        has_top_right,
+                    );
                 } else {
                     self.enc.encode_symbol(
-                        3,
+                        PARTITION_SPLIT,
                         &mut self.cdf.partition[bl][part_ctx],
                         PARTITION_NSYMS[bl],
                     );
-                    self.encode_inter_partition(bl + 1, bx, by);
-                    self.encode_inter_partition(bl + 1, bx + hsz, by);
-                    self.encode_inter_partition(bl + 1, bx, by + hsz);
-                    self.encode_inter_partition(bl + 1, bx + hsz, by + hsz);
+                    // Subblocks of a block that splits by a wide margin are
+                    // not tested for skip again.
+                    let settled = costs.is_some_and(|c| self.early_exit.split_is_settled(c));
+                    let outer = self.split_is_settled;
+                    self.split_is_settled = outer || settled;
+                    self.encode_inter_partition(bl + 1, bx, by, true);
+                    self.encode_inter_partition(bl + 1, bx + hsz, by, has_top_right);
+                    self.encode_inter_partition(bl + 1, bx, by + hsz, true);
+                    self.encode_inter_partition(bl + 1, bx + hsz, by + hsz, false);
+                    self.split_is_settled = outer;
                 }
             } else {
                 self.enc.encode_symbol(
-                    0,
+                    PARTITION_NONE,
                     &mut self.cdf.partition[bl][part_ctx],
                     PARTITION_NSYMS[bl],
                 );
-                self.encode_inter_block(bx, by, bl);
+                self.encode_inter_block(bx, by, bl, has_top_right);
             }
         } else if have_h_split {
+            // Bottom edge: the bool codes SPLIT against HORZ, whose lower half
+            // is outside the frame.
             let part_ctx = self.ctx.partition_ctx(bx, by, bl);
             let prob = gather_top_partition_prob(&self.cdf.partition[bl][part_ctx], bl);
-            self.enc.encode_bool_prob(true, prob);
-
-            self.encode_inter_partition(bl + 1, bx, by);
-            self.encode_inter_partition(bl + 1, bx + hsz, by);
+            let horz = (2..4)
+                .contains(&bl)
+                .then(|| self.inter_partition_edge_skip(bx, by, bl, prob, has_top_right))
+                .flatten();
+            self.enc.encode_bool_prob(horz.is_none(), prob);
+
+            if let Some(reference) = horz {
+                self.encode_inter_skip_block(bx, by, bl, PARTITION_HORZ, reference, has_top_right);
+            } else {
+                self.encode_inter_partition(bl + 1, bx, by, true);
+                self.encode_inter_partition(bl + 1, bx + hsz, by, has_top_right);
+            }
         } else if have_v_split {
+            // Right edge: SPLIT against VERT, whose right half is outside.
             let part_ctx = self.ctx.partition_ctx(bx, by, bl);
             let prob = gather_left_partition_prob(&self.cdf.partition[bl][part_ctx], bl);
-            self.enc.encode_bool_prob(true, prob);
-
-            self.encode_inter_partition(bl + 1, bx, by);
-            self.encode_inter_partition(bl + 1, bx, by + hsz);
+            let vert = (2..4)
+                .contains(&bl)
+                .then(|| self.inter_partition_edge_skip(bx, by, bl, prob, has_top_right))
+                .flatten();
+            self.enc.encode_bool_prob(vert.is_none(), prob);
+
+            if let Some(reference) = vert {
+                self.encode_inter_skip_block(bx, by, bl, PARTITION_VERT, reference, true);
+            } else {
+                self.encode_inter_partition(bl + 1, bx, by, true);
+                self.encode_inter_partition(bl + 1, bx, by + hsz, true);
+            }
         } else {
-            self.encode_inter_partition(bl + 1, bx, by);
+            self.encode_inter_partition(bl + 1, bx, by, true);
         }
     }
 }
 
+#[cfg(feature = "inter")]
 pub fn encode_inter_tile(pixels: &FramePixels, reference: &FramePixels) -> Vec<u8> {
     let dq = crate::dequant::lookup_dequant(crate::DEFAULT_BASE_Q_IDX, pixels.bit_depth);
     encode_inter_tile_with_recon(pixels, reference, None, dq, crate::DEFAULT_BASE_Q_IDX).0
 }
 
-fn estimate_global_motion(
+pub(crate) fn estimate_global_motion(
     source: &[u16],
     reference: &[u16],
     width: u32,
@@ -3665,14 +5613,41 @@ fn estimate_global_motion(
     (best_dx * 4, best_dy * 4)
 }
 
+/// The compound average of `a` and `b`, without distance weighting.
+#[cfg(feature = "inter")]
+fn average_frames(a: &FramePixels, b: &FramePixels) -> FramePixels {
+    let average = |a: &[u16], b: &[u16]| -> Vec<u16> {
+        a.iter().zip(b).map(|(&a, &b)| (a + b + 1) >> 1).collect()
+    };
+    FramePixels {
+        width: a.width,
+        height: a.height,
+        bit_depth: a.bit_depth,
+        color_range: a.color_range,
+        y: average(&a.y, &b.y),
+        u: average(&a.u, &b.u),
+        v: average(&a.v, &b.v),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+#[cfg(feature = "inter")]
 fn encode_inter_tile_with_recon_with_global_mv(
     pixels: &FramePixels,
     reference: &FramePixels,
+    alternates: &[(RefFrame, &FramePixels)],
     forward_reference: Option<&FramePixels>,
     dq: DequantValues,
     base_q_idx: u8,
     global_mv: (i32, i32),
-) -> (Vec<u8>, FramePixels) {
+    motion: MotionParams,
+    early_exit: EarlyExit,
+    colocated: Option<MvField>,
+    cdef: CdefSignal,
+    restoration: RestorationSignal,
+    delta_q: SbDeltaQ,
+    frame: Option<TileFrame<'_>>,
+) -> (Vec<u8>, FramePixels, SkipMap, FrameMotion) {
     assert_eq!(
         pixels.width, reference.width,
         "reference frame width mismatch"
@@ -3681,6 +5656,7 @@ fn encode_inter_tile_with_recon_with_global_mv(
         pixels.height, reference.height,
         "reference frame height mismatch"
     );
+    let compound = forward_reference.map(|fwd| average_frames(reference, fwd));
     let mut tile = InterTileEncoder::new(
         pixels,
         reference,
@@ -3689,6 +5665,19 @@ fn encode_inter_tile_with_recon_with_global_mv(
         base_q_idx,
         global_mv,
     );
+    tile.alternates = alternates.to_vec();
+    tile.compound = compound.as_ref();
+    tile.motion = motion;
+    if motion.prefilter {
+        tile.search_luma = Some(SearchLuma::new(&tile));
+    }
+    tile.early_exit = early_exit;
+    tile.colocated =
+        colocated.filter(|field| (field.mi_cols, field.mi_rows) == (tile.mi_cols, tile.mi_rows));
+    tile.cdef = cdef;
+    tile.restoration = restoration;
+    tile.delta_q = DeltaQCursor::new(delta_q, base_q_idx, dq);
+    tile.frame = frame;
 
     let sb_cols = tile.mi_cols.div_ceil(16);
     let sb_rows = tile.mi_rows.div_ceil(16);
@@ -3698,14 +5687,22 @@ fn encode_inter_tile_with_recon_with_global_mv(
         for sb_col in 0..sb_cols {
             let bx = sb_col * 16;
             let by = sb_row * 16;
-            tile.encode_inter_partition(1, bx, by);
+            tile.cdef_pending = true;
+            tile.start_superblock(sb_col, sb_row);
+            tile.encode_inter_partition(1, bx, by, true);
         }
     }
 
+    let coded = tile.coded_mvs();
     let tile_bytes = tile.enc.finalize();
-    (tile_bytes, tile.recon)
+    let motion = FrameMotion {
+        estimates: tile.motion_estimates,
+        coded,
+    };
+    (tile_bytes, tile.recon, tile.skips, motion)
 }
 
+#[cfg(feature = "inter")]
 pub fn encode_inter_tile_with_recon(
     pixels: &FramePixels,
     reference: &FramePixels,
@@ -3714,24 +5711,46 @@ pub fn encode_inter_tile_with_recon(
     base_q_idx: u8,
 ) -> (Vec<u8>, FramePixels) {
     let global_mv = estimate_global_motion(&pixels.y, &reference.y, pixels.width, pixels.height);
-    encode_inter_tile_with_recon_with_global_mv(
+    let (bytes, recon, _, _) = encode_inter_tile_with_recon_with_global_mv(
         pixels,
         reference,
+        &[],
         forward_reference,
         dq,
         base_q_idx,
         global_mv,
-    )
+        MotionParams::default(),
+        EarlyExit::default(),
+        None,
+        CdefSignal::default(),
+        RestorationSignal::default(),
+        SbDeltaQ::default(),
+        None,
+    );
+    (bytes, recon)
 }
 
+/// Codes every tile of `plan` against the references. As with
+/// [`encode_tiles_with_recon`], the reconstruction is padded to whole 8x8
+/// blocks and `on_tile` sees each tile as soon as it is in order.
+#[allow(clippy::too_many_arguments)]
+#[cfg(feature = "inter")]
 pub fn encode_inter_tiles_with_recon(
     pixels: &FramePixels,
     reference: &FramePixels,
+    alternates: &[(RefFrame, &FramePixels)],
     forward_reference: Option<&FramePixels>,
     dq: DequantValues,
     base_q_idx: u8,
     plan: &TilePlan,
-) -> (Vec<Vec<u8>>, FramePixels) {
+    motion: MotionParams,
+    early_exit: EarlyExit,
+    colocated: Option<&MvField>,
+    cdef: &CdefSignal,
+    restoration: &RestorationPlan,
+    delta_q: &SbDeltaQ,
+    mut on_tile: Option<TileSink<'_>>,
+) -> (Vec<Vec<u8>>, FramePixels, SkipMap, FrameMotion) {
     assert_eq!(
         pixels.width, reference.width,
         "reference frame width mismatch"
@@ -3740,6 +5759,13 @@ pub fn encode_inter_tiles_with_recon(
         pixels.height, reference.height,
         "reference frame height mismatch"
     );
+    for (_, alternate) in alternates {
+        assert_eq!(
+            (pixels.width, pixels.height),
+            (alternate.width, alternate.height),
+            "alternate reference frame size mismatch"
+        );
+    }
     if let Some(fwd) = forward_reference {
         assert_eq!(
             pixels.width, fwd.width,
@@ -3752,41 +5778,109 @@ pub fn encode_inter_tiles_with_recon(
     }
 
     let global_mv = estimate_global_motion(&pixels.y, &reference.y, pixels.width, pixels.height);
+    let pixels = &pad_to_block_grid(pixels);
+    let reference = &pad_to_block_grid(reference);
+    let alternates: Vec<_> = alternates
+        .iter()
+        .map(|&(ref_frame, alternate)| (ref_frame, pad_to_block_grid(alternate)))
+        .collect();
+    let alternates: Vec<_> = alternates
+        .iter()
+        .map(|(ref_frame, alternate)| (*ref_frame, alternate))
+        .collect();
+    let forward_reference = forward_reference.map(pad_to_block_grid);
+    let forward_reference = forward_reference.as_ref();
     if plan.tiles.len() == 1 {
-        let (bytes, recon) = encode_inter_tile_with_recon_with_global_mv(
+        let (bytes, recon, skips, mvs) = encode_inter_tile_with_recon_with_global_mv(
             pixels,
             reference,
+            &alternates,
             forward_reference,
             dq,
             base_q_idx,
             global_mv,
+            motion,
+            early_exit,
+            colocated.cloned(),
+            cdef.clone(),
+            RestorationSignal::for_tile(restoration, &plan.tiles[0]),
+            delta_q.clone(),
+            None,
         );
-        return (vec![bytes], recon);
+        if let Some(on_tile) = on_tile {
+            on_tile(0, &bytes);
+        }
+        return (vec![bytes], recon, skips, mvs);
     }
 
     let mut all_tiles = Vec::with_capacity(plan.tiles.len());
     let mut stitched_recon = empty_frame_like(pixels);
+    let mut stitched_skips = SkipMap::all_skipped(pixels.width, pixels.height);
+    let (mi_cols, mi_rows) = (2 * pixels.width.div_ceil(8), 2 * pixels.height.div_ceil(8));
+    let mut stitched_mvs = FrameMotion {
+        estimates: MvField::new(mi_cols, mi_rows),
+        coded: MvField::new(mi_cols, mi_rows),
+    };
 
-    for rect in &plan.tiles {
-        let tile_pixels = crop_tile_region(pixels, rect);
-        let tile_reference = crop_tile_region(reference, rect);
-        let tile_forward_reference = forward_reference.map(|fwd| crop_tile_region(fwd, rect));
+    let encoded = map_tiles(
+        plan,
+        |rect| {
+            let tile_pixels = crop_tile_region(pixels, rect);
+            let tile_reference = crop_tile_region(reference, rect);
+            let tile_alternates: Vec<_> = alternates
+                .iter()
+                .map(|&(ref_frame, alternate)| (ref_frame, crop_tile_region(alternate, rect)))
+                .collect();
+            let tile_alternate_refs: Vec<_> = tile_alternates
+                .iter()
+                .map(|(ref_frame, alternate)| (*ref_frame, alternate))
+                .collect();
+            let tile_forward_reference = forward_reference.map(|fwd| crop_tile_region(fwd, rect));
+            let (x, y, _, _) = tile_rect_to_pixel_bounds(rect, pixels.width, pixels.height);
+            let frame = TileFrame {
+                references: std::iter::once((RefFrame::Last, reference))
+                    .chain(alternates.iter().copied())
+                    .chain(forward_reference.map(|fwd| (RefFrame::Altref, fwd)))
+                    .collect(),
+                x,
+                y,
+            };
 
-        let (tile_bytes, tile_recon) = encode_inter_tile_with_recon_with_global_mv(
-            &tile_pixels,
-            &tile_reference,
-            tile_forward_reference.as_ref(),
-            dq,
-            base_q_idx,
-            global_mv,
-        );
+            encode_inter_tile_with_recon_with_global_mv(
+                &tile_pixels,
+                &tile_reference,
+                &tile_alternate_refs,
+                tile_forward_reference.as_ref(),
+                dq,
+                base_q_idx,
+                global_mv,
+                motion,
+                early_exit,
+                colocated.map(|field| field.crop(rect)),
+                cdef.for_tile(rect),
+                RestorationSignal::for_tile(restoration, rect),
+                delta_q.for_tile(rect),
+                Some(frame),
+            )
+        },
+        |i, (bytes, ..)| {
+            if let Some(on_tile) = on_tile.as_mut() {
+                on_tile(i, bytes);
+            }
+        },
+    );
+    for (rect, (tile_bytes, tile_recon, tile_skips, tile_mvs)) in plan.tiles.iter().zip(encoded) {
         stitch_tile_region(&mut stitched_recon, &tile_recon, rect);
+        stitched_skips.paste(&tile_skips, rect);
+        stitched_mvs.estimates.paste(&tile_mvs.estimates, rect);
+        stitched_mvs.coded.paste(&tile_mvs.coded, rect);
         all_tiles.push(tile_bytes);
     }
 
-    (all_tiles, stitched_recon)
+    (all_tiles, stitched_recon, stitched_skips, stitched_mvs)
 }
 
+#[cfg(feature = "inter")]
 fn decompose_mv_diff(diff: u32) -> (u32, u32, u32) {
     let raw = diff - 1;
     let fp = (raw >> 1) & 3;
@@ -3799,6 +5893,7 @@ fn decompose_mv_diff(diff: u32) -> (u32, u32, u32) {
     }
 }
 
+#[cfg(feature = "inter")]
 fn encode_mv_component(
     enc: &mut MsacEncoder,
     comp_cdf: &mut crate::cdf::MvComponentCdf,
@@ -3823,6 +5918,7 @@ fn encode_mv_component(
     }
 }
 
+#[cfg(feature = "inter")]
 fn encode_mv_residual(enc: &mut MsacEncoder, mv_cdf: &mut crate::cdf::MvCdf, dy: i32, dx: i32) {
     let joint = match (dy != 0, dx != 0) {
         (false, false) => 0,
@@ -3841,8 +5937,327 @@ fn encode_mv_residual(enc: &mut MsacEncoder, mv_cdf: &mut crate::cdf::MvCdf, dy:
     }
 }
 
+/// Full-pel motion search radius for a reference one frame away.
+pub const DEFAULT_MV_SEARCH_RANGE: u32 = 32;
+/// Upper bound on the scaled search radius, well inside what
+/// `encode_mv_component` can code.
+pub const MAX_MV_SEARCH_RANGE: u32 = 256;
+
+/// Motion search window and RD lambda scale for one inter frame.
+///
+/// Motion grows roughly linearly with the display-order distance to the
+/// reference, so the window scales with it. A frame predicted from a distant
+/// reference (the hidden frame ending a B-frame mini-GOP) also leaves more
+/// residual per block; with the lambda of an adjacent-frame prediction, RD
+/// treats that residual as cheap to drop and skips far more blocks, although
+/// the B-frames in between then predict from the result. The lambda is
+/// scaled by `2 / (1 + distance)` so those decisions stay comparable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MotionParams {
+    pub search_range: i32,
+    /// RD lambda multiplier in 1/256 units.
+    pub lambda_scale_q8: u64,
+    /// Keep searched motion vectors on whole pixels, skipping the sub-pixel
+    /// refinement, so text and UI edges are copied rather than interpolated.
+    pub integer_mv: bool,
+    /// Search full-pel motion on smoothed luma (see [`smooth_luma`]) so
+    /// sensor noise does not steer the vectors.
+    pub prefilter: bool,
+}
+
+impl Default for MotionParams {
+    fn default() -> Self {
+        Self::for_distance(DEFAULT_MV_SEARCH_RANGE, 1)
+    }
+}
+
+impl MotionParams {
+    pub fn for_distance(search_range: u32, ref_distance: u64) -> Self {
+        let distance = ref_distance.max(1);
+        let range = (search_range as u64 * distance).clamp(1, MAX_MV_SEARCH_RANGE as u64);
+        Self {
+            search_range: range as i32,
+            lambda_scale_q8: 512 / (1 + distance),
+            integer_mv: false,
+            prefilter: false,
+        }
+    }
+
+    pub fn scale_lambda(self, lambda: u64) -> u64 {
+        (lambda * self.lambda_scale_q8) >> 8
+    }
+}
+
+#[cfg(feature = "inter")]
+/// Zero-motion prediction of a block.
+struct ZeroMvPrediction<'a> {
+    refs: BlockRefs,
+    pixels: &'a FramePixels,
+    y_sse: u64,
+    uv_sse: u64,
+    /// Rate of the reference and mode symbols that select it.
+    rate: u32,
+}
+
+/// The prediction of an 8x8 leaf of an inter frame coded intra.
+#[cfg(feature = "inter")]
+struct IntraLeaf {
+    mode: u8,
+    angle_delta: i8,
+    y_pred: Vec<u16>,
+    /// Chroma is predicted with DC.
+    u_pred: u16,
+    v_pred: u16,
+}
+
+/// The context `y_mode` is coded with in inter frames: the size group of
+/// a square `bl` block.
+#[cfg(feature = "inter")]
+fn y_mode_size_group(bl: usize) -> usize {
+    [3, 3, 3, 2, 1][bl]
+}
+
+#[cfg(feature = "inter")]
+/// RD costs of the two sides of an inter partition decision.
+#[derive(Debug, Clone, Copy)]
+struct SkipOrSplit {
+    skip: u64,
+    split: u64,
+    /// Full-pel motion the split side was costed with.
+    motion: Option<(i32, i32)>,
+    /// References the skip side predicts from.
+    reference: BlockRefs,
+}
+
+#[cfg(feature = "inter")]
+impl SkipOrSplit {
+    fn skip_uncosted(reference: BlockRefs) -> SkipOrSplit {
+        SkipOrSplit {
+            skip: 0,
+            split: u64::MAX,
+            motion: None,
+            reference,
+        }
+    }
+
+    fn skip_wins(self) -> bool {
+        self.skip <= self.split
+    }
+}
+
+/// Fastest speed setting; see [`EarlyExit::for_speed`].
+pub const MAX_SPEED: u8 = 2;
+
+/// Early-termination thresholds for the block-level RD searches.
+///
+/// Thresholds are relative to the pixel-domain quantizer step `ac_dq / 8`,
+/// in sixteenths: a prediction whose error is already below a fraction of
+/// the step gains little from the searches that follow it. Zero disables a
+/// rule, and [`EarlyExit::default`] (speed 0) disables them all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EarlyExit {
+    /// Keep intra DC without trying the other modes when its SATD per
+    /// sample is at most this many sixteenths of the step.
+    pub intra_dc_satd_q4: u32,
+    /// Intra candidates, in SATD order, given an exact RD cost; with one,
+    /// the SATD winner is taken as is.
+    pub intra_rd_candidates: usize,
+    /// Keep DCT_DCT for an intra 8x8 block without trying the other
+    /// transform types when its RD cost per sample is at most this many
+    /// sixteenths of the squared step.
+    pub intra_txtype_cost_q4: u64,
+    /// Keep an intra 16x16 to 64x64 block whole, without trial-coding its
+    /// split, when its MSE is at most this many sixteenths of the squared
+    /// step.
+    pub intra_none_mse_q4: u64,
+    /// Take the zero-motion skip block for an inter NONE partition, without
+    /// costing the split, when its MSE is at most this many sixteenths of
+    /// the squared step.
+    pub inter_none_mse_q4: u64,
+    /// Code an 8x8 inter block from zero motion, without a motion search,
+    /// when its luma MSE is at most this many sixteenths of the squared step.
+    pub inter_zero_mv_mse_q4: u64,
+    /// Stop testing the subblocks of an inter block for skip when its split
+    /// RD cost is at most this many sixteenths of its skip cost.
+    pub inter_split_margin_q4: u64,
+    /// Hook that reorders or vetoes intra mode candidates.
+    #[cfg(feature = "mode-hook")]
+    pub intra_mode_hook: Option<crate::mode_hook::IntraModeHookRef>,
+}
+
+impl Default for EarlyExit {
+    fn default() -> Self {
+        Self::for_speed(0)
+    }
+}
+
+impl EarlyExit {
+    /// Thresholds for `speed` in `0..=MAX_SPEED`; higher is faster. Speed 1
+    /// mostly stops where the remaining error is below the quantization
+    /// noise (`step^2 / 12`); speed 2 loosens every threshold and also trusts
+    /// the SATD ranking of intra modes.
+    pub fn for_speed(speed: u8) -> Self {
+        match speed {
+            0 => Self {
+                intra_dc_satd_q4: 0,
+                intra_rd_candidates: 2,
+                intra_txtype_cost_q4: 0,
+                intra_none_mse_q4: 0,
+                inter_none_mse_q4: 0,
+                inter_zero_mv_mse_q4: 0,
+                inter_split_margin_q4: 0,
+                #[cfg(feature = "mode-hook")]
+                intra_mode_hook: None,
+            },
+            1 => Self {
+                intra_dc_satd_q4: 4,
+                intra_rd_candidates: 2,
+                intra_txtype_cost_q4: 4,
+                intra_none_mse_q4: 1,
+                inter_none_mse_q4: 1,
+                inter_zero_mv_mse_q4: 1,
+                inter_split_margin_q4: 4,
+                #[cfg(feature = "mode-hook")]
+                intra_mode_hook: None,
+            },
+            _ => Self {
+                intra_dc_satd_q4: 8,
+                intra_rd_candidates: 1,
+                intra_txtype_cost_q4: 16,
+                intra_none_mse_q4: 2,
+                inter_none_mse_q4: 2,
+                inter_zero_mv_mse_q4: 2,
+                inter_split_margin_q4: 8,
+                #[cfg(feature = "mode-hook")]
+                intra_mode_hook: None,
+            },
+        }
+    }
+
+    fn step(ac_dq: u32) -> u64 {
+        (ac_dq as u64 / 8).max(1)
+    }
+
+    fn intra_dc_is_good_enough(&self, satd: u64, samples: u64, ac_dq: u32) -> bool {
+        self.intra_dc_satd_q4 > 0
+            && 16 * satd <= samples * Self::step(ac_dq) * self.intra_dc_satd_q4 as u64
+    }
+
+    fn intra_dct_is_good_enough(&self, cost: u64, samples: u64, ac_dq: u32) -> bool {
+        let step = Self::step(ac_dq);
+        self.intra_txtype_cost_q4 > 0
+            && 16 * cost <= samples * step * step * self.intra_txtype_cost_q4
+    }
+
+    fn intra_none_is_good_enough(&self, sse: u64, samples: u64, ac_dq: u32) -> bool {
+        let step = Self::step(ac_dq);
+        self.intra_none_mse_q4 > 0 && 16 * sse <= samples * step * step * self.intra_none_mse_q4
+    }
+
+    #[cfg(feature = "inter")]
+    fn inter_none_is_good_enough(&self, sse: u64, samples: u64, ac_dq: u32) -> bool {
+        let step = Self::step(ac_dq);
+        self.inter_none_mse_q4 > 0 && 16 * sse <= samples * step * step * self.inter_none_mse_q4
+    }
+
+    #[cfg(feature = "inter")]
+    fn split_is_settled(&self, costs: SkipOrSplit) -> bool {
+        self.inter_split_margin_q4 > 0
+            && costs.split != u64::MAX
+            && 16 * costs.split as u128 <= costs.skip as u128 * self.inter_split_margin_q4 as u128
+    }
+
+    #[cfg(feature = "inter")]
+    fn inter_zero_mv_is_good_enough(&self, sse: u64, samples: u64, ac_dq: u32) -> bool {
+        let step = Self::step(ac_dq);
+        self.inter_zero_mv_mse_q4 > 0
+            && 16 * sse <= samples * step * step * self.inter_zero_mv_mse_q4
+    }
+}
+
+/// `plane` through a 3x3 binomial blur (weights 1-2-1 in each direction),
+/// repeating the edge samples. Halves the amplitude of pixel-to-pixel noise
+/// while moving edges by less than a pixel, which is what a full-pel search
+/// needs to lock onto the structure rather than the grain.
+#[cfg(feature = "inter")]
+pub(crate) fn smooth_luma(plane: &[u16], width: u32, height: u32) -> Vec<u16> {
+    let (w, h) = (width as usize, height as usize);
+    let mut rows = vec![0u32; w * h];
+    for y in 0..h {
+        let row = &plane[y * w..(y + 1) * w];
+        for x in 0..w {
+            let left = row[x.saturating_sub(1)] as u32;
+            let right = row[(x + 1).min(w - 1)] as u32;
+            rows[y * w + x] = left + 2 * row[x] as u32 + right;
+        }
+    }
+    let mut out = vec![0u16; w * h];
+    for y in 0..h {
+        let (above, below) = (y.saturating_sub(1) * w, (y + 1).min(h - 1) * w);
+        for x in 0..w {
+            let sum = rows[above + x] + 2 * rows[y * w + x] + rows[below + x];
+            out[y * w + x] = ((sum + 8) >> 4) as u16;
+        }
+    }
+    out
+}
+
+/// SSE over the top-left `rows` x `cols` samples and estimated coefficient
+/// rate of coding the residual of an 8x8 `prediction` of `source` as one
+/// DCT_DCT transform block.
+#[cfg(feature = "inter")]
+fn coded_8x8_cost(
+    source: &[u16],
+    prediction: &[u16],
+    dq: &DequantValues,
+    bit_depth: crate::BitDepth,
+    rows: usize,
+    cols: usize,
+) -> (u64, u64) {
+    // eob position and tx type signaling for a block with coefficients.
+    const TX_BLOCK_COST: u64 = 6 << crate::rdo::COST_SHIFT;
+
+    let mut residual = [0i32; 64];
+    for i in 0..64 {
+        residual[i] = source[i] as i32 - prediction[i] as i32;
+    }
+    let coeffs = dct::forward_transform_8x8(&residual, dct::TxType::DctDct);
+    let quant = quantize_coeffs(&coeffs, 64, dq.dc, dq.ac);
+    let mut rate = 0;
+    if quant.iter().any(|&q| q != 0) {
+        rate += TX_BLOCK_COST;
+        rate += quant
+            .iter()
+            .map(|&q| crate::rdo::estimate_coeff_cost(q.unsigned_abs()) as u64)
+            .sum::<u64>();
+    }
+    let deq = dequantize_coeffs(&quant, 64, dq.dc, dq.ac);
+    let mut deq_arr = [0i32; 64];
+    deq_arr.copy_from_slice(&deq);
+    let recon_residual = dct::inverse_transform_8x8(&deq_arr, dct::TxType::DctDct, bit_depth);
+
+    let mut sse = 0u64;
+    for r in 0..rows {
+        for c in 0..cols {
+            let i = r * 8 + c;
+            let recon =
+                (prediction[i] as i32 + recon_residual[i]).clamp(0, bit_depth.max_value() as i32);
+            let d = (source[i] as i32 - recon) as i64;
+            sse += (d * d) as u64;
+        }
+    }
+    (sse, rate)
+}
+
+/// Largest power of two no greater than half the search radius, so the
+/// first refinement round can reach the edge of the window.
+fn initial_search_step(search_range: i32) -> i32 {
+    let half = (search_range / 2).max(1) as u32;
+    1 << (31 - half.leading_zeros())
+}
+
 #[allow(clippy::too_many_arguments)]
-fn motion_search_block(
+pub(crate) fn motion_search_block(
     source: &[u16],
     reference: &[u16],
     width: u32,
@@ -3850,13 +6265,14 @@ fn motion_search_block(
     px_x: u32,
     px_y: u32,
     block_size: u32,
-    start_dx: i32,
-    start_dy: i32,
+    seeds: &[(i32, i32)],
+    search_range: i32,
 ) -> (i32, i32) {
     if px_x + block_size > width || px_y + block_size > height {
         return (0, 0);
     }
 
+    let sad = cpu::kernels().sad;
     let eval = |dx: i32, dy: i32| -> u32 {
         let ref_x = px_x as i32 + dx;
         let ref_y = px_y as i32 + dy;
@@ -3869,17 +6285,16 @@ fn motion_search_block(
             return u32::MAX;
         }
 
-        let mut sad: u32 = 0;
-        for row in 0..block_size {
-            let src_off = ((px_y + row) * width + px_x) as usize;
-            let ref_off = ((ref_y as u32 + row) * width + ref_x as u32) as usize;
-            for col in 0..block_size as usize {
-                let s = source[src_off + col] as i32;
-                let r = reference[ref_off + col] as i32;
-                sad += (s - r).unsigned_abs();
-            }
-        }
-        sad
+        let src_off = (px_y * width + px_x) as usize;
+        let ref_off = (ref_y as u32 * width + ref_x as u32) as usize;
+        sad(
+            &source[src_off..],
+            width as usize,
+            &reference[ref_off..],
+            width as usize,
+            block_size as usize,
+            block_size as usize,
+        )
     };
 
     let do_search = |mut b_dx: i32, mut b_dy: i32| -> (i32, i32, u32, i32) {
@@ -3891,7 +6306,7 @@ fn motion_search_block(
         let search_center_dx = b_dx;
         let search_center_dy = b_dy;
 
-        let mut step = 16i32;
+        let mut step = initial_search_step(search_range);
         while step >= 1 {
             let mut found_better = false;
 
@@ -3912,10 +6327,10 @@ fn motion_search_block(
             let mut c_cost = b_cost;
 
             for &(dx, dy) in &points {
-                if dx < search_center_dx - 32
-                    || dx > search_center_dx + 32
-                    || dy < search_center_dy - 32
-                    || dy > search_center_dy + 32
+                if dx < search_center_dx - search_range
+                    || dx > search_center_dx + search_range
+                    || dy < search_center_dy - search_range
+                    || dy > search_center_dy + search_range
                 {
                     continue;
                 }
@@ -3946,44 +6361,358 @@ fn motion_search_block(
         (b_dx, b_dy, b_sad, b_cost)
     };
 
-    let (dx1, dy1, sad1, cost1) = do_search(0, 0);
+    // Each seed gets its own window, so motion beyond `search_range` from
+    // zero is still found when a seed already points near it.
+    let (mut best_dx, mut best_dy, mut best_sad, mut best_cost) = do_search(0, 0);
+    for (i, &(seed_dx, seed_dy)) in seeds.iter().enumerate() {
+        if (seed_dx, seed_dy) == (0, 0) || seeds[..i].contains(&(seed_dx, seed_dy)) {
+            continue;
+        }
+        let (dx, dy, sad, cost) = do_search(seed_dx, seed_dy);
+        if sad < best_sad || (sad == best_sad && cost < best_cost) {
+            (best_dx, best_dy, best_sad, best_cost) = (dx, dy, sad, cost);
+        }
+    }
+    (best_dx, best_dy)
+}
+
+/// Motion (1/8 pel) an inter frame's block searches found, per 4x4 unit,
+/// kept to seed the motion search of the next inter frame. Most blocks are
+/// coded with zero motion or a predicted vector, so the searched motion says
+/// more about the content than the coded vectors do. Units no search
+/// covered hold `None`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MvField {
+    mi_cols: u32,
+    mi_rows: u32,
+    mvs: Vec<Option<(i32, i32)>>,
+}
+
+impl MvField {
+    pub fn new(mi_cols: u32, mi_rows: u32) -> Self {
+        Self {
+            mi_cols,
+            mi_rows,
+            mvs: vec![None; (mi_cols * mi_rows) as usize],
+        }
+    }
+
+    #[cfg(feature = "inter")]
+    /// Sets every unit of the `bl` block at `(bx, by)` that lies inside the
+    /// field.
+    fn fill(&mut self, bx: u32, by: u32, bl: usize, mv: (i32, i32)) {
+        let side = 2u32 << (4 - bl);
+        for row in by..by.saturating_add(side).min(self.mi_rows) {
+            for col in bx..bx.saturating_add(side).min(self.mi_cols) {
+                self.mvs[(row * self.mi_cols + col) as usize] = Some(mv);
+            }
+        }
+    }
 
-    if start_dx == 0 && start_dy == 0 {
-        return (dx1, dy1);
+    pub fn mi_cols(&self) -> u32 {
+        self.mi_cols
     }
 
-    let (dx2, dy2, sad2, cost2) = do_search(start_dx, start_dy);
+    pub fn mi_rows(&self) -> u32 {
+        self.mi_rows
+    }
 
-    if sad2 < sad1 || (sad2 == sad1 && cost2 < cost1) {
-        (dx2, dy2)
-    } else {
-        (dx1, dy1)
+    /// The vector of the 4x4 unit at `(bx, by)`, if it is inside the field
+    /// and was inter coded.
+    pub fn get(&self, bx: u32, by: u32) -> Option<(i32, i32)> {
+        if bx >= self.mi_cols || by >= self.mi_rows {
+            return None;
+        }
+        self.mvs[(by * self.mi_cols + bx) as usize]
+    }
+
+    /// The field rescaled from a reference `from_distance` frames away to
+    /// one `to_distance` away (signed display-order distances), as a
+    /// prediction of the motion of another frame.
+    pub fn rescaled(&self, to_distance: i64, from_distance: i64) -> Self {
+        let scale = |v: i32| -> i32 {
+            if from_distance == 0 {
+                return 0;
+            }
+            (v as i64 * to_distance / from_distance).clamp(i32::MIN as i64, i32::MAX as i64) as i32
+        };
+        Self {
+            mi_cols: self.mi_cols,
+            mi_rows: self.mi_rows,
+            mvs: self
+                .mvs
+                .iter()
+                .map(|mv| mv.map(|(x, y)| (scale(x), scale(y))))
+                .collect(),
+        }
+    }
+
+    #[cfg(feature = "inter")]
+    fn crop(&self, rect: &TileRect) -> Self {
+        let col0 = rect.sb_col_start * 16;
+        let row0 = rect.sb_row_start * 16;
+        let cols = (rect.sb_col_end * 16)
+            .min(self.mi_cols)
+            .saturating_sub(col0);
+        let rows = (rect.sb_row_end * 16)
+            .min(self.mi_rows)
+            .saturating_sub(row0);
+        let mut tile = Self::new(cols, rows);
+        for row in 0..rows {
+            for col in 0..cols {
+                tile.mvs[(row * cols + col) as usize] = self.get(col0 + col, row0 + row);
+            }
+        }
+        tile
+    }
+
+    #[cfg(feature = "inter")]
+    fn paste(&mut self, tile: &MvField, rect: &TileRect) {
+        let col0 = rect.sb_col_start * 16;
+        let row0 = rect.sb_row_start * 16;
+        for row in 0..tile.mi_rows.min(self.mi_rows.saturating_sub(row0)) {
+            for col in 0..tile.mi_cols.min(self.mi_cols.saturating_sub(col0)) {
+                self.mvs[((row0 + row) * self.mi_cols + col0 + col) as usize] = tile.get(col, row);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "inter")]
+/// Nearest full-pel offset to a 1/8 pel vector.
+fn full_pel(mv: (i32, i32)) -> (i32, i32) {
+    ((mv.0 + 4) >> 3, (mv.1 + 4) >> 3)
+}
+
+/// Motion of a coded inter frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameMotion {
+    /// What the block searches found; seeds the next frame's search.
+    pub estimates: MvField,
+    /// The vectors the blocks were coded with, relative to the frame's
+    /// LAST reference. Intra units hold `None`.
+    pub coded: MvField,
+}
+
+/// The motion of one inter frame, from `Encoder::receive_motion_field`.
+/// Vectors are 1/8-pel `(x, y)` per 4x4 luma unit. Blocks next to inter
+/// blocks mostly code zero motion, so as an optical-flow estimate
+/// `estimates` is the better field; `coded` is what a decoder sees.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MotionField {
+    /// Display index of the frame.
+    pub frame_number: u64,
+    /// Display index of the frame the vectors point into.
+    pub reference_frame_number: u64,
+    pub coded: MvField,
+    /// Search results; `None` where no block search ran.
+    pub estimates: MvField,
+}
+
+/// Reference frames, numbered like AV1's names less one.
+#[cfg(feature = "inter")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RefFrame {
+    Last = 0,
+    Last2 = 1,
+    Golden = 3,
+    Altref = 6,
+}
+
+#[cfg(feature = "inter")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BlockRefs {
+    Single(RefFrame),
+    Compound(RefFrame, RefFrame),
+}
+
+#[cfg(feature = "inter")]
+impl BlockRefs {
+    fn first(self) -> RefFrame {
+        match self {
+            BlockRefs::Single(r) | BlockRefs::Compound(r, _) => r,
+        }
+    }
+
+    fn pair(self) -> [i8; 2] {
+        match self {
+            BlockRefs::Single(r) => [r as i8, -1],
+            BlockRefs::Compound(a, b) => [a as i8, b as i8],
+        }
     }
 }
 
+#[cfg(feature = "inter")]
 #[derive(Clone, Copy)]
 struct BlockMv {
     mv_x: i32,
     mv_y: i32,
     ref_frame: i8,
+    second_ref: i8,
+    newmv: bool,
+    bw4: u8,
+    bh4: u8,
 }
 
+#[cfg(feature = "inter")]
 impl Default for BlockMv {
     fn default() -> Self {
         Self {
             mv_x: 0,
             mv_y: 0,
             ref_frame: -1,
+            second_ref: -1,
+            newmv: false,
+            bw4: 2,
+            bh4: 2,
+        }
+    }
+}
+
+#[cfg(feature = "inter")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RefCdf {
+    CompMode,
+    CompRefType,
+    SingleRef(usize),
+    CompRef(usize),
+    CompBwdref(usize),
+}
+
+#[cfg(feature = "inter")]
+impl RefCdf {
+    fn select(self, cdf: &CdfContext, ctx: usize) -> &[u16; 4] {
+        match self {
+            RefCdf::CompMode => &cdf.comp_mode[ctx],
+            RefCdf::CompRefType => &cdf.comp_ref_type[ctx],
+            RefCdf::SingleRef(bit) => &cdf.single_ref[ctx][bit],
+            RefCdf::CompRef(bit) => &cdf.comp_ref[ctx][bit],
+            RefCdf::CompBwdref(bit) => &cdf.comp_bwdref[ctx][bit],
+        }
+    }
+
+    fn select_mut(self, cdf: &mut CdfContext, ctx: usize) -> &mut [u16; 4] {
+        match self {
+            RefCdf::CompMode => &mut cdf.comp_mode[ctx],
+            RefCdf::CompRefType => &mut cdf.comp_ref_type[ctx],
+            RefCdf::SingleRef(bit) => &mut cdf.single_ref[ctx][bit],
+            RefCdf::CompRef(bit) => &mut cdf.comp_ref[ctx][bit],
+            RefCdf::CompBwdref(bit) => &mut cdf.comp_bwdref[ctx][bit],
+        }
+    }
+}
+
+#[cfg(feature = "inter")]
+impl BlockMv {
+    fn predicts_from(&self, refs: BlockRefs) -> bool {
+        match refs {
+            BlockRefs::Single(r) => self.ref_frame == r as i8 || self.second_ref == r as i8,
+            pair => [self.ref_frame, self.second_ref] == pair.pair(),
+        }
+    }
+}
+
+#[cfg(feature = "inter")]
+fn block_dims(bl: usize, partition: u32) -> (u32, u32) {
+    let side = 32u32 >> bl;
+    match partition {
+        PARTITION_HORZ => (side, side / 2),
+        PARTITION_VERT => (side / 2, side),
+        _ => (side, side),
+    }
+}
+
+/// The spatial reference MV scan of dav1d `refmvs_find`, noting only matches.
+#[cfg(feature = "inter")]
+struct RefMvScan<'a> {
+    block_mvs: &'a [BlockMv],
+    mi_cols: u32,
+    refs: BlockRefs,
+    have_newmv: bool,
+}
+
+#[cfg(feature = "inter")]
+impl RefMvScan<'_> {
+    fn block(&self, col: u32, row: u32) -> BlockMv {
+        self.block_mvs[(row * self.mi_cols + col) as usize]
+    }
+
+    fn visit(&mut self, col: u32, row: u32, nearest: bool) -> bool {
+        let block = self.block(col, row);
+        let matched = block.predicts_from(self.refs);
+        if matched && nearest {
+            self.have_newmv |= block.newmv;
+        }
+        matched
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn scan_row(
+        &mut self,
+        row: u32,
+        col: u32,
+        bw4: u32,
+        w4: u32,
+        max_rows: u32,
+        step: u32,
+        nearest: bool,
+    ) -> (u32, bool) {
+        let first = self.block(col, row);
+        if bw4 <= first.bw4 as u32 {
+            let weight = (2 * max_rows).min(first.bh4 as u32).max(2);
+            return (weight >> 1, self.visit(col, row, nearest));
+        }
+        let mut matched = false;
+        let mut len = step.max(first.bw4 as u32);
+        let mut x = 0;
+        loop {
+            matched |= self.visit(col + x, row, nearest);
+            x += len;
+            if x >= w4 {
+                return (1, matched);
+            }
+            len = step.max(self.block(col + x, row).bw4 as u32);
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn scan_col(
+        &mut self,
+        row: u32,
+        col: u32,
+        bh4: u32,
+        h4: u32,
+        max_cols: u32,
+        step: u32,
+        nearest: bool,
+    ) -> (u32, bool) {
+        let first = self.block(col, row);
+        if bh4 <= first.bh4 as u32 {
+            let weight = (2 * max_cols).min(first.bw4 as u32).max(2);
+            return (weight >> 1, self.visit(col, row, nearest));
+        }
+        let mut matched = false;
+        let mut len = step.max(first.bh4 as u32);
+        let mut y = 0;
+        loop {
+            matched |= self.visit(col, row + y, nearest);
+            y += len;
+            if y >= h4 {
+                return (1, matched);
+            }
+            len = step.max(self.block(col, row + y).bh4 as u32);
         }
     }
 }
 
+#[cfg(feature = "inter")]
 struct MvCandidate {
     mv_x: i32,
     mv_y: i32,
     weight: u32,
 }
 
+#[cfg(feature = "inter")]
 fn add_candidate(candidates: &mut Vec<MvCandidate>, mv_x: i32, mv_y: i32, weight: u32) {
     for c in candidates.iter_mut() {
         if c.mv_x == mv_x && c.mv_y == mv_y {
@@ -3994,13 +6723,16 @@ fn add_candidate(candidates: &mut Vec<MvCandidate>, mv_x: i32, mv_y: i32, weight
     candidates.push(MvCandidate { mv_x, mv_y, weight });
 }
 
+#[cfg(feature = "inter")]
 fn predict_mv(
     block_mvs: &[BlockMv],
     mi_cols: u32,
     mi_rows: u32,
     bx4: u32,
     by4: u32,
+    ref_frame: RefFrame,
 ) -> (i32, i32, Vec<MvCandidate>) {
+    let refs = BlockRefs::Single(ref_frame);
     let mut candidates: Vec<MvCandidate> = Vec::new();
 
     if by4 > 0 {
@@ -4008,7 +6740,7 @@ fn predict_mv(
             let idx = ((by4 - 1) * mi_cols + col) as usize;
             if idx < block_mvs.len() {
                 let b = &block_mvs[idx];
-                if b.ref_frame == 0 {
+                if b.predicts_from(refs) {
                     add_candidate(&mut candidates, b.mv_x, b.mv_y, 2);
                 }
             }
@@ -4020,7 +6752,7 @@ fn predict_mv(
             let idx = (row * mi_cols + bx4 - 1) as usize;
             if idx < block_mvs.len() {
                 let b = &block_mvs[idx];
-                if b.ref_frame == 0 {
+                if b.predicts_from(refs) {
                     add_candidate(&mut candidates, b.mv_x, b.mv_y, 2);
                 }
             }
@@ -4031,7 +6763,7 @@ fn predict_mv(
         let idx = ((by4 - 1) * mi_cols + bx4 + 2) as usize;
         if idx < block_mvs.len() {
             let b = &block_mvs[idx];
-            if b.ref_frame == 0 {
+            if b.predicts_from(refs) {
                 add_candidate(&mut candidates, b.mv_x, b.mv_y, 2);
             }
         }
@@ -4045,11 +6777,12 @@ fn predict_mv(
         c.weight += 640;
     }
 
-    candidates.sort_by(|a, b| b.weight.cmp(&a.weight));
+    candidates.sort_by_key(|c| std::cmp::Reverse(c.weight));
 
     (candidates[0].mv_x, candidates[0].mv_y, candidates)
 }
 
+#[cfg(feature = "inter")]
 fn get_drl_context(candidates: &[MvCandidate], ref_idx: usize) -> usize {
     if candidates.len() <= ref_idx + 1 {
         return 2;
@@ -4212,8 +6945,8 @@ mod tests {
             h,
             dq_dc,
             dq_ac,
-            128,
-            255,
+            crate::BitDepth::Eight,
+            &EarlyExit::default(),
         )
     }
 
@@ -4230,7 +6963,7 @@ mod tests {
             dq_dc,
             dq_ac,
             tx_type,
-            255,
+            crate::BitDepth::Eight,
         )
     }
 
@@ -4254,8 +6987,8 @@ mod tests {
             bx,
             by,
             bsize,
-            pred_x,
-            pred_y,
+            &[(pred_x, pred_y)],
+            DEFAULT_MV_SEARCH_RANGE as i32,
         )
     }
 
@@ -4326,6 +7059,232 @@ mod tests {
         assert_eq!(ctx_at_bl1, 3);
     }
 
+    #[test]
+    fn rectangular_partitions_halve_one_context_dimension() {
+        let mut ctx = TileContext::new(32, 128);
+        ctx.update_partition_ctx_for(0, 0, 2, PARTITION_HORZ, 32, 32);
+        assert_eq!(ctx.above_partition[0], PARTITION_CTX_NONE[2]);
+        assert_eq!(ctx.left_partition[0], PARTITION_CTX_NONE[3]);
+
+        ctx.update_partition_ctx_for(8, 0, 2, PARTITION_VERT, 32, 32);
+        assert_eq!(ctx.above_partition[4], PARTITION_CTX_NONE[3]);
+        assert_eq!(ctx.left_partition[0], PARTITION_CTX_NONE[2]);
+        // A 16-wide block above is narrower than 32: the 32x32 above context
+        // bit is set, the 64x64 one is not.
+        assert_eq!(ctx.partition_ctx(8, 8, 2) & 1, 1);
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn static_block_on_right_frame_edge_is_coded_as_vertical_half() {
+        let frame = FramePixels::solid(80, 48, 100, 120, 140);
+        let dq = crate::dequant::lookup_dequant(128, crate::BitDepth::Eight);
+        let mut tile = InterTileEncoder::new(&frame, &frame, None, dq, 128, (0, 0));
+        tile.ctx.reset_left_for_sb_row();
+        tile.cdef_pending = true;
+        // The 64x64 superblock at x=64 has 16 columns in frame, so its upper
+        // 32x32 block is coded as the left 16x32 half.
+        tile.encode_inter_partition(1, 16, 0, true);
+        assert_eq!(tile.ctx.left_partition[0], PARTITION_CTX_NONE[2]);
+        assert_eq!(tile.ctx.above_partition[8], PARTITION_CTX_NONE[3]);
+        for row in tile.recon.y.chunks(80) {
+            assert!(row[64..].iter().all(|&y| y == 100));
+        }
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn blocks_of_a_new_scene_fall_back_to_intra() {
+        let mut reference = FramePixels::solid(64, 64, 128, 128, 128);
+        for (i, y) in reference.y.iter_mut().enumerate() {
+            *y = ((i % 64) * 3 + (i / 64) % 7 * 11) as u16;
+        }
+        // The right half cuts to a vertical gradient the reference lacks.
+        let mut source = reference.clone();
+        for (i, y) in source.y.iter_mut().enumerate() {
+            if i % 64 >= 32 {
+                *y = (40 + i / 64 * 2) as u16;
+            }
+        }
+        let dq = crate::dequant::lookup_dequant(128, crate::BitDepth::Eight);
+        let mut tile = InterTileEncoder::new(&source, &reference, None, dq, 128, (0, 0));
+        tile.ctx.reset_left_for_sb_row();
+        tile.cdef_pending = true;
+        tile.encode_inter_partition(1, 0, 0, true);
+
+        let intra =
+            |bx: u32, by: u32| tile.block_mvs[(by * tile.mi_cols + bx) as usize].ref_frame < 0;
+        for by in (0..16).step_by(2) {
+            assert!((0..8).step_by(2).all(|bx| !intra(bx, by)), "row {by}");
+            assert!((8..16).step_by(2).all(|bx| intra(bx, by)), "row {by}");
+        }
+        let sse: u64 = source
+            .y
+            .iter()
+            .zip(&tile.recon.y)
+            .map(|(&s, &r)| (s as i64 - r as i64).pow(2) as u64)
+            .sum();
+        assert!(sse < 64 * 64 * 4, "mse {}", sse / (64 * 64));
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn blocks_predict_from_the_alternate_reference_that_matches() {
+        let mut source = FramePixels::solid(64, 64, 128, 128, 128);
+        for (i, y) in source.y.iter_mut().enumerate() {
+            *y = ((i % 64) * 3 + (i / 64) % 7 * 11) as u16;
+        }
+        let last = FramePixels::solid(64, 64, 20, 60, 200);
+        let dq = crate::dequant::lookup_dequant(128, crate::BitDepth::Eight);
+        let encode = |alternates: &[(RefFrame, &FramePixels)]| {
+            encode_inter_tile_with_recon_with_global_mv(
+                &source,
+                &last,
+                alternates,
+                None,
+                dq,
+                128,
+                (0, 0),
+                MotionParams::default(),
+                EarlyExit::default(),
+                None,
+                CdefSignal::default(),
+                RestorationSignal::default(),
+                SbDeltaQ::default(),
+                None,
+            )
+        };
+        let (last_only, _, _, _) = encode(&[]);
+        let (bytes, recon, _, motion) = encode(&[(RefFrame::Golden, &source)]);
+        assert_eq!(recon.y, source.y);
+        assert!(bytes.len() * 4 < last_only.len());
+        assert!(motion.coded.get(0, 0).is_none());
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn b_frame_blocks_average_last_and_altref() {
+        let mut source = FramePixels::solid(64, 64, 128, 128, 128);
+        for (i, y) in source.y.iter_mut().enumerate() {
+            *y = (20 + (i % 64) * 2 + (i / 64) % 7 * 11) as u16;
+        }
+        let mut last = source.clone();
+        let mut altref = source.clone();
+        for ((l, a), s) in last.y.iter_mut().zip(&mut altref.y).zip(&source.y) {
+            (*l, *a) = (s - 6, s + 6);
+        }
+        let dq = crate::dequant::lookup_dequant(128, crate::BitDepth::Eight);
+        let encode = |forward_reference| {
+            encode_inter_tile_with_recon_with_global_mv(
+                &source,
+                &last,
+                &[],
+                forward_reference,
+                dq,
+                128,
+                (0, 0),
+                MotionParams::default(),
+                EarlyExit::default(),
+                None,
+                CdefSignal::default(),
+                RestorationSignal::default(),
+                SbDeltaQ::default(),
+                None,
+            )
+        };
+        let (last_only, _, _, _) = encode(None);
+        let (bytes, recon, _, motion) = encode(Some(&altref));
+        assert_eq!(average_frames(&last, &altref).y, source.y);
+        assert_eq!(recon.y, source.y);
+        assert!(bytes.len() * 4 < last_only.len());
+        assert_eq!(motion.coded.get(0, 0), Some((0, 0)));
+    }
+
+    fn encode_intra_superblock(frame: &FramePixels, q_idx: u8) -> TileEncoder<'_> {
+        let dq = crate::dequant::lookup_dequant(q_idx, crate::BitDepth::Eight);
+        let mut tile = TileEncoder::new(frame, dq, q_idx);
+        tile.ctx.reset_left_for_sb_row();
+        tile.cdef_pending = true;
+        tile.start_superblock(0, 0);
+        tile.encode_partition(1, 0, 0);
+        tile
+    }
+
+    #[test]
+    fn flat_superblock_is_coded_as_one_64x64_block() {
+        let frame = FramePixels::solid(64, 64, 100, 120, 140);
+        let tile = encode_intra_superblock(&frame, 128);
+        assert_eq!(tile.ctx.above_partition[0], PARTITION_CTX_NONE[1]);
+        assert_eq!(tile.ctx.left_partition[7], PARTITION_CTX_NONE[1]);
+        assert!(tile.recon.y.iter().all(|&y| y.abs_diff(100) <= 1));
+        assert!(tile.recon.v.iter().all(|&v| v.abs_diff(140) <= 1));
+    }
+
+    #[test]
+    fn partition_search_splits_detail_and_keeps_smooth_areas_whole() {
+        let mut frame = FramePixels::solid(64, 64, 0, 128, 128);
+        for (i, s) in frame.y.iter_mut().enumerate() {
+            let (x, y) = (i % 64, i / 64);
+            *s = if x < 32 {
+                (60 + x + y) as u16
+            } else {
+                ((x * 7 + y * 3 + (x * y) % 23) % 220 + 16) as u16
+            };
+        }
+        let tile = encode_intra_superblock(&frame, 60);
+        assert_eq!(tile.ctx.above_partition[0], PARTITION_CTX_NONE[2]);
+        assert!(
+            tile.ctx.above_partition[4..8]
+                .iter()
+                .any(|&p| p == PARTITION_CTX_NONE[4])
+        );
+    }
+
+    #[test]
+    fn whole_block_transforms_reconstruct_smooth_content() {
+        let mut frame = FramePixels::solid(64, 64, 0, 90, 160);
+        for (i, s) in frame.y.iter_mut().enumerate() {
+            let (x, y) = (i % 64, i / 64);
+            *s = (40 + x + y + (x * y) / 64) as u16;
+        }
+        for bl in 1..=3 {
+            let dq = crate::dequant::lookup_dequant(20, crate::BitDepth::Eight);
+            let mut tile = TileEncoder::new(&frame, dq, 20);
+            tile.ctx.reset_left_for_sb_row();
+            tile.cdef_pending = true;
+            tile.encode_block_square(0, 0, bl);
+            let n = 128 >> bl;
+            for r in 0..n {
+                for c in 0..n {
+                    let i = r * 64 + c;
+                    assert!(
+                        tile.recon.y[i].abs_diff(frame.y[i]) <= 3,
+                        "{n}x{n} at ({c}, {r}): {} vs {}",
+                        tile.recon.y[i],
+                        frame.y[i]
+                    );
+                }
+            }
+            assert!(tile.recon.u[..n / 2].iter().all(|&u| u == 90));
+        }
+    }
+
+    #[test]
+    fn rejected_partition_trial_leaves_no_trace() {
+        let frame = FramePixels::solid(64, 64, 100, 120, 140);
+        let dq = crate::dequant::lookup_dequant(128, crate::BitDepth::Eight);
+        let mut tile = TileEncoder::new(&frame, dq, 128);
+        tile.ctx.reset_left_for_sb_row();
+        tile.cdef_pending = true;
+        let start = tile.trial_start(0, 0, 1);
+        tile.encode_partition_split(1, 0, 0);
+        tile.trial_rollback(&start);
+        tile.encode_partition_none(1, 0, 0);
+        let direct = encode_intra_superblock(&frame, 128);
+        assert_eq!(tile.enc.finalize(), direct.enc.finalize());
+        assert_eq!(tile.recon.y, direct.recon.y);
+    }
+
     #[test]
     fn skip_ctx_updates() {
         let mut ctx = TileContext::new(32, 128);
@@ -4415,6 +7374,7 @@ mod tests {
         assert!(!bytes.is_empty());
     }
 
+    #[cfg(feature = "inter")]
     #[allow(clippy::too_many_arguments)]
     fn encode_inter_tile_solid(
         width: u32,
@@ -4431,30 +7391,35 @@ mod tests {
         encode_inter_tile(&pixels, &reference)
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn inter_tile_64x64_produces_bytes() {
         let bytes = encode_inter_tile_solid(64, 64, 128, 128, 128, 128, 128, 128);
         assert!(!bytes.is_empty());
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn inter_tile_128x128_produces_bytes() {
         let bytes = encode_inter_tile_solid(128, 128, 128, 128, 128, 128, 128, 128);
         assert!(!bytes.is_empty());
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn inter_tile_100x100_produces_bytes() {
         let bytes = encode_inter_tile_solid(100, 100, 64, 128, 128, 128, 128, 128);
         assert!(!bytes.is_empty());
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn inter_tile_320x240_produces_bytes() {
         let bytes = encode_inter_tile_solid(320, 240, 0, 128, 128, 128, 128, 128);
         assert!(!bytes.is_empty());
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn inter_tile_same_as_reference_is_small() {
         let same = encode_inter_tile_solid(64, 64, 128, 128, 128, 128, 128, 128);
@@ -4462,6 +7427,7 @@ mod tests {
         assert!(diff.len() > same.len());
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn inter_tile_differs_from_intra_tile() {
         let intra = encode_tile_solid(64, 64, 128, 128, 128);
@@ -4469,43 +7435,50 @@ mod tests {
         assert_ne!(intra, inter);
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn inter_tile_different_reference_produces_different_output() {
-        let a = encode_inter_tile_solid(64, 64, 128, 128, 128, 0, 0, 0);
+        let a = encode_inter_tile_solid(64, 64, 128, 128, 128, 128, 128, 128);
         let b = encode_inter_tile_solid(64, 64, 128, 128, 128, 255, 255, 255);
         assert_ne!(a, b);
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn inter_tile_8x8_produces_bytes() {
         let bytes = encode_inter_tile_solid(8, 8, 128, 128, 128, 100, 100, 100);
         assert!(!bytes.is_empty());
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn inter_tile_odd_dimensions() {
         let bytes = encode_inter_tile_solid(17, 33, 100, 128, 128, 50, 128, 128);
         assert!(!bytes.is_empty());
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn inter_tile_wide_frame() {
         let bytes = encode_inter_tile_solid(256, 64, 128, 128, 128, 128, 128, 128);
         assert!(!bytes.is_empty());
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn inter_tile_tall_frame() {
         let bytes = encode_inter_tile_solid(64, 256, 128, 128, 128, 128, 128, 128);
         assert!(!bytes.is_empty());
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn is_inter_ctx_no_neighbors() {
         let ctx = TileContext::new(32, 128);
         assert_eq!(ctx.is_inter_ctx(0, 0), 0);
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn is_inter_ctx_both_intra_neighbors() {
         let mut ctx = TileContext::new(32, 128);
@@ -4514,6 +7487,7 @@ mod tests {
         assert_eq!(ctx.is_inter_ctx(2, 2), 3);
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn is_inter_ctx_both_inter_neighbors() {
         let mut ctx = TileContext::new(32, 128);
@@ -4522,6 +7496,7 @@ mod tests {
         assert_eq!(ctx.is_inter_ctx(2, 2), 0);
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn is_inter_ctx_one_intra_neighbor() {
         let mut ctx = TileContext::new(32, 128);
@@ -4530,12 +7505,14 @@ mod tests {
         assert_eq!(ctx.is_inter_ctx(2, 2), 1);
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn is_inter_ctx_top_only_inter() {
         let ctx = TileContext::new(32, 128);
         assert_eq!(ctx.is_inter_ctx(0, 2), 0);
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn is_inter_ctx_top_only_intra() {
         let mut ctx = TileContext::new(32, 128);
@@ -4543,63 +7520,230 @@ mod tests {
         assert_eq!(ctx.is_inter_ctx(0, 2), 2);
     }
 
+    #[cfg(feature = "inter")]
+    fn place_block(
+        tile: &mut InterTileEncoder,
+        (bx, by, w4, h4): (u32, u32, u32, u32),
+        ref_frame: RefFrame,
+        newmv: bool,
+    ) {
+        for row in by..by + h4 {
+            for col in bx..bx + w4 {
+                tile.block_mvs[(row * tile.mi_cols + col) as usize] = BlockMv {
+                    ref_frame: ref_frame as i8,
+                    newmv,
+                    bw4: w4 as u8,
+                    bh4: h4 as u8,
+                    ..Default::default()
+                };
+            }
+        }
+    }
+
+    #[cfg(feature = "inter")]
+    fn newmv_ctx_of_16x16(
+        tile: &InterTileEncoder,
+        (bx, by): (u32, u32),
+        ref_frame: RefFrame,
+        has_top_right: bool,
+    ) -> usize {
+        tile.newmv_ctx(bx, by, 3, PARTITION_NONE, ref_frame, has_top_right)
+    }
+
+    #[cfg(feature = "inter")]
+    fn newmv_ctx_tile(frame: &FramePixels) -> InterTileEncoder<'_> {
+        let dq = crate::dequant::lookup_dequant(128, crate::BitDepth::Eight);
+        InterTileEncoder::new(frame, frame, None, dq, 128, (0, 0))
+    }
+
+    #[cfg(feature = "inter")]
     #[test]
     fn newmv_ctx_no_neighbors() {
-        let ctx = TileContext::new(32, 128);
-        assert_eq!(ctx.newmv_ctx(0, 0), 0);
+        let frame = FramePixels::solid(128, 128, 128, 128, 128);
+        let tile = newmv_ctx_tile(&frame);
+        assert_eq!(newmv_ctx_of_16x16(&tile, (0, 0), RefFrame::Last, true), 0);
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn newmv_ctx_left_only() {
-        let mut ctx = TileContext::new(32, 128);
-        ctx.above_intra[16] = false;
-        ctx.left_intra[0] = false;
-        assert_eq!(ctx.newmv_ctx(16, 0), 3);
+        let frame = FramePixels::solid(128, 128, 128, 128, 128);
+        let mut tile = newmv_ctx_tile(&frame);
+        place_block(&mut tile, (12, 0, 4, 4), RefFrame::Last, false);
+        assert_eq!(newmv_ctx_of_16x16(&tile, (16, 0), RefFrame::Last, true), 3);
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn newmv_ctx_top_only() {
-        let ctx = TileContext::new(32, 128);
-        assert_eq!(ctx.newmv_ctx(0, 16), 3);
+        let frame = FramePixels::solid(128, 128, 128, 128, 128);
+        let mut tile = newmv_ctx_tile(&frame);
+        place_block(&mut tile, (0, 12, 4, 4), RefFrame::Last, false);
+        assert_eq!(newmv_ctx_of_16x16(&tile, (0, 16), RefFrame::Last, true), 3);
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn newmv_ctx_both_neighbors() {
-        let ctx = TileContext::new(64, 128);
-        assert_eq!(ctx.newmv_ctx(16, 16), 5);
+        let frame = FramePixels::solid(128, 128, 128, 128, 128);
+        let mut tile = newmv_ctx_tile(&frame);
+        place_block(&mut tile, (16, 12, 4, 4), RefFrame::Last, false);
+        place_block(&mut tile, (12, 16, 4, 4), RefFrame::Last, false);
+        assert_eq!(newmv_ctx_of_16x16(&tile, (16, 16), RefFrame::Last, true), 5);
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn newmv_ctx_intra_neighbor_not_counted() {
-        let mut ctx = TileContext::new(64, 128);
-        ctx.above_intra[16] = true;
-        assert_eq!(ctx.newmv_ctx(16, 16), 3);
+        let frame = FramePixels::solid(128, 128, 128, 128, 128);
+        let mut tile = newmv_ctx_tile(&frame);
+        place_block(&mut tile, (12, 16, 4, 4), RefFrame::Last, false);
+        assert_eq!(newmv_ctx_of_16x16(&tile, (16, 16), RefFrame::Last, true), 3);
     }
 
+    #[cfg(feature = "inter")]
     #[test]
-    fn newmv_ctx_neighbor_used_newmv_one_side() {
-        let mut ctx = TileContext::new(64, 128);
-        ctx.above_newmv[16] = true;
-        assert_eq!(ctx.newmv_ctx(16, 16), 4);
+    fn newmv_ctx_neighbor_used_newmv() {
+        let frame = FramePixels::solid(128, 128, 128, 128, 128);
+        let mut tile = newmv_ctx_tile(&frame);
+        place_block(&mut tile, (16, 12, 4, 4), RefFrame::Last, true);
+        place_block(&mut tile, (12, 16, 4, 4), RefFrame::Last, false);
+        assert_eq!(newmv_ctx_of_16x16(&tile, (16, 16), RefFrame::Last, true), 4);
+        place_block(&mut tile, (12, 16, 4, 4), RefFrame::Last, true);
+        assert_eq!(newmv_ctx_of_16x16(&tile, (16, 16), RefFrame::Last, true), 4);
     }
 
+    #[cfg(feature = "inter")]
     #[test]
-    fn newmv_ctx_neighbor_used_newmv_both_sides() {
-        let mut ctx = TileContext::new(64, 128);
-        ctx.above_newmv[16] = true;
-        ctx.left_newmv[16] = true;
-        assert_eq!(ctx.newmv_ctx(16, 16), 4);
+    fn newmv_ctx_neighbor_used_newmv_left_only() {
+        let frame = FramePixels::solid(128, 128, 128, 128, 128);
+        let mut tile = newmv_ctx_tile(&frame);
+        place_block(&mut tile, (12, 0, 4, 4), RefFrame::Last, true);
+        assert_eq!(newmv_ctx_of_16x16(&tile, (16, 0), RefFrame::Last, true), 2);
     }
 
+    #[cfg(feature = "inter")]
     #[test]
-    fn newmv_ctx_neighbor_used_newmv_left_only() {
+    fn newmv_ctx_counts_only_neighbors_with_the_same_reference() {
+        let frame = FramePixels::solid(128, 128, 128, 128, 128);
+        let mut tile = newmv_ctx_tile(&frame);
+        place_block(&mut tile, (0, 12, 4, 4), RefFrame::Golden, true);
+        assert_eq!(newmv_ctx_of_16x16(&tile, (0, 16), RefFrame::Last, true), 0);
+        assert_eq!(
+            newmv_ctx_of_16x16(&tile, (0, 16), RefFrame::Golden, true),
+            2
+        );
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn newmv_ctx_top_right_is_a_nearest_neighbor_when_decoded() {
+        let frame = FramePixels::solid(128, 128, 128, 128, 128);
+        let mut tile = newmv_ctx_tile(&frame);
+        place_block(&mut tile, (16, 12, 4, 4), RefFrame::Last2, false);
+        place_block(&mut tile, (20, 12, 4, 4), RefFrame::Last, false);
+        assert_eq!(newmv_ctx_of_16x16(&tile, (16, 16), RefFrame::Last, true), 3);
+        assert_eq!(
+            newmv_ctx_of_16x16(&tile, (16, 16), RefFrame::Last, false),
+            0
+        );
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn newmv_ctx_outer_rows_only_count_as_a_match() {
+        let frame = FramePixels::solid(128, 128, 128, 128, 128);
+        let mut tile = newmv_ctx_tile(&frame);
+        place_block(&mut tile, (16, 14, 2, 2), RefFrame::Last2, false);
+        place_block(&mut tile, (18, 14, 2, 2), RefFrame::Last2, false);
+        place_block(&mut tile, (16, 12, 4, 2), RefFrame::Last, true);
+        assert_eq!(
+            newmv_ctx_of_16x16(&tile, (16, 16), RefFrame::Last, false),
+            1
+        );
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn single_ref_bits_follow_the_reference_tree() {
+        let ctx = TileContext::new(32, 128);
+        let bits = |ref_frame| -> Vec<_> {
+            ctx.single_ref_bits(0, 0, ref_frame)
+                .into_iter()
+                .map(|(bit, _, value)| (bit, value))
+                .collect()
+        };
+        assert_eq!(bits(RefFrame::Last), [(0, false), (2, false), (3, false)]);
+        assert_eq!(bits(RefFrame::Last2), [(0, false), (2, false), (3, true)]);
+        assert_eq!(bits(RefFrame::Golden), [(0, false), (2, true), (4, true)]);
+        assert_eq!(bits(RefFrame::Altref), [(0, true), (1, true)]);
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn single_ref_ctx_compares_neighbor_references() {
         let mut ctx = TileContext::new(32, 128);
-        ctx.above_intra[16] = false;
-        ctx.left_intra[0] = false;
-        ctx.left_newmv[0] = true;
-        assert_eq!(ctx.newmv_ctx(16, 0), 2);
+        ctx.update_ref_ctx(2, 0, 4, 32, 32, BlockRefs::Single(RefFrame::Last2));
+        ctx.update_ref_ctx(0, 2, 4, 32, 32, BlockRefs::Single(RefFrame::Last));
+        let contexts = |ctx: &TileContext, ref_frame| -> Vec<_> {
+            ctx.single_ref_bits(2, 2, ref_frame)
+                .into_iter()
+                .map(|(_, c, _)| c)
+                .collect()
+        };
+        assert_eq!(contexts(&ctx, RefFrame::Last), [2, 2, 1]);
+
+        ctx.update_ref_ctx(0, 2, 4, 32, 32, BlockRefs::Single(RefFrame::Golden));
+        assert_eq!(contexts(&ctx, RefFrame::Last), [2, 1, 0]);
+        assert_eq!(contexts(&ctx, RefFrame::Golden)[2], 0);
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn compound_refs_are_coded_after_comp_mode() {
+        let ctx = TileContext::new(32, 128);
+        let compound = BlockRefs::Compound(RefFrame::Last, RefFrame::Altref);
+        let symbols: Vec<_> = ctx
+            .ref_symbols(0, 0, compound, true)
+            .into_iter()
+            .map(|(cdf, _, value)| (cdf, value))
+            .collect();
+        assert_eq!(
+            symbols,
+            [
+                (RefCdf::CompMode, true),
+                (RefCdf::CompRefType, true),
+                (RefCdf::CompRef(0), false),
+                (RefCdf::CompRef(1), false),
+                (RefCdf::CompBwdref(0), true),
+            ]
+        );
+        let single = ctx.ref_symbols(0, 0, BlockRefs::Single(RefFrame::Last), true);
+        assert_eq!((single[0].0, single[0].2), (RefCdf::CompMode, false));
+        assert_eq!(
+            ctx.ref_symbols(0, 0, BlockRefs::Single(RefFrame::Last), false)
+                .len(),
+            3
+        );
     }
 
+    #[cfg(feature = "inter")]
+    #[test]
+    fn comp_mode_ctx_counts_compound_and_backward_neighbors() {
+        let mut ctx = TileContext::new(32, 128);
+        assert_eq!(ctx.comp_mode_ctx(0, 0), 1);
+        let compound = BlockRefs::Compound(RefFrame::Last, RefFrame::Altref);
+        ctx.update_ref_ctx(2, 0, 4, 32, 32, compound);
+        ctx.update_ref_ctx(0, 2, 4, 32, 32, BlockRefs::Single(RefFrame::Last));
+        assert_eq!(ctx.comp_mode_ctx(2, 2), 2);
+        ctx.update_ref_ctx(0, 2, 4, 32, 32, BlockRefs::Single(RefFrame::Altref));
+        assert_eq!(ctx.comp_mode_ctx(2, 2), 3);
+        ctx.update_ref_ctx(0, 2, 4, 32, 32, compound);
+        assert_eq!(ctx.comp_mode_ctx(2, 2), 4);
+        assert_eq!(ctx.comp_ref_type_ctx(2, 2), 0);
+    }
+
+    #[cfg(feature = "inter")]
     #[test]
     fn inter_tile_with_gradient() {
         let mut pixels = FramePixels::solid(64, 64, 128, 128, 128);
@@ -4631,6 +7775,39 @@ mod tests {
         assert_eq!(eob_to_bin(127), 7);
         assert_eq!(eob_to_bin(128), 8);
         assert_eq!(eob_to_bin(255), 8);
+        assert_eq!(eob_to_bin(256), 9);
+        assert_eq!(eob_to_bin(511), 9);
+        assert_eq!(eob_to_bin(512), 10);
+        assert_eq!(eob_to_bin(1023), 10);
+    }
+
+    #[test]
+    fn quantize_block_dead_zone_and_skip_bias() {
+        let plain = crate::dequant::lookup_dequant(128, crate::BitDepth::Eight);
+        let mut coeffs = [0i32; 16];
+        coeffs[0] = plain.dc as i32 * 3 / 5;
+        coeffs[5] = plain.ac as i32 * 3 / 5;
+        assert_eq!(
+            quantize_block(&coeffs, 16, &plain),
+            quantize_coeffs(&coeffs, 16, plain.dc, plain.ac)
+        );
+
+        let dead_zone = crate::dequant::DeadZone {
+            inter_ac: 64,
+            ..Default::default()
+        };
+        let inter = plain.with_dead_zone(&dead_zone, false);
+        let quant = quantize_block(&coeffs, 16, &inter);
+        assert_eq!((quant[0], quant[5]), (1, 0));
+
+        let biased = plain.with_dead_zone(
+            &crate::dequant::DeadZone {
+                skip_bias: 2,
+                ..Default::default()
+            },
+            true,
+        );
+        assert!(quantize_block(&coeffs, 16, &biased).iter().all(|&q| q == 0));
     }
 
     #[test]
@@ -5052,24 +8229,28 @@ mod tests {
         assert!(!bytes.is_empty());
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn mv_diff_decompose_one_pixel() {
         let (cl, up, fp) = decompose_mv_diff(8);
         assert_eq!((cl, up, fp), (0, 0, 3));
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn mv_diff_decompose_two_pixels() {
         let (cl, up, fp) = decompose_mv_diff(16);
         assert_eq!((cl, up, fp), (0, 1, 3));
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn mv_diff_decompose_three_pixels() {
         let (cl, up, fp) = decompose_mv_diff(24);
         assert_eq!((cl, up, fp), (1, 2, 3));
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn mv_diff_roundtrip() {
         for diff in (2u32..=128).step_by(2) {
@@ -5080,6 +8261,19 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "inter")]
+    #[test]
+    fn smooth_luma_keeps_flat_areas_and_damps_grain() {
+        let flat = vec![300u16; 8 * 4];
+        assert_eq!(smooth_luma(&flat, 8, 4), flat);
+
+        let checker: Vec<u16> = (0..64).map(|i| 100 + 40 * ((i + i / 8) % 2)).collect();
+        let smoothed = smooth_luma(&checker, 8, 8);
+        // The inner samples average to the mean, the edges stay within it.
+        assert!(smoothed.iter().all(|&s| (110..=130).contains(&s)));
+        assert_eq!(smoothed[3 * 8 + 3], 120);
+    }
+
     #[test]
     fn motion_search_finds_shifted_block() {
         let mut reference = vec![128u8; 64 * 64];
@@ -5098,6 +8292,138 @@ mod tests {
         assert_eq!((dx, dy), (4, 0));
     }
 
+    #[test]
+    fn motion_search_window_follows_search_range() {
+        // A horizontal ramp shifted by 48 pixels: SAD falls steadily towards
+        // the true offset, so only the window limits the search.
+        let (width, height) = (160u32, 64u32);
+        let ramp = |x: u32, y: u32| (x + (y % 4) * 2) as u16;
+        let mut reference = vec![0u16; (width * height) as usize];
+        let mut source = reference.clone();
+        for y in 0..height {
+            for x in 0..width {
+                reference[(y * width + x) as usize] = ramp(x, y);
+                source[(y * width + x) as usize] = ramp((x + 48).min(width - 1), y);
+            }
+        }
+        let search = |range| {
+            super::motion_search_block(&source, &reference, width, height, 10, 20, 8, &[], range)
+        };
+        assert_ne!(search(DEFAULT_MV_SEARCH_RANGE as i32), (48, 0));
+        assert_eq!(search(64), (48, 0));
+    }
+
+    #[test]
+    fn motion_params_scale_with_reference_distance() {
+        let adjacent = MotionParams::for_distance(DEFAULT_MV_SEARCH_RANGE, 1);
+        assert_eq!(adjacent, MotionParams::default());
+        assert_eq!(adjacent.scale_lambda(1000), 1000);
+
+        let distant = MotionParams::for_distance(DEFAULT_MV_SEARCH_RANGE, 3);
+        assert_eq!(distant.search_range, 96);
+        assert_eq!(distant.scale_lambda(1000), 500);
+
+        let capped = MotionParams::for_distance(200, 8);
+        assert_eq!(capped.search_range, MAX_MV_SEARCH_RANGE as i32);
+        assert_eq!(initial_search_step(32), 16);
+        assert_eq!(initial_search_step(96), 32);
+        assert_eq!(initial_search_step(1), 1);
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn early_exit_rules_loosen_with_speed() {
+        let exhaustive = EarlyExit::for_speed(0);
+        assert_eq!(exhaustive, EarlyExit::default());
+        assert!(!exhaustive.intra_dc_is_good_enough(0, 64, 100));
+        assert!(!exhaustive.intra_dct_is_good_enough(0, 64, 100));
+        assert!(!exhaustive.intra_none_is_good_enough(0, 64, 100));
+        assert!(!exhaustive.inter_none_is_good_enough(0, 64, 100));
+        assert!(!exhaustive.inter_zero_mv_is_good_enough(0, 64, 100));
+
+        for speed in 1..=MAX_SPEED {
+            let (slower, faster) = (EarlyExit::for_speed(speed - 1), EarlyExit::for_speed(speed));
+            assert!(faster.intra_dc_satd_q4 > slower.intra_dc_satd_q4);
+            assert!(faster.intra_rd_candidates <= slower.intra_rd_candidates);
+            assert!(faster.intra_txtype_cost_q4 > slower.intra_txtype_cost_q4);
+            assert!(faster.intra_none_mse_q4 > slower.intra_none_mse_q4);
+            assert!(faster.inter_none_mse_q4 > slower.inter_none_mse_q4);
+            assert!(faster.inter_zero_mv_mse_q4 > slower.inter_zero_mv_mse_q4);
+            assert!(faster.inter_split_margin_q4 > slower.inter_split_margin_q4);
+        }
+
+        // Thresholds follow the quantizer: ac_dq 160 is a step of 20.
+        let fast = EarlyExit::for_speed(1);
+        assert!(fast.inter_none_is_good_enough(64 * 25, 64, 160));
+        assert!(!fast.inter_none_is_good_enough(64 * 25 + 1, 64, 160));
+        assert!(!fast.inter_none_is_good_enough(64 * 25, 64, 80));
+        let settled = SkipOrSplit {
+            skip: 1000,
+            split: 250,
+            motion: None,
+            reference: BlockRefs::Single(RefFrame::Last),
+        };
+        assert!(fast.split_is_settled(settled));
+        assert!(
+            !fast.split_is_settled(SkipOrSplit::skip_uncosted(BlockRefs::Single(
+                RefFrame::Last
+            )))
+        );
+    }
+
+    #[test]
+    fn motion_search_reaches_past_the_window_from_a_seed() {
+        let (width, height) = (160u32, 64u32);
+        let ramp = |x: u32, y: u32| (x + (y % 4) * 2) as u16;
+        let mut reference = vec![0u16; (width * height) as usize];
+        let mut source = reference.clone();
+        for y in 0..height {
+            for x in 0..width {
+                reference[(y * width + x) as usize] = ramp(x, y);
+                source[(y * width + x) as usize] = ramp((x + 48).min(width - 1), y);
+            }
+        }
+        let search = |seeds: &[(i32, i32)]| {
+            super::motion_search_block(&source, &reference, width, height, 10, 20, 8, seeds, 16)
+        };
+        assert_ne!(search(&[]), (48, 0));
+        assert_eq!(search(&[(0, 0), (40, 0)]), (48, 0));
+        assert_eq!(search(&[(40, 0), (40, 0), (-8, 0)]), (48, 0));
+    }
+
+    #[cfg(feature = "inter")]
+    #[test]
+    fn mv_field_crops_pastes_and_rescales() {
+        let mut field = MvField::new(40, 20);
+        field.fill(16, 0, 1, (24, -8));
+        field.fill(38, 18, 4, (-16, 4));
+        assert_eq!(field.get(16, 0), Some((24, -8)));
+        assert_eq!(field.get(31, 15), Some((24, -8)));
+        assert_eq!(field.get(15, 0), None);
+        assert_eq!(field.get(39, 19), Some((-16, 4)));
+        assert_eq!(field.get(40, 0), None);
+
+        let rect = TileRect {
+            sb_col_start: 1,
+            sb_col_end: 3,
+            sb_row_start: 0,
+            sb_row_end: 2,
+        };
+        let tile = field.crop(&rect);
+        assert_eq!((tile.mi_cols(), tile.mi_rows()), (24, 20));
+        assert_eq!(tile.get(0, 0), Some((24, -8)));
+        assert_eq!(tile.get(22, 18), Some((-16, 4)));
+        let mut stitched = MvField::new(40, 20);
+        stitched.paste(&tile, &rect);
+        assert_eq!(stitched, field);
+
+        assert_eq!(field.rescaled(2, 1).get(16, 0), Some((48, -16)));
+        assert_eq!(field.rescaled(-1, 2).get(16, 0), Some((-12, 4)));
+        assert_eq!(field.rescaled(1, 0).get(16, 0), Some((0, 0)));
+        assert_eq!(full_pel((24, -8)), (3, -1));
+        assert_eq!(full_pel((-12, 4)), (-1, 1));
+    }
+
     #[test]
     fn motion_search_zero_when_same() {
         let reference = vec![200u8; 64 * 64];
@@ -5106,16 +8432,18 @@ mod tests {
         assert_eq!((dx, dy), (0, 0));
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn mv_prediction_no_neighbors() {
         let mi_cols = 10u32;
         let mi_rows = 10u32;
         let block_mvs = vec![BlockMv::default(); (mi_cols * mi_rows) as usize];
-        let (px, py, cands) = predict_mv(&block_mvs, mi_cols, mi_rows, 0, 0);
+        let (px, py, cands) = predict_mv(&block_mvs, mi_cols, mi_rows, 0, 0, RefFrame::Last);
         assert_eq!((px, py), (0, 0));
         assert!(cands.is_empty());
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn mv_prediction_from_left_neighbor() {
         let mi_cols = 10u32;
@@ -5128,13 +8456,15 @@ mod tests {
                     mv_x: 16,
                     mv_y: 8,
                     ref_frame: 0,
+                    ..Default::default()
                 };
             }
         }
-        let (px, py, _) = predict_mv(&block_mvs, mi_cols, mi_rows, 2, 2);
+        let (px, py, _) = predict_mv(&block_mvs, mi_cols, mi_rows, 2, 2, RefFrame::Last);
         assert_eq!((px, py), (16, 8));
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn mv_prediction_from_above_neighbor() {
         let mi_cols = 10u32;
@@ -5147,13 +8477,15 @@ mod tests {
                     mv_x: 24,
                     mv_y: -16,
                     ref_frame: 0,
+                    ..Default::default()
                 };
             }
         }
-        let (px, py, _) = predict_mv(&block_mvs, mi_cols, mi_rows, 2, 2);
+        let (px, py, _) = predict_mv(&block_mvs, mi_cols, mi_rows, 2, 2, RefFrame::Last);
         assert_eq!((px, py), (24, -16));
     }
 
+    #[cfg(feature = "inter")]
     #[test]
     fn drl_context_computation() {
         let cands = vec![
@@ -0,0 +1,140 @@
+//! Mapping capture timestamps onto the stream's frame-rate time base.
+
+use crate::fps::Fps;
+
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+
+/// Maps capture timestamps in nanoseconds (e.g. `CLOCK_MONOTONIC` from a
+/// V4L2 buffer) to presentation timestamps counted in frame periods of the
+/// configured [`Fps`].
+///
+/// Timestamps are measured from an anchor frame rather than from the
+/// previous one, so per-frame jitter is rounded away instead of
+/// accumulating. A capture clock running slower than the nominal rate
+/// shows up as an occasional skipped tick. When it runs faster, two frames
+/// would land on the same tick; the later one takes the next tick and
+/// becomes the new anchor, so the mapping follows the capture clock instead
+/// of drifting ahead of it. Timestamps are strictly increasing.
+#[derive(Debug, Clone)]
+pub struct CaptureClock {
+    fps: Fps,
+    /// Capture time and pts every later timestamp is measured from.
+    anchor: Option<(u64, u64)>,
+    last_capture_ns: Option<u64>,
+    last_pts: Option<u64>,
+}
+
+impl CaptureClock {
+    pub fn new(fps: Fps) -> Self {
+        Self {
+            fps,
+            anchor: None,
+            last_capture_ns: None,
+            last_pts: None,
+        }
+    }
+
+    /// The pts of the next frame, captured at `capture_time_ns` when known.
+    /// A frame without a timestamp is one period after the previous frame,
+    /// so a stream that never supplies one gets `0, 1, 2, ...`.
+    pub fn next_pts(&mut self, capture_time_ns: Option<u64>) -> u64 {
+        let next = self.last_pts.map_or(0, |pts| pts + 1);
+        let pts = match (capture_time_ns, self.anchor) {
+            (None, _) => next,
+            (Some(ns), Some((anchor_ns, anchor_pts))) if ns >= anchor_ns => {
+                let pts = anchor_pts + self.periods(ns - anchor_ns);
+                if pts < next {
+                    self.anchor = Some((ns, next));
+                }
+                pts.max(next)
+            }
+            // First timestamp, or the capture clock went backwards.
+            (Some(ns), _) => {
+                self.anchor = Some((ns, next));
+                next
+            }
+        };
+        if capture_time_ns.is_some() {
+            self.last_capture_ns = capture_time_ns;
+        }
+        self.last_pts = Some(pts);
+        pts
+    }
+
+    /// Changes the frame period. Later timestamps are measured from the
+    /// last timestamped frame at its existing pts.
+    pub fn set_fps(&mut self, fps: Fps) {
+        if fps == self.fps {
+            return;
+        }
+        self.fps = fps;
+        self.anchor = self.last_capture_ns.zip(self.last_pts);
+    }
+
+    /// `elapsed_ns` in frame periods, rounded to the nearest.
+    fn periods(&self, elapsed_ns: u64) -> u64 {
+        let scaled = elapsed_ns as u128 * self.fps.num as u128;
+        let period = NANOS_PER_SECOND * self.fps.den as u128;
+        ((scaled + period / 2) / period) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NTSC_PERIOD_NS: u64 = 33_366_667;
+
+    #[test]
+    fn untimed_frames_count_up() {
+        let mut clock = CaptureClock::new(Fps::default());
+        let pts: Vec<u64> = (0..4).map(|_| clock.next_pts(None)).collect();
+        assert_eq!(pts, [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn jitter_is_rounded_away_and_gaps_skip_ticks() {
+        let mut clock = CaptureClock::new(Fps::new(30_000, 1001).unwrap());
+        let t0 = 5_000_000_000u64;
+        let jitter = [0i64, 4_000_000, -3_000_000, 1_000_000, -6_000_000];
+        let pts: Vec<u64> = jitter
+            .iter()
+            .enumerate()
+            .map(|(i, &j)| {
+                clock.next_pts(Some(
+                    (t0 + i as u64 * NTSC_PERIOD_NS).saturating_add_signed(j),
+                ))
+            })
+            .collect();
+        assert_eq!(pts, [0, 1, 2, 3, 4]);
+        // Two frames dropped by the capture device.
+        assert_eq!(clock.next_pts(Some(t0 + 7 * NTSC_PERIOD_NS)), 7);
+    }
+
+    #[test]
+    fn fast_capture_clock_never_repeats_a_tick() {
+        let mut clock = CaptureClock::new(Fps::from_int(30).unwrap());
+        // 1% faster than 30 fps: eventually two frames round to one tick.
+        let period = 33_000_000u64;
+        let pts: Vec<u64> = (0..200).map(|i| clock.next_pts(Some(i * period))).collect();
+        assert!(pts.windows(2).all(|w| w[1] == w[0] + 1));
+    }
+
+    #[test]
+    fn backwards_timestamp_reanchors() {
+        let mut clock = CaptureClock::new(Fps::from_int(25).unwrap());
+        assert_eq!(clock.next_pts(Some(1_000_000_000)), 0);
+        assert_eq!(clock.next_pts(Some(1_040_000_000)), 1);
+        assert_eq!(clock.next_pts(Some(10_000)), 2);
+        assert_eq!(clock.next_pts(Some(80_010_000)), 4);
+    }
+
+    #[test]
+    fn fps_change_keeps_the_last_pts() {
+        let mut clock = CaptureClock::new(Fps::from_int(25).unwrap());
+        assert_eq!(clock.next_pts(Some(0)), 0);
+        assert_eq!(clock.next_pts(Some(40_000_000)), 1);
+        clock.set_fps(Fps::from_int(50).unwrap());
+        assert_eq!(clock.next_pts(Some(80_000_000)), 3);
+    }
+}
@@ -0,0 +1,146 @@
+//! Two-pass encoding: first-pass frame statistics and their serialization.
+
+use std::sync::Arc;
+
+use crate::analysis::FrameComplexity;
+use crate::error::EncoderError;
+
+const MAGIC: &[u8; 4] = b"W1FP";
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = 4 + 1 + 4 + 4 + 8;
+const FRAME_LEN: usize = 8 + 8 + 4 + 4;
+const NONE_U64: u64 = u64::MAX;
+const NONE_U32: u32 = u32::MAX;
+
+/// Which pass of a two-pass encode an encoder runs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum TwoPass {
+    #[default]
+    Off,
+    /// Analyse every frame into `Encoder::first_pass_stats` without coding it.
+    FirstPass,
+    /// Allocate `target_bitrate` per frame from a first pass over the same frames.
+    SecondPass(Arc<FirstPassStats>),
+}
+
+/// Per-frame costs recorded by a first pass, by display index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FirstPassStats {
+    pub width: u32,
+    pub height: u32,
+    pub frames: Vec<FrameComplexity>,
+}
+
+impl FirstPassStats {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER_LEN + self.frames.len() * FRAME_LEN);
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        out.extend_from_slice(&self.width.to_le_bytes());
+        out.extend_from_slice(&self.height.to_le_bytes());
+        out.extend_from_slice(&(self.frames.len() as u64).to_le_bytes());
+        for frame in &self.frames {
+            out.extend_from_slice(&frame.intra_cost.to_le_bytes());
+            out.extend_from_slice(&frame.inter_cost.unwrap_or(NONE_U64).to_le_bytes());
+            out.extend_from_slice(&frame.motion_magnitude.unwrap_or(NONE_U32).to_le_bytes());
+            out.extend_from_slice(&frame.blocks.to_le_bytes());
+        }
+        out
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, EncoderError> {
+        let invalid = |reason| EncoderError::InvalidConfig { reason };
+        if data.len() < HEADER_LEN || &data[..4] != MAGIC {
+            return Err(invalid("not wav1c first-pass stats"));
+        }
+        if data[4] != VERSION {
+            return Err(invalid("unsupported first-pass stats version"));
+        }
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+        let u64_at =
+            |offset: usize| u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
+        let count = u64_at(13);
+        if count.checked_mul(FRAME_LEN as u64) != Some((data.len() - HEADER_LEN) as u64) {
+            return Err(invalid("first-pass stats are truncated"));
+        }
+        let frames = (0..count)
+            .map(|frame_number| {
+                let at = HEADER_LEN + frame_number as usize * FRAME_LEN;
+                FrameComplexity {
+                    frame_number,
+                    intra_cost: u64_at(at),
+                    inter_cost: Some(u64_at(at + 8)).filter(|&cost| cost != NONE_U64),
+                    motion_magnitude: Some(u32_at(at + 16)).filter(|&mv| mv != NONE_U32),
+                    blocks: u32_at(at + 20),
+                }
+            })
+            .collect();
+        Ok(Self {
+            width: u32_at(5),
+            height: u32_at(9),
+            frames,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_stats() -> FirstPassStats {
+        let mut stats = FirstPassStats::new(64, 48);
+        stats.frames = vec![
+            FrameComplexity {
+                frame_number: 0,
+                intra_cost: 91_000,
+                inter_cost: None,
+                motion_magnitude: None,
+                blocks: 12,
+            },
+            FrameComplexity {
+                frame_number: 1,
+                intra_cost: 90_500,
+                inter_cost: Some(4_200),
+                motion_magnitude: Some(16),
+                blocks: 12,
+            },
+        ];
+        stats
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let stats = sample_stats();
+        let bytes = stats.to_bytes();
+        assert_eq!(bytes.len(), HEADER_LEN + 2 * FRAME_LEN);
+        assert_eq!(FirstPassStats::from_bytes(&bytes).unwrap(), stats);
+    }
+
+    #[test]
+    fn rejects_foreign_or_corrupt_blobs() {
+        let bytes = sample_stats().to_bytes();
+        assert!(FirstPassStats::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(FirstPassStats::from_bytes(&bytes[..HEADER_LEN - 1]).is_err());
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'X';
+        assert!(FirstPassStats::from_bytes(&bad_magic).is_err());
+
+        let mut bad_version = bytes.clone();
+        bad_version[4] = VERSION + 1;
+        assert!(FirstPassStats::from_bytes(&bad_version).is_err());
+
+        let mut bad_count = bytes;
+        bad_count[13..21].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(FirstPassStats::from_bytes(&bad_count).is_err());
+    }
+}
@@ -32,6 +32,57 @@ pub enum ColorRange {
     Full,
 }
 
+/// Where the 4:2:0 chroma samples sit relative to luma, the sequence
+/// header's `chroma_sample_position`. Decoders ignore it, but converters
+/// upsampling chroma back to 4:4:4 need it to line the planes up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChromaSamplePosition {
+    /// Not signalled (`CSP_UNKNOWN`).
+    #[default]
+    Unknown = 0,
+    /// Horizontally co-sited with the left luma column, vertically between
+    /// two luma rows (`CSP_VERTICAL`): MPEG-2 and most BT.709 video.
+    Vertical = 1,
+    /// Co-sited with the top-left luma sample (`CSP_COLOCATED`), as BT.2020
+    /// and BT.2100 4:2:0 video usually is.
+    Colocated = 2,
+}
+
+impl ChromaSamplePosition {
+    /// Names accepted by [`ChromaSamplePosition::from_name`], one per value.
+    pub const NAMES: [&'static str; 3] = ["unknown", "vertical", "colocated"];
+
+    /// A position by name, ignoring case. `left` and `topleft`, the FFmpeg
+    /// names for the same sitings, are accepted for `vertical` and
+    /// `colocated`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "unknown" => Some(Self::Unknown),
+            "vertical" | "left" => Some(Self::Vertical),
+            "colocated" | "topleft" => Some(Self::Colocated),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        Self::NAMES[self as usize]
+    }
+
+    /// The 2-bit code written to the bitstream.
+    pub fn code(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(Self::Unknown),
+            1 => Some(Self::Vertical),
+            2 => Some(Self::Colocated),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ColorDescription {
     pub color_primaries: u8,
@@ -39,6 +90,79 @@ pub struct ColorDescription {
     pub matrix_coefficients: u8,
 }
 
+/// One of the three CICP fields (ITU-T H.273) in a [`ColorDescription`],
+/// for mapping between code points and their common names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CicpField {
+    ColorPrimaries,
+    TransferCharacteristics,
+    MatrixCoefficients,
+}
+
+#[rustfmt::skip]
+const COLOR_PRIMARIES_NAMES: &[(&str, u8)] = &[
+    ("bt709", 1), ("srgb", 1), ("unspecified", 2), ("bt470m", 4),
+    ("bt470bg", 5), ("bt601", 6), ("smpte170m", 6), ("smpte240m", 7),
+    ("film", 8), ("bt2020", 9), ("xyz", 10), ("smpte431", 11),
+    ("dci-p3", 11), ("smpte432", 12), ("display-p3", 12), ("ebu3213", 22),
+];
+
+#[rustfmt::skip]
+const TRANSFER_NAMES: &[(&str, u8)] = &[
+    ("bt709", 1), ("unspecified", 2), ("bt470m", 4), ("bt470bg", 5),
+    ("bt601", 6), ("smpte170m", 6), ("smpte240m", 7), ("linear", 8),
+    ("log100", 9), ("log100-sqrt10", 10), ("iec61966", 11), ("bt1361", 12),
+    ("srgb", 13), ("bt2020-10", 14), ("bt2020-12", 15), ("smpte2084", 16),
+    ("pq", 16), ("smpte428", 17), ("hlg", 18), ("arib-std-b67", 18),
+];
+
+#[rustfmt::skip]
+const MATRIX_NAMES: &[(&str, u8)] = &[
+    ("identity", 0), ("rgb", 0), ("bt709", 1), ("unspecified", 2),
+    ("fcc", 4), ("bt470bg", 5), ("bt601", 6), ("smpte170m", 6),
+    ("smpte240m", 7), ("ycgco", 8), ("bt2020nc", 9), ("bt2020c", 10),
+    ("smpte2085", 11), ("chroma-ncl", 12), ("chroma-cl", 13), ("ictcp", 14),
+];
+
+impl CicpField {
+    pub const ALL: [CicpField; 3] = [
+        CicpField::ColorPrimaries,
+        CicpField::TransferCharacteristics,
+        CicpField::MatrixCoefficients,
+    ];
+
+    /// Accepted names and their code points. Where several names share a
+    /// code point, the first is the canonical one.
+    pub fn names(self) -> &'static [(&'static str, u8)] {
+        match self {
+            CicpField::ColorPrimaries => COLOR_PRIMARIES_NAMES,
+            CicpField::TransferCharacteristics => TRANSFER_NAMES,
+            CicpField::MatrixCoefficients => MATRIX_NAMES,
+        }
+    }
+
+    /// A code point given as a decimal number or a name, ignoring case and
+    /// accepting `_` for `-`, so `BT2020` and `arib_std_b67` both parse.
+    pub fn parse(self, value: &str) -> Option<u8> {
+        if let Ok(code) = value.parse::<u8>() {
+            return Some(code);
+        }
+        let name = value.trim().to_ascii_lowercase().replace('_', "-");
+        self.names()
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|&(_, code)| code)
+    }
+
+    /// The canonical name of `code`, if it has one.
+    pub fn name(self, code: u8) -> Option<&'static str> {
+        self.names()
+            .iter()
+            .find(|&&(_, c)| c == code)
+            .map(|&(n, _)| n)
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ContentLightLevel {
     pub max_content_light_level: u16,
@@ -58,6 +182,7 @@ pub struct VideoSignal {
     pub bit_depth: BitDepth,
     pub color_range: ColorRange,
     pub color_description: Option<ColorDescription>,
+    pub chroma_sample_position: ChromaSamplePosition,
 }
 
 impl Default for VideoSignal {
@@ -66,20 +191,169 @@ impl Default for VideoSignal {
             bit_depth: BitDepth::Eight,
             color_range: ColorRange::Limited,
             color_description: None,
+            chroma_sample_position: ChromaSamplePosition::Unknown,
         }
     }
 }
 
 impl VideoSignal {
+    /// Names accepted by the [`std::str::FromStr`] impl, one per preset.
+    pub const PRESET_NAMES: [&'static str; 4] = ["sdr709", "srgb-full", "hdr10", "hlg"];
+
+    /// 8-bit limited-range BT.709, the usual HD SDR signal.
+    pub fn sdr709() -> Self {
+        Self::with_cicp(BitDepth::Eight, ColorRange::Limited, 1, 1, 1)
+    }
+
+    /// 8-bit full-range sRGB: BT.709 primaries, the sRGB transfer and the
+    /// BT.601 matrix, as in JPEG and most still-image AVIF.
+    pub fn srgb_full() -> Self {
+        Self::with_cicp(BitDepth::Eight, ColorRange::Full, 1, 13, 6)
+    }
+
+    /// 10-bit BT.2100 PQ: BT.2020 primaries, SMPTE ST 2084 transfer and the
+    /// BT.2020 non-constant-luminance matrix.
     pub fn hdr10(color_range: ColorRange) -> Self {
+        Self::with_cicp(BitDepth::Ten, color_range, 9, 16, 9)
+    }
+
+    /// 10-bit BT.2100 HLG: as [`VideoSignal::hdr10`] with the ARIB STD-B67
+    /// transfer.
+    pub fn hlg(color_range: ColorRange) -> Self {
+        Self::with_cicp(BitDepth::Ten, color_range, 9, 18, 9)
+    }
+
+    fn with_cicp(
+        bit_depth: BitDepth,
+        color_range: ColorRange,
+        color_primaries: u8,
+        transfer_characteristics: u8,
+        matrix_coefficients: u8,
+    ) -> Self {
         Self {
-            bit_depth: BitDepth::Ten,
+            bit_depth,
             color_range,
             color_description: Some(ColorDescription {
-                color_primaries: 9,
-                transfer_characteristics: 16,
-                matrix_coefficients: 9,
+                color_primaries,
+                transfer_characteristics,
+                matrix_coefficients,
             }),
+            chroma_sample_position: ChromaSamplePosition::Unknown,
+        }
+    }
+}
+
+/// A preset name not in [`VideoSignal::PRESET_NAMES`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownSignalPreset(pub String);
+
+impl std::fmt::Display for UnknownSignalPreset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "unknown video signal preset: {} (expected one of: {})",
+            self.0,
+            VideoSignal::PRESET_NAMES.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnknownSignalPreset {}
+
+/// Parses a preset name, ignoring case. HDR presets are limited range.
+impl std::str::FromStr for VideoSignal {
+    type Err = UnknownSignalPreset;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "sdr709" => Ok(Self::sdr709()),
+            "srgb-full" => Ok(Self::srgb_full()),
+            "hdr10" => Ok(Self::hdr10(ColorRange::Limited)),
+            "hlg" => Ok(Self::hlg(ColorRange::Limited)),
+            _ => Err(UnknownSignalPreset(s.to_owned())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cicp_names_parse_to_code_points() {
+        let cp = CicpField::ColorPrimaries;
+        let tc = CicpField::TransferCharacteristics;
+        let mc = CicpField::MatrixCoefficients;
+        assert_eq!(cp.parse("bt709"), Some(1));
+        assert_eq!(cp.parse("BT2020"), Some(9));
+        assert_eq!(cp.parse("srgb"), Some(1));
+        assert_eq!(tc.parse("smpte2084"), Some(16));
+        assert_eq!(tc.parse("pq"), Some(16));
+        assert_eq!(tc.parse("arib_std_b67"), Some(18));
+        assert_eq!(tc.parse("srgb"), Some(13));
+        assert_eq!(mc.parse("bt2020nc"), Some(9));
+        assert_eq!(mc.parse("identity"), Some(0));
+        assert_eq!(mc.parse("42"), Some(42));
+        assert_eq!(mc.parse("srgb"), None);
+        assert_eq!(cp.parse("256"), None);
+    }
+
+    #[test]
+    fn cicp_canonical_names_round_trip() {
+        for field in CicpField::ALL {
+            for &(name, code) in field.names() {
+                assert_eq!(field.parse(name), Some(code), "{field:?} {name}");
+                let canonical = field.name(code).unwrap();
+                assert_eq!(field.parse(canonical), Some(code));
+            }
+        }
+        assert_eq!(
+            CicpField::TransferCharacteristics.name(16),
+            Some("smpte2084")
+        );
+        assert_eq!(CicpField::ColorPrimaries.name(3), None);
+    }
+
+    #[test]
+    fn signal_presets_parse_by_name() {
+        for name in VideoSignal::PRESET_NAMES {
+            let signal: VideoSignal = name.parse().unwrap();
+            assert!(signal.color_description.is_some(), "{name}");
+        }
+        assert_eq!("HDR10".parse(), Ok(VideoSignal::hdr10(ColorRange::Limited)));
+        let hlg = VideoSignal::hlg(ColorRange::Full);
+        assert_eq!(hlg.bit_depth, BitDepth::Ten);
+        assert_eq!(hlg.color_description.unwrap().transfer_characteristics, 18);
+        let srgb = VideoSignal::srgb_full();
+        assert_eq!(srgb.color_range, ColorRange::Full);
+        assert_eq!(
+            CicpField::TransferCharacteristics
+                .name(srgb.color_description.unwrap().transfer_characteristics),
+            Some("srgb")
+        );
+        let err = "rec709".parse::<VideoSignal>().unwrap_err();
+        assert!(err.to_string().contains("sdr709, srgb-full, hdr10, hlg"));
+    }
+
+    #[test]
+    fn chroma_sample_positions_round_trip_names_and_codes() {
+        for name in ChromaSamplePosition::NAMES {
+            let position = ChromaSamplePosition::from_name(name).unwrap();
+            assert_eq!(position.name(), name);
+            assert_eq!(
+                ChromaSamplePosition::from_code(position.code()),
+                Some(position)
+            );
         }
+        assert_eq!(
+            ChromaSamplePosition::from_name("TopLeft"),
+            Some(ChromaSamplePosition::Colocated)
+        );
+        assert_eq!(
+            ChromaSamplePosition::from_name("left"),
+            Some(ChromaSamplePosition::Vertical)
+        );
+        assert_eq!(ChromaSamplePosition::from_name("center"), None);
+        assert_eq!(ChromaSamplePosition::from_code(3), None);
     }
 }
@@ -0,0 +1,114 @@
+use crate::error::EncoderError;
+use crate::video::BitDepth;
+
+const MAGIC: &[u8; 4] = b"W1WS";
+const VERSION: u8 = 1;
+const ENCODED_LEN: usize = 4 + 1 + 1 + 8 + 8 + 8;
+
+/// State carried from one encode into the next, for batches of similar
+/// images or clips (slideshows, burst photos) where each encode otherwise
+/// starts cold.
+///
+/// Every frame wav1c writes is error resilient with `disable_cdf_update`, so
+/// the entropy coder always starts from the default CDFs and a new sequence
+/// starts with a keyframe that drops all references. What does carry over is
+/// the rate model: the quantizer and frame size a previous encode settled on.
+/// A warm-started rate-controlled encode starts from that model instead of
+/// the bits-per-pixel guess, so its first frames land near the target.
+///
+/// Take state with [`crate::Encoder::warm_start_state`], persist it with
+/// [`WarmStartState::to_bytes`], and apply it with
+/// [`crate::Encoder::warm_start`] before the first frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WarmStartState {
+    pub bit_depth: BitDepth,
+    /// Frames that contributed to the model; 0 means there is nothing to
+    /// prime and `warm_start` leaves the encoder as it is.
+    pub frames_observed: u64,
+    pub avg_qp: f64,
+    /// Average coded bits per frame divided by the luma pixel count.
+    pub bits_per_pixel: f64,
+}
+
+impl WarmStartState {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(ENCODED_LEN);
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        out.push(self.bit_depth.bits());
+        out.extend_from_slice(&self.frames_observed.to_le_bytes());
+        out.extend_from_slice(&self.avg_qp.to_le_bytes());
+        out.extend_from_slice(&self.bits_per_pixel.to_le_bytes());
+        out
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, EncoderError> {
+        let invalid = |reason| EncoderError::InvalidConfig { reason };
+        if data.len() != ENCODED_LEN || &data[..4] != MAGIC {
+            return Err(invalid("not a wav1c warm-start state"));
+        }
+        if data[4] != VERSION {
+            return Err(invalid("unsupported warm-start state version"));
+        }
+        let bit_depth = BitDepth::from_u8(data[5])
+            .ok_or(invalid("warm-start state has an invalid bit depth"))?;
+        let word = |offset: usize| -> [u8; 8] { data[offset..offset + 8].try_into().unwrap() };
+        let state = Self {
+            bit_depth,
+            frames_observed: u64::from_le_bytes(word(6)),
+            avg_qp: f64::from_le_bytes(word(14)),
+            bits_per_pixel: f64::from_le_bytes(word(22)),
+        };
+        if !state.avg_qp.is_finite()
+            || !state.bits_per_pixel.is_finite()
+            || state.bits_per_pixel < 0.0
+        {
+            return Err(invalid("warm-start state has an invalid rate model"));
+        }
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_state() -> WarmStartState {
+        WarmStartState {
+            bit_depth: BitDepth::Ten,
+            frames_observed: 12,
+            avg_qp: 87.25,
+            bits_per_pixel: 0.125,
+        }
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let state = sample_state();
+        let bytes = state.to_bytes();
+        assert_eq!(bytes.len(), ENCODED_LEN);
+        assert_eq!(WarmStartState::from_bytes(&bytes).unwrap(), state);
+    }
+
+    #[test]
+    fn rejects_foreign_or_corrupt_blobs() {
+        let bytes = sample_state().to_bytes();
+        assert!(WarmStartState::from_bytes(&bytes[..ENCODED_LEN - 1]).is_err());
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'X';
+        assert!(WarmStartState::from_bytes(&bad_magic).is_err());
+
+        let mut bad_version = bytes.clone();
+        bad_version[4] = VERSION + 1;
+        assert!(WarmStartState::from_bytes(&bad_version).is_err());
+
+        let mut bad_depth = bytes.clone();
+        bad_depth[5] = 12;
+        assert!(WarmStartState::from_bytes(&bad_depth).is_err());
+
+        let mut nan = bytes;
+        nan[14..22].copy_from_slice(&f64::NAN.to_le_bytes());
+        assert!(WarmStartState::from_bytes(&nan).is_err());
+    }
+}
@@ -1,5 +1,7 @@
+use std::io::{self, BufRead, BufReader, Read};
+
 use crate::fps::Fps;
-use crate::video::{BitDepth, ColorRange};
+use crate::video::{BitDepth, ChromaSamplePosition, ColorRange};
 
 #[derive(Debug, Clone)]
 pub struct FramePixels {
@@ -30,7 +32,7 @@ impl std::fmt::Display for Y4mError {
             Y4mError::InvalidHeaderUtf8 => write!(f, "Invalid Y4M header"),
             Y4mError::InvalidHeader(msg) => write!(f, "Invalid Y4M header: {msg}"),
             Y4mError::UnsupportedColorspace(cs) => {
-                write!(f, "Only 4:2:0 Y4M is supported, got {cs}")
+                write!(f, "Only 4:2:0 and mono Y4M are supported, got {cs}")
             }
             Y4mError::InvalidDimensions => write!(f, "Missing or invalid W/H in Y4M header"),
             Y4mError::NoFrameMarker => write!(f, "No FRAME marker in Y4M data"),
@@ -53,14 +55,32 @@ fn parse_color_range_token(token: &str) -> Option<ColorRange> {
     }
 }
 
-fn parse_bit_depth_from_colorspace(colorspace: &str) -> Result<BitDepth, Y4mError> {
+/// The bit depth of a `C` colorspace, and whether it is `mono` (luma only,
+/// e.g. an alpha or depth plane from `-pix_fmt gray`).
+fn parse_colorspace(colorspace: &str) -> Result<(BitDepth, bool), Y4mError> {
+    match colorspace {
+        "mono" => return Ok((BitDepth::Eight, true)),
+        "mono10" => return Ok((BitDepth::Ten, true)),
+        _ => {}
+    }
     if !colorspace.starts_with("420") {
         return Err(Y4mError::UnsupportedColorspace(colorspace.to_owned()));
     }
     if colorspace.contains("p10") || colorspace.contains("P10") {
-        Ok(BitDepth::Ten)
+        Ok((BitDepth::Ten, false))
     } else {
-        Ok(BitDepth::Eight)
+        Ok((BitDepth::Eight, false))
+    }
+}
+
+/// Chroma siting of a 4:2:0 colorspace tag, as FFmpeg reads them:
+/// `420mpeg2` is left (vertical), `420paldv` top-left (colocated). `420jpeg`
+/// is centred between luma samples, which AV1 has no code for.
+fn chroma_sample_position_of(colorspace: &str) -> ChromaSamplePosition {
+    match colorspace {
+        "420mpeg2" => ChromaSamplePosition::Vertical,
+        "420paldv" => ChromaSamplePosition::Colocated,
+        _ => ChromaSamplePosition::Unknown,
     }
 }
 
@@ -77,9 +97,84 @@ fn parse_fps_token(value: &str) -> Result<Fps, Y4mError> {
     Fps::new(num, den).map_err(|_| Y4mError::InvalidHeader("Invalid frame rate"))
 }
 
-fn parse_main_header(
-    line: &str,
-) -> Result<(u32, u32, BitDepth, ColorRange, Option<Fps>), Y4mError> {
+/// Field order of interlaced content, from the Y4M `I` parameter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interlacing {
+    Progressive,
+    TopFieldFirst,
+    BottomFieldFirst,
+    /// Signalled per frame (`Im`).
+    Mixed,
+}
+
+/// Y4M parameters that `FramePixels` does not carry, as returned by
+/// `FramePixels::try_all_from_y4m_with_metadata`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Y4mMetadata {
+    pub fps: Option<Fps>,
+    /// Pixel aspect ratio `(horizontal, vertical)`; `None` when absent or
+    /// unknown (`A0:0`).
+    pub pixel_aspect: Option<(u32, u32)>,
+    /// `None` when absent or unknown (`I?`).
+    pub interlacing: Option<Interlacing>,
+    /// From the `C` parameter; `Unknown` unless it names a siting AV1 can
+    /// signal.
+    pub chroma_sample_position: ChromaSamplePosition,
+    /// The stream header's `X` parameters in order, without the `X`, e.g.
+    /// `COLORRANGE=FULL`.
+    pub x_params: Vec<String>,
+    /// One entry per decoded frame, in file order.
+    pub frames: Vec<FrameMetadata>,
+}
+
+/// Parameters of one Y4M `FRAME` line.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FrameMetadata {
+    /// The frame's `X` parameters in order, without the `X`.
+    pub x_params: Vec<String>,
+}
+
+fn parse_aspect_token(value: &str) -> Result<Option<(u32, u32)>, Y4mError> {
+    const INVALID: Y4mError = Y4mError::InvalidHeader("Invalid pixel aspect ratio");
+    let (num, den) = value.split_once(':').ok_or(INVALID)?;
+    let num: u32 = num.parse().map_err(|_| INVALID)?;
+    let den: u32 = den.parse().map_err(|_| INVALID)?;
+    match (num, den) {
+        (0, 0) => Ok(None),
+        (0, _) | (_, 0) => Err(INVALID),
+        _ => Ok(Some((num, den))),
+    }
+}
+
+fn parse_interlacing_token(value: &str) -> Result<Option<Interlacing>, Y4mError> {
+    match value {
+        "p" => Ok(Some(Interlacing::Progressive)),
+        "t" => Ok(Some(Interlacing::TopFieldFirst)),
+        "b" => Ok(Some(Interlacing::BottomFieldFirst)),
+        "m" => Ok(Some(Interlacing::Mixed)),
+        "?" => Ok(None),
+        _ => Err(Y4mError::InvalidHeader("Invalid interlacing")),
+    }
+}
+
+/// Splits a header token into its one-letter key and value. Tokens may
+/// start with any character, so this never splits inside one.
+fn split_token(token: &str) -> (char, &str) {
+    let mut chars = token.chars();
+    let key = chars.next().unwrap_or(' ');
+    (key, chars.as_str())
+}
+
+struct MainHeader {
+    width: u32,
+    height: u32,
+    bit_depth: BitDepth,
+    mono: bool,
+    color_range: ColorRange,
+    metadata: Y4mMetadata,
+}
+
+fn parse_main_header(line: &str) -> Result<MainHeader, Y4mError> {
     if !line.starts_with("YUV4MPEG2") {
         return Err(Y4mError::InvalidHeader("Not a YUV4MPEG2 file"));
     }
@@ -87,33 +182,43 @@ fn parse_main_header(
     let mut width = 0u32;
     let mut height = 0u32;
     let mut bit_depth = BitDepth::Eight;
+    let mut mono = false;
     let mut default_color_range = ColorRange::Limited;
-    let mut fps = None;
+    let mut metadata = Y4mMetadata::default();
 
     for token in line.split_whitespace().skip(1) {
-        let (key, val) = token.split_at(1);
+        let (key, val) = split_token(token);
         match key {
-            "W" => {
+            'W' => {
                 width = val
                     .parse()
                     .map_err(|_| Y4mError::InvalidHeader("Invalid width"))?;
             }
-            "H" => {
+            'H' => {
                 height = val
                     .parse()
                     .map_err(|_| Y4mError::InvalidHeader("Invalid height"))?;
             }
-            "C" => {
-                bit_depth = parse_bit_depth_from_colorspace(val)?;
+            'C' => {
+                (bit_depth, mono) = parse_colorspace(val)?;
+                metadata.chroma_sample_position = chroma_sample_position_of(val);
+            }
+            'F' => {
+                metadata.fps = Some(parse_fps_token(val)?);
             }
-            "F" => {
-                fps = Some(parse_fps_token(val)?);
+            'A' => {
+                metadata.pixel_aspect = parse_aspect_token(val)?;
             }
-            _ => {
+            'I' => {
+                metadata.interlacing = parse_interlacing_token(val)?;
+            }
+            'X' => {
                 if let Some(r) = parse_color_range_token(token) {
                     default_color_range = r;
                 }
+                metadata.x_params.push(val.to_owned());
             }
+            _ => {}
         }
     }
 
@@ -121,42 +226,242 @@ fn parse_main_header(
         return Err(Y4mError::InvalidDimensions);
     }
 
-    Ok((width, height, bit_depth, default_color_range, fps))
+    Ok(MainHeader {
+        width,
+        height,
+        bit_depth,
+        mono,
+        color_range: default_color_range,
+        metadata,
+    })
 }
 
 fn parse_frame_header_line(
     line: &[u8],
     default_color_range: ColorRange,
-) -> Result<ColorRange, Y4mError> {
+) -> Result<(ColorRange, FrameMetadata), Y4mError> {
     let s = std::str::from_utf8(line).map_err(|_| Y4mError::InvalidHeaderUtf8)?;
     if !s.starts_with("FRAME") {
         return Err(Y4mError::NoFrameMarker);
     }
     let mut color_range = default_color_range;
+    let mut metadata = FrameMetadata::default();
     for token in s.split_whitespace().skip(1) {
         if let Some(r) = parse_color_range_token(token) {
             color_range = r;
         }
+        if let Some(param) = token.strip_prefix('X') {
+            metadata.x_params.push(param.to_owned());
+        }
+    }
+    Ok((color_range, metadata))
+}
+
+/// Sample layout of every frame of a stream, from its header.
+struct FrameLayout {
+    width: u32,
+    height: u32,
+    bit_depth: BitDepth,
+    mono: bool,
+    y_size: usize,
+    uv_size: usize,
+    /// Bytes after each `FRAME` line.
+    frame_data_size: usize,
+}
+
+impl FrameLayout {
+    fn new(header: &MainHeader) -> Result<Self, Y4mError> {
+        let (width, height) = (header.width, header.height);
+        let bytes_per_sample = if header.bit_depth == BitDepth::Ten {
+            2
+        } else {
+            1
+        };
+        // Checked, so absurd dimensions fail instead of overflowing.
+        let plane_sizes = || {
+            let y_size = (width as usize).checked_mul(height as usize)?;
+            let uv_size = (width.div_ceil(2) as usize).checked_mul(height.div_ceil(2) as usize)?;
+            let stored_uv_size = if header.mono { 0 } else { uv_size };
+            let frame_data_size = stored_uv_size
+                .checked_mul(2)?
+                .checked_add(y_size)?
+                .checked_mul(bytes_per_sample)?;
+            Some((y_size, uv_size, frame_data_size))
+        };
+        let (y_size, uv_size, frame_data_size) =
+            plane_sizes().ok_or(Y4mError::InvalidDimensions)?;
+        Ok(Self {
+            width,
+            height,
+            bit_depth: header.bit_depth,
+            mono: header.mono,
+            y_size,
+            uv_size,
+            frame_data_size,
+        })
+    }
+
+    /// The frame stored in `frame_data`, `frame_data_size` bytes.
+    fn decode(&self, frame_data: &[u8], color_range: ColorRange) -> FramePixels {
+        let (y_size, uv_size) = (self.y_size, self.uv_size);
+        let samples = |bytes: &[u8]| -> Vec<u16> {
+            if self.bit_depth == BitDepth::Ten {
+                bytes
+                    .chunks_exact(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .collect()
+            } else {
+                bytes.iter().map(|&b| b as u16).collect()
+            }
+        };
+        let (y, u, v) = if self.mono {
+            // Neutral chroma, so the luma codes as a gray image.
+            let neutral = 1u16 << (self.bit_depth.bits() - 1);
+            (
+                samples(frame_data),
+                vec![neutral; uv_size],
+                vec![neutral; uv_size],
+            )
+        } else {
+            let bytes_per_sample = if self.bit_depth == BitDepth::Ten {
+                2
+            } else {
+                1
+            };
+            let (y_bytes, uv_bytes) = (y_size * bytes_per_sample, uv_size * bytes_per_sample);
+            (
+                samples(&frame_data[..y_bytes]),
+                samples(&frame_data[y_bytes..y_bytes + uv_bytes]),
+                samples(&frame_data[y_bytes + uv_bytes..y_bytes + 2 * uv_bytes]),
+            )
+        };
+        FramePixels {
+            y,
+            u,
+            v,
+            width: self.width,
+            height: self.height,
+            bit_depth: self.bit_depth,
+            color_range,
+        }
+    }
+}
+
+/// The color range and parameters of a `FRAME` line, `line` without its
+/// newline. An empty line stands for a bare `FRAME`.
+fn parse_frame_line(
+    line: &[u8],
+    default_color_range: ColorRange,
+) -> Result<(ColorRange, FrameMetadata), Y4mError> {
+    if line.is_empty() {
+        Ok((default_color_range, FrameMetadata::default()))
+    } else {
+        parse_frame_header_line(line, default_color_range)
+    }
+}
+
+/// Reads a Y4M stream one frame at a time from any reader, stdin included,
+/// so memory stays at a frame however long the input is. It accepts what
+/// `FramePixels::try_all_from_y4m` does; malformed input fails with
+/// `io::ErrorKind::InvalidData` wrapping a [`Y4mError`].
+pub struct Y4mReader<R: Read> {
+    reader: BufReader<R>,
+    layout: FrameLayout,
+    default_color_range: ColorRange,
+    metadata: Y4mMetadata,
+    line: Vec<u8>,
+    frame_data: Vec<u8>,
+}
+
+impl<R: Read> Y4mReader<R> {
+    /// Reads the stream header.
+    pub fn new(reader: R) -> io::Result<Self> {
+        let mut reader = BufReader::new(reader);
+        let mut line = Vec::new();
+        reader.read_until(b'\n', &mut line)?;
+        if line.pop() != Some(b'\n') {
+            return Err(invalid_data(Y4mError::MissingHeader));
+        }
+        let header_line =
+            std::str::from_utf8(&line).map_err(|_| invalid_data(Y4mError::InvalidHeaderUtf8))?;
+        let header = parse_main_header(header_line).map_err(invalid_data)?;
+        let layout = FrameLayout::new(&header).map_err(invalid_data)?;
+        Ok(Self {
+            reader,
+            layout,
+            default_color_range: header.color_range,
+            metadata: header.metadata,
+            line,
+            frame_data: Vec::new(),
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.layout.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.layout.height
+    }
+
+    /// The stream header's parameters. `frames` stays empty: each frame's
+    /// come with it from `read_frame_with_metadata`.
+    pub fn metadata(&self) -> &Y4mMetadata {
+        &self.metadata
+    }
+
+    /// The next frame, or `None` at the end of the stream.
+    pub fn read_frame(&mut self) -> io::Result<Option<FramePixels>> {
+        Ok(self.read_frame_with_metadata()?.map(|(frame, _)| frame))
+    }
+
+    /// The next frame and the parameters of its `FRAME` line.
+    pub fn read_frame_with_metadata(&mut self) -> io::Result<Option<(FramePixels, FrameMetadata)>> {
+        self.line.clear();
+        if self.reader.read_until(b'\n', &mut self.line)? == 0 {
+            return Ok(None);
+        }
+        if self.line.pop() != Some(b'\n') {
+            return Err(invalid_data(Y4mError::TruncatedFrameData));
+        }
+        let (color_range, metadata) =
+            parse_frame_line(&self.line, self.default_color_range).map_err(invalid_data)?;
+        self.frame_data.resize(self.layout.frame_data_size, 0);
+        self.reader
+            .read_exact(&mut self.frame_data)
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::UnexpectedEof => invalid_data(Y4mError::TruncatedFrameData),
+                _ => e,
+            })?;
+        let frame = self.layout.decode(&self.frame_data, color_range);
+        Ok(Some((frame, metadata)))
     }
-    Ok(color_range)
+}
+
+impl<R: Read> Iterator for Y4mReader<R> {
+    type Item = io::Result<FramePixels>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_frame().transpose()
+    }
+}
+
+fn invalid_data(e: Y4mError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
 }
 
 impl FramePixels {
-    fn try_all_from_y4m_impl(data: &[u8]) -> Result<(Vec<Self>, Option<Fps>), Y4mError> {
+    fn try_all_from_y4m_impl(data: &[u8]) -> Result<(Vec<Self>, Y4mMetadata), Y4mError> {
         let header_end = data
             .iter()
             .position(|&b| b == b'\n')
             .ok_or(Y4mError::MissingHeader)?;
         let header_line =
             std::str::from_utf8(&data[..header_end]).map_err(|_| Y4mError::InvalidHeaderUtf8)?;
-        let (width, height, bit_depth, default_color_range, fps) = parse_main_header(header_line)?;
-
-        let y_size = (width * height) as usize;
-        let uv_w = width.div_ceil(2) as usize;
-        let uv_h = height.div_ceil(2) as usize;
-        let uv_size = uv_w * uv_h;
-        let bytes_per_sample = if bit_depth == BitDepth::Ten { 2 } else { 1 };
-        let frame_data_size = (y_size + 2 * uv_size) * bytes_per_sample;
+        let header = parse_main_header(header_line)?;
+        let layout = FrameLayout::new(&header)?;
+        let mut metadata = header.metadata;
+        let frame_data_size = layout.frame_data_size;
 
         let mut frames = Vec::new();
         let mut pos = header_end + 1;
@@ -167,54 +472,16 @@ impl FramePixels {
                 .position(|&b| b == b'\n')
                 .ok_or(Y4mError::TruncatedFrameData)?;
             let line_end = pos + line_end_rel;
-            let line = &data[pos..line_end];
-            let mut color_range = default_color_range;
-            if !line.is_empty() {
-                color_range = parse_frame_header_line(line, default_color_range)?;
-            }
+            let (color_range, frame_metadata) =
+                parse_frame_line(&data[pos..line_end], header.color_range)?;
 
             let pixel_start = line_end + 1;
-            if pixel_start + frame_data_size > data.len() {
+            if frame_data_size > data.len() - pixel_start {
                 return Err(Y4mError::TruncatedFrameData);
             }
-
             let frame_data = &data[pixel_start..pixel_start + frame_data_size];
-
-            let (y_plane, u_plane, v_plane) = if bytes_per_sample == 1 {
-                let y_plane = frame_data[..y_size].iter().map(|&b| b as u16).collect();
-                let u_plane = frame_data[y_size..y_size + uv_size]
-                    .iter()
-                    .map(|&b| b as u16)
-                    .collect();
-                let v_plane = frame_data[y_size + uv_size..y_size + 2 * uv_size]
-                    .iter()
-                    .map(|&b| b as u16)
-                    .collect();
-                (y_plane, u_plane, v_plane)
-            } else {
-                let parse_16le = |slice: &[u8]| -> Vec<u16> {
-                    slice
-                        .chunks_exact(2)
-                        .map(|c| u16::from_le_bytes([c[0], c[1]]))
-                        .collect()
-                };
-                let y_bytes = y_size * 2;
-                let uv_bytes = uv_size * 2;
-                let y_plane = parse_16le(&frame_data[..y_bytes]);
-                let u_plane = parse_16le(&frame_data[y_bytes..y_bytes + uv_bytes]);
-                let v_plane = parse_16le(&frame_data[y_bytes + uv_bytes..y_bytes + 2 * uv_bytes]);
-                (y_plane, u_plane, v_plane)
-            };
-
-            frames.push(Self {
-                y: y_plane,
-                u: u_plane,
-                v: v_plane,
-                width,
-                height,
-                bit_depth,
-                color_range,
-            });
+            frames.push(layout.decode(frame_data, color_range));
+            metadata.frames.push(frame_metadata);
 
             pos = pixel_start + frame_data_size;
         }
@@ -223,7 +490,7 @@ impl FramePixels {
             return Err(Y4mError::NoFrameMarker);
         }
 
-        Ok((frames, fps))
+        Ok((frames, metadata))
     }
 
     pub fn try_all_from_y4m(data: &[u8]) -> Result<Vec<Self>, Y4mError> {
@@ -231,6 +498,15 @@ impl FramePixels {
     }
 
     pub fn try_all_from_y4m_with_fps(data: &[u8]) -> Result<(Vec<Self>, Option<Fps>), Y4mError> {
+        Self::try_all_from_y4m_impl(data).map(|(frames, metadata)| (frames, metadata.fps))
+    }
+
+    /// Like `try_all_from_y4m`, plus the header parameters the frames do not
+    /// carry: frame rate, pixel aspect ratio, interlacing and the stream and
+    /// per-frame `X` parameters. Other parameters are ignored.
+    pub fn try_all_from_y4m_with_metadata(
+        data: &[u8],
+    ) -> Result<(Vec<Self>, Y4mMetadata), Y4mError> {
         Self::try_all_from_y4m_impl(data)
     }
 
@@ -252,6 +528,14 @@ impl FramePixels {
             .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
     }
 
+    pub fn all_from_y4m_file_with_metadata(
+        path: &std::path::Path,
+    ) -> std::io::Result<(Vec<Self>, Y4mMetadata)> {
+        let data = std::fs::read(path)?;
+        Self::try_all_from_y4m_with_metadata(&data)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+
     pub fn try_from_y4m(data: &[u8]) -> Result<Self, Y4mError> {
         let mut frames = Self::try_all_from_y4m(data)?;
         Ok(frames.swap_remove(0))
@@ -350,6 +634,50 @@ impl FramePixels {
             color_range,
         }
     }
+
+    /// Rescales samples between limited (studio) and full range, clamping to
+    /// the legal range of the target. Returns a copy unchanged if the frame
+    /// already uses `target`.
+    pub fn convert_range(&self, target: ColorRange) -> Self {
+        if self.color_range == target {
+            return self.clone();
+        }
+        let shift = self.bit_depth.bits() - 8;
+        let max = self.bit_depth.max_value() as i64;
+        let mid = self.bit_depth.mid_value() as i64;
+        let black = 16i64 << shift;
+        let luma_span = 219i64 << shift;
+        let chroma_span = 224i64 << shift;
+
+        // (origin, span) of luma and chroma in each range
+        let full = ((0, max), (mid, max));
+        let limited = ((black, luma_span), (mid, chroma_span));
+        let (from, to) = match target {
+            ColorRange::Limited => (full, limited),
+            ColorRange::Full => (limited, full),
+        };
+        let rescale =
+            |s: u16, (from_origin, from_span): (i64, i64), (to_origin, to_span): (i64, i64)| {
+                let v = to_origin + round_div((s as i64 - from_origin) * to_span, from_span);
+                v.clamp(0, max) as u16
+            };
+
+        Self {
+            y: self.y.iter().map(|&s| rescale(s, from.0, to.0)).collect(),
+            u: self.u.iter().map(|&s| rescale(s, from.1, to.1)).collect(),
+            v: self.v.iter().map(|&s| rescale(s, from.1, to.1)).collect(),
+            color_range: target,
+            ..*self
+        }
+    }
+}
+
+fn round_div(n: i64, d: i64) -> i64 {
+    if n >= 0 {
+        (n + d / 2) / d
+    } else {
+        -((-n + d / 2) / d)
+    }
 }
 
 #[cfg(test)]
@@ -428,6 +756,76 @@ mod tests {
         assert_eq!(pixels.v, vec![600]);
     }
 
+    #[test]
+    fn mono_y4m_gets_neutral_chroma() {
+        let mut data = b"YUV4MPEG2 W3 H1 F1:1 Cmono\nFRAME\n".to_vec();
+        data.extend_from_slice(&[0, 128, 255]);
+        let pixels = FramePixels::from_y4m(&data);
+        assert_eq!(pixels.y, vec![0, 128, 255]);
+        assert_eq!((pixels.u, pixels.v), (vec![128, 128], vec![128, 128]));
+
+        let mut data = b"YUV4MPEG2 W1 H1 F1:1 Cmono10\nFRAME\n".to_vec();
+        data.extend_from_slice(&1000u16.to_le_bytes());
+        let pixels = FramePixels::from_y4m(&data);
+        assert_eq!(pixels.bit_depth, BitDepth::Ten);
+        assert_eq!((pixels.y, pixels.u), (vec![1000], vec![512]));
+    }
+
+    #[test]
+    fn reader_streams_what_the_parser_returns() {
+        let mut data = b"YUV4MPEG2 W2 H2 F25:1 Ip C420p10 XCOLORRANGE=LIMITED\n".to_vec();
+        for (i, line) in ["FRAME\n", "FRAME XCOLORRANGE=FULL Xfoo\n", "\n"]
+            .iter()
+            .enumerate()
+        {
+            data.extend_from_slice(line.as_bytes());
+            for s in 0..6u16 {
+                data.extend_from_slice(&(s * 100 + i as u16).to_le_bytes());
+            }
+        }
+        let (frames, metadata) = FramePixels::try_all_from_y4m_with_metadata(&data).unwrap();
+
+        let mut reader = Y4mReader::new(&data[..]).unwrap();
+        assert_eq!((reader.width(), reader.height()), (2, 2));
+        assert_eq!(reader.metadata().fps, metadata.fps);
+        assert_eq!(reader.metadata().x_params, metadata.x_params);
+        let mut streamed = Vec::new();
+        while let Some((frame, frame_metadata)) = reader.read_frame_with_metadata().unwrap() {
+            streamed.push((frame, frame_metadata));
+        }
+        assert_eq!(streamed.len(), frames.len());
+        for ((frame, frame_metadata), (expected, expected_metadata)) in
+            streamed.iter().zip(frames.iter().zip(&metadata.frames))
+        {
+            assert_eq!(
+                (&frame.y, &frame.u, &frame.v),
+                (&expected.y, &expected.u, &expected.v)
+            );
+            assert_eq!(frame.color_range, expected.color_range);
+            assert_eq!(frame_metadata, expected_metadata);
+        }
+    }
+
+    #[test]
+    fn reader_rejects_truncated_streams() {
+        let y4m = create_test_y4m(4, 4, 16, 128, 128);
+        let mut two = y4m.clone();
+        two.extend_from_slice(&y4m[y4m.iter().position(|&b| b == b'\n').unwrap() + 1..]);
+
+        let frames: Vec<_> = Y4mReader::new(&two[..]).unwrap().collect();
+        assert_eq!(frames.len(), 2);
+        assert!(frames.iter().all(Result::is_ok));
+
+        let mut reader = Y4mReader::new(&two[..two.len() - 1]).unwrap();
+        assert!(reader.read_frame().unwrap().is_some());
+        let err = reader.read_frame().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), Y4mError::TruncatedFrameData.to_string());
+
+        let err = Y4mReader::new(&b"YUV4MPEG2 W4 H4"[..]).err().unwrap();
+        assert_eq!(err.to_string(), Y4mError::MissingHeader.to_string());
+    }
+
     #[test]
     fn parse_frame_header_with_params() {
         let header = b"YUV4MPEG2 W2 H2 F1:1 Ip C420p10\n";
@@ -449,4 +847,154 @@ mod tests {
             Y4mError::MissingHeader | Y4mError::InvalidHeader(_)
         ));
     }
+
+    #[test]
+    fn parses_aspect_interlacing_and_x_params() {
+        let mut data =
+            b"YUV4MPEG2 W2 H2 F25:1 It A16:15 C420jpeg XYSCSS=420JPEG XCOLORRANGE=FULL\n".to_vec();
+        data.extend_from_slice(b"FRAME Xsource=cam1 Ixyz\n");
+        data.extend_from_slice(&[16; 6]);
+        data.extend_from_slice(b"FRAME\n");
+        data.extend_from_slice(&[16; 6]);
+        let (frames, metadata) = FramePixels::try_all_from_y4m_with_metadata(&data).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].color_range, ColorRange::Full);
+        assert_eq!(metadata.fps, Some(Fps::from_int(25).unwrap()));
+        assert_eq!(metadata.pixel_aspect, Some((16, 15)));
+        assert_eq!(metadata.interlacing, Some(Interlacing::TopFieldFirst));
+        assert_eq!(metadata.x_params, ["YSCSS=420JPEG", "COLORRANGE=FULL"]);
+        assert_eq!(metadata.frames[0].x_params, ["source=cam1"]);
+        assert!(metadata.frames[1].x_params.is_empty());
+
+        // Unknown values are the same as absent ones.
+        let (_, metadata) =
+            FramePixels::try_all_from_y4m_with_metadata(&create_test_y4m(2, 2, 0, 0, 0)).unwrap();
+        assert_eq!(metadata.interlacing, Some(Interlacing::Progressive));
+        let data = [&b"YUV4MPEG2 W2 H2 A0:0 I?\nFRAME\n"[..], &[0; 6]].concat();
+        let (_, metadata) = FramePixels::try_all_from_y4m_with_metadata(&data).unwrap();
+        assert_eq!((metadata.pixel_aspect, metadata.interlacing), (None, None));
+    }
+
+    #[test]
+    fn colorspace_tags_carry_chroma_siting() {
+        for (tag, position) in [
+            ("C420jpeg", ChromaSamplePosition::Unknown),
+            ("C420mpeg2", ChromaSamplePosition::Vertical),
+            ("C420paldv", ChromaSamplePosition::Colocated),
+            ("C420p10", ChromaSamplePosition::Unknown),
+        ] {
+            let data = [
+                format!("YUV4MPEG2 W2 H2 {tag}\nFRAME\n").as_bytes(),
+                &vec![0; if tag == "C420p10" { 12 } else { 6 }],
+            ]
+            .concat();
+            let (_, metadata) = FramePixels::try_all_from_y4m_with_metadata(&data).unwrap();
+            assert_eq!(metadata.chroma_sample_position, position, "{tag}");
+        }
+    }
+
+    #[test]
+    fn malformed_headers_are_rejected() {
+        let parse = |header: &str| {
+            let data = [header.as_bytes(), b"\nFRAME\n", &[0; 6]].concat();
+            FramePixels::try_all_from_y4m_with_metadata(&data).map(|_| ())
+        };
+        for (header, message) in [
+            ("YUV4MPEG2 W2 H2 A16", "Invalid pixel aspect ratio"),
+            ("YUV4MPEG2 W2 H2 A0:1", "Invalid pixel aspect ratio"),
+            ("YUV4MPEG2 W2 H2 A1:x", "Invalid pixel aspect ratio"),
+            ("YUV4MPEG2 W2 H2 Ix", "Invalid interlacing"),
+            ("YUV4MPEG2 W2 H2 I", "Invalid interlacing"),
+            ("YUV4MPEG2 W2 H2 F30", "Invalid frame rate"),
+            ("YUV4MPEG2 W-2 H2", "Invalid width"),
+        ] {
+            assert_eq!(
+                parse(header),
+                Err(Y4mError::InvalidHeader(message)),
+                "{header}"
+            );
+        }
+        assert_eq!(
+            parse("YUV4MPEG2 W4294967295 H4294967295"),
+            Err(Y4mError::InvalidDimensions)
+        );
+        // Multi-byte characters and stray bytes are tolerated where unknown.
+        assert_eq!(parse("YUV4MPEG2 W2 H2 \u{e9}t\u{e9} X\u{1f600} Z"), Ok(()));
+    }
+
+    #[test]
+    fn mutated_headers_never_panic() {
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        let alphabet = b"YUV4MPEG2 WHCFAIX0123456789:?ptbmjpeg420p10=\n-\xc3\xa9\xff";
+        let valid = [
+            &b"YUV4MPEG2 W4 H2 F30:1 It A1:1 C420p10 XCOLORRANGE=FULL\nFRAME Xa=b\n"[..],
+            &[0; 24],
+        ]
+        .concat();
+        for _ in 0..4000 {
+            let mut data = valid.clone();
+            for _ in 0..1 + next() % 4 {
+                let pos = (next() % 72) as usize;
+                let byte = alphabet[(next() % alphabet.len() as u64) as usize];
+                match next() % 3 {
+                    0 => data[pos] = byte,
+                    1 => data.insert(pos, byte),
+                    _ => {
+                        data.remove(pos);
+                    }
+                }
+            }
+            data.truncate(data.len() - (next() % 8) as usize);
+            let _ = FramePixels::try_all_from_y4m_with_metadata(&data);
+        }
+    }
+
+    #[test]
+    fn converts_full_to_limited_range_endpoints() {
+        let mut frame = FramePixels::solid(2, 2, 0, 0, 255);
+        frame.color_range = ColorRange::Full;
+        frame.y = vec![0, 255, 128, 64];
+        let limited = frame.convert_range(ColorRange::Limited);
+        assert_eq!(limited.color_range, ColorRange::Limited);
+        assert_eq!(limited.y, vec![16, 235, 126, 71]);
+        assert_eq!(limited.u, vec![16]);
+        assert_eq!(limited.v, vec![240]);
+    }
+
+    #[test]
+    fn converts_limited_to_full_range_with_clamping() {
+        let frame = FramePixels::solid_with_bit_depth(
+            2,
+            2,
+            64,
+            512,
+            960,
+            BitDepth::Ten,
+            ColorRange::Limited,
+        );
+        let mut frame = frame;
+        frame.y = vec![0, 64, 940, 1023];
+        let full = frame.convert_range(ColorRange::Full);
+        assert_eq!(full.y, vec![0, 0, 1023, 1023]);
+        assert_eq!(full.u, vec![512]);
+        assert_eq!(full.v, vec![1023]);
+    }
+
+    #[test]
+    fn range_round_trip_is_close_to_identity() {
+        let mut frame = FramePixels::solid(16, 1, 0, 128, 128);
+        frame.y = (0..16).map(|i| 16 + i * 14).collect();
+        let back = frame
+            .convert_range(ColorRange::Full)
+            .convert_range(ColorRange::Limited);
+        for (a, b) in frame.y.iter().zip(&back.y) {
+            assert!(a.abs_diff(*b) <= 1, "{a} vs {b}");
+        }
+    }
 }
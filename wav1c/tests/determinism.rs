@@ -0,0 +1,171 @@
+//! Golden bitstream hashes for a small corpus of encodes.
+//!
+//! Encoder decisions use integer and fixed-point arithmetic only, so the
+//! output must be byte-identical on every platform and optimization level.
+//! CI runs this file on x86_64 and aarch64, in debug and release builds, and
+//! every build must reproduce the hashes below. A change that alters encoder
+//! output on purpose updates the table; the failure message prints the new one.
+//! The corpus covers inter coding and rate control, so it needs both features.
+
+#![cfg(all(feature = "rate-control", feature = "inter"))]
+
+use wav1c::y4m::FramePixels;
+use wav1c::{BitDepth, ColorRange, EncodeConfig};
+
+const GOLDEN: &[(&str, u64)] = &[
+    ("solid keyframe", 0xa7cc529b7ba5f8f4),
+    ("moving texture", 0x0311520c5807e760),
+    ("10-bit noise", 0xaf7293fc75e07771),
+    ("lossless", 0xd7aa9272348c5157),
+    ("rate control", 0x0df55663abb40f9b),
+    ("b-frames", 0x25a527ec1aa52e3b),
+    ("tiles and cdef search", 0x6343e80c6f9102f6),
+    ("loop restoration", 0x846ae24da5e2c598),
+];
+
+fn fnv1a(data: &[u8]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for &byte in data {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+fn textured_frame(width: u32, height: u32, shift: u32) -> FramePixels {
+    let mut frame = FramePixels::solid(width, height, 0, 0, 0);
+    let cw = width.div_ceil(2);
+    for (i, s) in frame.y.iter_mut().enumerate() {
+        let (x, y) = (i as u32 % width + shift, i as u32 / width);
+        *s = ((x * 5 + y * 3 + (x * y) % 29) % 220 + 16) as u16;
+    }
+    for (i, s) in frame.u.iter_mut().chain(frame.v.iter_mut()).enumerate() {
+        let (x, y) = (i as u32 % cw + shift / 2, i as u32 / cw);
+        *s = ((x * 3 + y * 7) % 200 + 28) as u16;
+    }
+    frame
+}
+
+fn noisy_frame(width: u32, height: u32, bit_depth: BitDepth) -> FramePixels {
+    let max = bit_depth.max_value() as u32;
+    let mut frame =
+        FramePixels::solid_with_bit_depth(width, height, 0, 0, 0, bit_depth, ColorRange::Limited);
+    let mut state = 0x2545_f491u32;
+    for s in frame
+        .y
+        .iter_mut()
+        .chain(frame.u.iter_mut())
+        .chain(frame.v.iter_mut())
+    {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        *s = ((state >> 16) % (max + 1)) as u16;
+    }
+    frame
+}
+
+/// Triangle waves rather than `sin`, for the same source on every platform.
+fn rippled_frame(width: u32, height: u32, shift: u32) -> FramePixels {
+    let triangle = |t: u32, period: u32| (t % period).abs_diff(period / 2) as i32;
+    let mut frame = textured_frame(width, height, shift);
+    let mut state = 0x1234_5678u32;
+    for (i, s) in frame.y.iter_mut().enumerate() {
+        let (x, y) = (i as u32 % width + shift, i as u32 / width);
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        let grain = ((state >> 16) % 9) as i32 - 4;
+        let ripple = triangle(x, 30) * triangle(y, 44) / 4 + 2 * triangle(x + y, 70);
+        *s = (60 + ripple + grain) as u16;
+    }
+    frame
+}
+
+fn corpus() -> Vec<(&'static str, EncodeConfig, Vec<FramePixels>)> {
+    let moving = |width, height, count| -> Vec<FramePixels> {
+        (0..count)
+            .map(|i| textured_frame(width, height, i * 3))
+            .collect()
+    };
+    let mut ten_bit = EncodeConfig::default();
+    ten_bit.video_signal.bit_depth = BitDepth::Ten;
+    vec![
+        (
+            "solid keyframe",
+            EncodeConfig::default(),
+            vec![FramePixels::solid(64, 64, 90, 120, 140)],
+        ),
+        ("moving texture", EncodeConfig::default(), moving(96, 64, 4)),
+        (
+            "10-bit noise",
+            ten_bit,
+            vec![noisy_frame(64, 64, BitDepth::Ten); 2],
+        ),
+        (
+            "lossless",
+            EncodeConfig {
+                base_q_idx: 0,
+                ..EncodeConfig::default()
+            },
+            vec![noisy_frame(40, 24, BitDepth::Eight)],
+        ),
+        (
+            "rate control",
+            EncodeConfig {
+                target_bitrate: Some(300_000),
+                keyint: 4,
+                ..EncodeConfig::default()
+            },
+            moving(96, 96, 8),
+        ),
+        (
+            "b-frames",
+            EncodeConfig {
+                b_frames: true,
+                gop_size: 3,
+                ..EncodeConfig::default()
+            },
+            moving(64, 64, 7),
+        ),
+        (
+            "tiles and cdef search",
+            EncodeConfig {
+                tile_columns: 2,
+                cdef_search: true,
+                ..EncodeConfig::default()
+            },
+            vec![
+                noisy_frame(256, 128, BitDepth::Eight),
+                textured_frame(256, 128, 2),
+            ],
+        ),
+        (
+            "loop restoration",
+            EncodeConfig {
+                base_q_idx: 160,
+                loop_restoration: true,
+                ..EncodeConfig::default()
+            },
+            (0..3).map(|i| rippled_frame(160, 128, i * 2)).collect(),
+        ),
+    ]
+}
+
+#[test]
+fn encodes_reproduce_golden_hashes() {
+    let actual: Vec<(&str, u64)> = corpus()
+        .into_iter()
+        .map(|(name, config, frames)| {
+            let bitstream: Vec<u8> = wav1c::encode_packets(&frames, &config)
+                .into_iter()
+                .flat_map(|packet| packet.data)
+                .collect();
+            (name, fnv1a(&bitstream))
+        })
+        .collect();
+    let table: String = actual
+        .iter()
+        .map(|(name, hash)| format!("    (\"{name}\", {hash:#018x}),\n"))
+        .collect();
+    assert_eq!(
+        actual, GOLDEN,
+        "encoder output changed; if intended, update GOLDEN to:\n{table}"
+    );
+}
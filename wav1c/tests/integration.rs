@@ -116,6 +116,7 @@ fn ffprobe_path() -> Option<std::path::PathBuf> {
     None
 }
 
+#[cfg(feature = "hdr")]
 fn read_leb128(data: &[u8], mut pos: usize) -> Option<(usize, usize)> {
     let mut value: usize = 0;
     let mut shift = 0usize;
@@ -135,6 +136,7 @@ fn read_leb128(data: &[u8], mut pos: usize) -> Option<(usize, usize)> {
     Some((value, pos - start))
 }
 
+#[cfg(feature = "hdr")]
 fn parse_obu_types_from_first_ivf_frame(ivf_data: &[u8]) -> Vec<u8> {
     if ivf_data.len() < 44 {
         return Vec::new();
@@ -144,7 +146,11 @@ fn parse_obu_types_from_first_ivf_frame(ivf_data: &[u8]) -> Vec<u8> {
     if ivf_data.len() < 44 + frame_size {
         return Vec::new();
     }
-    let payload = &ivf_data[44..44 + frame_size];
+    parse_obu_types(&ivf_data[44..44 + frame_size])
+}
+
+#[cfg(feature = "hdr")]
+fn parse_obu_types(payload: &[u8]) -> Vec<u8> {
     let mut types = Vec::new();
     let mut pos = 0usize;
     while pos < payload.len() {
@@ -992,6 +998,7 @@ fn dav1d_decodes_multi_sb_inter() {
     }
 }
 
+#[cfg(feature = "hdr")]
 #[test]
 fn hdr_cll_emits_metadata_obu() {
     let frame = FramePixels::solid_with_bit_depth(
@@ -1021,6 +1028,82 @@ fn hdr_cll_emits_metadata_obu() {
     );
 }
 
+#[cfg(all(feature = "hdr", feature = "inter"))]
+fn hdr_metadata_packets(cadence: wav1c::MetadataCadence) -> Vec<(bool, Vec<u8>)> {
+    let frames: Vec<FramePixels> = (0..8)
+        .map(|i| {
+            FramePixels::solid_with_bit_depth(
+                64,
+                64,
+                400 + i * 8,
+                512,
+                512,
+                wav1c::BitDepth::Ten,
+                wav1c::ColorRange::Full,
+            )
+        })
+        .collect();
+    let cfg = wav1c::EncodeConfig {
+        video_signal: wav1c::VideoSignal::hdr10(wav1c::ColorRange::Full),
+        content_light: Some(wav1c::ContentLightLevel {
+            max_content_light_level: 203,
+            max_frame_average_light_level: 64,
+        }),
+        mastering_display: Some(wav1c::MasteringDisplayMetadata {
+            primaries: [[34000, 16000], [13250, 34500], [7500, 3000]],
+            white_point: [15635, 16450],
+            max_luminance: 10_000_000,
+            min_luminance: 1,
+        }),
+        keyint: 4,
+        b_frames: true,
+        gop_size: 3,
+        metadata_cadence: cadence,
+        ..Default::default()
+    };
+    wav1c::encode_packets(&frames, &cfg)
+        .into_iter()
+        .map(|p| {
+            (
+                p.frame_type == wav1c::FrameType::Key,
+                parse_obu_types(&p.data),
+            )
+        })
+        .collect()
+}
+
+#[cfg(all(feature = "hdr", feature = "inter"))]
+#[test]
+fn hdr_metadata_obus_sit_between_sequence_header_and_frame() {
+    for (_, types) in hdr_metadata_packets(wav1c::MetadataCadence::EveryFrame) {
+        // TD, sequence header, CLL, MDCV, then the frame (or frame header).
+        assert_eq!(&types[..4], &[2, 1, 5, 5], "got {types:?}");
+        assert!(
+            types[4..].iter().all(|&t| t == 6 || t == 3),
+            "got {types:?}"
+        );
+    }
+}
+
+#[cfg(all(feature = "hdr", feature = "inter"))]
+#[test]
+fn metadata_cadence_controls_which_packets_repeat_metadata() {
+    let with_metadata = |cadence| -> Vec<(bool, bool)> {
+        hdr_metadata_packets(cadence)
+            .into_iter()
+            .map(|(key, types)| (key, types.contains(&5)))
+            .collect()
+    };
+
+    let keyframes = with_metadata(wav1c::MetadataCadence::Keyframes);
+    assert!(keyframes.iter().filter(|(key, _)| *key).count() >= 2);
+    assert!(keyframes.iter().all(|(key, meta)| key == meta));
+
+    let once = with_metadata(wav1c::MetadataCadence::Once);
+    assert!(once[0].1);
+    assert!(once[1..].iter().all(|(_, meta)| !meta));
+}
+
 #[test]
 fn ffprobe_reports_hdr10_signaling_for_10bit_output() {
     let Some(ffprobe) = ffprobe_path() else {
@@ -1070,3 +1153,129 @@ fn ffprobe_reports_hdr10_signaling_for_10bit_output() {
         stdout
     );
 }
+
+#[test]
+fn dav1d_decodes_separate_chroma_delta_q_to_encoder_recon() {
+    let Some(dav1d) = dav1d_path() else {
+        return;
+    };
+
+    let y4m_data = create_test_y4m(128, 96, |col, row| {
+        let y = ((col * 3 + row * 5) % 256) as u8;
+        let u = (col * 256 / 128) as u8;
+        let v = (255 - row * 2) as u8;
+        (y, u, v)
+    });
+    let pixels = FramePixels::from_y4m(&y4m_data);
+
+    let mut config = wav1c::EncoderConfig::from(&wav1c::EncodeConfig::default());
+    config.chroma_delta_q = wav1c::ChromaDeltaQ {
+        u_dc: -12,
+        u_ac: -20,
+        v_dc: 6,
+        v_ac: 10,
+    };
+    let mut encoder = wav1c::Encoder::new(128, 96, config).unwrap();
+    encoder.send_frame(&pixels).unwrap();
+    encoder.flush();
+    let packet = encoder.receive_packet().unwrap();
+    let recon = encoder.last_reconstruction().unwrap().clone();
+
+    let mut ivf = Vec::new();
+    write_ivf_header(&mut ivf, 128, 96, 1);
+    write_ivf_frame(&mut ivf, 0, &packet.data);
+
+    let (success, stderr, dav1d_y4m) = decode_to_y4m(&dav1d, &ivf, "chroma_delta_q");
+    assert!(success, "dav1d failed: {}", stderr);
+
+    let (y, u, v) = extract_y4m_planes(&dav1d_y4m, 128, 96);
+    let to_u16 = |p: Vec<u8>| p.into_iter().map(u16::from).collect::<Vec<_>>();
+    assert_eq!(to_u16(y), recon.y);
+    assert_eq!(to_u16(u), recon.u);
+    assert_eq!(to_u16(v), recon.v);
+}
+
+#[test]
+fn dav1d_decodes_per_row_cdef_strengths_to_encoder_recon() {
+    let Some(dav1d) = dav1d_path() else {
+        return;
+    };
+
+    // Detailed texture on top, a smooth gradient below: the rows want
+    // different CDEF strengths.
+    let y4m_data = create_test_y4m(128, 192, |col, row| {
+        let y = if row < 64 {
+            ((col * 37 + row * 91 + col * row) % 200 + 28) as u8
+        } else {
+            (40 + col + row / 3) as u8
+        };
+        (y, (100 + col / 4) as u8, (150 - row / 4) as u8)
+    });
+    let pixels = FramePixels::from_y4m(&y4m_data);
+
+    let mut config = wav1c::EncoderConfig::from(&wav1c::EncodeConfig::default());
+    config.base_q_idx = 180;
+    config.cdef_search = true;
+    let mut encoder = wav1c::Encoder::new(128, 192, config).unwrap();
+    let mut ivf = Vec::new();
+    write_ivf_header(&mut ivf, 128, 192, 2);
+    let mut recon = Vec::new();
+    for timestamp in 0..2 {
+        encoder.send_frame(&pixels).unwrap();
+        let packet = encoder.receive_packet().unwrap();
+        write_ivf_frame(&mut ivf, timestamp, &packet.data);
+        recon.push(encoder.last_reconstruction().unwrap().clone());
+    }
+
+    let (success, stderr, dav1d_y4m) = decode_to_y4m(&dav1d, &ivf, "cdef_search");
+    assert!(success, "dav1d failed: {}", stderr);
+
+    let to_u16 = |p: Vec<u8>| p.into_iter().map(u16::from).collect::<Vec<_>>();
+    let second_frame = dav1d_y4m
+        .windows(6)
+        .rposition(|w| w == b"FRAME\n")
+        .expect("second frame");
+    for (data, expected) in [
+        (&dav1d_y4m[..], &recon[0]),
+        (&dav1d_y4m[second_frame..], &recon[1]),
+    ] {
+        let (y, u, v) = extract_y4m_planes(data, 128, 192);
+        assert_eq!(to_u16(y), expected.y);
+        assert_eq!(to_u16(u), expected.u);
+        assert_eq!(to_u16(v), expected.v);
+    }
+}
+
+#[test]
+fn dav1d_decodes_lossless_frame_to_source() {
+    let Some(dav1d) = dav1d_path() else {
+        return;
+    };
+
+    let y4m_data = create_test_y4m(100, 70, |col, row| {
+        let y = ((col * 37 + row * 11 + col * row) % 256) as u8;
+        let u = ((col * 5 + row * 3) % 256) as u8;
+        let v = (255 - (col * row) % 200) as u8;
+        (y, u, v)
+    });
+    let pixels = FramePixels::from_y4m(&y4m_data);
+
+    let config = wav1c::EncoderConfig::from(&wav1c::EncodeConfig::default());
+    let mut encoder = wav1c::Encoder::new_lossless(100, 70, config).unwrap();
+    encoder.send_frame(&pixels).unwrap();
+    encoder.flush();
+    let packet = encoder.receive_packet().unwrap();
+
+    let mut ivf = Vec::new();
+    write_ivf_header(&mut ivf, 100, 70, 1);
+    write_ivf_frame(&mut ivf, 0, &packet.data);
+
+    let (success, stderr, dav1d_y4m) = decode_to_y4m(&dav1d, &ivf, "lossless");
+    assert!(success, "dav1d failed: {}", stderr);
+
+    let (y, u, v) = extract_y4m_planes(&dav1d_y4m, 100, 70);
+    let to_u16 = |p: Vec<u8>| p.into_iter().map(u16::from).collect::<Vec<_>>();
+    assert_eq!(to_u16(y), pixels.y);
+    assert_eq!(to_u16(u), pixels.u);
+    assert_eq!(to_u16(v), pixels.v);
+}
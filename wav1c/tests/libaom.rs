@@ -0,0 +1,744 @@
+//! Decodes a corpus of encoded test vectors with libaom's AV1 decoder and
+//! checks every decoded frame against the encoder's own reconstruction.
+//!
+//! An entropy-coding desync (a CDF, context or EOB edge case the encoder gets
+//! wrong) either fails the decode or shows up as a reconstruction mismatch,
+//! without needing a `dav1d` binary on the PATH. Build with
+//! `cargo test -p wav1c --features libaom-tests`; libaom must be installed
+//! where the linker finds `-laom`.
+#![cfg(feature = "libaom-tests")]
+
+use wav1c::y4m::FramePixels;
+use wav1c::{BitDepth, ColorRange, EncodeConfig, Encoder, EncoderConfig};
+
+mod aom {
+    #![allow(non_camel_case_types)]
+
+    use std::ffi::{c_char, c_int, c_long, c_uint, c_void};
+
+    pub type aom_codec_err_t = c_int;
+    pub const AOM_CODEC_OK: aom_codec_err_t = 0;
+
+    /// `AOM_DECODER_ABI_VERSION` for libaom 3.x: decoder 6 + codec 7 +
+    /// image 9. `aom_codec_dec_init_ver` rejects other versions with
+    /// `AOM_CODEC_ABI_MISMATCH`.
+    pub const AOM_DECODER_ABI_VERSION: c_int = 6 + 7 + 9;
+
+    pub const AOM_IMG_FMT_HIGHBITDEPTH: c_int = 0x800;
+
+    #[repr(C)]
+    pub struct aom_codec_iface_t {
+        _private: [u8; 0],
+    }
+
+    #[repr(C)]
+    pub struct aom_codec_ctx_t {
+        pub name: *const c_char,
+        pub iface: *mut aom_codec_iface_t,
+        pub err: aom_codec_err_t,
+        pub err_detail: *const c_char,
+        pub init_flags: c_long,
+        pub config: *const c_void,
+        pub priv_: *mut c_void,
+    }
+
+    /// Leading fields of `aom_image_t`; images are only read through
+    /// pointers libaom hands out, so the tail is left undeclared.
+    #[repr(C)]
+    pub struct aom_image_t {
+        pub fmt: c_int,
+        pub cp: c_int,
+        pub tc: c_int,
+        pub mc: c_int,
+        pub monochrome: c_int,
+        pub csp: c_int,
+        pub range: c_int,
+        pub w: c_uint,
+        pub h: c_uint,
+        pub bit_depth: c_uint,
+        pub d_w: c_uint,
+        pub d_h: c_uint,
+        pub r_w: c_uint,
+        pub r_h: c_uint,
+        pub x_chroma_shift: c_uint,
+        pub y_chroma_shift: c_uint,
+        pub planes: [*mut u8; 3],
+        pub stride: [c_int; 3],
+    }
+
+    #[link(name = "aom")]
+    unsafe extern "C" {
+        pub fn aom_codec_av1_dx() -> *mut aom_codec_iface_t;
+        pub fn aom_codec_dec_init_ver(
+            ctx: *mut aom_codec_ctx_t,
+            iface: *mut aom_codec_iface_t,
+            cfg: *const c_void,
+            flags: c_long,
+            ver: c_int,
+        ) -> aom_codec_err_t;
+        pub fn aom_codec_decode(
+            ctx: *mut aom_codec_ctx_t,
+            data: *const u8,
+            data_sz: usize,
+            user_priv: *mut c_void,
+        ) -> aom_codec_err_t;
+        pub fn aom_codec_get_frame(
+            ctx: *mut aom_codec_ctx_t,
+            iter: *mut *const c_void,
+        ) -> *mut aom_image_t;
+        pub fn aom_codec_error_detail(ctx: *const aom_codec_ctx_t) -> *const c_char;
+        pub fn aom_codec_err_to_string(err: aom_codec_err_t) -> *const c_char;
+        pub fn aom_codec_destroy(ctx: *mut aom_codec_ctx_t) -> aom_codec_err_t;
+    }
+}
+
+struct AomDecoder {
+    ctx: Box<aom::aom_codec_ctx_t>,
+}
+
+impl AomDecoder {
+    fn new() -> Self {
+        // SAFETY: an all-null context is what `aom_codec_dec_init_ver`
+        // expects to initialize.
+        let mut ctx: Box<aom::aom_codec_ctx_t> = Box::new(unsafe { std::mem::zeroed() });
+        let err = unsafe {
+            aom::aom_codec_dec_init_ver(
+                &mut *ctx,
+                aom::aom_codec_av1_dx(),
+                std::ptr::null(),
+                0,
+                aom::AOM_DECODER_ABI_VERSION,
+            )
+        };
+        assert_eq!(
+            err,
+            aom::AOM_CODEC_OK,
+            "aom decoder init: {}",
+            error_string(err)
+        );
+        Self { ctx }
+    }
+
+    /// Decodes one temporal unit and returns the frames it shows.
+    fn decode(&mut self, data: &[u8]) -> Result<Vec<FramePixels>, String> {
+        let err = unsafe {
+            aom::aom_codec_decode(
+                &mut *self.ctx,
+                data.as_ptr(),
+                data.len(),
+                std::ptr::null_mut(),
+            )
+        };
+        if err != aom::AOM_CODEC_OK {
+            let detail = unsafe { aom::aom_codec_error_detail(&*self.ctx) };
+            let detail = if detail.is_null() {
+                String::new()
+            } else {
+                unsafe { std::ffi::CStr::from_ptr(detail) }
+                    .to_string_lossy()
+                    .into_owned()
+            };
+            return Err(format!("{}: {detail}", error_string(err)));
+        }
+
+        let mut frames = Vec::new();
+        let mut iter = std::ptr::null();
+        loop {
+            let img = unsafe { aom::aom_codec_get_frame(&mut *self.ctx, &mut iter) };
+            if img.is_null() {
+                break;
+            }
+            frames.push(unsafe { image_to_frame(&*img) });
+        }
+        Ok(frames)
+    }
+}
+
+impl Drop for AomDecoder {
+    fn drop(&mut self) {
+        unsafe { aom::aom_codec_destroy(&mut *self.ctx) };
+    }
+}
+
+fn error_string(err: aom::aom_codec_err_t) -> String {
+    unsafe { std::ffi::CStr::from_ptr(aom::aom_codec_err_to_string(err)) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Copies a decoded 4:2:0 image out of libaom's buffers.
+///
+/// # Safety
+/// `img` must be an image returned by `aom_codec_get_frame` that has not
+/// been released by a later decode call.
+unsafe fn image_to_frame(img: &aom::aom_image_t) -> FramePixels {
+    let high_bitdepth = img.fmt & aom::AOM_IMG_FMT_HIGHBITDEPTH != 0;
+    let bit_depth = if img.bit_depth == 10 {
+        BitDepth::Ten
+    } else {
+        BitDepth::Eight
+    };
+    let color_range = if img.range == 1 {
+        ColorRange::Full
+    } else {
+        ColorRange::Limited
+    };
+    let plane = |index: usize, width: u32, height: u32| -> Vec<u16> {
+        let stride = img.stride[index] as usize;
+        let mut out = Vec::with_capacity((width * height) as usize);
+        for row in 0..height as usize {
+            let line = unsafe { img.planes[index].add(row * stride) };
+            for col in 0..width as usize {
+                out.push(if high_bitdepth {
+                    unsafe { line.cast::<u16>().add(col).read_unaligned() }
+                } else {
+                    unsafe { *line.add(col) as u16 }
+                });
+            }
+        }
+        out
+    };
+    let (width, height) = (img.d_w, img.d_h);
+    let (cw, ch) = (width.div_ceil(2), height.div_ceil(2));
+    FramePixels {
+        width,
+        height,
+        bit_depth,
+        color_range,
+        y: plane(0, width, height),
+        u: plane(1, cw, ch),
+        v: plane(2, cw, ch),
+    }
+}
+
+/// FNV-1a over the three planes, so mismatches report compactly.
+fn frame_hash(frame: &FramePixels) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for sample in frame.y.iter().chain(&frame.u).chain(&frame.v) {
+        for byte in sample.to_le_bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+fn textured_frame(width: u32, height: u32, shift: u32, bit_depth: BitDepth) -> FramePixels {
+    let max = bit_depth.max_value() as u32;
+    let mut frame =
+        FramePixels::solid_with_bit_depth(width, height, 0, 0, 0, bit_depth, ColorRange::Limited);
+    let cw = width.div_ceil(2);
+    for (i, s) in frame.y.iter_mut().enumerate() {
+        let (x, y) = (i as u32 % width + shift, i as u32 / width);
+        *s = ((x * 5 + y * 3 + (x * y) % 29) * (max + 1) / 256 % (max + 1)) as u16;
+    }
+    for (i, s) in frame.u.iter_mut().chain(frame.v.iter_mut()).enumerate() {
+        let (x, y) = (i as u32 % cw + shift / 2, i as u32 / cw);
+        *s = ((x * 3 + y * 7) * (max + 1) / 256 % (max + 1)) as u16;
+    }
+    frame
+}
+
+/// Pseudo-random samples: nearly every coefficient survives quantization,
+/// which exercises full-length EOBs and the high-token contexts.
+fn noisy_frame(width: u32, height: u32, bit_depth: BitDepth) -> FramePixels {
+    let max = bit_depth.max_value() as u32;
+    let mut frame = textured_frame(width, height, 0, bit_depth);
+    let mut state = 0x2545_f491u32;
+    for s in frame
+        .y
+        .iter_mut()
+        .chain(frame.u.iter_mut())
+        .chain(frame.v.iter_mut())
+    {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        *s = ((state >> 16) % (max + 1)) as u16;
+    }
+    frame
+}
+
+fn rippled_frame(width: u32, height: u32, phase: f64) -> FramePixels {
+    let mut frame = textured_frame(width, height, 0, BitDepth::Eight);
+    let mut state = 0x1234_5678u32;
+    for (i, s) in frame.y.iter_mut().enumerate() {
+        let (x, y) = ((i as u32 % width) as f64, (i as u32 / width) as f64);
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        let grain = ((state >> 16) % 9) as f64 - 4.0;
+        let ripple = 60.0 * ((x + phase) / 5.0).sin() * (y / 7.0).cos();
+        *s = (128.0 + ripple + 30.0 * ((x + y) / 23.0).sin() + grain) as u16;
+    }
+    frame
+}
+
+/// Grainy texture panned by `(dx, dy)` samples: motion along both axes
+/// that the search locks on to, with vectors that cross tile edges.
+fn panning_frame(width: u32, height: u32, dx: u32, dy: u32) -> FramePixels {
+    let mut frame = textured_frame(width, height, dx, BitDepth::Eight);
+    for (i, s) in frame.y.iter_mut().enumerate() {
+        let (x, y) = (i as u32 % width + dx, i as u32 / width + dy);
+        let grain = (x.wrapping_mul(0x9e37_79b9) ^ y.wrapping_mul(0x85eb_ca6b))
+            .wrapping_mul(0xc2b2_ae35)
+            >> 27;
+        *s = (64 + (x * 5 + y * 3) % 96 + grain) as u16;
+    }
+    frame
+}
+
+struct TestVector {
+    name: &'static str,
+    config: EncodeConfig,
+    frames: Vec<FramePixels>,
+}
+
+fn corpus() -> Vec<TestVector> {
+    let moving = |width, height, count, bit_depth| -> Vec<FramePixels> {
+        (0..count)
+            .map(|i| textured_frame(width, height, i * 3, bit_depth))
+            .collect()
+    };
+    let ten_bit = |config: EncodeConfig| {
+        let mut config = config;
+        config.video_signal.bit_depth = BitDepth::Ten;
+        config
+    };
+    vec![
+        TestVector {
+            name: "solid keyframe",
+            config: EncodeConfig::default(),
+            frames: vec![FramePixels::solid(64, 64, 90, 120, 140)],
+        },
+        TestVector {
+            name: "odd dimensions",
+            config: EncodeConfig::default(),
+            frames: moving(33, 17, 3, BitDepth::Eight),
+        },
+        TestVector {
+            name: "moving texture P-frames",
+            config: EncodeConfig::default(),
+            frames: moving(128, 96, 6, BitDepth::Eight),
+        },
+        TestVector {
+            name: "10-bit moving texture",
+            config: ten_bit(EncodeConfig::default()),
+            frames: moving(96, 64, 4, BitDepth::Ten),
+        },
+        TestVector {
+            name: "noise at fine quantizer",
+            config: EncodeConfig {
+                base_q_idx: 8,
+                ..EncodeConfig::default()
+            },
+            frames: vec![noisy_frame(64, 64, BitDepth::Eight); 2],
+        },
+        TestVector {
+            name: "10-bit noise",
+            config: ten_bit(EncodeConfig::default()),
+            frames: vec![noisy_frame(64, 64, BitDepth::Ten); 2],
+        },
+        TestVector {
+            name: "lossless",
+            config: EncodeConfig {
+                base_q_idx: 0,
+                ..EncodeConfig::default()
+            },
+            frames: vec![noisy_frame(40, 24, BitDepth::Eight)],
+        },
+        TestVector {
+            name: "tile columns",
+            config: EncodeConfig {
+                tile_columns: 4,
+                ..EncodeConfig::default()
+            },
+            frames: (0..12)
+                .map(|i| panning_frame(512, 128, i * 3, i * 2))
+                .collect(),
+        },
+        TestVector {
+            name: "tile rows",
+            config: EncodeConfig {
+                tile_rows: 2,
+                ..EncodeConfig::default()
+            },
+            frames: moving(128, 256, 4, BitDepth::Eight),
+        },
+        TestVector {
+            name: "2x2 tiles on four threads",
+            config: EncodeConfig {
+                tile_columns: 2,
+                tile_rows: 2,
+                threads: 4,
+                ..EncodeConfig::default()
+            },
+            frames: moving(256, 256, 4, BitDepth::Eight),
+        },
+        TestVector {
+            name: "per-row CDEF search",
+            config: EncodeConfig {
+                cdef_search: true,
+                ..EncodeConfig::default()
+            },
+            frames: vec![
+                noisy_frame(64, 128, BitDepth::Eight),
+                textured_frame(64, 128, 2, BitDepth::Eight),
+            ],
+        },
+        TestVector {
+            name: "CDEF at a fine quantizer",
+            config: EncodeConfig {
+                base_q_idx: 40,
+                ..EncodeConfig::default()
+            },
+            frames: moving(96, 64, 4, BitDepth::Eight),
+        },
+        TestVector {
+            name: "Wiener loop restoration",
+            config: EncodeConfig {
+                base_q_idx: 160,
+                loop_restoration: true,
+                ..EncodeConfig::default()
+            },
+            frames: (0..3).map(|i| rippled_frame(264, 200, i as f64)).collect(),
+        },
+        TestVector {
+            name: "Wiener loop restoration across tiles",
+            config: EncodeConfig {
+                base_q_idx: 120,
+                tile_columns: 2,
+                cdef_search: true,
+                loop_restoration: true,
+                ..EncodeConfig::default()
+            },
+            frames: (0..3)
+                .map(|i| rippled_frame(448, 320, i as f64 * 2.0))
+                .collect(),
+        },
+        TestVector {
+            name: "rate control",
+            config: EncodeConfig {
+                target_bitrate: Some(300_000),
+                keyint: 4,
+                ..EncodeConfig::default()
+            },
+            frames: moving(96, 96, 8, BitDepth::Eight),
+        },
+        TestVector {
+            name: "intra blocks where half the frame cuts to a new scene",
+            config: EncodeConfig {
+                base_q_idx: 8,
+                ..EncodeConfig::default()
+            },
+            frames: vec![textured_frame(96, 64, 0, BitDepth::Eight), {
+                let mut frame = textured_frame(96, 64, 3, BitDepth::Eight);
+                for (i, y) in frame.y.iter_mut().enumerate() {
+                    if i % 96 >= 40 {
+                        *y = (30 + i / 96 * 3) as u16;
+                    }
+                }
+                frame
+            }],
+        },
+        TestVector {
+            name: "scene returning through LAST2 and GOLDEN",
+            config: EncodeConfig::default(),
+            frames: [0, 1, 0, 1, 2, 0]
+                .map(|scene| match scene {
+                    0 => textured_frame(96, 64, 0, BitDepth::Eight),
+                    1 => noisy_frame(96, 64, BitDepth::Eight),
+                    _ => textured_frame(96, 64, 9, BitDepth::Eight),
+                })
+                .to_vec(),
+        },
+    ]
+}
+
+/// Encodes P-only so that every packet is one shown frame and the
+/// encoder's reconstruction after it is what a decoder must output.
+fn encode_with_reconstructions(vector: &TestVector) -> Vec<(Vec<u8>, FramePixels)> {
+    let first = &vector.frames[0];
+    let mut encoder = Encoder::new(
+        first.width,
+        first.height,
+        EncoderConfig::from(&vector.config),
+    )
+    .unwrap();
+    let mut out = Vec::new();
+    for frame in &vector.frames {
+        encoder.send_frame(frame).unwrap();
+        let packet = encoder
+            .receive_packet()
+            .expect("P-only encode emits a packet");
+        assert!(encoder.receive_packet().is_none());
+        out.push((packet.data, encoder.last_reconstruction().unwrap().clone()));
+    }
+    out
+}
+
+#[test]
+fn libaom_decodes_corpus_to_encoder_reconstruction() {
+    for vector in corpus() {
+        assert!(!vector.config.b_frames);
+        let mut decoder = AomDecoder::new();
+        for (index, (data, recon)) in encode_with_reconstructions(&vector).iter().enumerate() {
+            let decoded = decoder
+                .decode(data)
+                .unwrap_or_else(|e| panic!("{}: frame {index} failed to decode: {e}", vector.name));
+            assert_eq!(decoded.len(), 1, "{}: frame {index}", vector.name);
+            assert_eq!(
+                frame_hash(&decoded[0]),
+                frame_hash(recon),
+                "{}: frame {index} decodes differently from the encoder's reconstruction",
+                vector.name
+            );
+        }
+    }
+}
+
+#[test]
+fn libaom_decodes_b_frame_mini_gops_in_display_order() {
+    let frames: Vec<_> = (0..7)
+        .map(|i| textured_frame(96, 64, i * 4, BitDepth::Eight))
+        .collect();
+    let config = EncodeConfig {
+        b_frames: true,
+        gop_size: 3,
+        ..EncodeConfig::default()
+    };
+    let packets = wav1c::encode_packets(&frames, &config);
+
+    let mut decoder = AomDecoder::new();
+    let mut shown = Vec::new();
+    for packet in &packets {
+        let decoded = decoder
+            .decode(&packet.data)
+            .unwrap_or_else(|e| panic!("packet {}: {e}", packet.frame_number));
+        shown.extend(
+            decoded
+                .into_iter()
+                .map(|frame| (packet.frame_number, frame)),
+        );
+    }
+    assert_eq!(shown.len(), frames.len());
+    for (display_index, frame) in &shown {
+        let source = &frames[*display_index as usize];
+        let psnr = wav1c::metrics::frame_psnr(source, frame);
+        assert!(psnr.y > 25.0, "frame {display_index}: luma PSNR {}", psnr.y);
+    }
+}
+
+#[test]
+fn libaom_decodes_intra_refresh_mid_stream() {
+    let frames: Vec<_> = (0..6)
+        .map(|i| textured_frame(128, 96, i * 3, BitDepth::Eight))
+        .collect();
+    let mut encoder = Encoder::new(128, 96, EncoderConfig::from(&EncodeConfig::default())).unwrap();
+    let mut decoder = AomDecoder::new();
+    for (index, frame) in frames.iter().enumerate() {
+        if index == 3 {
+            encoder.request_intra_refresh().unwrap();
+        }
+        encoder.send_frame(frame).unwrap();
+        let packet = encoder.receive_packet().unwrap();
+        let decoded = decoder
+            .decode(&packet.data)
+            .unwrap_or_else(|e| panic!("frame {index} failed to decode: {e}"));
+        assert_eq!(decoded.len(), 1, "frame {index}");
+        let psnr = wav1c::metrics::frame_psnr(frame, &decoded[0]);
+        assert!(psnr.y > 25.0, "frame {index}: luma PSNR {}", psnr.y);
+    }
+}
+
+#[test]
+fn libaom_decodes_cyclic_refresh_delta_q() {
+    for tile_columns in [0, 2] {
+        let frames: Vec<_> = (0..6)
+            .map(|i| textured_frame(256, 128, i * 3, BitDepth::Eight))
+            .collect();
+        let config = EncodeConfig {
+            aq_mode: wav1c::AqMode::CyclicRefresh,
+            tile_columns,
+            ..EncodeConfig::default()
+        };
+        let packets = wav1c::encode_packets(&frames, &config);
+        let mut decoder = AomDecoder::new();
+        for (packet, frame) in packets.iter().zip(&frames) {
+            let index = packet.frame_number;
+            let decoded = decoder
+                .decode(&packet.data)
+                .unwrap_or_else(|e| panic!("{tile_columns} tiles: frame {index}: {e}"));
+            assert_eq!(decoded.len(), 1);
+            let psnr = wav1c::metrics::frame_psnr(frame, &decoded[0]);
+            assert!(psnr.y > 25.0, "frame {index}: luma PSNR {}", psnr.y);
+        }
+    }
+}
+
+#[test]
+fn libaom_decodes_equirect_delta_q_in_every_frame_type() {
+    for (tile_columns, aq_mode) in [(0, wav1c::AqMode::Off), (2, wav1c::AqMode::CyclicRefresh)] {
+        let frames: Vec<_> = (0..5)
+            .map(|i| textured_frame(256, 320, i * 3, BitDepth::Eight))
+            .collect();
+        let config = EncodeConfig {
+            projection: wav1c::Projection::Equirect,
+            aq_mode,
+            tile_columns,
+            ..EncodeConfig::default()
+        };
+        let mut encoder = Encoder::new(256, 320, EncoderConfig::from(&config)).unwrap();
+        let mut packets = Vec::new();
+        for (i, frame) in frames.iter().enumerate() {
+            if i == 3 {
+                encoder.request_intra_refresh().unwrap();
+            }
+            encoder.send_frame(frame).unwrap();
+            packets.extend(std::iter::from_fn(|| encoder.receive_packet()));
+        }
+        encoder.flush();
+        packets.extend(std::iter::from_fn(|| encoder.receive_packet()));
+        assert_eq!(packets[3].frame_type, wav1c::FrameType::IntraOnly);
+
+        let mut decoder = AomDecoder::new();
+        for (packet, frame) in packets.iter().zip(&frames) {
+            let index = packet.frame_number;
+            let decoded = decoder
+                .decode(&packet.data)
+                .unwrap_or_else(|e| panic!("{tile_columns} tiles: frame {index}: {e}"));
+            assert_eq!(decoded.len(), 1);
+            let psnr = wav1c::metrics::frame_ws_psnr(frame, &decoded[0]);
+            assert!(psnr.y > 25.0, "frame {index}: luma WS-PSNR {}", psnr.y);
+        }
+    }
+}
+
+/// The OBUs of `data` up to and including the first OBU_FRAME.
+fn through_first_frame_obu(data: &[u8]) -> &[u8] {
+    let mut pos = 0;
+    while pos < data.len() {
+        let obu_type = (data[pos] >> 3) & 0xF;
+        let mut header_len = 1 + usize::from(data[pos] & 0x04 != 0);
+        let (mut size, mut shift) = (0usize, 0);
+        loop {
+            let byte = data[pos + header_len];
+            header_len += 1;
+            size |= usize::from(byte & 0x7F) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        pos += header_len + size;
+        if obu_type == 6 {
+            break;
+        }
+    }
+    &data[..pos]
+}
+
+/// Decodes `packets` from the start, or from the forward keyframe packet
+/// `start` the way a player seeking there would: the leading frames coded
+/// with the keyframe and after it are dropped until the keyframe is shown.
+/// Checks every shown frame against its source and returns their display
+/// indices.
+fn decode_from(packets: &[wav1c::Packet], frames: &[FramePixels], start: usize) -> Vec<u64> {
+    let mut decoder = AomDecoder::new();
+    let mut shown = Vec::new();
+    let mut leading = start > 0;
+    for (i, packet) in packets.iter().enumerate().skip(start) {
+        let index = packet.frame_number;
+        let data = if i == start && leading {
+            through_first_frame_obu(&packet.data)
+        } else if leading && packet.frame_type != wav1c::FrameType::ShowExisting {
+            continue;
+        } else {
+            leading = false;
+            &packet.data
+        };
+        let decoded = decoder
+            .decode(data)
+            .unwrap_or_else(|e| panic!("frame {index}: {e}"));
+        for frame in decoded {
+            let psnr = wav1c::metrics::frame_psnr(&frames[index as usize], &frame);
+            assert!(psnr.y > 25.0, "frame {index}: luma PSNR {}", psnr.y);
+            shown.push(index);
+        }
+    }
+    shown
+}
+
+#[test]
+fn libaom_decodes_closed_and_open_gops() {
+    let frames: Vec<_> = (0..12)
+        .map(|i| textured_frame(96, 64, i * 4, BitDepth::Eight))
+        .collect();
+    for closed_gop in [true, false] {
+        // Keyframes at 5 and 10 fall inside mini-GOPs of 3.
+        let config = EncodeConfig {
+            b_frames: true,
+            gop_size: 3,
+            keyint: 5,
+            closed_gop,
+            ..EncodeConfig::default()
+        };
+        let packets = wav1c::encode_packets(&frames, &config);
+        let mut shown = decode_from(&packets, &frames, 0);
+        shown.sort_unstable();
+        assert_eq!(
+            shown,
+            (0..12).collect::<Vec<_>>(),
+            "closed_gop {closed_gop}"
+        );
+
+        let keys = |frame_type: wav1c::FrameType| {
+            (0..packets.len())
+                .filter(|&i| packets[i].frame_type == frame_type)
+                .collect::<Vec<_>>()
+        };
+        if closed_gop {
+            assert_eq!(keys(wav1c::FrameType::Key).len(), 3);
+            assert!(keys(wav1c::FrameType::ForwardKey).is_empty());
+        } else {
+            // Random access at a forward keyframe shows it and everything
+            // after it.
+            let forward = keys(wav1c::FrameType::ForwardKey);
+            assert_eq!(forward.len(), 2);
+            let shown = decode_from(&packets, &frames, forward[1]);
+            assert_eq!(shown.first(), Some(&10));
+            assert_eq!(shown.len(), 2);
+        }
+    }
+}
+
+#[test]
+fn libaom_decodes_frame_slices() {
+    for (frame_slices, cdef_search) in [(2, false), (4, true)] {
+        let frames: Vec<_> = (0..6)
+            .map(|i| panning_frame(128, 256, i * 3, i * 2))
+            .collect();
+        let mut config = EncoderConfig::from(&EncodeConfig {
+            cdef_search,
+            ..EncodeConfig::default()
+        });
+        config.frame_slices = frame_slices;
+        let mut encoder = Encoder::new(128, 256, config).unwrap();
+        let rows = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let hooked = rows.clone();
+        encoder.set_slice_hook(move |packet| hooked.lock().unwrap().push(packet));
+        let mut decoder = AomDecoder::new();
+        for (index, frame) in frames.iter().enumerate() {
+            encoder.send_frame(frame).unwrap();
+            let mut slices = std::mem::take(&mut *rows.lock().unwrap());
+            slices.extend(std::iter::from_fn(|| encoder.receive_packet()));
+            assert_eq!(slices.len(), frame_slices, "frame {index}");
+            let data: Vec<u8> = slices.into_iter().flat_map(|p| p.data).collect();
+            let decoded = decoder
+                .decode(&data)
+                .unwrap_or_else(|e| panic!("{frame_slices} slices: frame {index}: {e}"));
+            assert_eq!(decoded.len(), 1);
+            assert_eq!(
+                frame_hash(&decoded[0]),
+                frame_hash(encoder.last_reconstruction().unwrap()),
+                "{frame_slices} slices: frame {index} decodes differently from the encoder's reconstruction"
+            );
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//! Rate-control conformance: the one-pass bitrate controller fed synthetic
+//! content whose complexity changes under it.
+//!
+//! Each test encodes a small clip at a fixed target and checks the numbers a
+//! CBR stream is judged by: how full the one-second buffer gets, how long it
+//! stays at either bound, and how close the coded bitrate comes to the
+//! target. The tolerances leave room for tuning but catch a controller that
+//! stops reacting. The target is sized for inter coding, so the suite needs
+//! the `inter` feature as well.
+
+#![cfg(all(feature = "rate-control", feature = "inter"))]
+
+use wav1c::y4m::FramePixels;
+use wav1c::{EncodeConfig, Encoder, EncoderConfig, Fps};
+
+const WIDTH: u32 = 96;
+const HEIGHT: u32 = 64;
+const FPS: u32 = 25;
+const FRAMES: u64 = 90;
+const TARGET_BITRATE: u64 = 300_000;
+
+/// A diagonal gradient drifting one pixel per frame, plus uniform noise of
+/// up to `noise` levels that changes every frame. Motion search predicts the
+/// gradient; the noise costs bits in proportion to its amplitude.
+fn noisy_frame(index: u64, noise: u32) -> FramePixels {
+    let mut frame = FramePixels::solid(WIDTH, HEIGHT, 0, 128, 128);
+    let mut state = index.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+    for (i, s) in frame.y.iter_mut().enumerate() {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        let (x, y) = (i as u32 % WIDTH, i as u32 / WIDTH);
+        let base = 64 + (x + y + index as u32) % 96;
+        let offset = (state % (2 * noise as u64 + 1)) as u32;
+        *s = (base + offset).saturating_sub(noise).clamp(16, 235) as u16;
+    }
+    frame
+}
+
+struct RateTrace {
+    /// Coded bits of each packet, in coding order.
+    frame_bits: Vec<u64>,
+    /// Buffer fullness in percent after each frame.
+    buffer: Vec<u32>,
+}
+
+impl RateTrace {
+    fn encode(noise: impl Fn(u64) -> u32) -> Self {
+        let config = EncodeConfig {
+            target_bitrate: Some(TARGET_BITRATE),
+            fps: Fps::from_int(FPS).unwrap(),
+            speed: 2,
+            ..EncodeConfig::default()
+        };
+        let mut encoder = Encoder::new(WIDTH, HEIGHT, EncoderConfig::from(&config)).unwrap();
+        let mut frame_bits = Vec::new();
+        for i in 0..FRAMES {
+            encoder.send_frame(&noisy_frame(i, noise(i))).unwrap();
+            while let Some(packet) = encoder.receive_packet() {
+                frame_bits.push(packet.data.len() as u64 * 8);
+            }
+        }
+        encoder.flush();
+        while let Some(packet) = encoder.receive_packet() {
+            frame_bits.push(packet.data.len() as u64 * 8);
+        }
+        let stats = encoder.rate_control_stats().unwrap();
+        let buffer = stats
+            .gops
+            .iter()
+            .flat_map(|gop| gop.buffer_trajectory.iter().copied())
+            .collect();
+        Self { frame_bits, buffer }
+    }
+
+    /// Coded bitrate of `frames` over the target.
+    fn bitrate_ratio(&self, frames: std::ops::Range<usize>) -> f64 {
+        let bits: u64 = self.frame_bits[frames.clone()].iter().sum();
+        let bitrate = bits as f64 * FPS as f64 / frames.len() as f64;
+        bitrate / TARGET_BITRATE as f64
+    }
+
+    /// Longest run of frames after which the buffer sat at a bound: empty
+    /// (every bit of the target unused) or full (overflowing).
+    fn longest_run_at_bound(&self) -> usize {
+        self.buffer
+            .split(|&pct| pct > 0 && pct < 100)
+            .map(<[u32]>::len)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+#[test]
+fn steady_content_converges_to_the_target() {
+    let trace = RateTrace::encode(|_| 6);
+    assert_eq!(trace.frame_bits.len(), FRAMES as usize);
+    let ratio = trace.bitrate_ratio(0..90);
+    assert!((0.9..=1.1).contains(&ratio), "clip at {ratio:.3}x");
+    assert!(
+        trace.buffer.iter().all(|pct| (25..=95).contains(pct)),
+        "buffer left 25-95%: {:?}",
+        trace.buffer
+    );
+    // The early overshoot is paid back: the buffer settles near half full.
+    let settled = trace.buffer[trace.buffer.len() - 1];
+    assert!((30..=70).contains(&settled), "{:?}", trace.buffer);
+}
+
+#[test]
+fn complexity_ramp_keeps_the_buffer_off_its_bounds() {
+    // Noise grows from none to 15 levels over the clip.
+    let trace = RateTrace::encode(|i| i as u32 / 6);
+    assert_eq!(trace.longest_run_at_bound(), 0, "{:?}", trace.buffer);
+    let ratio = trace.bitrate_ratio(0..90);
+    assert!((0.85..=1.15).contains(&ratio), "clip at {ratio:.3}x");
+}
+
+#[test]
+fn scene_cuts_fill_the_buffer_only_briefly() {
+    // Quiet, busy, quiet: each cut changes the cost per frame about sixfold.
+    let trace = RateTrace::encode(|i| if (i / 30) % 2 == 0 { 2 } else { 12 });
+    let run = trace.longest_run_at_bound();
+    assert!(
+        run <= 10,
+        "buffer at a bound for {run} frames: {:?}",
+        trace.buffer
+    );
+    // The quiet scene after the busy one pays the overflow back.
+    assert!(trace.bitrate_ratio(60..90) < 1.0);
+    assert!(
+        trace.buffer[trace.buffer.len() - 1] < 50,
+        "{:?}",
+        trace.buffer
+    );
+    let ratio = trace.bitrate_ratio(0..90);
+    assert!((0.85..=1.15).contains(&ratio), "clip at {ratio:.3}x");
+}